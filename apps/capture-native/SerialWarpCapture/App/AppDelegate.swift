@@ -16,12 +16,32 @@ class AppDelegate: NSObject, NSApplicationDelegate {
 
         // Check for screen recording permission
         checkScreenRecordingPermission()
+
+        // Say up front if virtual displays won't work, rather than when one
+        // is created
+        if let reason = streamingService.checkVirtualDisplaySupport() {
+            print("[App] Virtual displays unavailable: \(reason.localizedDescription)")
+        }
+
+        // Connect (and resume streaming) whenever the cable is plugged in
+        if AppState.shared.settings.autoConnect {
+            streamingService.startAutoConnect()
+        }
+
+        // Start and stop streaming from any app
+        streamingService.registerToggleShortcut()
     }
 
     func applicationWillTerminate(_ notification: Notification) {
         // Clean up: stop streaming
+        streamingService.stopAutoConnect()
         streamingService.stopStreaming()
-        streamingService.destroyVirtualDisplay()
+        streamingService.destroyAllVirtualDisplays()
+    }
+
+    func applicationDidBecomeActive(_ notification: Notification) {
+        // Screen recording may have been allowed in System Settings meanwhile
+        streamingService.checkVirtualDisplaySupport()
     }
 
     func applicationShouldTerminateAfterLastWindowClosed(_ sender: NSApplication) -> Bool {
@@ -35,8 +55,8 @@ class AppDelegate: NSObject, NSApplicationDelegate {
     private func checkScreenRecordingPermission() {
         // Check if we have screen recording permission
         // This triggers the permission dialog if not granted
-        if !CGPreflightScreenCaptureAccess() {
-            CGRequestScreenCaptureAccess()
+        if streamingService.screenRecordingPermission() == .denied {
+            streamingService.requestScreenRecordingPermission()
         }
     }
 
@@ -71,6 +91,10 @@ class AppDelegate: NSObject, NSApplicationDelegate {
         let fileMenu = NSMenu(title: "File")
         fileMenuItem.submenu = fileMenu
 
+        let pauseItem = NSMenuItem(title: "Pause Streaming", action: #selector(togglePause(_:)), keyEquivalent: "p")
+        pauseItem.keyEquivalentModifierMask = [.command, .shift]
+        fileMenu.addItem(pauseItem)
+        fileMenu.addItem(NSMenuItem.separator())
         fileMenu.addItem(NSMenuItem(title: "Close Window", action: #selector(NSWindow.performClose(_:)), keyEquivalent: "w"))
 
         // View menu
@@ -119,4 +143,28 @@ class AppDelegate: NSObject, NSApplicationDelegate {
     @objc func showPreferences(_ sender: Any?) {
         // TODO: Show preferences window
     }
+
+    @objc func togglePause(_ sender: Any?) {
+        let paused = AppState.shared.connectionStatus == .paused
+        Task {
+            do {
+                if paused {
+                    try await streamingService.resumeStreaming()
+                } else {
+                    try await streamingService.pauseStreaming()
+                }
+            } catch {
+                AppState.shared.lastError = error.localizedDescription
+            }
+        }
+    }
+}
+
+extension AppDelegate: NSMenuItemValidation {
+    func validateMenuItem(_ menuItem: NSMenuItem) -> Bool {
+        guard menuItem.action == #selector(togglePause(_:)) else { return true }
+        let status = AppState.shared.connectionStatus
+        menuItem.title = status == .paused ? "Resume Streaming" : "Pause Streaming"
+        return status == .streaming || status == .paused
+    }
 }
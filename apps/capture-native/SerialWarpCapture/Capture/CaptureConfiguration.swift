@@ -2,10 +2,10 @@ import Foundation
 
 /// Configuration for screen capture
 struct CaptureConfiguration: Sendable {
-    /// Capture width in pixels
+    /// Visible capture width in pixels; frames come out at `codedSize`
     let width: UInt32
 
-    /// Capture height in pixels
+    /// Visible capture height in pixels
     let height: UInt32
 
     /// Target frames per second
@@ -17,9 +17,22 @@ struct CaptureConfiguration: Sendable {
     /// Whether to show cursor in capture
     let showCursor: Bool
 
-    /// Queue depth for buffering frames
+    /// Surfaces ScreenCaptureKit renders into (`SCStreamConfiguration`'s
+    /// `queueDepth`). Each holds a whole frame, so this is what capture
+    /// costs in memory, see `estimatedBufferBytes`: 8 BGRA surfaces are
+    /// 66 MB at 1080p and 265 MB at 4K. A deeper queue rides out longer
+    /// encoder hiccups; with all of them in use, capture drops frames.
     let queueDepth: Int
 
+    /// Captured frames buffered for the pipeline before the oldest is
+    /// dropped. They still hold their ScreenCaptureKit surfaces, so this
+    /// costs no memory of its own, but frames waiting here count against
+    /// `queueDepth`.
+    let channelCapacity: Int
+
+    /// Windows and applications to leave out
+    let exclusions: CaptureExclusions
+
     /// Create a capture configuration
     init(
         width: UInt32,
@@ -27,7 +40,9 @@ struct CaptureConfiguration: Sendable {
         fps: UInt32,
         pixelFormat: UInt32 = 0x42475241,  // 'BGRA' = kCVPixelFormatType_32BGRA
         showCursor: Bool = true,
-        queueDepth: Int = 8
+        queueDepth: Int = CaptureConfiguration.defaultQueueDepth,
+        channelCapacity: Int = CaptureConfiguration.defaultChannelCapacity,
+        exclusions: CaptureExclusions = .none
     ) {
         self.width = width
         self.height = height
@@ -35,6 +50,71 @@ struct CaptureConfiguration: Sendable {
         self.pixelFormat = pixelFormat
         self.showCursor = showCursor
         self.queueDepth = queueDepth
+        self.channelCapacity = channelCapacity
+        self.exclusions = exclusions
+    }
+
+    /// The same configuration with a different size or frame rate
+    func with(width: UInt32? = nil, height: UInt32? = nil, fps: UInt32? = nil) -> CaptureConfiguration {
+        CaptureConfiguration(
+            width: width ?? self.width,
+            height: height ?? self.height,
+            fps: fps ?? self.fps,
+            pixelFormat: pixelFormat,
+            showCursor: showCursor,
+            queueDepth: queueDepth,
+            channelCapacity: channelCapacity,
+            exclusions: exclusions
+        )
+    }
+
+    /// Size of the delivered frames: the visible size rounded up to even,
+    /// with the padding column and row left empty
+    var codedSize: Resolution {
+        Resolution(width: width, height: height).coded
+    }
+
+    /// Bytes of one captured frame: 4 per pixel for BGRA, 1.5 for NV12
+    var bytesPerFrame: Int {
+        Self.bytesPerFrame(width: Int(codedSize.width), height: Int(codedSize.height), pixelFormat: pixelFormat)
+    }
+
+    /// Memory held by ScreenCaptureKit's surfaces for this configuration
+    var estimatedBufferBytes: Int {
+        queueDepth * bytesPerFrame
+    }
+
+    static func bytesPerFrame(width: Int, height: Int, pixelFormat: UInt32) -> Int {
+        switch pixelFormat {
+        case pixelFormatNV12, pixelFormatNV12FullRange:
+            // Full size luma plus a quarter size plane of two-byte chroma
+            return width * height + (width / 2) * (height / 2) * 2
+        default:
+            return width * height * 4
+        }
+    }
+
+    /// Reject an out of range size, a zero frame rate, or a queue depth or
+    /// channel capacity outside `depthRange`
+    func validate() throws {
+        guard Resolution(width: width, height: height).isValid else {
+            throw SerialWarpError.invalidCaptureConfiguration(
+                "resolution \(width)x\(height) is outside 1x1 to \(Resolution.maxDimension)x\(Resolution.maxDimension)"
+            )
+        }
+        guard fps > 0 else {
+            throw SerialWarpError.invalidCaptureConfiguration("fps must be above zero")
+        }
+        guard Self.depthRange.contains(queueDepth) else {
+            throw SerialWarpError.invalidCaptureConfiguration(
+                "queue depth \(queueDepth) is outside \(Self.depthRange.lowerBound) to \(Self.depthRange.upperBound)"
+            )
+        }
+        guard Self.depthRange.contains(channelCapacity) else {
+            throw SerialWarpError.invalidCaptureConfiguration(
+                "channel capacity \(channelCapacity) is outside \(Self.depthRange.lowerBound) to \(Self.depthRange.upperBound)"
+            )
+        }
     }
 
     /// Configuration string for debugging
@@ -43,6 +123,17 @@ struct CaptureConfiguration: Sendable {
     }
 }
 
+// MARK: - Queue Constants
+
+extension CaptureConfiguration {
+    static let defaultQueueDepth = 8
+
+    static let defaultChannelCapacity = 8
+
+    /// Allowed queue depths and channel capacities
+    static let depthRange = 1...16
+}
+
 // MARK: - Pixel Format Constants
 
 extension CaptureConfiguration {
@@ -51,4 +142,7 @@ extension CaptureConfiguration {
 
     /// NV12 pixel format (YUV 4:2:0 bi-planar)
     static let pixelFormatNV12: UInt32 = 0x34323076  // kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange
+
+    /// NV12 pixel format with full range luma and chroma
+    static let pixelFormatNV12FullRange: UInt32 = 0x34323066  // kCVPixelFormatType_420YpCbCr8BiPlanarFullRange
 }
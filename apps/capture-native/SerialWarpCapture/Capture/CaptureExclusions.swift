@@ -0,0 +1,57 @@
+import Foundation
+import CoreGraphics
+import ScreenCaptureKit
+
+/// A window that capture could include or leave out
+protocol ShareableWindow {
+    var windowID: CGWindowID { get }
+    /// Bundle ID of the app that owns the window, if known
+    var owningBundleIdentifier: String? { get }
+}
+
+@available(macOS 12.3, *)
+extension SCWindow: ShareableWindow {
+    var owningBundleIdentifier: String? {
+        owningApplication?.bundleIdentifier
+    }
+}
+
+/// Windows and applications left out of capture, e.g. password managers
+/// and notification popups. Leaving out every window is fine: the display
+/// is still captured and shows the wallpaper.
+struct CaptureExclusions: Codable, Equatable, Sendable {
+    /// Every window of these applications
+    var bundleIds: [String] = []
+
+    /// Individual windows, by CGWindowID
+    var windowIds: [UInt32] = []
+
+    static let none = CaptureExclusions()
+
+    var isEmpty: Bool {
+        bundleIds.isEmpty && windowIds.isEmpty
+    }
+
+    /// The windows among `windows` to leave out. Windows of an app that
+    /// isn't running yet can't be matched, so this is done again as the
+    /// windows on screen change.
+    func matching<W: ShareableWindow>(_ windows: [W]) -> [W] {
+        guard !isEmpty else { return [] }
+        let bundleIds = Set(bundleIds)
+        let windowIds = Set(windowIds)
+        return windows.filter { window in
+            windowIds.contains(window.windowID)
+                || window.owningBundleIdentifier.map(bundleIds.contains) == true
+        }
+    }
+}
+
+/// An application that can be excluded from capture
+struct RunningApplication: Identifiable, Equatable, Sendable {
+    let bundleId: String
+    let name: String
+
+    var id: String {
+        bundleId
+    }
+}
@@ -22,6 +22,19 @@ actor CaptureService: NSObject {
     /// Content filter
     private var filter: SCContentFilter?
 
+    /// The display being captured
+    private var display: SCDisplay?
+
+    /// What to leave out, and the windows that currently matches
+    private var exclusions = CaptureExclusions.none
+    private var excludedWindowIds: Set<CGWindowID> = []
+
+    /// Re-applies the exclusions as windows come and go
+    private var exclusionRefreshTask: Task<Void, Never>?
+
+    /// How often windows are checked against the exclusions
+    static let exclusionRefreshInterval: TimeInterval = 2
+
     /// Whether capture is active
     private(set) var isCapturing: Bool = false
 
@@ -31,10 +44,20 @@ actor CaptureService: NSObject {
     /// Frame handler for async stream
     private var frameContinuation: AsyncThrowingStream<CapturedFrame, Error>.Continuation?
 
+    /// Whether the screen last stopped changing, so no frames are delivered
+    private var isIdle = false
+
+    /// Told when the screen stops changing and when it changes again
+    private var idleHandler: (@Sendable (Bool) -> Void)?
+
     /// CIContext for converting frames to CGImage for preview
     private let ciContext = CIContext()
 
-    override init() {
+    /// Whether screen recording is allowed; replaceable in tests
+    private let preflight: @Sendable () -> Bool
+
+    init(preflight: @escaping @Sendable () -> Bool = { CaptureService.hasPermission() }) {
+        self.preflight = preflight
         super.init()
     }
 
@@ -50,9 +73,20 @@ actor CaptureService: NSObject {
         guard !isCapturing else {
             throw SerialWarpError.captureFailed("Already capturing")
         }
+        // Without permission capture would only deliver black frames, or
+        // fail with an error that doesn't say why
+        guard preflight() else {
+            throw SerialWarpError.permissionDenied
+        }
+        try config.validate()
 
         // Get shareable content
-        let content = try await SCShareableContent.excludingDesktopWindows(false, onScreenWindowsOnly: true)
+        let content: SCShareableContent
+        do {
+            content = try await SCShareableContent.excludingDesktopWindows(false, onScreenWindowsOnly: true)
+        } catch {
+            throw Self.mapCaptureError(error)
+        }
 
         // Find the target display
         guard let display = content.displays.first(where: { $0.displayID == displayId }) else {
@@ -60,19 +94,18 @@ actor CaptureService: NSObject {
         }
 
         // Create content filter
-        filter = SCContentFilter(display: display, excludingWindows: [])
+        self.display = display
+        exclusions = config.exclusions
+        let excluded = exclusions.matching(content.windows)
+        excludedWindowIds = Set(excluded.map(\.windowID))
+        filter = SCContentFilter(display: display, excludingWindows: excluded)
 
-        // Create stream configuration
-        let streamConfig = SCStreamConfiguration()
-        streamConfig.width = Int(config.width)
-        streamConfig.height = Int(config.height)
-        streamConfig.minimumFrameInterval = CMTime(value: 1, timescale: CMTimeScale(config.fps))
-        streamConfig.pixelFormat = config.pixelFormat
-        streamConfig.showsCursor = config.showCursor
-        streamConfig.queueDepth = config.queueDepth
+        let streamConfig = Self.streamConfiguration(for: config)
 
         // Create the async stream
-        let frameStream = AsyncThrowingStream<CapturedFrame, Error> { continuation in
+        let frameStream = AsyncThrowingStream<CapturedFrame, Error>(
+            bufferingPolicy: Self.frameBuffering(for: config)
+        ) { continuation in
             self.frameContinuation = continuation
 
             continuation.onTermination = { @Sendable _ in
@@ -85,16 +118,27 @@ actor CaptureService: NSObject {
         try stream?.addStreamOutput(self, type: .screen, sampleHandlerQueue: .global(qos: .userInteractive))
 
         // Start capture
-        try await stream?.startCapture()
+        do {
+            try await stream?.startCapture()
+        } catch {
+            throw Self.mapCaptureError(error)
+        }
 
         isCapturing = true
         configuration = config
+        startExclusionRefresh()
 
         print("[Capture] Started capturing display \(displayId) at \(config.description)")
 
         return frameStream
     }
 
+    /// Call `handler` with true when the screen stops changing, so capture
+    /// delivers no frames, and with false when frames come again
+    func setIdleHandler(_ handler: (@Sendable (Bool) -> Void)?) {
+        idleHandler = handler
+    }
+
     /// Stop capturing
     func stopCapture() async {
         guard isCapturing else { return }
@@ -105,15 +149,135 @@ actor CaptureService: NSObject {
             print("[Capture] Error stopping capture: \(error)")
         }
 
+        tearDown(finishingWith: nil)
+        print("[Capture] Stopped capturing")
+    }
+
+    /// The system stopped the stream, so end the frame stream with `error`
+    /// rather than leaving its consumer waiting for frames that won't come
+    private func handleStreamStopped(_ error: Error) {
+        guard isCapturing else { return }
+        tearDown(finishingWith: error)
+    }
+
+    private func tearDown(finishingWith error: Error?) {
+        exclusionRefreshTask?.cancel()
+        exclusionRefreshTask = nil
+
         stream = nil
         filter = nil
+        display = nil
+        exclusions = .none
+        excludedWindowIds = []
         isCapturing = false
         configuration = nil
+        isIdle = false
 
-        frameContinuation?.finish()
+        frameContinuation?.finish(throwing: error)
         frameContinuation = nil
+    }
 
-        print("[Capture] Stopped capturing")
+    /// Change the frame rate of the running capture without restarting it.
+    /// Frame timestamps carry on from where they were.
+    func setFps(_ fps: UInt32) async throws {
+        guard let config = configuration else {
+            throw SerialWarpError.captureUpdateFailed("Not capturing")
+        }
+        try await applyConfiguration(config.with(fps: fps))
+    }
+
+    /// Change the size of the running capture without restarting it
+    func setDimensions(width: UInt32, height: UInt32) async throws {
+        guard let config = configuration else {
+            throw SerialWarpError.captureUpdateFailed("Not capturing")
+        }
+        try await applyConfiguration(config.with(width: width, height: height))
+    }
+
+    private func applyConfiguration(_ config: CaptureConfiguration) async throws {
+        guard isCapturing, let stream = stream else {
+            throw SerialWarpError.captureUpdateFailed("Not capturing")
+        }
+        try config.validate()
+
+        do {
+            try await stream.updateConfiguration(Self.streamConfiguration(for: config))
+        } catch {
+            throw SerialWarpError.captureUpdateFailed(error.localizedDescription)
+        }
+        configuration = config
+
+        print("[Capture] Now capturing at \(config.description)")
+    }
+
+    /// Frames match the encoder's even coded size. An odd display is drawn
+    /// 1:1 into the top-left, and the sink crops off the empty column and
+    /// row.
+    static func streamConfiguration(for config: CaptureConfiguration) -> SCStreamConfiguration {
+        let streamConfig = SCStreamConfiguration()
+        let coded = config.codedSize
+        streamConfig.width = Int(coded.width)
+        streamConfig.height = Int(coded.height)
+        streamConfig.destinationRect = CGRect(x: 0, y: 0, width: Int(config.width), height: Int(config.height))
+        streamConfig.minimumFrameInterval = CMTime(value: 1, timescale: CMTimeScale(config.fps))
+        streamConfig.pixelFormat = config.pixelFormat
+        streamConfig.showsCursor = config.showCursor
+        streamConfig.queueDepth = config.queueDepth
+        return streamConfig
+    }
+
+    /// Hold up to `channelCapacity` frames for the pipeline, dropping the
+    /// oldest when it falls behind, so what it encodes next is recent
+    static func frameBuffering(
+        for config: CaptureConfiguration
+    ) -> AsyncThrowingStream<CapturedFrame, Error>.Continuation.BufferingPolicy {
+        .bufferingNewest(config.channelCapacity)
+    }
+
+    /// Change what's left out of a running capture
+    func updateExclusions(_ exclusions: CaptureExclusions) async throws {
+        self.exclusions = exclusions
+        guard isCapturing else { return }
+        try await refreshExclusions(force: true)
+        startExclusionRefresh()
+    }
+
+    /// Match the exclusions against the windows on screen now, updating the
+    /// filter if that leaves out different windows
+    private func refreshExclusions(force: Bool = false) async throws {
+        guard let stream = stream, let display = display else { return }
+
+        let content = try await SCShareableContent.excludingDesktopWindows(false, onScreenWindowsOnly: true)
+        let excluded = exclusions.matching(content.windows)
+        let ids = Set(excluded.map(\.windowID))
+        guard force || ids != excludedWindowIds else { return }
+
+        let filter = SCContentFilter(display: display, excludingWindows: excluded)
+        try await stream.updateContentFilter(filter)
+        self.filter = filter
+        excludedWindowIds = ids
+
+        print("[Capture] Excluding \(ids.count) window(s)")
+    }
+
+    /// Catch windows that open after capture started, e.g. an excluded app
+    /// being launched. Nothing to watch without exclusions.
+    private func startExclusionRefresh() {
+        exclusionRefreshTask?.cancel()
+        exclusionRefreshTask = nil
+        guard !exclusions.isEmpty else { return }
+
+        exclusionRefreshTask = Task { [weak self] in
+            while !Task.isCancelled {
+                try? await Task.sleep(nanoseconds: UInt64(Self.exclusionRefreshInterval * 1_000_000_000))
+                guard !Task.isCancelled, let self = self else { break }
+                do {
+                    try await self.refreshExclusions()
+                } catch {
+                    print("[Capture] Error refreshing exclusions: \(error)")
+                }
+            }
+        }
     }
 
     /// Handle stream termination
@@ -136,13 +300,14 @@ actor CaptureService: NSObject {
 extension CaptureService: SCStreamDelegate {
     nonisolated func stream(_ stream: SCStream, didStopWithError error: Error) {
         print("[Capture] Stream stopped with error: \(error)")
+        let error = Self.stopError(for: error)
 
         Task { @MainActor in
             self.delegate?.captureService(self, didEncounterError: error)
         }
 
         Task {
-            await self.stopCapture()
+            await self.handleStreamStopped(error)
         }
     }
 }
@@ -154,6 +319,13 @@ extension CaptureService: SCStreamOutput {
     nonisolated func stream(_ stream: SCStream, didOutputSampleBuffer sampleBuffer: CMSampleBuffer, of type: SCStreamOutputType) {
         guard type == .screen else { return }
 
+        // Idle buffers carry no image, only that nothing changed
+        if let status = Self.frameStatus(of: sampleBuffer), status == .idle || status == .complete {
+            Task {
+                await self.setIdle(status == .idle)
+            }
+        }
+
         // Create captured frame
         guard let frame = CapturedFrame(sampleBuffer: sampleBuffer) else {
             return
@@ -174,19 +346,71 @@ extension CaptureService: SCStreamOutput {
     private func yieldFrame(_ frame: CapturedFrame) {
         frameContinuation?.yield(frame)
     }
+
+    private func setIdle(_ idle: Bool) {
+        guard isCapturing, idle != isIdle else { return }
+        isIdle = idle
+        idleHandler?(idle)
+    }
+
+    /// Whether ScreenCaptureKit says the buffer is a new frame, or idle etc.
+    nonisolated private static func frameStatus(of sampleBuffer: CMSampleBuffer) -> SCFrameStatus? {
+        guard let attachments = CMSampleBufferGetSampleAttachmentsArray(sampleBuffer, createIfNecessary: false)
+                as? [[SCStreamFrameInfo: Any]],
+              let rawStatus = attachments.first?[.status] as? Int else {
+            return nil
+        }
+        return SCFrameStatus(rawValue: rawStatus)
+    }
 }
 
 // MARK: - Permission Check
 
+/// Whether the app may record the screen
+enum ScreenRecordingPermission: String, Sendable {
+    case granted
+    case denied
+}
+
 @available(macOS 12.3, *)
 extension CaptureService {
+    /// Privacy & Security > Screen Recording in System Settings
+    static let permissionSettingsURL = URL(
+        string: "x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture"
+    )!
+
     /// Check if screen recording permission is granted
     static func hasPermission() -> Bool {
         CGPreflightScreenCaptureAccess()
     }
 
-    /// Request screen recording permission
+    /// Whether screen recording is allowed, without prompting
+    static func permissionStatus() -> ScreenRecordingPermission {
+        hasPermission() ? .granted : .denied
+    }
+
+    /// Request screen recording permission. macOS only prompts the first
+    /// time; after that the user has to allow it in System Settings.
     static func requestPermission() -> Bool {
         CGRequestScreenCaptureAccess()
     }
+
+    /// Map ScreenCaptureKit's refusal to `SerialWarpError.permissionDenied`;
+    /// other errors are returned as they are
+    static func mapCaptureError(_ error: Error) -> Error {
+        if let error = error as? SCStreamError, error.code == .userDeclined {
+            return SerialWarpError.permissionDenied
+        }
+        return error
+    }
+
+    /// The error for ScreenCaptureKit stopping a running stream: permission
+    /// revoked maps to `permissionDenied`, anything else to `captureStopped`
+    static func stopError(for error: Error) -> Error {
+        let mapped = mapCaptureError(error)
+        if mapped is SerialWarpError {
+            return mapped
+        }
+        return SerialWarpError.captureStopped(error.localizedDescription)
+    }
 }
@@ -46,6 +46,43 @@ struct CapturedFrame: @unchecked Sendable {
         self.presentationTime = CMSampleBufferGetPresentationTimeStamp(sampleBuffer)
     }
 
+    /// A black frame of the given size and format (BGRA or NV12), or nil
+    /// if the format isn't one of those or the buffer can't be created
+    static func black(width: Int, height: Int, pixelFormat: OSType, presentationTime: CMTime) -> CapturedFrame? {
+        var buffer: CVPixelBuffer?
+        let attributes = [kCVPixelBufferIOSurfacePropertiesKey: [:]] as CFDictionary
+        guard CVPixelBufferCreate(kCFAllocatorDefault, width, height, pixelFormat, attributes, &buffer) == kCVReturnSuccess,
+              let pixelBuffer = buffer else {
+            return nil
+        }
+
+        CVPixelBufferLockBaseAddress(pixelBuffer, [])
+        defer { CVPixelBufferUnlockBaseAddress(pixelBuffer, []) }
+
+        switch pixelFormat {
+        case kCVPixelFormatType_32BGRA:
+            guard let baseAddress = CVPixelBufferGetBaseAddress(pixelBuffer) else { return nil }
+            let bytesPerRow = CVPixelBufferGetBytesPerRow(pixelBuffer)
+            let pixels = baseAddress.bindMemory(to: UInt32.self, capacity: bytesPerRow / 4 * height)
+            // Opaque black: bytes B, G, R = 0 then A = 255
+            pixels.initialize(repeating: UInt32(0xFF00_0000).littleEndian, count: bytesPerRow / 4 * height)
+        case kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange, kCVPixelFormatType_420YpCbCr8BiPlanarFullRange:
+            // Black is luma 16 in video range and 0 in full range; chroma is
+            // neutral either way
+            let luma: UInt8 = pixelFormat == kCVPixelFormatType_420YpCbCr8BiPlanarFullRange ? 0 : 16
+            for (plane, value) in [(0, luma), (1, UInt8(128))] {
+                guard let baseAddress = CVPixelBufferGetBaseAddressOfPlane(pixelBuffer, plane) else { return nil }
+                let size = CVPixelBufferGetBytesPerRowOfPlane(pixelBuffer, plane)
+                    * CVPixelBufferGetHeightOfPlane(pixelBuffer, plane)
+                memset(baseAddress, Int32(value), size)
+            }
+        default:
+            return nil
+        }
+
+        return CapturedFrame(pixelBuffer: pixelBuffer, presentationTime: presentationTime)
+    }
+
     /// Lock the pixel buffer for reading
     func withLockedBaseAddress<T>(_ body: (UnsafeRawPointer, Int) throws -> T) rethrows -> T {
         CVPixelBufferLockBaseAddress(pixelBuffer, .readOnly)
@@ -57,12 +94,29 @@ struct CapturedFrame: @unchecked Sendable {
         return try body(baseAddress, bytesPerRow)
     }
 
-    /// Get the frame data as Data (copies the buffer)
+    /// Get the frame data as Data (copies the buffer). Planar formats such
+    /// as NV12 come back as each plane in turn.
     func getData() -> Data {
-        withLockedBaseAddress { baseAddress, bytesPerRow in
-            let height = CVPixelBufferGetHeight(pixelBuffer)
-            let dataSize = bytesPerRow * height
-            return Data(bytes: baseAddress, count: dataSize)
+        guard CVPixelBufferIsPlanar(pixelBuffer) else {
+            return withLockedBaseAddress { baseAddress, bytesPerRow in
+                let height = CVPixelBufferGetHeight(pixelBuffer)
+                let dataSize = bytesPerRow * height
+                return Data(bytes: baseAddress, count: dataSize)
+            }
+        }
+
+        CVPixelBufferLockBaseAddress(pixelBuffer, .readOnly)
+        defer { CVPixelBufferUnlockBaseAddress(pixelBuffer, .readOnly) }
+
+        var data = Data()
+        for plane in 0..<CVPixelBufferGetPlaneCount(pixelBuffer) {
+            guard let baseAddress = CVPixelBufferGetBaseAddressOfPlane(pixelBuffer, plane) else {
+                continue
+            }
+            let bytesPerRow = CVPixelBufferGetBytesPerRowOfPlane(pixelBuffer, plane)
+            let height = CVPixelBufferGetHeightOfPlane(pixelBuffer, plane)
+            data.append(Data(bytes: baseAddress, count: bytesPerRow * height))
         }
+        return data
     }
 }
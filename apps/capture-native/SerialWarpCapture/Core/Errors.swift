@@ -29,6 +29,10 @@ enum SerialWarpError: LocalizedError {
     /// Unexpected packet type received
     case unexpectedPacketType(expected: String, actual: UInt8)
 
+    /// Width or height out of range, or coded and display sizes that don't
+    /// match
+    case invalidResolution(width: UInt32, height: UInt32)
+
     /// Handshake failed
     case handshakeFailed(_ reason: String)
 
@@ -90,6 +94,9 @@ enum SerialWarpError: LocalizedError {
     /// Pixel buffer operation failed
     case pixelBufferFailed(status: Int32)
 
+    /// Invalid encoder configuration
+    case invalidEncoderConfiguration(_ reason: String)
+
     // MARK: - Capture Errors
 
     /// Display not found
@@ -107,6 +114,12 @@ enum SerialWarpError: LocalizedError {
     /// Capture failed
     case captureFailed(_ reason: String)
 
+    /// A running capture couldn't be reconfigured
+    case captureUpdateFailed(_ reason: String)
+
+    /// The system stopped a running capture, e.g. the display went away
+    case captureStopped(_ reason: String)
+
     // MARK: - Virtual Display Errors
 
     /// Virtual display creation failed
@@ -124,6 +137,9 @@ enum SerialWarpError: LocalizedError {
     /// Display not available
     case displayNotAvailable
 
+    /// No virtual display has the handle
+    case virtualDisplayNotFound(handle: UInt32)
+
     // MARK: - LocalizedError Implementation
 
     var errorDescription: String? {
@@ -145,6 +161,8 @@ enum SerialWarpError: LocalizedError {
             return "Invalid sequence number: expected \(expected), got \(actual)"
         case .unexpectedPacketType(let expected, let actual):
             return "Unexpected packet type: expected \(expected), got 0x\(String(actual, radix: 16))"
+        case .invalidResolution(let width, let height):
+            return "Invalid resolution \(width)x\(height)"
         case .handshakeFailed(let reason):
             return "Handshake failed: \(reason)"
         case .frameReassemblyError(let reason):
@@ -187,6 +205,8 @@ enum SerialWarpError: LocalizedError {
             return "Invalid encoder input: \(reason)"
         case .pixelBufferFailed(let status):
             return "Pixel buffer operation failed with status: \(status)"
+        case .invalidEncoderConfiguration(let reason):
+            return "Invalid encoder configuration: \(reason)"
 
         // Capture
         case .displayNotFound(let displayId):
@@ -194,11 +214,16 @@ enum SerialWarpError: LocalizedError {
         case .captureStreamCreationFailed:
             return "Capture stream creation failed"
         case .permissionDenied:
-            return "Screen recording permission denied"
+            return "Screen recording permission denied. Allow SerialWarp Capture in System Settings > Privacy & Security > Screen Recording."
         case .invalidCaptureConfiguration(let reason):
             return "Invalid capture configuration: \(reason)"
         case .captureFailed(let reason):
             return "Capture failed: \(reason)"
+        case .captureUpdateFailed(let reason):
+            return "Capture update failed: \(reason)"
+
+        case .captureStopped(let reason):
+            return "Capture stopped: \(reason)"
 
         // Virtual Display
         case .virtualDisplayCreationFailed:
@@ -211,6 +236,8 @@ enum SerialWarpError: LocalizedError {
             return "Virtual display already exists"
         case .displayNotAvailable:
             return "Display not available"
+        case .virtualDisplayNotFound(let handle):
+            return "No virtual display with handle \(handle)"
         }
     }
 }
@@ -0,0 +1,58 @@
+import Foundation
+
+/// A frame size in pixels, matching `Resolution` in serialwarp-core.
+///
+/// 4:2:0 chroma covers 2x2 blocks of pixels, so streams are coded at an
+/// even size. An odd visible size is padded out to `coded` and the sink
+/// crops the padding back off using the visible size sent in START.
+///
+/// On a HiDPI display a point covers `scale` x `scale` pixels. Capture,
+/// encode and the protocol's sizes are always pixels; only display modes
+/// and windows are sized in points.
+struct Resolution: Equatable, Sendable, CustomStringConvertible {
+    /// Largest width or height accepted, the most H.264 level 6.2 allows
+    static let maxDimension: UInt32 = 8192
+
+    let width: UInt32
+    let height: UInt32
+
+    /// Whether both dimensions are between 1 and `maxDimension`
+    var isValid: Bool {
+        (1...Self.maxDimension).contains(width) && (1...Self.maxDimension).contains(height)
+    }
+
+    /// The size rounded up to even, as the stream is coded
+    var coded: Resolution {
+        // Saturates like the Rust side, for sizes that are invalid anyway
+        let roundUp = { (value: UInt32) in value == .max ? value : value + value % 2 }
+        return Resolution(width: roundUp(width), height: roundUp(height))
+    }
+
+    /// Whether coding this size needs a padding column or row
+    var isPadded: Bool {
+        coded != self
+    }
+
+    /// The size in points at `scale` pixels per point, rounded up so the
+    /// points cover every pixel. A scale of 0 is taken as 1.
+    func toPoints(scale: UInt16) -> Resolution {
+        let scale = UInt32(max(scale, 1))
+        let roundUp = { (pixels: UInt32) in pixels / scale + (pixels % scale == 0 ? 0 : 1) }
+        return Resolution(width: roundUp(width), height: roundUp(height))
+    }
+
+    /// The size in pixels of this many points at `scale` pixels per point.
+    /// A scale of 0 is taken as 1.
+    func toPixels(scale: UInt16) -> Resolution {
+        let scale = UInt32(max(scale, 1))
+        let multiply = { (points: UInt32) -> UInt32 in
+            let (pixels, overflow) = points.multipliedReportingOverflow(by: scale)
+            return overflow ? .max : pixels
+        }
+        return Resolution(width: multiply(width), height: multiply(height))
+    }
+
+    var description: String {
+        "\(width)x\(height)"
+    }
+}
@@ -5,7 +5,7 @@ import Foundation
 ///   - software_version: u16 (2 bytes)
 ///   - min_protocol_version: u16 (2 bytes)
 ///   - max_protocol_version: u16 (2 bytes)
-///   - reserved1: u16 (2 bytes)
+///   - max_scale: u16 (2 bytes) - largest pixels per point, 0 meaning 1
 ///   - max_width: u32 (4 bytes)
 ///   - max_height: u32 (4 bytes)
 ///   - max_fps_fixed: u32 (4 bytes) - Fixed-point 16.16
@@ -15,7 +15,9 @@ struct HelloPayload: Sendable {
     let softwareVersion: UInt16
     let minProtocolVersion: UInt16
     let maxProtocolVersion: UInt16
-    let reserved1: UInt16
+    /// Largest scale the sender can capture or show; 0 from peers that
+    /// predate it. See `effectiveMaxScale`.
+    let maxScale: UInt16
     let maxWidth: UInt32
     let maxHeight: UInt32
     let maxFpsFixed: UInt32  // Fixed-point 16.16 format
@@ -28,12 +30,13 @@ struct HelloPayload: Sendable {
         maxWidth: UInt32,
         maxHeight: UInt32,
         maxFps: UInt32,
-        capabilities: UInt32
+        capabilities: UInt32,
+        maxScale: UInt16 = 1
     ) {
         self.softwareVersion = softwareVersion
         self.minProtocolVersion = UInt16(SWRPConstants.protocolVersion)
         self.maxProtocolVersion = UInt16(SWRPConstants.protocolVersion)
-        self.reserved1 = 0
+        self.maxScale = maxScale
         self.maxWidth = maxWidth
         self.maxHeight = maxHeight
         self.maxFpsFixed = maxFps << 16  // Convert to fixed 16.16
@@ -46,7 +49,7 @@ struct HelloPayload: Sendable {
         softwareVersion: UInt16,
         minProtocolVersion: UInt16,
         maxProtocolVersion: UInt16,
-        reserved1: UInt16,
+        maxScale: UInt16,
         maxWidth: UInt32,
         maxHeight: UInt32,
         maxFpsFixed: UInt32,
@@ -56,7 +59,7 @@ struct HelloPayload: Sendable {
         self.softwareVersion = softwareVersion
         self.minProtocolVersion = minProtocolVersion
         self.maxProtocolVersion = maxProtocolVersion
-        self.reserved1 = reserved1
+        self.maxScale = maxScale
         self.maxWidth = maxWidth
         self.maxHeight = maxHeight
         self.maxFpsFixed = maxFpsFixed
@@ -69,6 +72,11 @@ struct HelloPayload: Sendable {
         maxFpsFixed >> 16
     }
 
+    /// Largest scale the sender handles, at least 1
+    var effectiveMaxScale: UInt16 {
+        max(maxScale, 1)
+    }
+
     /// Check if HiDPI capability is set
     var supportsHidpi: Bool {
         capabilities & SWRPConstants.Capabilities.hidpi != 0
@@ -79,13 +87,18 @@ struct HelloPayload: Sendable {
         capabilities & SWRPConstants.Capabilities.audio != 0
     }
 
+    /// Check if PAUSE capability is set
+    var supportsPause: Bool {
+        capabilities & SWRPConstants.Capabilities.pause != 0
+    }
+
     /// Serialize payload to bytes (28 bytes)
     func toBytes() -> Data {
         var data = Data.withCapacity(SWRPConstants.PayloadSize.hello)
         data.appendUInt16LE(softwareVersion)
         data.appendUInt16LE(minProtocolVersion)
         data.appendUInt16LE(maxProtocolVersion)
-        data.appendUInt16LE(reserved1)
+        data.appendUInt16LE(maxScale)
         data.appendUInt32LE(maxWidth)
         data.appendUInt32LE(maxHeight)
         data.appendUInt32LE(maxFpsFixed)
@@ -106,7 +119,7 @@ struct HelloPayload: Sendable {
         guard let softwareVersion = data.readUInt16LE(at: 0),
               let minProtocolVersion = data.readUInt16LE(at: 2),
               let maxProtocolVersion = data.readUInt16LE(at: 4),
-              let reserved1 = data.readUInt16LE(at: 6),
+              let maxScale = data.readUInt16LE(at: 6),
               let maxWidth = data.readUInt32LE(at: 8),
               let maxHeight = data.readUInt32LE(at: 12),
               let maxFpsFixed = data.readUInt32LE(at: 16),
@@ -119,7 +132,7 @@ struct HelloPayload: Sendable {
             softwareVersion: softwareVersion,
             minProtocolVersion: minProtocolVersion,
             maxProtocolVersion: maxProtocolVersion,
-            reserved1: reserved1,
+            maxScale: maxScale,
             maxWidth: maxWidth,
             maxHeight: maxHeight,
             maxFpsFixed: maxFpsFixed,
@@ -139,6 +139,11 @@ extension Packet {
         Packet(type: .stopAck, sequence: sequence, payload: Data())
     }
 
+    /// Create a PAUSE packet
+    static func pause(sequence: UInt32, payload: PausePayload) -> Packet {
+        Packet(type: .pause, sequence: sequence, payload: payload.toBytes())
+    }
+
     /// Create a PING packet
     static func ping(sequence: UInt32, payload: PingPayload) -> Packet {
         Packet(type: .ping, sequence: sequence, payload: payload.toBytes())
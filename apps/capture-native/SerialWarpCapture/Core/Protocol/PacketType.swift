@@ -10,6 +10,7 @@ enum PacketType: UInt8, Sendable, CaseIterable {
     case frameAck = 0x11
     case stop = 0x30
     case stopAck = 0x31
+    case pause = 0x32
     case ping = 0x40
     case pong = 0x41
 
@@ -24,6 +25,7 @@ enum PacketType: UInt8, Sendable, CaseIterable {
         case .frameAck: return "FRAME_ACK"
         case .stop: return "STOP"
         case .stopAck: return "STOP_ACK"
+        case .pause: return "PAUSE"
         case .ping: return "PING"
         case .pong: return "PONG"
         }
@@ -32,7 +34,7 @@ enum PacketType: UInt8, Sendable, CaseIterable {
     /// Whether this packet type is a request (vs a response)
     var isRequest: Bool {
         switch self {
-        case .hello, .start, .frame, .stop, .ping:
+        case .hello, .start, .frame, .stop, .pause, .ping:
             return true
         case .helloAck, .startAck, .frameAck, .stopAck, .pong:
             return false
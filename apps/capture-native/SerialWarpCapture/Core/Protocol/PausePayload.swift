@@ -0,0 +1,40 @@
+import Foundation
+
+/// PAUSE payload (4 bytes): frames stopping, or starting again, while the
+/// session carries on
+/// Layout:
+///   - paused: u8 (1 byte) - Nonzero when frames stop
+///   - reserved: 3 bytes
+struct PausePayload: Sendable, Equatable {
+    let paused: Bool
+
+    init(paused: Bool) {
+        self.paused = paused
+    }
+
+    /// Serialize payload to bytes (4 bytes)
+    func toBytes() -> Data {
+        var data = Data.withCapacity(SWRPConstants.PayloadSize.pause)
+        data.appendUInt8(paused ? 1 : 0)
+        for _ in 0..<3 {
+            data.appendUInt8(0) // reserved
+        }
+        return data
+    }
+
+    /// Parse payload from bytes
+    static func parse(_ data: Data) throws -> PausePayload {
+        guard data.count >= SWRPConstants.PayloadSize.pause else {
+            throw SerialWarpError.invalidPayloadLength(
+                expected: SWRPConstants.PayloadSize.pause,
+                actual: data.count
+            )
+        }
+
+        guard let paused = data.readUInt8(at: 0) else {
+            throw SerialWarpError.parseError("Failed to parse PausePayload flag")
+        }
+
+        return PausePayload(paused: paused != 0)
+    }
+}
@@ -25,16 +25,21 @@ enum SWRPConstants {
     enum Capabilities {
         static let hidpi: UInt32 = 0x01
         static let audio: UInt32 = 0x02
+        /// Frames stopping and starting again are announced with PAUSE
+        static let pause: UInt32 = 0x200
     }
 
     /// Payload sizes for each packet type
     enum PayloadSize {
         static let hello: Int = 28
         static let start: Int = 24
+        /// START with the display size appended
+        static let startExtended: Int = 32
         static let startAck: Int = 4
         static let frameHeader: Int = 32
         static let frameAck: Int = 16
         static let ping: Int = 8
         static let pong: Int = 16
+        static let pause: Int = 4
     }
 }
@@ -1,9 +1,9 @@
 import Foundation
 
-/// START payload (24 bytes)
+/// START payload (32 bytes; peers without the display size send 24)
 /// Layout:
-///   - width: u32 (4 bytes)
-///   - height: u32 (4 bytes)
+///   - width: u32 (4 bytes) - coded width, always even
+///   - height: u32 (4 bytes) - coded height, always even
 ///   - fps_fixed: u32 (4 bytes) - Fixed-point 16.16
 ///   - bitrate_bps: u32 (4 bytes)
 ///   - pixel_format: u8 (1 byte)
@@ -11,7 +11,11 @@ import Foundation
 ///   - audio_sample_rate: u16 (2 bytes)
 ///   - audio_channels: u8 (1 byte)
 ///   - audio_bits: u8 (1 byte)
-///   - reserved: u16 (2 bytes)
+///   - scale: u16 (2 bytes) - pixels per point, 0 meaning 1
+///   - display_width: u32 (4 bytes) - visible width, the sink crops to it
+///   - display_height: u32 (4 bytes) - visible height
+///
+/// Sizes are in pixels; the sink divides by `scale` to size its window.
 struct StartPayload: Sendable {
     let width: UInt32
     let height: UInt32
@@ -22,12 +26,17 @@ struct StartPayload: Sendable {
     let audioSampleRate: UInt16
     let audioChannels: UInt8
     let audioBits: UInt8
-    let reserved: UInt16
-
-    /// Create a new START payload
-    init(width: UInt32, height: UInt32, fps: UInt32, bitrateBps: UInt32) {
-        self.width = width
-        self.height = height
+    /// Pixels per point; 0 from peers that predate it. See `effectiveScale`.
+    let scale: UInt16
+    let displayWidth: UInt32
+    let displayHeight: UInt32
+
+    /// Create a new START payload for a `width` x `height` pixel display,
+    /// coded at the next even size
+    init(width: UInt32, height: UInt32, fps: UInt32, bitrateBps: UInt32, scale: UInt16 = 1) {
+        let coded = Resolution(width: width, height: height).coded
+        self.width = coded.width
+        self.height = coded.height
         self.fpsFixed = fps << 16  // Convert to fixed 16.16
         self.bitrateBps = bitrateBps
         self.pixelFormat = 0  // NV12
@@ -35,7 +44,9 @@ struct StartPayload: Sendable {
         self.audioSampleRate = 0
         self.audioChannels = 0
         self.audioBits = 0
-        self.reserved = 0
+        self.scale = scale
+        self.displayWidth = width
+        self.displayHeight = height
     }
 
     /// Create from all fields (used during parsing)
@@ -49,7 +60,9 @@ struct StartPayload: Sendable {
         audioSampleRate: UInt16,
         audioChannels: UInt8,
         audioBits: UInt8,
-        reserved: UInt16
+        scale: UInt16,
+        displayWidth: UInt32,
+        displayHeight: UInt32
     ) {
         self.width = width
         self.height = height
@@ -60,7 +73,29 @@ struct StartPayload: Sendable {
         self.audioSampleRate = audioSampleRate
         self.audioChannels = audioChannels
         self.audioBits = audioBits
-        self.reserved = reserved
+        self.scale = scale
+        self.displayWidth = displayWidth
+        self.displayHeight = displayHeight
+    }
+
+    /// Size the stream is coded at
+    var codedSize: Resolution {
+        Resolution(width: width, height: height)
+    }
+
+    /// Size the sink shows, with any padding cropped off
+    var displaySize: Resolution {
+        Resolution(width: displayWidth, height: displayHeight)
+    }
+
+    /// Pixels per point, at least 1
+    var effectiveScale: UInt16 {
+        max(scale, 1)
+    }
+
+    /// The display size in points, for sizing the sink's window
+    var pointSize: Resolution {
+        displaySize.toPoints(scale: effectiveScale)
     }
 
     /// Get FPS as integer (extracts whole part from fixed 16.16)
@@ -68,9 +103,9 @@ struct StartPayload: Sendable {
         fpsFixed >> 16
     }
 
-    /// Serialize payload to bytes (24 bytes)
+    /// Serialize payload to bytes (32 bytes)
     func toBytes() -> Data {
-        var data = Data.withCapacity(SWRPConstants.PayloadSize.start)
+        var data = Data.withCapacity(SWRPConstants.PayloadSize.startExtended)
         data.appendUInt32LE(width)
         data.appendUInt32LE(height)
         data.appendUInt32LE(fpsFixed)
@@ -80,7 +115,9 @@ struct StartPayload: Sendable {
         data.appendUInt16LE(audioSampleRate)
         data.appendUInt8(audioChannels)
         data.appendUInt8(audioBits)
-        data.appendUInt16LE(reserved)
+        data.appendUInt16LE(scale)
+        data.appendUInt32LE(displayWidth)
+        data.appendUInt32LE(displayHeight)
         return data
     }
 
@@ -102,13 +139,29 @@ struct StartPayload: Sendable {
               let audioSampleRate = data.readUInt16LE(at: 18),
               let audioChannels = data.readUInt8(at: 20),
               let audioBits = data.readUInt8(at: 21),
-              let reserved = data.readUInt16LE(at: 22) else {
+              let scale = data.readUInt16LE(at: 22) else {
             throw SerialWarpError.parseError("Failed to parse StartPayload fields")
         }
 
-        // Validate dimensions
-        guard width > 0 && height > 0 else {
-            throw SerialWarpError.invalidPayloadLength(expected: 1, actual: 0)
+        // Without the extension the whole coded frame is visible
+        var displayWidth = width
+        var displayHeight = height
+        if data.count >= SWRPConstants.PayloadSize.startExtended,
+           let extendedWidth = data.readUInt32LE(at: 24),
+           let extendedHeight = data.readUInt32LE(at: 28) {
+            displayWidth = extendedWidth
+            displayHeight = extendedHeight
+        }
+
+        // The coded size must be a valid even size, and the display size at
+        // most one padding column and row smaller
+        let coded = Resolution(width: width, height: height)
+        let display = Resolution(width: displayWidth, height: displayHeight)
+        guard coded.isValid && !coded.isPadded else {
+            throw SerialWarpError.invalidResolution(width: width, height: height)
+        }
+        guard display.isValid && display.coded == coded else {
+            throw SerialWarpError.invalidResolution(width: displayWidth, height: displayHeight)
         }
 
         return StartPayload(
@@ -121,7 +174,9 @@ struct StartPayload: Sendable {
             audioSampleRate: audioSampleRate,
             audioChannels: audioChannels,
             audioBits: audioBits,
-            reserved: reserved
+            scale: scale,
+            displayWidth: displayWidth,
+            displayHeight: displayHeight
         )
     }
 }
@@ -1,11 +1,19 @@
 import Foundation
 
 /// Configuration for creating a virtual display
-struct DisplayConfiguration: Sendable {
-    /// Display width in pixels
+struct DisplayConfiguration: Equatable, Sendable {
+    /// Serial number of the first virtual display; `VirtualDisplayManager`
+    /// numbers the others from it
+    static let defaultSerialNumber: UInt32 = 12345
+
+    /// Name of the first virtual display
+    static let defaultName = "SerialWarp"
+
+    /// Requested width in pixels. A HiDPI mode is whole points, so the
+    /// display actually has `pixelSize`.
     let width: UInt32
 
-    /// Display height in pixels
+    /// Requested height in pixels
     let height: UInt32
 
     /// Refresh rate in Hz
@@ -26,8 +34,8 @@ struct DisplayConfiguration: Sendable {
         height: UInt32,
         refreshRate: UInt32,
         hidpiEnabled: Bool = false,
-        serialNumber: UInt32 = 12345,
-        name: String = "SerialWarp"
+        serialNumber: UInt32 = defaultSerialNumber,
+        name: String = defaultName
     ) {
         self.width = width
         self.height = height
@@ -37,18 +45,38 @@ struct DisplayConfiguration: Sendable {
         self.name = name
     }
 
-    /// The actual mode dimensions (for HiDPI this is half the display resolution)
-    var modeWidth: UInt32 {
-        hidpiEnabled ? width / 2 : width
+    /// The same display with a different identity
+    func withIdentity(serialNumber: UInt32, name: String) -> DisplayConfiguration {
+        DisplayConfiguration(
+            width: width,
+            height: height,
+            refreshRate: refreshRate,
+            hidpiEnabled: hidpiEnabled,
+            serialNumber: serialNumber,
+            name: name
+        )
+    }
+
+    /// Pixels per point: 2 with HiDPI, otherwise 1
+    var scale: UInt16 {
+        hidpiEnabled ? 2 : 1
+    }
+
+    /// Size of the display mode in points, covering every requested pixel
+    var pointSize: Resolution {
+        Resolution(width: width, height: height).toPoints(scale: scale)
     }
 
-    var modeHeight: UInt32 {
-        hidpiEnabled ? height / 2 : height
+    /// Size the display actually has in pixels, and so what's captured and
+    /// encoded. Only differs from the requested size for an odd size in
+    /// HiDPI, which gains a column or row.
+    var pixelSize: Resolution {
+        pointSize.toPixels(scale: scale)
     }
 
     /// Resolution string
     var resolutionString: String {
-        "\(width)x\(height)@\(refreshRate)Hz\(hidpiEnabled ? " (HiDPI)" : "")"
+        "\(pixelSize)@\(refreshRate)Hz\(hidpiEnabled ? " (HiDPI, \(pointSize) points)" : "")"
     }
 }
 
@@ -1,39 +1,152 @@
 import Foundation
 import CoreGraphics
 
+/// Identifies a virtual display created by `VirtualDisplayManager`. Handles
+/// aren't reused while the app runs.
+struct VirtualDisplayHandle: Hashable, Comparable, Sendable, CustomStringConvertible {
+    let rawValue: UInt32
+
+    static func < (lhs: VirtualDisplayHandle, rhs: VirtualDisplayHandle) -> Bool {
+        lhs.rawValue < rhs.rawValue
+    }
+
+    var description: String {
+        "#\(rawValue)"
+    }
+}
+
+/// A virtual display that is up
+struct VirtualDisplayInfo: Equatable, Sendable {
+    let handle: VirtualDisplayHandle
+    let displayId: CGDirectDisplayID
+    /// With the serial number and name it was given
+    let configuration: DisplayConfiguration
+}
+
 /// Manager for creating and controlling virtual displays using the private CGVirtualDisplay API
 /// Requires macOS 14+ (Sonoma)
+///
+/// Any number of displays can be up at once. Each gets its own serial
+/// number and name, since macOS treats displays with the same serial number
+/// as one monitor; the lowest ones free are used, so the first display keeps
+/// its arrangement across launches.
 @MainActor
 final class VirtualDisplayManager: ObservableObject {
 
+    /// Creates a display for a configuration, returning the object that
+    /// keeps it alive and its display ID
+    typealias MakeDisplay = @MainActor (DisplayConfiguration) throws -> (display: AnyObject, displayId: CGDirectDisplayID)
+
     /// Shared instance
-    static let shared = VirtualDisplayManager()
+    static let shared = VirtualDisplayManager(makeDisplay: VirtualDisplayManager.makeVirtualDisplay)
 
-    /// The virtual display object (CGVirtualDisplay, private API)
-    private var virtualDisplay: AnyObject?
+    private let makeDisplay: MakeDisplay
 
-    /// The display ID of the created virtual display
-    @Published private(set) var displayId: CGDirectDisplayID?
+    /// The virtual display objects (CGVirtualDisplay, private API); a
+    /// display goes away when its object is released
+    private var objects: [VirtualDisplayHandle: AnyObject] = [:]
 
-    /// Current configuration
-    @Published private(set) var configuration: DisplayConfiguration?
+    /// Displays that are up, by handle
+    @Published private(set) var displays: [VirtualDisplayHandle: VirtualDisplayInfo] = [:]
 
-    /// Whether a virtual display is currently active
+    private var nextHandle: UInt32 = 1
+
+    /// Whether any virtual display is active
     var isActive: Bool {
-        displayId != nil
+        !displays.isEmpty
     }
 
-    private init() {}
+    /// Displays that are up, oldest first
+    var list: [VirtualDisplayInfo] {
+        displays.values.sorted { $0.handle < $1.handle }
+    }
 
-    /// Create a virtual display with the given configuration
-    /// - Parameter config: Display configuration
-    /// - Returns: The display ID of the created display
+    init(makeDisplay: @escaping MakeDisplay) {
+        self.makeDisplay = makeDisplay
+    }
+
+    /// Create a virtual display alongside any there are already
+    /// - Parameter config: Display configuration; its serial number and name
+    ///   are the base the display's own are numbered from
+    /// - Returns: The handle to destroy the display with
     /// - Throws: SerialWarpError if creation fails
     @discardableResult
-    func create(config: DisplayConfiguration) throws -> CGDirectDisplayID {
-        // Destroy any existing display first
-        destroy()
+    func create(config: DisplayConfiguration) throws -> VirtualDisplayHandle {
+        let inUse = Set(displays.values.map(\.configuration.serialNumber))
+        var index: UInt32 = 0
+        while inUse.contains(config.serialNumber &+ index) {
+            index += 1
+        }
+        let identified = config.withIdentity(
+            serialNumber: config.serialNumber &+ index,
+            name: index == 0 ? config.name : "\(config.name) \(index + 1)"
+        )
+
+        let (display, displayId) = try makeDisplay(identified)
+
+        let handle = VirtualDisplayHandle(rawValue: nextHandle)
+        nextHandle += 1
+        objects[handle] = display
+        displays[handle] = VirtualDisplayInfo(handle: handle, displayId: displayId, configuration: identified)
+
+        print("[VirtualDisplay] Created display \(displayId) (\(handle), \(identified.name)) with config: \(identified.resolutionString)")
+
+        return handle
+    }
+
+    /// Destroy one virtual display
+    func destroy(_ handle: VirtualDisplayHandle) throws {
+        guard let info = displays[handle] else {
+            throw SerialWarpError.virtualDisplayNotFound(handle: handle.rawValue)
+        }
+
+        // The display is destroyed when we release the reference
+        objects[handle] = nil
+        displays[handle] = nil
+
+        print("[VirtualDisplay] Display \(info.displayId) (\(handle)) destroyed")
+    }
+
+    /// Destroy every virtual display
+    func destroyAll() {
+        for handle in displays.keys {
+            try? destroy(handle)
+        }
+    }
+
+    /// The display ID of a virtual display
+    func displayId(for handle: VirtualDisplayHandle) -> CGDirectDisplayID? {
+        displays[handle]?.displayId
+    }
+
+    /// Get list of all active displays
+    static func getActiveDisplays() -> [CGDirectDisplayID] {
+        var displayCount: UInt32 = 0
+
+        // Get display count
+        guard CGGetActiveDisplayList(0, nil, &displayCount) == .success else {
+            return []
+        }
+
+        guard displayCount > 0 else { return [] }
 
+        var displays = [CGDirectDisplayID](repeating: 0, count: Int(displayCount))
+        guard CGGetActiveDisplayList(displayCount, &displays, &displayCount) == .success else {
+            return []
+        }
+
+        return displays
+    }
+
+    /// Check if a display ID corresponds to one of the virtual displays
+    func isVirtualDisplay(_ id: CGDirectDisplayID) -> Bool {
+        displays.values.contains { $0.displayId == id }
+    }
+
+    // MARK: - CGVirtualDisplay
+
+    /// Create a display with the private CGVirtualDisplay API
+    private static func makeVirtualDisplay(config: DisplayConfiguration) throws -> (display: AnyObject, displayId: CGDirectDisplayID) {
         // Get the private CGVirtualDisplay classes via Objective-C runtime
         guard let descriptorClass = NSClassFromString("CGVirtualDisplayDescriptor"),
               let modeClass = NSClassFromString("CGVirtualDisplayMode"),
@@ -55,8 +168,9 @@ final class VirtualDisplayManager: ObservableObject {
 
         // Create display mode
         let mode = (modeClass as! NSObject.Type).init()
-        mode.setValue(NSNumber(value: config.modeWidth), forKey: "width")
-        mode.setValue(NSNumber(value: config.modeHeight), forKey: "height")
+        // Modes are in points; HiDPI doubles them to the pixel size
+        mode.setValue(NSNumber(value: config.pointSize.width), forKey: "width")
+        mode.setValue(NSNumber(value: config.pointSize.height), forKey: "height")
         mode.setValue(NSNumber(value: Double(config.refreshRate)), forKey: "refreshRate")
 
         // Set modes array
@@ -92,49 +206,7 @@ final class VirtualDisplayManager: ObservableObject {
             throw SerialWarpError.virtualDisplayCreationFailed
         }
 
-        virtualDisplay = display
-        displayId = displayIdValue
-        configuration = config
-
-        print("[VirtualDisplay] Created display \(displayIdValue) with config: \(config.resolutionString)")
-
-        return displayIdValue
-    }
-
-    /// Destroy the virtual display
-    func destroy() {
-        guard virtualDisplay != nil else { return }
-
-        // The display is destroyed when we release the reference
-        virtualDisplay = nil
-        displayId = nil
-        configuration = nil
-
-        print("[VirtualDisplay] Display destroyed")
-    }
-
-    /// Get list of all active displays
-    static func getActiveDisplays() -> [CGDirectDisplayID] {
-        var displayCount: UInt32 = 0
-
-        // Get display count
-        guard CGGetActiveDisplayList(0, nil, &displayCount) == .success else {
-            return []
-        }
-
-        guard displayCount > 0 else { return [] }
-
-        var displays = [CGDirectDisplayID](repeating: 0, count: Int(displayCount))
-        guard CGGetActiveDisplayList(displayCount, &displays, &displayCount) == .success else {
-            return []
-        }
-
-        return displays
-    }
-
-    /// Check if a display ID corresponds to the virtual display
-    func isVirtualDisplay(_ id: CGDirectDisplayID) -> Bool {
-        displayId == id
+        return (display, displayIdValue)
     }
 }
 
@@ -0,0 +1,92 @@
+import Foundation
+import CoreGraphics
+
+/// A macOS version, ordered by major, minor then patch
+struct OSVersion: Comparable, Sendable, CustomStringConvertible {
+    let major: Int
+    let minor: Int
+    let patch: Int
+
+    init(_ major: Int, _ minor: Int = 0, _ patch: Int = 0) {
+        self.major = major
+        self.minor = minor
+        self.patch = patch
+    }
+
+    init(_ version: OperatingSystemVersion) {
+        self.init(version.majorVersion, version.minorVersion, version.patchVersion)
+    }
+
+    /// The version this Mac is running
+    static var current: OSVersion {
+        OSVersion(ProcessInfo.processInfo.operatingSystemVersion)
+    }
+
+    static func < (lhs: OSVersion, rhs: OSVersion) -> Bool {
+        (lhs.major, lhs.minor, lhs.patch) < (rhs.major, rhs.minor, rhs.patch)
+    }
+
+    var description: String {
+        patch == 0 ? "\(major).\(minor)" : "\(major).\(minor).\(patch)"
+    }
+}
+
+/// Why virtual displays can't be created on this Mac
+enum VirtualDisplayUnavailability: LocalizedError, Equatable, Sendable {
+    /// CGVirtualDisplay is private API that first appeared in macOS 14
+    case unsupportedOS(current: OSVersion, required: OSVersion)
+    /// The private classes aren't there, e.g. Apple renamed them
+    case missingClasses([String])
+    /// The display could be created but not captured
+    case screenRecordingDenied
+
+    var errorDescription: String? {
+        switch self {
+        case .unsupportedOS(let current, let required):
+            return "Virtual displays need macOS \(required) or later; this Mac is running \(current)"
+        case .missingClasses(let classes):
+            return "This version of macOS doesn't have the virtual display API (\(classes.joined(separator: ", ")) not found)"
+        case .screenRecordingDenied:
+            return "Screen recording permission hasn't been granted. Allow SerialWarp Capture in System Settings > Privacy & Security > Screen Recording."
+        }
+    }
+}
+
+/// Checks up front whether virtual displays will work, so the UI can say
+/// why not before anything is clicked
+enum VirtualDisplaySupport {
+    /// First version with CGVirtualDisplay
+    static let minimumOS = OSVersion(14)
+
+    /// The private classes creating a display needs
+    static let requiredClasses = [
+        "CGVirtualDisplayDescriptor",
+        "CGVirtualDisplayMode",
+        "CGVirtualDisplaySettings",
+        "CGVirtualDisplay",
+    ]
+
+    /// Check the OS version, then the private classes, then screen
+    /// recording permission, returning the first thing missing. Doesn't
+    /// prompt for permission.
+    static func check(
+        osVersion: OSVersion = .current,
+        classExists: (String) -> Bool = { NSClassFromString($0) != nil },
+        screenRecordingAllowed: () -> Bool = { CaptureService.hasPermission() }
+    ) -> Result<Void, VirtualDisplayUnavailability> {
+        guard osVersion >= minimumOS else {
+            return .failure(.unsupportedOS(current: osVersion, required: minimumOS))
+        }
+
+        let missing = requiredClasses.filter { !classExists($0) }
+        guard missing.isEmpty else {
+            return .failure(.missingClasses(missing))
+        }
+
+        guard screenRecordingAllowed() else {
+            return .failure(.screenRecordingDenied)
+        }
+
+        return .success(())
+    }
+}
@@ -1,21 +1,28 @@
 import Foundation
+import CoreMedia
+import CoreVideo
+import VideoToolbox
 
 /// Configuration for the H.264 video encoder
 struct EncoderConfiguration: Sendable {
-    /// Video width in pixels
+    /// Visible video width in pixels; odd sizes are coded at `codedSize`
     let width: UInt32
 
-    /// Video height in pixels
+    /// Visible video height in pixels
     let height: UInt32
 
     /// Target frame rate
     let fps: UInt32
 
-    /// Target bitrate in bits per second
+    /// Target bitrate in bits per second; the ceiling in constrained
+    /// quality mode, unused in quality mode
     let bitrateBps: UInt32
 
-    /// Maximum keyframe interval (in frames)
-    let maxKeyframeInterval: UInt32
+    /// Constant bitrate or a quality target
+    let rateControl: RateControl
+
+    /// Longest time between keyframes in seconds, whatever the frame rate
+    let keyframeInterval: TimeInterval
 
     /// Whether to enable real-time encoding
     let realTime: Bool
@@ -26,32 +33,238 @@ struct EncoderConfiguration: Sendable {
     /// Whether to allow frame reordering (B-frames)
     let allowFrameReordering: Bool
 
+    /// Entropy coding, or nil for the encoder's choice
+    let entropyMode: EntropyMode?
+
+    /// Most frames the encoder may hold before emitting output, or nil for
+    /// no limit
+    let maxFrameDelay: Int?
+
+    /// Color primaries signaled in the bitstream
+    let colorPrimaries: ColorPrimaries
+
+    /// Transfer function signaled in the bitstream
+    let transferFunction: TransferFunction
+
+    /// YCbCr matrix the encoder converts BGRA input with
+    let yCbCrMatrix: YCbCrMatrix
+
+    /// Pixel format of the frames handed to the encoder
+    let inputFormat: InputFormat
+
+    /// Largest slice the encoder may emit, or nil for one slice per frame.
+    /// At `SWRPConstants.maxSegmentSize` no slice spans more than two FRAME
+    /// segments, so a lost segment damages part of the picture rather than
+    /// all of it.
+    let maxSliceBytes: Int?
+
+    /// Whether each frame carries its capture time in a `LatencySEI`, so
+    /// the sink can measure end-to-end latency
+    let latencySEI: Bool
+
+    /// Encoder input formats, matching `InputPixelFormat` in serialwarp-core.
+    /// NV12 arrives already converted to YCbCr, so the encoder skips its own
+    /// BGRA conversion.
+    enum InputFormat: String, Codable, CaseIterable, Sendable {
+        case bgra
+        case nv12
+        case nv12FullRange
+
+        var cvPixelFormat: OSType {
+            switch self {
+            case .bgra: return kCVPixelFormatType_32BGRA
+            case .nv12: return kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange
+            case .nv12FullRange: return kCVPixelFormatType_420YpCbCr8BiPlanarFullRange
+            }
+        }
+    }
+
+    /// How the encoder spends bits, matching `RateControl` in serialwarp-core
+    enum RateControl: Equatable, Sendable {
+        /// Average `bitrateBps`, whatever the content
+        case constantBitrate
+        /// Quality from 0.0 (smallest) to 1.0 (best); the bitrate follows
+        /// the content
+        case quality(Float)
+        /// Like `quality`, but never above `bitrateBps`
+        case constrainedQuality(Float)
+
+        var quality: Float? {
+            switch self {
+            case .constantBitrate: return nil
+            case .quality(let value), .constrainedQuality(let value): return value
+            }
+        }
+
+        /// Whether `bitrateBps` applies, as a target or a ceiling
+        var usesBitrate: Bool {
+            if case .quality = self { return false }
+            return true
+        }
+    }
+
     /// Encoder profile levels
-    enum ProfileLevel: String, Sendable {
+    enum ProfileLevel: String, Codable, CaseIterable, Sendable {
         case baseline = "H264_Baseline_AutoLevel"
         case main = "H264_Main_AutoLevel"
         case high = "H264_High_AutoLevel"
     }
 
+    /// H.264 entropy coding
+    enum EntropyMode: String, Codable, CaseIterable, Sendable {
+        case cavlc = "CAVLC"
+        case cabac = "CABAC"
+
+        var cfValue: CFString {
+            switch self {
+            case .cavlc: return kVTH264EntropyMode_CAVLC
+            case .cabac: return kVTH264EntropyMode_CABAC
+            }
+        }
+    }
+
+    /// Frame reordering actually requested; Baseline has no B-frames
+    var effectiveAllowFrameReordering: Bool {
+        allowFrameReordering && profileLevel != .baseline
+    }
+
+    /// Entropy coding actually requested; Baseline only supports CAVLC
+    var effectiveEntropyMode: EntropyMode? {
+        profileLevel == .baseline ? .cavlc : entropyMode
+    }
+
+    /// Color primaries, matching `ColorPrimaries` in serialwarp-core
+    enum ColorPrimaries: String, Sendable {
+        case bt709 = "ITU_R_709_2"
+        case bt601 = "SMPTE_C"
+
+        var cfValue: CFString {
+            switch self {
+            case .bt709: return kCVImageBufferColorPrimaries_ITU_R_709_2
+            case .bt601: return kCVImageBufferColorPrimaries_SMPTE_C
+            }
+        }
+    }
+
+    /// Transfer functions, matching `TransferFunction` in serialwarp-core
+    enum TransferFunction: String, Sendable {
+        case bt709 = "ITU_R_709_2"
+        case sRGB = "sRGB"
+
+        var cfValue: CFString {
+            switch self {
+            case .bt709: return kCVImageBufferTransferFunction_ITU_R_709_2
+            case .sRGB: return kCVImageBufferTransferFunction_sRGB
+            }
+        }
+    }
+
+    /// YCbCr matrices. VideoToolbox always encodes BGRA input as video
+    /// (limited) range, so unlike `ColorMatrix` in serialwarp-core there's
+    /// no full range choice here; full range comes from `nv12FullRange`
+    /// input.
+    enum YCbCrMatrix: String, Sendable {
+        case bt709 = "ITU_R_709_2"
+        case bt601 = "ITU_R_601_4"
+
+        var cfValue: CFString {
+            switch self {
+            case .bt709: return kCVImageBufferYCbCrMatrix_ITU_R_709_2
+            case .bt601: return kCVImageBufferYCbCrMatrix_ITU_R_601_4
+            }
+        }
+    }
+
     /// Create an encoder configuration
     init(
         width: UInt32,
         height: UInt32,
         fps: UInt32,
         bitrateBps: UInt32,
+        rateControl: RateControl = .constantBitrate,
+        keyframeInterval: TimeInterval = 1,
         maxKeyframeInterval: UInt32? = nil,
         realTime: Bool = true,
         profileLevel: ProfileLevel = .high,
-        allowFrameReordering: Bool = false
+        allowFrameReordering: Bool = false,
+        entropyMode: EntropyMode? = nil,
+        maxFrameDelay: Int? = nil,
+        colorPrimaries: ColorPrimaries = .bt709,
+        transferFunction: TransferFunction = .bt709,
+        yCbCrMatrix: YCbCrMatrix = .bt709,
+        inputFormat: InputFormat = .bgra,
+        maxSliceBytes: Int? = nil,
+        latencySEI: Bool = false
     ) {
         self.width = width
         self.height = height
         self.fps = fps
         self.bitrateBps = bitrateBps
-        self.maxKeyframeInterval = maxKeyframeInterval ?? fps  // Default to 1 second
+        self.rateControl = rateControl
+        // A frame count, as the interval used to be given, wins
+        if let frames = maxKeyframeInterval {
+            self.keyframeInterval = TimeInterval(frames) / TimeInterval(max(fps, 1))
+        } else {
+            self.keyframeInterval = keyframeInterval
+        }
         self.realTime = realTime
         self.profileLevel = profileLevel
         self.allowFrameReordering = allowFrameReordering
+        self.entropyMode = entropyMode
+        self.maxFrameDelay = maxFrameDelay
+        self.colorPrimaries = colorPrimaries
+        self.transferFunction = transferFunction
+        self.yCbCrMatrix = yCbCrMatrix
+        self.inputFormat = inputFormat
+        self.maxSliceBytes = maxSliceBytes
+        self.latencySEI = latencySEI
+    }
+
+    /// The keyframe interval in frames at `fps`, rounded to the nearest
+    /// frame and at least one
+    var maxKeyframeInterval: UInt32 {
+        let frames = (keyframeInterval * TimeInterval(fps)).rounded()
+        return UInt32(min(max(frames, 1), TimeInterval(UInt32.max)))
+    }
+
+    /// How long each frame is shown, passed with every frame so rate
+    /// control knows the cadence
+    var frameDuration: CMTime {
+        Self.frameDuration(fps: fps)
+    }
+
+    /// One frame at `fps`, in microseconds like the pts
+    static func frameDuration(fps: UInt32) -> CMTime {
+        CMTime(value: CMTimeValue(1_000_000 / max(fps, 1)), timescale: 1_000_000)
+    }
+
+    /// Size the session encodes at, with a padding column or row for odd
+    /// sizes
+    var codedSize: Resolution {
+        Resolution(width: width, height: height).coded
+    }
+
+    /// Reject an out of range size, a zero keyframe interval, a quality
+    /// outside 0.0-1.0, a zero bitrate where one is used or a zero slice
+    /// size
+    func validate() throws {
+        guard Resolution(width: width, height: height).isValid else {
+            throw SerialWarpError.invalidEncoderConfiguration(
+                "resolution \(width)x\(height) is outside 1x1 to \(Resolution.maxDimension)x\(Resolution.maxDimension)"
+            )
+        }
+        guard keyframeInterval > 0 else {
+            throw SerialWarpError.invalidEncoderConfiguration("keyframe interval must be above zero")
+        }
+        if let quality = rateControl.quality, !(0.0...1.0).contains(quality) {
+            throw SerialWarpError.invalidEncoderConfiguration("quality \(quality) is outside 0.0-1.0")
+        }
+        if rateControl.usesBitrate, bitrateBps == 0 {
+            throw SerialWarpError.invalidEncoderConfiguration("bitrate must be above zero")
+        }
+        if let maxSliceBytes = maxSliceBytes, maxSliceBytes <= 0 {
+            throw SerialWarpError.invalidEncoderConfiguration("slice size must be above zero")
+        }
     }
 
     /// Bitrate in megabits per second
@@ -61,7 +274,18 @@ struct EncoderConfiguration: Sendable {
 
     /// Configuration string for debugging
     var description: String {
-        "\(width)x\(height)@\(fps)fps, \(String(format: "%.1f", bitrateMbps))Mbps, \(profileLevel.rawValue)"
+        "\(width)x\(height)@\(fps)fps, \(rateDescription), \(profileLevel.rawValue)"
+    }
+
+    private var rateDescription: String {
+        switch rateControl {
+        case .constantBitrate:
+            return String(format: "%.1fMbps", bitrateMbps)
+        case .quality(let quality):
+            return String(format: "quality %.2f", quality)
+        case .constrainedQuality(let quality):
+            return String(format: "quality %.2f, max %.1fMbps", quality, bitrateMbps)
+        }
     }
 }
 
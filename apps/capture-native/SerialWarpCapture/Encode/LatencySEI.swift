@@ -0,0 +1,120 @@
+import Foundation
+
+/// Capture time and frame number carried in the bitstream as an unregistered
+/// user data SEI message, matching `LatencySei` in serialwarp-core. The sink
+/// reads it back after decoding to measure latency from the stream alone.
+struct LatencySEI: Equatable {
+    /// Identifies serialwarp's latency message among other user data
+    static let uuid: [UInt8] = [
+        0x5e, 0x71, 0xa1, 0x3c, 0x8d, 0x42, 0x4b, 0x0f,
+        0x9a, 0x27, 0xd4, 0x61, 0x0b, 0xe8, 0x35, 0xc2
+    ]
+
+    private static let nalTypeSEI: UInt8 = 6
+    private static let payloadTypeUnregistered: UInt8 = 5
+    private static let payloadSize = 16 + 8 + 8
+    private static let rbspTrailing: UInt8 = 0x80
+
+    /// Capture time in microseconds since the Unix epoch
+    let captureTsUs: UInt64
+
+    let frameNumber: UInt64
+
+    /// The SEI NAL unit with a 4-byte start code
+    var nalUnit: Data {
+        var rbsp: [UInt8] = [Self.payloadTypeUnregistered, UInt8(Self.payloadSize)]
+        rbsp.append(contentsOf: Self.uuid)
+        rbsp.append(contentsOf: Self.bigEndianBytes(captureTsUs))
+        rbsp.append(contentsOf: Self.bigEndianBytes(frameNumber))
+        rbsp.append(Self.rbspTrailing)
+
+        var nal = Data(NALUConverter.startCode)
+        nal.append(Self.nalTypeSEI)
+        nal.append(contentsOf: Self.addEmulationPrevention(rbsp))
+        return nal
+    }
+
+    /// Read the latency message from a NAL unit without its start code
+    static func parse(nalUnit: Data) -> LatencySEI? {
+        let bytes = [UInt8](nalUnit)
+        guard let header = bytes.first, header & 0x1F == nalTypeSEI else {
+            return nil
+        }
+
+        let rbsp = removeEmulationPrevention(Array(bytes.dropFirst()))
+        var index = 0
+        while index < rbsp.count, !(rbsp[index] == rbspTrailing && rbsp[(index + 1)...].allSatisfy { $0 == 0 }) {
+            guard let payloadType = readValue(rbsp, &index),
+                  let size = readValue(rbsp, &index),
+                  index + size <= rbsp.count else {
+                return nil
+            }
+            let message = rbsp[index..<(index + size)]
+            index += size
+
+            if payloadType == Int(payloadTypeUnregistered), size == payloadSize,
+               Array(message.prefix(16)) == uuid {
+                let fields = Array(message.dropFirst(16))
+                return LatencySEI(
+                    captureTsUs: fields[0..<8].reduce(0) { $0 << 8 | UInt64($1) },
+                    frameNumber: fields[8..<16].reduce(0) { $0 << 8 | UInt64($1) }
+                )
+            }
+        }
+        return nil
+    }
+
+    /// Escape RBSP bytes: 0x03 after every two zero bytes followed by a byte
+    /// of 0x03 or less, and after trailing zeros
+    static func addEmulationPrevention(_ rbsp: [UInt8]) -> [UInt8] {
+        var out: [UInt8] = []
+        out.reserveCapacity(rbsp.count + rbsp.count / 64 + 1)
+        var zeros = 0
+        for byte in rbsp {
+            if zeros >= 2 && byte <= 3 {
+                out.append(3)
+                zeros = 0
+            }
+            out.append(byte)
+            zeros = byte == 0 ? zeros + 1 : 0
+        }
+        if zeros > 0 {
+            out.append(3)
+        }
+        return out
+    }
+
+    /// Drop the 0x03 following each pair of zero bytes
+    static func removeEmulationPrevention(_ ebsp: [UInt8]) -> [UInt8] {
+        var out: [UInt8] = []
+        out.reserveCapacity(ebsp.count)
+        var zeros = 0
+        for byte in ebsp {
+            if zeros >= 2 && byte == 3 {
+                zeros = 0
+                continue
+            }
+            out.append(byte)
+            zeros = byte == 0 ? zeros + 1 : 0
+        }
+        return out
+    }
+
+    private static func bigEndianBytes(_ value: UInt64) -> [UInt8] {
+        (0..<8).reversed().map { UInt8(truncatingIfNeeded: value >> ($0 * 8)) }
+    }
+
+    /// An SEI payload type or size: 0xFF bytes each add 255 to the final byte
+    private static func readValue(_ bytes: [UInt8], _ index: inout Int) -> Int? {
+        var value = 0
+        while index < bytes.count {
+            let byte = bytes[index]
+            index += 1
+            value += Int(byte)
+            if byte != 0xFF {
+                return value
+            }
+        }
+        return nil
+    }
+}
@@ -16,7 +16,8 @@ struct NALUConverter {
     /// - Returns: H.264 data in Annex B format
     static func convertToAnnexB(
         _ sampleBuffer: CMSampleBuffer,
-        includeParameterSets: Bool = true
+        includeParameterSets: Bool = true,
+        sei: Data? = nil
     ) throws -> Data {
         guard let formatDescription = CMSampleBufferGetFormatDescription(sampleBuffer),
               let dataBuffer = CMSampleBufferGetDataBuffer(sampleBuffer) else {
@@ -42,6 +43,11 @@ struct NALUConverter {
             result.append(parameterSets)
         }
 
+        // SEI goes ahead of the slices
+        if let sei = sei {
+            result.append(sei)
+        }
+
         // Convert NAL units from AVCC to Annex B
         var offset = 0
         while offset < totalLength {
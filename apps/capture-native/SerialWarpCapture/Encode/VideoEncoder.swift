@@ -27,6 +27,12 @@ actor VideoEncoder {
     /// Frame number counter
     private var frameNumber: UInt64 = 0
 
+    /// Whether the next frame is encoded as a keyframe
+    private var forceNextKeyframe = false
+
+    /// Duration passed with each frame, following the frame rate
+    private(set) var frameDuration: CMTime = .invalid
+
     /// Continuation for async stream
     private var frameContinuation: AsyncThrowingStream<EncodedFrame, Error>.Continuation?
 
@@ -36,6 +42,8 @@ actor VideoEncoder {
     /// Configure the encoder
     /// - Parameter config: Encoder configuration
     func configure(_ config: EncoderConfiguration) throws {
+        try config.validate()
+
         // Clean up existing session
         if let existingSession = session {
             VTCompressionSessionInvalidate(existingSession)
@@ -44,17 +52,19 @@ actor VideoEncoder {
 
         var newSession: VTCompressionSession?
 
-        // Create compression session
+        // Create compression session. VideoToolbox rejects odd sizes, so the
+        // session runs at the even coded size and capture pads to match.
+        let coded = config.codedSize
         let status = VTCompressionSessionCreate(
             allocator: kCFAllocatorDefault,
-            width: Int32(config.width),
-            height: Int32(config.height),
+            width: Int32(coded.width),
+            height: Int32(coded.height),
             codecType: kCMVideoCodecType_H264,
             encoderSpecification: nil,
             imageBufferAttributes: [
-                kCVPixelBufferPixelFormatTypeKey: kCVPixelFormatType_32BGRA,
-                kCVPixelBufferWidthKey: config.width,
-                kCVPixelBufferHeightKey: config.height
+                kCVPixelBufferPixelFormatTypeKey: config.inputFormat.cvPixelFormat,
+                kCVPixelBufferWidthKey: coded.width,
+                kCVPixelBufferHeightKey: coded.height
             ] as CFDictionary,
             compressedDataAllocator: nil,
             outputCallback: nil,
@@ -80,6 +90,9 @@ actor VideoEncoder {
         self.configuration = config
         self.isReady = true
         self.frameNumber = 0
+        self.frameDuration = config.frameDuration
+        // A new session starts the stream over, so it opens with a keyframe
+        self.forceNextKeyframe = true
 
         print("[Encoder] Configured: \(config.description)")
     }
@@ -116,14 +129,39 @@ actor VideoEncoder {
             throw SerialWarpError.propertySetFailed(property: "ProfileLevel", status: status)
         }
 
-        // Average bitrate
-        status = VTSessionSetProperty(
-            session,
-            key: kVTCompressionPropertyKey_AverageBitRate,
-            value: NSNumber(value: config.bitrateBps)
-        )
-        guard status == noErr else {
-            throw SerialWarpError.propertySetFailed(property: "AverageBitRate", status: status)
+        // Rate control: an average bitrate, or a quality target
+        switch config.rateControl {
+        case .constantBitrate:
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_AverageBitRate,
+                value: NSNumber(value: config.bitrateBps)
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "AverageBitRate", status: status)
+            }
+        case .quality(let quality), .constrainedQuality(let quality):
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_Quality,
+                value: NSNumber(value: quality)
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "Quality", status: status)
+            }
+        }
+
+        // Bitrate ceiling for constrained quality: bytes per one second
+        if case .constrainedQuality = config.rateControl {
+            let limits = [NSNumber(value: config.bitrateBps / 8), NSNumber(value: 1.0)] as CFArray
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_DataRateLimits,
+                value: limits
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "DataRateLimits", status: status)
+            }
         }
 
         // Max keyframe interval
@@ -136,23 +174,87 @@ actor VideoEncoder {
             throw SerialWarpError.propertySetFailed(property: "MaxKeyFrameInterval", status: status)
         }
 
+        // The same limit in time, which holds when the frame rate drops
+        // below the expected one
+        status = VTSessionSetProperty(
+            session,
+            key: kVTCompressionPropertyKey_MaxKeyFrameIntervalDuration,
+            value: NSNumber(value: config.keyframeInterval)
+        )
+        guard status == noErr else {
+            throw SerialWarpError.propertySetFailed(property: "MaxKeyFrameIntervalDuration", status: status)
+        }
+
         // Frame reordering (B-frames)
         status = VTSessionSetProperty(
             session,
             key: kVTCompressionPropertyKey_AllowFrameReordering,
-            value: config.allowFrameReordering ? kCFBooleanTrue : kCFBooleanFalse
+            value: config.effectiveAllowFrameReordering ? kCFBooleanTrue : kCFBooleanFalse
         )
         guard status == noErr else {
             throw SerialWarpError.propertySetFailed(property: "AllowFrameReordering", status: status)
         }
 
-        // Expected frame rate
+        // Entropy coding
+        if let entropyMode = config.effectiveEntropyMode {
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_H264EntropyMode,
+                value: entropyMode.cfValue
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "H264EntropyMode", status: status)
+            }
+        }
+
+        // Frames held before output
+        if let maxFrameDelay = config.maxFrameDelay {
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_MaxFrameDelayCount,
+                value: NSNumber(value: maxFrameDelay)
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "MaxFrameDelayCount", status: status)
+            }
+        }
+
+        // Color description, written to the SPS VUI so the sink converts
+        // back with the same primaries, transfer function and matrix
+        let colorProperties: [(String, CFString, CFString)] = [
+            ("ColorPrimaries", kVTCompressionPropertyKey_ColorPrimaries, config.colorPrimaries.cfValue),
+            ("TransferFunction", kVTCompressionPropertyKey_TransferFunction, config.transferFunction.cfValue),
+            ("YCbCrMatrix", kVTCompressionPropertyKey_YCbCrMatrix, config.yCbCrMatrix.cfValue)
+        ]
+        for (name, key, value) in colorProperties {
+            status = VTSessionSetProperty(session, key: key, value: value)
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: name, status: status)
+            }
+        }
+
+        // Slices small enough to fit a transport segment
+        if let maxSliceBytes = config.maxSliceBytes {
+            status = VTSessionSetProperty(
+                session,
+                key: kVTCompressionPropertyKey_MaxH264SliceBytes,
+                value: NSNumber(value: maxSliceBytes)
+            )
+            guard status == noErr else {
+                throw SerialWarpError.propertySetFailed(property: "MaxH264SliceBytes", status: status)
+            }
+        }
+
+        // Expected frame rate, which rate control budgets each frame by.
+        // This property might not be supported, so we don't throw on failure
         status = VTSessionSetProperty(
             session,
             key: kVTCompressionPropertyKey_ExpectedFrameRate,
             value: NSNumber(value: config.fps)
         )
-        // This property might not be supported, so we don't throw on failure
+        if status != noErr {
+            print("[Encoder] ExpectedFrameRate not set: \(status)")
+        }
     }
 
     /// Encode a captured frame
@@ -165,9 +267,17 @@ actor VideoEncoder {
 
         let currentFrameNumber = frameNumber
         frameNumber += 1
+        let captureTsUs = UInt64(Date().timeIntervalSince1970 * 1_000_000)
+        let sei = configuration?.latencySEI == true
+            ? LatencySEI(captureTsUs: captureTsUs, frameNumber: currentFrameNumber).nalUnit
+            : nil
 
         // Create frame properties for forcing keyframes if needed
-        let frameProperties: CFDictionary? = nil
+        var frameProperties: CFDictionary?
+        if forceNextKeyframe {
+            frameProperties = [kVTEncodeFrameOptionKey_ForceKeyFrame: kCFBooleanTrue] as CFDictionary
+            forceNextKeyframe = false
+        }
 
         // Use VTCompressionSessionEncodeFrameWithOutputHandler for synchronous encoding
         var encodedFrame: EncodedFrame?
@@ -177,7 +287,7 @@ actor VideoEncoder {
             session,
             imageBuffer: frame.pixelBuffer,
             presentationTimeStamp: frame.presentationTime,
-            duration: .invalid,
+            duration: frameDuration,
             frameProperties: frameProperties,
             infoFlagsOut: nil
         ) { [weak self] status, infoFlags, sampleBuffer in
@@ -192,13 +302,13 @@ actor VideoEncoder {
 
             do {
                 // Convert to Annex B format
-                let annexBData = try NALUConverter.convertToAnnexB(sampleBuffer)
+                let annexBData = try NALUConverter.convertToAnnexB(sampleBuffer, sei: sei)
                 let isKeyframe = NALUConverter.isKeyframeSampleBuffer(sampleBuffer)
 
                 let metadata = FrameMetadata(
                     frameNumber: currentFrameNumber,
                     ptsUs: frame.ptsUs,
-                    captureTsUs: UInt64(Date().timeIntervalSince1970 * 1_000_000),
+                    captureTsUs: captureTsUs,
                     isKeyframe: isKeyframe
                 )
 
@@ -274,8 +384,35 @@ actor VideoEncoder {
         stopEncoding()
     }
 
+    /// Tell the encoder frames now come at `fps`, for its rate control,
+    /// and pass their new duration from the next frame on. Best effort,
+    /// like when the session is configured; returns whether the property
+    /// was set.
+    @discardableResult
+    func setExpectedFrameRate(_ fps: UInt32) -> Bool {
+        guard let session = session else { return false }
+        frameDuration = EncoderConfiguration.frameDuration(fps: fps)
+        let status = VTSessionSetProperty(
+            session,
+            key: kVTCompressionPropertyKey_ExpectedFrameRate,
+            value: NSNumber(value: fps)
+        )
+        return status == noErr
+    }
+
+    /// A property of the compression session as VideoToolbox reports it,
+    /// or nil without a session or if it isn't supported
+    func sessionProperty(_ key: CFString) -> Any? {
+        guard let session = session else { return nil }
+        var value: CFTypeRef?
+        let status = withUnsafeMutablePointer(to: &value) {
+            VTSessionCopyProperty(session, key: key, allocator: kCFAllocatorDefault, valueOut: $0)
+        }
+        return status == noErr ? value : nil
+    }
+
     /// Force a keyframe on the next encode
     func forceKeyframe() {
-        // This would be implemented by setting frame properties on the next encode call
+        forceNextKeyframe = true
     }
 }
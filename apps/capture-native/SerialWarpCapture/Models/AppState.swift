@@ -10,6 +10,7 @@ enum ConnectionStatus: String, Sendable {
     case handshaking
     case ready
     case streaming
+    case paused
     case stopping
     case error
 }
@@ -25,8 +26,11 @@ class AppState: ObservableObject {
     /// Current connection status
     @Published var connectionStatus: ConnectionStatus = .disconnected
 
-    /// Virtual display ID (if created)
-    @Published var virtualDisplayId: UInt32?
+    /// Display IDs of the virtual displays that are up
+    @Published var virtualDisplays: [VirtualDisplayHandle: CGDirectDisplayID] = [:]
+
+    /// Why virtual displays can't be created, as of the last check
+    @Published var virtualDisplayUnavailable: VirtualDisplayUnavailability?
 
     /// Whether streaming is active
     @Published var isStreaming: Bool = false
@@ -34,6 +38,9 @@ class AppState: ObservableObject {
     /// Last error message
     @Published var lastError: String?
 
+    /// What auto-connect is doing, while it's on
+    @Published var autoConnectStatus: AutoConnectStatus?
+
     // MARK: - Stream Configuration
 
     /// Current stream configuration
@@ -44,11 +51,23 @@ class AppState: ObservableObject {
     /// Current stream statistics
     @Published var streamStats: StreamStats = StreamStats()
 
+    /// Per-second stats for graphs
+    private(set) var statsHistory = StatsHistory()
+
+    /// Whether a capture + encode benchmark is running
+    @Published var isBenchmarking: Bool = false
+
+    /// Result of the last benchmark
+    @Published var lastBenchmark: BenchmarkResult?
+
     // MARK: - Settings
 
     /// Application settings
     @Published var settings: AppSettings = .default
 
+    /// Saved stream profiles
+    @Published var profiles: StreamProfiles = .default
+
     // MARK: - Displays
 
     /// Available displays
@@ -67,6 +86,7 @@ class AppState: ObservableObject {
     private init() {
         refreshDisplays()
         loadSettings()
+        loadProfiles()
     }
 
     // MARK: - Display Management
@@ -78,7 +98,7 @@ class AppState: ObservableObject {
 
         for (index, displayID) in displayIDs.enumerated() {
             let bounds = CGDisplayBounds(displayID)
-            let isVirtual = displayID == virtualDisplayId
+            let isVirtual = virtualDisplays.values.contains(displayID)
 
             let display = DisplayInfo(
                 id: displayID,
@@ -119,6 +139,20 @@ class AppState: ObservableObject {
         }
     }
 
+    /// Load saved profiles, keeping the default profile if none were saved
+    func loadProfiles() {
+        if let data = UserDefaults.standard.data(forKey: "streamProfiles"),
+           let decoded = try? JSONDecoder().decode(StreamProfiles.self, from: data) {
+            profiles = decoded
+        }
+    }
+
+    func saveProfiles() {
+        if let data = try? JSONEncoder().encode(profiles) {
+            UserDefaults.standard.set(data, forKey: "streamProfiles")
+        }
+    }
+
     // MARK: - State Updates from Pipeline
 
     func updateFromPipelineState(_ state: PipelineState) {
@@ -137,9 +171,12 @@ class AppState: ObservableObject {
             isStreaming = false
         case .starting:
             connectionStatus = .streaming
+            resetStats()
         case .streaming:
             connectionStatus = .streaming
             isStreaming = true
+        case .paused:
+            connectionStatus = .paused
         case .stopping:
             connectionStatus = .stopping
         case .error:
@@ -148,7 +185,20 @@ class AppState: ObservableObject {
         }
     }
 
+    /// Up to the last `seconds` of per-second stats, oldest first
+    func getStatsHistory(seconds: UInt32) -> [StatsSample] {
+        statsHistory.recent(seconds: seconds)
+    }
+
+    /// Clear the current stats and their history, e.g. when a new stream
+    /// starts
+    func resetStats() {
+        streamStats = StreamStats()
+        statsHistory.clear()
+    }
+
     func updateStats(from pipelineStats: PipelineStats) {
+        statsHistory.record(pipelineStats)
         streamStats = StreamStats(
             fps: pipelineStats.currentFps,
             bitrateBps: pipelineStats.currentBitrateBps,
@@ -163,27 +213,54 @@ class AppState: ObservableObject {
 
 // MARK: - Stream Configuration
 
-struct StreamConfig: Codable, Sendable {
+struct StreamConfig: Codable, Equatable, Sendable {
     var width: UInt32 = 1920
     var height: UInt32 = 1080
     var fps: UInt32 = 60
     var bitrateMbps: UInt32 = 20
     var hidpi: Bool = false
+    var allowSleep: Bool = false
+    var rateMode: RateMode = .constantBitrate
+    /// 0.0-1.0, used by the quality modes
+    var quality: Float = 0.7
 
     static let `default` = StreamConfig()
 
+    enum RateMode: String, Codable, Sendable {
+        case constantBitrate
+        /// Bitrate follows the content
+        case quality
+        /// Bitrate follows the content, capped at `bitrateMbps`
+        case constrainedQuality
+    }
+
+    var rateControl: EncoderConfiguration.RateControl {
+        switch rateMode {
+        case .constantBitrate: return .constantBitrate
+        case .quality: return .quality(quality)
+        case .constrainedQuality: return .constrainedQuality(quality)
+        }
+    }
+
     var resolution: String {
         "\(width)x\(height)"
     }
 
     /// Convert to StreamConfiguration for pipeline
-    func toStreamConfiguration() -> StreamConfiguration {
+    func toStreamConfiguration(
+        encoder: EncoderSettings = .default,
+        exclusions: CaptureExclusions = .none
+    ) -> StreamConfiguration {
         StreamConfiguration(
             width: width,
             height: height,
             fps: fps,
             bitrateMbps: bitrateMbps,
-            hidpi: hidpi
+            hidpi: hidpi,
+            allowSleep: allowSleep,
+            rateControl: rateControl,
+            encoder: encoder,
+            exclusions: exclusions
         )
     }
 
@@ -198,6 +275,23 @@ struct StreamConfig: Codable, Sendable {
     static let bitrates: [UInt32] = [5, 10, 15, 20, 30, 50]
 }
 
+extension StreamConfig {
+    /// Fields missing from configurations saved before they existed take
+    /// their defaults
+    init(from decoder: Decoder) throws {
+        self.init()
+        let container = try decoder.container(keyedBy: CodingKeys.self)
+        width = try container.decodeIfPresent(UInt32.self, forKey: .width) ?? width
+        height = try container.decodeIfPresent(UInt32.self, forKey: .height) ?? height
+        fps = try container.decodeIfPresent(UInt32.self, forKey: .fps) ?? fps
+        bitrateMbps = try container.decodeIfPresent(UInt32.self, forKey: .bitrateMbps) ?? bitrateMbps
+        hidpi = try container.decodeIfPresent(Bool.self, forKey: .hidpi) ?? hidpi
+        allowSleep = try container.decodeIfPresent(Bool.self, forKey: .allowSleep) ?? allowSleep
+        rateMode = try container.decodeIfPresent(RateMode.self, forKey: .rateMode) ?? rateMode
+        quality = try container.decodeIfPresent(Float.self, forKey: .quality) ?? quality
+    }
+}
+
 // MARK: - Stream Statistics
 
 struct StreamStats: Sendable {
@@ -234,10 +328,52 @@ struct AppSettings: Codable, Sendable {
     var autoConnect: Bool = false
     var previewEnabled: Bool = true
     var previewQuality: UInt32 = 50
+    /// Advanced encoder options; nil (as in settings saved before they
+    /// existed) means the defaults
+    var encoder: EncoderSettings?
+    /// Whether auto-connect restarts `lastSession`; nil means off
+    var resumeLastSession: Bool?
+    /// Configuration of the last stream started
+    var lastSession: StreamConfig?
+    /// Windows and applications left out of capture; nil means none
+    var captureExclusions: CaptureExclusions?
+    /// System-wide shortcut that starts and stops streaming, as
+    /// `KeyShortcut` parses it; nil means none
+    var toggleStreamingShortcut: String?
 
     static let `default` = AppSettings()
 }
 
+// MARK: - Encoder Settings
+
+struct EncoderSettings: Codable, Equatable, Sendable {
+    var profile: EncoderConfiguration.ProfileLevel = .high
+    var allowBFrames: Bool = false
+    /// nil lets the encoder choose
+    var entropy: EncoderConfiguration.EntropyMode?
+    /// nil for no limit
+    var maxFrameDelay: Int?
+    /// Capture and encoder pixel format; nil for BGRA
+    var inputFormat: EncoderConfiguration.InputFormat?
+    /// Whether slices are kept to one transport segment; nil means off
+    var limitSliceSize: Bool?
+    /// ScreenCaptureKit's queue depth; nil for the default
+    var captureQueueDepth: Int?
+    /// Captured frames buffered for the pipeline; nil for the default
+    var captureChannelCapacity: Int?
+
+    var effectiveInputFormat: EncoderConfiguration.InputFormat {
+        inputFormat ?? .bgra
+    }
+
+    static let `default` = EncoderSettings()
+
+    static let frameDelays: [Int?] = [nil, 0, 1, 2, 4]
+
+    /// Capture queue depths and channel capacities offered
+    static let captureDepths: [Int?] = [nil, 1, 2, 3, 4, 6, 8, 12, 16]
+}
+
 // MARK: - CoreGraphics Helper
 
 func CGGetActiveDisplayList() -> [CGDirectDisplayID]? {
@@ -0,0 +1,170 @@
+import Foundation
+import Carbon.HIToolbox
+
+enum KeyShortcutError: LocalizedError, Equatable {
+    case empty
+    case unknownKey(String)
+    case unknownModifier(String)
+    /// Only function keys work without Command, Option or Control, so
+    /// typing doesn't set the shortcut off
+    case needsModifier(String)
+    /// macOS or every app already uses it
+    case reserved(String)
+    /// Another app has registered it
+    case inUse(String)
+    case registrationFailed(String, status: Int32)
+
+    var errorDescription: String? {
+        switch self {
+        case .empty:
+            return "The shortcut is empty"
+        case .unknownKey(let key):
+            return "\"\(key)\" isn't a key a shortcut can use"
+        case .unknownModifier(let modifier):
+            return "\"\(modifier)\" isn't a modifier; use Cmd, Option, Ctrl or Shift"
+        case .needsModifier(let shortcut):
+            return "\(shortcut) needs Cmd, Option or Ctrl"
+        case .reserved(let shortcut):
+            return "\(shortcut) is reserved by macOS"
+        case .inUse(let shortcut):
+            return "\(shortcut) is already used by another app"
+        case .registrationFailed(let shortcut, let status):
+            return "\(shortcut) couldn't be registered (status \(status))"
+        }
+    }
+}
+
+/// A key with modifiers, written like "Ctrl+Option+S". Parsing is case
+/// insensitive and takes the usual names and symbols for each modifier;
+/// `description` gives the canonical form, modifiers in menu order.
+struct KeyShortcut: Equatable, Hashable, Sendable, CustomStringConvertible {
+
+    struct Modifiers: OptionSet, Hashable, Sendable {
+        let rawValue: UInt8
+
+        static let control = Modifiers(rawValue: 1 << 0)
+        static let option = Modifiers(rawValue: 1 << 1)
+        static let shift = Modifiers(rawValue: 1 << 2)
+        static let command = Modifiers(rawValue: 1 << 3)
+    }
+
+    /// Canonical name of the key, e.g. "S", "5" or "F6"
+    let key: String
+    let modifiers: Modifiers
+
+    /// Carbon virtual key code
+    var keyCode: UInt32 {
+        UInt32(Self.keyCodes[key] ?? 0)
+    }
+
+    /// F1 to F19, which can be used without modifiers
+    var isFunctionKey: Bool {
+        key.count > 1 && key.hasPrefix("F")
+    }
+
+    /// Modifiers as Carbon's `RegisterEventHotKey` takes them
+    var carbonModifiers: UInt32 {
+        var flags = 0
+        if modifiers.contains(.control) { flags |= controlKey }
+        if modifiers.contains(.option) { flags |= optionKey }
+        if modifiers.contains(.shift) { flags |= shiftKey }
+        if modifiers.contains(.command) { flags |= cmdKey }
+        return UInt32(flags)
+    }
+
+    var description: String {
+        let names: [(Modifiers, String)] = [(.control, "Ctrl"), (.option, "Option"), (.shift, "Shift"), (.command, "Cmd")]
+        return (names.filter { modifiers.contains($0.0) }.map(\.1) + [key]).joined(separator: "+")
+    }
+
+    init(key: String, modifiers: Modifiers) {
+        self.key = key
+        self.modifiers = modifiers
+    }
+
+    /// Parse and check a shortcut, e.g. as typed in settings
+    init(parsing text: String) throws {
+        let parts = text.split(separator: "+", omittingEmptySubsequences: false)
+            .map { $0.trimmingCharacters(in: .whitespaces) }
+        guard let last = parts.last, !last.isEmpty else {
+            throw text.trimmingCharacters(in: .whitespaces).isEmpty
+                ? KeyShortcutError.empty
+                : KeyShortcutError.unknownKey(text)
+        }
+
+        var modifiers: Modifiers = []
+        for part in parts.dropLast() {
+            guard let modifier = Self.modifierNames[part.lowercased()] else {
+                throw KeyShortcutError.unknownModifier(part)
+            }
+            modifiers.insert(modifier)
+        }
+        guard let key = Self.keyNames[last.lowercased()] else {
+            throw KeyShortcutError.unknownKey(last)
+        }
+
+        self.init(key: key, modifiers: modifiers)
+        if modifiers.isDisjoint(with: [.command, .option, .control]) && !isFunctionKey {
+            throw KeyShortcutError.needsModifier(description)
+        }
+        if Self.reserved.contains(self) {
+            throw KeyShortcutError.reserved(description)
+        }
+    }
+
+    /// Offered in settings until one is set
+    static let suggested = KeyShortcut(key: "S", modifiers: [.control, .option, .command])
+
+    private static let modifierNames: [String: Modifiers] = [
+        "ctrl": .control, "control": .control, "⌃": .control,
+        "opt": .option, "option": .option, "alt": .option, "⌥": .option,
+        "shift": .shift, "⇧": .shift,
+        "cmd": .command, "command": .command, "⌘": .command,
+    ]
+
+    private static let keyCodes: [String: Int] = {
+        var codes: [String: Int] = [
+            "A": kVK_ANSI_A, "B": kVK_ANSI_B, "C": kVK_ANSI_C, "D": kVK_ANSI_D, "E": kVK_ANSI_E,
+            "F": kVK_ANSI_F, "G": kVK_ANSI_G, "H": kVK_ANSI_H, "I": kVK_ANSI_I, "J": kVK_ANSI_J,
+            "K": kVK_ANSI_K, "L": kVK_ANSI_L, "M": kVK_ANSI_M, "N": kVK_ANSI_N, "O": kVK_ANSI_O,
+            "P": kVK_ANSI_P, "Q": kVK_ANSI_Q, "R": kVK_ANSI_R, "S": kVK_ANSI_S, "T": kVK_ANSI_T,
+            "U": kVK_ANSI_U, "V": kVK_ANSI_V, "W": kVK_ANSI_W, "X": kVK_ANSI_X, "Y": kVK_ANSI_Y,
+            "Z": kVK_ANSI_Z,
+            "0": kVK_ANSI_0, "1": kVK_ANSI_1, "2": kVK_ANSI_2, "3": kVK_ANSI_3, "4": kVK_ANSI_4,
+            "5": kVK_ANSI_5, "6": kVK_ANSI_6, "7": kVK_ANSI_7, "8": kVK_ANSI_8, "9": kVK_ANSI_9,
+            "Space": kVK_Space, "Return": kVK_Return, "Tab": kVK_Tab, "Escape": kVK_Escape,
+            "Delete": kVK_Delete, "Left": kVK_LeftArrow, "Right": kVK_RightArrow,
+            "Up": kVK_UpArrow, "Down": kVK_DownArrow,
+        ]
+        let functionKeys = [
+            kVK_F1, kVK_F2, kVK_F3, kVK_F4, kVK_F5, kVK_F6, kVK_F7, kVK_F8, kVK_F9, kVK_F10,
+            kVK_F11, kVK_F12, kVK_F13, kVK_F14, kVK_F15, kVK_F16, kVK_F17, kVK_F18, kVK_F19,
+        ]
+        for (index, code) in functionKeys.enumerated() {
+            codes["F\(index + 1)"] = code
+        }
+        return codes
+    }()
+
+    /// Canonical key names by lowercased name or alias
+    private static let keyNames: [String: String] = {
+        var names = Dictionary(uniqueKeysWithValues: keyCodes.keys.map { ($0.lowercased(), $0) })
+        names["esc"] = "Escape"
+        names["enter"] = "Return"
+        names["backspace"] = "Delete"
+        return names
+    }()
+
+    /// Taken by macOS before an app sees them
+    private static let reserved: Set<KeyShortcut> = [
+        KeyShortcut(key: "Tab", modifiers: [.command]),
+        KeyShortcut(key: "Space", modifiers: [.command]),
+        KeyShortcut(key: "Space", modifiers: [.control]),
+        KeyShortcut(key: "Q", modifiers: [.command]),
+        KeyShortcut(key: "Q", modifiers: [.control, .command]),
+        KeyShortcut(key: "3", modifiers: [.shift, .command]),
+        KeyShortcut(key: "4", modifiers: [.shift, .command]),
+        KeyShortcut(key: "5", modifiers: [.shift, .command]),
+        KeyShortcut(key: "Escape", modifiers: [.option, .command]),
+    ]
+}
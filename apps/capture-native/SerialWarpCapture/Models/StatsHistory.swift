@@ -0,0 +1,99 @@
+import Foundation
+
+/// One second of stream statistics
+struct StatsSample: Codable, Equatable, Sendable {
+    /// Seconds since streaming started, at the end of the sample
+    let elapsedSeconds: Double
+
+    /// Frames captured per second over the sample
+    let fps: Double
+
+    /// Bits sent per second over the sample
+    let bitrateBps: UInt64
+
+    /// Frames dropped during the sample
+    let framesDropped: UInt64
+
+    /// Latest latency reported by the sink
+    let latencyMs: Double
+
+    /// Percentiles of the time between captured frames, of encoding and of
+    /// sending, as the pipeline last reported them; nil before any frames
+    let captureInterval: TimingPercentiles?
+    let encodeDuration: TimingPercentiles?
+    let sendDuration: TimingPercentiles?
+}
+
+/// Ring buffer of the most recent `StatsSample`s, built from the stats the
+/// pipeline reports each second
+struct StatsHistory: Sendable {
+    /// Ten minutes of per-second samples
+    static let defaultCapacity = 600
+
+    let capacity: Int
+
+    private var samples: [StatsSample] = []
+
+    /// Index of the oldest sample once the buffer is full
+    private var head = 0
+
+    /// Elapsed time and captured/sent/dropped counts at the last sample
+    private var last = (elapsed: 0.0, captured: UInt64(0), bytesSent: UInt64(0), dropped: UInt64(0))
+
+    init(capacity: Int = StatsHistory.defaultCapacity) {
+        self.capacity = max(capacity, 1)
+        samples.reserveCapacity(self.capacity)
+    }
+
+    var count: Int {
+        samples.count
+    }
+
+    var isEmpty: Bool {
+        samples.isEmpty
+    }
+
+    /// Add a sample covering the time since the last one, once a second or
+    /// more has passed; the oldest is overwritten when full. Returns whether
+    /// a sample was added.
+    @discardableResult
+    mutating func record(_ stats: PipelineStats) -> Bool {
+        let elapsed = stats.elapsedSeconds
+        let interval = elapsed - last.elapsed
+        guard interval >= 1 else { return false }
+
+        let captured = stats.framesCaptured - min(last.captured, stats.framesCaptured)
+        let bytesSent = stats.bytesSent - min(last.bytesSent, stats.bytesSent)
+        let sample = StatsSample(
+            elapsedSeconds: elapsed,
+            fps: Double(captured) / interval,
+            bitrateBps: UInt64(Double(bytesSent * 8) / interval),
+            framesDropped: stats.framesDropped - min(last.dropped, stats.framesDropped),
+            latencyMs: Double(stats.latencyUs) / 1000,
+            captureInterval: stats.captureInterval,
+            encodeDuration: stats.encodeDuration,
+            sendDuration: stats.sendDuration
+        )
+
+        if samples.count < capacity {
+            samples.append(sample)
+        } else {
+            samples[head] = sample
+            head = (head + 1) % capacity
+        }
+        last = (elapsed, stats.framesCaptured, stats.bytesSent, stats.framesDropped)
+        return true
+    }
+
+    /// The most recent `seconds` samples, oldest first
+    func recent(seconds: UInt32) -> [StatsSample] {
+        let ordered = Array(samples[head...] + samples[..<head])
+        return Array(ordered.suffix(Int(seconds)))
+    }
+
+    mutating func clear() {
+        samples.removeAll(keepingCapacity: true)
+        head = 0
+        last = (0, 0, 0, 0)
+    }
+}
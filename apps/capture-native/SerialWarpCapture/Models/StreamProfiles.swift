@@ -0,0 +1,76 @@
+import Foundation
+
+/// A named stream configuration, with the advanced encoder options to use
+/// with it
+struct StreamProfile: Codable, Equatable, Sendable {
+    var name: String
+    var config: StreamConfig
+    /// nil means the defaults
+    var encoder: EncoderSettings?
+}
+
+enum StreamProfileError: LocalizedError, Equatable {
+    /// A profile has the name and overwriting wasn't asked for
+    case nameTaken(String)
+    case notFound(String)
+    case emptyName
+
+    var errorDescription: String? {
+        switch self {
+        case .nameTaken(let name):
+            return "A profile named \"\(name)\" already exists"
+        case .notFound(let name):
+            return "No profile named \"\(name)\""
+        case .emptyName:
+            return "Profile names can't be empty"
+        }
+    }
+}
+
+/// Saved stream profiles, kept in the order they were first saved. Names are
+/// unique, ignoring surrounding whitespace.
+struct StreamProfiles: Codable, Equatable, Sendable {
+    /// Name of the profile there is on first launch
+    static let defaultProfileName = "Default"
+
+    static let `default` = StreamProfiles(profiles: [
+        StreamProfile(name: defaultProfileName, config: .default, encoder: nil)
+    ])
+
+    private(set) var profiles: [StreamProfile]
+
+    var names: [String] {
+        profiles.map(\.name)
+    }
+
+    func profile(named name: String) -> StreamProfile? {
+        let name = name.trimmingCharacters(in: .whitespaces)
+        return profiles.first { $0.name == name }
+    }
+
+    /// Add `profile`, or replace the one with its name if `overwrite` is set
+    mutating func save(_ profile: StreamProfile, overwrite: Bool = false) throws {
+        var profile = profile
+        profile.name = profile.name.trimmingCharacters(in: .whitespaces)
+        guard !profile.name.isEmpty else {
+            throw StreamProfileError.emptyName
+        }
+
+        if let index = profiles.firstIndex(where: { $0.name == profile.name }) {
+            guard overwrite else {
+                throw StreamProfileError.nameTaken(profile.name)
+            }
+            profiles[index] = profile
+        } else {
+            profiles.append(profile)
+        }
+    }
+
+    mutating func delete(named name: String) throws {
+        let name = name.trimmingCharacters(in: .whitespaces)
+        guard let index = profiles.firstIndex(where: { $0.name == name }) else {
+            throw StreamProfileError.notFound(name)
+        }
+        profiles.remove(at: index)
+    }
+}
@@ -0,0 +1,191 @@
+import Foundation
+import Combine
+
+/// Delay before each automatic connection attempt: none at first, then
+/// doubling from `initial` with each consecutive failure up to `max`
+struct ReconnectBackoff: Equatable, Sendable {
+    static let defaultInitial: TimeInterval = 1
+    static let defaultMax: TimeInterval = 60
+
+    let initial: TimeInterval
+    let max: TimeInterval
+
+    /// Consecutive failed attempts
+    private(set) var failures: UInt32 = 0
+
+    init(initial: TimeInterval = defaultInitial, max: TimeInterval = defaultMax) {
+        self.initial = initial
+        self.max = Swift.max(max, initial)
+    }
+
+    /// How long to wait before the next attempt
+    var delay: TimeInterval {
+        guard failures > 0 else { return 0 }
+        return min(initial * pow(2, Double(min(failures - 1, 31))), max)
+    }
+
+    /// Record a failed attempt, returning the delay before the next one
+    mutating func fail() -> TimeInterval {
+        failures = failures == .max ? failures : failures + 1
+        return delay
+    }
+
+    /// Record a successful attempt
+    mutating func reset() {
+        failures = 0
+    }
+}
+
+/// Progress of automatic connection, for the UI
+enum AutoConnectStatus: Equatable, Sendable {
+    /// No supported device is plugged in
+    case waitingForDevice
+    /// Backing off after a failed attempt
+    case waiting(delay: TimeInterval)
+    /// `attempt` counts from 1 since the last success
+    case connecting(attempt: UInt32)
+    case connected
+    /// Recreating the last session's virtual display and stream
+    case resuming
+    case streaming
+    /// The attempt failed; the next one waits `retryIn`
+    case failed(error: String, retryIn: TimeInterval)
+    /// Connected, but the last session couldn't be restarted
+    case resumeFailed(error: String)
+}
+
+/// Reports whether a supported device is plugged in; replaceable in tests
+@MainActor
+protocol DeviceWatcher: AnyObject {
+    /// Whether a supported device is attached now
+    var isAttached: Bool { get }
+
+    /// Wait until a supported device is attached (or, with `false`, none
+    /// is), returning at once if that's already so. `false` if the wait
+    /// was cancelled.
+    func waitUntil(attached: Bool) async -> Bool
+}
+
+extension USBDeviceManager: DeviceWatcher {
+    var isAttached: Bool {
+        !connectedDevices.isEmpty
+    }
+
+    func waitUntil(attached: Bool) async -> Bool {
+        // The publisher replays the current list first
+        for await devices in $connectedDevices.values where devices.isEmpty != attached {
+            return true
+        }
+        return false
+    }
+}
+
+/// Connects whenever a supported device is plugged in, optionally resuming
+/// the last session, and backs off after failures so a device that can't be
+/// opened isn't retried in a tight loop
+@MainActor
+final class AutoConnector {
+
+    typealias Sleep = (TimeInterval) async throws -> Void
+
+    private let watcher: DeviceWatcher
+    /// Connect if nothing is connected yet; whether it did
+    private let connect: () async throws -> Bool
+    /// Whether to restart the last session once connected
+    private let shouldResume: () -> Bool
+    private let resume: () async throws -> Void
+    private let sleep: Sleep
+    private let onStatus: (AutoConnectStatus) -> Void
+
+    private(set) var backoff: ReconnectBackoff
+    private var task: Task<Void, Never>?
+
+    init(
+        watcher: DeviceWatcher,
+        backoff: ReconnectBackoff = ReconnectBackoff(),
+        connect: @escaping () async throws -> Bool,
+        shouldResume: @escaping () -> Bool,
+        resume: @escaping () async throws -> Void,
+        sleep: @escaping Sleep = { try await Task.sleep(nanoseconds: UInt64($0 * 1_000_000_000)) },
+        onStatus: @escaping (AutoConnectStatus) -> Void
+    ) {
+        self.watcher = watcher
+        self.backoff = backoff
+        self.connect = connect
+        self.shouldResume = shouldResume
+        self.resume = resume
+        self.sleep = sleep
+        self.onStatus = onStatus
+    }
+
+    var isRunning: Bool {
+        task != nil
+    }
+
+    /// Start watching; each time a device is attached, connect once and
+    /// wait for it to be unplugged again
+    func start() {
+        guard task == nil else { return }
+        task = Task { [weak self] in
+            while let self = self, !Task.isCancelled {
+                guard await self.connectWhenAttached() else { break }
+                guard await self.watcher.waitUntil(attached: false) else { break }
+            }
+        }
+    }
+
+    func stop() {
+        task?.cancel()
+        task = nil
+    }
+
+    /// Wait for a device and connect, retrying with backoff until it works,
+    /// then resume the last session if set to. `false` if cancelled first.
+    func connectWhenAttached() async -> Bool {
+        while !Task.isCancelled {
+            if !watcher.isAttached {
+                onStatus(.waitingForDevice)
+            }
+            guard await watcher.waitUntil(attached: true) else { return false }
+
+            let delay = backoff.delay
+            if delay > 0 {
+                onStatus(.waiting(delay: delay))
+                do {
+                    try await sleep(delay)
+                } catch {
+                    return false
+                }
+                if !watcher.isAttached {
+                    continue
+                }
+            }
+
+            onStatus(.connecting(attempt: backoff.failures + 1))
+            do {
+                // Nothing to do if something else connected in the meantime
+                guard try await connect() else { return true }
+            } catch {
+                let retryIn = backoff.fail()
+                print("[AutoConnect] Failed: \(error.localizedDescription); retrying in \(retryIn)s")
+                onStatus(.failed(error: error.localizedDescription, retryIn: retryIn))
+                continue
+            }
+
+            backoff.reset()
+            onStatus(.connected)
+
+            if shouldResume() {
+                onStatus(.resuming)
+                do {
+                    try await resume()
+                    onStatus(.streaming)
+                } catch {
+                    onStatus(.resumeFailed(error: error.localizedDescription))
+                }
+            }
+            return true
+        }
+        return false
+    }
+}
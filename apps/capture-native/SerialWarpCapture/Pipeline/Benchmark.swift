@@ -0,0 +1,83 @@
+import Foundation
+
+/// What capture + encode achieved in a benchmark, with no transport attached
+struct BenchmarkResult: Codable, Equatable, Sendable {
+    /// How long the benchmark ran
+    let durationSeconds: Double
+
+    /// Frames captured per second
+    let captureFps: Double
+
+    /// Frames encoded per second
+    let encodeFps: Double
+
+    /// Mean time to encode a frame
+    let averageEncodeLatencyMs: Double
+
+    /// 95th percentile time to encode a frame
+    let p95EncodeLatencyMs: Double
+
+    /// Encoder output rate
+    let bitrateBps: UInt64
+
+    /// Frames captured but never encoded
+    let framesDropped: UInt64
+
+    /// Whether the Mac kept up with `fps`, allowing 5% for timing jitter
+    func sustains(fps: UInt32) -> Bool {
+        encodeFps >= Double(fps) * 0.95 && framesDropped == 0
+    }
+}
+
+/// Collects benchmark samples into the same counters as a stream, plus each
+/// frame's encode time
+struct BenchmarkRecorder {
+    /// Captured, encoded and dropped frames, and encoder output bytes
+    private(set) var stats = PipelineStats()
+
+    private var encodeLatenciesUs: [UInt64] = []
+
+    init(startTime: Date = Date()) {
+        stats.startTime = startTime
+    }
+
+    mutating func recordCapture() {
+        stats.framesCaptured += 1
+    }
+
+    /// Record a frame through the encoder; `bytes` is nil if it produced
+    /// no output
+    mutating func recordEncode(latencyUs: UInt64, bytes: Int?) {
+        encodeLatenciesUs.append(latencyUs)
+        if let bytes = bytes {
+            stats.framesEncoded += 1
+            stats.bytesSent += UInt64(bytes)
+        } else {
+            stats.framesDropped += 1
+        }
+    }
+
+    /// Summarize the samples from a run of `elapsed` seconds. Frames still
+    /// waiting for the encoder when it ended count as dropped.
+    func result(elapsed: TimeInterval) -> BenchmarkResult {
+        let seconds = max(elapsed, 0)
+        let perSecond = { (count: UInt64) in seconds > 0 ? Double(count) / seconds : 0 }
+
+        let sorted = encodeLatenciesUs.sorted()
+        let average = sorted.isEmpty ? 0 : Double(sorted.reduce(0, +)) / Double(sorted.count)
+        // Nearest rank
+        let p95 = sorted.isEmpty ? 0 : sorted[Int((Double(sorted.count) * 0.95).rounded(.up)) - 1]
+
+        let unencoded = stats.framesCaptured - min(UInt64(sorted.count), stats.framesCaptured)
+
+        return BenchmarkResult(
+            durationSeconds: seconds,
+            captureFps: perSecond(stats.framesCaptured),
+            encodeFps: perSecond(stats.framesEncoded),
+            averageEncodeLatencyMs: average / 1000,
+            p95EncodeLatencyMs: Double(p95) / 1000,
+            bitrateBps: UInt64(perSecond(stats.bytesSent * 8)),
+            framesDropped: stats.framesDropped + unencoded
+        )
+    }
+}
@@ -23,6 +23,9 @@ enum PipelineState: String, Sendable {
     /// Actively streaming
     case streaming
 
+    /// Streaming session kept open, but no frames are being captured
+    case paused
+
     /// Stopping stream
     case stopping
 
@@ -37,7 +40,7 @@ enum PipelineState: String, Sendable {
     /// Whether connected (any state after connecting)
     var isConnected: Bool {
         switch self {
-        case .connected, .handshaking, .ready, .starting, .streaming, .stopping:
+        case .connected, .handshaking, .ready, .starting, .streaming, .paused, .stopping:
             return true
         default:
             return false
@@ -65,7 +68,9 @@ enum PipelineState: String, Sendable {
         case .starting:
             return [.streaming, .ready, .disconnected, .error]
         case .streaming:
-            return [.stopping, .disconnected, .error]
+            return [.paused, .stopping, .disconnected, .error]
+        case .paused:
+            return [.streaming, .stopping, .disconnected, .error]
         case .stopping:
             return [.ready, .disconnected, .error]
         case .error:
@@ -105,6 +110,16 @@ struct PipelineStats: Sendable {
     /// Round-trip latency in microseconds
     var latencyUs: UInt64 = 0
 
+    /// Time from one captured frame to the next, over the last timing
+    /// window or two; nil until something is timed
+    var captureInterval: TimingPercentiles?
+
+    /// Time the encoder took per frame
+    var encodeDuration: TimingPercentiles?
+
+    /// Time taken to send a frame's packets
+    var sendDuration: TimingPercentiles?
+
     /// Stream start time
     var startTime: Date?
 
@@ -124,6 +139,9 @@ struct PipelineStats: Sendable {
         currentFps = 0
         currentBitrateBps = 0
         latencyUs = 0
+        captureInterval = nil
+        encodeDuration = nil
+        sendDuration = nil
         startTime = nil
     }
 }
@@ -0,0 +1,83 @@
+import Foundation
+import IOKit.pwr_mgt
+
+/// Creates and releases IOKit power assertions; replaceable in tests
+protocol PowerAssertionBackend: AnyObject {
+    /// Create an assertion of the given type, or nil on failure
+    func create(type: String, name: String) -> IOPMAssertionID?
+
+    /// Release an assertion created by `create`
+    func release(_ id: IOPMAssertionID)
+}
+
+/// Backend calling IOPMAssertionCreateWithName/IOPMAssertionRelease
+final class IOKitPowerAssertionBackend: PowerAssertionBackend {
+    func create(type: String, name: String) -> IOPMAssertionID? {
+        var id = IOPMAssertionID(0)
+        let result = IOPMAssertionCreateWithName(
+            type as CFString,
+            IOPMAssertionLevel(kIOPMAssertionLevelOn),
+            name as CFString,
+            &id
+        )
+        guard result == kIOReturnSuccess else {
+            print("[Power] Failed to create \(type) assertion: \(result)")
+            return nil
+        }
+        return id
+    }
+
+    func release(_ id: IOPMAssertionID) {
+        let result = IOPMAssertionRelease(id)
+        if result != kIOReturnSuccess {
+            print("[Power] Failed to release assertion \(id): \(result)")
+        }
+    }
+}
+
+/// Keeps the display and system awake, and App Nap off, for as long as it
+/// is alive
+///
+/// Failing to take an assertion is logged and streaming carries on.
+final class PowerAssertion {
+
+    /// Assertion types held while streaming
+    static let assertionTypes = [
+        "PreventUserIdleDisplaySleep",
+        "PreventUserIdleSystemSleep",
+    ]
+
+    private let backend: PowerAssertionBackend
+    private var assertionIds: [IOPMAssertionID] = []
+    private var activity: NSObjectProtocol?
+
+    /// Take the assertions, naming them `reason` in `pmset -g assertions`
+    init(reason: String, backend: PowerAssertionBackend = IOKitPowerAssertionBackend()) {
+        self.backend = backend
+
+        for type in Self.assertionTypes {
+            if let id = backend.create(type: type, name: reason) {
+                assertionIds.append(id)
+            }
+        }
+
+        activity = ProcessInfo.processInfo.beginActivity(
+            options: [.userInitiated, .idleDisplaySleepDisabled, .latencyCritical],
+            reason: reason
+        )
+    }
+
+    /// Whether every assertion was taken
+    var isHeld: Bool {
+        assertionIds.count == Self.assertionTypes.count
+    }
+
+    deinit {
+        for id in assertionIds {
+            backend.release(id)
+        }
+        if let activity = activity {
+            ProcessInfo.processInfo.endActivity(activity)
+        }
+    }
+}
@@ -0,0 +1,72 @@
+import Foundation
+
+/// What the start/stop streaming shortcut does, from the pipeline's state.
+/// A press while the last one is still being carried out, or while the
+/// pipeline is between states, does nothing.
+struct StreamToggle: Equatable, Sendable {
+
+    enum Action: Equatable, Sendable {
+        /// Connect to the device first, then start
+        case connectAndStart
+        case start
+        case stop
+        case ignore
+    }
+
+    /// Action started by the last press and not yet finished
+    private(set) var inFlight: Action?
+
+    /// Decide what a press does, marking it in flight until `finish`
+    mutating func press(state: PipelineState) -> Action {
+        guard inFlight == nil else { return .ignore }
+
+        let action = Self.action(for: state)
+        if action != .ignore {
+            inFlight = action
+        }
+        return action
+    }
+
+    /// The last press's action is done, or failed
+    mutating func finish() {
+        inFlight = nil
+    }
+
+    static func action(for state: PipelineState) -> Action {
+        switch state {
+        case .disconnected, .error:
+            return .connectAndStart
+        case .ready:
+            return .start
+        case .streaming, .paused:
+            return .stop
+        case .connecting, .connected, .handshaking, .starting, .stopping:
+            return .ignore
+        }
+    }
+}
+
+/// How a toggle from the shortcut turned out, posted as the object of a
+/// `streamingToggled` notification
+enum StreamToggleResult: Equatable, Sendable {
+    case started
+    case stopped
+    case failed(String)
+
+    /// Title and body of the notification confirming it
+    var message: (title: String, body: String) {
+        switch self {
+        case .started:
+            return ("Streaming started", "Press the shortcut again to stop")
+        case .stopped:
+            return ("Streaming stopped", "Press the shortcut again to start")
+        case .failed(let error):
+            return ("Streaming couldn't be toggled", error)
+        }
+    }
+}
+
+extension Notification.Name {
+    /// Posted on the main thread after the start/stop shortcut has acted
+    static let streamingToggled = Notification.Name("SerialWarpStreamingToggled")
+}
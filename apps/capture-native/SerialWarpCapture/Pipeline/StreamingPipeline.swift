@@ -1,5 +1,6 @@
 import Foundation
 import CoreGraphics
+import CoreMedia
 
 /// Delegate protocol for pipeline events
 @MainActor
@@ -45,6 +46,18 @@ actor StreamingPipeline {
     /// Current sequence number
     private var sequence: UInt32 = 0
 
+    /// Largest scale the sink's HELLO_ACK said it can show
+    private var sinkMaxScale: UInt16 = 1
+
+    /// Whether the sink's HELLO_ACK said it takes PAUSE
+    private var sinkTakesPause = false
+
+    /// Whether the sink was last told frames stopped
+    private var pauseSent = false
+
+    /// Whether capture last said the screen stopped changing
+    private var captureIdle = false
+
     /// Pipeline statistics
     private var stats = PipelineStats()
 
@@ -60,6 +73,47 @@ actor StreamingPipeline {
     /// Stats update task
     private var statsTask: Task<Void, Never>?
 
+    /// How often the frame timing percentiles are logged and started over
+    static let timingWindow: TimeInterval = 10
+
+    /// Capture interval, encode and send timings, see `PipelineStats`
+    private var captureTimings = TimingWindow()
+    private var encodeTimings = TimingWindow()
+    private var sendTimings = TimingWindow()
+
+    /// When the current timing window started
+    private var timingWindowStart = Date()
+
+    /// When the last frame was captured, for the interval to the next; nil
+    /// after a pause or a capture restart
+    private var lastCaptureTime: Date?
+
+    /// Keeps the Mac awake while streaming, unless the config allows sleep
+    private var powerAssertion: PowerAssertion?
+
+    /// Whether `runBenchmark` is in progress
+    private(set) var isBenchmarking = false
+
+    /// The virtual display `startStreaming` created, destroyed when the
+    /// stream stops. nil when streaming a display made by someone else.
+    private var ownedDisplay: VirtualDisplayHandle?
+
+    /// The display being captured, to restart capture on if the system
+    /// stops it
+    private var captureDisplayId: CGDirectDisplayID?
+
+    /// Capture restarts since a frame was last sent
+    private var captureRestarts = 0
+
+    /// Restarts tried before a stopped capture ends the stream
+    static let maxCaptureRestarts = 3
+
+    /// Wait before restarting, e.g. for a display that's reconnecting
+    static let captureRestartDelay: TimeInterval = 1
+
+    /// The last frame sent, to size the black frame sent on pause
+    private var lastFrame: (width: Int, height: Int, pixelFormat: OSType, presentationTime: CMTime)?
+
     /// Create a streaming pipeline
     init() {}
 
@@ -89,55 +143,71 @@ actor StreamingPipeline {
         }
     }
 
-    /// Start streaming with the given configuration
-    func startStreaming(config: StreamConfiguration) async throws {
+    /// Start streaming with the given configuration, to `display` if given
+    /// or else to a virtual display created for the stream
+    func startStreaming(config: StreamConfiguration, display: VirtualDisplayHandle? = nil) async throws {
         guard state == .ready else {
             throw SerialWarpError.captureFailed("Invalid state for startStreaming: \(state)")
         }
+        guard !isBenchmarking else {
+            throw SerialWarpError.captureFailed("Can't start streaming during a benchmark")
+        }
 
         streamConfig = config
         state = .starting
 
         do {
-            // Create virtual display
-            let displayConfig = DisplayConfiguration(
-                width: config.width,
-                height: config.height,
-                refreshRate: config.fps,
-                hidpiEnabled: config.hidpi
-            )
-
-            let displayId = try await MainActor.run {
-                try displayManager.create(config: displayConfig)
+            let displayId: CGDirectDisplayID
+            if let display = display {
+                guard let existing = await MainActor.run(body: { displayManager.displayId(for: display) }) else {
+                    throw SerialWarpError.virtualDisplayNotFound(handle: display.rawValue)
+                }
+                displayId = existing
+            } else {
+                // Create virtual display
+                let (handle, created) = try await MainActor.run {
+                    let handle = try displayManager.create(config: config.displayConfiguration)
+                    return (handle, displayManager.displayId(for: handle)!)
+                }
+                ownedDisplay = handle
+                displayId = created
             }
 
             // Configure encoder
-            let encoderConfig = EncoderConfiguration(
-                width: config.width,
-                height: config.height,
-                fps: config.fps,
-                bitrateBps: config.bitrateBps
-            )
-            try await encoder.configure(encoderConfig)
+            try await encoder.configure(config.encoderConfiguration)
 
             // Send START packet
             try await sendStartPacket(config: config)
 
-            // Start capture
-            let captureConfig = CaptureConfiguration(
-                width: config.width,
-                height: config.height,
-                fps: config.fps
+            // Start capture, telling the sink while the screen is still so
+            // it doesn't take the lack of frames for a stall
+            pauseSent = false
+            captureIdle = false
+            await captureService.setIdleHandler { [weak self] idle in
+                Task { await self?.captureIdleChanged(idle) }
+            }
+            let frameStream = try await captureService.startCapture(
+                displayId: displayId,
+                config: config.captureConfiguration
             )
-
-            let frameStream = try await captureService.startCapture(displayId: displayId, config: captureConfig)
+            captureDisplayId = displayId
+            captureRestarts = 0
 
             // Reset stats
             stats.reset()
             stats.startTime = Date()
+            captureTimings = TimingWindow()
+            encodeTimings = TimingWindow()
+            sendTimings = TimingWindow()
+            timingWindowStart = Date()
+            lastCaptureTime = nil
 
             state = .streaming
 
+            if !config.allowSleep {
+                powerAssertion = PowerAssertion(reason: "SerialWarp streaming")
+            }
+
             // Start receive task
             startReceiveTask()
 
@@ -150,14 +220,93 @@ actor StreamingPipeline {
             }
 
         } catch {
+            await destroyOwnedDisplay()
             state = .error
             throw error
         }
     }
 
+    /// Stop sending frames without ending the session: the transport, the
+    /// virtual display and the encoder stay up, so `resume` doesn't need a
+    /// new START. With `sendBlack`, one black keyframe is sent first so the
+    /// sink doesn't sit on the last frame.
+    func pause(sendBlack: Bool = true) async throws {
+        guard state == .streaming else {
+            throw SerialWarpError.captureFailed("Invalid state for pause: \(state)")
+        }
+
+        state = .paused
+        try await sendPause(true)
+
+        guard sendBlack, let last = lastFrame,
+              let black = CapturedFrame.black(
+                  width: last.width,
+                  height: last.height,
+                  pixelFormat: last.pixelFormat,
+                  presentationTime: last.presentationTime
+              ) else {
+            return
+        }
+
+        await encoder.forceKeyframe()
+        guard let encodedFrame = try await encoder.encode(black) else { return }
+        await flowControl.waitForCredit()
+        // Stopped while waiting for a credit
+        guard state == .paused else { return }
+        try await sendFrame(encodedFrame)
+    }
+
+    /// Send captured frames again, starting with a keyframe since the sink
+    /// may have dropped its reference frames while paused
+    func resume() async throws {
+        guard state == .paused else {
+            throw SerialWarpError.captureFailed("Invalid state for resume: \(state)")
+        }
+
+        await encoder.forceKeyframe()
+        state = .streaming
+        try await sendPause(captureIdle)
+    }
+
+    /// Capture stopped or started delivering frames as the screen stopped or
+    /// started changing. Only the sink is told; paused, it already knows.
+    private func captureIdleChanged(_ idle: Bool) async {
+        captureIdle = idle
+        guard state == .streaming else { return }
+        do {
+            try await sendPause(idle)
+        } catch {
+            print("[Pipeline] Error sending PAUSE: \(error)")
+        }
+    }
+
+    /// Tell the sink frames stop, or start again, if it takes PAUSE and
+    /// hasn't been told already
+    private func sendPause(_ paused: Bool) async throws {
+        guard sinkTakesPause, paused != pauseSent, let transport = transport else { return }
+        pauseSent = paused
+        let packet = Packet.pause(sequence: nextSequence(), payload: PausePayload(paused: paused))
+        try await transport.send(packet.toBytes())
+    }
+
+    /// Change the capture frame rate while streaming, e.g. to save
+    /// bandwidth, without restarting capture or the session
+    func setFps(_ fps: UInt32) async throws {
+        guard state == .streaming || state == .paused else {
+            throw SerialWarpError.captureFailed("Invalid state for setFps: \(state)")
+        }
+        try await captureService.setFps(fps)
+        await encoder.setExpectedFrameRate(fps)
+    }
+
+    /// Change what's left out of capture while streaming
+    func updateCaptureExclusions(_ exclusions: CaptureExclusions) async throws {
+        try await captureService.updateExclusions(exclusions)
+    }
+
     /// Stop streaming
     func stopStreaming() async {
-        guard state == .streaming else { return }
+        guard state == .streaming || state == .paused else { return }
 
         state = .stopping
 
@@ -177,9 +326,7 @@ actor StreamingPipeline {
         await encoder.invalidate()
 
         // Destroy virtual display
-        await MainActor.run {
-            displayManager.destroy()
-        }
+        await destroyOwnedDisplay()
 
         // Send STOP packet
         do {
@@ -191,12 +338,16 @@ actor StreamingPipeline {
         // Reset flow control
         await flowControl.reset()
 
+        powerAssertion = nil
+        lastFrame = nil
+        captureDisplayId = nil
+
         state = .ready
     }
 
     /// Disconnect from USB device
     func disconnect() async {
-        if state == .streaming {
+        if state == .streaming || state == .paused {
             await stopStreaming()
         }
 
@@ -208,6 +359,99 @@ actor StreamingPipeline {
         state = .disconnected
     }
 
+    // MARK: - Benchmark
+
+    /// Capture and encode with `config` for `duration` seconds, sending
+    /// nothing, to see whether this Mac can sustain it. Uses the virtual
+    /// display if there is one, otherwise creates one for the run.
+    /// Everything the run sets up is torn down when it ends, fails or its
+    /// task is cancelled.
+    func runBenchmark(config: StreamConfiguration, duration: TimeInterval) async throws -> BenchmarkResult {
+        guard !isBenchmarking, state != .starting, state != .streaming, state != .paused, state != .stopping else {
+            throw SerialWarpError.captureFailed("Can't benchmark while streaming")
+        }
+        isBenchmarking = true
+        defer { isBenchmarking = false }
+
+        let existingDisplay = await MainActor.run { displayManager.list.first?.displayId }
+        let displayId: CGDirectDisplayID
+        var benchmarkDisplay: VirtualDisplayHandle?
+        if let existingDisplay = existingDisplay {
+            displayId = existingDisplay
+        } else {
+            let (handle, created) = try await MainActor.run {
+                let handle = try displayManager.create(config: config.displayConfiguration)
+                return (handle, displayManager.displayId(for: handle)!)
+            }
+            benchmarkDisplay = handle
+            displayId = created
+        }
+
+        // Separate from the streaming capture and encoder, which stay idle
+        let capture = CaptureService()
+        let encoder = VideoEncoder()
+
+        let result: Result<BenchmarkResult, Error>
+        do {
+            try await encoder.configure(config.encoderConfiguration)
+            let frameStream = try await capture.startCapture(
+                displayId: displayId,
+                config: config.captureConfiguration
+            )
+
+            let started = Date()
+            let deadline = started.addingTimeInterval(duration)
+            var recorder = BenchmarkRecorder(startTime: started)
+
+            // Capture only delivers frames when the screen changes, so the
+            // run ends on a timer rather than on the next frame
+            let timer = Task {
+                try? await Task.sleep(nanoseconds: UInt64(max(duration, 0) * 1_000_000_000))
+                await capture.stopCapture()
+            }
+            try await withTaskCancellationHandler {
+                for try await frame in frameStream {
+                    recorder.recordCapture()
+                    // Frames left queued at the deadline are drained
+                    // unencoded and count as dropped
+                    guard Date() < deadline, !Task.isCancelled else { continue }
+
+                    let encodeStart = Date()
+                    let encoded = try await encoder.encode(frame)
+                    let latencyUs = UInt64(Date().timeIntervalSince(encodeStart) * 1_000_000)
+                    recorder.recordEncode(latencyUs: latencyUs, bytes: encoded?.data.count)
+                }
+            } onCancel: {
+                Task { await capture.stopCapture() }
+            }
+            timer.cancel()
+            try Task.checkCancellation()
+
+            result = .success(recorder.result(elapsed: min(Date(), deadline).timeIntervalSince(started)))
+        } catch {
+            result = .failure(error)
+        }
+
+        await capture.stopCapture()
+        await encoder.invalidate()
+        if let benchmarkDisplay = benchmarkDisplay {
+            await MainActor.run {
+                try? displayManager.destroy(benchmarkDisplay)
+            }
+        }
+
+        return try result.get()
+    }
+
+    /// Destroy the display `startStreaming` created, if it did
+    private func destroyOwnedDisplay() async {
+        guard let handle = ownedDisplay else { return }
+        ownedDisplay = nil
+        await MainActor.run {
+            try? displayManager.destroy(handle)
+        }
+    }
+
     // MARK: - Handshake
 
     /// Perform HELLO handshake
@@ -224,7 +468,8 @@ actor StreamingPipeline {
             maxWidth: 3840,
             maxHeight: 2160,
             maxFps: 120,
-            capabilities: SWRPConstants.Capabilities.hidpi
+            capabilities: SWRPConstants.Capabilities.hidpi | SWRPConstants.Capabilities.pause,
+            maxScale: 2
         )
 
         let helloPacket = Packet.hello(sequence: nextSequence(), payload: hello)
@@ -240,7 +485,9 @@ actor StreamingPipeline {
 
         // Parse HELLO_ACK payload
         let ackPayload = try HelloPayload.parse(ackPacket.payload)
-        print("[Pipeline] Handshake complete. Sink capabilities: hidpi=\(ackPayload.supportsHidpi)")
+        sinkMaxScale = ackPayload.effectiveMaxScale
+        sinkTakesPause = ackPayload.supportsPause
+        print("[Pipeline] Handshake complete. Sink capabilities: hidpi=\(ackPayload.supportsHidpi), pause=\(sinkTakesPause), max scale \(sinkMaxScale)x")
 
         state = .ready
     }
@@ -251,11 +498,13 @@ actor StreamingPipeline {
             throw SerialWarpError.disconnected
         }
 
+        // A sink that can't show this scale gets the pixels one to a point
         let start = StartPayload(
-            width: config.width,
-            height: config.height,
+            width: config.pixelSize.width,
+            height: config.pixelSize.height,
             fps: config.fps,
-            bitrateBps: config.bitrateBps
+            bitrateBps: config.bitrateBps,
+            scale: min(config.scale, sinkMaxScale)
         )
 
         let startPacket = Packet.start(sequence: nextSequence(), payload: start)
@@ -294,46 +543,121 @@ actor StreamingPipeline {
 
     // MARK: - Capture Loop
 
-    /// Main capture/encode/send loop
+    /// Main capture/encode/send loop. If the system stops capture, it's
+    /// restarted on the same display; if that fails too, streaming stops.
+    /// Either way the error is reported.
     private func runCaptureLoop(frameStream: AsyncThrowingStream<CapturedFrame, Error>) async {
-        do {
-            for try await frame in frameStream {
-                guard !Task.isCancelled else { break }
-
-                stats.framesCaptured += 1
-
-                // Create preview image
-                if let previewImage = captureService.createPreviewImage(from: frame) {
-                    Task { @MainActor [weak self] in
-                        guard let self = self else { return }
-                        self.delegate?.pipeline(self, didCapturePreviewFrame: previewImage)
-                    }
+        var frameStream = frameStream
+        while true {
+            do {
+                try await sendCapturedFrames(from: frameStream)
+                return
+            } catch {
+                guard !Task.isCancelled else { return }
+                print("[Pipeline] Capture loop error: \(error)")
+                Task { @MainActor [weak self] in
+                    guard let self = self else { return }
+                    self.delegate?.pipeline(self, didEncounterError: error)
                 }
 
-                // Encode frame
-                guard let encodedFrame = try await encoder.encode(frame) else {
-                    continue
+                guard let restarted = await restartCapture(after: error) else {
+                    // Not from this task, which stopStreaming cancels
+                    powerAssertion = nil
+                    Task { await self.stopStreaming() }
+                    return
                 }
+                frameStream = restarted
+                lastCaptureTime = nil
+            }
+        }
+    }
 
-                stats.framesEncoded += 1
-
-                // Wait for credit
-                await flowControl.waitForCredit()
+    /// Capture, encode and send until the frame stream ends. Only
+    /// stopStreaming ends capture on purpose, and it cancels this task
+    /// first, so the stream ending otherwise is an error.
+    private func sendCapturedFrames(from frameStream: AsyncThrowingStream<CapturedFrame, Error>) async throws {
+        for try await frame in frameStream {
+            guard !Task.isCancelled else { break }
+            // Capture keeps running while paused; its frames are dropped
+            guard state == .streaming else {
+                lastCaptureTime = nil
+                continue
+            }
 
-                // Send frame
-                try await sendFrame(encodedFrame)
+            let captureTime = Date()
+            if let last = lastCaptureTime {
+                captureTimings.record(captureTime.timeIntervalSince(last))
             }
-        } catch {
-            if !Task.isCancelled {
-                print("[Pipeline] Capture loop error: \(error)")
+            lastCaptureTime = captureTime
+            stats.framesCaptured += 1
+
+            // Create preview image
+            if let previewImage = captureService.createPreviewImage(from: frame) {
                 Task { @MainActor [weak self] in
                     guard let self = self else { return }
-                    self.delegate?.pipeline(self, didEncounterError: error)
+                    self.delegate?.pipeline(self, didCapturePreviewFrame: previewImage)
                 }
             }
+
+            // Encode frame
+            let encodeStart = Date()
+            let encoded = try await encoder.encode(frame)
+            encodeTimings.record(Date().timeIntervalSince(encodeStart))
+            guard let encodedFrame = encoded else {
+                continue
+            }
+
+            stats.framesEncoded += 1
+
+            // Wait for credit
+            await flowControl.waitForCredit()
+            // Paused while encoding or waiting
+            guard state == .streaming else { continue }
+
+            // Send frame
+            let sendStart = Date()
+            try await sendFrame(encodedFrame)
+            sendTimings.record(Date().timeIntervalSince(sendStart))
+            lastFrame = (frame.width, frame.height, frame.pixelFormat, frame.presentationTime)
+            captureRestarts = 0
+        }
+        if !Task.isCancelled {
+            throw SerialWarpError.captureStopped("Capture ended unexpectedly")
         }
     }
 
+    /// Start capture again after the system stopped it, trying up to
+    /// `maxCaptureRestarts` times. nil if it can't be restarted, or
+    /// shouldn't be, e.g. permission was revoked.
+    private func restartCapture(after error: Error) async -> AsyncThrowingStream<CapturedFrame, Error>? {
+        guard case SerialWarpError.captureStopped = error,
+              let displayId = captureDisplayId,
+              let config = streamConfig else {
+            return nil
+        }
+
+        while captureRestarts < Self.maxCaptureRestarts {
+            captureRestarts += 1
+            print("[Pipeline] Restarting capture (attempt \(captureRestarts))")
+            try? await Task.sleep(nanoseconds: UInt64(Self.captureRestartDelay * 1_000_000_000))
+
+            // Stopped while waiting
+            guard state == .streaming || state == .paused, !Task.isCancelled else { return nil }
+            do {
+                let frameStream = try await captureService.startCapture(
+                    displayId: displayId,
+                    config: config.captureConfiguration
+                )
+                // Reference frames from before the gap are no use to the sink
+                await encoder.forceKeyframe()
+                return frameStream
+            } catch {
+                print("[Pipeline] Capture restart failed: \(error)")
+            }
+        }
+        return nil
+    }
+
     /// Send an encoded frame
     private func sendFrame(_ frame: EncodedFrame) async throws {
         guard let transport = transport else {
@@ -427,6 +751,7 @@ actor StreamingPipeline {
                     stats.currentFps = Double(stats.framesCaptured) / elapsed
                     stats.currentBitrateBps = UInt64(Double(stats.bytesSent * 8) / elapsed)
                 }
+                updateTimings()
 
                 Task { @MainActor [weak self] in
                     guard let self = self else { return }
@@ -436,6 +761,29 @@ actor StreamingPipeline {
         }
     }
 
+    /// Copy the timing percentiles into the stats, and start a new window
+    /// once the current one has run `timingWindow`, logging what it timed
+    private func updateTimings() {
+        stats.captureInterval = captureTimings.percentiles
+        stats.encodeDuration = encodeTimings.percentiles
+        stats.sendDuration = sendTimings.percentiles
+
+        guard Date().timeIntervalSince(timingWindowStart) >= Self.timingWindow else { return }
+        if let capture = captureTimings.current.percentiles {
+            func describe(_ percentiles: TimingPercentiles?) -> String {
+                guard let p = percentiles else { return "-" }
+                return String(format: "p50=%.1fms p95=%.1fms p99=%.1fms", p.p50Ms, p.p95Ms, p.p99Ms)
+            }
+            print("[Pipeline] Timings: capture interval \(describe(capture)), "
+                + "encode \(describe(encodeTimings.current.percentiles)), "
+                + "send \(describe(sendTimings.current.percentiles))")
+        }
+        captureTimings.rotate()
+        encodeTimings.rotate()
+        sendTimings.rotate()
+        timingWindowStart = Date()
+    }
+
     // MARK: - Helpers
 
     /// Get next sequence number
@@ -455,18 +803,94 @@ actor StreamingPipeline {
 
 /// Configuration for streaming
 struct StreamConfiguration: Sendable {
+    /// Requested display size in pixels; see `pixelSize` for what's
+    /// actually captured and sent
     let width: UInt32
     let height: UInt32
     let fps: UInt32
     let bitrateBps: UInt32
     let hidpi: Bool
-
-    init(width: UInt32, height: UInt32, fps: UInt32, bitrateMbps: UInt32, hidpi: Bool = false) {
+    /// Let the display and system sleep while streaming
+    let allowSleep: Bool
+    /// Constant bitrate (`bitrateBps`) or a quality target
+    let rateControl: EncoderConfiguration.RateControl
+    /// Profile, B-frame and entropy options for the encoder
+    let encoder: EncoderSettings
+    /// Windows and applications left out of capture
+    let exclusions: CaptureExclusions
+
+    init(
+        width: UInt32,
+        height: UInt32,
+        fps: UInt32,
+        bitrateMbps: UInt32,
+        hidpi: Bool = false,
+        allowSleep: Bool = false,
+        rateControl: EncoderConfiguration.RateControl = .constantBitrate,
+        encoder: EncoderSettings = .default,
+        exclusions: CaptureExclusions = .none
+    ) {
         self.width = width
         self.height = height
         self.fps = fps
         self.bitrateBps = bitrateMbps * 1_000_000
         self.hidpi = hidpi
+        self.allowSleep = allowSleep
+        self.rateControl = rateControl
+        self.encoder = encoder
+        self.exclusions = exclusions
+    }
+
+    /// The virtual display to stream
+    var displayConfiguration: DisplayConfiguration {
+        DisplayConfiguration(
+            width: width,
+            height: height,
+            refreshRate: fps,
+            hidpiEnabled: hidpi
+        )
+    }
+
+    /// Size of the virtual display in pixels, which capture, the encoder
+    /// and START all use
+    var pixelSize: Resolution {
+        displayConfiguration.pixelSize
+    }
+
+    /// Pixels per point on the virtual display
+    var scale: UInt16 {
+        displayConfiguration.scale
+    }
+
+    /// The encoder settings for this stream
+    var encoderConfiguration: EncoderConfiguration {
+        EncoderConfiguration(
+            width: pixelSize.width,
+            height: pixelSize.height,
+            fps: fps,
+            bitrateBps: bitrateBps,
+            rateControl: rateControl,
+            profileLevel: encoder.profile,
+            allowFrameReordering: encoder.allowBFrames,
+            entropyMode: encoder.entropy,
+            maxFrameDelay: encoder.maxFrameDelay,
+            inputFormat: encoder.effectiveInputFormat,
+            maxSliceBytes: encoder.limitSliceSize == true ? SWRPConstants.maxSegmentSize : nil
+        )
+    }
+
+    /// Capture straight into the encoder's input format so neither side
+    /// converts
+    var captureConfiguration: CaptureConfiguration {
+        CaptureConfiguration(
+            width: pixelSize.width,
+            height: pixelSize.height,
+            fps: fps,
+            pixelFormat: encoder.effectiveInputFormat.cvPixelFormat,
+            queueDepth: encoder.captureQueueDepth ?? CaptureConfiguration.defaultQueueDepth,
+            channelCapacity: encoder.captureChannelCapacity ?? CaptureConfiguration.defaultChannelCapacity,
+            exclusions: exclusions
+        )
     }
 
     /// Default 1080p60 configuration
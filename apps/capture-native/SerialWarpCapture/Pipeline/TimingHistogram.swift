@@ -0,0 +1,120 @@
+import Foundation
+
+/// Median, 95th and 99th percentile of some frame timing, in milliseconds
+struct TimingPercentiles: Codable, Equatable, Sendable {
+    let p50Ms: Double
+    let p95Ms: Double
+    let p99Ms: Double
+}
+
+/// Counts of durations in fixed log-scale buckets, the same ones as
+/// serialwarp-core's `TimingHistogram`: eight to each power of two of
+/// microseconds, so percentiles are accurate to within an eighth
+struct TimingHistogram: Equatable, Sendable {
+    static let bucketsPerOctave = 8
+
+    /// Powers of two of microseconds covered, 1 µs up to about 134 s
+    private static let octaves = 27
+
+    /// Bucket 0 holds anything under a microsecond; the last also holds
+    /// anything too long for the rest
+    static let bucketCount = 1 + octaves * bucketsPerOctave
+
+    private var buckets = [UInt64](repeating: 0, count: TimingHistogram.bucketCount)
+    private(set) var count: UInt64 = 0
+    private var minUs: Double = 0
+    private var maxUs: Double = 0
+
+    var isEmpty: Bool {
+        count == 0
+    }
+
+    /// Add a duration in seconds
+    mutating func record(_ seconds: TimeInterval) {
+        let us = max(seconds, 0) * 1_000_000
+        buckets[Self.bucketIndex(us: us)] += 1
+        minUs = isEmpty ? us : min(minUs, us)
+        maxUs = max(maxUs, us)
+        count += 1
+    }
+
+    /// The duration in milliseconds `p` percent of those recorded are at or
+    /// under, `p` being 0 to 100, interpolated within its bucket and kept
+    /// within the recorded range
+    func percentileMs(_ p: Double) -> Double? {
+        guard !isEmpty else { return nil }
+        let rank = min(max(p, 0), 100) / 100 * Double(count)
+        var below: UInt64 = 0
+        var estimate = maxUs
+        for (index, bucketCount) in buckets.enumerated() where bucketCount > 0 {
+            if Double(below + bucketCount) >= rank {
+                let (low, high) = Self.bucketBoundsUs(index)
+                let fraction = (rank - Double(below)) / Double(bucketCount)
+                estimate = low + fraction * (high - low)
+                break
+            }
+            below += bucketCount
+        }
+        return min(max(estimate, minUs), maxUs) / 1000
+    }
+
+    var percentiles: TimingPercentiles? {
+        guard let p50 = percentileMs(50), let p95 = percentileMs(95), let p99 = percentileMs(99) else {
+            return nil
+        }
+        return TimingPercentiles(p50Ms: p50, p95Ms: p95, p99Ms: p99)
+    }
+
+    /// Add everything recorded in `other`, as if it had been recorded here
+    mutating func merge(_ other: TimingHistogram) {
+        guard !other.isEmpty else { return }
+        for index in buckets.indices {
+            buckets[index] += other.buckets[index]
+        }
+        minUs = isEmpty ? other.minUs : min(minUs, other.minUs)
+        maxUs = max(maxUs, other.maxUs)
+        count += other.count
+    }
+
+    static func bucketIndex(us: Double) -> Int {
+        guard us >= 1 else { return 0 }
+        let value = UInt64(min(us, Double(UInt64.max >> 4)))
+        let octave = 63 - value.leadingZeroBitCount
+        let sub = Int((value << 3) >> octave) & (bucketsPerOctave - 1)
+        return min(1 + octave * bucketsPerOctave + sub, bucketCount - 1)
+    }
+
+    static func bucketBoundsUs(_ index: Int) -> (Double, Double) {
+        guard index > 0 else { return (0, 1) }
+        let octave = (index - 1) / bucketsPerOctave
+        let sub = (index - 1) % bucketsPerOctave
+        let base = Double(UInt64(1) << octave)
+        let width = base / Double(bucketsPerOctave)
+        let low = base + Double(sub) * width
+        return (low, low + width)
+    }
+}
+
+/// A `TimingHistogram` over a rolling stats window: queries cover the
+/// current window and the one before, so they never rest on just the few
+/// samples of one that has only just started
+struct TimingWindow: Sendable {
+    private(set) var current = TimingHistogram()
+    private var previous = TimingHistogram()
+
+    mutating func record(_ seconds: TimeInterval) {
+        current.record(seconds)
+    }
+
+    var percentiles: TimingPercentiles? {
+        var histogram = previous
+        histogram.merge(current)
+        return histogram.percentiles
+    }
+
+    /// Start a new window; the current one becomes the previous
+    mutating func rotate() {
+        previous = current
+        current = TimingHistogram()
+    }
+}
@@ -0,0 +1,105 @@
+import Foundation
+import Carbon.HIToolbox
+
+/// A system-wide hot key, registered with Carbon so it works while another
+/// app is in front. One shortcut at a time; registering another replaces it.
+@MainActor
+final class GlobalShortcut {
+
+    /// Tags our hot key's events
+    private static let signature: OSType = 0x5357_5250 // "SWRP"
+
+    private(set) var shortcut: KeyShortcut?
+    private var hotKey: EventHotKeyRef?
+    private var eventHandler: EventHandlerRef?
+    private var action: (() -> Void)?
+
+    /// Run `action` whenever `shortcut` is pressed. If it can't be
+    /// registered, e.g. because another app has it, the shortcut before is
+    /// kept and the error thrown.
+    func register(_ shortcut: KeyShortcut, action: @escaping () -> Void) throws {
+        try installEventHandler()
+
+        let previous = self.shortcut
+        unregisterHotKey()
+        do {
+            hotKey = try Self.registerHotKey(shortcut)
+        } catch {
+            if let previous {
+                hotKey = try? Self.registerHotKey(previous)
+            }
+            throw error
+        }
+        self.shortcut = shortcut
+        self.action = action
+    }
+
+    func unregister() {
+        unregisterHotKey()
+        shortcut = nil
+        action = nil
+    }
+
+    private func unregisterHotKey() {
+        if let hotKey {
+            UnregisterEventHotKey(hotKey)
+        }
+        hotKey = nil
+    }
+
+    private static func registerHotKey(_ shortcut: KeyShortcut) throws -> EventHotKeyRef {
+        var hotKey: EventHotKeyRef?
+        let status = RegisterEventHotKey(
+            shortcut.keyCode,
+            shortcut.carbonModifiers,
+            EventHotKeyID(signature: signature, id: 1),
+            GetApplicationEventTarget(),
+            0,
+            &hotKey
+        )
+        guard status == noErr, let hotKey else {
+            if status == eventHotKeyExistsErr {
+                throw KeyShortcutError.inUse(shortcut.description)
+            }
+            throw KeyShortcutError.registrationFailed(shortcut.description, status: status)
+        }
+        return hotKey
+    }
+
+    /// Listen for hot key presses, once for the life of the app. Carbon
+    /// calls the handler on the main thread.
+    private func installEventHandler() throws {
+        guard eventHandler == nil else { return }
+
+        var eventType = EventTypeSpec(eventClass: OSType(kEventClassKeyboard), eventKind: UInt32(kEventHotKeyPressed))
+        let status = InstallEventHandler(
+            GetApplicationEventTarget(),
+            { _, event, userData in
+                guard let event, let userData else { return OSStatus(eventNotHandledErr) }
+                var id = EventHotKeyID()
+                GetEventParameter(
+                    event,
+                    EventParamName(kEventParamDirectObject),
+                    EventParamType(typeEventHotKeyID),
+                    nil,
+                    MemoryLayout<EventHotKeyID>.size,
+                    nil,
+                    &id
+                )
+                guard id.signature == GlobalShortcut.signature else { return OSStatus(eventNotHandledErr) }
+                let shortcut = Unmanaged<GlobalShortcut>.fromOpaque(userData).takeUnretainedValue()
+                MainActor.assumeIsolated {
+                    shortcut.action?()
+                }
+                return noErr
+            },
+            1,
+            &eventType,
+            Unmanaged.passUnretained(self).toOpaque(),
+            &eventHandler
+        )
+        guard status == noErr else {
+            throw KeyShortcutError.registrationFailed("The shortcut handler", status: status)
+        }
+    }
+}
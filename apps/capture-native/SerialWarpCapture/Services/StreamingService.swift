@@ -1,5 +1,7 @@
 import Foundation
 import CoreGraphics
+import AppKit
+import UserNotifications
 
 /// Main streaming service that wraps the StreamingPipeline
 /// Provides a simplified interface for the UI
@@ -19,6 +21,16 @@ final class StreamingService: ObservableObject, StreamingPipelineDelegate {
     /// Whether the service is initialized
     private(set) var isInitialized = false
 
+    /// Connects when a device is plugged in, while `AppSettings.autoConnect`
+    /// is on
+    private var autoConnector: AutoConnector?
+
+    /// `AppSettings.toggleStreamingShortcut`, while one is registered
+    private let toggleShortcut = GlobalShortcut()
+
+    /// Keeps shortcut presses from overlapping
+    private var toggle = StreamToggle()
+
     private init() {
         Task {
             pipeline = StreamingPipeline()
@@ -37,14 +49,68 @@ final class StreamingService: ObservableObject, StreamingPipelineDelegate {
         try await pipeline.connect()
     }
 
-    /// Start streaming with current configuration
-    func startStreaming() async throws {
+    /// Start streaming with current configuration, to `display` if given or
+    /// else to a virtual display created for the stream
+    func startStreaming(display: VirtualDisplayHandle? = nil) async throws {
+        guard let pipeline = pipeline else {
+            throw SerialWarpError.encoderNotReady
+        }
+
+        let config = appState.streamConfig.toStreamConfiguration(
+            encoder: appState.settings.encoder ?? .default,
+            exclusions: appState.settings.captureExclusions ?? .none
+        )
+        try await pipeline.startStreaming(config: config, display: display)
+
+        // For auto-connect to resume
+        appState.settings.lastSession = appState.streamConfig
+        appState.saveSettings()
+    }
+
+    /// Capture and encode `config` (the current configuration by default)
+    /// for `seconds` without sending anything, to check this Mac can keep
+    /// up before connecting. Fails while streaming.
+    func runBenchmark(config: StreamConfig? = nil, seconds: UInt32) async throws -> BenchmarkResult {
         guard let pipeline = pipeline else {
             throw SerialWarpError.encoderNotReady
         }
 
-        let config = appState.streamConfig.toStreamConfiguration()
-        try await pipeline.startStreaming(config: config)
+        let streamConfig = (config ?? appState.streamConfig).toStreamConfiguration(
+            encoder: appState.settings.encoder ?? .default
+        )
+        appState.isBenchmarking = true
+        defer { appState.isBenchmarking = false }
+
+        let result = try await pipeline.runBenchmark(config: streamConfig, duration: TimeInterval(seconds))
+        appState.lastBenchmark = result
+        return result
+    }
+
+    /// Capture at `fps` from now on, changing the running stream if there
+    /// is one rather than restarting it
+    func setFps(_ fps: UInt32) async throws {
+        appState.streamConfig.fps = fps
+        let state = await getCurrentState()
+        if state == .streaming || state == .paused {
+            try await pipeline?.setFps(fps)
+        }
+    }
+
+    /// Stop sending frames but keep the session, optionally showing black
+    /// on the display in the meantime
+    func pauseStreaming(black: Bool = true) async throws {
+        guard let pipeline = pipeline else {
+            throw SerialWarpError.disconnected
+        }
+        try await pipeline.pause(sendBlack: black)
+    }
+
+    /// Carry on streaming after `pauseStreaming`
+    func resumeStreaming() async throws {
+        guard let pipeline = pipeline else {
+            throw SerialWarpError.disconnected
+        }
+        try await pipeline.resume()
     }
 
     /// Stop streaming
@@ -59,10 +125,97 @@ final class StreamingService: ObservableObject, StreamingPipelineDelegate {
         await pipeline.disconnect()
     }
 
-    /// Destroy virtual display
-    func destroyVirtualDisplay() {
-        VirtualDisplayManager.shared.destroy()
-        appState.virtualDisplayId = nil
+    // MARK: - Capture Exclusions
+
+    /// Applications with windows that could be left out of capture, by name
+    func listRunningApplications() -> [RunningApplication] {
+        NSWorkspace.shared.runningApplications
+            .filter { $0.activationPolicy == .regular }
+            .compactMap { app in
+                guard let bundleId = app.bundleIdentifier else { return nil }
+                return RunningApplication(bundleId: bundleId, name: app.localizedName ?? bundleId)
+            }
+            .sorted { $0.name.localizedCaseInsensitiveCompare($1.name) == .orderedAscending }
+    }
+
+    /// Save what to leave out of capture, applying it to the running stream
+    /// if there is one
+    func setCaptureExclusions(_ exclusions: CaptureExclusions) async throws {
+        appState.settings.captureExclusions = exclusions.isEmpty ? nil : exclusions
+        appState.saveSettings()
+
+        let state = await getCurrentState()
+        if state == .streaming || state == .paused {
+            try await pipeline?.updateCaptureExclusions(exclusions)
+        }
+    }
+
+    // MARK: - Screen Recording Permission
+
+    /// Whether the app may record the screen, without prompting
+    func screenRecordingPermission() -> ScreenRecordingPermission {
+        CaptureService.permissionStatus()
+    }
+
+    /// Ask for screen recording permission. Only the first request shows a
+    /// prompt; `openScreenRecordingSettings` is the way after that.
+    @discardableResult
+    func requestScreenRecordingPermission() -> ScreenRecordingPermission {
+        CaptureService.requestPermission() ? .granted : .denied
+    }
+
+    /// Show the Screen Recording pane of Privacy & Security
+    func openScreenRecordingSettings() {
+        NSWorkspace.shared.open(CaptureService.permissionSettingsURL)
+    }
+
+    // MARK: - Virtual Displays
+
+    /// Check whether virtual displays can be created, recording why not in
+    /// the app state
+    @discardableResult
+    func checkVirtualDisplaySupport() -> VirtualDisplayUnavailability? {
+        if case .failure(let reason) = VirtualDisplaySupport.check() {
+            appState.virtualDisplayUnavailable = reason
+        } else {
+            appState.virtualDisplayUnavailable = nil
+        }
+        return appState.virtualDisplayUnavailable
+    }
+
+    /// Create a virtual display for `config` alongside any there are
+    @discardableResult
+    func createVirtualDisplay(config: StreamConfig) throws -> VirtualDisplayHandle {
+        if let reason = checkVirtualDisplaySupport() {
+            throw reason
+        }
+        let handle = try VirtualDisplayManager.shared.create(
+            config: config.toStreamConfiguration().displayConfiguration
+        )
+        syncVirtualDisplays()
+        return handle
+    }
+
+    /// Virtual displays that are up, oldest first
+    func listVirtualDisplays() -> [VirtualDisplayInfo] {
+        VirtualDisplayManager.shared.list
+    }
+
+    /// Destroy one virtual display
+    func destroyVirtualDisplay(_ handle: VirtualDisplayHandle) throws {
+        try VirtualDisplayManager.shared.destroy(handle)
+        syncVirtualDisplays()
+    }
+
+    /// Destroy every virtual display
+    func destroyAllVirtualDisplays() {
+        VirtualDisplayManager.shared.destroyAll()
+        syncVirtualDisplays()
+    }
+
+    /// Copy the virtual displays that are up into the app state
+    private func syncVirtualDisplays() {
+        appState.virtualDisplays = VirtualDisplayManager.shared.displays.mapValues(\.displayId)
         appState.refreshDisplays()
     }
 
@@ -72,20 +225,181 @@ final class StreamingService: ObservableObject, StreamingPipelineDelegate {
         return await pipeline.state
     }
 
+    // MARK: - Profiles
+
+    /// Saved profiles, in the order they were first saved
+    func listProfiles() -> [StreamProfile] {
+        appState.profiles.profiles
+    }
+
+    /// Save the current configuration and encoder options as `name`,
+    /// replacing a profile of that name only if `overwrite` is set
+    func saveProfile(name: String, overwrite: Bool = false) throws {
+        let profile = StreamProfile(
+            name: name,
+            config: appState.streamConfig,
+            encoder: appState.settings.encoder
+        )
+        try appState.profiles.save(profile, overwrite: overwrite)
+        appState.saveProfiles()
+    }
+
+    func deleteProfile(name: String) throws {
+        try appState.profiles.delete(named: name)
+        appState.saveProfiles()
+    }
+
+    /// Make profile `name` the current configuration. The pipeline can't
+    /// change settings mid-stream, so a running stream is restarted with it.
+    func applyProfile(name: String) async throws {
+        guard let profile = appState.profiles.profile(named: name) else {
+            throw StreamProfileError.notFound(name)
+        }
+
+        appState.streamConfig = profile.config
+        appState.settings.encoder = profile.encoder
+        appState.saveSettings()
+
+        let state = await getCurrentState()
+        if state == .streaming || state == .paused {
+            await stopStreaming()
+            try await startStreaming()
+        }
+    }
+
+    // MARK: - Auto-connect
+
+    /// Watch for a supported device being plugged in, connecting to it and,
+    /// if `AppSettings.resumeLastSession` is set, restarting the last stream
+    func startAutoConnect() {
+        guard autoConnector == nil else { return }
+
+        let devices = USBDeviceManager.shared
+        devices.startMonitoring()
+
+        let connector = AutoConnector(
+            watcher: devices,
+            connect: { [weak self] in
+                guard let self = self else { return false }
+                let state = await self.getCurrentState()
+                guard state == .disconnected || state == .error else { return false }
+                try await self.connect()
+                return true
+            },
+            shouldResume: { [weak self] in
+                guard let settings = self?.appState.settings else { return false }
+                return settings.resumeLastSession == true && settings.lastSession != nil
+            },
+            resume: { [weak self] in
+                guard let self = self, let session = self.appState.settings.lastSession else { return }
+                self.appState.streamConfig = session
+                try await self.startStreaming()
+            },
+            onStatus: { [weak self] status in
+                self?.appState.autoConnectStatus = status
+            }
+        )
+        autoConnector = connector
+        connector.start()
+    }
+
+    func stopAutoConnect() {
+        autoConnector?.stop()
+        autoConnector = nil
+        USBDeviceManager.shared.stopMonitoring()
+        appState.autoConnectStatus = nil
+    }
+
+    // MARK: - Start/Stop Shortcut
+
+    /// Register the shortcut saved in settings, e.g. at launch. One that
+    /// can't be registered any more is reported rather than dropped.
+    func registerToggleShortcut() {
+        guard let text = appState.settings.toggleStreamingShortcut else { return }
+        do {
+            try registerToggleShortcut(KeyShortcut(parsing: text))
+        } catch {
+            appState.lastError = "Start/stop shortcut: \(error.localizedDescription)"
+        }
+    }
+
+    /// Set the start/stop shortcut, or remove it with nil or an empty
+    /// string. It's only saved once registered, so a conflict or a typo is
+    /// thrown for settings to show.
+    func setToggleShortcut(_ text: String?) throws {
+        guard let text, !text.trimmingCharacters(in: .whitespaces).isEmpty else {
+            toggleShortcut.unregister()
+            appState.settings.toggleStreamingShortcut = nil
+            appState.saveSettings()
+            return
+        }
+
+        let shortcut = try KeyShortcut(parsing: text)
+        try registerToggleShortcut(shortcut)
+        appState.settings.toggleStreamingShortcut = shortcut.description
+        appState.saveSettings()
+
+        // Asked for here, when the user has just set it up, rather than at launch
+        UNUserNotificationCenter.current().requestAuthorization(options: [.alert]) { _, _ in }
+    }
+
+    private func registerToggleShortcut(_ shortcut: KeyShortcut) throws {
+        try toggleShortcut.register(shortcut) { [weak self] in
+            Task { await self?.toggleStreaming() }
+        }
+    }
+
+    /// Start streaming the current configuration, connecting first if need
+    /// be, or stop the stream, then confirm with a notification. What the
+    /// start/stop shortcut does.
+    func toggleStreaming() async {
+        let action = toggle.press(state: await getCurrentState())
+        guard action != .ignore else { return }
+        defer { toggle.finish() }
+
+        let result: StreamToggleResult
+        do {
+            switch action {
+            case .connectAndStart:
+                try await connect()
+                try await startStreaming()
+                result = .started
+            case .start:
+                try await startStreaming()
+                result = .started
+            case .stop:
+                await stopStreaming()
+                result = .stopped
+            case .ignore:
+                return
+            }
+        } catch {
+            appState.lastError = error.localizedDescription
+            result = .failed(error.localizedDescription)
+        }
+
+        NotificationCenter.default.post(name: .streamingToggled, object: result)
+        postNotification(result.message)
+    }
+
+    /// Show a banner, the app likely being behind others when the shortcut
+    /// is used
+    private func postNotification(_ message: (title: String, body: String)) {
+        let content = UNMutableNotificationContent()
+        content.title = message.title
+        content.body = message.body
+        let request = UNNotificationRequest(identifier: "streamingToggled", content: content, trigger: nil)
+        UNUserNotificationCenter.current().add(request)
+    }
+
     // MARK: - StreamingPipelineDelegate
 
     nonisolated func pipeline(_ pipeline: StreamingPipeline, didChangeState state: PipelineState) {
         Task { @MainActor in
             self.appState.updateFromPipelineState(state)
 
-            if state == .streaming {
-                self.appState.virtualDisplayId = VirtualDisplayManager.shared.displayId
-                self.appState.refreshDisplays()
-            } else if state == .disconnected || state == .ready {
-                if state == .disconnected {
-                    self.appState.virtualDisplayId = nil
-                }
-                self.appState.refreshDisplays()
+            if state == .streaming || state == .disconnected || state == .ready {
+                self.syncVirtualDisplays()
             }
         }
     }
@@ -105,6 +419,11 @@ final class StreamingService: ObservableObject, StreamingPipelineDelegate {
     nonisolated func pipeline(_ pipeline: StreamingPipeline, didEncounterError error: Error) {
         Task { @MainActor in
             self.appState.lastError = error.localizedDescription
+            // The pipeline restarts capture the system stopped, or stops
+            // streaming if it can't; the status follows its state
+            if case SerialWarpError.captureStopped = error {
+                return
+            }
             self.appState.connectionStatus = .error
         }
     }
@@ -81,7 +81,7 @@ class AboutView: NSView {
 
         // Screen Recording Permission row
         let permissionRow = SettingsRowView(label: "Screen Recording")
-        let hasPermission = CGPreflightScreenCaptureAccess()
+        let hasPermission = CaptureService.hasPermission()
         let permissionLabel = NSTextField(labelWithString: hasPermission ? "Granted" : "Not Granted")
         permissionLabel.translatesAutoresizingMaskIntoConstraints = false
         permissionLabel.font = .systemFont(ofSize: 13)
@@ -102,10 +102,18 @@ class AboutView: NSView {
             requestButton.bezelStyle = .rounded
             buttonView.addSubview(requestButton)
 
+            // macOS only prompts once; after that it has to be allowed here
+            let settingsButton = NSButton(title: "Open Privacy Settings", target: self, action: #selector(openPermissionSettings(_:)))
+            settingsButton.translatesAutoresizingMaskIntoConstraints = false
+            settingsButton.bezelStyle = .rounded
+            buttonView.addSubview(settingsButton)
+
             NSLayoutConstraint.activate([
                 buttonView.heightAnchor.constraint(equalToConstant: 40),
                 requestButton.centerYAnchor.constraint(equalTo: buttonView.centerYAnchor),
-                requestButton.leadingAnchor.constraint(equalTo: buttonView.leadingAnchor)
+                requestButton.leadingAnchor.constraint(equalTo: buttonView.leadingAnchor),
+                settingsButton.centerYAnchor.constraint(equalTo: buttonView.centerYAnchor),
+                settingsButton.leadingAnchor.constraint(equalTo: requestButton.trailingAnchor, constant: 8)
             ])
 
             stackView.addArrangedSubview(buttonView)
@@ -114,6 +122,10 @@ class AboutView: NSView {
     }
 
     @objc private func requestPermission(_ sender: NSButton) {
-        CGRequestScreenCaptureAccess()
+        StreamingService.shared.requestScreenRecordingPermission()
+    }
+
+    @objc private func openPermissionSettings(_ sender: NSButton) {
+        StreamingService.shared.openScreenRecordingSettings()
     }
 }
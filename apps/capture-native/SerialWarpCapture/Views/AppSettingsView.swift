@@ -8,8 +8,20 @@ class AppSettingsView: NSView {
 
     // Controls
     private var autoConnectSwitch: NSSwitch!
+    private var resumeSessionSwitch: NSSwitch!
+    private var shortcutField: NSTextField!
+    private var shortcutErrorLabel: NSTextField!
     private var previewEnabledSwitch: NSSwitch!
     private var previewQualitySlider: NSSlider!
+    private var profilePopup: NSPopUpButton!
+    private var bFramesSwitch: NSSwitch!
+    private var entropyPopup: NSPopUpButton!
+    private var frameDelayPopup: NSPopUpButton!
+    private var inputFormatPopup: NSPopUpButton!
+    private var sliceSizeSwitch: NSSwitch!
+    private var queueDepthPopup: NSPopUpButton!
+    private var channelCapacityPopup: NSPopUpButton!
+    private var excludedAppsPopup: NSPopUpButton!
 
     override init(frame frameRect: NSRect) {
         super.init(frame: frameRect)
@@ -47,13 +59,19 @@ class AppSettingsView: NSView {
 
         // Preview Settings Card
         setupPreviewCard()
+
+        // Privacy Settings Card
+        setupPrivacyCard()
+
+        // Advanced Encoder Settings Card
+        setupEncoderCard()
     }
 
     private func setupGeneralCard() {
         let card = SettingsCardView(title: "General")
 
         // Auto-connect row
-        let autoConnectRow = SettingsRowView(label: "Auto-connect on launch")
+        let autoConnectRow = SettingsRowView(label: "Connect when the cable is plugged in")
         autoConnectSwitch = NSSwitch()
         autoConnectSwitch.translatesAutoresizingMaskIntoConstraints = false
         autoConnectSwitch.target = self
@@ -61,6 +79,33 @@ class AppSettingsView: NSView {
         autoConnectRow.addControl(autoConnectSwitch)
         card.addRow(autoConnectRow)
 
+        // Resume last session row
+        let resumeSessionRow = SettingsRowView(label: "Resume the last session on connect")
+        resumeSessionSwitch = NSSwitch()
+        resumeSessionSwitch.translatesAutoresizingMaskIntoConstraints = false
+        resumeSessionSwitch.target = self
+        resumeSessionSwitch.action = #selector(resumeSessionChanged(_:))
+        resumeSessionRow.addControl(resumeSessionSwitch)
+        card.addRow(resumeSessionRow)
+
+        // Start/stop shortcut row, applied on Return; why a shortcut can't
+        // be used shows under it
+        let shortcutRow = SettingsRowView(label: "Start/stop streaming shortcut")
+        shortcutField = NSTextField()
+        shortcutField.translatesAutoresizingMaskIntoConstraints = false
+        shortcutField.placeholderString = KeyShortcut.suggested.description
+        shortcutField.widthAnchor.constraint(equalToConstant: 150).isActive = true
+        shortcutField.target = self
+        shortcutField.action = #selector(shortcutChanged(_:))
+        shortcutRow.addControl(shortcutField)
+        card.addRow(shortcutRow)
+
+        shortcutErrorLabel = NSTextField(labelWithString: "")
+        shortcutErrorLabel.font = .systemFont(ofSize: 11)
+        shortcutErrorLabel.textColor = .systemRed
+        shortcutErrorLabel.isHidden = true
+        card.addCustomView(shortcutErrorLabel)
+
         stackView.addArrangedSubview(card)
         card.widthAnchor.constraint(equalTo: stackView.widthAnchor).isActive = true
     }
@@ -90,15 +135,186 @@ class AppSettingsView: NSView {
         card.widthAnchor.constraint(equalTo: stackView.widthAnchor).isActive = true
     }
 
+    private func setupPrivacyCard() {
+        let card = SettingsCardView(title: "Privacy")
+
+        // Excluded applications row; the menu lists what's running when opened
+        let excludedAppsRow = SettingsRowView(label: "Hide applications from capture")
+        excludedAppsPopup = NSPopUpButton(frame: .zero, pullsDown: true)
+        excludedAppsPopup.translatesAutoresizingMaskIntoConstraints = false
+        excludedAppsPopup.menu?.delegate = self
+        excludedAppsRow.addControl(excludedAppsPopup)
+        card.addRow(excludedAppsRow)
+
+        stackView.addArrangedSubview(card)
+        card.widthAnchor.constraint(equalTo: stackView.widthAnchor).isActive = true
+    }
+
+    private func setupEncoderCard() {
+        let card = SettingsCardView(title: "Encoder (Advanced)")
+
+        // Profile row
+        let profileRow = SettingsRowView(label: "H.264 profile")
+        profilePopup = NSPopUpButton(frame: .zero, pullsDown: false)
+        profilePopup.translatesAutoresizingMaskIntoConstraints = false
+        profilePopup.addItems(withTitles: ["Baseline", "Main", "High"])
+        profilePopup.target = self
+        profilePopup.action = #selector(profileChanged(_:))
+        profileRow.addControl(profilePopup)
+        card.addRow(profileRow)
+
+        // B-frames row
+        let bFramesRow = SettingsRowView(label: "Allow B-frames (adds latency)")
+        bFramesSwitch = NSSwitch()
+        bFramesSwitch.translatesAutoresizingMaskIntoConstraints = false
+        bFramesSwitch.target = self
+        bFramesSwitch.action = #selector(bFramesChanged(_:))
+        bFramesRow.addControl(bFramesSwitch)
+        card.addRow(bFramesRow)
+
+        // Entropy mode row
+        let entropyRow = SettingsRowView(label: "Entropy coding")
+        entropyPopup = NSPopUpButton(frame: .zero, pullsDown: false)
+        entropyPopup.translatesAutoresizingMaskIntoConstraints = false
+        entropyPopup.addItems(withTitles: ["Automatic", "CAVLC", "CABAC"])
+        entropyPopup.target = self
+        entropyPopup.action = #selector(entropyChanged(_:))
+        entropyRow.addControl(entropyPopup)
+        card.addRow(entropyRow)
+
+        // Max frame delay row
+        let frameDelayRow = SettingsRowView(label: "Max frame delay")
+        frameDelayPopup = NSPopUpButton(frame: .zero, pullsDown: false)
+        frameDelayPopup.translatesAutoresizingMaskIntoConstraints = false
+        for delay in EncoderSettings.frameDelays {
+            frameDelayPopup.addItem(withTitle: delay.map { "\($0) frames" } ?? "No limit")
+        }
+        frameDelayPopup.target = self
+        frameDelayPopup.action = #selector(frameDelayChanged(_:))
+        frameDelayRow.addControl(frameDelayPopup)
+        card.addRow(frameDelayRow)
+
+        // Capture format row
+        let inputFormatRow = SettingsRowView(label: "Capture format")
+        inputFormatPopup = NSPopUpButton(frame: .zero, pullsDown: false)
+        inputFormatPopup.translatesAutoresizingMaskIntoConstraints = false
+        inputFormatPopup.addItems(withTitles: ["BGRA", "NV12 (video range)", "NV12 (full range)"])
+        inputFormatPopup.target = self
+        inputFormatPopup.action = #selector(inputFormatChanged(_:))
+        inputFormatRow.addControl(inputFormatPopup)
+        card.addRow(inputFormatRow)
+
+        // Slice size row
+        let sliceSizeRow = SettingsRowView(label: "Fit slices in one USB segment")
+        sliceSizeSwitch = NSSwitch()
+        sliceSizeSwitch.translatesAutoresizingMaskIntoConstraints = false
+        sliceSizeSwitch.target = self
+        sliceSizeSwitch.action = #selector(sliceSizeChanged(_:))
+        sliceSizeRow.addControl(sliceSizeSwitch)
+        card.addRow(sliceSizeRow)
+
+        // Capture queue rows; each queued frame is a whole frame of memory
+        let queueDepthRow = SettingsRowView(label: "Capture queue depth")
+        queueDepthPopup = captureDepthPopup(defaultDepth: CaptureConfiguration.defaultQueueDepth)
+        queueDepthPopup.action = #selector(queueDepthChanged(_:))
+        queueDepthRow.addControl(queueDepthPopup)
+        card.addRow(queueDepthRow)
+
+        let channelCapacityRow = SettingsRowView(label: "Frames buffered for encoding")
+        channelCapacityPopup = captureDepthPopup(defaultDepth: CaptureConfiguration.defaultChannelCapacity)
+        channelCapacityPopup.action = #selector(channelCapacityChanged(_:))
+        channelCapacityRow.addControl(channelCapacityPopup)
+        card.addRow(channelCapacityRow)
+
+        stackView.addArrangedSubview(card)
+        card.widthAnchor.constraint(equalTo: stackView.widthAnchor).isActive = true
+    }
+
+    private func captureDepthPopup(defaultDepth: Int) -> NSPopUpButton {
+        let popup = NSPopUpButton(frame: .zero, pullsDown: false)
+        popup.translatesAutoresizingMaskIntoConstraints = false
+        for depth in EncoderSettings.captureDepths {
+            popup.addItem(withTitle: depth.map { "\($0) frames" } ?? "Default (\(defaultDepth))")
+        }
+        popup.target = self
+        return popup
+    }
+
     private func loadSettings() {
         autoConnectSwitch.state = appState.settings.autoConnect ? .on : .off
+        resumeSessionSwitch.state = appState.settings.resumeLastSession == true ? .on : .off
+        resumeSessionSwitch.isEnabled = appState.settings.autoConnect
+        shortcutField.stringValue = appState.settings.toggleStreamingShortcut ?? ""
         previewEnabledSwitch.state = appState.settings.previewEnabled ? .on : .off
         previewQualitySlider.integerValue = Int(appState.settings.previewQuality)
+        loadExclusions()
+        loadEncoderSettings()
+    }
+
+    private func loadExclusions() {
+        let count = appState.settings.captureExclusions?.bundleIds.count ?? 0
+        // A pull-down shows its first item as the title
+        excludedAppsPopup.removeAllItems()
+        excludedAppsPopup.addItem(withTitle: count == 0 ? "None" : "\(count) hidden")
+    }
+
+    private func loadEncoderSettings() {
+        let encoder = appState.settings.encoder ?? .default
+        let profiles = EncoderConfiguration.ProfileLevel.allCases
+        profilePopup.selectItem(at: profiles.firstIndex(of: encoder.profile) ?? 0)
+        bFramesSwitch.state = encoder.allowBFrames ? .on : .off
+        let modes = EncoderConfiguration.EntropyMode.allCases
+        entropyPopup.selectItem(at: encoder.entropy.flatMap { modes.firstIndex(of: $0) }.map { $0 + 1 } ?? 0)
+        frameDelayPopup.selectItem(at: EncoderSettings.frameDelays.firstIndex(of: encoder.maxFrameDelay) ?? 0)
+        let formats = EncoderConfiguration.InputFormat.allCases
+        inputFormatPopup.selectItem(at: formats.firstIndex(of: encoder.effectiveInputFormat) ?? 0)
+        sliceSizeSwitch.state = encoder.limitSliceSize == true ? .on : .off
+        queueDepthPopup.selectItem(at: EncoderSettings.captureDepths.firstIndex(of: encoder.captureQueueDepth) ?? 0)
+        channelCapacityPopup.selectItem(
+            at: EncoderSettings.captureDepths.firstIndex(of: encoder.captureChannelCapacity) ?? 0
+        )
+
+        // Baseline has no B-frames and only CAVLC
+        let baseline = encoder.profile == .baseline
+        bFramesSwitch.isEnabled = !baseline
+        entropyPopup.isEnabled = !baseline
+    }
+
+    private func updateEncoderSettings(_ update: (inout EncoderSettings) -> Void) {
+        var encoder = appState.settings.encoder ?? .default
+        update(&encoder)
+        appState.settings.encoder = encoder
+        appState.saveSettings()
+        loadEncoderSettings()
     }
 
     @objc private func autoConnectChanged(_ sender: NSSwitch) {
         appState.settings.autoConnect = sender.state == .on
-        appState.saveCurrentSettings()
+        appState.saveSettings()
+        resumeSessionSwitch.isEnabled = appState.settings.autoConnect
+
+        if appState.settings.autoConnect {
+            StreamingService.shared.startAutoConnect()
+        } else {
+            StreamingService.shared.stopAutoConnect()
+        }
+    }
+
+    @objc private func resumeSessionChanged(_ sender: NSSwitch) {
+        appState.settings.resumeLastSession = sender.state == .on
+        appState.saveSettings()
+    }
+
+    @objc private func shortcutChanged(_ sender: NSTextField) {
+        do {
+            try StreamingService.shared.setToggleShortcut(sender.stringValue)
+            shortcutErrorLabel.isHidden = true
+            // Shown as parsed, e.g. "cmd+opt+s" as "Option+Cmd+S"
+            sender.stringValue = appState.settings.toggleStreamingShortcut ?? ""
+        } catch {
+            shortcutErrorLabel.stringValue = error.localizedDescription
+            shortcutErrorLabel.isHidden = false
+        }
     }
 
     @objc private func previewEnabledChanged(_ sender: NSSwitch) {
@@ -110,4 +326,84 @@ class AppSettingsView: NSView {
         appState.settings.previewQuality = UInt32(sender.integerValue)
         appState.saveCurrentSettings()
     }
+
+    @objc private func profileChanged(_ sender: NSPopUpButton) {
+        let profiles = EncoderConfiguration.ProfileLevel.allCases
+        updateEncoderSettings { $0.profile = profiles[sender.indexOfSelectedItem] }
+    }
+
+    @objc private func bFramesChanged(_ sender: NSSwitch) {
+        updateEncoderSettings { $0.allowBFrames = sender.state == .on }
+    }
+
+    @objc private func entropyChanged(_ sender: NSPopUpButton) {
+        let modes = EncoderConfiguration.EntropyMode.allCases
+        let index = sender.indexOfSelectedItem
+        updateEncoderSettings { $0.entropy = index == 0 ? nil : modes[index - 1] }
+    }
+
+    @objc private func frameDelayChanged(_ sender: NSPopUpButton) {
+        updateEncoderSettings { $0.maxFrameDelay = EncoderSettings.frameDelays[sender.indexOfSelectedItem] }
+    }
+
+    @objc private func inputFormatChanged(_ sender: NSPopUpButton) {
+        let formats = EncoderConfiguration.InputFormat.allCases
+        updateEncoderSettings { $0.inputFormat = formats[sender.indexOfSelectedItem] }
+    }
+
+    @objc private func sliceSizeChanged(_ sender: NSSwitch) {
+        updateEncoderSettings { $0.limitSliceSize = sender.state == .on }
+    }
+
+    @objc private func queueDepthChanged(_ sender: NSPopUpButton) {
+        updateEncoderSettings { $0.captureQueueDepth = EncoderSettings.captureDepths[sender.indexOfSelectedItem] }
+    }
+
+    @objc private func channelCapacityChanged(_ sender: NSPopUpButton) {
+        updateEncoderSettings { $0.captureChannelCapacity = EncoderSettings.captureDepths[sender.indexOfSelectedItem] }
+    }
+
+    @objc private func excludedAppToggled(_ sender: NSMenuItem) {
+        guard let bundleId = sender.representedObject as? String else { return }
+        var exclusions = appState.settings.captureExclusions ?? .none
+        if let index = exclusions.bundleIds.firstIndex(of: bundleId) {
+            exclusions.bundleIds.remove(at: index)
+        } else {
+            exclusions.bundleIds.append(bundleId)
+        }
+
+        Task {
+            do {
+                try await StreamingService.shared.setCaptureExclusions(exclusions)
+            } catch {
+                appState.lastError = error.localizedDescription
+            }
+            loadExclusions()
+        }
+    }
+}
+
+// MARK: - NSMenuDelegate
+
+extension AppSettingsView: NSMenuDelegate {
+    /// List the running applications, plus excluded ones that aren't
+    /// running, each checked if it's excluded
+    func menuNeedsUpdate(_ menu: NSMenu) {
+        let excluded = appState.settings.captureExclusions?.bundleIds ?? []
+        var apps = StreamingService.shared.listRunningApplications()
+        let running = Set(apps.map(\.bundleId))
+        apps += excluded.filter { !running.contains($0) }.map { RunningApplication(bundleId: $0, name: $0) }
+
+        // Keep the title item
+        while menu.items.count > 1 {
+            menu.removeItem(at: 1)
+        }
+        for app in apps {
+            let item = NSMenuItem(title: app.name, action: #selector(excludedAppToggled(_:)), keyEquivalent: "")
+            item.target = self
+            item.representedObject = app.bundleId
+            item.state = excluded.contains(app.bundleId) ? .on : .off
+            menu.addItem(item)
+        }
+    }
 }
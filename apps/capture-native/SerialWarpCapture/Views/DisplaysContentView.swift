@@ -236,7 +236,7 @@ class DisplaysContentView: NSView {
     }
 
     private func bindState() {
-        appState.$virtualDisplayId
+        appState.$virtualDisplays
             .receive(on: DispatchQueue.main)
             .sink { [weak self] _ in
                 self?.updateState()
@@ -250,6 +250,20 @@ class DisplaysContentView: NSView {
             }
             .store(in: &cancellables)
 
+        appState.$connectionStatus
+            .receive(on: DispatchQueue.main)
+            .sink { [weak self] _ in
+                self?.updateState()
+            }
+            .store(in: &cancellables)
+
+        appState.$virtualDisplayUnavailable
+            .receive(on: DispatchQueue.main)
+            .sink { [weak self] _ in
+                self?.updateState()
+            }
+            .store(in: &cancellables)
+
         appState.$streamStats
             .receive(on: DispatchQueue.main)
             .sink { [weak self] stats in
@@ -268,26 +282,35 @@ class DisplaysContentView: NSView {
     }
 
     func updateState() {
-        let hasDisplay = appState.virtualDisplayId != nil
+        let hasDisplay = !appState.virtualDisplays.isEmpty
         let isStreaming = appState.isStreaming
+        let unavailable = appState.virtualDisplayUnavailable
 
         // Update buttons
         createDisplayButton.title = hasDisplay ? "Destroy Virtual Display" : "Create Virtual Display"
+        // Destroying is always allowed
+        createDisplayButton.isEnabled = hasDisplay || unavailable == nil
+        createDisplayButton.toolTip = hasDisplay ? nil : unavailable?.localizedDescription
         startStreamButton.isEnabled = hasDisplay
         startStreamButton.title = isStreaming ? "Stop Streaming" : "Start Streaming"
 
         // Update status indicator
-        if isStreaming {
+        if appState.connectionStatus == .paused {
+            statusIndicator.setStatus(.paused, text: "Paused")
+        } else if isStreaming {
             statusIndicator.setStatus(.streaming, text: "Streaming")
         } else if hasDisplay {
             statusIndicator.setStatus(.ready, text: "Ready")
+        } else if let unavailable = unavailable {
+            statusIndicator.setStatus(.error, text: unavailable.localizedDescription)
         } else {
             statusIndicator.setStatus(.idle, text: "No display")
         }
 
         // Disable settings while streaming
         resolutionPopup.isEnabled = !isStreaming && !hasDisplay
-        fpsPopup.isEnabled = !isStreaming && !hasDisplay
+        // Frame rate can change while streaming without restarting capture
+        fpsPopup.isEnabled = isStreaming || !hasDisplay
         hidpiSwitch.isEnabled = !isStreaming && !hasDisplay
         bitratePopup.isEnabled = !isStreaming
     }
@@ -311,7 +334,18 @@ class DisplaysContentView: NSView {
     @objc private func fpsChanged(_ sender: NSPopUpButton) {
         let index = sender.indexOfSelectedItem
         guard index >= 0 && index < StreamConfig.frameRates.count else { return }
-        appState.streamConfig.fps = StreamConfig.frameRates[index]
+        let fps = StreamConfig.frameRates[index]
+        guard appState.isStreaming else {
+            appState.streamConfig.fps = fps
+            return
+        }
+        Task {
+            do {
+                try await StreamingService.shared.setFps(fps)
+            } catch {
+                showError(error)
+            }
+        }
     }
 
     @objc private func bitrateChanged(_ sender: NSPopUpButton) {
@@ -325,9 +359,9 @@ class DisplaysContentView: NSView {
     }
 
     @objc private func createDisplayTapped(_ sender: NSButton) {
-        if appState.virtualDisplayId != nil {
+        if !appState.virtualDisplays.isEmpty {
             // Destroy display
-            StreamingService.shared.destroyVirtualDisplay()
+            StreamingService.shared.destroyAllVirtualDisplays()
         } else {
             // Create display
             do {
@@ -360,7 +394,13 @@ class DisplaysContentView: NSView {
         alert.informativeText = error.localizedDescription
         alert.alertStyle = .warning
         alert.addButton(withTitle: "OK")
-        alert.runModal()
+        if case SerialWarpError.permissionDenied = error {
+            alert.messageText = "Screen Recording Not Allowed"
+            alert.addButton(withTitle: "Open Privacy Settings")
+        }
+        if alert.runModal() == .alertSecondButtonReturn {
+            StreamingService.shared.openScreenRecordingSettings()
+        }
     }
 }
 
@@ -396,6 +436,7 @@ enum StatusState {
     case idle
     case ready
     case streaming
+    case paused
     case error
 }
 
@@ -453,6 +494,8 @@ class StatusIndicatorView: NSView {
         case .streaming:
             dotView.layer?.backgroundColor = NSColor.systemGreen.cgColor
             addPulseAnimation()
+        case .paused:
+            dotView.layer?.backgroundColor = NSColor.systemYellow.cgColor
         case .error:
             dotView.layer?.backgroundColor = NSColor.systemRed.cgColor
         }
@@ -0,0 +1,82 @@
+import XCTest
+@testable import SerialWarpCapture
+
+private struct FakeWindow: ShareableWindow, Equatable {
+    let windowID: CGWindowID
+    let owningBundleIdentifier: String?
+}
+
+final class CaptureExclusionsTests: XCTestCase {
+
+    private let windows = [
+        FakeWindow(windowID: 1, owningBundleIdentifier: "com.apple.Safari"),
+        FakeWindow(windowID: 2, owningBundleIdentifier: "com.1password.1password"),
+        FakeWindow(windowID: 3, owningBundleIdentifier: "com.1password.1password"),
+        FakeWindow(windowID: 4, owningBundleIdentifier: "com.apple.notificationcenterui"),
+        FakeWindow(windowID: 5, owningBundleIdentifier: nil),
+    ]
+
+    // MARK: - Matching
+
+    func testNoExclusions() {
+        XCTAssertTrue(CaptureExclusions.none.isEmpty)
+        XCTAssertEqual(CaptureExclusions.none.matching(windows), [])
+    }
+
+    func testByBundleId() {
+        let exclusions = CaptureExclusions(bundleIds: ["com.1password.1password"])
+        XCTAssertEqual(exclusions.matching(windows).map(\.windowID), [2, 3])
+    }
+
+    func testByWindowId() {
+        let exclusions = CaptureExclusions(windowIds: [4, 5, 99])
+        XCTAssertEqual(exclusions.matching(windows).map(\.windowID), [4, 5])
+    }
+
+    func testBothCombined() {
+        let exclusions = CaptureExclusions(bundleIds: ["com.apple.Safari"], windowIds: [3])
+        XCTAssertEqual(exclusions.matching(windows).map(\.windowID), [1, 3])
+    }
+
+    func testAppNotRunningYet() {
+        // Matches nothing now; the periodic refresh picks it up on launch
+        let exclusions = CaptureExclusions(bundleIds: ["com.example.NotRunning"])
+        XCTAssertEqual(exclusions.matching(windows), [])
+
+        let launched = windows + [FakeWindow(windowID: 6, owningBundleIdentifier: "com.example.NotRunning")]
+        XCTAssertEqual(exclusions.matching(launched).map(\.windowID), [6])
+    }
+
+    func testEveryWindowExcluded() {
+        // Not an error: the display is still captured, showing the wallpaper
+        let exclusions = CaptureExclusions(windowIds: windows.map(\.windowID))
+        XCTAssertEqual(exclusions.matching(windows), windows)
+    }
+
+    // MARK: - Plumbing
+
+    func testReachesCaptureConfiguration() {
+        let exclusions = CaptureExclusions(bundleIds: ["com.1password.1password"], windowIds: [7])
+        let config = StreamConfig.default.toStreamConfiguration(exclusions: exclusions)
+        XCTAssertEqual(config.captureConfiguration.exclusions, exclusions)
+        XCTAssertEqual(StreamConfig.default.toStreamConfiguration().captureConfiguration.exclusions, .none)
+    }
+
+    func testSettingsRoundTrip() throws {
+        var settings = AppSettings.default
+        settings.captureExclusions = CaptureExclusions(bundleIds: ["com.1password.1password"])
+
+        let data = try JSONEncoder().encode(settings)
+        let decoded = try JSONDecoder().decode(AppSettings.self, from: data)
+        XCTAssertEqual(decoded.captureExclusions, settings.captureExclusions)
+    }
+
+    func testDecodesSettingsFromBeforeExclusions() throws {
+        let json = """
+        {"defaultResolution":"1920x1080","defaultFps":60,"defaultBitrateMbps":20,
+         "autoConnect":false,"previewEnabled":true,"previewQuality":50}
+        """
+        let settings = try JSONDecoder().decode(AppSettings.self, from: Data(json.utf8))
+        XCTAssertNil(settings.captureExclusions)
+    }
+}
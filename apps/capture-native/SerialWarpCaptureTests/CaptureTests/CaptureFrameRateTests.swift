@@ -0,0 +1,92 @@
+import XCTest
+import AppKit
+import CoreMedia
+@testable import SerialWarpCapture
+
+final class CaptureFrameRateTests: XCTestCase {
+
+    private let config = CaptureConfiguration(width: 1920, height: 1080, fps: 60)
+
+    // MARK: - Configuration
+
+    func testWithKeepsOtherSettings() {
+        let changed = config.with(fps: 30)
+        XCTAssertEqual(changed.fps, 30)
+        XCTAssertEqual(changed.width, 1920)
+        XCTAssertEqual(changed.height, 1080)
+        XCTAssertEqual(changed.pixelFormat, config.pixelFormat)
+
+        let resized = config.with(width: 1280, height: 720)
+        XCTAssertEqual(resized.width, 1280)
+        XCTAssertEqual(resized.height, 720)
+        XCTAssertEqual(resized.fps, 60)
+    }
+
+    func testNotCapturing() async {
+        let capture = CaptureService()
+
+        do {
+            try await capture.setFps(30)
+            XCTFail("Changed the frame rate without capturing")
+        } catch SerialWarpError.captureUpdateFailed {
+            // Expected
+        } catch {
+            XCTFail("Unexpected error: \(error)")
+        }
+    }
+
+    // MARK: - Live Capture
+
+    /// Captures the main display, which only produces frames when something
+    /// changes, so a window flashes on it throughout
+    @MainActor
+    func testIntervalDoublesAfterHalvingFps() async throws {
+        try XCTSkipUnless(CaptureService.hasPermission(), "Needs screen recording permission")
+
+        let window = NSWindow(
+            contentRect: NSRect(x: 0, y: 0, width: 200, height: 200),
+            styleMask: .borderless,
+            backing: .buffered,
+            defer: false
+        )
+        window.level = .floating
+        window.orderFrontRegardless()
+        let flasher = Timer.scheduledTimer(withTimeInterval: 1.0 / 240.0, repeats: true) { _ in
+            window.backgroundColor = window.backgroundColor == .red ? .blue : .red
+        }
+        defer {
+            flasher.invalidate()
+            window.orderOut(nil)
+        }
+
+        let capture = CaptureService()
+        let frames = try await capture.startCapture(displayId: CGMainDisplayID(), config: config)
+        var iterator = frames.makeAsyncIterator()
+
+        func collect(for seconds: Double) async throws -> [Double] {
+            var times: [Double] = []
+            let end = Date().addingTimeInterval(seconds)
+            while Date() < end, let frame = try await iterator.next() {
+                times.append(CMTimeGetSeconds(frame.presentationTime))
+            }
+            return times
+        }
+
+        let before = try await collect(for: 1)
+        try await capture.setFps(30)
+        // Frames already in flight were captured at the old rate
+        _ = try await collect(for: 0.2)
+        let after = try await collect(for: 1)
+        await capture.stopCapture()
+
+        let all = before + after
+        XCTAssertEqual(all, all.sorted(), "Timestamps went backwards")
+
+        func minimumInterval(_ times: [Double]) -> Double {
+            zip(times.dropFirst(), times).map { $0 - $1 }.min() ?? 0
+        }
+        XCTAssertGreaterThan(after.count, 10)
+        XCTAssertLessThan(minimumInterval(before), 0.025)
+        XCTAssertGreaterThan(minimumInterval(after), 0.030)
+    }
+}
@@ -0,0 +1,61 @@
+import XCTest
+import ScreenCaptureKit
+@testable import SerialWarpCapture
+
+final class CapturePermissionTests: XCTestCase {
+
+    private let config = CaptureConfiguration(width: 1920, height: 1080, fps: 60)
+
+    // MARK: - Preflight
+
+    func testDeniedBeforeCapturing() async {
+        let capture = CaptureService(preflight: { false })
+
+        do {
+            _ = try await capture.startCapture(displayId: CGMainDisplayID(), config: config)
+            XCTFail("Capture started without permission")
+        } catch SerialWarpError.permissionDenied {
+            // Expected
+        } catch {
+            XCTFail("Unexpected error: \(error)")
+        }
+
+        let isCapturing = await capture.isCapturing
+        XCTAssertFalse(isCapturing)
+    }
+
+    func testPreflightCheckedFirst() async {
+        // An invalid configuration would fail too, but permission is the
+        // more useful thing to report
+        let capture = CaptureService(preflight: { false })
+        let invalid = CaptureConfiguration(width: 0, height: 0, fps: 60)
+
+        do {
+            _ = try await capture.startCapture(displayId: CGMainDisplayID(), config: invalid)
+            XCTFail("Capture started without permission")
+        } catch SerialWarpError.permissionDenied {
+            // Expected
+        } catch {
+            XCTFail("Unexpected error: \(error)")
+        }
+    }
+
+    // MARK: - Error Mapping
+
+    func testMapsUserDeclined() {
+        let mapped = CaptureService.mapCaptureError(SCStreamError(.userDeclined))
+        guard case SerialWarpError.permissionDenied = mapped else {
+            return XCTFail("Unexpected error: \(mapped)")
+        }
+    }
+
+    func testKeepsOtherErrors() {
+        let mapped = CaptureService.mapCaptureError(SCStreamError(.noDisplayList))
+        XCTAssertEqual((mapped as? SCStreamError)?.code, .noDisplayList)
+
+        let other = CaptureService.mapCaptureError(SerialWarpError.displayNotFound(7))
+        guard case SerialWarpError.displayNotFound(7) = other else {
+            return XCTFail("Unexpected error: \(other)")
+        }
+    }
+}
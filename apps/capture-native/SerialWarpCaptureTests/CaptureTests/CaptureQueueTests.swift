@@ -0,0 +1,87 @@
+import XCTest
+import CoreMedia
+@testable import SerialWarpCapture
+
+final class CaptureQueueTests: XCTestCase {
+
+    // MARK: - Validation
+
+    func testDepthsValidated() {
+        XCTAssertNoThrow(try CaptureConfiguration(width: 1920, height: 1080, fps: 60).validate())
+        XCTAssertNoThrow(try CaptureConfiguration(width: 1920, height: 1080, fps: 60, queueDepth: 1, channelCapacity: 16).validate())
+
+        for depth in [0, 17] {
+            let queue = CaptureConfiguration(width: 1920, height: 1080, fps: 60, queueDepth: depth)
+            XCTAssertThrowsError(try queue.validate())
+            let channel = CaptureConfiguration(width: 1920, height: 1080, fps: 60, channelCapacity: depth)
+            XCTAssertThrowsError(try channel.validate())
+        }
+    }
+
+    func testWithKeepsDepths() {
+        let config = CaptureConfiguration(width: 1920, height: 1080, fps: 60, queueDepth: 3, channelCapacity: 2)
+        let changed = config.with(width: 3840, height: 2160)
+        XCTAssertEqual(changed.queueDepth, 3)
+        XCTAssertEqual(changed.channelCapacity, 2)
+    }
+
+    // MARK: - Capacities
+
+    func testQueueDepthReachesStream() {
+        let config = CaptureConfiguration(width: 1920, height: 1080, fps: 60, queueDepth: 5)
+        XCTAssertEqual(CaptureService.streamConfiguration(for: config).queueDepth, 5)
+    }
+
+    func testChannelKeepsNewestFrames() throws {
+        let config = CaptureConfiguration(width: 64, height: 32, fps: 60, channelCapacity: 3)
+        var continuation: AsyncThrowingStream<CapturedFrame, Error>.Continuation!
+        let stream = AsyncThrowingStream<CapturedFrame, Error>(
+            bufferingPolicy: CaptureService.frameBuffering(for: config)
+        ) { continuation = $0 }
+
+        var pixelBuffer: CVPixelBuffer?
+        CVPixelBufferCreate(kCFAllocatorDefault, 64, 32, CaptureConfiguration.pixelFormatBGRA, nil, &pixelBuffer)
+        let buffer = try XCTUnwrap(pixelBuffer)
+        var dropped = 0
+        for value in 0..<5 {
+            let frame = CapturedFrame(pixelBuffer: buffer, presentationTime: CMTime(value: CMTimeValue(value), timescale: 60))
+            if case .dropped = continuation.yield(frame) {
+                dropped += 1
+            }
+        }
+        continuation.finish()
+        XCTAssertEqual(dropped, 2)
+
+        let expectation = expectation(description: "frames read")
+        Task {
+            var values: [CMTimeValue] = []
+            for try await frame in stream {
+                values.append(frame.presentationTime.value)
+            }
+            XCTAssertEqual(values, [2, 3, 4])
+            expectation.fulfill()
+        }
+        wait(for: [expectation], timeout: 1)
+    }
+
+    // MARK: - Memory
+
+    func testMemoryEstimate() {
+        XCTAssertEqual(
+            CaptureConfiguration.bytesPerFrame(width: 1920, height: 1080, pixelFormat: CaptureConfiguration.pixelFormatBGRA),
+            8_294_400
+        )
+        XCTAssertEqual(
+            CaptureConfiguration.bytesPerFrame(width: 1920, height: 1080, pixelFormat: CaptureConfiguration.pixelFormatNV12),
+            3_110_400
+        )
+
+        // 4K BGRA at the default depth
+        let uhd = CaptureConfiguration(width: 3840, height: 2160, fps: 60)
+        XCTAssertEqual(uhd.estimatedBufferBytes, 8 * 33_177_600)
+
+        // An odd size is captured at its even coded size
+        let odd = CaptureConfiguration(width: 1921, height: 1081, fps: 60, queueDepth: 2)
+        XCTAssertEqual(odd.estimatedBufferBytes, 2 * 1922 * 1082 * 4)
+    }
+}
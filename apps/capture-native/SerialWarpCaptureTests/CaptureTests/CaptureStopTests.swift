@@ -0,0 +1,68 @@
+import XCTest
+import ScreenCaptureKit
+@testable import SerialWarpCapture
+
+final class CaptureStopTests: XCTestCase {
+
+    // MARK: - Error Mapping
+
+    func testPermissionRevoked() {
+        let error = CaptureService.stopError(for: SCStreamError(.userDeclined))
+        guard case SerialWarpError.permissionDenied = error else {
+            return XCTFail("Unexpected error: \(error)")
+        }
+    }
+
+    func testSystemStop() {
+        let error = CaptureService.stopError(for: SCStreamError(.connectionInterrupted))
+        guard case SerialWarpError.captureStopped = error else {
+            return XCTFail("Unexpected error: \(error)")
+        }
+    }
+
+    func testKeepsOwnErrors() {
+        let error = CaptureService.stopError(for: SerialWarpError.displayNotFound(7))
+        guard case SerialWarpError.displayNotFound(7) = error else {
+            return XCTFail("Unexpected error: \(error)")
+        }
+    }
+
+    // MARK: - Delegate
+
+    /// ScreenCaptureKit calling the delegate ends the frame stream with the
+    /// error, instead of the consumer waiting forever
+    func testStopEndsFrameStream() async throws {
+        try XCTSkipUnless(CaptureService.hasPermission(), "Needs screen recording permission")
+
+        let capture = CaptureService()
+        let frames = try await capture.startCapture(
+            displayId: CGMainDisplayID(),
+            config: CaptureConfiguration(width: 1280, height: 720, fps: 30)
+        )
+
+        // Any stream will do; the delegate doesn't look at it
+        let content = try await SCShareableContent.current
+        let display = try XCTUnwrap(content.displays.first)
+        let stream = SCStream(
+            filter: SCContentFilter(display: display, excludingWindows: []),
+            configuration: SCStreamConfiguration(),
+            delegate: nil
+        )
+        capture.stream(stream, didStopWithError: SCStreamError(.connectionInterrupted))
+
+        let drained = Task {
+            for try await _ in frames {}
+        }
+        do {
+            try await drained.value
+            XCTFail("Frame stream ended without an error")
+        } catch SerialWarpError.captureStopped {
+            // Expected
+        } catch {
+            XCTFail("Unexpected error: \(error)")
+        }
+
+        let isCapturing = await capture.isCapturing
+        XCTAssertFalse(isCapturing)
+    }
+}
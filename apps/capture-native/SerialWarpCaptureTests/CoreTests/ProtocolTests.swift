@@ -14,6 +14,7 @@ final class ProtocolTests: XCTestCase {
         XCTAssertEqual(PacketType.frameAck.rawValue, 0x11)
         XCTAssertEqual(PacketType.stop.rawValue, 0x30)
         XCTAssertEqual(PacketType.stopAck.rawValue, 0x31)
+        XCTAssertEqual(PacketType.pause.rawValue, 0x32)
         XCTAssertEqual(PacketType.ping.rawValue, 0x40)
         XCTAssertEqual(PacketType.pong.rawValue, 0x41)
     }
@@ -159,6 +160,19 @@ final class ProtocolTests: XCTestCase {
         XCTAssertEqual(parsed.capabilities, 0x03)
         XCTAssertTrue(parsed.supportsHidpi)
         XCTAssertTrue(parsed.supportsAudio)
+        XCTAssertEqual(parsed.effectiveMaxScale, 1)
+    }
+
+    func testHelloPayloadMaxScale() throws {
+        let payload = HelloPayload(softwareVersion: 1, maxWidth: 3840, maxHeight: 2160, maxFps: 60, capabilities: 0, maxScale: 2)
+        let bytes = payload.toBytes()
+        XCTAssertEqual(bytes.readUInt16LE(at: 6), 2)
+        XCTAssertEqual(try HelloPayload.parse(bytes).effectiveMaxScale, 2)
+
+        // Peers that predate the scale leave it 0
+        var old = bytes
+        old[6] = 0
+        XCTAssertEqual(try HelloPayload.parse(old).effectiveMaxScale, 1)
     }
 
     // MARK: - Start Payload Tests
@@ -172,7 +186,7 @@ final class ProtocolTests: XCTestCase {
         )
 
         let bytes = payload.toBytes()
-        XCTAssertEqual(bytes.count, SWRPConstants.PayloadSize.start)
+        XCTAssertEqual(bytes.count, SWRPConstants.PayloadSize.startExtended)
 
         XCTAssertEqual(bytes.readUInt32LE(at: 0), 1920)
         XCTAssertEqual(bytes.readUInt32LE(at: 4), 1080)
@@ -197,6 +211,59 @@ final class ProtocolTests: XCTestCase {
         XCTAssertEqual(parsed.bitrateBps, 20_000_000)
     }
 
+    func testStartPayloadPadsOddSizes() throws {
+        let cases: [(UInt32, UInt32, UInt32, UInt32)] = [
+            (1366, 768, 1366, 768),
+            (1367, 769, 1368, 770),
+            (1, 1, 2, 2)
+        ]
+        for (width, height, codedWidth, codedHeight) in cases {
+            let bytes = StartPayload(width: width, height: height, fps: 60, bitrateBps: 1_000_000).toBytes()
+            let parsed = try StartPayload.parse(bytes)
+            XCTAssertEqual(parsed.codedSize, Resolution(width: codedWidth, height: codedHeight))
+            XCTAssertEqual(parsed.displaySize, Resolution(width: width, height: height))
+        }
+    }
+
+    func testStartPayloadScale() throws {
+        let bytes = StartPayload(width: 2880, height: 1800, fps: 60, bitrateBps: 20_000_000, scale: 2).toBytes()
+        XCTAssertEqual(bytes.readUInt16LE(at: 22), 2)
+
+        let parsed = try StartPayload.parse(bytes)
+        XCTAssertEqual(parsed.codedSize, Resolution(width: 2880, height: 1800))
+        XCTAssertEqual(parsed.effectiveScale, 2)
+        XCTAssertEqual(parsed.pointSize, Resolution(width: 1440, height: 900))
+
+        let unscaled = try StartPayload.parse(StartPayload(width: 1920, height: 1080, fps: 60, bitrateBps: 20_000_000, scale: 0).toBytes())
+        XCTAssertEqual(unscaled.effectiveScale, 1)
+        XCTAssertEqual(unscaled.pointSize, unscaled.displaySize)
+    }
+
+    func testStartPayloadWithoutDisplaySize() throws {
+        let bytes = StartPayload(width: 1366, height: 768, fps: 60, bitrateBps: 1_000_000).toBytes()
+        let parsed = try StartPayload.parse(bytes.prefix(SWRPConstants.PayloadSize.start))
+        XCTAssertEqual(parsed.displaySize, parsed.codedSize)
+    }
+
+    func testStartPayloadRejectsBadSizes() {
+        // Zero, odd coded and a display size that doesn't pad to the coded one
+        let cases: [(UInt32, UInt32, UInt32, UInt32)] = [
+            (0, 1080, 0, 1080),
+            (1367, 769, 1367, 769),
+            (1920, 1080, 1280, 720)
+        ]
+        for (width, height, displayWidth, displayHeight) in cases {
+            let valid = StartPayload(width: 1920, height: 1080, fps: 60, bitrateBps: 1_000_000).toBytes()
+            var data = Data()
+            data.appendUInt32LE(width)
+            data.appendUInt32LE(height)
+            data.append(valid[8..<24])
+            data.appendUInt32LE(displayWidth)
+            data.appendUInt32LE(displayHeight)
+            XCTAssertThrowsError(try StartPayload.parse(data))
+        }
+    }
+
     // MARK: - Frame Header Tests
 
     func testFrameHeaderSerialization() {
@@ -300,4 +367,16 @@ final class ProtocolTests: XCTestCase {
         XCTAssertEqual(parsed.pongTimestampUs, 1_234_567_900)
         XCTAssertEqual(parsed.roundTripUs, 10)
     }
+
+    // MARK: - Pause Tests
+
+    func testPausePayloadRoundtrip() throws {
+        for paused in [true, false] {
+            let bytes = PausePayload(paused: paused).toBytes()
+            XCTAssertEqual(bytes.count, SWRPConstants.PayloadSize.pause)
+            XCTAssertEqual(try PausePayload.parse(bytes).paused, paused)
+        }
+        XCTAssertEqual(PausePayload(paused: true).toBytes(), Data([1, 0, 0, 0]))
+        XCTAssertThrowsError(try PausePayload.parse(Data([1])))
+    }
 }
@@ -0,0 +1,52 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class DisplayConfigurationTests: XCTestCase {
+
+    // MARK: - Points and Pixels
+
+    func testResolutionConversions() {
+        let pixels = Resolution(width: 2880, height: 1800)
+        XCTAssertEqual(pixels.toPoints(scale: 2), Resolution(width: 1440, height: 900))
+        XCTAssertEqual(pixels.toPoints(scale: 2).toPixels(scale: 2), pixels)
+        XCTAssertEqual(pixels.toPoints(scale: 1), pixels)
+        XCTAssertEqual(pixels.toPoints(scale: 0), pixels)
+        XCTAssertEqual(pixels.toPixels(scale: 0), pixels)
+
+        // The last point of an odd size is only partly covered
+        XCTAssertEqual(Resolution(width: 1367, height: 769).toPoints(scale: 2), Resolution(width: 684, height: 385))
+        XCTAssertEqual(Resolution(width: .max, height: 1).toPixels(scale: 2), Resolution(width: .max, height: 2))
+    }
+
+    func testStandardDisplay() {
+        let config = DisplayConfiguration.fhd60
+        XCTAssertEqual(config.scale, 1)
+        XCTAssertEqual(config.pointSize, Resolution(width: 1920, height: 1080))
+        XCTAssertEqual(config.pixelSize, Resolution(width: 1920, height: 1080))
+    }
+
+    func testHiDPIDisplay() {
+        let config = DisplayConfiguration.fhd60HiDPI
+        XCTAssertEqual(config.scale, 2)
+        XCTAssertEqual(config.pointSize, Resolution(width: 960, height: 540))
+        XCTAssertEqual(config.pixelSize, Resolution(width: 1920, height: 1080))
+    }
+
+    func testOddHiDPIDisplayGainsPixels() {
+        let config = DisplayConfiguration(width: 1367, height: 769, refreshRate: 60, hidpiEnabled: true)
+        XCTAssertEqual(config.pointSize, Resolution(width: 684, height: 385))
+        XCTAssertEqual(config.pixelSize, Resolution(width: 1368, height: 770))
+    }
+
+    // MARK: - Stream Configuration
+
+    func testCaptureAndEncodeInPixels() {
+        let config = StreamConfiguration(width: 2880, height: 1800, fps: 60, bitrateMbps: 20, hidpi: true)
+        XCTAssertEqual(config.scale, 2)
+        XCTAssertEqual(config.displayConfiguration.pointSize, Resolution(width: 1440, height: 900))
+        XCTAssertEqual(config.captureConfiguration.width, 2880)
+        XCTAssertEqual(config.captureConfiguration.height, 1800)
+        XCTAssertEqual(config.encoderConfiguration.width, 2880)
+        XCTAssertEqual(config.encoderConfiguration.height, 1800)
+    }
+}
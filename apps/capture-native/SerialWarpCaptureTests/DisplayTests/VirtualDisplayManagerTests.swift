@@ -0,0 +1,141 @@
+import XCTest
+import CoreGraphics
+@testable import SerialWarpCapture
+
+private struct TestError: Error {}
+
+/// Stands in for a CGVirtualDisplay; counts how many are alive
+private final class FakeDisplay {
+    static var alive = 0
+
+    init() {
+        FakeDisplay.alive += 1
+    }
+
+    deinit {
+        FakeDisplay.alive -= 1
+    }
+}
+
+@MainActor
+final class VirtualDisplayManagerTests: XCTestCase {
+
+    /// Configurations the manager asked to create, in order
+    private var created: [DisplayConfiguration] = []
+    private var failNext = false
+
+    override func setUp() async throws {
+        created = []
+        failNext = false
+        FakeDisplay.alive = 0
+    }
+
+    private func makeManager() -> VirtualDisplayManager {
+        VirtualDisplayManager { [unowned self] config in
+            if self.failNext {
+                self.failNext = false
+                throw TestError()
+            }
+            self.created.append(config)
+            return (FakeDisplay(), CGDirectDisplayID(100 + self.created.count))
+        }
+    }
+
+    // MARK: - Bookkeeping
+
+    func testCreatesAlongsideExisting() throws {
+        let manager = makeManager()
+        let first = try manager.create(config: .fhd60)
+        let second = try manager.create(config: .uhd60)
+
+        XCTAssertNotEqual(first, second)
+        XCTAssertEqual(manager.list.map(\.handle), [first, second])
+        XCTAssertEqual(manager.displayId(for: first), 101)
+        XCTAssertEqual(manager.displayId(for: second), 102)
+        XCTAssertEqual(manager.list.map(\.configuration.width), [1920, 3840])
+        XCTAssertTrue(manager.isVirtualDisplay(102))
+        XCTAssertEqual(FakeDisplay.alive, 2)
+    }
+
+    func testDestroyTargetsOneDisplay() throws {
+        let manager = makeManager()
+        let first = try manager.create(config: .fhd60)
+        let second = try manager.create(config: .fhd60)
+
+        try manager.destroy(first)
+
+        XCTAssertEqual(manager.list.map(\.handle), [second])
+        XCTAssertNil(manager.displayId(for: first))
+        XCTAssertFalse(manager.isVirtualDisplay(101))
+        XCTAssertEqual(FakeDisplay.alive, 1)
+
+        XCTAssertThrowsError(try manager.destroy(first)) { error in
+            guard case SerialWarpError.virtualDisplayNotFound(let handle) = error else {
+                return XCTFail("Unexpected error: \(error)")
+            }
+            XCTAssertEqual(handle, first.rawValue)
+        }
+    }
+
+    func testDestroyAll() throws {
+        let manager = makeManager()
+        try manager.create(config: .fhd60)
+        try manager.create(config: .fhd60)
+
+        manager.destroyAll()
+
+        XCTAssertFalse(manager.isActive)
+        XCTAssertTrue(manager.list.isEmpty)
+        XCTAssertEqual(FakeDisplay.alive, 0)
+    }
+
+    func testHandlesAreNotReused() throws {
+        let manager = makeManager()
+        let first = try manager.create(config: .fhd60)
+        try manager.destroy(first)
+        let second = try manager.create(config: .fhd60)
+
+        XCTAssertNotEqual(first, second)
+        XCTAssertNil(manager.displayId(for: first))
+    }
+
+    func testFailedCreateLeavesNothing() throws {
+        let manager = makeManager()
+        let first = try manager.create(config: .fhd60)
+
+        failNext = true
+        XCTAssertThrowsError(try manager.create(config: .fhd60))
+
+        XCTAssertEqual(manager.list.map(\.handle), [first])
+        XCTAssertEqual(FakeDisplay.alive, 1)
+    }
+
+    // MARK: - Identity
+
+    func testUniqueSerialNumbersAndNames() throws {
+        let manager = makeManager()
+        for _ in 0..<3 {
+            try manager.create(config: .fhd60)
+        }
+
+        let base = DisplayConfiguration.defaultSerialNumber
+        XCTAssertEqual(created.map(\.serialNumber), [base, base + 1, base + 2])
+        XCTAssertEqual(created.map(\.name), ["SerialWarp", "SerialWarp 2", "SerialWarp 3"])
+        XCTAssertEqual(manager.list.map(\.configuration), created)
+    }
+
+    func testFreedIdentityIsReused() throws {
+        let manager = makeManager()
+        let first = try manager.create(config: .fhd60)
+        try manager.create(config: .fhd60)
+
+        // The replacement takes the first display's identity, so macOS
+        // puts it back where the first one was
+        try manager.destroy(first)
+        try manager.create(config: .fhd60)
+
+        let base = DisplayConfiguration.defaultSerialNumber
+        XCTAssertEqual(created.map(\.serialNumber), [base, base + 1, base])
+        XCTAssertEqual(created.last?.name, "SerialWarp")
+    }
+}
@@ -0,0 +1,64 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class VirtualDisplaySupportTests: XCTestCase {
+
+    // MARK: - Version Comparison
+
+    func testVersionOrdering() {
+        XCTAssertLessThan(OSVersion(13, 6, 9), OSVersion(14))
+        XCTAssertLessThan(OSVersion(14), OSVersion(14, 0, 1))
+        XCTAssertLessThan(OSVersion(14, 9), OSVersion(14, 10))
+        XCTAssertLessThan(OSVersion(14, 10), OSVersion(15))
+        XCTAssertEqual(OSVersion(14), OSVersion(14, 0, 0))
+        XCTAssertGreaterThanOrEqual(OSVersion(26), VirtualDisplaySupport.minimumOS)
+    }
+
+    func testVersionDescription() {
+        XCTAssertEqual(OSVersion(14).description, "14.0")
+        XCTAssertEqual(OSVersion(13, 6, 9).description, "13.6.9")
+    }
+
+    // MARK: - Check
+
+    private func check(
+        os: OSVersion = OSVersion(14, 5),
+        missing: Set<String> = [],
+        screenRecording: Bool = true
+    ) -> Result<Void, VirtualDisplayUnavailability> {
+        VirtualDisplaySupport.check(
+            osVersion: os,
+            classExists: { !missing.contains($0) },
+            screenRecordingAllowed: { screenRecording }
+        )
+    }
+
+    private func reason(_ result: Result<Void, VirtualDisplayUnavailability>) -> VirtualDisplayUnavailability? {
+        guard case .failure(let reason) = result else { return nil }
+        return reason
+    }
+
+    func testAvailable() {
+        XCTAssertNil(reason(check()))
+        XCTAssertNil(reason(check(os: OSVersion(14))))
+    }
+
+    func testOldMacOS() {
+        // Checked first; the classes don't exist before 14 either
+        XCTAssertEqual(
+            reason(check(os: OSVersion(13, 6), missing: ["CGVirtualDisplay"])),
+            .unsupportedOS(current: OSVersion(13, 6), required: OSVersion(14))
+        )
+    }
+
+    func testMissingClasses() {
+        XCTAssertEqual(
+            reason(check(missing: ["CGVirtualDisplay", "CGVirtualDisplayMode"])),
+            .missingClasses(["CGVirtualDisplayMode", "CGVirtualDisplay"])
+        )
+    }
+
+    func testScreenRecordingDenied() {
+        XCTAssertEqual(reason(check(screenRecording: false)), .screenRecordingDenied)
+    }
+}
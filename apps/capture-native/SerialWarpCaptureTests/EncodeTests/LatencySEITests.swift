@@ -0,0 +1,74 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class LatencySEITests: XCTestCase {
+
+    // MARK: - Encoding
+
+    func testMatchesRustEncoding() {
+        // `LatencySei::new(1_700_000_000_000_000, 42).to_nal()` in serialwarp-core
+        let expected: [UInt8] = [
+            0x00, 0x00, 0x00, 0x01, 0x06, 0x05, 0x20,
+            0x5e, 0x71, 0xa1, 0x3c, 0x8d, 0x42, 0x4b, 0x0f,
+            0x9a, 0x27, 0xd4, 0x61, 0x0b, 0xe8, 0x35, 0xc2,
+            0x00, 0x06, 0x0a, 0x24, 0x18, 0x1e, 0x40, 0x00,
+            0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03,
+            0x00, 0x00, 0x2a, 0x80
+        ]
+
+        let sei = LatencySEI(captureTsUs: 1_700_000_000_000_000, frameNumber: 42)
+        XCTAssertEqual([UInt8](sei.nalUnit), expected)
+    }
+
+    func testRoundTrip() {
+        let cases: [(UInt64, UInt64)] = [
+            (1_700_000_000_000_000, 42),
+            (0, 0),
+            (.max, .max),
+            (0x0000_0300_0001_0002, 3)
+        ]
+
+        for (captureTsUs, frameNumber) in cases {
+            let sei = LatencySEI(captureTsUs: captureTsUs, frameNumber: frameNumber)
+            let nal = sei.nalUnit
+            XCTAssertEqual(LatencySEI.parse(nalUnit: nal.dropFirst(4)), sei)
+        }
+    }
+
+    // MARK: - Emulation Prevention
+
+    func testEmulationPrevention() {
+        let cases: [([UInt8], [UInt8])] = [
+            ([0, 0, 0], [0, 0, 3, 0, 3]),
+            ([0, 0, 1], [0, 0, 3, 1]),
+            ([0, 0, 3], [0, 0, 3, 3]),
+            ([0, 0, 4], [0, 0, 4]),
+            ([1, 0, 0, 3, 0, 0, 0x80], [1, 0, 0, 3, 3, 0, 0, 0x80])
+        ]
+
+        for (rbsp, ebsp) in cases {
+            XCTAssertEqual(LatencySEI.addEmulationPrevention(rbsp), ebsp)
+            XCTAssertEqual(Array(LatencySEI.removeEmulationPrevention(ebsp).prefix(rbsp.count)), rbsp)
+        }
+    }
+
+    // MARK: - Parsing
+
+    func testRejectsOtherData() {
+        var slice = [UInt8](LatencySEI(captureTsUs: 1, frameNumber: 2).nalUnit.dropFirst(4))
+        slice[0] = 0x65
+        XCTAssertNil(LatencySEI.parse(nalUnit: Data(slice)))
+
+        var otherUUID = [UInt8](LatencySEI(captureTsUs: 1, frameNumber: 2).nalUnit.dropFirst(4))
+        otherUUID[3] ^= 0xFF
+        XCTAssertNil(LatencySEI.parse(nalUnit: Data(otherUUID)))
+
+        let truncated = LatencySEI(captureTsUs: 1, frameNumber: 2).nalUnit.dropFirst(4).dropLast(4)
+        XCTAssertNil(LatencySEI.parse(nalUnit: truncated))
+    }
+
+    func testDisabledByDefault() {
+        let config = EncoderConfiguration(width: 1280, height: 720, fps: 60, bitrateBps: 10_000_000)
+        XCTAssertFalse(config.latencySEI)
+    }
+}
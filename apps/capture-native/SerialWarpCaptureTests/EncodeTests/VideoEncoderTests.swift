@@ -0,0 +1,302 @@
+import XCTest
+import CoreMedia
+import CoreVideo
+import VideoToolbox
+@testable import SerialWarpCapture
+
+final class VideoEncoderTests: XCTestCase {
+
+    // MARK: - Profile Implications
+
+    func testBaselineImpliesCavlcWithoutReordering() {
+        let config = EncoderConfiguration(
+            width: 1280,
+            height: 720,
+            fps: 60,
+            bitrateBps: 10_000_000,
+            profileLevel: .baseline,
+            allowFrameReordering: true,
+            entropyMode: .cabac
+        )
+
+        XCTAssertFalse(config.effectiveAllowFrameReordering)
+        XCTAssertEqual(config.effectiveEntropyMode, .cavlc)
+    }
+
+    func testOtherProfilesKeepOptions() {
+        let config = EncoderConfiguration(
+            width: 1280,
+            height: 720,
+            fps: 60,
+            bitrateBps: 10_000_000,
+            profileLevel: .main,
+            allowFrameReordering: true,
+            entropyMode: .cabac
+        )
+
+        XCTAssertTrue(config.effectiveAllowFrameReordering)
+        XCTAssertEqual(config.effectiveEntropyMode, .cabac)
+    }
+
+    // MARK: - Keyframe Interval
+
+    func testKeyframeIntervalInTime() {
+        let config = EncoderConfiguration(
+            width: 1280, height: 720, fps: 60, bitrateBps: 10_000_000, keyframeInterval: 2
+        )
+        XCTAssertEqual(config.maxKeyframeInterval, 120)
+
+        // The same duration is fewer frames at a lower rate
+        let slower = EncoderConfiguration(
+            width: 1280, height: 720, fps: 30, bitrateBps: 10_000_000, keyframeInterval: 2
+        )
+        XCTAssertEqual(slower.maxKeyframeInterval, 60)
+    }
+
+    func testKeyframeIntervalFromFrameCount() {
+        let config = EncoderConfiguration(
+            width: 1280, height: 720, fps: 60, bitrateBps: 10_000_000, maxKeyframeInterval: 30
+        )
+        XCTAssertEqual(config.keyframeInterval, 0.5, accuracy: 1e-9)
+        XCTAssertEqual(config.maxKeyframeInterval, 30)
+
+        let zero = EncoderConfiguration(
+            width: 1280, height: 720, fps: 60, bitrateBps: 10_000_000, keyframeInterval: 0
+        )
+        XCTAssertThrowsError(try zero.validate())
+    }
+
+    // MARK: - Rate Control
+
+    func testQualityOutOfRangeRejected() {
+        for quality: Float in [-0.1, 1.5, .nan] {
+            let config = EncoderConfiguration(
+                width: 1280,
+                height: 720,
+                fps: 60,
+                bitrateBps: 10_000_000,
+                rateControl: .quality(quality)
+            )
+            XCTAssertThrowsError(try config.validate())
+        }
+    }
+
+    func testBitrateRequiredOnlyWhenUsed() {
+        let quality = EncoderConfiguration(
+            width: 1280, height: 720, fps: 60, bitrateBps: 0, rateControl: .quality(0.5)
+        )
+        XCTAssertNoThrow(try quality.validate())
+
+        let constrained = EncoderConfiguration(
+            width: 1280, height: 720, fps: 60, bitrateBps: 0, rateControl: .constrainedQuality(0.5)
+        )
+        XCTAssertThrowsError(try constrained.validate())
+    }
+
+    func testSessionCreatedForQualityModes() async throws {
+        for rateControl: EncoderConfiguration.RateControl in [.quality(0.7), .constrainedQuality(0.7)] {
+            let config = EncoderConfiguration(
+                width: 1280,
+                height: 720,
+                fps: 60,
+                bitrateBps: 10_000_000,
+                rateControl: rateControl
+            )
+            let encoder = VideoEncoder()
+            try await encoder.configure(config)
+            let isReady = await encoder.isReady
+            XCTAssertTrue(isReady)
+            await encoder.invalidate()
+        }
+    }
+
+    // MARK: - Session Creation
+
+    func testSessionCreatedForEachProfile() async throws {
+        for profile in EncoderConfiguration.ProfileLevel.allCases {
+            for allowBFrames in [false, true] {
+                let config = EncoderConfiguration(
+                    width: 1280,
+                    height: 720,
+                    fps: 60,
+                    bitrateBps: 10_000_000,
+                    profileLevel: profile,
+                    allowFrameReordering: allowBFrames,
+                    entropyMode: .cabac,
+                    maxFrameDelay: 2
+                )
+
+                let encoder = VideoEncoder()
+                do {
+                    try await encoder.configure(config)
+                } catch {
+                    XCTFail("\(profile.rawValue) with B-frames \(allowBFrames): \(error)")
+                }
+                let isReady = await encoder.isReady
+                XCTAssertTrue(isReady)
+                await encoder.invalidate()
+            }
+        }
+    }
+
+    // MARK: - Resolution
+
+    func testCodedSizeRoundsUpOddSizes() throws {
+        let cases: [(UInt32, UInt32, UInt32, UInt32)] = [
+            (1366, 768, 1366, 768),
+            (1367, 769, 1368, 770),
+            (1, 1, 2, 2)
+        ]
+        for (width, height, codedWidth, codedHeight) in cases {
+            let config = EncoderConfiguration(width: width, height: height, fps: 60, bitrateBps: 10_000_000)
+            XCTAssertNoThrow(try config.validate())
+            XCTAssertEqual(config.codedSize, Resolution(width: codedWidth, height: codedHeight))
+
+            let capture = CaptureConfiguration(width: width, height: height, fps: 60)
+            XCTAssertNoThrow(try capture.validate())
+            XCTAssertEqual(capture.codedSize, config.codedSize)
+        }
+    }
+
+    func testRejectsOutOfRangeSizes() {
+        for (width, height): (UInt32, UInt32) in [(0, 1080), (1920, 0), (Resolution.maxDimension + 1, 1080)] {
+            let config = EncoderConfiguration(width: width, height: height, fps: 60, bitrateBps: 10_000_000)
+            XCTAssertThrowsError(try config.validate())
+            XCTAssertThrowsError(try CaptureConfiguration(width: width, height: height, fps: 60).validate())
+        }
+    }
+
+    func testSessionCreatedForOddSize() async throws {
+        let config = EncoderConfiguration(width: 1367, height: 769, fps: 60, bitrateBps: 10_000_000)
+        let encoder = VideoEncoder()
+        try await encoder.configure(config)
+        let isReady = await encoder.isReady
+        XCTAssertTrue(isReady)
+        await encoder.invalidate()
+    }
+
+    // MARK: - Input Format
+
+    func testSessionCreatedForEachInputFormat() async throws {
+        for inputFormat in EncoderConfiguration.InputFormat.allCases {
+            let config = EncoderConfiguration(
+                width: 1280,
+                height: 720,
+                fps: 60,
+                bitrateBps: 10_000_000,
+                inputFormat: inputFormat
+            )
+            let encoder = VideoEncoder()
+            do {
+                try await encoder.configure(config)
+            } catch {
+                XCTFail("\(inputFormat.rawValue): \(error)")
+            }
+            let isReady = await encoder.isReady
+            XCTAssertTrue(isReady)
+            await encoder.invalidate()
+        }
+    }
+
+    func testPlanarFrameDataHasBothPlanes() throws {
+        var pixelBuffer: CVPixelBuffer?
+        let status = CVPixelBufferCreate(
+            kCFAllocatorDefault,
+            64,
+            32,
+            EncoderConfiguration.InputFormat.nv12.cvPixelFormat,
+            nil,
+            &pixelBuffer
+        )
+        XCTAssertEqual(status, kCVReturnSuccess)
+        let frame = CapturedFrame(pixelBuffer: try XCTUnwrap(pixelBuffer), presentationTime: .zero)
+
+        let lumaStride = CVPixelBufferGetBytesPerRowOfPlane(frame.pixelBuffer, 0)
+        let chromaStride = CVPixelBufferGetBytesPerRowOfPlane(frame.pixelBuffer, 1)
+        XCTAssertEqual(frame.getData().count, lumaStride * 32 + chromaStride * 16)
+    }
+
+    // MARK: - Frame Cadence
+
+    func testFrameDurationFromFps() {
+        XCTAssertEqual(EncoderConfiguration.frameDuration(fps: 60), CMTime(value: 16_666, timescale: 1_000_000))
+        XCTAssertEqual(EncoderConfiguration.frameDuration(fps: 30), CMTime(value: 33_333, timescale: 1_000_000))
+        // A zero rate, rejected elsewhere, doesn't divide by zero here
+        XCTAssertEqual(EncoderConfiguration.frameDuration(fps: 0), CMTime(value: 1_000_000, timescale: 1_000_000))
+    }
+
+    func testFrameRateAndDurationApplied() async throws {
+        let config = EncoderConfiguration(width: 1280, height: 720, fps: 60, bitrateBps: 10_000_000)
+        let encoder = VideoEncoder()
+        try await encoder.configure(config)
+
+        var duration = await encoder.frameDuration
+        XCTAssertEqual(duration, config.frameDuration)
+        var expected = await encoder.sessionProperty(kVTCompressionPropertyKey_ExpectedFrameRate) as? NSNumber
+        XCTAssertEqual(expected?.intValue, 60)
+
+        // Capture dropping to 30 fps reaches rate control and the durations
+        let applied = await encoder.setExpectedFrameRate(30)
+        XCTAssertTrue(applied)
+        duration = await encoder.frameDuration
+        XCTAssertEqual(duration, CMTime(value: 33_333, timescale: 1_000_000))
+        expected = await encoder.sessionProperty(kVTCompressionPropertyKey_ExpectedFrameRate) as? NSNumber
+        XCTAssertEqual(expected?.intValue, 30)
+
+        // Frames encode with the real duration passed
+        var pixelBuffer: CVPixelBuffer?
+        CVPixelBufferCreate(kCFAllocatorDefault, 1280, 720, config.inputFormat.cvPixelFormat, nil, &pixelBuffer)
+        let frame = CapturedFrame(pixelBuffer: try XCTUnwrap(pixelBuffer), presentationTime: .zero)
+        _ = try await encoder.encode(frame)
+        await encoder.invalidate()
+    }
+
+    // MARK: - Slices
+
+    func testSliceSizeApplied() async throws {
+        let config = EncoderConfiguration(
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            bitrateBps: 20_000_000,
+            maxSliceBytes: SWRPConstants.maxSegmentSize
+        )
+        let encoder = VideoEncoder()
+        try await encoder.configure(config)
+        let maxSliceBytes = await encoder.sessionProperty(kVTCompressionPropertyKey_MaxH264SliceBytes) as? NSNumber
+        XCTAssertEqual(maxSliceBytes?.intValue, SWRPConstants.maxSegmentSize)
+        await encoder.invalidate()
+
+        let zero = EncoderConfiguration(width: 1920, height: 1080, fps: 60, bitrateBps: 20_000_000, maxSliceBytes: 0)
+        XCTAssertThrowsError(try zero.validate())
+    }
+
+    func testSliceLimitFromSettings() {
+        var encoder = EncoderSettings.default
+        XCTAssertNil(StreamConfiguration.fhd60.encoderConfiguration.maxSliceBytes)
+
+        encoder.limitSliceSize = true
+        let config = StreamConfiguration(width: 1920, height: 1080, fps: 60, bitrateMbps: 20, encoder: encoder)
+        XCTAssertEqual(config.encoderConfiguration.maxSliceBytes, SWRPConstants.maxSegmentSize)
+    }
+
+    // MARK: - Settings
+
+    func testEncoderSettingsMissingFromSavedSettings() throws {
+        let json = """
+        {"defaultResolution":"1920x1080","defaultFps":60,"defaultBitrateMbps":20,
+         "autoConnect":false,"previewEnabled":true,"previewQuality":50}
+        """
+        let settings = try JSONDecoder().decode(AppSettings.self, from: Data(json.utf8))
+        XCTAssertNil(settings.encoder)
+    }
+
+    func testEncoderSettingsDefaultToBGRA() throws {
+        let json = """
+        {"profile":"H264_High_AutoLevel","allowBFrames":false}
+        """
+        let settings = try JSONDecoder().decode(EncoderSettings.self, from: Data(json.utf8))
+        XCTAssertNil(settings.inputFormat)
+        XCTAssertEqual(settings.effectiveInputFormat, .bgra)
+    }
+}
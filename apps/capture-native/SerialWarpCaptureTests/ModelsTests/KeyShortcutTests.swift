@@ -0,0 +1,63 @@
+import XCTest
+import Carbon.HIToolbox
+@testable import SerialWarpCapture
+
+final class KeyShortcutTests: XCTestCase {
+
+    // MARK: - Parsing
+
+    func testParsesNamesAndSymbols() throws {
+        let expected = KeyShortcut(key: "S", modifiers: [.control, .option, .command])
+        for text in ["Ctrl+Option+Cmd+S", "cmd + alt + control + s", "⌃+⌥+⌘+S", "Command+Opt+Ctrl+s"] {
+            XCTAssertEqual(try KeyShortcut(parsing: text), expected, text)
+        }
+
+        // Written back in menu order, and parsed the same again
+        XCTAssertEqual(expected.description, "Ctrl+Option+Cmd+S")
+        XCTAssertEqual(try KeyShortcut(parsing: expected.description), expected)
+
+        XCTAssertEqual(try KeyShortcut(parsing: "Shift+Cmd+esc").description, "Shift+Cmd+Escape")
+        XCTAssertEqual(try KeyShortcut(parsing: "cmd+f12").description, "Cmd+F12")
+    }
+
+    func testCarbonCodes() throws {
+        let shortcut = try KeyShortcut(parsing: "Option+Cmd+S")
+        XCTAssertEqual(shortcut.keyCode, UInt32(kVK_ANSI_S))
+        XCTAssertEqual(shortcut.carbonModifiers, UInt32(optionKey | cmdKey))
+
+        XCTAssertEqual(try KeyShortcut(parsing: "F6").keyCode, UInt32(kVK_F6))
+        XCTAssertEqual(try KeyShortcut(parsing: "Ctrl+Space").keyCode, UInt32(kVK_Space))
+    }
+
+    // MARK: - Validation
+
+    func testInvalidShortcuts() {
+        let cases: [(String, KeyShortcutError)] = [
+            ("", .empty),
+            ("  ", .empty),
+            ("Cmd+", .unknownKey("Cmd+")),
+            ("Cmd+Shift+Home", .unknownKey("Home")),
+            ("Hyper+S", .unknownModifier("Hyper")),
+            ("Cmd+S+Shift", .unknownModifier("S")),
+            // Would go off while typing
+            ("S", .needsModifier("S")),
+            ("Shift+F", .needsModifier("Shift+F")),
+            ("Cmd+Shift+4", .reserved("Shift+Cmd+4")),
+            ("cmd+tab", .reserved("Cmd+Tab")),
+        ]
+        for (text, expected) in cases {
+            XCTAssertThrowsError(try KeyShortcut(parsing: text), text) { error in
+                XCTAssertEqual(error as? KeyShortcutError, expected, text)
+                XCTAssertFalse(error.localizedDescription.isEmpty)
+            }
+        }
+
+        // Function keys are fine on their own
+        XCTAssertNoThrow(try KeyShortcut(parsing: "F13"))
+        XCTAssertNoThrow(try KeyShortcut(parsing: "Shift+F5"))
+    }
+
+    func testSuggestedIsValid() throws {
+        XCTAssertEqual(try KeyShortcut(parsing: KeyShortcut.suggested.description), KeyShortcut.suggested)
+    }
+}
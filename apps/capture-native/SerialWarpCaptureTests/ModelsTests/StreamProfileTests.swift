@@ -0,0 +1,101 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class StreamProfileTests: XCTestCase {
+
+    private var gaming: StreamProfile {
+        var config = StreamConfig.default
+        config.fps = 120
+        config.rateMode = .constrainedQuality
+        return StreamProfile(name: "1080p60 gaming", config: config, encoder: nil)
+    }
+
+    private var docs: StreamProfile {
+        var config = StreamConfig.default
+        config.width = 3840
+        config.height = 2160
+        config.fps = 30
+        var encoder = EncoderSettings.default
+        encoder.profile = .main
+        return StreamProfile(name: "4K30 docs", config: config, encoder: encoder)
+    }
+
+    // MARK: - Saving
+
+    func testStartsWithDefaultProfile() {
+        let profiles = StreamProfiles.default
+        XCTAssertEqual(profiles.names, [StreamProfiles.defaultProfileName])
+        XCTAssertEqual(profiles.profile(named: "Default")?.config, .default)
+    }
+
+    func testSaveAndDelete() throws {
+        var profiles = StreamProfiles.default
+        try profiles.save(gaming)
+        try profiles.save(docs)
+        XCTAssertEqual(profiles.names, ["Default", "1080p60 gaming", "4K30 docs"])
+        XCTAssertEqual(profiles.profile(named: "4K30 docs"), docs)
+
+        try profiles.delete(named: "1080p60 gaming")
+        XCTAssertEqual(profiles.names, ["Default", "4K30 docs"])
+        XCTAssertThrowsError(try profiles.delete(named: "1080p60 gaming")) { error in
+            XCTAssertEqual(error as? StreamProfileError, .notFound("1080p60 gaming"))
+        }
+    }
+
+    func testOverwriteNeedsFlag() throws {
+        var profiles = StreamProfiles.default
+        try profiles.save(gaming)
+
+        var changed = gaming
+        changed.config.bitrateMbps = 50
+        // Surrounding whitespace doesn't make a different name
+        changed.name = " 1080p60 gaming "
+        XCTAssertThrowsError(try profiles.save(changed)) { error in
+            XCTAssertEqual(error as? StreamProfileError, .nameTaken("1080p60 gaming"))
+        }
+        XCTAssertEqual(profiles.profile(named: "1080p60 gaming")?.config.bitrateMbps, 20)
+
+        try profiles.save(changed, overwrite: true)
+        XCTAssertEqual(profiles.names, ["Default", "1080p60 gaming"])
+        XCTAssertEqual(profiles.profile(named: "1080p60 gaming")?.config.bitrateMbps, 50)
+    }
+
+    func testRejectsEmptyName() {
+        var profiles = StreamProfiles.default
+        var unnamed = gaming
+        unnamed.name = "  "
+        XCTAssertThrowsError(try profiles.save(unnamed)) { error in
+            XCTAssertEqual(error as? StreamProfileError, .emptyName)
+        }
+    }
+
+    // MARK: - Serialization
+
+    func testRoundTrip() throws {
+        var profiles = StreamProfiles.default
+        try profiles.save(gaming)
+        try profiles.save(docs)
+
+        let data = try JSONEncoder().encode(profiles)
+        XCTAssertEqual(try JSONDecoder().decode(StreamProfiles.self, from: data), profiles)
+    }
+
+    func testDecodesConfigFromBeforeRateControl() throws {
+        // Saved before rateMode, quality and allowSleep existed
+        let json = """
+        {"profiles":[{"name":"Old","config":{"width":2560,"height":1440,"fps":30,"bitrateMbps":15,"hidpi":true}}]}
+        """
+        let profiles = try JSONDecoder().decode(StreamProfiles.self, from: Data(json.utf8))
+
+        let config = try XCTUnwrap(profiles.profile(named: "Old")?.config)
+        XCTAssertEqual(config.width, 2560)
+        XCTAssertEqual(config.height, 1440)
+        XCTAssertEqual(config.fps, 30)
+        XCTAssertEqual(config.bitrateMbps, 15)
+        XCTAssertTrue(config.hidpi)
+        XCTAssertFalse(config.allowSleep)
+        XCTAssertEqual(config.rateMode, .constantBitrate)
+        XCTAssertEqual(config.quality, StreamConfig.default.quality)
+        XCTAssertNil(profiles.profile(named: "Old")?.encoder)
+    }
+}
@@ -0,0 +1,163 @@
+import XCTest
+@testable import SerialWarpCapture
+
+/// Plugged in or not as the test says. Nothing changes it during a wait,
+/// so waiting for the other state ends at once as if cancelled.
+@MainActor
+private final class MockWatcher: DeviceWatcher {
+    var isAttached: Bool
+    /// Attached states to switch to, one per backoff sleep
+    var plugs: [Bool] = []
+
+    init(attached: Bool) {
+        self.isAttached = attached
+    }
+
+    func waitUntil(attached: Bool) async -> Bool {
+        isAttached == attached
+    }
+}
+
+private struct TestError: LocalizedError {
+    var errorDescription: String? { "device busy" }
+}
+
+@MainActor
+final class AutoConnectTests: XCTestCase {
+
+    private var statuses: [AutoConnectStatus] = []
+    private var sleeps: [TimeInterval] = []
+    private var attempts = 0
+    private var resumes = 0
+
+    override func setUp() async throws {
+        statuses = []
+        sleeps = []
+        attempts = 0
+        resumes = 0
+    }
+
+    /// An auto-connector whose first `failures` attempts fail. Backoff
+    /// sleeps are recorded and skipped, and each applies the watcher's next
+    /// plug state.
+    private func makeConnector(
+        watcher: MockWatcher,
+        failures: Int,
+        resume: Bool = false,
+        backoff: ReconnectBackoff = ReconnectBackoff(initial: 1, max: 4)
+    ) -> AutoConnector {
+        let recordSleep: (TimeInterval) -> Void = { [unowned self] delay in
+            self.sleeps.append(delay)
+            if !watcher.plugs.isEmpty {
+                watcher.isAttached = watcher.plugs.removeFirst()
+            }
+        }
+        return AutoConnector(
+            watcher: watcher,
+            backoff: backoff,
+            connect: { [unowned self] in
+                self.attempts += 1
+                if self.attempts <= failures {
+                    throw TestError()
+                }
+                return true
+            },
+            shouldResume: { resume },
+            resume: { [unowned self] in self.resumes += 1 },
+            sleep: { recordSleep($0) },
+            onStatus: { [unowned self] in self.statuses.append($0) }
+        )
+    }
+
+    // MARK: - Backoff
+
+    func testBackoffDoublesUpToMax() {
+        var backoff = ReconnectBackoff(initial: 1, max: 10)
+        XCTAssertEqual(backoff.delay, 0)
+
+        let delays = (0..<6).map { _ in backoff.fail() }
+        XCTAssertEqual(delays, [1, 2, 4, 8, 10, 10])
+        XCTAssertEqual(backoff.failures, 6)
+
+        backoff.reset()
+        XCTAssertEqual(backoff.delay, 0)
+        XCTAssertEqual(backoff.fail(), 1)
+    }
+
+    func testBackoffStaysCapped() {
+        var backoff = ReconnectBackoff()
+        for _ in 0..<100 {
+            _ = backoff.fail()
+        }
+        XCTAssertEqual(backoff.delay, ReconnectBackoff.defaultMax)
+    }
+
+    // MARK: - Connecting
+
+    func testRetriesWithBackoffUntilConnected() async {
+        let connector = makeConnector(watcher: MockWatcher(attached: true), failures: 4)
+
+        let connected = await connector.connectWhenAttached()
+
+        XCTAssertTrue(connected)
+        XCTAssertEqual(attempts, 5)
+        XCTAssertEqual(sleeps, [1, 2, 4, 4])
+        XCTAssertEqual(connector.backoff.failures, 0)
+        XCTAssertEqual(Array(statuses.prefix(3)), [
+            .connecting(attempt: 1),
+            .failed(error: "device busy", retryIn: 1),
+            .waiting(delay: 1),
+        ])
+        XCTAssertEqual(statuses.last, .connected)
+        XCTAssertEqual(resumes, 0)
+    }
+
+    func testNoAttemptAfterUnplug() async {
+        let watcher = MockWatcher(attached: true)
+        // Unplugged during the first backoff
+        watcher.plugs = [false]
+        let connector = makeConnector(watcher: watcher, failures: 1)
+
+        let connected = await connector.connectWhenAttached()
+
+        XCTAssertFalse(connected)
+        XCTAssertEqual(attempts, 1)
+        XCTAssertEqual(sleeps, [1])
+        XCTAssertEqual(statuses.last, .waitingForDevice)
+        // The failure still counts towards the next attempt
+        XCTAssertEqual(connector.backoff.failures, 1)
+    }
+
+    func testWaitsForDevice() async {
+        let connector = makeConnector(watcher: MockWatcher(attached: false), failures: 0)
+
+        let connected = await connector.connectWhenAttached()
+
+        XCTAssertFalse(connected)
+        XCTAssertEqual(attempts, 0)
+        XCTAssertEqual(statuses, [.waitingForDevice])
+    }
+
+    func testResumesLastSession() async {
+        let connector = makeConnector(watcher: MockWatcher(attached: true), failures: 0, resume: true)
+
+        let connected = await connector.connectWhenAttached()
+
+        XCTAssertTrue(connected)
+        XCTAssertEqual(resumes, 1)
+        XCTAssertEqual(statuses, [.connecting(attempt: 1), .connected, .resuming, .streaming])
+    }
+
+    // MARK: - Settings
+
+    func testDecodesSettingsFromBeforeResume() throws {
+        let json = """
+        {"defaultResolution":"1920x1080","defaultFps":60,"defaultBitrateMbps":20,
+         "autoConnect":true,"previewEnabled":true,"previewQuality":50}
+        """
+        let settings = try JSONDecoder().decode(AppSettings.self, from: Data(json.utf8))
+        XCTAssertTrue(settings.autoConnect)
+        XCTAssertNil(settings.resumeLastSession)
+        XCTAssertNil(settings.lastSession)
+    }
+}
@@ -0,0 +1,71 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class BenchmarkTests: XCTestCase {
+
+    // MARK: - Aggregation
+
+    func testAggregatesSamples() {
+        var recorder = BenchmarkRecorder()
+
+        // 120 frames over 2s, encoded in 1-20ms, 50KB each
+        for index in 0..<120 {
+            recorder.recordCapture()
+            recorder.recordEncode(latencyUs: UInt64(index % 20 + 1) * 1_000, bytes: 50_000)
+        }
+
+        let result = recorder.result(elapsed: 2)
+        XCTAssertEqual(result.durationSeconds, 2)
+        XCTAssertEqual(result.captureFps, 60)
+        XCTAssertEqual(result.encodeFps, 60)
+        XCTAssertEqual(result.averageEncodeLatencyMs, 10.5, accuracy: 0.001)
+        XCTAssertEqual(result.p95EncodeLatencyMs, 19)
+        XCTAssertEqual(result.bitrateBps, 24_000_000)
+        XCTAssertEqual(result.framesDropped, 0)
+        XCTAssertTrue(result.sustains(fps: 60))
+        XCTAssertFalse(result.sustains(fps: 120))
+    }
+
+    func testCountsDroppedFrames() {
+        var recorder = BenchmarkRecorder()
+
+        // Ten captured: seven encoded, one without output, two never
+        // reached the encoder
+        for _ in 0..<10 {
+            recorder.recordCapture()
+        }
+        for _ in 0..<7 {
+            recorder.recordEncode(latencyUs: 4_000, bytes: 1_000)
+        }
+        recorder.recordEncode(latencyUs: 4_000, bytes: nil)
+
+        let result = recorder.result(elapsed: 1)
+        XCTAssertEqual(result.captureFps, 10)
+        XCTAssertEqual(result.encodeFps, 7)
+        XCTAssertEqual(result.framesDropped, 3)
+        XCTAssertEqual(result.p95EncodeLatencyMs, 4)
+        XCTAssertFalse(result.sustains(fps: 7))
+    }
+
+    func testEmptyRun() {
+        let result = BenchmarkRecorder().result(elapsed: 0)
+
+        XCTAssertEqual(result.captureFps, 0)
+        XCTAssertEqual(result.encodeFps, 0)
+        XCTAssertEqual(result.averageEncodeLatencyMs, 0)
+        XCTAssertEqual(result.p95EncodeLatencyMs, 0)
+        XCTAssertEqual(result.bitrateBps, 0)
+        XCTAssertEqual(result.framesDropped, 0)
+    }
+
+    func testReusesPipelineStats() {
+        var recorder = BenchmarkRecorder()
+        recorder.recordCapture()
+        recorder.recordEncode(latencyUs: 1_000, bytes: 512)
+
+        XCTAssertEqual(recorder.stats.framesCaptured, 1)
+        XCTAssertEqual(recorder.stats.framesEncoded, 1)
+        XCTAssertEqual(recorder.stats.bytesSent, 512)
+        XCTAssertNotNil(recorder.stats.startTime)
+    }
+}
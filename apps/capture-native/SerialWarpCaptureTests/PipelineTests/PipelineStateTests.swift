@@ -0,0 +1,80 @@
+import XCTest
+import CoreMedia
+@testable import SerialWarpCapture
+
+final class PipelineStateTests: XCTestCase {
+
+    /// Whether each step of `path` is a valid transition
+    private func isValid(_ path: [PipelineState]) -> Bool {
+        zip(path, path.dropFirst()).allSatisfy { $0.canTransition(to: $1) }
+    }
+
+    // MARK: - Pause and Resume
+
+    func testPauseResumeStop() {
+        XCTAssertTrue(isValid([.streaming, .paused, .streaming, .stopping, .ready]))
+        XCTAssertTrue(isValid([.streaming, .paused, .streaming, .paused, .streaming]))
+    }
+
+    func testStopWhilePaused() {
+        XCTAssertTrue(isValid([.streaming, .paused, .stopping, .ready]))
+        XCTAssertTrue(isValid([.streaming, .paused, .disconnected]))
+        XCTAssertTrue(isValid([.streaming, .paused, .error]))
+    }
+
+    func testPauseOnlyWhileStreaming() {
+        for state in [PipelineState.disconnected, .connected, .ready, .starting, .stopping, .error] {
+            XCTAssertFalse(state.canTransition(to: .paused), "\(state) → paused")
+        }
+        // Resuming goes back to the same session, never through START
+        XCTAssertFalse(PipelineState.paused.canTransition(to: .starting))
+        XCTAssertFalse(PipelineState.paused.canTransition(to: .ready))
+    }
+
+    func testPausedIsConnected() {
+        XCTAssertTrue(PipelineState.paused.isConnected)
+        XCTAssertFalse(PipelineState.paused.isStreaming)
+    }
+
+    // MARK: - Black Frame
+
+    func testBlackFrameBGRA() throws {
+        let frame = try XCTUnwrap(CapturedFrame.black(
+            width: 64,
+            height: 32,
+            pixelFormat: kCVPixelFormatType_32BGRA,
+            presentationTime: .zero
+        ))
+        XCTAssertEqual(frame.width, 64)
+        XCTAssertEqual(frame.height, 32)
+
+        let data = frame.getData()
+        for pixel in stride(from: 0, to: data.count, by: 4) {
+            XCTAssertEqual(Array(data[pixel..<pixel + 4]), [0, 0, 0, 255])
+        }
+    }
+
+    func testBlackFrameNV12() throws {
+        let frame = try XCTUnwrap(CapturedFrame.black(
+            width: 64,
+            height: 32,
+            pixelFormat: kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange,
+            presentationTime: .zero
+        ))
+        let pixelBuffer = frame.pixelBuffer
+        let lumaSize = CVPixelBufferGetBytesPerRowOfPlane(pixelBuffer, 0) * CVPixelBufferGetHeightOfPlane(pixelBuffer, 0)
+
+        let data = frame.getData()
+        XCTAssertTrue(data.prefix(lumaSize).allSatisfy { $0 == 16 })
+        XCTAssertTrue(data.dropFirst(lumaSize).allSatisfy { $0 == 128 })
+    }
+
+    func testBlackFrameRejectsOtherFormats() {
+        XCTAssertNil(CapturedFrame.black(
+            width: 64,
+            height: 32,
+            pixelFormat: kCVPixelFormatType_24RGB,
+            presentationTime: .zero
+        ))
+    }
+}
@@ -0,0 +1,63 @@
+import XCTest
+import IOKit.pwr_mgt
+@testable import SerialWarpCapture
+
+/// Records assertions instead of calling IOKit
+private final class MockPowerAssertionBackend: PowerAssertionBackend {
+    var created: [(type: String, name: String)] = []
+    var released: [IOPMAssertionID] = []
+    var failingTypes: Set<String> = []
+    private var nextId: IOPMAssertionID = 1
+
+    func create(type: String, name: String) -> IOPMAssertionID? {
+        guard !failingTypes.contains(type) else { return nil }
+        created.append((type, name))
+        let id = nextId
+        nextId += 1
+        return id
+    }
+
+    func release(_ id: IOPMAssertionID) {
+        released.append(id)
+    }
+}
+
+final class PowerAssertionTests: XCTestCase {
+
+    func testTakesDisplayAndSystemAssertions() {
+        let backend = MockPowerAssertionBackend()
+        let assertion = PowerAssertion(reason: "Streaming", backend: backend)
+
+        XCTAssertTrue(assertion.isHeld)
+        XCTAssertEqual(backend.created.map(\.type), [
+            "PreventUserIdleDisplaySleep",
+            "PreventUserIdleSystemSleep",
+        ])
+        XCTAssertTrue(backend.created.allSatisfy { $0.name == "Streaming" })
+        XCTAssertTrue(backend.released.isEmpty)
+    }
+
+    func testReleasesOnDeinit() {
+        let backend = MockPowerAssertionBackend()
+        var assertion: PowerAssertion? = PowerAssertion(reason: "Streaming", backend: backend)
+        XCTAssertNotNil(assertion)
+
+        assertion = nil
+
+        XCTAssertEqual(backend.released.sorted(), [1, 2])
+    }
+
+    func testFailureIsNotFatal() {
+        let backend = MockPowerAssertionBackend()
+        backend.failingTypes = ["PreventUserIdleSystemSleep"]
+
+        var assertion: PowerAssertion? = PowerAssertion(reason: "Streaming", backend: backend)
+        XCTAssertFalse(assertion!.isHeld)
+        XCTAssertEqual(backend.created.count, 1)
+
+        assertion = nil
+
+        // Only the assertion that was taken is released
+        XCTAssertEqual(backend.released, [1])
+    }
+}
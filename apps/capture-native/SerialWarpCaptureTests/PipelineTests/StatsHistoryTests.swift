@@ -0,0 +1,85 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class StatsHistoryTests: XCTestCase {
+
+    /// Stats as the pipeline reports them `elapsed` seconds into a stream
+    private func stats(elapsed: TimeInterval, captured: UInt64, dropped: UInt64 = 0) -> PipelineStats {
+        var stats = PipelineStats()
+        stats.startTime = Date(timeIntervalSinceNow: -elapsed)
+        stats.framesCaptured = captured
+        stats.bytesSent = captured * 10_000
+        stats.framesDropped = dropped
+        stats.latencyUs = 4_000
+        stats.encodeDuration = TimingPercentiles(p50Ms: 2, p95Ms: 5, p99Ms: 9)
+        return stats
+    }
+
+    /// Sample times a little over a second apart, so timing jitter can't
+    /// bring them under
+    private func time(_ second: Int) -> TimeInterval {
+        Double(second) * 1.25
+    }
+
+    // MARK: - Sampling
+
+    func testSamplesOnceASecond() {
+        var history = StatsHistory(capacity: 10)
+
+        XCTAssertFalse(history.record(stats(elapsed: 0.5, captured: 30)))
+        XCTAssertTrue(history.record(stats(elapsed: 1.0, captured: 60, dropped: 1)))
+        XCTAssertFalse(history.record(stats(elapsed: 1.5, captured: 90, dropped: 1)))
+        XCTAssertTrue(history.record(stats(elapsed: 3.0, captured: 180, dropped: 4)))
+
+        let samples = history.recent(seconds: 10)
+        XCTAssertEqual(samples.count, 2)
+        XCTAssertEqual(samples[0].fps, 60, accuracy: 1)
+        XCTAssertEqual(samples[0].framesDropped, 1)
+        XCTAssertEqual(samples[1].fps, 60, accuracy: 1)
+        XCTAssertEqual(samples[1].framesDropped, 3)
+        XCTAssertEqual(Double(samples[1].bitrateBps), 4_800_000, accuracy: 100_000)
+        XCTAssertEqual(samples[1].latencyMs, 4)
+        XCTAssertEqual(samples[1].encodeDuration?.p95Ms, 5)
+        XCTAssertNil(samples[1].captureInterval)
+    }
+
+    // MARK: - Ring Buffer
+
+    func testWrapsAround() {
+        var history = StatsHistory(capacity: 3)
+        for second in 1...5 {
+            history.record(stats(elapsed: time(second), captured: UInt64(second) * 60))
+        }
+
+        XCTAssertEqual(history.count, 3)
+        let elapsed = history.recent(seconds: 10).map { ($0.elapsedSeconds / 1.25).rounded() }
+        XCTAssertEqual(elapsed, [3, 4, 5])
+    }
+
+    func testWindowedQuery() {
+        var history = StatsHistory()
+        for second in 1...20 {
+            history.record(stats(elapsed: time(second), captured: UInt64(second) * 60))
+        }
+
+        XCTAssertEqual(history.count, 20)
+        let recent = history.recent(seconds: 5)
+        XCTAssertEqual(recent.count, 5)
+        XCTAssertEqual(recent.first?.elapsedSeconds ?? 0, time(16), accuracy: 0.1)
+        XCTAssertEqual(recent.last?.elapsedSeconds ?? 0, time(20), accuracy: 0.1)
+        XCTAssertTrue(history.recent(seconds: 0).isEmpty)
+    }
+
+    func testClearStartsOver() {
+        var history = StatsHistory(capacity: 2)
+        for second in 1...3 {
+            history.record(stats(elapsed: time(second), captured: UInt64(second) * 60))
+        }
+
+        history.clear()
+        XCTAssertTrue(history.isEmpty)
+
+        XCTAssertTrue(history.record(stats(elapsed: 1, captured: 30)))
+        XCTAssertEqual(history.recent(seconds: 1).first?.fps ?? 0, 30, accuracy: 1)
+    }
+}
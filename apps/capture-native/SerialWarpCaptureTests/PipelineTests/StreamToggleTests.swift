@@ -0,0 +1,64 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class StreamToggleTests: XCTestCase {
+
+    // MARK: - Actions
+
+    func testActionFollowsState() {
+        let expected: [PipelineState: StreamToggle.Action] = [
+            .disconnected: .connectAndStart,
+            .error: .connectAndStart,
+            .ready: .start,
+            .streaming: .stop,
+            .paused: .stop,
+            // Between states, a press does nothing
+            .connecting: .ignore,
+            .connected: .ignore,
+            .handshaking: .ignore,
+            .starting: .ignore,
+            .stopping: .ignore,
+        ]
+        for (state, action) in expected {
+            XCTAssertEqual(StreamToggle.action(for: state), action, state.rawValue)
+        }
+    }
+
+    // MARK: - Presses
+
+    func testPressWhileInFlightIgnored() {
+        var toggle = StreamToggle()
+        XCTAssertEqual(toggle.press(state: .ready), .start)
+        XCTAssertEqual(toggle.inFlight, .start)
+
+        // A second press before the stream is up does nothing
+        XCTAssertEqual(toggle.press(state: .streaming), .ignore)
+        XCTAssertEqual(toggle.inFlight, .start)
+
+        toggle.finish()
+        XCTAssertNil(toggle.inFlight)
+        XCTAssertEqual(toggle.press(state: .streaming), .stop)
+        toggle.finish()
+
+        // A failed start leaves the pipeline in error, from which the next
+        // press connects again
+        XCTAssertEqual(toggle.press(state: .disconnected), .connectAndStart)
+        toggle.finish()
+        XCTAssertEqual(toggle.press(state: .error), .connectAndStart)
+    }
+
+    func testIgnoredPressNotInFlight() {
+        var toggle = StreamToggle()
+        XCTAssertEqual(toggle.press(state: .handshaking), .ignore)
+        XCTAssertNil(toggle.inFlight)
+        XCTAssertEqual(toggle.press(state: .ready), .start)
+    }
+
+    // MARK: - Results
+
+    func testResultMessages() {
+        XCTAssertEqual(StreamToggleResult.started.message.title, "Streaming started")
+        XCTAssertEqual(StreamToggleResult.stopped.message.title, "Streaming stopped")
+        XCTAssertEqual(StreamToggleResult.failed("No device").message.body, "No device")
+    }
+}
@@ -0,0 +1,67 @@
+import XCTest
+@testable import SerialWarpCapture
+
+final class TimingHistogramTests: XCTestCase {
+
+    // MARK: - Buckets
+
+    func testBucketBoundaries() {
+        XCTAssertEqual(TimingHistogram.bucketIndex(us: 0.5), 0)
+        XCTAssertEqual(TimingHistogram.bucketIndex(us: 1), 1)
+        XCTAssertEqual(TimingHistogram.bucketIndex(us: 8), 1 + 3 * TimingHistogram.bucketsPerOctave)
+        XCTAssertEqual(TimingHistogram.bucketIndex(us: 16), 1 + 4 * TimingHistogram.bucketsPerOctave)
+
+        // A bucket holds its lower bound but not its upper
+        for value in [1.0, 5, 100, 1023, 1024, 1151, 1152, 16_667, 33_333] {
+            let (low, high) = TimingHistogram.bucketBoundsUs(TimingHistogram.bucketIndex(us: value))
+            XCTAssertLessThanOrEqual(low, value)
+            XCTAssertLessThan(value, high)
+        }
+
+        // Anything too long lands in the last bucket
+        XCTAssertEqual(TimingHistogram.bucketIndex(us: 3_600_000_000), TimingHistogram.bucketCount - 1)
+    }
+
+    // MARK: - Percentiles
+
+    func testPercentileInterpolation() {
+        var histogram = TimingHistogram()
+        XCTAssertNil(histogram.percentiles)
+
+        // A spike in one frame in ten shows at p95 but not p50
+        for _ in 0..<90 {
+            histogram.record(0.001)
+        }
+        for _ in 0..<10 {
+            histogram.record(0.008)
+        }
+        let percentiles = histogram.percentiles
+        XCTAssertEqual(percentiles?.p50Ms ?? 0, 1, accuracy: 0.001)
+        // Halfway through the [7680, 8192) µs bucket
+        XCTAssertEqual(percentiles?.p95Ms ?? 0, 7.936, accuracy: 0.001)
+        XCTAssertEqual(percentiles?.p99Ms ?? 0, 8, accuracy: 0.001)
+    }
+
+    // MARK: - Windows
+
+    func testMergeAcrossWindowReset() {
+        var whole = TimingHistogram()
+        var window = TimingWindow()
+        for i in 0..<200 {
+            if i == 120 {
+                window.rotate()
+            }
+            let seconds = Double(15_000 + (i * 37) % 4000) / 1_000_000
+            whole.record(seconds)
+            window.record(seconds)
+        }
+
+        XCTAssertEqual(window.current.count, 80)
+        XCTAssertEqual(window.percentiles, whole.percentiles)
+
+        // A second reset drops the first window
+        window.rotate()
+        window.rotate()
+        XCTAssertNil(window.percentiles)
+    }
+}
@@ -0,0 +1,99 @@
+//! Connecting to the source on its own when `AppSettings::auto_wait` is set
+//! and the transport is USB
+
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+use serialwarp_core::{Context, DeviceRegistry, SerialwarpError};
+use serialwarp_pipeline::{auto_connect, Backoff};
+use serialwarp_transport::DeviceWatch;
+
+use crate::commands::{connect, start_receiving};
+use crate::events::{self, AutoConnectProgress};
+use crate::state::AppState;
+
+/// How often to recheck the setting, and whether the app is idle
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whenever auto-wait is on and nothing is connected, wait for a supported
+/// device, connect and start receiving. Failed attempts back off so an
+/// unresponsive source isn't retried in a tight loop.
+pub async fn run_auto_wait(app: AppHandle, state: Arc<AppState>) {
+    let mut backoff = Backoff::default();
+    // Only watched while auto-wait is on
+    let mut watch: Option<DeviceWatch> = None;
+
+    loop {
+        let enabled = state.settings.lock().await.waits_for_device();
+        if !enabled {
+            watch = None;
+        }
+        if !enabled || !state.is_idle().await {
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+            continue;
+        }
+
+        if watch.is_none() {
+            match open_watch() {
+                Ok(opened) => watch = Some(opened),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    state.emit_error(&app, e.to_string(), false);
+                    return;
+                }
+            }
+        }
+        let Some(watcher) = watch.as_mut() else {
+            continue;
+        };
+
+        if !watcher.is_attached() {
+            emit(&app, AutoConnectProgress::WaitingForDevice);
+        }
+
+        // Give up on the attempt if the user connected, played a file or
+        // turned auto-wait off in the meantime
+        let (app_ref, state_ref) = (&app, &*state);
+        let connected = auto_connect(
+            watcher,
+            &mut backoff,
+            || async move {
+                if !state_ref.settings.lock().await.waits_for_device() || !state_ref.is_idle().await
+                {
+                    return Ok(false);
+                }
+                connect(app_ref, state_ref).await.map(|_| true)
+            },
+            |event| emit(&app, event.into()),
+        )
+        .await;
+
+        match connected {
+            Some(true) => {
+                if let Err(e) = start_receiving(app.clone(), Arc::clone(&state)).await {
+                    state.emit_error(&app, e.to_string(), false);
+                }
+            }
+            Some(false) => {}
+            None => {
+                tracing::warn!("USB device watch ended");
+                watch = None;
+                tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Watch for the supported devices, including any added through
+/// SERIALWARP_EXTRA_DEVICES
+fn open_watch() -> Result<DeviceWatch, SerialwarpError> {
+    let registry = DeviceRegistry::from_env()?;
+    DeviceWatch::new(registry).context("Failed to watch for USB devices")
+}
+
+fn emit(app: &AppHandle, progress: AutoConnectProgress) {
+    if let Err(e) = app.emit(events::AUTO_CONNECT, progress) {
+        tracing::warn!("Failed to emit auto-connect progress: {:?}", e);
+    }
+}
@@ -1,25 +1,47 @@
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tauri::{AppHandle, State, WebviewWindow};
+use tauri::{AppHandle, Emitter, State, WebviewWindow};
 
-use serialwarp_core::frame::FrameReassembler;
-use serialwarp_core::SUPPORTED_USB_DEVICES;
+use serialwarp_core::{
+    Context, DeviceRegistry, ErrorKind, ErrorPayload, Packet, PacketType, PingPayload, PongPayload,
+    Resolution, SerialwarpError, StopPayload, StopReason, TransportError, VideoDecoder,
+};
 use serialwarp_decode::{Decoder, DecoderConfig};
-use serialwarp_transport::{Transport, UsbTransport};
+use serialwarp_pipeline::{
+    SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, StatsExportConfig, StatsExporter,
+};
+use serialwarp_transport::{split_shared, MockTransport, TcpTransport, Transport, UsbTransport};
 
-/// Simulated frame interval (~60fps)
+/// How long a receive waits before rechecking whether to stop (~60fps)
 const FRAME_INTERVAL: Duration = Duration::from_millis(16);
 
+/// How long to listen for a TCP source before giving up, like USB does
+/// when no device is plugged in
+const TCP_ACCEPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Shortest stats push interval, whatever the settings say
+const MIN_STATS_INTERVAL: Duration = Duration::from_millis(50);
 
+use crate::events::{self, HandshakeProgress, StreamStalled};
+use crate::handshake::negotiate;
+use crate::logging;
+use crate::native;
+use crate::playback::{load_access_units, run_playback, DEFAULT_PLAYBACK_FPS};
 use crate::state::{
-    AppSettings, AppState, ConnectionStatus, DisplayStats, NegotiatedParams, UsbDeviceInfo,
+    AppSettings, AppState, ConnectionStatus, DisplayStats, NegotiatedParams, PresentationMode,
+    StatsSample, TransportKind, UsbDeviceInfo,
 };
+use crate::test_source;
 
-/// List supported USB devices
+/// List supported USB devices, including any added through
+/// SERIALWARP_EXTRA_DEVICES
 #[tauri::command]
-pub async fn list_usb_devices() -> Result<Vec<UsbDeviceInfo>, String> {
-    let supported = SUPPORTED_USB_DEVICES
+pub async fn list_usb_devices() -> Result<Vec<UsbDeviceInfo>, SerialwarpError> {
+    let registry = DeviceRegistry::from_env()?;
+    let supported = registry
+        .devices()
         .iter()
         .map(|d| UsbDeviceInfo {
             name: d.name.to_string(),
@@ -33,29 +55,112 @@ pub async fn list_usb_devices() -> Result<Vec<UsbDeviceInfo>, String> {
 /// Wait for connection from Mac and perform handshake
 #[tauri::command]
 pub async fn wait_for_connection(
+    app: AppHandle,
     state: State<'_, Arc<AppState>>,
-) -> Result<NegotiatedParams, String> {
+) -> Result<NegotiatedParams, SerialwarpError> {
+    connect(&app, &state).await
+}
+
+/// Open the transport chosen in the settings. TCP waits up to
+/// `TCP_ACCEPT_TIMEOUT` for a source to connect; the mock transport's other end gets a built-in test source.
+async fn open_transport(
+    kind: TransportKind,
+    tcp_address: &str,
+) -> Result<Arc<dyn Transport>, TransportError> {
+    Ok(match kind {
+        TransportKind::Usb => Arc::new(UsbTransport::open().await?),
+        TransportKind::Tcp => {
+            let listen = TcpTransport::listen(tcp_address);
+            match tokio::time::timeout(TCP_ACCEPT_TIMEOUT, listen).await {
+                Ok(transport) => Arc::new(transport?),
+                Err(_) => {
+                    return Err(TransportError::Timeout {
+                        duration_ms: TCP_ACCEPT_TIMEOUT.as_millis() as u64,
+                    })
+                }
+            }
+        }
+        TransportKind::Mock => {
+            let (source, sink) = MockTransport::pair();
+            test_source::spawn(source);
+            Arc::new(sink)
+        }
+    })
+}
+
+/// Open the transport and run the handshake, as `wait_for_connection` and the
+/// auto-wait task do
+pub async fn connect(
+    app: &AppHandle,
+    state: &AppState,
+) -> Result<NegotiatedParams, SerialwarpError> {
     // Update status to waiting
-    {
-        let mut status = state.connection_status.lock().await;
-        *status = ConnectionStatus::Waiting;
-    }
+    state.set_status(app, ConnectionStatus::Waiting).await;
+    *state.last_error.lock().await = None;
 
-    // Try to open USB transport
-    let transport = match UsbTransport::open().await {
+    let (kind, tcp_address) = {
+        let settings = state.settings.lock().await;
+        (settings.transport, settings.tcp_address.clone())
+    };
+    let transport = match open_transport(kind, &tcp_address).await {
         Ok(t) => t,
         Err(e) => {
-            let mut status = state.connection_status.lock().await;
-            *status = ConnectionStatus::Error;
-            return Err(format!("Failed to open USB transport: {:?}", e));
+            let hint = e.hint();
+            let error = SerialwarpError::from(e).context(match kind {
+                TransportKind::Usb => "Failed to open USB transport".to_string(),
+                TransportKind::Tcp => format!("Failed to listen on {}", tcp_address),
+                TransportKind::Mock => "Failed to open the test source".to_string(),
+            });
+            let message = match hint {
+                Some(hint) => format!("{}. {}", error, hint),
+                None => error.to_string(),
+            };
+            state.set_error(app, message).await;
+            return Err(error);
         }
     };
 
     // Update status to connecting
-    {
-        let mut status = state.connection_status.lock().await;
-        *status = ConnectionStatus::Connecting;
-    }
+    state.set_status(app, ConnectionStatus::Connecting).await;
+
+    // HELLO/START handshake, advertising the limits from settings
+    let (handshake, timeout) = {
+        let settings = state.settings.lock().await;
+        let handshake = SinkHandshake {
+            max_width: settings.max_width,
+            max_height: settings.max_height,
+            initial_credits: settings.max_credits,
+            ..Default::default()
+        };
+        (handshake, Duration::from_millis(settings.handshake_timeout_ms))
+    };
+    let progress = |stage| {
+        if let Err(e) = app.emit(events::HANDSHAKE_PROGRESS, HandshakeProgress { stage }) {
+            tracing::warn!("Failed to emit handshake progress: {:?}", e);
+        }
+    };
+    let negotiated = match negotiate(transport.as_ref(), &handshake, timeout, progress).await {
+        Ok(negotiated) => negotiated,
+        Err(error) => {
+            transport.close().await;
+            state.set_error(app, error.to_string()).await;
+            return Err(error);
+        }
+    };
+
+    // Frames are cropped to the visible size, so that's what's reported
+    let display_size = negotiated.start.display_size();
+    let peer = negotiated.hello.identity;
+    tracing::info!("Connected to {}", peer);
+    let params = NegotiatedParams {
+        width: display_size.width,
+        height: display_size.height,
+        fps: negotiated.start.fps(),
+        bitrate_bps: negotiated.start.bitrate_bps,
+        peer_host_name: peer.host_name,
+        peer_platform: peer.platform,
+        peer_app_version: peer.app_version,
+    };
 
     // Store transport
     {
@@ -63,41 +168,37 @@ pub async fn wait_for_connection(
         *t = Some(transport);
     }
 
-    // In a full implementation, we'd wait for HELLO packet and send HELLO_ACK
-    // For now, return default params
-    let params = NegotiatedParams {
-        width: 1920,
-        height: 1080,
-        fps: 60,
-        bitrate_bps: 20_000_000,
-    };
-
     // Store receiving state (decoder will be created in receiving_loop's blocking task)
     {
         let mut receiving = state.receiving.lock().await;
         receiving.params = Some(params.clone());
+        receiving.sequence = negotiated.sequence;
+        receiving.keyframe_requests = negotiated.keyframe_requests;
         receiving.start_time = Some(Instant::now());
     }
 
     // Update status
-    {
-        let mut status = state.connection_status.lock().await;
-        *status = ConnectionStatus::Connected;
-    }
+    state.set_status(app, ConnectionStatus::Connected).await;
 
     Ok(params)
 }
 
 /// Disconnect from Mac
 #[tauri::command]
-pub async fn disconnect(state: State<'_, Arc<AppState>>) -> Result<(), String> {
+pub async fn disconnect(
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), SerialwarpError> {
     // Stop receiving
-    state.is_receiving.store(false, Ordering::SeqCst);
+    state.stop_receiving();
 
-    // Close transport
+    // Tell the source we're leaving, then close transport
     {
         let mut transport = state.transport.lock().await;
         if let Some(t) = transport.take() {
+            let stop = StopPayload::new(StopReason::UserRequested, false);
+            let packet = Packet::new(PacketType::Stop, 0, 0, stop.to_bytes());
+            let _ = t.send(packet.to_bytes()).await;
             t.close().await;
         }
     }
@@ -110,10 +211,7 @@ pub async fn disconnect(state: State<'_, Arc<AppState>>) -> Result<(), String> {
     }
 
     // Update status
-    {
-        let mut status = state.connection_status.lock().await;
-        *status = ConnectionStatus::Disconnected;
-    }
+    state.set_status(&app, ConnectionStatus::Disconnected).await;
 
     state.reset_stats();
 
@@ -125,17 +223,28 @@ pub async fn disconnect(state: State<'_, Arc<AppState>>) -> Result<(), String> {
 pub async fn start_display(
     app: AppHandle,
     state: State<'_, Arc<AppState>>,
-) -> Result<(), String> {
+) -> Result<(), SerialwarpError> {
+    start_receiving(app, Arc::clone(&state)).await
+}
+
+/// Spawn the receiving and stats tasks for a connected source
+pub async fn start_receiving(app: AppHandle, state: Arc<AppState>) -> Result<(), SerialwarpError> {
     // Check if already receiving
     if state.is_receiving.load(Ordering::SeqCst) {
-        return Err("Already receiving".to_string());
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            "Already receiving",
+        ));
     }
 
     // Verify we're connected
     {
         let status = state.connection_status.lock().await;
         if *status != ConnectionStatus::Connected {
-            return Err("Not connected".to_string());
+            return Err(SerialwarpError::new(
+                ErrorKind::InvalidInput,
+                "Not connected",
+            ));
         }
     }
 
@@ -143,98 +252,368 @@ pub async fn start_display(
     state.reset_stats();
 
     // Update status
-    {
-        let mut status = state.connection_status.lock().await;
-        *status = ConnectionStatus::Receiving;
-    }
-
-    // Spawn the receiving task
-    let state_clone = Arc::clone(&*state);
-    let _app_clone = app.clone();
+    state.set_status(&app, ConnectionStatus::Receiving).await;
+
+    // In native mode frames get a window of their own from here on
+    let size = state
+        .receiving
+        .lock()
+        .await
+        .params
+        .as_ref()
+        .map(|params| (params.width, params.height));
+    native::open(&app, &state, size).await;
+
+    // Spawn the receiving and stats tasks
+    let state_clone = Arc::clone(&state);
+    let app_clone = app.clone();
 
     tokio::spawn(async move {
-        receiving_loop(state_clone).await;
+        receiving_loop(app_clone, state_clone).await;
     });
+    tokio::spawn(stats_loop(app, state));
 
     Ok(())
 }
 
-/// Main receiving loop - runs in a separate blocking task
-async fn receiving_loop(state: Arc<AppState>) {
+/// Push display stats to the frontend until receiving stops
+async fn stats_loop(app: AppHandle, state: Arc<AppState>) {
+    let interval = Duration::from_millis(state.settings.lock().await.stats_interval_ms);
+    let mut ticker = tokio::time::interval(interval.max(MIN_STATS_INTERVAL));
+
+    loop {
+        ticker.tick().await;
+        if !state.is_receiving.load(Ordering::SeqCst) {
+            break;
+        }
+        let stats = state.display_stats().await;
+        state.record_history(&stats);
+        if let Err(e) = app.emit(events::DISPLAY_STATS, stats) {
+            tracing::warn!("Failed to emit stats: {:?}", e);
+        }
+    }
+}
+
+/// Main receiving loop - runs in a separate blocking task. Decoded frames
+/// reach the frontend as `display_frame` events carrying a base64 JPEG, or go
+/// to the native window while one is open.
+async fn receiving_loop(app: AppHandle, state: Arc<AppState>) {
+    let transport: Arc<dyn Transport> = match state.transport.lock().await.as_ref() {
+        Some(t) => t.clone(),
+        None => return,
+    };
+    let (fps, display_size, sequence, keyframe_requests) = {
+        let receiving = state.receiving.lock().await;
+        let fps = receiving.params.as_ref().map_or(60, |params| params.fps);
+        let display_size = receiving
+            .params
+            .as_ref()
+            .map(|params| Resolution::new(params.width, params.height));
+        (
+            fps,
+            display_size,
+            receiving.sequence,
+            receiving.keyframe_requests,
+        )
+    };
+    let (trace_frames, stall_timeout) = {
+        let settings = state.settings.lock().await;
+        let stall_timeout = (settings.stall_timeout_ms > 0)
+            .then(|| Duration::from_millis(settings.stall_timeout_ms));
+        (settings.trace_frames, stall_timeout)
+    };
+
     // Use spawn_blocking for non-Send decoder
     let state_clone = Arc::clone(&state);
+    let app_clone = app.clone();
+    let handle = tokio::runtime::Handle::current();
 
     let _ = tokio::task::spawn_blocking(move || {
         // Create decoder (not Send-safe)
-        let decoder = match Decoder::new(DecoderConfig::default()) {
-            Ok(d) => d,
-            Err(_e) => {
-                // Can't easily set status from blocking task without more complexity
-                // In production, use channels to communicate errors
+        let decoder: Box<dyn VideoDecoder> = match Decoder::new(DecoderConfig::default()) {
+            Ok(d) => Box::new(d),
+            Err(e) => {
+                let message = format!("Failed to create decoder: {:?}", e);
+                handle.block_on(state_clone.set_error(&app_clone, message));
                 return;
             }
         };
 
-        let _reassembler = FrameReassembler::default();
-        let _decoder = decoder; // Keep decoder alive
+        // The web view shows only the newest frame, so don't queue behind it
+        let config = SinkPipelineConfig {
+            fps,
+            queue_depth: 1,
+            display_size,
+            keyframe_requests,
+            trace_frames,
+            stall_timeout,
+            ..Default::default()
+        };
+        let mut pipeline =
+            SinkPipeline::new(split_shared(transport.clone()), decoder, config, sequence);
 
-        // Synchronous receiving loop
         loop {
-            if !state_clone.is_receiving.load(Ordering::SeqCst) {
+            handle.block_on(native::poll(&app_clone, &state_clone));
+            // A stalled stream is told apart from a lost one, which ends
+            // the loop with a transport error
+            if let Some(event) = handle.block_on(pipeline.check_stall(Instant::now())) {
+                let payload = StreamStalled::from(event);
+                if let Err(e) = app_clone.emit(events::STREAM_STALLED, payload) {
+                    tracing::warn!("Failed to emit stall: {:?}", e);
+                }
+            }
+            let received = handle.block_on(
+                state_clone.while_receiving(tokio::time::timeout(FRAME_INTERVAL, pipeline.recv())),
+            );
+            let Some(received) = received else {
                 break;
+            };
+            let output = match received {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => {
+                    if !transport.is_connected() {
+                        handle.block_on(
+                            state_clone
+                                .set_error(&app_clone, format!("Transport disconnected: {:?}", e)),
+                        );
+                        break;
+                    }
+                    tracing::warn!("Receive error: {:?}", e);
+                    continue;
+                }
+                Err(_) => {
+                    handle.block_on(pipeline.reap(Instant::now()));
+                    continue;
+                }
+            };
+
+            match output {
+                SinkOutput::Frame {
+                    pictures,
+                    decode_time,
+                    ..
+                } => {
+                    let stats = pipeline.stats();
+                    state_clone
+                        .frames_received
+                        .store(stats.frames_received, Ordering::SeqCst);
+                    state_clone
+                        .frames_decoded
+                        .store(stats.frames_decoded, Ordering::SeqCst);
+                    state_clone.frames_dropped.store(
+                        stats.frames_dropped_late + stats.frames_evicted,
+                        Ordering::SeqCst,
+                    );
+                    state_clone
+                        .frames_skipped
+                        .store(stats.frames_skipped, Ordering::SeqCst);
+                    if pictures > 0 {
+                        state_clone.add_decode_time(decode_time.as_micros() as u64);
+                    }
+
+                    if let Some(frame) = pipeline.next_decoded_frame() {
+                        let frame_number = frame.frame_number;
+                        if handle.block_on(native::show_frame(&app_clone, &state_clone, frame)) {
+                            state_clone.frames_displayed.fetch_add(1, Ordering::SeqCst);
+                            pipeline.frame_presented(frame_number);
+                            state_clone.set_latency(pipeline.latency_report());
+                        }
+                    }
+                }
+                SinkOutput::Segment => {}
+                SinkOutput::Control(packet) => match packet.packet_type() {
+                    PacketType::Stop => break,
+                    PacketType::Error => {
+                        if let Ok(payload) = ErrorPayload::parse(&packet.payload) {
+                            handle
+                                .block_on(state_clone.record_remote_error(&app_clone, &payload));
+                            if payload.fatal {
+                                break;
+                            }
+                        }
+                    }
+                    PacketType::Ping => {
+                        let Ok(ping) = PingPayload::parse(&packet.payload) else {
+                            continue;
+                        };
+                        let now_us = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_micros() as u64;
+                        let pong = PongPayload::new(ping.timestamp_us, now_us);
+                        let _ = handle
+                            .block_on(pipeline.send_packet(PacketType::Pong, pong.to_bytes()));
+                    }
+                    other => tracing::debug!("Ignoring {:?} packet", other),
+                },
             }
-
-            // In production, we'd:
-            // 1. Receive FRAME packets from transport (blocking read)
-            // 2. Reassemble using FrameReassembler
-            // 3. Decode using Decoder
-            // 4. Send decoded frame to frontend for display via channel
-
-            // Simulated delay
-            std::thread::sleep(FRAME_INTERVAL);
         }
     })
     .await;
 
-    // Update status when loop ends
-    let transport = state.transport.lock().await;
-    let mut status = state.connection_status.lock().await;
-    *status = if transport.is_some() {
-        ConnectionStatus::Connected
-    } else {
-        ConnectionStatus::Disconnected
-    };
+    // Update status when loop ends, unless it ended in an error
+    state.stop_receiving();
+    native::close(&app, &state).await;
+    if *state.connection_status.lock().await == ConnectionStatus::Receiving {
+        let status = state.idle_status().await;
+        state.set_status(&app, status).await;
+    }
 }
 
 /// Stop receiving and displaying
 #[tauri::command]
-pub async fn stop_display(state: State<'_, Arc<AppState>>) -> Result<(), String> {
-    state.is_receiving.store(false, Ordering::SeqCst);
+pub async fn stop_display(
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), SerialwarpError> {
+    state.stop_receiving();
 
     // Update status
-    let transport = state.transport.lock().await;
-    let mut status = state.connection_status.lock().await;
-    *status = if transport.is_some() {
-        ConnectionStatus::Connected
-    } else {
-        ConnectionStatus::Disconnected
-    };
+    let status = state.idle_status().await;
+    state.set_status(&app, status).await;
 
     Ok(())
 }
 
-/// Toggle fullscreen mode
+/// Play an Annex B `.h264` file through the same decode and display path as
+/// a live stream, at `fps` (default 60). Not available while connected.
+#[tauri::command]
+pub async fn play_file(
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+    path: String,
+    fps: Option<u32>,
+    looping: Option<bool>,
+) -> Result<(), SerialwarpError> {
+    start_playback(
+        app,
+        Arc::clone(&*state),
+        PathBuf::from(path),
+        fps,
+        looping.unwrap_or(false),
+    )
+    .await
+}
+
+/// Start file playback; shared by `play_file` and files dropped on the window
+pub async fn start_playback(
+    app: AppHandle,
+    state: Arc<AppState>,
+    path: PathBuf,
+    fps: Option<u32>,
+    looping: bool,
+) -> Result<(), SerialwarpError> {
+    if state.transport.lock().await.is_some() {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            "Disconnect before playing a file",
+        ));
+    }
+    let access_units = load_access_units(&path)?;
+    if state
+        .is_receiving
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            "Already receiving",
+        ));
+    }
+
+    state
+        .playback
+        .start(fps.unwrap_or(DEFAULT_PLAYBACK_FPS), looping);
+    state.reset_stats();
+    state.receiving.lock().await.start_time = Some(Instant::now());
+    state.set_status(&app, ConnectionStatus::Playing).await;
+    native::open(&app, &state, None).await;
+
+    tokio::spawn(run_playback(
+        app.clone(),
+        Arc::clone(&state),
+        path,
+        access_units,
+    ));
+    tokio::spawn(stats_loop(app, state));
+
+    Ok(())
+}
+
+/// Pause file playback
+#[tauri::command]
+pub async fn pause_playback(state: State<'_, Arc<AppState>>) -> Result<(), SerialwarpError> {
+    ensure_playing(&state).await?;
+    state.playback.pause();
+    Ok(())
+}
+
+/// Resume paused file playback
+#[tauri::command]
+pub async fn resume_playback(state: State<'_, Arc<AppState>>) -> Result<(), SerialwarpError> {
+    ensure_playing(&state).await?;
+    state.playback.resume();
+    Ok(())
+}
+
+/// Advance file playback by one frame, pausing it if it's playing
+#[tauri::command]
+pub async fn step_playback(state: State<'_, Arc<AppState>>) -> Result<(), SerialwarpError> {
+    ensure_playing(&state).await?;
+    state.playback.step();
+    Ok(())
+}
+
+/// Set whether file playback starts over at the end
+#[tauri::command]
+pub async fn set_playback_loop(
+    looping: bool,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), SerialwarpError> {
+    ensure_playing(&state).await?;
+    state.playback.set_looping(looping);
+    Ok(())
+}
+
+/// Change the file playback frame rate
+#[tauri::command]
+pub async fn set_playback_fps(
+    fps: u32,
+    state: State<'_, Arc<AppState>>,
+) -> Result<(), SerialwarpError> {
+    ensure_playing(&state).await?;
+    state.playback.set_fps(fps);
+    Ok(())
+}
+
+async fn ensure_playing(state: &AppState) -> Result<(), SerialwarpError> {
+    if *state.connection_status.lock().await != ConnectionStatus::Playing {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            "Not playing a file",
+        ));
+    }
+    Ok(())
+}
+
+/// Toggle fullscreen mode, for the native window while one is open or else
+/// the app's own
 #[tauri::command]
 pub async fn toggle_fullscreen(
     window: WebviewWindow,
     state: State<'_, Arc<AppState>>,
-) -> Result<bool, String> {
+) -> Result<bool, SerialwarpError> {
     let is_fullscreen = state.is_fullscreen.load(Ordering::SeqCst);
     let new_state = !is_fullscreen;
 
-    window
-        .set_fullscreen(new_state)
-        .map_err(|e| format!("Failed to set fullscreen: {:?}", e))?;
+    if !state.native.set_fullscreen(new_state).await? {
+        window.set_fullscreen(new_state).map_err(|e| {
+            SerialwarpError::new(
+                ErrorKind::Other,
+                format!("Failed to set fullscreen: {:?}", e),
+            )
+        })?;
+    }
 
     state.is_fullscreen.store(new_state, Ordering::SeqCst);
 
@@ -243,56 +622,80 @@ pub async fn toggle_fullscreen(
 
 /// Get display statistics
 #[tauri::command]
-pub async fn get_display_stats(state: State<'_, Arc<AppState>>) -> Result<DisplayStats, String> {
-    let receiving = state.receiving.lock().await;
-    let elapsed = receiving
-        .start_time
-        .map(|t| t.elapsed().as_secs_f64())
-        .unwrap_or(0.0);
-    drop(receiving);
-
-    let frames_received = state.frames_received.load(Ordering::SeqCst);
-    let frames_decoded = state.frames_decoded.load(Ordering::SeqCst);
-    let frames_displayed = state.frames_displayed.load(Ordering::SeqCst);
-    let frames_dropped = state.frames_dropped.load(Ordering::SeqCst);
-
-    let fps = if elapsed > 0.0 {
-        frames_displayed as f64 / elapsed
-    } else {
-        0.0
-    };
+pub async fn get_display_stats(
+    state: State<'_, Arc<AppState>>,
+) -> Result<DisplayStats, SerialwarpError> {
+    Ok(state.display_stats().await)
+}
 
-    Ok(DisplayStats {
-        fps,
-        frames_received,
-        frames_decoded,
-        frames_displayed,
-        frames_dropped,
-        decode_time_ms: state.get_avg_decode_time_ms(),
-        latency_ms: state.get_avg_latency_ms(),
-        elapsed_seconds: elapsed,
-    })
+/// Get up to the last `seconds` of per-second stats, oldest first
+#[tauri::command]
+pub async fn get_stats_history(
+    seconds: u32,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<StatsSample>, SerialwarpError> {
+    let history = state
+        .stats_history
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    Ok(history.recent(seconds))
+}
+
+/// Write the per-second stats history to `path`, as CSV or, for a .json or
+/// .jsonl path, one JSON object per line. Returns the number of rows written.
+#[tauri::command]
+pub async fn export_session_report(
+    path: PathBuf,
+    state: State<'_, Arc<AppState>>,
+) -> Result<usize, SerialwarpError> {
+    let rows = state
+        .stats_history
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .rows();
+    let mut exporter = StatsExporter::create(StatsExportConfig::new(&path))
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    for row in &rows {
+        exporter
+            .write_row(row)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    exporter
+        .flush()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(rows.len())
 }
 
 /// Get current connection status
 #[tauri::command]
-pub async fn get_connection_status(state: State<'_, Arc<AppState>>) -> Result<ConnectionStatus, String> {
+pub async fn get_connection_status(
+    state: State<'_, Arc<AppState>>,
+) -> Result<ConnectionStatus, SerialwarpError> {
     let status = state.connection_status.lock().await;
     Ok(status.clone())
 }
 
+/// Get the most recent error, if any
+#[tauri::command]
+pub async fn get_last_error(
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<String>, SerialwarpError> {
+    let last_error = state.last_error.lock().await;
+    Ok(last_error.clone())
+}
+
 /// Get negotiated parameters
 #[tauri::command]
 pub async fn get_negotiated_params(
     state: State<'_, Arc<AppState>>,
-) -> Result<Option<NegotiatedParams>, String> {
+) -> Result<Option<NegotiatedParams>, SerialwarpError> {
     let receiving = state.receiving.lock().await;
     Ok(receiving.params.clone())
 }
 
 /// Get application settings
 #[tauri::command]
-pub async fn get_settings(state: State<'_, Arc<AppState>>) -> Result<AppSettings, String> {
+pub async fn get_settings(state: State<'_, Arc<AppState>>) -> Result<AppSettings, SerialwarpError> {
     let settings = state.settings.lock().await;
     Ok(settings.clone())
 }
@@ -302,8 +705,43 @@ pub async fn get_settings(state: State<'_, Arc<AppState>>) -> Result<AppSettings
 pub async fn save_settings(
     settings: AppSettings,
     state: State<'_, Arc<AppState>>,
-) -> Result<(), String> {
+) -> Result<(), SerialwarpError> {
+    if !settings.presentation.is_supported() {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            "Native windows aren't available on this platform",
+        ));
+    }
+    logging::set_level(&settings.log_level)?;
     let mut s = state.settings.lock().await;
     *s = settings;
     Ok(())
 }
+
+/// Ways of showing frames this platform supports, for the settings
+#[tauri::command]
+pub async fn get_presentation_modes() -> Result<Vec<PresentationMode>, SerialwarpError> {
+    Ok(PresentationMode::supported())
+}
+
+/// Path of the log file being written, or None if it couldn't be opened
+#[tauri::command]
+pub async fn get_log_path() -> Result<Option<PathBuf>, SerialwarpError> {
+    Ok(logging::log_path())
+}
+
+/// Zip the current and rotated log files next to them, for attaching to a
+/// bug report. Returns the zip's path.
+#[tauri::command]
+pub async fn export_logs() -> Result<PathBuf, SerialwarpError> {
+    let dir = logging::log_path()
+        .as_deref()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .ok_or_else(|| SerialwarpError::new(ErrorKind::Io, "No log file is open"))?;
+    logging::flush();
+    let dest = dir.join(logging::EXPORT_FILE_NAME);
+    logging::zip_logs(&dir, &dest)
+        .with_context(|| format!("Failed to write {}", dest.display()))?;
+    Ok(dest)
+}
@@ -0,0 +1,251 @@
+//! Events pushed to the frontend
+
+use serde::Serialize;
+use serialwarp_pipeline::{AutoConnectEvent, StallEvent};
+
+use crate::state::ConnectionStatus;
+
+/// Emitted with `ConnectionStatusChanged` whenever the status changes
+pub const CONNECTION_STATUS_CHANGED: &str = "connection_status_changed";
+
+/// Emitted with `DisplayStats` every `AppSettings::stats_interval_ms` while
+/// receiving
+pub const DISPLAY_STATS: &str = "display_stats";
+
+/// Emitted with `StreamError` for local failures and errors the source reports
+pub const STREAM_ERROR: &str = "stream_error";
+
+/// Emitted with a base64 JPEG for each decoded frame shown
+pub const DISPLAY_FRAME: &str = "display_frame";
+
+/// Emitted with `StreamStalled` when frames stop arriving while the source is
+/// still connected, and again when they resume
+pub const STREAM_STALLED: &str = "stream_stalled";
+
+/// Emitted with `NativeWindowChanged` when the native video window opens,
+/// closes, or enters or leaves fullscreen
+pub const NATIVE_WINDOW: &str = "native_window";
+
+/// Emitted with `PlaybackPosition` whenever file playback moves on, pauses or
+/// resumes
+pub const PLAYBACK_POSITION: &str = "playback_position";
+
+/// Emitted with `HandshakeProgress` as `wait_for_connection` moves through the
+/// handshake
+pub const HANDSHAKE_PROGRESS: &str = "handshake_progress";
+
+/// Emitted with `AutoConnectProgress` as the auto-wait task waits for a
+/// device and connects to it
+pub const AUTO_CONNECT: &str = "auto_connect";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatusChanged {
+    pub status: ConnectionStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamError {
+    pub message: String,
+    /// Whether the error ended the connection
+    pub fatal: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StreamStalled {
+    /// Whether the stream is stalled, or has recovered
+    pub stalled: bool,
+    /// How long it has gone, or went, without a frame
+    pub waited_ms: u64,
+}
+
+impl From<StallEvent> for StreamStalled {
+    fn from(event: StallEvent) -> Self {
+        match event {
+            StallEvent::Stalled { waited, .. } => Self {
+                stalled: true,
+                waited_ms: waited.as_millis() as u64,
+            },
+            StallEvent::Recovered { stalled_for } => Self {
+                stalled: false,
+                waited_ms: stalled_for.as_millis() as u64,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct NativeWindowChanged {
+    /// Whether frames are going to the native window rather than the web view
+    pub open: bool,
+    /// Whether whichever window shows the frames is fullscreen
+    pub fullscreen: bool,
+}
+
+/// Stage of the sink handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HandshakeStage {
+    /// Transport open, no HELLO yet
+    WaitingForHello,
+    /// HELLO received, exchanging HELLO_ACK/START/START_ACK
+    Negotiating,
+    /// START acknowledged; the stream can begin
+    Ready,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HandshakeProgress {
+    pub stage: HandshakeStage,
+}
+
+/// Where file playback is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PlaybackPosition {
+    /// Index of the next access unit to decode
+    pub frame: u64,
+    pub total_frames: u64,
+    pub paused: bool,
+    pub looping: bool,
+}
+
+/// Step of connecting on its own with `AppSettings::auto_wait`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", rename_all = "kebab-case")]
+pub enum AutoConnectProgress {
+    /// No supported device is plugged in
+    WaitingForDevice,
+    /// Backing off after a failed attempt
+    Waiting {
+        delay_ms: u64,
+    },
+    Connecting {
+        attempt: u32,
+    },
+    Connected,
+    Failed {
+        error: String,
+        retry_in_ms: u64,
+    },
+}
+
+impl From<AutoConnectEvent> for AutoConnectProgress {
+    fn from(event: AutoConnectEvent) -> Self {
+        match event {
+            AutoConnectEvent::Waiting { delay } => Self::Waiting {
+                delay_ms: delay.as_millis() as u64,
+            },
+            AutoConnectEvent::Connecting { attempt } => Self::Connecting { attempt },
+            AutoConnectEvent::Connected => Self::Connected,
+            AutoConnectEvent::Failed { error, retry_in } => Self::Failed {
+                error,
+                retry_in_ms: retry_in.as_millis() as u64,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DisplayStats;
+    use serde_json::json;
+
+    #[test]
+    fn test_status_payload() {
+        let payload = ConnectionStatusChanged {
+            status: ConnectionStatus::Receiving,
+        };
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "status": "receiving" })
+        );
+    }
+
+    #[test]
+    fn test_error_payload() {
+        let payload = StreamError {
+            message: "Transport disconnected".to_string(),
+            fatal: true,
+        };
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "message": "Transport disconnected", "fatal": true })
+        );
+    }
+
+    #[test]
+    fn test_stalled_payload() {
+        let payload = StreamStalled::from(StallEvent::Stalled {
+            waited: std::time::Duration::from_secs(2),
+            probes: 1,
+        });
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "stalled": true, "waited_ms": 2000 })
+        );
+        let payload = StreamStalled::from(StallEvent::Recovered {
+            stalled_for: std::time::Duration::from_millis(3500),
+        });
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "stalled": false, "waited_ms": 3500 })
+        );
+    }
+
+    #[test]
+    fn test_handshake_payload() {
+        let payload = HandshakeProgress {
+            stage: HandshakeStage::WaitingForHello,
+        };
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "stage": "waiting-for-hello" })
+        );
+    }
+
+    #[test]
+    fn test_playback_position_payload() {
+        let payload = PlaybackPosition {
+            frame: 12,
+            total_frames: 300,
+            paused: true,
+            looping: false,
+        };
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "frame": 12, "total_frames": 300, "paused": true, "looping": false })
+        );
+    }
+
+    #[test]
+    fn test_auto_connect_payload() {
+        let payload = AutoConnectProgress::from(AutoConnectEvent::Failed {
+            error: "No response".to_string(),
+            retry_in: std::time::Duration::from_secs(4),
+        });
+        assert_eq!(
+            serde_json::to_value(payload).unwrap(),
+            json!({ "stage": "failed", "error": "No response", "retry_in_ms": 4000 })
+        );
+        assert_eq!(
+            serde_json::to_value(AutoConnectProgress::WaitingForDevice).unwrap(),
+            json!({ "stage": "waiting-for-device" })
+        );
+    }
+
+    #[test]
+    fn test_stats_payload() {
+        let payload = DisplayStats {
+            frames_received: 3,
+            frames_displayed: 2,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(payload).unwrap();
+        assert_eq!(value["frames_received"], 3);
+        assert_eq!(value["frames_displayed"], 2);
+        assert_eq!(value["fps"], 0.0);
+        assert_eq!(value["link_bitrate_bps"], 0.0);
+        assert_eq!(value["frames_skipped"], 0);
+        assert_eq!(value.as_object().unwrap().len(), 10);
+    }
+}
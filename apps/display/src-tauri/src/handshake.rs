@@ -0,0 +1,176 @@
+//! Sink side of the HELLO/START handshake, with a timeout and progress
+//! reporting
+
+use std::time::Duration;
+
+use serialwarp_core::{Context, ErrorKind, Packet, SerialwarpError};
+use serialwarp_pipeline::{NegotiatedStream, SinkHandshake};
+use serialwarp_transport::Transport;
+
+use crate::events::HandshakeStage;
+
+/// Wait for the source's HELLO and complete the handshake, giving each of
+/// the two stages `timeout`. `progress` is called as each stage begins and
+/// once the stream is ready.
+pub async fn negotiate(
+    transport: &dyn Transport,
+    handshake: &SinkHandshake,
+    timeout: Duration,
+    mut progress: impl FnMut(HandshakeStage),
+) -> Result<NegotiatedStream, SerialwarpError> {
+    progress(HandshakeStage::WaitingForHello);
+    let hello = match tokio::time::timeout(timeout, transport.recv()).await {
+        Ok(Ok(data)) => Packet::parse(&data)
+            .map(|(packet, _)| packet)
+            .context("Handshake failed: invalid HELLO")?,
+        Ok(Err(e)) => return Err(SerialwarpError::from(e).context("Handshake failed")),
+        Err(_) => return Err(timed_out("HELLO", timeout)),
+    };
+
+    progress(HandshakeStage::Negotiating);
+    let negotiated =
+        match tokio::time::timeout(timeout, handshake.accept_hello(transport, hello, 0)).await {
+            Ok(Ok(negotiated)) => negotiated,
+            Ok(Err(e)) => return Err(SerialwarpError::from(e).context("Handshake failed")),
+            Err(_) => return Err(timed_out("START", timeout)),
+        };
+
+    progress(HandshakeStage::Ready);
+    Ok(negotiated)
+}
+
+fn timed_out(packet: &str, timeout: Duration) -> SerialwarpError {
+    SerialwarpError::new(
+        ErrorKind::Timeout,
+        format!(
+            "no {} from the source within {}ms",
+            packet,
+            timeout.as_millis()
+        ),
+    )
+    .context("Handshake failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::{HelloPayload, PacketType};
+    use serialwarp_pipeline::SourceHandshake;
+    use serialwarp_transport::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn sink_handshake() -> SinkHandshake {
+        SinkHandshake {
+            max_width: 2560,
+            max_height: 1440,
+            initial_credits: 6,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_with_source() {
+        let (sink, source) = MockTransport::pair();
+        let source = tokio::spawn(async move {
+            let handshake = SourceHandshake {
+                width: 1280,
+                height: 720,
+                fps: 30,
+                bitrate_bps: 8_000_000,
+                ..Default::default()
+            };
+            handshake.connect(&source, 0).await
+        });
+
+        let mut stages = Vec::new();
+        let negotiated = negotiate(&sink, &sink_handshake(), TIMEOUT, |stage| {
+            stages.push(stage)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            stages,
+            vec![
+                HandshakeStage::WaitingForHello,
+                HandshakeStage::Negotiating,
+                HandshakeStage::Ready,
+            ]
+        );
+        assert_eq!(
+            (negotiated.start.width, negotiated.start.height),
+            (1280, 720)
+        );
+        assert_eq!(negotiated.start.fps(), 30);
+        assert_eq!(negotiated.start.bitrate_bps, 8_000_000);
+        assert_eq!(negotiated.sequence, 2);
+
+        // The source sees the limits and credits from the settings
+        let started = source.await.unwrap().unwrap();
+        assert_eq!(
+            (started.hello_ack.max_width, started.hello_ack.max_height),
+            (2560, 1440)
+        );
+        assert_eq!(started.initial_credits, 6);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_times_out_without_hello() {
+        let (sink, _source) = MockTransport::pair();
+
+        let mut stages = Vec::new();
+        let error = negotiate(
+            &sink,
+            &sink_handshake(),
+            Duration::from_millis(20),
+            |stage| stages.push(stage),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(stages, vec![HandshakeStage::WaitingForHello]);
+        assert_eq!(error.kind(), ErrorKind::Timeout);
+        let message = error.to_string();
+        assert!(message.contains("no HELLO"), "{}", message);
+        assert!(message.contains("20ms"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_times_out_without_start() {
+        let (sink, source) = MockTransport::pair();
+        let hello = HelloPayload::new(1, 1920, 1080, 60, 0);
+        let hello = Packet::new(PacketType::Hello, 0, 0, hello.to_bytes());
+        source.send(hello.to_bytes()).await.unwrap();
+
+        let error = negotiate(&sink, &sink_handshake(), Duration::from_millis(20), |_| {})
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+        assert!(error.to_string().contains("no START"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_rejects_out_of_order_packet() {
+        let (sink, source) = MockTransport::pair();
+        let ack = HelloPayload::new(1, 1920, 1080, 60, 0);
+        let ack = Packet::new(PacketType::HelloAck, 0, 0, ack.to_bytes());
+        source.send(ack.to_bytes()).await.unwrap();
+
+        let mut stages = Vec::new();
+        let error = negotiate(&sink, &sink_handshake(), TIMEOUT, |stage| {
+            stages.push(stage)
+        })
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            stages,
+            vec![HandshakeStage::WaitingForHello, HandshakeStage::Negotiating]
+        );
+        assert_eq!(error.kind(), ErrorKind::Protocol);
+        assert!(!error.is_retryable());
+        let message = error.to_string();
+        assert!(message.starts_with("Handshake failed"), "{}", message);
+    }
+}
@@ -1,10 +1,20 @@
+mod auto_wait;
 mod commands;
+mod events;
+mod handshake;
+mod logging;
+mod native;
+mod playback;
+mod preview;
 mod state;
+mod test_source;
 
 use std::sync::Arc;
 use state::AppState;
+use tauri::{DragDropEvent, Manager, WindowEvent};
 
 pub fn run() {
+    logging::init();
     let state = Arc::new(AppState::new());
 
     tauri::Builder::default()
@@ -13,18 +23,68 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_process::init())
         .manage(state)
+        .setup(|app| {
+            match app.path().app_log_dir() {
+                Ok(dir) => match logging::open(&dir) {
+                    Ok(path) => tracing::info!("Logging to {}", path.display()),
+                    Err(e) => {
+                        tracing::warn!("Failed to open log file in {}: {}", dir.display(), e)
+                    }
+                },
+                Err(e) => tracing::warn!("No log directory: {}", e),
+            }
+            let state = Arc::clone(app.state::<Arc<AppState>>().inner());
+            tauri::async_runtime::spawn(auto_wait::run_auto_wait(app.handle().clone(), state));
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            // A file dropped on the window plays as if passed to `play_file`
+            if let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event {
+                let Some(path) = paths.first().cloned() else {
+                    return;
+                };
+                let app = window.app_handle().clone();
+                let state = Arc::clone(window.state::<Arc<AppState>>().inner());
+                tauri::async_runtime::spawn(async move {
+                    let result = commands::start_playback(
+                        app.clone(),
+                        Arc::clone(&state),
+                        path,
+                        None,
+                        false,
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        tracing::warn!("{}", e);
+                        state.emit_error(&app, e.to_string(), false);
+                    }
+                });
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             commands::list_usb_devices,
             commands::wait_for_connection,
             commands::disconnect,
             commands::start_display,
             commands::stop_display,
+            commands::play_file,
+            commands::pause_playback,
+            commands::resume_playback,
+            commands::step_playback,
+            commands::set_playback_loop,
+            commands::set_playback_fps,
             commands::toggle_fullscreen,
             commands::get_display_stats,
+            commands::get_stats_history,
+            commands::export_session_report,
             commands::get_connection_status,
+            commands::get_last_error,
             commands::get_negotiated_params,
             commands::get_settings,
             commands::save_settings,
+            commands::get_presentation_modes,
+            commands::get_log_path,
+            commands::export_logs,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,383 @@
+//! Log file for bug reports: everything logged at the configured level also
+//! goes to a size-rotated file in the platform log directory, which the
+//! settings dialog can export as a zip
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use serialwarp_core::{ErrorKind, SerialwarpError};
+
+/// Name of the file being written; rotated files become `serialwarp-display.1.log`,
+/// `serialwarp-display.2.log` and so on, oldest last
+pub const LOG_FILE_NAME: &str = "serialwarp-display.log";
+
+/// Name of the zip `export_logs` writes next to the logs
+pub const EXPORT_FILE_NAME: &str = "serialwarp-display-logs.zip";
+
+/// Size at which the log file is rotated
+pub const MAX_LOG_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Log files kept, counting the one being written
+pub const MAX_LOG_FILES: usize = 5;
+
+/// Level written to the log file unless the settings say otherwise
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Per-frame timings, logged only when enabled in settings
+const FRAMES_DIRECTIVE: &str = "serialwarp::frames=debug";
+
+const LOG_FILE_STEM: &str = "serialwarp-display";
+
+/// A log file that moves itself aside once it reaches `max_size`, keeping at
+/// most `max_files` files in its directory
+#[derive(Debug)]
+pub struct RotatingFile {
+    dir: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Append to the log file in `dir`, creating the directory if needed
+    pub fn open(dir: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE_NAME))?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_size,
+            max_files: max_files.max(1),
+            file,
+            written,
+        })
+    }
+
+    /// Path of the file being written
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(LOG_FILE_NAME)
+    }
+
+    /// Shift every file down one place, dropping the oldest, and start a new
+    /// file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let oldest = rotated_path(&self.dir, self.max_files - 1);
+        if self.max_files == 1 {
+            fs::remove_file(self.path())?;
+        } else {
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for index in (1..self.max_files - 1).rev() {
+                let from = rotated_path(&self.dir, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.dir, index + 1))?;
+                }
+            }
+            fs::rename(self.path(), rotated_path(&self.dir, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(dir: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        dir.join(LOG_FILE_NAME)
+    } else {
+        dir.join(format!("{}.{}.log", LOG_FILE_STEM, index))
+    }
+}
+
+/// The log files in `dir`, newest first
+pub fn log_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<(usize, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let index = if name == LOG_FILE_NAME {
+            Some(0)
+        } else {
+            name.strip_prefix(LOG_FILE_STEM)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .and_then(|rest| rest.strip_suffix(".log"))
+                .and_then(|index| index.parse().ok())
+        };
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Write the log files in `dir` into a zip at `dest`. Returns the number of
+/// files written.
+pub fn zip_logs(dir: &Path, dest: &Path) -> io::Result<usize> {
+    let files = log_files(dir)?;
+    let mut zip = ZipWriter::new(File::create(dest)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for path in &files {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        zip.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(files.len())
+}
+
+/// Where the file layer writes. Events are dropped until `open` is called,
+/// since the log directory is only known once the app is set up.
+#[derive(Clone, Default)]
+struct LogFile(Arc<Mutex<Option<RotatingFile>>>);
+
+impl Write for &LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = &'a LogFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+struct Logging {
+    file: LogFile,
+    filter: reload::Handle<EnvFilter, Registry>,
+}
+
+static LOGGING: OnceLock<Logging> = OnceLock::new();
+
+fn file_filter(level: LevelFilter) -> EnvFilter {
+    EnvFilter::default()
+        .add_directive(level.into())
+        .add_directive(FRAMES_DIRECTIVE.parse().unwrap())
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, SerialwarpError> {
+    level.parse().map_err(|_| {
+        SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown log level '{}'", level),
+        )
+    })
+}
+
+/// Install the global subscriber: stderr as before, plus the log file once
+/// `open` is called. Call once, before the app is built.
+pub fn init() {
+    let file = LogFile::default();
+    let (filter, handle) = reload::Layer::new(file_filter(LevelFilter::INFO));
+    let file_layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(file.clone())
+        .with_filter(filter);
+    let stderr_layer = tracing_subscriber::fmt::layer().with_filter(
+        EnvFilter::from_default_env()
+            .add_directive(tracing::Level::INFO.into())
+            .add_directive(FRAMES_DIRECTIVE.parse().unwrap()),
+    );
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(stderr_layer)
+        .init();
+    let _ = LOGGING.set(Logging {
+        file,
+        filter: handle,
+    });
+}
+
+/// Start writing the log file in `dir`. Returns its path.
+pub fn open(dir: &Path) -> io::Result<PathBuf> {
+    let file = RotatingFile::open(dir, MAX_LOG_FILE_SIZE, MAX_LOG_FILES)?;
+    let path = file.path();
+    if let Some(logging) = LOGGING.get() {
+        *logging.file.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    }
+    Ok(path)
+}
+
+/// Path of the log file being written, if one is open
+pub fn log_path() -> Option<PathBuf> {
+    let logging = LOGGING.get()?;
+    let file = logging.file.0.lock().unwrap_or_else(|e| e.into_inner());
+    file.as_ref().map(RotatingFile::path)
+}
+
+/// Change the level written to the log file, e.g. "debug"
+pub fn set_level(level: &str) -> Result<(), SerialwarpError> {
+    let level = parse_level(level)?;
+    if let Some(logging) = LOGGING.get() {
+        logging
+            .filter
+            .reload(file_filter(level))
+            .map_err(|e| SerialwarpError::new(ErrorKind::Other, e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Flush the log file so an export includes everything logged so far
+pub fn flush() {
+    if let Some(logging) = LOGGING.get() {
+        let _ = (&logging.file).flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("serialwarp-logs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_rotates_at_max_size() {
+        let dir = temp_dir("rotate");
+        let mut file = RotatingFile::open(&dir, 100, 3).unwrap();
+        for line in 0..10 {
+            file.write_all(format!("{:049}\n", line).as_bytes())
+                .unwrap();
+        }
+        file.flush().unwrap();
+
+        // Two 50-byte lines per file, and only the newest three files kept
+        let files = log_files(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.join(LOG_FILE_NAME),
+                dir.join("serialwarp-display.1.log"),
+                dir.join("serialwarp-display.2.log"),
+            ]
+        );
+        let newest = fs::read_to_string(&files[0]).unwrap();
+        assert!(newest.starts_with(&format!("{:049}\n", 8)));
+        let oldest = fs::read_to_string(&files[2]).unwrap();
+        assert!(oldest.starts_with(&format!("{:049}\n", 4)));
+        for path in &files {
+            assert_eq!(fs::metadata(path).unwrap().len(), 100);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reopen_appends() {
+        let dir = temp_dir("reopen");
+        let mut file = RotatingFile::open(&dir, 100, 2).unwrap();
+        file.write_all(&[b'a'; 60]).unwrap();
+        drop(file);
+
+        // The size already written counts toward the next rotation
+        let mut file = RotatingFile::open(&dir, 100, 2).unwrap();
+        file.write_all(&[b'b'; 60]).unwrap();
+        assert_eq!(log_files(&dir).unwrap().len(), 2);
+        assert_eq!(fs::metadata(file.path()).unwrap().len(), 60);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_single_file_truncates() {
+        let dir = temp_dir("single");
+        let mut file = RotatingFile::open(&dir, 10, 1).unwrap();
+        file.write_all(b"0123456789").unwrap();
+        file.write_all(b"abc").unwrap();
+        assert_eq!(log_files(&dir).unwrap(), vec![dir.join(LOG_FILE_NAME)]);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "abc");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_zip_logs() {
+        let dir = temp_dir("zip");
+        let mut file = RotatingFile::open(&dir, 8, 3).unwrap();
+        file.write_all(b"old line").unwrap();
+        file.write_all(b"new line").unwrap();
+        fs::write(dir.join("unrelated.txt"), b"skip me").unwrap();
+
+        let dest = dir.join(EXPORT_FILE_NAME);
+        assert_eq!(zip_logs(&dir, &dest).unwrap(), 2);
+
+        let mut archive = zip::ZipArchive::new(File::open(&dest).unwrap()).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut contents = String::new();
+        io::Read::read_to_string(
+            &mut archive.by_name("serialwarp-display.1.log").unwrap(),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, "old line");
+        assert!(archive.by_name(LOG_FILE_NAME).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("debug").unwrap(), LevelFilter::DEBUG);
+        assert_eq!(parse_level("off").unwrap(), LevelFilter::OFF);
+        assert_eq!(
+            parse_level("loud").unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
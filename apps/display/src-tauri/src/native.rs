@@ -0,0 +1,350 @@
+//! A video window of the app's own, for `PresentationMode::Native`
+//!
+//! The web view can only show frames sent to it as events, each one
+//! downscaled to a JPEG and copied through IPC. In native mode decoded frames
+//! go straight to a serialwarp-render window instead, opened over the app's
+//! window while receiving, and the web view keeps the controls and stats.
+//! Not on macOS, where the window would have to be on the main thread that
+//! Tauri runs on; see `PresentationMode::is_supported`.
+
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+
+use serialwarp_core::{DecodedFrame, ErrorKind, SerialwarpError};
+use serialwarp_render::{
+    RenderEvent, Renderer, RendererConfig, RendererHandle, ScalingMode, WindowGeometry,
+};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::Mutex;
+
+use crate::events::{self, NativeWindowChanged};
+use crate::preview::{encode_preview, MAX_PREVIEW_WIDTH};
+use crate::state::{AppState, PresentationMode};
+
+/// Label of the app's own window, from tauri.conf.json
+const MAIN_WINDOW: &str = "main";
+
+/// Window size when the app's window can't be measured
+const DEFAULT_SIZE: (u32, u32) = (1280, 720);
+
+/// What happened to the native window since it was last polled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeChange {
+    /// The user closed it, so frames go back to the web view
+    Closed,
+    /// The user took it into or out of fullscreen
+    Fullscreen(bool),
+}
+
+/// An open window and the thread drawing it
+struct Open {
+    handle: RendererHandle,
+    thread: JoinHandle<()>,
+    fullscreen: bool,
+}
+
+impl Open {
+    /// Close the window and wait for its thread to finish
+    async fn close(self) {
+        drop(self.handle);
+        let thread = self.thread;
+        match tokio::task::spawn_blocking(move || thread.join()).await {
+            Ok(Ok(())) => {}
+            _ => tracing::warn!("Native window thread panicked"),
+        }
+    }
+}
+
+/// The native video window, while one is open
+#[derive(Default)]
+pub struct NativeWindow {
+    open: Mutex<Option<Open>>,
+}
+
+impl NativeWindow {
+    /// Open a window with `config`, closing any already open first
+    pub async fn open(&self, config: RendererConfig) -> Result<(), SerialwarpError> {
+        let mut open = self.open.lock().await;
+        if let Some(previous) = open.take() {
+            previous.close().await;
+        }
+
+        let fullscreen = config
+            .geometry
+            .map_or(config.fullscreen, |geometry| geometry.fullscreen);
+        // Blocks until the window is up
+        let (handle, thread) = tokio::task::spawn_blocking(move || Renderer::spawn(config))
+            .await
+            .map_err(|e| SerialwarpError::new(ErrorKind::Render, e.to_string()))??;
+        *open = Some(Open {
+            handle,
+            thread,
+            fullscreen,
+        });
+        Ok(())
+    }
+
+    /// Close the window, returning whether one was open
+    pub async fn close(&self) -> bool {
+        let Some(open) = self.open.lock().await.take() else {
+            return false;
+        };
+        open.close().await;
+        true
+    }
+
+    pub async fn is_open(&self) -> bool {
+        self.open.lock().await.is_some()
+    }
+
+    /// Whether the window is fullscreen, or None if none is open
+    pub async fn is_fullscreen(&self) -> Option<bool> {
+        self.open.lock().await.as_ref().map(|open| open.fullscreen)
+    }
+
+    /// Queue `frame` for the window. Hands it back if no window is open, so
+    /// it can be shown in the web view instead.
+    pub async fn present(&self, frame: DecodedFrame) -> Option<DecodedFrame> {
+        let open = self.open.lock().await;
+        let Some(open) = open.as_ref() else {
+            return Some(frame);
+        };
+        // A window that's gone is noticed, and closed, by the next `poll`
+        if let Err(e) = open.handle.present(frame).await {
+            tracing::debug!("Native window didn't take a frame: {}", e);
+        }
+        None
+    }
+
+    /// Take the window into or out of fullscreen. Returns false, changing
+    /// nothing, if none is open.
+    pub async fn set_fullscreen(&self, fullscreen: bool) -> Result<bool, SerialwarpError> {
+        let mut open = self.open.lock().await;
+        let Some(open) = open.as_mut() else {
+            return Ok(false);
+        };
+        open.handle.set_fullscreen(fullscreen).await?;
+        open.fullscreen = fullscreen;
+        Ok(true)
+    }
+
+    /// Read what the user did to the window since the last poll. If they
+    /// closed it, it's closed here too.
+    pub async fn poll(&self) -> Option<NativeChange> {
+        let mut guard = self.open.lock().await;
+        let open = guard.as_mut()?;
+        let was_fullscreen = open.fullscreen;
+        loop {
+            match open.handle.events().try_recv() {
+                Ok(RenderEvent::WindowChanged(geometry)) => open.fullscreen = geometry.fullscreen,
+                Ok(RenderEvent::PresentFailed(e)) => {
+                    tracing::warn!("Native window failed to show a frame: {}", e)
+                }
+                Ok(RenderEvent::Key(_) | RenderEvent::Shortcut(_)) => {}
+                Ok(RenderEvent::Quit) | Err(TryRecvError::Disconnected) => {
+                    if let Some(open) = guard.take() {
+                        open.close().await;
+                    }
+                    return Some(NativeChange::Closed);
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        (open.fullscreen != was_fullscreen).then_some(NativeChange::Fullscreen(open.fullscreen))
+    }
+}
+
+/// Open the native window over the app's own, if the settings ask for one.
+/// `size` is the stream's, used if the app's window can't be measured. If
+/// the window can't be opened frames go to the web view as usual.
+pub async fn open(app: &AppHandle, state: &AppState, size: Option<(u32, u32)>) {
+    let vsync = {
+        let settings = state.settings.lock().await;
+        if settings.presentation != PresentationMode::Native {
+            return;
+        }
+        settings.vsync
+    };
+    // Settings saved before this check, or copied from another machine
+    if !PresentationMode::Native.is_supported() {
+        let message = "Native windows aren't available here, showing frames in the app";
+        tracing::warn!("{}", message);
+        state.emit_error(app, message.to_string(), false);
+        return;
+    }
+
+    let main = app.get_webview_window(MAIN_WINDOW);
+    let (width, height) = size.unwrap_or(DEFAULT_SIZE);
+    let mut geometry = main.as_ref().and_then(|window| {
+        let scale = window.scale_factor().ok()?;
+        let position = window.inner_position().ok()?.to_logical::<i32>(scale);
+        let size = window.inner_size().ok()?.to_logical::<u32>(scale);
+        Some(WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            display_index: None,
+            fullscreen: false,
+        })
+    });
+
+    // Only one of the two windows is fullscreen at a time; the native one
+    // takes over while it's open
+    let fullscreen = state.is_fullscreen.load(Ordering::SeqCst);
+    if fullscreen {
+        set_app_fullscreen(app, false);
+        if let Some(geometry) = &mut geometry {
+            geometry.fullscreen = true;
+        }
+    }
+
+    let config = RendererConfig {
+        title: "SerialWarp Display".to_string(),
+        width,
+        height,
+        fullscreen,
+        vsync,
+        scaling_mode: ScalingMode::Fit,
+        geometry,
+        ..Default::default()
+    };
+    match state.native.open(config).await {
+        Ok(()) => {
+            tracing::info!("Showing frames in a native window");
+            emit(app, true, fullscreen);
+        }
+        Err(e) => {
+            let message = format!(
+                "Native window unavailable, showing frames in the app: {}",
+                e
+            );
+            tracing::warn!("{}", message);
+            state.emit_error(app, message, false);
+            if fullscreen {
+                set_app_fullscreen(app, true);
+            }
+        }
+    }
+}
+
+/// Close the native window, if one is open, handing fullscreen back to the
+/// app's window
+pub async fn close(app: &AppHandle, state: &AppState) {
+    if !state.native.close().await {
+        return;
+    }
+    let fullscreen = state.is_fullscreen.load(Ordering::SeqCst);
+    if fullscreen {
+        set_app_fullscreen(app, true);
+    }
+    emit(app, false, fullscreen);
+}
+
+/// Act on what the user did to the native window: closing it sends frames
+/// back to the web view, and its fullscreen state becomes the app's
+pub async fn poll(app: &AppHandle, state: &AppState) {
+    match state.native.poll().await {
+        Some(NativeChange::Closed) => {
+            tracing::info!("Native window closed, showing frames in the app");
+            state.is_fullscreen.store(false, Ordering::SeqCst);
+            emit(app, false, false);
+        }
+        Some(NativeChange::Fullscreen(fullscreen)) => {
+            state.is_fullscreen.store(fullscreen, Ordering::SeqCst);
+            emit(app, true, fullscreen);
+        }
+        None => {}
+    }
+}
+
+/// Show `frame` in the native window if one is open, or else send it to the
+/// web view. Returns whether it was shown.
+pub async fn show_frame(app: &AppHandle, state: &AppState, frame: DecodedFrame) -> bool {
+    let Some(frame) = state.native.present(frame).await else {
+        return true;
+    };
+    let Some(preview) = encode_preview(&frame, MAX_PREVIEW_WIDTH) else {
+        return false;
+    };
+    match app.emit(events::DISPLAY_FRAME, preview) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("Failed to emit frame: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Take the app's own window into or out of fullscreen
+fn set_app_fullscreen(app: &AppHandle, fullscreen: bool) {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW) else {
+        return;
+    };
+    if let Err(e) = window.set_fullscreen(fullscreen) {
+        tracing::warn!("Failed to set fullscreen: {:?}", e);
+    }
+}
+
+fn emit(app: &AppHandle, open: bool, fullscreen: bool) {
+    let payload = NativeWindowChanged { open, fullscreen };
+    if let Err(e) = app.emit(events::NATIVE_WINDOW, payload) {
+        tracing::warn!("Failed to emit native window change: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_number: u64) -> DecodedFrame {
+        DecodedFrame::new(frame_number, 0, 64, 32, vec![128; 64 * 32 * 3 / 2])
+    }
+
+    /// A small window on SDL's dummy video driver, which needs no display
+    fn headless_config() -> RendererConfig {
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        RendererConfig {
+            width: 64,
+            height: 32,
+            vsync: false,
+            ..Default::default()
+        }
+    }
+
+    // One test, as SDL can only be open once at a time in a process
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_open_close_cycles() {
+        let window = NativeWindow::default();
+        assert!(!window.close().await);
+        assert_eq!(window.poll().await, None);
+        assert!(!window.set_fullscreen(true).await.unwrap());
+
+        // Each connect opens a window and each disconnect closes it
+        for cycle in 0..3 {
+            if let Err(e) = window.open(headless_config()).await {
+                eprintln!("Skipping, no headless SDL renderer: {}", e);
+                return;
+            }
+            assert!(window.is_open().await);
+            assert_eq!(window.is_fullscreen().await, Some(false));
+            assert!(window.present(frame(cycle)).await.is_none());
+            assert!(window.set_fullscreen(true).await.unwrap());
+            assert_eq!(window.is_fullscreen().await, Some(true));
+            assert_eq!(window.poll().await, None);
+
+            assert!(window.close().await);
+            assert!(!window.is_open().await);
+            assert_eq!(window.is_fullscreen().await, None);
+            // With no window, the frame is handed back for the web view
+            assert!(window.present(frame(cycle)).await.is_some());
+        }
+
+        // Opening again replaces the open window rather than adding one
+        window.open(headless_config()).await.unwrap();
+        window.open(headless_config()).await.unwrap();
+        assert!(window.close().await);
+        assert!(!window.close().await);
+    }
+}
@@ -0,0 +1,302 @@
+//! Playback of Annex B files through the live display path, so decoding and
+//! presentation can be checked without a source attached
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::runtime::Handle;
+
+use serialwarp_core::{Context, DecodedFrame, ErrorKind, SerialwarpError};
+use serialwarp_decode::{split_access_units, Decoder, DecoderConfig};
+
+use crate::events::{self, PlaybackPosition};
+use crate::native;
+use crate::state::{AppState, ConnectionStatus};
+
+/// Frame rate used when `play_file` isn't given one
+pub const DEFAULT_PLAYBACK_FPS: u32 = 60;
+
+/// How long to sleep between control checks while paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Play/pause/step/loop state, shared between the playback commands and the
+/// playback task
+#[derive(Debug, Default)]
+pub struct PlaybackControl {
+    paused: AtomicBool,
+    looping: AtomicBool,
+    /// Access units to advance by while paused
+    pending_steps: AtomicU32,
+    fps: AtomicU32,
+}
+
+impl PlaybackControl {
+    /// Reset for a new file, playing from the start
+    pub fn start(&self, fps: u32, looping: bool) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.pending_steps.store(0, Ordering::SeqCst);
+        self.looping.store(looping, Ordering::SeqCst);
+        self.set_fps(fps);
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.pending_steps.store(0, Ordering::SeqCst);
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Advance one access unit, pausing first if playing
+    pub fn step(&self) {
+        if self.paused.swap(true, Ordering::SeqCst) {
+            self.pending_steps.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.looping.store(looping, Ordering::SeqCst);
+    }
+
+    pub fn set_fps(&self, fps: u32) {
+        self.fps.store(fps.max(1), Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping.load(Ordering::SeqCst)
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.fps.load(Ordering::SeqCst).max(1) as f64)
+    }
+
+    /// Whether to decode the next access unit now. `due` is whether its
+    /// time has come at the playback rate; while paused only a pending step
+    /// advances, and is used up doing so.
+    fn should_advance(&self, due: bool) -> bool {
+        if !self.is_paused() {
+            return due;
+        }
+        self.pending_steps
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |steps| {
+                steps.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
+
+/// Read and split an Annex B file for `run_playback`
+pub fn load_access_units(path: &Path) -> Result<Vec<Vec<u8>>, SerialwarpError> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let access_units = split_access_units(&data);
+    if access_units.is_empty() {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            format!("No H.264 access units found in {}", path.display()),
+        ));
+    }
+    Ok(access_units)
+}
+
+/// Decode `access_units` at the playback rate and send the pictures to the
+/// frontend as `display_frame` events, or to the native window if one is
+/// open, updating the stats counters as the live stream does. Runs until the
+/// file ends (unless looping) or `is_receiving` is cleared.
+pub async fn run_playback(
+    app: AppHandle,
+    state: Arc<AppState>,
+    path: PathBuf,
+    access_units: Vec<Vec<u8>>,
+) {
+    tracing::info!(
+        "Playing {} access units from {}",
+        access_units.len(),
+        path.display()
+    );
+
+    // Use spawn_blocking for non-Send decoder
+    let state_clone = Arc::clone(&state);
+    let app_clone = app.clone();
+    let handle = Handle::current();
+
+    let _ = tokio::task::spawn_blocking(move || {
+        let new_decoder = || {
+            Decoder::new(DecoderConfig::default())
+                .map_err(|e| format!("Failed to create decoder: {:?}", e))
+        };
+        let mut decoder = match new_decoder() {
+            Ok(decoder) => decoder,
+            Err(message) => {
+                handle.block_on(state_clone.set_error(&app_clone, message));
+                return;
+            }
+        };
+
+        let control = &state_clone.playback;
+        let total_frames = access_units.len() as u64;
+        let mut next_index = 0;
+        let mut next_frame_at = Instant::now();
+        let mut last_position = None;
+
+        while state_clone.is_receiving.load(Ordering::SeqCst) {
+            handle.block_on(native::poll(&app_clone, &state_clone));
+            let position = PlaybackPosition {
+                frame: next_index as u64,
+                total_frames,
+                paused: control.is_paused(),
+                looping: control.is_looping(),
+            };
+            if last_position != Some(position) {
+                if let Err(e) = app_clone.emit(events::PLAYBACK_POSITION, position) {
+                    tracing::warn!("Failed to emit playback position: {:?}", e);
+                }
+                last_position = Some(position);
+            }
+
+            let now = Instant::now();
+            if !control.should_advance(now >= next_frame_at) {
+                if control.is_paused() {
+                    // Resuming plays on from then rather than catching up
+                    next_frame_at = now;
+                    std::thread::sleep(PAUSED_POLL_INTERVAL);
+                } else {
+                    let wait = next_frame_at.saturating_duration_since(now);
+                    std::thread::sleep(wait.min(PAUSED_POLL_INTERVAL));
+                }
+                continue;
+            }
+
+            if next_index == access_units.len() {
+                if !control.is_looping() {
+                    // Show whatever the decoder still holds, then stop
+                    let frames = decoder.flush().unwrap_or_default();
+                    present(&app_clone, &state_clone, &handle, frames);
+                    tracing::info!("End of {}", path.display());
+                    break;
+                }
+
+                // Restart with a fresh decoder so no reference state carries over
+                next_index = 0;
+                decoder = match new_decoder() {
+                    Ok(decoder) => decoder,
+                    Err(message) => {
+                        handle.block_on(state_clone.set_error(&app_clone, message));
+                        break;
+                    }
+                };
+            }
+
+            let frame_interval = control.frame_interval();
+            let pts_us = next_index as i64 * frame_interval.as_micros() as i64;
+            state_clone.frames_received.fetch_add(1, Ordering::SeqCst);
+            let started = Instant::now();
+            match decoder.decode(&access_units[next_index], pts_us) {
+                Ok(output) => {
+                    let frames = output.frames;
+                    if !frames.is_empty() {
+                        state_clone
+                            .frames_decoded
+                            .fetch_add(frames.len() as u64, Ordering::SeqCst);
+                        state_clone.add_decode_time(started.elapsed().as_micros() as u64);
+                    }
+                    present(&app_clone, &state_clone, &handle, frames);
+                }
+                Err(e) => {
+                    tracing::warn!("Decode error at access unit {}: {:?}", next_index, e);
+                    state_clone.frames_dropped.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            next_index += 1;
+
+            // Don't try to catch up after a stall
+            next_frame_at = (next_frame_at + frame_interval).max(now);
+        }
+    })
+    .await;
+
+    // Update status when playback ends, unless it ended in an error
+    state.is_receiving.store(false, Ordering::SeqCst);
+    state.receiving.lock().await.start_time = None;
+    native::close(&app, &state).await;
+    if *state.connection_status.lock().await == ConnectionStatus::Playing {
+        let status = state.idle_status().await;
+        state.set_status(&app, status).await;
+    }
+}
+
+/// Show the newest of `frames`; as with the live stream's one-deep queue, any
+/// older ones are dropped
+fn present(app: &AppHandle, state: &AppState, handle: &Handle, mut frames: Vec<DecodedFrame>) {
+    let Some(frame) = frames.pop() else {
+        return;
+    };
+    state
+        .frames_dropped
+        .fetch_add(frames.len() as u64, Ordering::SeqCst);
+
+    if handle.block_on(native::show_frame(app, state, frame)) {
+        state.frames_displayed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playing_advances_when_due() {
+        let control = PlaybackControl::default();
+        control.start(30, false);
+        assert!(!control.should_advance(false));
+        assert!(control.should_advance(true));
+        assert_eq!(
+            control.frame_interval(),
+            Duration::from_secs_f64(1.0 / 30.0)
+        );
+    }
+
+    #[test]
+    fn test_steps_while_paused() {
+        let control = PlaybackControl::default();
+        control.start(60, false);
+
+        // The first step only pauses
+        control.step();
+        assert!(control.is_paused());
+        assert!(!control.should_advance(true));
+
+        control.step();
+        control.step();
+        assert!(control.should_advance(false));
+        assert!(control.should_advance(false));
+        assert!(!control.should_advance(true));
+
+        // Resuming drops unused steps
+        control.step();
+        control.resume();
+        assert!(!control.is_paused());
+        assert!(!control.should_advance(false));
+    }
+
+    #[test]
+    fn test_start_resets() {
+        let control = PlaybackControl::default();
+        control.start(0, true);
+        assert!(control.is_looping());
+        assert_eq!(control.frame_interval(), Duration::from_secs(1));
+
+        control.pause();
+        control.step();
+        control.start(60, false);
+        assert!(!control.is_paused());
+        assert!(!control.is_looping());
+        assert!(control.should_advance(true));
+    }
+}
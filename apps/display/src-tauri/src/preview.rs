@@ -0,0 +1,69 @@
+//! Downscaled frame previews for the web view
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use jpeg_encoder::{ColorType, Encoder};
+use serialwarp_core::DecodedFrame;
+
+/// Widest preview sent to the frontend; larger frames are subsampled
+pub const MAX_PREVIEW_WIDTH: u32 = 960;
+
+/// JPEG quality of previews; enough to read text at preview size while
+/// keeping each event a few tens of kilobytes
+const PREVIEW_QUALITY: u8 = 80;
+
+/// Convert a decoded YUV420P frame to a base64 JPEG no wider than
+/// `max_width`, subsampling by a whole factor. None if the frame is too
+/// small to subsample or the encoder fails.
+pub fn encode_preview(frame: &DecodedFrame, max_width: u32) -> Option<String> {
+    let step = ((frame.width + max_width - 1) / max_width.max(1)).max(1) as usize;
+    let width = frame.width as usize / step;
+    let height = frame.height as usize / step;
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        for col in 0..width {
+            rgb.extend_from_slice(&frame.rgb_at(col * step, row * step, frame.color_space.matrix));
+        }
+    }
+
+    let mut jpeg = Vec::new();
+    let encoded = Encoder::new(&mut jpeg, PREVIEW_QUALITY).encode(
+        &rgb,
+        u16::try_from(width).ok()?,
+        u16::try_from(height).ok()?,
+        ColorType::Rgb,
+    );
+    if let Err(e) = encoded {
+        tracing::warn!("Failed to encode a {}x{} preview: {}", width, height, e);
+        return None;
+    }
+    Some(STANDARD.encode(jpeg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_is_downscaled_jpeg() {
+        let frame = DecodedFrame::new(0, 0, 1920, 1080, vec![128; 1920 * 1080 * 3 / 2]);
+        let jpeg = STANDARD
+            .decode(encode_preview(&frame, 960).unwrap())
+            .unwrap();
+        // Start and end of image markers
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+        assert_eq!(&jpeg[jpeg.len() - 2..], &[0xFF, 0xD9]);
+        // Far smaller than the 960x540 RGB it holds
+        assert!(jpeg.len() < 960 * 540 * 3 / 10);
+
+        // Width and height from the SOF0 segment
+        let sof = jpeg
+            .windows(2)
+            .position(|marker| marker == [0xFF, 0xC0])
+            .unwrap();
+        let height = u16::from_be_bytes([jpeg[sof + 5], jpeg[sof + 6]]);
+        let width = u16::from_be_bytes([jpeg[sof + 7], jpeg[sof + 8]]);
+        assert_eq!((width, height), (960, 540));
+    }
+}
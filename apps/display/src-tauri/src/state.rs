@@ -1,9 +1,23 @@
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::Instant;
-use tokio::sync::Mutex;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Mutex, Notify};
 
-use serialwarp_transport::UsbTransport;
+use serialwarp_core::{unix_time_us, ErrorPayload, ThroughputMeter};
+use serialwarp_pipeline::{LatencyReport, StatsRow, DEFAULT_STALL_TIMEOUT};
+use serialwarp_transport::Transport;
+
+use crate::events::{self, ConnectionStatusChanged, StreamError};
+use crate::logging;
+use crate::native::NativeWindow;
+use crate::playback::PlaybackControl;
+
+/// Address listened on for a TCP source unless the settings say otherwise
+pub const DEFAULT_TCP_ADDRESS: &str = "0.0.0.0:7800";
 
 /// USB device information for the UI
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +36,8 @@ pub enum ConnectionStatus {
     Connecting,
     Connected,
     Receiving,
+    /// Showing a file from `play_file` rather than a live stream
+    Playing,
     Error,
 }
 
@@ -32,6 +48,10 @@ pub struct NegotiatedParams {
     pub height: u32,
     pub fps: u32,
     pub bitrate_bps: u32,
+    /// Who the source says it is, from its HELLO; empty if it didn't say
+    pub peer_host_name: String,
+    pub peer_platform: String,
+    pub peer_app_version: String,
 }
 
 /// Display statistics
@@ -42,9 +62,195 @@ pub struct DisplayStats {
     pub frames_decoded: u64,
     pub frames_displayed: u64,
     pub frames_dropped: u64,
+    /// Frames the decoder skipped to catch up after falling behind
+    pub frames_skipped: u64,
     pub decode_time_ms: f64,
+    /// Average capture to display time over the last few seconds, 0 if the
+    /// source sends no capture times
     pub latency_ms: f64,
+    /// 95th percentile capture to display time, over the same frames
+    pub latency_p95_ms: f64,
+    /// Longest capture to display time, over the same frames
+    pub latency_max_ms: f64,
     pub elapsed_seconds: f64,
+    /// Bits per second actually received over the link
+    pub link_bitrate_bps: f64,
+}
+
+/// Per-second samples kept for graphs; ten minutes' worth
+pub const STATS_HISTORY_CAPACITY: usize = 600;
+
+/// One second of display statistics
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct StatsSample {
+    /// Wall-clock time at the end of the sample, in milliseconds since the
+    /// Unix epoch
+    pub timestamp_ms: u64,
+    /// Seconds since receiving started, at the end of the sample
+    pub elapsed_seconds: f64,
+    /// Frames displayed per second over the sample
+    pub fps: f64,
+    pub link_bitrate_bps: f64,
+    pub frames_received: u64,
+    pub frames_decoded: u64,
+    pub frames_displayed: u64,
+    /// Frames dropped during the sample
+    pub frames_dropped: u64,
+    pub decode_time_ms: f64,
+    pub latency_ms: f64,
+    pub latency_p95_ms: f64,
+    pub latency_max_ms: f64,
+}
+
+/// Ring buffer of the most recent `StatsSample`s, built from the stats the
+/// stats task pushes
+#[derive(Debug, Clone)]
+pub struct StatsHistory {
+    samples: VecDeque<StatsSample>,
+    capacity: usize,
+    /// Elapsed time and displayed/dropped counts at the last sample
+    last: (f64, u64, u64),
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        Self::new(STATS_HISTORY_CAPACITY)
+    }
+}
+
+impl StatsHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            last: (0.0, 0, 0),
+        }
+    }
+
+    /// Add a sample covering the time since the last one, once a second or
+    /// more has passed; the oldest is dropped when full. Returns whether a
+    /// sample was added.
+    pub fn record(&mut self, stats: &DisplayStats) -> bool {
+        let (last_elapsed, last_displayed, last_dropped) = self.last;
+        let interval = stats.elapsed_seconds - last_elapsed;
+        if interval < 1.0 {
+            return false;
+        }
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(StatsSample {
+            timestamp_ms: unix_time_us() / 1000,
+            elapsed_seconds: stats.elapsed_seconds,
+            fps: stats.frames_displayed.saturating_sub(last_displayed) as f64 / interval,
+            link_bitrate_bps: stats.link_bitrate_bps,
+            frames_received: stats.frames_received,
+            frames_decoded: stats.frames_decoded,
+            frames_displayed: stats.frames_displayed,
+            frames_dropped: stats.frames_dropped.saturating_sub(last_dropped),
+            decode_time_ms: stats.decode_time_ms,
+            latency_ms: stats.latency_ms,
+            latency_p95_ms: stats.latency_p95_ms,
+            latency_max_ms: stats.latency_max_ms,
+        });
+        self.last = (
+            stats.elapsed_seconds,
+            stats.frames_displayed,
+            stats.frames_dropped,
+        );
+        true
+    }
+
+    /// The most recent `seconds` samples, oldest first
+    pub fn recent(&self, seconds: u32) -> Vec<StatsSample> {
+        let skip = self.samples.len().saturating_sub(seconds as usize);
+        self.samples.iter().skip(skip).cloned().collect()
+    }
+
+    /// Every sample as a `StatsRow` for a session report, oldest first.
+    /// Drops are counted from the oldest sample kept.
+    pub fn rows(&self) -> Vec<StatsRow> {
+        let mut frames_dropped = 0;
+        let ms = |ms: f64| (ms > 0.0).then_some(ms);
+        self.samples
+            .iter()
+            .map(|sample| {
+                frames_dropped += sample.frames_dropped;
+                StatsRow {
+                    timestamp_ms: sample.timestamp_ms,
+                    elapsed_s: sample.elapsed_seconds,
+                    state: "receiving".to_string(),
+                    fps: sample.fps,
+                    bitrate_bps: sample.link_bitrate_bps,
+                    frames_received: Some(sample.frames_received),
+                    frames_decoded: Some(sample.frames_decoded),
+                    frames_presented: Some(sample.frames_displayed),
+                    frames_dropped,
+                    latency_avg_ms: ms(sample.latency_ms),
+                    latency_p95_ms: ms(sample.latency_p95_ms),
+                    latency_max_ms: ms(sample.latency_max_ms),
+                    decode_ms: ms(sample.decode_time_ms),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.last = (0.0, 0, 0);
+    }
+}
+
+/// Where decoded frames are shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentationMode {
+    /// In the app's window, as previews sent to the web view
+    #[default]
+    Webview,
+    /// In a native window over the app's, at full size and straight from the
+    /// decoder
+    Native,
+}
+
+impl PresentationMode {
+    /// Whether frames can be shown this way here. macOS only lets the main
+    /// thread open windows, and Tauri's event loop never gives it up, so
+    /// there's no native window there.
+    pub fn is_supported(self) -> bool {
+        self != PresentationMode::Native || !cfg!(target_os = "macos")
+    }
+
+    /// The modes this platform supports
+    pub fn supported() -> Vec<PresentationMode> {
+        [PresentationMode::Webview, PresentationMode::Native]
+            .into_iter()
+            .filter(|mode| mode.is_supported())
+            .collect()
+    }
+}
+
+/// How the app is reached by the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// A USB bridge cable
+    #[default]
+    Usb,
+    /// A source on the network, connecting to `AppSettings::tcp_address`
+    Tcp,
+    /// A test pattern from inside the app, to try it out without a device
+    Mock,
 }
 
 /// Application settings (persisted)
@@ -55,6 +261,62 @@ pub struct AppSettings {
     pub max_width: u32,
     pub max_height: u32,
     pub max_credits: u16,
+    /// How often stats are pushed to the frontend while receiving
+    #[serde(default = "default_stats_interval_ms")]
+    pub stats_interval_ms: u64,
+    /// How long each handshake stage may wait on the source
+    #[serde(default = "default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+    /// How long the stream may go without a frame, while still connected,
+    /// before it's reported stalled; 0 doesn't watch for stalls
+    #[serde(default = "default_stall_timeout_ms")]
+    pub stall_timeout_ms: u64,
+    /// Connect on its own whenever idle and a supported device is plugged in
+    #[serde(default)]
+    pub auto_wait: bool,
+    /// Log stage timings for every Nth frame; 0 logs none
+    #[serde(default)]
+    pub trace_frames: u32,
+    /// Most verbose level written to the log file, e.g. "info" or "debug"
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Where frames are shown while receiving or playing a file
+    #[serde(default)]
+    pub presentation: PresentationMode,
+    /// How to reach the source when connecting
+    #[serde(default)]
+    pub transport: TransportKind,
+    /// Address listened on for `TransportKind::Tcp`
+    #[serde(default = "default_tcp_address")]
+    pub tcp_address: String,
+}
+
+fn default_stats_interval_ms() -> u64 {
+    500
+}
+
+fn default_handshake_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_stall_timeout_ms() -> u64 {
+    DEFAULT_STALL_TIMEOUT.as_millis() as u64
+}
+
+fn default_tcp_address() -> String {
+    DEFAULT_TCP_ADDRESS.to_string()
+}
+
+fn default_log_level() -> String {
+    logging::DEFAULT_LOG_LEVEL.to_string()
+}
+
+impl AppSettings {
+    /// Whether to connect on its own when a device is plugged in. Only USB
+    /// devices are watched, so other transports are connected by hand.
+    pub fn waits_for_device(&self) -> bool {
+        self.auto_wait && self.transport == TransportKind::Usb
+    }
 }
 
 impl Default for AppSettings {
@@ -65,6 +327,15 @@ impl Default for AppSettings {
             max_width: 1920,
             max_height: 1080,
             max_credits: 4,
+            stats_interval_ms: default_stats_interval_ms(),
+            handshake_timeout_ms: default_handshake_timeout_ms(),
+            stall_timeout_ms: default_stall_timeout_ms(),
+            auto_wait: false,
+            trace_frames: 0,
+            log_level: default_log_level(),
+            presentation: PresentationMode::default(),
+            transport: TransportKind::default(),
+            tcp_address: default_tcp_address(),
         }
     }
 }
@@ -75,24 +346,43 @@ impl Default for AppSettings {
 pub struct ReceivingState {
     pub start_time: Option<Instant>,
     pub params: Option<NegotiatedParams>,
+    /// Next outgoing sequence number after the handshake
+    pub sequence: u32,
+    /// Whether the source can be asked for a keyframe
+    pub keyframe_requests: bool,
 }
 
 /// Main application state
 pub struct AppState {
-    pub transport: Mutex<Option<UsbTransport>>,
+    pub transport: Mutex<Option<Arc<dyn Transport>>>,
     pub receiving: Mutex<ReceivingState>,
     pub connection_status: Mutex<ConnectionStatus>,
     pub settings: Mutex<AppSettings>,
+    pub last_error: Mutex<Option<String>>,
     pub is_fullscreen: AtomicBool,
+    /// Whether frames are flowing to the frontend, from the source or a file
     pub is_receiving: AtomicBool,
+    /// Woken by `stop_receiving`, so the receiving loop stops at once rather
+    /// than once its wait for the next packet times out
+    receive_stopped: Notify,
+    pub playback: PlaybackControl,
+    /// The video window frames go to in `PresentationMode::Native`
+    pub native: NativeWindow,
 
     // Atomic counters for stats
     pub frames_received: AtomicU64,
     pub frames_decoded: AtomicU64,
     pub frames_displayed: AtomicU64,
     pub frames_dropped: AtomicU64,
+    pub frames_skipped: AtomicU64,
     pub total_decode_time_us: AtomicU64,
-    pub total_latency_us: AtomicU64,
+    /// Latency of the frames recently displayed, from the sink pipeline
+    pub latency: StdMutex<Option<LatencyReport>>,
+
+    /// Link throughput, sampled whenever stats are read
+    pub link_meter: StdMutex<ThroughputMeter>,
+    /// Per-second stats for graphs, appended by the stats task
+    pub stats_history: StdMutex<StatsHistory>,
 }
 
 impl Default for AppState {
@@ -102,14 +392,21 @@ impl Default for AppState {
             receiving: Mutex::new(ReceivingState::default()),
             connection_status: Mutex::new(ConnectionStatus::Disconnected),
             settings: Mutex::new(AppSettings::default()),
+            last_error: Mutex::new(None),
             is_fullscreen: AtomicBool::new(false),
             is_receiving: AtomicBool::new(false),
+            receive_stopped: Notify::new(),
+            playback: PlaybackControl::default(),
+            native: NativeWindow::default(),
             frames_received: AtomicU64::new(0),
             frames_decoded: AtomicU64::new(0),
             frames_displayed: AtomicU64::new(0),
             frames_dropped: AtomicU64::new(0),
+            frames_skipped: AtomicU64::new(0),
             total_decode_time_us: AtomicU64::new(0),
-            total_latency_us: AtomicU64::new(0),
+            latency: StdMutex::new(None),
+            link_meter: StdMutex::new(ThroughputMeter::default()),
+            stats_history: StdMutex::new(StatsHistory::default()),
         }
     }
 }
@@ -119,23 +416,173 @@ impl AppState {
         Self::default()
     }
 
+    /// Update the connection status and notify the frontend
+    pub async fn set_status(&self, app: &AppHandle, status: ConnectionStatus) {
+        *self.connection_status.lock().await = status.clone();
+        let payload = ConnectionStatusChanged { status };
+        if let Err(e) = app.emit(events::CONNECTION_STATUS_CHANGED, payload) {
+            tracing::warn!("Failed to emit status change: {:?}", e);
+        }
+    }
+
+    /// Stop the receiving loop or file playback
+    pub fn stop_receiving(&self) {
+        self.is_receiving.store(false, Ordering::SeqCst);
+        self.receive_stopped.notify_waiters();
+    }
+
+    /// Run `future` while receiving, returning `None` instead if receiving
+    /// has stopped or stops before it completes
+    pub async fn while_receiving<F: Future>(&self, future: F) -> Option<F::Output> {
+        let stopped = self.receive_stopped.notified();
+        tokio::pin!(stopped);
+        // Registered before checking, so a stop in between still wakes it
+        stopped.as_mut().enable();
+        if !self.is_receiving.load(Ordering::SeqCst) {
+            return None;
+        }
+        tokio::select! {
+            _ = stopped => None,
+            output = future => Some(output),
+        }
+    }
+
+    /// Status to fall back to once receiving ends
+    pub async fn idle_status(&self) -> ConnectionStatus {
+        if self.transport.lock().await.is_some() {
+            ConnectionStatus::Connected
+        } else {
+            ConnectionStatus::Disconnected
+        }
+    }
+
+    /// Whether nothing is connected, connecting or playing
+    pub async fn is_idle(&self) -> bool {
+        let status = self.connection_status.lock().await.clone();
+        matches!(
+            status,
+            ConnectionStatus::Disconnected | ConnectionStatus::Error
+        ) && !self.is_receiving.load(Ordering::SeqCst)
+            && self.transport.lock().await.is_none()
+    }
+
+    /// Record an error for the UI and mark the connection as failed
+    pub async fn set_error(&self, app: &AppHandle, message: String) {
+        tracing::error!("{}", message);
+        *self.last_error.lock().await = Some(message.clone());
+        self.emit_error(app, message, true);
+        self.set_status(app, ConnectionStatus::Error).await;
+    }
+
+    /// Record an ERROR packet received from the source. Fatal errors mark the
+    /// connection as failed; non-fatal ones are only surfaced to the UI.
+    pub async fn record_remote_error(&self, app: &AppHandle, payload: &ErrorPayload) {
+        let message = format!(
+            "Source reported {}: {}",
+            payload.code_name(),
+            payload.message
+        );
+        if payload.fatal {
+            self.set_error(app, message).await;
+        } else {
+            tracing::warn!("{}", message);
+            *self.last_error.lock().await = Some(message.clone());
+            self.emit_error(app, message, false);
+        }
+    }
+
+    pub fn emit_error(&self, app: &AppHandle, message: String, fatal: bool) {
+        if let Err(e) = app.emit(events::STREAM_ERROR, StreamError { message, fatal }) {
+            tracing::warn!("Failed to emit error: {:?}", e);
+        }
+    }
+
+    /// Current display statistics
+    pub async fn display_stats(&self) -> DisplayStats {
+        let elapsed = self
+            .receiving
+            .lock()
+            .await
+            .start_time
+            .map(|t| t.elapsed().as_secs_f64())
+            .unwrap_or(0.0);
+
+        let frames_displayed = self.frames_displayed.load(Ordering::SeqCst);
+        let fps = if elapsed > 0.0 {
+            frames_displayed as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let bytes_received = self
+            .transport
+            .lock()
+            .await
+            .as_ref()
+            .map(|t| t.stats().bytes_received);
+        let link_bitrate_bps = {
+            let mut meter = self.link_meter.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(bytes_received) = bytes_received {
+                meter.sample(Instant::now(), bytes_received);
+            }
+            meter.bits_per_second()
+        };
+
+        let latency = *self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        let latency_ms = |pick: fn(&LatencyReport) -> Duration| {
+            latency
+                .as_ref()
+                .map_or(0.0, |report| pick(report).as_secs_f64() * 1000.0)
+        };
+
+        DisplayStats {
+            fps,
+            frames_received: self.frames_received.load(Ordering::SeqCst),
+            frames_decoded: self.frames_decoded.load(Ordering::SeqCst),
+            frames_displayed,
+            frames_dropped: self.frames_dropped.load(Ordering::SeqCst),
+            frames_skipped: self.frames_skipped.load(Ordering::SeqCst),
+            decode_time_ms: self.get_avg_decode_time_ms(),
+            latency_ms: latency_ms(|report| report.average),
+            latency_p95_ms: latency_ms(|report| report.p95),
+            latency_max_ms: latency_ms(|report| report.max),
+            elapsed_seconds: elapsed,
+            link_bitrate_bps,
+        }
+    }
+
     pub fn reset_stats(&self) {
         self.frames_received.store(0, Ordering::SeqCst);
         self.frames_decoded.store(0, Ordering::SeqCst);
         self.frames_displayed.store(0, Ordering::SeqCst);
         self.frames_dropped.store(0, Ordering::SeqCst);
+        self.frames_skipped.store(0, Ordering::SeqCst);
         self.total_decode_time_us.store(0, Ordering::SeqCst);
-        self.total_latency_us.store(0, Ordering::SeqCst);
+        *self.latency.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.link_meter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .reset();
+        self.stats_history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Add the latest stats to the history if a second has passed
+    pub fn record_history(&self, stats: &DisplayStats) {
+        self.stats_history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(stats);
     }
 
-    #[allow(dead_code)]
     pub fn add_decode_time(&self, time_us: u64) {
         self.total_decode_time_us.fetch_add(time_us, Ordering::SeqCst);
     }
 
-    #[allow(dead_code)]
-    pub fn add_latency(&self, latency_us: u64) {
-        self.total_latency_us.fetch_add(latency_us, Ordering::SeqCst);
+    pub fn set_latency(&self, report: Option<LatencyReport>) {
+        *self.latency.lock().unwrap_or_else(|e| e.into_inner()) = report;
     }
 
     pub fn get_avg_decode_time_ms(&self) -> f64 {
@@ -146,13 +593,153 @@ impl AppState {
             0.0
         }
     }
+}
 
-    pub fn get_avg_latency_ms(&self) -> f64 {
-        let decoded = self.frames_decoded.load(Ordering::SeqCst);
-        if decoded > 0 {
-            self.total_latency_us.load(Ordering::SeqCst) as f64 / decoded as f64 / 1000.0
-        } else {
-            0.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_at(elapsed_seconds: f64, frames_displayed: u64, frames_dropped: u64) -> DisplayStats {
+        DisplayStats {
+            elapsed_seconds,
+            frames_displayed,
+            frames_dropped,
+            link_bitrate_bps: 8_000_000.0,
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn test_history_samples_once_a_second() {
+        let mut history = StatsHistory::new(10);
+        assert!(!history.record(&stats_at(0.5, 30, 0)));
+        assert!(history.record(&stats_at(1.0, 60, 1)));
+        assert!(!history.record(&stats_at(1.5, 90, 1)));
+        assert!(history.record(&stats_at(2.5, 150, 4)));
+
+        let samples = history.recent(10);
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].fps, 60.0);
+        assert_eq!(samples[0].frames_dropped, 1);
+        assert_eq!(samples[1].fps, 60.0);
+        assert_eq!(samples[1].frames_dropped, 3);
+        assert_eq!(samples[1].elapsed_seconds, 2.5);
+        assert_eq!(samples[1].link_bitrate_bps, 8_000_000.0);
+    }
+
+    #[test]
+    fn test_history_wraps_around() {
+        let mut history = StatsHistory::new(3);
+        for second in 1..=5 {
+            history.record(&stats_at(second as f64, second * 60, 0));
+        }
+        assert_eq!(history.len(), 3);
+
+        let elapsed: Vec<f64> = history
+            .recent(10)
+            .iter()
+            .map(|sample| sample.elapsed_seconds)
+            .collect();
+        assert_eq!(elapsed, [3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_history_window() {
+        let mut history = StatsHistory::new(STATS_HISTORY_CAPACITY);
+        for second in 1..=20 {
+            history.record(&stats_at(second as f64, second * 60, 0));
+        }
+
+        let recent = history.recent(5);
+        assert_eq!(recent.len(), 5);
+        assert_eq!(recent[0].elapsed_seconds, 16.0);
+        assert_eq!(recent[4].elapsed_seconds, 20.0);
+        assert!(history.recent(0).is_empty());
+
+        history.clear();
+        assert!(history.is_empty());
+        // Counting starts over after a reset
+        assert!(history.record(&stats_at(1.0, 60, 0)));
+        assert_eq!(history.recent(1)[0].fps, 60.0);
+    }
+
+    #[test]
+    fn test_history_rows() {
+        let mut history = StatsHistory::new(10);
+        history.record(&stats_at(1.0, 60, 1));
+        history.record(&DisplayStats {
+            latency_ms: 20.0,
+            ..stats_at(2.0, 120, 3)
+        });
+
+        let rows = history.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].frames_presented, Some(60));
+        assert_eq!(rows[0].latency_avg_ms, None);
+        assert_eq!(rows[1].frames_dropped, 3);
+        assert_eq!(rows[1].bitrate_bps, 8_000_000.0);
+        assert_eq!(rows[1].latency_avg_ms, Some(20.0));
+    }
+
+    #[tokio::test]
+    async fn test_stop_interrupts_blocked_receive() {
+        let state = Arc::new(AppState::new());
+        assert_eq!(state.while_receiving(async { 1 }).await, None);
+
+        state.is_receiving.store(true, Ordering::SeqCst);
+        assert_eq!(state.while_receiving(async { 1 }).await, Some(1));
+
+        // A receive that never completes, as with a stalled source
+        let stopper = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                state.stop_receiving();
+            }
+        });
+        let stopped = tokio::time::timeout(
+            Duration::from_secs(1),
+            state.while_receiving(std::future::pending::<()>()),
+        )
+        .await
+        .expect("stop didn't interrupt the receive");
+        assert_eq!(stopped, None);
+        assert!(!state.is_receiving.load(Ordering::SeqCst));
+        stopper.await.unwrap();
+    }
+
+    #[test]
+    fn test_reset_stats_clears_history() {
+        let state = AppState::new();
+        state.record_history(&stats_at(1.0, 60, 0));
+        assert_eq!(state.stats_history.lock().unwrap().len(), 1);
+
+        state.reset_stats();
+        assert!(state.stats_history.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_presentation_modes() {
+        let modes = PresentationMode::supported();
+        assert_eq!(modes[0], PresentationMode::Webview);
+        assert_eq!(
+            modes.contains(&PresentationMode::Native),
+            !cfg!(target_os = "macos")
+        );
+    }
+
+    #[test]
+    fn test_transport_settings() {
+        // Settings saved before the transport could be chosen stay on USB
+        let mut value = serde_json::to_value(AppSettings::default()).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("transport");
+        fields.remove("tcp_address");
+        let settings: AppSettings = serde_json::from_value(value).unwrap();
+        assert_eq!(settings.transport, TransportKind::Usb);
+        assert_eq!(settings.tcp_address, DEFAULT_TCP_ADDRESS);
+
+        let kind: TransportKind = serde_json::from_str("\"mock\"").unwrap();
+        assert_eq!(kind, TransportKind::Mock);
+    }
 }
@@ -0,0 +1,81 @@
+//! A test pattern source inside the app, for `TransportKind::Mock`
+//!
+//! Runs the same handshake and source pipeline a Mac would, over the other
+//! end of a `MockTransport` pair, so receiving can be tried without a device.
+
+use std::time::Duration;
+
+use serialwarp_core::{EncoderBackend, EncoderConfig, PipelineError, RateControl};
+use serialwarp_encode::create_encoder;
+use serialwarp_pipeline::{SourceHandshake, SourcePipeline, SourcePipelineConfig};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{MockTransport, Transport};
+
+/// Stream asked for; the app's size limits may scale it down
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+const FPS: u32 = 30;
+const BITRATE_BPS: u32 = 4_000_000;
+
+/// Stream a test pattern on `transport` until the app closes its end
+pub fn spawn(transport: MockTransport) {
+    tokio::spawn(async move {
+        if let Err(e) = run(transport).await {
+            tracing::warn!("Test source stopped: {}", e);
+        }
+    });
+}
+
+async fn run(transport: MockTransport) -> Result<(), PipelineError> {
+    let handshake = SourceHandshake {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: FPS,
+        bitrate_bps: BITRATE_BPS,
+        ..Default::default()
+    };
+    let started = handshake.connect(&transport, 0).await?;
+
+    let (width, height, fps) = (
+        started.start.width,
+        started.start.height,
+        started.start.fps(),
+    );
+    let source = TestPatternSource::new(TestPatternConfig {
+        width,
+        height,
+        fps,
+        paced: true,
+    })?;
+    let encoder = create_encoder(EncoderConfig {
+        width,
+        height,
+        fps,
+        rate_control: RateControl::ConstantBitrate {
+            bps: started.bitrate_bps,
+        },
+        backend: EncoderBackend::Software,
+        keyframe_interval: Duration::from_secs(2),
+        ..Default::default()
+    })?;
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        encoder,
+        transport.split(),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            crc: started.crc,
+            compression: started.compression,
+            pause_packets: started.pause,
+            ..Default::default()
+        },
+    );
+    pipeline.start()?;
+    tracing::info!("Test source streaming {}x{} at {}fps", width, height, fps);
+
+    // Stops once the app closes the transport
+    pipeline.shutdown_token().cancelled().await;
+    pipeline.stop().await;
+    Ok(())
+}
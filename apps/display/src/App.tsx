@@ -1,15 +1,26 @@
-import { useEffect, useCallback, useRef } from "react";
+import { useEffect, useCallback } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
+import { save } from "@tauri-apps/plugin-dialog";
 import {
   useStore,
+  ConnectionStatus,
   DisplayStats,
+  HandshakeStage,
+  AutoConnectProgress,
   AppSettings,
+  CommandError,
   NegotiatedParams,
+  PlaybackPosition,
 } from "./hooks/useStore";
 import { Button } from "./components/ui/button";
 import { Card, CardContent } from "./components/ui/card";
-import { formatDuration, formatLatency, formatFps } from "./lib/utils";
+import {
+  formatBitrate,
+  formatDuration,
+  formatLatency,
+  formatFps,
+} from "./lib/utils";
 import { SettingsDialog } from "./components/SettingsDialog";
 import { VideoDisplay } from "./components/VideoDisplay";
 
@@ -17,27 +28,85 @@ function App() {
   const {
     connectionStatus,
     setConnectionStatus,
+    handshakeStage,
+    setHandshakeStage,
+    autoConnect,
+    setAutoConnect,
     params,
     setParams,
     displayStats,
     setDisplayStats,
     displayFrame,
     setDisplayFrame,
+    stalledMs,
+    setStalledMs,
+    playbackPosition,
+    setPlaybackPosition,
     isFullscreen,
     setIsFullscreen,
+    nativeWindowOpen,
+    setNativeWindowOpen,
     settings,
     setSettings,
     settingsOpen,
     setSettingsOpen,
   } = useStore();
 
-  const statsIntervalRef = useRef<number | null>(null);
-
-  // Load settings on mount
+  // Load settings and initial status on mount
   useEffect(() => {
     invoke<AppSettings>("get_settings")
       .then((s) => setSettings(s))
       .catch(console.error);
+    invoke<ConnectionStatus>("get_connection_status")
+      .then((s) => setConnectionStatus(s))
+      .catch(console.error);
+  }, []);
+
+  // Status, stats, and errors are pushed by the backend
+  useEffect(() => {
+    const unlisteners = [
+      listen<{ status: ConnectionStatus }>(
+        "connection_status_changed",
+        (event) => {
+          setConnectionStatus(event.payload.status);
+          // A stall only lasts as long as the stream it's in
+          if (event.payload.status !== "receiving") setStalledMs(null);
+        }
+      ),
+      listen<{ stage: HandshakeStage }>("handshake_progress", (event) =>
+        setHandshakeStage(event.payload.stage)
+      ),
+      listen<AutoConnectProgress>("auto_connect", (event) =>
+        setAutoConnect(event.payload)
+      ),
+      listen<DisplayStats>("display_stats", (event) =>
+        setDisplayStats(event.payload)
+      ),
+      listen<{ message: string; fatal: boolean }>("stream_error", (event) =>
+        console.error("Stream error:", event.payload.message)
+      ),
+      // Frames stopped with the source still connected, or came back
+      listen<{ stalled: boolean; waited_ms: number }>(
+        "stream_stalled",
+        (event) =>
+          setStalledMs(event.payload.stalled ? event.payload.waited_ms : null)
+      ),
+      listen<PlaybackPosition>("playback_position", (event) =>
+        setPlaybackPosition(event.payload)
+      ),
+      // The native window, while open, is the one that goes fullscreen
+      listen<{ open: boolean; fullscreen: boolean }>(
+        "native_window",
+        (event) => {
+          setNativeWindowOpen(event.payload.open);
+          setIsFullscreen(event.payload.fullscreen);
+        }
+      ),
+    ];
+
+    return () => {
+      unlisteners.forEach((unlisten) => unlisten.then((fn) => fn()));
+    };
   }, []);
 
   // Listen for display frames
@@ -65,31 +134,6 @@ function App() {
     return () => window.removeEventListener("keydown", handleKeyDown);
   }, [isFullscreen]);
 
-  // Poll stats while receiving
-  useEffect(() => {
-    if (connectionStatus === "receiving") {
-      statsIntervalRef.current = window.setInterval(async () => {
-        try {
-          const stats = await invoke<DisplayStats>("get_display_stats");
-          setDisplayStats(stats);
-        } catch (e) {
-          console.error("Failed to get stats:", e);
-        }
-      }, 500);
-    } else {
-      if (statsIntervalRef.current) {
-        clearInterval(statsIntervalRef.current);
-        statsIntervalRef.current = null;
-      }
-    }
-
-    return () => {
-      if (statsIntervalRef.current) {
-        clearInterval(statsIntervalRef.current);
-      }
-    };
-  }, [connectionStatus]);
-
   const handleWaitForConnection = useCallback(async () => {
     try {
       setConnectionStatus("waiting");
@@ -106,7 +150,8 @@ function App() {
         handleToggleFullscreen();
       }
     } catch (e) {
-      console.error("Connection failed:", e);
+      const error = e as CommandError;
+      console.error(`Connection failed (${error.kind}):`, error.message);
       setConnectionStatus("error");
     }
   }, [settings.auto_fullscreen]);
@@ -123,6 +168,45 @@ function App() {
     }
   }, []);
 
+  // Files dropped on the window are played by the backend
+  const handleStopPlayback = useCallback(async () => {
+    try {
+      await invoke("stop_display");
+      setPlaybackPosition(null);
+      setDisplayFrame(null);
+    } catch (e) {
+      console.error("Stop playback failed:", e);
+    }
+  }, []);
+
+  const handleTogglePause = useCallback(async () => {
+    try {
+      await invoke(
+        playbackPosition?.paused ? "resume_playback" : "pause_playback"
+      );
+    } catch (e) {
+      console.error("Playback control failed:", e);
+    }
+  }, [playbackPosition?.paused]);
+
+  const handleStep = useCallback(async () => {
+    try {
+      await invoke("step_playback");
+    } catch (e) {
+      console.error("Playback step failed:", e);
+    }
+  }, []);
+
+  const handleToggleLoop = useCallback(async () => {
+    try {
+      await invoke("set_playback_loop", {
+        looping: !playbackPosition?.looping,
+      });
+    } catch (e) {
+      console.error("Playback loop toggle failed:", e);
+    }
+  }, [playbackPosition?.looping]);
+
   const handleToggleFullscreen = useCallback(async () => {
     try {
       const newState = await invoke<boolean>("toggle_fullscreen");
@@ -132,6 +216,23 @@ function App() {
     }
   }, []);
 
+  const handleExportReport = useCallback(async () => {
+    try {
+      const path = await save({
+        defaultPath: "serialwarp-session.csv",
+        filters: [
+          { name: "CSV", extensions: ["csv"] },
+          { name: "JSON Lines", extensions: ["jsonl", "json"] },
+        ],
+      });
+      if (path) {
+        await invoke<number>("export_session_report", { path });
+      }
+    } catch (e) {
+      console.error("Session report export failed:", e);
+    }
+  }, []);
+
   const handleSaveSettings = useCallback(async (newSettings: AppSettings) => {
     try {
       await invoke("save_settings", { settings: newSettings });
@@ -144,16 +245,30 @@ function App() {
   const getStatusText = () => {
     switch (connectionStatus) {
       case "disconnected":
+        if (settings.auto_wait && autoConnect?.stage === "waiting-for-device") {
+          return "Waiting for cable...";
+        }
         return "Disconnected";
       case "waiting":
         return "Waiting for Mac...";
       case "connecting":
-        return "Connecting...";
+        return handshakeStage === "negotiating"
+          ? "Negotiating stream..."
+          : "Waiting for HELLO from Mac...";
       case "connected":
         return "Connected";
       case "receiving":
         return "Receiving from Mac";
+      case "playing":
+        return playbackPosition
+          ? `Playing file (${playbackPosition.frame}/${playbackPosition.total_frames})`
+          : "Playing file";
       case "error":
+        if (settings.auto_wait && autoConnect?.stage === "failed") {
+          return `Connection Error, retrying in ${Math.round(
+            autoConnect.retry_in_ms / 1000
+          )}s`;
+        }
         return "Connection Error";
       default:
         return "Unknown";
@@ -165,6 +280,7 @@ function App() {
       case "connected":
         return "connected";
       case "receiving":
+      case "playing":
         return "receiving";
       case "waiting":
         return "waiting";
@@ -173,19 +289,21 @@ function App() {
     }
   };
 
-  const isReceiving = connectionStatus === "receiving";
+  const isPlaying = connectionStatus === "playing";
+  const isReceiving = connectionStatus === "receiving" || isPlaying;
   const isConnected =
     connectionStatus === "connected" || connectionStatus === "receiving";
   const isWaiting = connectionStatus === "waiting";
 
-  // Fullscreen mode - just show the video
-  if (isFullscreen) {
+  // Fullscreen mode - just show the video, unless it's in the native window
+  if (isFullscreen && !nativeWindowOpen) {
     return (
       <div className="fullscreen-mode">
         <VideoDisplay
           frame={displayFrame}
           params={params}
           isReceiving={isReceiving}
+          stalledMs={stalledMs}
         />
       </div>
     );
@@ -200,6 +318,8 @@ function App() {
             frame={displayFrame}
             params={params}
             isReceiving={isReceiving}
+            inNativeWindow={nativeWindowOpen}
+            stalledMs={stalledMs}
           />
         </CardContent>
       </Card>
@@ -213,6 +333,12 @@ function App() {
             Resolution: {params.width}x{params.height} @ {params.fps}fps
           </span>
         )}
+        {params?.peer_host_name && (
+          <span className="text-muted-foreground ml-4">
+            Source: {params.peer_host_name}
+            {params.peer_app_version && ` (${params.peer_app_version})`}
+          </span>
+        )}
       </div>
 
       {/* Stats */}
@@ -222,7 +348,12 @@ function App() {
           <span>|</span>
           <span>Decode: {formatLatency(displayStats.decode_time_ms)}</span>
           <span>|</span>
-          <span>Latency: {formatLatency(displayStats.latency_ms)}</span>
+          <span>
+            Latency: {formatLatency(displayStats.latency_ms)} (p95{" "}
+            {formatLatency(displayStats.latency_p95_ms)})
+          </span>
+          <span>|</span>
+          <span>Link: {formatBitrate(displayStats.link_bitrate_bps)}</span>
           <span>|</span>
           <span>{formatDuration(displayStats.elapsed_seconds)}</span>
         </div>
@@ -230,7 +361,22 @@ function App() {
 
       {/* Action Buttons */}
       <div className="flex items-center justify-center gap-4">
-        {!isConnected && !isWaiting ? (
+        {isPlaying ? (
+          <>
+            <Button onClick={handleTogglePause}>
+              {playbackPosition?.paused ? "Play" : "Pause"}
+            </Button>
+            <Button variant="outline" onClick={handleStep}>
+              Step
+            </Button>
+            <Button variant="outline" onClick={handleToggleLoop}>
+              Loop: {playbackPosition?.looping ? "On" : "Off"}
+            </Button>
+            <Button variant="destructive" onClick={handleStopPlayback}>
+              Stop
+            </Button>
+          </>
+        ) : !isConnected && !isWaiting ? (
           <Button onClick={handleWaitForConnection}>Wait for Connection</Button>
         ) : isWaiting ? (
           <Button variant="outline" disabled>
@@ -241,6 +387,9 @@ function App() {
             <Button onClick={handleToggleFullscreen}>
               Fullscreen (F11)
             </Button>
+            <Button variant="outline" onClick={handleExportReport}>
+              Export Report
+            </Button>
             <Button variant="destructive" onClick={handleDisconnect}>
               Disconnect
             </Button>
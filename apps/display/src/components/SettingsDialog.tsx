@@ -3,6 +3,7 @@ import { check } from "@tauri-apps/plugin-updater";
 import { ask } from "@tauri-apps/plugin-dialog";
 import { relaunch } from "@tauri-apps/plugin-process";
 import { getVersion } from "@tauri-apps/api/app";
+import { invoke } from "@tauri-apps/api/core";
 import {
   Dialog,
   DialogContent,
@@ -13,7 +14,12 @@ import {
 import { Button } from "./ui/button";
 import { Select } from "./ui/select";
 import { Label } from "./ui/label";
-import { AppSettings } from "../hooks/useStore";
+import {
+  AppSettings,
+  CommandError,
+  PresentationMode,
+  TransportKind,
+} from "../hooks/useStore";
 
 interface SettingsDialogProps {
   open: boolean;
@@ -34,6 +40,25 @@ const CREDITS_OPTIONS = [
   { value: "8", label: "8 (Higher throughput)" },
 ];
 
+const PRESENTATION_OPTIONS = [
+  { value: "webview", label: "In the app (Default)" },
+  { value: "native", label: "Native window (Full size, lower latency)" },
+];
+
+const TRANSPORT_OPTIONS = [
+  { value: "usb", label: "USB cable (Default)" },
+  { value: "tcp", label: "Network (TCP)" },
+  { value: "mock", label: "Test pattern (No device needed)" },
+];
+
+const LOG_LEVEL_OPTIONS = [
+  { value: "error", label: "Errors only" },
+  { value: "warn", label: "Warnings" },
+  { value: "info", label: "Info (Default)" },
+  { value: "debug", label: "Debug" },
+  { value: "trace", label: "Trace (Very verbose)" },
+];
+
 export function SettingsDialog({
   open,
   onOpenChange,
@@ -45,6 +70,14 @@ export function SettingsDialog({
   const [updateStatus, setUpdateStatus] = useState<"idle" | "checking" | "available" | "downloading" | "uptodate" | "error">("idle");
   const [updateError, setUpdateError] = useState<string | null>(null);
   const [updateVersion, setUpdateVersion] = useState<string | null>(null);
+  const [logPath, setLogPath] = useState<string | null>(null);
+  const [exportedLogs, setExportedLogs] = useState<string | null>(null);
+  const [logError, setLogError] = useState<string | null>(null);
+  // Native windows aren't available everywhere; the backend says which are
+  const [presentationModes, setPresentationModes] = useState<PresentationMode[]>([
+    "webview",
+    "native",
+  ]);
 
   useEffect(() => {
     setLocalSettings(settings);
@@ -52,8 +85,22 @@ export function SettingsDialog({
 
   useEffect(() => {
     getVersion().then(setCurrentVersion).catch(console.error);
+    invoke<string | null>("get_log_path").then(setLogPath).catch(console.error);
+    invoke<PresentationMode[]>("get_presentation_modes")
+      .then(setPresentationModes)
+      .catch(console.error);
   }, []);
 
+  const handleExportLogs = async () => {
+    setLogError(null);
+    try {
+      setExportedLogs(await invoke<string>("export_logs"));
+    } catch (error) {
+      console.error("Log export failed:", error);
+      setLogError((error as CommandError).message);
+    }
+  };
+
   const handleCheckForUpdates = async () => {
     setUpdateStatus("checking");
     setUpdateError(null);
@@ -157,6 +204,61 @@ export function SettingsDialog({
             </p>
           </div>
 
+          <div className="space-y-2">
+            <Label htmlFor="transport">Connect Over</Label>
+            <Select
+              id="transport"
+              options={TRANSPORT_OPTIONS}
+              value={localSettings.transport}
+              onChange={(e) =>
+                setLocalSettings({
+                  ...localSettings,
+                  transport: e.target.value as TransportKind,
+                })
+              }
+            />
+            {localSettings.transport === "tcp" && (
+              <input
+                id="tcp_address"
+                type="text"
+                value={localSettings.tcp_address}
+                onChange={(e) =>
+                  setLocalSettings({
+                    ...localSettings,
+                    tcp_address: e.target.value,
+                  })
+                }
+                placeholder="0.0.0.0:7800"
+                className="flex h-9 w-full rounded-md border border-input bg-transparent px-3 py-1 text-sm shadow-sm"
+              />
+            )}
+            <p className="text-xs text-muted-foreground">
+              {localSettings.transport === "tcp"
+                ? "Address to listen on for the source"
+                : "Applies from the next connection"}
+            </p>
+          </div>
+
+          <div className="space-y-2">
+            <Label htmlFor="presentation">Show Video</Label>
+            <Select
+              id="presentation"
+              options={PRESENTATION_OPTIONS.filter((option) =>
+                presentationModes.includes(option.value as PresentationMode)
+              )}
+              value={localSettings.presentation}
+              onChange={(e) =>
+                setLocalSettings({
+                  ...localSettings,
+                  presentation: e.target.value as PresentationMode,
+                })
+              }
+            />
+            <p className="text-xs text-muted-foreground">
+              Applies from the next connection or file
+            </p>
+          </div>
+
           <div className="flex items-center gap-2">
             <input
               type="checkbox"
@@ -173,6 +275,22 @@ export function SettingsDialog({
             <Label htmlFor="auto_fullscreen">Auto-fullscreen on connect</Label>
           </div>
 
+          <div className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              id="auto_wait"
+              checked={localSettings.auto_wait}
+              onChange={(e) =>
+                setLocalSettings({
+                  ...localSettings,
+                  auto_wait: e.target.checked,
+                })
+              }
+              className="h-4 w-4 rounded border-input"
+            />
+            <Label htmlFor="auto_wait">Connect when the cable is plugged in (USB only)</Label>
+          </div>
+
           <div className="flex items-center gap-2">
             <input
               type="checkbox"
@@ -189,6 +307,66 @@ export function SettingsDialog({
             <Label htmlFor="vsync">Enable VSync</Label>
           </div>
 
+          <div className="flex items-center gap-2">
+            <input
+              type="checkbox"
+              id="trace_frames"
+              checked={localSettings.trace_frames > 0}
+              onChange={(e) =>
+                setLocalSettings({
+                  ...localSettings,
+                  trace_frames: e.target.checked ? 60 : 0,
+                })
+              }
+              className="h-4 w-4 rounded border-input"
+            />
+            <Label htmlFor="trace_frames">Log per-frame timings (one frame in 60)</Label>
+          </div>
+
+          <div className="space-y-2">
+            <Label htmlFor="log_level">Log Level</Label>
+            <Select
+              id="log_level"
+              options={LOG_LEVEL_OPTIONS}
+              value={localSettings.log_level}
+              onChange={(e) =>
+                setLocalSettings({
+                  ...localSettings,
+                  log_level: e.target.value,
+                })
+              }
+            />
+            <p className="text-xs text-muted-foreground">
+              How much is written to the log file
+            </p>
+          </div>
+
+          {/* Logs Section */}
+          <div className="border-t pt-4 mt-4">
+            <div className="flex items-center justify-between">
+              <div className="min-w-0">
+                <Label>Logs</Label>
+                <p className="text-xs text-muted-foreground break-all">
+                  {logPath ?? "Not writing a log file"}
+                </p>
+                {exportedLogs && (
+                  <p className="text-xs text-green-600 break-all">
+                    Saved to {exportedLogs}
+                  </p>
+                )}
+                {logError && <p className="text-xs text-red-600">{logError}</p>}
+              </div>
+              <Button
+                variant="outline"
+                size="sm"
+                onClick={handleExportLogs}
+                disabled={!logPath}
+              >
+                Export Logs
+              </Button>
+            </div>
+          </div>
+
           {/* Updates Section */}
           <div className="border-t pt-4 mt-4">
             <div className="flex items-center justify-between">
@@ -6,12 +6,19 @@ interface VideoDisplayProps {
   frame: string | null;
   params: NegotiatedParams | null;
   isReceiving: boolean;
+  // Frames are going to the native window, so there's nothing to draw here
+  inNativeWindow?: boolean;
+  // How long frames have been stopped with the source still connected, or
+  // null while they're arriving
+  stalledMs?: number | null;
 }
 
 export function VideoDisplay({
   frame,
   params,
   isReceiving,
+  inNativeWindow = false,
+  stalledMs = null,
 }: VideoDisplayProps) {
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const containerRef = useRef<HTMLDivElement>(null);
@@ -23,7 +30,7 @@ export function VideoDisplay({
     const ctx = canvas.getContext("2d");
     if (!ctx) return;
 
-    // Decode base64 image and draw to canvas
+    // Decode base64 JPEG preview and draw to canvas
     const img = new Image();
     img.onload = () => {
       // Clear canvas with black
@@ -79,11 +86,26 @@ export function VideoDisplay({
     <div
       ref={containerRef}
       className={cn(
-        "display-container w-full h-full flex items-center justify-center",
-        !isReceiving && "bg-muted"
+        "display-container relative w-full h-full flex items-center justify-center",
+        (!isReceiving || inNativeWindow) && "bg-muted"
       )}
     >
-      {isReceiving ? (
+      {isReceiving && stalledMs !== null && (
+        <div className="absolute top-2 left-1/2 -translate-x-1/2 rounded bg-destructive/90 px-3 py-1 text-sm text-destructive-foreground">
+          Stream stalled: no frames for {Math.round(stalledMs / 1000)}s, still
+          connected
+        </div>
+      )}
+      {isReceiving && inNativeWindow ? (
+        <div className="text-center text-muted-foreground">
+          <p className="text-lg">Showing video in its own window</p>
+          {params && (
+            <p className="text-sm mt-2">
+              {params.width}x{params.height} @ {params.fps}fps
+            </p>
+          )}
+        </div>
+      ) : isReceiving ? (
         <canvas
           ref={canvasRef}
           className="w-full h-full"
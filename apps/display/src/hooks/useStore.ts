@@ -6,13 +6,34 @@ export type ConnectionStatus =
   | "connecting"
   | "connected"
   | "receiving"
+  | "playing"
   | "error";
 
+export type HandshakeStage = "waiting-for-hello" | "negotiating" | "ready";
+
+export type AutoConnectProgress =
+  | { stage: "waiting-for-device" }
+  | { stage: "waiting"; delay_ms: number }
+  | { stage: "connecting"; attempt: number }
+  | { stage: "connected" }
+  | { stage: "failed"; error: string; retry_in_ms: number };
+
+// What a failed command rejects with; `kind` is one of serialwarp-core's
+// ErrorKind names, e.g. "timeout"
+export interface CommandError {
+  kind: string;
+  message: string;
+}
+
 export interface NegotiatedParams {
   width: number;
   height: number;
   fps: number;
   bitrate_bps: number;
+  // Who the source says it is; empty if it didn't say
+  peer_host_name: string;
+  peer_platform: string;
+  peer_app_version: string;
 }
 
 export interface DisplayStats {
@@ -21,23 +42,57 @@ export interface DisplayStats {
   frames_decoded: number;
   frames_displayed: number;
   frames_dropped: number;
+  frames_skipped: number;
   decode_time_ms: number;
   latency_ms: number;
+  latency_p95_ms: number;
+  latency_max_ms: number;
   elapsed_seconds: number;
+  link_bitrate_bps: number;
 }
 
+export interface PlaybackPosition {
+  frame: number;
+  total_frames: number;
+  paused: boolean;
+  looping: boolean;
+}
+
+// Where frames are shown: in the app, or in a native window over it
+export type PresentationMode = "webview" | "native";
+
+// How the source reaches the app: a USB cable, the network, or a test
+// pattern from inside the app
+export type TransportKind = "usb" | "tcp" | "mock";
+
 export interface AppSettings {
   auto_fullscreen: boolean;
   vsync: boolean;
   max_width: number;
   max_height: number;
   max_credits: number;
+  stats_interval_ms: number;
+  handshake_timeout_ms: number;
+  // No frame for this long while connected counts as a stall; 0 never does
+  stall_timeout_ms: number;
+  auto_wait: boolean;
+  trace_frames: number;
+  // Most verbose level written to the log file
+  log_level: string;
+  presentation: PresentationMode;
+  transport: TransportKind;
+  // Address listened on for a TCP source
+  tcp_address: string;
 }
 
 interface AppStore {
   // Connection state
   connectionStatus: ConnectionStatus;
   setConnectionStatus: (status: ConnectionStatus) => void;
+  handshakeStage: HandshakeStage | null;
+  setHandshakeStage: (stage: HandshakeStage | null) => void;
+  autoConnect: AutoConnectProgress | null;
+  setAutoConnect: (progress: AutoConnectProgress | null) => void;
 
   // Negotiated parameters
   params: NegotiatedParams | null;
@@ -47,14 +102,27 @@ interface AppStore {
   displayStats: DisplayStats;
   setDisplayStats: (stats: DisplayStats) => void;
 
+  // File playback
+  playbackPosition: PlaybackPosition | null;
+  setPlaybackPosition: (position: PlaybackPosition | null) => void;
+
   // Display frame (base64 encoded)
   displayFrame: string | null;
   setDisplayFrame: (frame: string | null) => void;
 
+  // How long frames have been stopped, while still connected, or null if
+  // they're arriving
+  stalledMs: number | null;
+  setStalledMs: (ms: number | null) => void;
+
   // Fullscreen
   isFullscreen: boolean;
   setIsFullscreen: (fs: boolean) => void;
 
+  // Whether frames are going to the native window instead of the web view
+  nativeWindowOpen: boolean;
+  setNativeWindowOpen: (open: boolean) => void;
+
   // Settings
   settings: AppSettings;
   setSettings: (settings: AppSettings) => void;
@@ -68,6 +136,10 @@ export const useStore = create<AppStore>((set) => ({
   // Connection state
   connectionStatus: "disconnected",
   setConnectionStatus: (status) => set({ connectionStatus: status }),
+  handshakeStage: null,
+  setHandshakeStage: (stage) => set({ handshakeStage: stage }),
+  autoConnect: null,
+  setAutoConnect: (progress) => set({ autoConnect: progress }),
 
   // Negotiated parameters
   params: null,
@@ -80,20 +152,36 @@ export const useStore = create<AppStore>((set) => ({
     frames_decoded: 0,
     frames_displayed: 0,
     frames_dropped: 0,
+    frames_skipped: 0,
     decode_time_ms: 0,
     latency_ms: 0,
+    latency_p95_ms: 0,
+    latency_max_ms: 0,
     elapsed_seconds: 0,
+    link_bitrate_bps: 0,
   },
   setDisplayStats: (stats) => set({ displayStats: stats }),
 
+  // File playback
+  playbackPosition: null,
+  setPlaybackPosition: (position) => set({ playbackPosition: position }),
+
   // Display frame
   displayFrame: null,
   setDisplayFrame: (frame) => set({ displayFrame: frame }),
 
+  // Stall
+  stalledMs: null,
+  setStalledMs: (ms) => set({ stalledMs: ms }),
+
   // Fullscreen
   isFullscreen: false,
   setIsFullscreen: (fs) => set({ isFullscreen: fs }),
 
+  // Native window
+  nativeWindowOpen: false,
+  setNativeWindowOpen: (open) => set({ nativeWindowOpen: open }),
+
   // Settings
   settings: {
     auto_fullscreen: false,
@@ -101,6 +189,15 @@ export const useStore = create<AppStore>((set) => ({
     max_width: 1920,
     max_height: 1080,
     max_credits: 4,
+    stats_interval_ms: 500,
+    handshake_timeout_ms: 10000,
+    stall_timeout_ms: 2000,
+    auto_wait: false,
+    trace_frames: 0,
+    log_level: "info",
+    presentation: "webview",
+    transport: "usb",
+    tcp_address: "0.0.0.0:7800",
   },
   setSettings: (settings) => set({ settings }),
 
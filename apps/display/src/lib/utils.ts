@@ -16,6 +16,10 @@ export function formatLatency(ms: number): string {
   return `${ms.toFixed(1)}ms`;
 }
 
+export function formatBitrate(bps: number): string {
+  return `${(bps / 1_000_000).toFixed(1)} Mbps`;
+}
+
 export function formatFps(fps: number): string {
   return `${fps.toFixed(1)} fps`;
 }
@@ -0,0 +1,107 @@
+//! Keyboard shortcuts from --keys and --bind
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serialwarp_core::{Context, ErrorKind, RenderError, SerialwarpError};
+use serialwarp_render::KeyBindings;
+
+/// The default shortcuts, changed by those in `file` and then by each of
+/// `assignments` in turn
+pub fn load(file: Option<&Path>, assignments: &[String]) -> Result<KeyBindings, SerialwarpError> {
+    let mut bindings = KeyBindings::default();
+    if let Some(path) = file {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        apply_toml(&mut bindings, &text)
+            .with_context(|| format!("Invalid key bindings in {}", path.display()))?;
+    }
+    for assignment in assignments {
+        bindings.assign(assignment).map_err(invalid)?;
+    }
+    bindings.check_conflicts().map_err(invalid)?;
+    Ok(bindings)
+}
+
+/// Apply a TOML table of `action = "keys"`, e.g. `quit = "ctrl+alt+q"`
+fn apply_toml(bindings: &mut KeyBindings, text: &str) -> Result<(), SerialwarpError> {
+    let table: BTreeMap<String, String> = toml::from_str(text)
+        .map_err(|e| SerialwarpError::with_source(ErrorKind::InvalidInput, e))?;
+    for (action, keys) in &table {
+        bindings
+            .bind(action.parse().map_err(invalid)?, keys)
+            .map_err(invalid)?;
+    }
+    Ok(())
+}
+
+fn invalid(e: RenderError) -> SerialwarpError {
+    SerialwarpError::with_source(ErrorKind::InvalidInput, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_render::KeyAction;
+
+    #[test]
+    fn test_toml() {
+        let mut bindings = KeyBindings::default();
+        apply_toml(
+            &mut bindings,
+            "quit = \"ctrl+shift+q\"\nscreenshot = \"none\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.get(KeyAction::Quit).unwrap().to_string(),
+            "ctrl+shift+q"
+        );
+        assert_eq!(bindings.get(KeyAction::Screenshot), None);
+        assert_eq!(
+            bindings.get(KeyAction::ToggleFullscreen),
+            KeyBindings::default().get(KeyAction::ToggleFullscreen)
+        );
+
+        for text in [
+            "quit = 1",
+            "jump = \"ctrl+j\"",
+            "quit = \"ctrl+\"",
+            "not toml",
+        ] {
+            let mut bindings = KeyBindings::default();
+            assert_eq!(
+                apply_toml(&mut bindings, text).unwrap_err().kind(),
+                ErrorKind::InvalidInput,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_bind_overrides_file() {
+        let path =
+            std::env::temp_dir().join(format!("serialwarp-keys-{}.toml", std::process::id()));
+        std::fs::write(&path, "fullscreen = \"f11\"\n").unwrap();
+
+        let bindings = load(Some(&path), &["fullscreen=ctrl+f11".to_string()]).unwrap();
+        assert_eq!(
+            bindings
+                .get(KeyAction::ToggleFullscreen)
+                .unwrap()
+                .to_string(),
+            "ctrl+f11"
+        );
+
+        // Moving a shortcut onto one that's taken fails, unless that one moves too
+        let taken = ["overlay=ctrl+alt+q".to_string()];
+        assert_eq!(
+            load(None, &taken).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        let moved = [taken[0].clone(), "quit=ctrl+alt+x".to_string()];
+        assert!(load(Some(&path), &moved).is_ok());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -3,24 +3,52 @@
 //! This binary runs on the PC side and receives video from the Mac source,
 //! decoding and rendering it to a window.
 
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
 use bytes::Bytes;
-use clap::Parser;
-use tracing::{error, info, warn};
+use clap::{Parser, ValueEnum};
+use tokio::sync::mpsc::error::TryRecvError;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::Directive;
 use tracing_subscriber::EnvFilter;
 
-/// Timeout for packet receive polling (allows event loop processing)
+mod keys;
+mod placeholder;
+mod playback;
+mod stream_view;
+mod window_state;
+
+/// Longest wait on the transport per loop iteration, so events keep being
+/// processed
 const PACKET_POLL_TIMEOUT: Duration = Duration::from_millis(10);
 
+/// How often the stats overlays are refreshed
+const OVERLAY_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
 use serialwarp_core::{
-    FrameAckPayload, FrameHeader, FrameReassembler, HelloPayload, Packet, PacketType,
-    StartAckPayload, StartPayload,
+    capabilities, error_codes, Context, DecodeEvent, DeviceRegistry, ErrorKind, ErrorPayload,
+    Packet, PacketType, SequenceGenerator, SerialwarpError, StopPayload, StopReason, StreamDump,
+    StreamDumpConfig, ThroughputMeter, TransportError, UsbDeviceId, VideoDecoder,
+};
+use serialwarp_decode::{Decoder, DecoderConfig, StreamRecorder};
+use serialwarp_pipeline::{
+    NegotiatedStream, SessionStats, SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig,
+    StatsExportConfig, StatsExporter, DEFAULT_LATENCY_WINDOW,
+};
+use serialwarp_render::{
+    KeyBindings, RenderBackend, RenderClosed, RenderEvent, RenderHost, RenderOpener,
+    RenderOverlayStats, RendererConfig, RendererHandle,
+};
+use serialwarp_transport::{
+    serial_link_bitrate_bps, split_shared, SerialTransport, Transport, UsbTransport,
+    DEFAULT_BAUD_RATE, DEFAULT_RECV_TIMEOUT,
 };
-use serialwarp_decode::{Decoder, DecoderConfig};
-use serialwarp_render::{Renderer, RendererConfig};
-use serialwarp_transport::{Transport, UsbTransport};
+
+use stream_view::StreamView;
 
 /// serialwarp sink - display video from Mac source
 #[derive(Parser, Debug)]
@@ -39,33 +67,229 @@ struct Args {
     #[arg(short, long)]
     fullscreen: bool,
 
+    /// While waiting for the source, keep its last frame on screen, dimmed,
+    /// under the message rather than clearing the window
+    #[arg(long)]
+    keep_last_frame: bool,
+
+    /// Display (monitor) index to show the stream on
+    #[arg(long, value_name = "N")]
+    display: Option<usize>,
+
+    /// Read keyboard shortcuts from this TOML file, one `action = "keys"`
+    /// per line, e.g. `fullscreen = "ctrl+alt+f"` or `quit = "none"`
+    #[arg(long, value_name = "FILE")]
+    keys: Option<PathBuf>,
+
+    /// Set a keyboard shortcut, e.g. quit=ctrl+alt+q, or turn one off with
+    /// e.g. screenshot=none; may be repeated, and overrides --keys. Actions:
+    /// quit, fullscreen, overlay, screenshot, scaling, input-capture
+    #[arg(long, value_name = "ACTION=KEYS")]
+    bind: Vec<String>,
+
     /// Initial flow control credits
     #[arg(long, default_value_t = 8)]
     credits: u16,
+
+    /// Offer to leave the CRC off packets after the handshake, for links
+    /// that already check their data; used only if the source offers it too
+    #[arg(long)]
+    no_crc: bool,
+
+    /// Credits to hold back from the source while frames wait to be shown,
+    /// slowing it down until they drain; 0 never holds any back. Only a
+    /// source that takes CREDIT_UPDATE has any held back.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    withhold_credits: u16,
+
+    /// Frames to acknowledge per FRAME_ACK; keep it below --credits
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    ack_batch: usize,
+
+    /// Once more than N frames wait to be shown, skip non-key frames and
+    /// ask the source for a keyframe to catch up; 0 never skips
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    catch_up_backlog: usize,
+
+    /// Save the received H.264 stream to this file (.mp4 or .mkv); with
+    /// several streams, the first
+    #[arg(long, value_name = "PATH")]
+    record_file: Option<PathBuf>,
+
+    /// Append the received H.264 stream, or the first of several, as-is to
+    /// this Annex B file, with a JSON index of its frames alongside, for
+    /// debugging the encoder
+    #[arg(long, value_name = "PATH")]
+    dump_stream: Option<PathBuf>,
+
+    /// Start a new --dump-stream file at the next keyframe once this many
+    /// bytes are written
+    #[arg(long, value_name = "BYTES", requires = "dump_stream")]
+    dump_max_bytes: Option<u64>,
+
+    /// Play an Annex B .h264 file instead of receiving over USB
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// Restart playback of --input when it reaches the end
+    #[arg(long = "loop", requires = "input")]
+    loop_input: bool,
+
+    /// Frame rate for --input playback
+    #[arg(long, default_value_t = 60)]
+    fps: u32,
+
+    /// Receive over this serial port (e.g. /dev/ttyUSB0) instead of USB
+    #[arg(long, value_name = "PORT", conflicts_with = "input")]
+    serial: Option<String>,
+
+    /// Baud rate for --serial
+    #[arg(long, default_value_t = DEFAULT_BAUD_RATE, requires = "serial")]
+    baud: u32,
+
+    /// Also accept this USB device (hex VID:PID, e.g. 067b:27a2); may be
+    /// repeated, and adds to SERIALWARP_EXTRA_DEVICES
+    #[arg(long, value_name = "VID:PID")]
+    extra_device: Vec<UsbDeviceId>,
+
+    /// Graphics stack to draw with; wgpu needs the sink built with its
+    /// `wgpu` feature
+    #[arg(long, value_enum, default_value_t = RendererChoice::Sdl)]
+    renderer: RendererChoice,
+
+    /// Serve Prometheus metrics over HTTP at this address (e.g.
+    /// 0.0.0.0:9100); needs the sink built with its `metrics` feature
+    #[arg(long, value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Log stage timings for every Nth frame at debug level, under the
+    /// `serialwarp::frames` target; 0 logs none
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    trace_frames: u32,
+
+    /// Log glass-to-glass latency (average, p95, max, and where the time
+    /// went) of the first stream over every SECS seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    latency_report: Option<u64>,
+
+    /// Write the first stream's statistics every second to this file, as
+    /// CSV, or as one JSON object per line if it ends in .json or .jsonl
+    #[arg(long, value_name = "PATH")]
+    stats_out: Option<PathBuf>,
+
+    /// Report the stream stalled, and ask the source for a keyframe, after
+    /// SECS seconds without a frame while still connected; 0 doesn't watch
+    /// for stalls
+    #[arg(long, value_name = "SECS", default_value_t = 2)]
+    stall_timeout: u64,
+
+    /// Let a source that reconnects within SECS seconds resume its session
+    /// without a new START; 0 always does the full handshake. Only a
+    /// single stream is resumed.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    resume_expiry: u64,
+
+    /// Show up to N displays from a source that streams several, each in a
+    /// window of its own; closing any of them quits. The wgpu renderer
+    /// shows only one.
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    #[arg(value_parser = clap::value_parser!(u8).range(1..=16))]
+    max_streams: u8,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("serialwarp=info".parse()?))
-        .init();
+/// Values for --renderer
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RendererChoice {
+    Sdl,
+    Wgpu,
+}
+
+impl From<RendererChoice> for RenderBackend {
+    fn from(choice: RendererChoice) -> Self {
+        match choice {
+            RendererChoice::Sdl => RenderBackend::Sdl,
+            RendererChoice::Wgpu => RenderBackend::Wgpu,
+        }
+    }
+}
 
+fn main() -> Result<(), SerialwarpError> {
     let args = Args::parse();
+    let runtime = tokio::runtime::Runtime::new().context("Failed to start async runtime")?;
+
+    // Windows are opened and drawn here on the main thread, as macOS
+    // requires, while the sink runs on a thread of its own
+    let (render_host, renderers) = RenderHost::new();
+    let sink = std::thread::Builder::new()
+        .name("serialwarp-sink".to_string())
+        .spawn(move || runtime.block_on(run(args, renderers)))
+        .context("Failed to start sink thread")?;
+    render_host.run();
+    sink.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+async fn run(args: Args, renderers: RenderOpener) -> Result<(), SerialwarpError> {
+    // Initialize logging
+    let mut filter =
+        EnvFilter::from_default_env().add_directive(parse_directive("serialwarp=info")?);
+    if args.trace_frames > 0 {
+        filter = filter.add_directive(parse_directive("serialwarp::frames=debug")?);
+    }
+    tracing_subscriber::fmt().with_env_filter(filter).init();
 
     info!("serialwarp-sink starting");
+
+    if let Some(addr) = args.metrics_listen {
+        serve_metrics(addr)?;
+    }
+
+    // Checked before waiting on the source, so a typo fails at once
+    let key_bindings = keys::load(args.keys.as_deref(), &args.bind)?;
+
+    if let Some(input) = &args.input {
+        return playback::run_playback(input, &args, key_bindings, &renderers).await;
+    }
+
     info!(
         "Max resolution: {}x{}, credits: {}",
         args.max_width, args.max_height, args.credits
     );
 
-    // Open USB transport (wait for connection)
-    info!("Waiting for USB connection...");
-    let transport = UsbTransport::open().await.context("Failed to open USB transport")?;
-    info!("USB transport connected");
+    let transport: Arc<dyn Transport> = match &args.serial {
+        Some(port) => {
+            let transport = SerialTransport::open(port, args.baud)
+                .with_context(|| format!("Failed to open serial port {}", port))?;
+            info!(
+                "Serial link carries about {:.1} Mbps; the source must stream well below that",
+                serial_link_bitrate_bps(args.baud) as f64 / 1_000_000.0
+            );
+            Arc::new(transport)
+        }
+        None => {
+            let mut registry =
+                DeviceRegistry::from_env().context("Invalid SERIALWARP_EXTRA_DEVICES")?;
+            for device in &args.extra_device {
+                registry.register(*device);
+            }
+
+            // Open USB transport (wait for connection)
+            info!("Waiting for USB connection...");
+            let transport = match UsbTransport::open_from(&registry, DEFAULT_RECV_TIMEOUT).await {
+                Ok(transport) => transport,
+                Err(e) => {
+                    if let Some(hint) = e.hint() {
+                        error!("{}", hint);
+                    }
+                    return Err(SerialwarpError::from(e).context("Failed to open USB transport"));
+                }
+            };
+            info!("USB transport connected");
+            Arc::new(transport)
+        }
+    };
 
     // Run main loop
-    if let Err(e) = run_sink(transport, &args).await {
+    if let Err(e) = run_sink(transport, &args, key_bindings, &renderers).await {
         error!("Sink error: {:?}", e);
         return Err(e);
     }
@@ -73,186 +297,419 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn run_sink<T: Transport>(transport: T, args: &Args) -> Result<()> {
-    let mut sequence = 0u32;
+/// Parse a log filter directive, e.g. `serialwarp=info`
+fn parse_directive(directive: &str) -> Result<Directive, SerialwarpError> {
+    directive
+        .parse()
+        .map_err(|e| SerialwarpError::with_source(ErrorKind::Other, e))
+}
 
-    // Step 1: Receive HELLO
-    info!("Waiting for HELLO...");
-    let hello = receive_packet(&transport).await?;
-    if hello.packet_type() != PacketType::Hello {
-        anyhow::bail!(
-            "Expected HELLO, got {:?}",
-            hello.packet_type()
-        );
-    }
+/// Export the pipeline's metrics for Prometheus to scrape at `addr`
+#[cfg(feature = "metrics")]
+fn serve_metrics(addr: SocketAddr) -> Result<(), SerialwarpError> {
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+        .map_err(|e| SerialwarpError::with_source(ErrorKind::Other, e))
+        .with_context(|| format!("Failed to serve metrics on {}", addr))?;
+    serialwarp_core::metrics::describe();
+    info!("Serving metrics on http://{}/metrics", addr);
+    Ok(())
+}
 
-    let hello_payload = HelloPayload::parse(&hello.payload)?;
-    info!(
-        "Received HELLO: max {}x{} @ {}fps",
-        hello_payload.max_width,
-        hello_payload.max_height,
-        hello_payload.max_fps()
-    );
+/// Export the pipeline's metrics for Prometheus to scrape at `addr`
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(_addr: SocketAddr) -> Result<(), SerialwarpError> {
+    Err(SerialwarpError::new(
+        ErrorKind::InvalidInput,
+        "--metrics-listen needs the sink built with its `metrics` feature",
+    ))
+}
 
-    // Step 2: Send HELLO_ACK
-    let ack_payload = HelloPayload::new(
-        1, // software version
-        args.max_width,
-        args.max_height,
-        60,
-        0x03, // capabilities: hidpi + audio
-    );
-    let ack = Packet::new(
-        PacketType::HelloAck,
-        0,
-        sequence,
-        ack_payload.to_bytes(),
-    );
-    sequence += 1;
-    transport.send(ack.to_bytes()).await?;
-    info!("Sent HELLO_ACK");
-
-    // Step 3: Receive START
-    info!("Waiting for START...");
-    let start = receive_packet(&transport).await?;
-    if start.packet_type() != PacketType::Start {
-        anyhow::bail!("Expected START, got {:?}", start.packet_type());
+async fn run_sink(
+    transport: Arc<dyn Transport>,
+    args: &Args,
+    key_bindings: KeyBindings,
+    renderers: &RenderOpener,
+) -> Result<(), SerialwarpError> {
+    // Step 1: Handshake
+    let mut handshake = SinkHandshake {
+        max_width: args.max_width,
+        max_height: args.max_height,
+        initial_credits: args.credits,
+        resume_expiry: (args.resume_expiry > 0).then(|| Duration::from_secs(args.resume_expiry)),
+        // winit only has one event loop, and so one window, per process
+        max_streams: match args.renderer {
+            RendererChoice::Sdl => args.max_streams,
+            RendererChoice::Wgpu => 1,
+        },
+        ..Default::default()
+    };
+    if args.no_crc {
+        handshake.capabilities |= capabilities::NO_CRC;
     }
-
-    let start_payload = StartPayload::parse(&start.payload)?;
-    info!(
-        "Received START: {}x{} @ {}fps, {} bps",
-        start_payload.width,
-        start_payload.height,
-        start_payload.fps(),
-        start_payload.bitrate_bps
-    );
-
-    // Step 4: Send START_ACK
-    let start_ack_payload = StartAckPayload::ok(args.credits);
-    let start_ack = Packet::new(
-        PacketType::StartAck,
-        0,
-        sequence,
-        start_ack_payload.to_bytes(),
-    );
-    sequence += 1;
-    transport.send(start_ack.to_bytes()).await?;
-    info!("Sent START_ACK with {} credits", args.credits);
-
-    // Step 5: Create decoder
-    let decoder_config = DecoderConfig::default();
-    let mut decoder = Decoder::new(decoder_config).context("Failed to create decoder")?;
+    let negotiated = handshake.accept(&*transport, 0).await?;
+    info!("Connected to {}", negotiated.hello.identity);
+    let point_size = negotiated.point_size();
+    let start_payload = &negotiated.start;
+    let mut sequence = negotiated.sequence;
+
+    // Step 2: Create decoder
+    let decoder = create_decoder(&*transport, &mut sequence).await?;
     info!("Decoder initialized");
 
-    // Step 6: Create renderer
-    let renderer_config = RendererConfig {
-        title: format!(
-            "serialwarp - {}x{}",
-            start_payload.width, start_payload.height
-        ),
-        width: start_payload.width,
-        height: start_payload.height,
+    // Step 3: Create renderer, at the visible size rather than the coded one.
+    // The window is sized in points so a HiDPI stream shows at its source's
+    // size; its textures are still the frame's pixels.
+    let display_size = start_payload.display_size();
+    let mut renderer_config = RendererConfig {
+        title: format!("serialwarp - {}", display_size),
+        width: point_size.width,
+        height: point_size.height,
         fullscreen: args.fullscreen,
         vsync: true,
+        display_index: args.display,
+        ..renderer_config(args, &key_bindings)
     };
-    let mut renderer = Renderer::new(renderer_config).context("Failed to create renderer")?;
+    let mut window_saver = window_state::restore(&mut renderer_config);
+    // The renderer runs on the main thread, so presenting a frame overlaps
+    // with receiving and decoding the next
+    let renderer = open_renderer(&*transport, renderers, renderer_config, &mut sequence).await?;
     info!("Renderer initialized");
+    if let Ok(displays) = renderer.0.list_displays().await {
+        // Not `display`, which tracing's macros take as their `display` helper
+        for screen in displays {
+            info!(
+                "Display {}: {} ({}x{} @ {}Hz)",
+                screen.index, screen.name, screen.width, screen.height, screen.refresh_rate
+            );
+        }
+    }
+
+    // Optionally record the received stream
+    let mut recorder = match &args.record_file {
+        Some(path) => {
+            let recorder = StreamRecorder::start(path).context("Failed to start recorder")?;
+            info!("Recording stream to {}", path.display());
+            Some(recorder)
+        }
+        None => None,
+    };
+    let mut stream_dump = match &args.dump_stream {
+        Some(path) => {
+            let config = StreamDumpConfig {
+                max_file_bytes: args.dump_max_bytes,
+                ..StreamDumpConfig::new(path)
+            };
+            let dump = StreamDump::start(config)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            info!("Dumping stream to {}", path.display());
+            Some(dump)
+        }
+        None => None,
+    };
+
+    let mut stats_exporter = match &args.stats_out {
+        Some(path) => {
+            let exporter = StatsExporter::create(StatsExportConfig::new(path))
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            info!("Writing stream statistics to {}", path.display());
+            Some(exporter)
+        }
+        None => None,
+    };
 
-    // Step 7: Main receive loop
-    let mut reassembler = FrameReassembler::new();
-    let mut credits = args.credits;
-    let mut frame_number = 0u64;
+    // Ctrl-C ends the loop like a window close, so the recording is finalized
+    let interrupted = Arc::new(AtomicBool::new(false));
+    tokio::spawn({
+        let interrupted = interrupted.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                interrupted.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    // Step 4: Main receive loop. Each stream's pipeline reassembles,
+    // decodes, and acknowledges its frames; this loop paces and presents
+    // them. The first stream is recorded, dumped and reported on, and its
+    // window remembered.
+    // The loop below keeps the whole transport for receiving and for the
+    // handshake after a reconnect; the pipelines only send on their halves
+    let latency_report_interval = args.latency_report.map(Duration::from_secs);
+    let pipeline = SinkPipeline::new(
+        split_shared(transport.clone()),
+        decoder,
+        pipeline_config(args, &negotiated),
+        sequence,
+    );
+    let mut views = vec![StreamView::new(
+        0,
+        pipeline,
+        renderer,
+        start_payload,
+        negotiated.scale,
+    )];
+    let extra_views = open_extra_views(
+        &transport,
+        args,
+        &key_bindings,
+        renderers,
+        &negotiated,
+        views[0].pipeline.shared_sequence(),
+    );
+    views.extend(extra_views.await?);
+    let mut awaiting_reconnect = false;
+    let mut link_rx = ThroughputMeter::default();
+    let mut link_tx = ThroughputMeter::default();
+    let mut last_latency_report = Instant::now();
 
     info!("Starting main loop");
 
     loop {
-        // Process SDL events (quit on escape or window close)
-        if !renderer.process_events() {
+        // Handle window events (quit on the quit shortcut or any window
+        // closing)
+        let mut quit = interrupted.load(Ordering::Relaxed);
+        for view in &mut views {
+            while !quit {
+                match view.renderer.events().try_recv() {
+                    Ok(RenderEvent::Quit) | Err(TryRecvError::Disconnected) => quit = true,
+                    Ok(RenderEvent::PresentFailed(e)) => warn!("Render error: {:?}", e),
+                    Ok(RenderEvent::Key(_)) => {}
+                    Ok(RenderEvent::Shortcut(action)) => {
+                        info!("The {} shortcut isn't supported by the sink yet", action)
+                    }
+                    Ok(RenderEvent::WindowChanged(geometry)) => {
+                        if let (0, Some(saver)) = (view.stream_id, &mut window_saver) {
+                            saver.changed(geometry, Instant::now());
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+        }
+        if let Some(saver) = &mut window_saver {
+            saver.save_settled(Instant::now());
+        }
+        if quit {
             info!("Quit requested");
+            let stop = StopPayload::new(StopReason::UserRequested, false);
+            let _ = views[0]
+                .pipeline
+                .send_packet(PacketType::Stop, stop.to_bytes())
+                .await;
             break;
         }
 
-        // Try to receive a packet (non-blocking would be better, but for now we use timeout)
-        match tokio::time::timeout(
-            PACKET_POLL_TIMEOUT,
-            receive_packet(&transport),
-        )
-        .await
-        {
-            Ok(Ok(packet)) => {
-                match packet.packet_type() {
-                    PacketType::Frame => {
-                        // Parse frame header
-                        if packet.payload.len() < FrameHeader::SIZE {
-                            warn!("Frame payload too small");
-                            continue;
-                        }
+        // Present whatever frame is due in each window, or else say why
+        // there's none
+        for view in &mut views {
+            view.present_due(awaiting_reconnect).await;
+        }
+
+        // Refresh the stats overlays
+        let now = Instant::now();
+        if now.duration_since(views[0].overlay_window.start) >= OVERLAY_UPDATE_INTERVAL {
+            for view in &mut views {
+                view.refresh_overlay(now).await;
+            }
+
+            let link = transport.stats();
+            link_rx.sample(now, link.bytes_received);
+            link_tx.sample(now, link.bytes_sent);
+            debug!(
+                "Link: {:.2} Mbps in, {:.2} Mbps out, {} errors",
+                link_rx.bits_per_second() / 1e6,
+                link_tx.bits_per_second() / 1e6,
+                link.send_errors + link.recv_errors
+            );
+        }
+
+        if let Some(interval) = latency_report_interval {
+            if now.duration_since(last_latency_report) >= interval {
+                last_latency_report = now;
+                match views[0].pipeline.latency_report() {
+                    Some(report) => info!("{}", report),
+                    None => info!("No frames with a capture time presented"),
+                }
+            }
+        }
+
+        if let Some(exporter) = &mut stats_exporter {
+            let pipeline = &mut views[0].pipeline;
+            let stats = pipeline.stats();
+            let session = SessionStats::Sink {
+                stats: &stats,
+                latency: pipeline.latency_report(),
+                state: if awaiting_reconnect {
+                    "reconnecting"
+                } else if pipeline.is_stalled() {
+                    "stalled"
+                } else {
+                    "streaming"
+                },
+            };
+            if let Err(e) = exporter.sample(now, session) {
+                warn!("Stopped writing stream statistics: {}", e);
+                stats_exporter = None;
+            }
+        }
 
-                        let header = FrameHeader::parse(&packet.payload)?;
-                        let data = packet.payload[FrameHeader::SIZE..].to_vec();
-
-                        // Add segment to reassembler
-                        if let Some(complete_frame) = reassembler.add_segment(&header, data) {
-                            // Decode frame
-                            let start_time = std::time::Instant::now();
-                            match decoder.decode(&complete_frame.data, complete_frame.metadata.pts_us as i64) {
-                                Ok(decoded_frames) => {
-                                    let decode_time = start_time.elapsed();
-
-                                    for mut decoded in decoded_frames {
-                                        // Set frame number
-                                        decoded = serialwarp_core::DecodedFrame::new(
-                                            frame_number,
-                                            decoded.pts_us,
-                                            decoded.width,
-                                            decoded.height,
-                                            decoded.y_plane().iter()
-                                                .chain(decoded.u_plane().iter())
-                                                .chain(decoded.v_plane().iter())
-                                                .copied()
-                                                .collect(),
-                                        );
-
-                                        // Render frame
-                                        if let Err(e) = renderer.present(&decoded) {
-                                            warn!("Render error: {:?}", e);
-                                        }
-                                    }
-
-                                    // Send FRAME_ACK
-                                    credits = credits.saturating_add(1);
-                                    let ack_payload = FrameAckPayload::new(
-                                        header.frame_number,
-                                        decode_time.as_micros() as u32,
-                                        1, // Return 1 credit per frame
-                                    );
-                                    let ack = Packet::new(
-                                        PacketType::FrameAck,
-                                        0,
-                                        sequence,
-                                        ack_payload.to_bytes(),
-                                    );
-                                    sequence += 1;
-                                    if let Err(e) = transport.send(ack.to_bytes()).await {
-                                        warn!("Failed to send FRAME_ACK: {:?}", e);
-                                    }
-
-                                    frame_number += 1;
-                                }
-                                Err(e) => {
-                                    warn!("Decode error: {:?}", e);
-                                }
+        // Don't wait on the transport past the next frame's presentation time
+        let poll_timeout = views
+            .iter()
+            .filter_map(|view| view.pacer.next_deadline())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .map_or(PACKET_POLL_TIMEOUT, |until| until.min(PACKET_POLL_TIMEOUT));
+
+        // With a frame already due, only take data that has arrived
+        let received = if poll_timeout.is_zero() {
+            transport.try_recv().await
+        } else {
+            match transport.recv_timeout(poll_timeout).await {
+                Ok(data) => Ok(Some(data)),
+                Err(TransportError::Timeout { .. }) => Ok(None),
+                Err(e) => Err(e),
+            }
+        };
+
+        match received {
+            Ok(Some(data)) => {
+                let packet = match Packet::parse(&data) {
+                    Ok((packet, _)) => packet,
+                    Err(e) => {
+                        warn!("Invalid packet: {}", e);
+                        continue;
+                    }
+                };
+                // Each stream's packets go to its own pipeline, and replies
+                // go back tagged with the same stream
+                let stream_id = packet.stream_id();
+                let Some(index) = views.iter().position(|view| view.stream_id == stream_id) else {
+                    warn!("Packet for stream {}, which wasn't started", stream_id);
+                    continue;
+                };
+                let view = &mut views[index];
+                view.overlay_window.bytes_received += packet.payload.len() as u64;
+                view.pipeline.set_consumer_backlog(view.pacer.queued());
+                let packet = match view.pipeline.handle_packet(packet).await {
+                    Ok(SinkOutput::Frame {
+                        frame,
+                        pictures,
+                        decode_time,
+                        events,
+                    }) => {
+                        for event in events {
+                            let DecodeEvent::ResolutionChanged { new, .. } = event;
+                            // Frames queued at the old size are stale now
+                            view.pacer.reset();
+                            view.stream_size = (new.width, new.height);
+                            let points = new.to_points(view.scale);
+                            let resized =
+                                view.renderer.set_window_size(points.width, points.height);
+                            if let Err(e) = resized.await {
+                                warn!("Failed to resize window: {:?}", e);
+                            }
+                        }
+                        if view.stream_id == 0 {
+                            if let Some(recorder) = recorder.as_mut() {
+                                recorder.record(&frame, view.stream_size.0, view.stream_size.1);
+                            }
+                            if let Some(dump) = &stream_dump {
+                                dump.write(&frame);
                             }
                         }
+                        if pictures > 0 {
+                            view.overlay_window.decode_time += decode_time;
+                            view.overlay_window.frames_decoded += 1;
+                        }
+
+                        // Queue for paced presentation
+                        while let Some(decoded) = view.pipeline.next_decoded_frame() {
+                            view.pacer.push(decoded, Instant::now());
+                        }
+                        continue;
                     }
+                    Ok(SinkOutput::Segment) => continue,
+                    Ok(SinkOutput::Control(packet)) => packet,
+                    Err(e) => {
+                        warn!("Invalid frame packet: {}", e);
+                        continue;
+                    }
+                };
+
+                match packet.packet_type() {
                     PacketType::Stop => {
-                        info!("Received STOP");
+                        // A STOP that doesn't parse still stops the stream
+                        let stop = StopPayload::parse(&packet.payload).unwrap_or_else(|e| {
+                            warn!("Malformed STOP payload: {}", e);
+                            StopPayload::new(StopReason::UserRequested, false)
+                        });
+                        info!("Received STOP: {}", stop.reason);
                         // Send STOP_ACK
-                        let stop_ack = Packet::new(PacketType::StopAck, 0, sequence, Bytes::new());
-                        let _ = transport.send(stop_ack.to_bytes()).await;
-                        break;
+                        let _ = view
+                            .pipeline
+                            .send_packet(PacketType::StopAck, Bytes::new())
+                            .await;
+
+                        if !stop.reconnect_hint {
+                            break;
+                        }
+
+                        // Keep the windows open and wait for the source to
+                        // come back, holding its session for it to resume
+                        info!("Waiting for source to reconnect...");
+                        handshake.suspend();
+                        for view in &mut views {
+                            view.pacer.reset();
+                            view.pipeline.set_watchdog_suspended(true);
+                        }
+                        awaiting_reconnect = true;
+                    }
+                    PacketType::Hello if awaiting_reconnect => {
+                        let negotiated = handshake
+                            .accept_hello(&*transport, packet, views[0].pipeline.sequence())
+                            .await?;
+                        let mut sequence = negotiated.sequence;
+                        let decoder = create_decoder(&*transport, &mut sequence).await?;
+                        views[0].restart(decoder, &negotiated, sequence);
+                        // It may come back with a different set of displays
+                        for view in views.drain(1..) {
+                            view.close();
+                        }
+                        let extra_views = open_extra_views(
+                            &transport,
+                            args,
+                            &key_bindings,
+                            renderers,
+                            &negotiated,
+                            views[0].pipeline.shared_sequence(),
+                        );
+                        views.extend(extra_views.await?);
+                        awaiting_reconnect = false;
+                        if negotiated.resumed {
+                            info!("Source resumed its session: {}", negotiated.hello.identity);
+                        } else {
+                            info!("Source reconnected: {}", negotiated.hello.identity);
+                        }
                     }
+                    PacketType::Error => match ErrorPayload::parse(&packet.payload) {
+                        Ok(payload) if payload.fatal => {
+                            error!(
+                                "Source reported fatal error {}: {}",
+                                payload.code_name(),
+                                payload.message
+                            );
+                            break;
+                        }
+                        Ok(payload) => warn!(
+                            "Source reported error {}: {}",
+                            payload.code_name(),
+                            payload.message
+                        ),
+                        Err(e) => warn!("Dropping malformed ERROR: {}", e),
+                    },
                     PacketType::Ping => {
                         // Respond with PONG
                         let now_us = std::time::SystemTime::now()
@@ -263,21 +720,23 @@ async fn run_sink<T: Transport>(transport: T, args: &Args) -> Result<()> {
                             serialwarp_core::PingPayload::parse(&packet.payload)?.timestamp_us,
                             now_us,
                         );
-                        let pong = Packet::new(
-                            PacketType::Pong,
-                            0,
-                            sequence,
-                            pong_payload.to_bytes(),
-                        );
-                        sequence += 1;
-                        let _ = transport.send(pong.to_bytes()).await;
+                        let _ = view
+                            .pipeline
+                            .send_packet(PacketType::Pong, pong_payload.to_bytes())
+                            .await;
                     }
+                    // Answered or taken in by the pipeline, or answering its
+                    // stall PING
+                    PacketType::BandwidthProbe | PacketType::Pause | PacketType::Pong => {}
                     _ => {
                         warn!("Unexpected packet type: {:?}", packet.packet_type());
                     }
                 }
             }
-            Ok(Err(e)) => {
+            Ok(None) => {
+                // Nothing yet - continue loop to process events
+            }
+            Err(e) => {
                 // Transport error
                 if !transport.is_connected() {
                     error!("Transport disconnected");
@@ -285,21 +744,249 @@ async fn run_sink<T: Transport>(transport: T, args: &Args) -> Result<()> {
                 }
                 warn!("Receive error: {:?}", e);
             }
-            Err(_) => {
-                // Timeout - continue loop to process events
-            }
+        }
+
+        // Drop frames whose remaining segments never arrived, send acks
+        // that have waited long enough for their batch, and notice if
+        // frames have stopped coming
+        let now = Instant::now();
+        for view in &mut views {
+            view.pipeline.reap(now).await;
+            view.pipeline.flush_due_acks(now).await;
+            view.pipeline.check_stall(now).await;
+        }
+    }
+
+    for view in &views {
+        view.log_stats();
+    }
+
+    if let Some(exporter) = stats_exporter.take() {
+        let pipeline = &mut views[0].pipeline;
+        let stats = pipeline.stats();
+        let session = SessionStats::Sink {
+            stats: &stats,
+            latency: pipeline.latency_report(),
+            state: "stopped",
+        };
+        if let Err(e) = exporter.finish(Instant::now(), session) {
+            warn!("Failed to finish stream statistics: {}", e);
+        }
+    }
+
+    if let Some(recorder) = recorder.take() {
+        match recorder.finish() {
+            Ok(stats) => info!(
+                "Recording: {} frames written to {} file(s), {} dropped",
+                stats.frames_written, stats.files_written, stats.frames_dropped
+            ),
+            Err(e) => warn!("Failed to finalize recording: {}", e),
+        }
+    }
+
+    if let Some(dump) = stream_dump.take() {
+        match dump.finish() {
+            Ok(stats) => info!(
+                "Stream dump: {} frames written to {} file(s), {} dropped",
+                stats.frames_written, stats.files_written, stats.frames_dropped
+            ),
+            Err(e) => warn!("Failed to finish stream dump: {}", e),
         }
     }
 
     // Cleanup
     info!("Shutting down");
+    if let Some(saver) = &mut window_saver {
+        if let Ok(geometry) = views[0].renderer.geometry().await {
+            saver.save(geometry);
+        }
+    }
+    for view in views {
+        view.close();
+    }
     transport.close().await;
 
     Ok(())
 }
 
-async fn receive_packet<T: Transport>(transport: &T) -> Result<Packet> {
-    let data = transport.recv().await?;
-    let (packet, _consumed) = Packet::parse(&data)?;
-    Ok(packet)
+/// Renderer settings every stream's window shares
+fn renderer_config(args: &Args, key_bindings: &KeyBindings) -> RendererConfig {
+    RendererConfig {
+        backend: args.renderer.into(),
+        key_bindings: key_bindings.clone(),
+        keep_last_frame: args.keep_last_frame,
+        ..Default::default()
+    }
+}
+
+/// Pipeline settings for the first stream `negotiated` started; the others
+/// differ in their id, rate and size
+fn pipeline_config(args: &Args, negotiated: &NegotiatedStream) -> SinkPipelineConfig {
+    let latency_report_interval = args.latency_report.map(Duration::from_secs);
+    SinkPipelineConfig {
+        fps: negotiated.start.fps(),
+        initial_credits: args.credits,
+        max_withheld_credits: args.withhold_credits,
+        crc: negotiated.crc,
+        compression: negotiated.compression,
+        keyframe_requests: negotiated.keyframe_requests,
+        credit_updates: negotiated.credit_updates,
+        ack_batch: args.ack_batch,
+        display_size: Some(negotiated.start.display_size()),
+        catch_up_backlog: args.catch_up_backlog,
+        trace_frames: args.trace_frames,
+        latency_window: latency_report_interval.unwrap_or(DEFAULT_LATENCY_WINDOW),
+        stall_timeout: (args.stall_timeout > 0).then(|| Duration::from_secs(args.stall_timeout)),
+        ..Default::default()
+    }
+}
+
+/// Open a window, reporting failure to the source
+async fn open_renderer<T: Transport + ?Sized>(
+    transport: &T,
+    renderers: &RenderOpener,
+    config: RendererConfig,
+    sequence: &mut u32,
+) -> Result<(RendererHandle, RenderClosed), SerialwarpError> {
+    match renderers.open(config) {
+        Ok(opened) => Ok(opened),
+        Err(e) => {
+            let message = e.to_string();
+            send_error(transport, sequence, error_codes::INTERNAL, true, &message).await;
+            Err(e).context("Failed to create renderer")
+        }
+    }
+}
+
+/// Open a window and pipeline for each stream the source started after the
+/// first. Their pipelines number packets from the first's `sequence`, since
+/// they all send on the one transport.
+async fn open_extra_views(
+    transport: &Arc<dyn Transport>,
+    args: &Args,
+    key_bindings: &KeyBindings,
+    renderers: &RenderOpener,
+    negotiated: &NegotiatedStream,
+    sequence: Arc<SequenceGenerator>,
+) -> Result<Vec<StreamView>, SerialwarpError> {
+    let mut views = Vec::with_capacity(negotiated.extra_streams.len());
+    for extra in &negotiated.extra_streams {
+        // Only a failure sends anything here, and it ends the session
+        let mut next_sequence = sequence.peek();
+        let decoder = create_decoder(&**transport, &mut next_sequence).await?;
+        let display_size = extra.start.display_size();
+        let point_size = extra.point_size();
+        let renderer_config = RendererConfig {
+            title: format!("serialwarp - {} (stream {})", display_size, extra.stream_id),
+            width: point_size.width,
+            height: point_size.height,
+            // The windows are drawn one after another on the same thread,
+            // so only the first waits for vsync
+            vsync: false,
+            ..renderer_config(args, key_bindings)
+        };
+        let renderer =
+            open_renderer(&**transport, renderers, renderer_config, &mut next_sequence).await?;
+        let pipeline = SinkPipeline::sharing_sequence(
+            split_shared(transport.clone()),
+            decoder,
+            SinkPipelineConfig {
+                stream_id: extra.stream_id,
+                fps: extra.start.fps(),
+                display_size: Some(display_size),
+                ..pipeline_config(args, negotiated)
+            },
+            Arc::clone(&sequence),
+        );
+        info!("Showing stream {} in a window of its own", extra.stream_id);
+        views.push(StreamView::new(
+            extra.stream_id,
+            pipeline,
+            renderer,
+            &extra.start,
+            extra.scale,
+        ));
+    }
+    Ok(views)
+}
+
+/// Counters accumulated over one overlay refresh interval
+struct OverlayWindow {
+    start: Instant,
+    frames_presented: u64,
+    frames_decoded: u64,
+    decode_time: Duration,
+    /// Capture to presentation time of frames the source timestamped
+    latency: Duration,
+    latency_samples: u64,
+    bytes_received: u64,
+}
+
+impl OverlayWindow {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            frames_presented: 0,
+            frames_decoded: 0,
+            decode_time: Duration::ZERO,
+            latency: Duration::ZERO,
+            latency_samples: 0,
+            bytes_received: 0,
+        }
+    }
+
+    fn finish(
+        &self,
+        now: Instant,
+        frames_dropped: u64,
+        frames_queued: usize,
+    ) -> RenderOverlayStats {
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let decode_time_ms = if self.frames_decoded > 0 {
+            self.decode_time.as_secs_f64() * 1000.0 / self.frames_decoded as f64
+        } else {
+            0.0
+        };
+
+        RenderOverlayStats {
+            fps: self.frames_presented as f64 / elapsed,
+            decode_time_ms,
+            latency_ms: (self.latency_samples > 0)
+                .then(|| self.latency.as_secs_f64() * 1000.0 / self.latency_samples as f64),
+            bitrate_bps: self.bytes_received as f64 * 8.0 / elapsed,
+            frames_dropped,
+            frames_queued,
+        }
+    }
+}
+
+/// Create a decoder, reporting failure to the source
+async fn create_decoder<T: Transport + ?Sized>(
+    transport: &T,
+    sequence: &mut u32,
+) -> Result<Box<dyn VideoDecoder>, SerialwarpError> {
+    match Decoder::new(DecoderConfig::default()) {
+        Ok(decoder) => Ok(Box::new(decoder)),
+        Err(e) => {
+            let message = e.to_string();
+            send_error(transport, sequence, error_codes::DECODER_FAILED, true, &message).await;
+            Err(e).context("Failed to create decoder")
+        }
+    }
+}
+
+/// Notify the source of a failure. Delivery is best effort.
+async fn send_error<T: Transport + ?Sized>(
+    transport: &T,
+    sequence: &mut u32,
+    code: u16,
+    fatal: bool,
+    message: &str,
+) {
+    let payload = ErrorPayload::new(code, fatal, message);
+    let packet = Packet::new(PacketType::Error, 0, *sequence, payload.to_bytes());
+    *sequence += 1;
+    if let Err(e) = transport.send(packet.to_bytes()).await {
+        warn!("Failed to send ERROR: {:?}", e);
+    }
 }
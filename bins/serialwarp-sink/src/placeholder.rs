@@ -0,0 +1,113 @@
+//! What the window shows while the sink has no video for it
+
+use serialwarp_render::PlaceholderKind;
+
+/// What's keeping video off the screen, as the receive loop sees it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkState {
+    /// The source sent STOP with a reconnect hint and hasn't come back yet
+    pub awaiting_reconnect: bool,
+    /// No frame has been presented since the stream started or restarted
+    pub awaiting_first_frame: bool,
+    /// Frames have stopped with the source still connected
+    pub stalled: bool,
+}
+
+impl SinkState {
+    /// The placeholder for this state, or None while video is flowing
+    pub fn placeholder(&self) -> Option<PlaceholderKind> {
+        if self.awaiting_reconnect {
+            Some(PlaceholderKind::Reconnecting)
+        } else if self.awaiting_first_frame {
+            Some(PlaceholderKind::WaitingForConnection)
+        } else if self.stalled {
+            Some(PlaceholderKind::Paused)
+        } else {
+            None
+        }
+    }
+}
+
+/// The placeholder on screen, so each is sent to the renderer once
+#[derive(Debug, Default)]
+pub struct PlaceholderScreen {
+    shown: Option<PlaceholderKind>,
+}
+
+impl PlaceholderScreen {
+    /// The placeholder to present for `state`, if it isn't already up. A
+    /// state with none forgets the one shown, which the next frame replaces.
+    pub fn update(&mut self, state: SinkState) -> Option<PlaceholderKind> {
+        let wanted = state.placeholder();
+        if wanted == self.shown {
+            return None;
+        }
+        self.shown = wanted;
+        wanted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_placeholder() {
+        let streaming = SinkState::default();
+        assert_eq!(streaming.placeholder(), None);
+
+        let starting = SinkState {
+            awaiting_first_frame: true,
+            ..streaming
+        };
+        assert_eq!(
+            starting.placeholder(),
+            Some(PlaceholderKind::WaitingForConnection)
+        );
+
+        let stalled = SinkState {
+            stalled: true,
+            ..streaming
+        };
+        assert_eq!(stalled.placeholder(), Some(PlaceholderKind::Paused));
+
+        // The source being away says more than the frames having stopped
+        let away = SinkState {
+            awaiting_reconnect: true,
+            awaiting_first_frame: true,
+            stalled: true,
+        };
+        assert_eq!(away.placeholder(), Some(PlaceholderKind::Reconnecting));
+    }
+
+    #[test]
+    fn test_screen_presents_changes_once() {
+        let mut screen = PlaceholderScreen::default();
+        let starting = SinkState {
+            awaiting_first_frame: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            screen.update(starting),
+            Some(PlaceholderKind::WaitingForConnection)
+        );
+        assert_eq!(screen.update(starting), None);
+
+        // Video takes over, then stops
+        assert_eq!(screen.update(SinkState::default()), None);
+        let stalled = SinkState {
+            stalled: true,
+            ..Default::default()
+        };
+        assert_eq!(screen.update(stalled), Some(PlaceholderKind::Paused));
+        let away = SinkState {
+            awaiting_reconnect: true,
+            ..stalled
+        };
+        assert_eq!(screen.update(away), Some(PlaceholderKind::Reconnecting));
+
+        // After video again, the same placeholder is presented anew
+        assert_eq!(screen.update(SinkState::default()), None);
+        assert_eq!(screen.update(stalled), Some(PlaceholderKind::Paused));
+    }
+}
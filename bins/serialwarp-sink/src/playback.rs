@@ -0,0 +1,164 @@
+//! Offline playback of an H.264 elementary stream file, bypassing the transport
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serialwarp_core::{Context, ErrorKind, SerialwarpError};
+use serialwarp_decode::{split_access_units, Decoder, DecoderConfig};
+use serialwarp_render::{KeyBindings, Keycode, RenderEvent, RenderOpener, RendererConfig};
+use tokio::sync::mpsc::error::TryRecvError;
+use tracing::{info, warn};
+
+use crate::window_state;
+use crate::Args;
+
+/// How long to sleep between event polls while paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Decode and render an Annex B file at `args.fps`.
+///
+/// Space pauses playback and then advances one frame per press; Return resumes.
+pub async fn run_playback(
+    path: &Path,
+    args: &Args,
+    key_bindings: KeyBindings,
+    renderers: &RenderOpener,
+) -> Result<(), SerialwarpError> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let access_units = split_access_units(&data);
+    if access_units.is_empty() {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            format!("No H.264 access units found in {}", path.display()),
+        ));
+    }
+    info!(
+        "Playing {} access units from {} at {} fps",
+        access_units.len(),
+        path.display(),
+        args.fps
+    );
+
+    let mut decoder = Decoder::new(DecoderConfig::default()).context("Failed to create decoder")?;
+    let mut renderer_config = RendererConfig {
+        title: format!("serialwarp - {}", path.display()),
+        fullscreen: args.fullscreen,
+        display_index: args.display,
+        backend: args.renderer.into(),
+        key_bindings,
+        ..Default::default()
+    };
+    let mut window_saver = window_state::restore(&mut renderer_config);
+    let (mut renderer, render_closed) = renderers
+        .open(renderer_config)
+        .context("Failed to create renderer")?;
+
+    let frame_interval = Duration::from_secs_f64(1.0 / args.fps.max(1) as f64);
+    let mut next_index = 0;
+    let mut next_frame_at = Instant::now();
+    let mut paused = false;
+    let mut pending_steps = 0u32;
+
+    'playback: loop {
+        loop {
+            let key = match renderer.events().try_recv() {
+                Ok(RenderEvent::Key(key)) => key,
+                Ok(RenderEvent::PresentFailed(e)) => {
+                    warn!("Render error: {:?}", e);
+                    continue;
+                }
+                Ok(RenderEvent::Shortcut(action)) => {
+                    info!("The {} shortcut isn't supported in playback yet", action);
+                    continue;
+                }
+                Ok(RenderEvent::WindowChanged(geometry)) => {
+                    if let Some(saver) = &mut window_saver {
+                        saver.changed(geometry, Instant::now());
+                    }
+                    continue;
+                }
+                Ok(RenderEvent::Quit) | Err(TryRecvError::Disconnected) => {
+                    info!("Quit requested");
+                    break 'playback;
+                }
+                Err(TryRecvError::Empty) => break,
+            };
+            match key {
+                Keycode::Space if paused => pending_steps += 1,
+                Keycode::Space => {
+                    info!("Paused at access unit {}", next_index);
+                    paused = true;
+                }
+                Keycode::Return if paused => {
+                    info!("Resumed");
+                    paused = false;
+                    pending_steps = 0;
+                    next_frame_at = Instant::now();
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(saver) = &mut window_saver {
+            saver.save_settled(now);
+        }
+        let advance = if paused {
+            let step = pending_steps > 0;
+            pending_steps = pending_steps.saturating_sub(1);
+            step
+        } else {
+            now >= next_frame_at
+        };
+
+        if !advance {
+            let wait = if paused {
+                PAUSED_POLL_INTERVAL
+            } else {
+                next_frame_at.saturating_duration_since(now).min(PAUSED_POLL_INTERVAL)
+            };
+            tokio::time::sleep(wait).await;
+            continue;
+        }
+
+        if next_index == access_units.len() {
+            if !args.loop_input {
+                // Show whatever the decoder still holds, then stop
+                for frame in decoder.flush().unwrap_or_default() {
+                    renderer.present(frame).await?;
+                }
+                info!("End of stream");
+                break;
+            }
+
+            // Restart with a fresh decoder so no reference state carries over
+            next_index = 0;
+            decoder.reset().context("Failed to reset decoder")?;
+        }
+
+        let pts_us = next_index as i64 * frame_interval.as_micros() as i64;
+        match decoder.decode(&access_units[next_index], pts_us) {
+            Ok(output) => {
+                for frame in output.frames {
+                    if let Err(e) = renderer.present(frame).await {
+                        warn!("Render error: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Decode error at access unit {}: {:?}", next_index, e),
+        }
+        next_index += 1;
+
+        // Don't try to catch up after a stall
+        next_frame_at = (next_frame_at + frame_interval).max(now);
+    }
+
+    if let Some(saver) = &mut window_saver {
+        if let Ok(geometry) = renderer.geometry().await {
+            saver.save(geometry);
+        }
+    }
+    drop(renderer);
+    render_closed.wait();
+    Ok(())
+}
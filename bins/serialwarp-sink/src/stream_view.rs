@@ -0,0 +1,150 @@
+//! One stream the sink shows, in a window of its own
+//!
+//! A source streaming several displays tags each one's packets with its
+//! stream id; each stream gets its own pipeline, pacer and window.
+
+use std::time::Instant;
+
+use serialwarp_core::{FramePacer, StartPayload, VideoDecoder};
+use serialwarp_pipeline::{NegotiatedStream, SinkPipeline};
+use serialwarp_render::{RenderClosed, RendererHandle};
+use tracing::{info, warn};
+
+use crate::placeholder::{PlaceholderScreen, SinkState};
+use crate::OverlayWindow;
+
+pub struct StreamView {
+    pub stream_id: u8,
+    pub pipeline: SinkPipeline,
+    pub renderer: RendererHandle,
+    render_closed: RenderClosed,
+    pub pacer: FramePacer,
+    placeholder_screen: PlaceholderScreen,
+    /// No frame has been presented since the stream started or restarted
+    awaiting_first_frame: bool,
+    pub overlay_window: OverlayWindow,
+    /// Coded size of the stream's frames
+    pub stream_size: (u32, u32),
+    /// Pixels per point the window shows frames at
+    pub scale: u16,
+}
+
+impl StreamView {
+    pub fn new(
+        stream_id: u8,
+        pipeline: SinkPipeline,
+        (renderer, render_closed): (RendererHandle, RenderClosed),
+        start: &StartPayload,
+        scale: u16,
+    ) -> Self {
+        Self {
+            stream_id,
+            pipeline,
+            renderer,
+            render_closed,
+            pacer: FramePacer::new(start.fps()),
+            placeholder_screen: PlaceholderScreen::default(),
+            awaiting_first_frame: true,
+            overlay_window: OverlayWindow::new(Instant::now()),
+            stream_size: (start.width, start.height),
+            scale,
+        }
+    }
+
+    /// Present whatever frame is due, or else say why there's none
+    pub async fn present_due(&mut self, awaiting_reconnect: bool) {
+        if let Some(frame) = self.pacer.next_due(Instant::now()) {
+            let frame_number = frame.frame_number;
+            if let Err(e) = self.renderer.present(frame).await {
+                warn!("Render error: {:?}", e);
+            }
+            self.awaiting_first_frame = false;
+            if let Some(latency) = self.pipeline.frame_presented(frame_number) {
+                self.overlay_window.latency += latency;
+                self.overlay_window.latency_samples += 1;
+            }
+            self.overlay_window.frames_presented += 1;
+        }
+        let state = SinkState {
+            awaiting_reconnect,
+            awaiting_first_frame: self.awaiting_first_frame,
+            stalled: self.pipeline.is_stalled(),
+        };
+        if let Some(kind) = self.placeholder_screen.update(state) {
+            if let Err(e) = self.renderer.present_placeholder(kind).await {
+                warn!("Render error: {:?}", e);
+            }
+        }
+    }
+
+    /// Show the stats gathered since the last refresh in the overlay, and
+    /// start gathering anew
+    pub async fn refresh_overlay(&mut self, now: Instant) {
+        let pacing = self.pacer.stats();
+        let dropped = pacing.dropped
+            + pacing.overflowed
+            + self.pipeline.stats().frames_evicted
+            + self.renderer.frames_dropped();
+        let stats = self
+            .overlay_window
+            .finish(now, dropped, self.pacer.queued());
+        let _ = self.renderer.set_overlay_stats(stats).await;
+        self.overlay_window = OverlayWindow::new(now);
+    }
+
+    /// Take the stream the source negotiated on reconnecting, in the same
+    /// window
+    pub fn restart(
+        &mut self,
+        decoder: Box<dyn VideoDecoder>,
+        negotiated: &NegotiatedStream,
+        sequence: u32,
+    ) {
+        self.pipeline.restart(decoder, negotiated, sequence);
+        self.pacer = FramePacer::new(negotiated.start.fps());
+        self.stream_size = (negotiated.start.width, negotiated.start.height);
+        self.scale = negotiated.scale;
+        self.awaiting_first_frame = true;
+    }
+
+    /// Log what became of the stream's frames
+    pub fn log_stats(&self) {
+        let stats = self.pipeline.reassembler().stats();
+        info!(
+            "Stream {} reassembly: {} completed, {} evicted, {} duplicate segments, {} rebuilt from parity, {} unrecoverable",
+            self.stream_id,
+            stats.frames_completed,
+            stats.frames_evicted,
+            stats.duplicate_segments,
+            stats.segments_recovered,
+            stats.frames_unrecoverable
+        );
+        let stats = self.pipeline.stats();
+        info!(
+            "Stream {} decoding: {} decoded, {} errors, {} skipped awaiting keyframe, {} skipped to catch up, {} acks sent, {} stalls",
+            self.stream_id,
+            stats.frames_decoded,
+            stats.decode_errors,
+            stats.frames_awaiting_keyframe,
+            stats.frames_skipped,
+            stats.acks_sent,
+            stats.stalls
+        );
+        let stats = self.pacer.stats();
+        info!(
+            "Stream {} pacing: {} presented, {} late, {} dropped late, {} dropped for a full queue, {} dropped by the renderer",
+            self.stream_id,
+            stats.presented,
+            stats.late,
+            stats.dropped,
+            stats.overflowed,
+            self.renderer.frames_dropped()
+        );
+    }
+
+    /// Close the window, waiting until it's gone
+    pub fn close(self) {
+        drop(self.renderer);
+        self.render_closed.wait();
+    }
+}
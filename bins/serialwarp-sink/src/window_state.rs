@@ -0,0 +1,261 @@
+//! Where the window was when the sink last ran, so the next launch opens it
+//! there rather than centered at the stream's size
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serialwarp_core::{Context, ErrorKind, SerialwarpError};
+use serialwarp_render::{RendererConfig, WindowGeometry};
+use tracing::{debug, warn};
+
+/// File the geometry is kept in, under the platform's config directory
+const FILE_NAME: &str = "sink-window.toml";
+
+/// How long the window must be left alone after a move or resize before
+/// it's saved, so a drag is written once rather than at every step
+pub const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// The file's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SavedWindow {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display: Option<usize>,
+    #[serde(default)]
+    fullscreen: bool,
+}
+
+impl From<WindowGeometry> for SavedWindow {
+    fn from(geometry: WindowGeometry) -> Self {
+        Self {
+            x: geometry.x,
+            y: geometry.y,
+            width: geometry.width,
+            height: geometry.height,
+            display: geometry.display_index,
+            fullscreen: geometry.fullscreen,
+        }
+    }
+}
+
+impl From<SavedWindow> for WindowGeometry {
+    fn from(saved: SavedWindow) -> Self {
+        Self {
+            x: saved.x,
+            y: saved.y,
+            width: saved.width,
+            height: saved.height,
+            display_index: saved.display,
+            fullscreen: saved.fullscreen,
+        }
+    }
+}
+
+/// Open the window where it was last time, unless `config` already picks a
+/// display for it. Returns what keeps the saved geometry up to date, or None
+/// if there's nowhere to keep it.
+pub fn restore(config: &mut RendererConfig) -> Option<WindowSaver> {
+    let path = default_path()?;
+    let saved = load(&path);
+    if let Some(mut geometry) = saved {
+        if config.display_index.is_none() {
+            geometry.fullscreen |= config.fullscreen;
+            config.geometry = Some(geometry);
+        }
+    }
+    Some(WindowSaver::new(path, saved))
+}
+
+/// Where the geometry is kept, e.g. ~/.config/serialwarp/sink-window.toml;
+/// None if the platform has no config directory
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("serialwarp").join(FILE_NAME))
+}
+
+fn to_toml(geometry: WindowGeometry) -> String {
+    // Every field is a plain number or bool, which TOML always has a form for
+    toml::to_string(&SavedWindow::from(geometry)).expect("window geometry is valid TOML")
+}
+
+fn from_toml(text: &str) -> Result<WindowGeometry, SerialwarpError> {
+    let saved: SavedWindow = toml::from_str(text)
+        .map_err(|e| SerialwarpError::with_source(ErrorKind::InvalidInput, e))?;
+    // A zero-sized window can't be seen or grabbed
+    if saved.width == 0 || saved.height == 0 {
+        return Err(SerialwarpError::new(
+            ErrorKind::InvalidInput,
+            format!("empty {}x{} window", saved.width, saved.height),
+        ));
+    }
+    Ok(saved.into())
+}
+
+/// The geometry saved at `path`, if there is one that can be read
+pub fn load(path: &Path) -> Option<WindowGeometry> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match from_toml(&text) {
+        Ok(geometry) => Some(geometry),
+        Err(e) => {
+            warn!("Ignoring {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Write `geometry` to `path`, creating its directory if needed
+pub fn save(path: &Path, geometry: WindowGeometry) -> Result<(), SerialwarpError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, to_toml(geometry))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Saves the window's geometry once it has settled after a change, and once
+/// more on shutdown
+#[derive(Debug)]
+pub struct WindowSaver {
+    path: PathBuf,
+    /// The latest change not yet saved, and when it came
+    pending: Option<(WindowGeometry, Instant)>,
+    saved: Option<WindowGeometry>,
+}
+
+impl WindowSaver {
+    /// Save to `path`, which already holds `saved` if anything
+    pub fn new(path: PathBuf, saved: Option<WindowGeometry>) -> Self {
+        Self {
+            path,
+            pending: None,
+            saved,
+        }
+    }
+
+    /// The window changed at `now`
+    pub fn changed(&mut self, geometry: WindowGeometry, now: Instant) {
+        self.pending = Some((geometry, now));
+    }
+
+    /// Save the latest change if the window has since been left alone for
+    /// `SAVE_DELAY`
+    pub fn save_settled(&mut self, now: Instant) {
+        match self.pending {
+            Some((geometry, at)) if now.duration_since(at) >= SAVE_DELAY => {
+                self.pending = None;
+                self.save(geometry);
+            }
+            _ => {}
+        }
+    }
+
+    /// Save `geometry` now, unless it's what was saved last
+    pub fn save(&mut self, geometry: WindowGeometry) {
+        self.pending = None;
+        if self.saved == Some(geometry) {
+            return;
+        }
+        match save(&self.path, geometry) {
+            Ok(()) => {
+                debug!("Saved window geometry to {}", self.path.display());
+                self.saved = Some(geometry);
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry() -> WindowGeometry {
+        WindowGeometry {
+            x: -1280,
+            y: 40,
+            width: 1280,
+            height: 720,
+            display_index: Some(1),
+            fullscreen: false,
+        }
+    }
+
+    #[test]
+    fn test_toml_format() {
+        assert_eq!(
+            to_toml(geometry()),
+            "x = -1280\ny = 40\nwidth = 1280\nheight = 720\ndisplay = 1\nfullscreen = false\n"
+        );
+        assert_eq!(from_toml(&to_toml(geometry())).unwrap(), geometry());
+
+        // Without a display, the key is left out
+        let geometry = WindowGeometry {
+            display_index: None,
+            fullscreen: true,
+            ..geometry()
+        };
+        assert!(!to_toml(geometry).contains("display"));
+        assert_eq!(from_toml(&to_toml(geometry)).unwrap(), geometry);
+    }
+
+    #[test]
+    fn test_toml_lenient() {
+        // Missing optional keys default, unknown ones are ignored
+        let geometry = from_toml("x = 10\ny = 20\nwidth = 800\nheight = 600\nscale = 2\n").unwrap();
+        assert_eq!(geometry.display_index, None);
+        assert!(!geometry.fullscreen);
+        assert_eq!((geometry.x, geometry.width), (10, 800));
+    }
+
+    #[test]
+    fn test_toml_invalid() {
+        for text in [
+            "",
+            "x = 10\ny = 20\nwidth = 800\n",
+            "x = 10\ny = 20\nwidth = -800\nheight = 600\n",
+            "x = 10\ny = 20\nwidth = 0\nheight = 600\n",
+            "not toml",
+        ] {
+            assert_eq!(
+                from_toml(text).unwrap_err().kind(),
+                ErrorKind::InvalidInput,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_saver_waits_to_settle() {
+        let dir = std::env::temp_dir().join(format!("serialwarp-window-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut saver = WindowSaver::new(path.clone(), None);
+
+        let start = Instant::now();
+        saver.changed(geometry(), start);
+        saver.save_settled(start + SAVE_DELAY / 2);
+        assert_eq!(load(&path), None);
+
+        // Another step of the drag restarts the wait
+        let moved = WindowGeometry { x: 0, ..geometry() };
+        saver.changed(moved, start + SAVE_DELAY / 2);
+        saver.save_settled(start + SAVE_DELAY);
+        assert_eq!(load(&path), None);
+        saver.save_settled(start + SAVE_DELAY * 2);
+        assert_eq!(load(&path), Some(moved));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
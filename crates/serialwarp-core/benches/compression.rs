@@ -0,0 +1,68 @@
+//! FRAME segment throughput with and without LZ4 payload compression
+//!
+//! Run with `cargo bench -p serialwarp-core --bench compression`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serialwarp_core::{EncodedFrame, FrameMetadata, Packet, PacketType, MAX_SEGMENT_SIZE};
+
+/// A full segment of a flat-color frame, like a mostly static desktop
+fn flat_segment() -> bytes::Bytes {
+    segment_payload(vec![0x80; MAX_SEGMENT_SIZE])
+}
+
+/// A full segment of data LZ4 can't shrink, like a busy scene
+fn noisy_segment() -> bytes::Bytes {
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let data = (0..MAX_SEGMENT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    segment_payload(data)
+}
+
+fn segment_payload(data: Vec<u8>) -> bytes::Bytes {
+    let frame = EncodedFrame::new(FrameMetadata::new(0, 0, 0, false), data);
+    frame.into_segments()[0].to_payload()
+}
+
+fn bench_send(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_segment");
+    for (name, payload) in [("flat", flat_segment()), ("noisy", noisy_segment())] {
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        group.bench_with_input(BenchmarkId::new("plain", name), &payload, |b, payload| {
+            b.iter(|| Packet::new(PacketType::Frame, 0, 0, black_box(payload.clone())).to_bytes())
+        });
+        group.bench_with_input(BenchmarkId::new("lz4", name), &payload, |b, payload| {
+            b.iter(|| {
+                Packet::new_compressed(PacketType::Frame, 0, 0, black_box(payload.clone()))
+                    .to_bytes()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_receive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("receive_segment");
+    for (name, payload) in [("flat", flat_segment()), ("noisy", noisy_segment())] {
+        let plain = Packet::new(PacketType::Frame, 0, 0, payload.clone()).to_bytes();
+        let compressed =
+            Packet::new_compressed(PacketType::Frame, 0, 0, payload.clone()).to_bytes();
+
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        group.bench_with_input(BenchmarkId::new("plain", name), &plain, |b, bytes| {
+            b.iter(|| Packet::parse(black_box(bytes)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("lz4", name), &compressed, |b, bytes| {
+            b.iter(|| Packet::parse(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_send, bench_receive);
+criterion_main!(benches);
@@ -0,0 +1,221 @@
+//! The per-packet work on both ends of the link: packet serialization and
+//! parsing, frame segmentation and reassembly, CRC32C, and splitting a byte
+//! stream back into packets
+//!
+//! Run with `cargo bench -p serialwarp-core --bench protocol`, or a single
+//! group with e.g. `cargo bench -p serialwarp-core --bench protocol -- reassemble`.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use serialwarp_core::{
+    EncodedFrame, FrameAckBatchPayload, FrameAckEntry, FrameHeader, FrameMetadata,
+    FrameReassembler, Packet, PacketDecoder, PacketHeader, PacketType, PingPayload,
+};
+
+/// Size of a large keyframe at 1080p
+const FRAME_SIZE: usize = 500 * 1024;
+
+/// How much one read from the link returns, about one USB bulk transfer
+const READ_SIZE: usize = 16 * 1024;
+
+/// Deterministic bytes that don't repeat within a segment
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn frame(frame_number: u64) -> EncodedFrame {
+    EncodedFrame::new(
+        FrameMetadata::new(frame_number, frame_number * 16_667, 0, frame_number == 0),
+        noise(FRAME_SIZE),
+    )
+}
+
+/// FRAME packets for every segment of `frame`, numbered from `sequence`
+fn frame_packets(frame: EncodedFrame, sequence: u32) -> Vec<Packet> {
+    frame
+        .into_segments()
+        .iter()
+        .zip(sequence..)
+        .map(|(segment, sequence)| {
+            Packet::new(PacketType::Frame, 0, sequence, segment.to_payload())
+        })
+        .collect()
+}
+
+fn bench_packet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet");
+    for size in [1024, 64 * 1024] {
+        let packet = Packet::new(PacketType::Frame, 0, 0, Bytes::from(noise(size)));
+        let bytes = packet.to_bytes();
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("to_bytes", size), &packet, |b, packet| {
+            b.iter(|| black_box(packet).to_bytes())
+        });
+        group.bench_with_input(BenchmarkId::new("parse", size), &bytes, |b, bytes| {
+            b.iter(|| Packet::parse(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Bytes(FRAME_SIZE as u64));
+
+    group.bench_function("into_segments", |b| {
+        b.iter_batched(
+            || frame(0),
+            |frame| frame.into_segments(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    // What the sink hands the reassembler: each FRAME payload split into its
+    // header and data
+    let segments: Vec<(FrameHeader, Bytes)> = frame(0)
+        .into_segments()
+        .iter()
+        .map(|segment| {
+            let payload = segment.to_payload();
+            let header = FrameHeader::parse(&payload).unwrap();
+            (header, payload.slice(FrameHeader::SIZE..))
+        })
+        .collect();
+    group.bench_function("reassemble", |b| {
+        b.iter_batched(
+            || segments.clone(),
+            |segments| {
+                let mut reassembler = FrameReassembler::new();
+                let mut complete = None;
+                for (header, data) in segments {
+                    complete = reassembler.add_segment(&header, data).unwrap();
+                }
+                complete.expect("frame not reassembled")
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let segments = frame(0).into_segments();
+    group.bench_function("to_payload_into", |b| {
+        let mut buf = BytesMut::with_capacity(FRAME_SIZE + segments.len() * FrameHeader::SIZE);
+        b.iter(|| {
+            buf.clear();
+            for segment in &segments {
+                segment.to_payload_into(&mut buf);
+            }
+            buf.len()
+        })
+    });
+
+    // Every FRAME packet of a frame ready for the transport: joined into one
+    // buffer, copying the data twice, or left in parts for a vectored send
+    group.bench_function("packets_joined", |b| {
+        b.iter(|| {
+            segments
+                .iter()
+                .map(|segment| {
+                    Packet::new(PacketType::Frame, 0, 0, segment.to_payload())
+                        .to_bytes()
+                        .len()
+                })
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("packets_vectored", |b| {
+        b.iter(|| {
+            segments
+                .iter()
+                .map(|segment| {
+                    let header = PacketHeader::new(PacketType::Frame, 0, 0, 0);
+                    Packet::parts_with_payload(header, &segment.payload_parts()).len()
+                })
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn bench_crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc32c");
+    for size in [1024, 64 * 1024] {
+        let data = noise(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| crc32c::crc32c(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+/// A few frames' worth of link traffic, as the sink reads it: FRAME segments
+/// with a batched FRAME_ACK and a PING between frames
+fn mixed_stream() -> Vec<u8> {
+    let mut packets = Vec::new();
+    let mut sequence = 0;
+    for frame_number in 0..4 {
+        let segments = frame_packets(frame(frame_number), sequence);
+        sequence += segments.len() as u32;
+        packets.extend(segments);
+
+        let ack = FrameAckBatchPayload::new(
+            vec![FrameAckEntry {
+                frame_number,
+                decode_time_us: 2_500,
+            }],
+            1,
+        );
+        packets.push(Packet::new(
+            PacketType::FrameAck,
+            0,
+            sequence,
+            ack.to_bytes(),
+        ));
+        packets.push(Packet::new(
+            PacketType::Ping,
+            0,
+            sequence + 1,
+            PingPayload::new(frame_number * 16_667).to_bytes(),
+        ));
+        sequence += 2;
+    }
+    packets
+        .iter()
+        .flat_map(|packet| packet.to_bytes())
+        .collect()
+}
+
+fn bench_decoder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet_decoder");
+    let stream = mixed_stream();
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("mixed_traffic", |b| {
+        b.iter(|| {
+            let mut decoder = PacketDecoder::new();
+            let mut packets = 0;
+            for read in black_box(&stream).chunks(READ_SIZE) {
+                decoder.push(read);
+                while let Some(packet) = decoder.next_packet().unwrap() {
+                    black_box(packet);
+                    packets += 1;
+                }
+            }
+            packets
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_packet, bench_frame, bench_crc, bench_decoder);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+//! Print the golden wire-format vectors, for `src/protocol_vectors.txt`
+//!
+//! Only run this when the wire format changes on purpose; see
+//! `serialwarp_core::protocol_vectors`.
+
+fn main() {
+    print!("{}", serialwarp_core::protocol_vectors::golden_file());
+}
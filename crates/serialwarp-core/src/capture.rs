@@ -0,0 +1,315 @@
+//! Captured frames and the frame source interface
+
+use std::time::{Duration, Instant};
+
+use crate::error::CaptureError;
+use crate::pool::{BufferPool, PoolStats, PooledBuffer};
+
+/// Bytes per BGRA pixel
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Largest preview `CapturedFrame::preview_rgba` is usually asked for
+pub const DEFAULT_PREVIEW_SIZE: (u32, u32) = (854, 480);
+
+/// Default preview rate, independent of the capture rate
+pub const DEFAULT_PREVIEW_FPS: u32 = 10;
+
+/// Default number of free buffers a `FramePool` keeps, enough for the
+/// frames queued between capture and the encoder
+pub const DEFAULT_FRAME_POOL_SIZE: usize = 4;
+
+/// A captured BGRA frame
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub frame_number: u64,
+    pub pts_us: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row
+    pub stride: usize,
+    /// BGRA pixels, `stride * height` bytes, returned to the source's
+    /// `FramePool` when the frame drops
+    pub data: PooledBuffer,
+}
+
+impl CapturedFrame {
+    /// BGRA value of the pixel at (x, y)
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = y as usize * self.stride + x as usize * BYTES_PER_PIXEL;
+        let mut pixel = [0u8; 4];
+        pixel.copy_from_slice(&self.data[offset..offset + BYTES_PER_PIXEL]);
+        pixel
+    }
+
+    /// Write an RGBA preview no larger than `max_width` x `max_height` into
+    /// `out`, returning its size.
+    ///
+    /// The frame is subsampled by a whole factor while it's read, so no
+    /// full-size copy is made; `out` keeps its allocation between calls.
+    pub fn preview_rgba(&self, max_width: u32, max_height: u32, out: &mut Vec<u8>) -> (u32, u32) {
+        let step_x = (self.width + max_width.max(1) - 1) / max_width.max(1);
+        let step_y = (self.height + max_height.max(1) - 1) / max_height.max(1);
+        let step = step_x.max(step_y).max(1) as usize;
+        let width = self.width as usize / step;
+        let height = self.height as usize / step;
+
+        out.clear();
+        out.reserve(width * height * BYTES_PER_PIXEL);
+        for row in 0..height {
+            let line = &self.data[row * step * self.stride..];
+            for bgra in line.chunks_exact(BYTES_PER_PIXEL).step_by(step).take(width) {
+                out.extend_from_slice(&[bgra[2], bgra[1], bgra[0], 255]);
+            }
+        }
+
+        (width as u32, height as u32)
+    }
+}
+
+/// Reusable pixel buffers for captured frames, so a source doesn't allocate
+/// and free a whole frame (~33 MB at 4K) for every capture.
+///
+/// At most `max_frames` free buffers are kept, whatever their size; after a
+/// resolution change, buffers of the old size are evicted first. Cloning is
+/// cheap and clones share the buffers, so frames can be dropped on any
+/// thread.
+#[derive(Debug, Clone)]
+pub struct FramePool {
+    buffers: BufferPool,
+}
+
+impl FramePool {
+    pub fn new(max_frames: usize) -> Self {
+        Self {
+            buffers: BufferPool::with_limits(usize::MAX, max_frames),
+        }
+    }
+
+    /// A zeroed buffer for a frame of `height` rows of `stride` bytes
+    pub fn get(&self, stride: usize, height: u32) -> PooledBuffer {
+        let mut buffer = self.buffers.get(stride * height as usize);
+        buffer.resize(stride * height as usize, 0);
+        buffer
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.buffers.stats()
+    }
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAME_POOL_SIZE)
+    }
+}
+
+/// Limits previews to a fixed rate, whatever the capture rate
+#[derive(Debug, Clone)]
+pub struct PreviewThrottle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl PreviewThrottle {
+    pub fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps.max(1),
+            last: None,
+        }
+    }
+
+    /// Whether a preview is due at `now`; if so, the next one is due an
+    /// interval later
+    pub fn ready(&mut self, now: Instant) -> bool {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < self.interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+impl Default for PreviewThrottle {
+    fn default() -> Self {
+        Self::new(DEFAULT_PREVIEW_FPS)
+    }
+}
+
+/// Something that produces frames for the encoder, such as screen capture or
+/// a test pattern
+pub trait FrameSource: Send {
+    /// Block until the next frame is available and return it
+    fn next_frame(&mut self) -> Result<CapturedFrame, CaptureError>;
+
+    /// Frame size in pixels
+    fn resolution(&self) -> (u32, u32);
+
+    /// Nominal frame rate
+    fn fps(&self) -> u32;
+
+    /// Change the frame rate without restarting the source. Frame pts keep
+    /// increasing across the change.
+    fn set_fps(&mut self, fps: u32) -> Result<(), CaptureError> {
+        Err(CaptureError::UpdateFailed(format!(
+            "frame rate can't be changed to {}",
+            fps
+        )))
+    }
+
+    /// Change the frame size without restarting the source, from the next
+    /// frame on
+    fn set_dimensions(&mut self, width: u32, height: u32) -> Result<(), CaptureError> {
+        Err(CaptureError::UpdateFailed(format!(
+            "frame size can't be changed to {}x{}",
+            width, height
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32) -> CapturedFrame {
+        let stride = width as usize * BYTES_PER_PIXEL + 16;
+        let mut data = vec![0u8; stride * height as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let offset = y * stride + x * BYTES_PER_PIXEL;
+                data[offset..offset + 4].copy_from_slice(&[x as u8, y as u8, 7, 255]);
+            }
+        }
+        CapturedFrame {
+            frame_number: 0,
+            pts_us: 0,
+            width,
+            height,
+            stride,
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn test_preview_fits_and_swizzles() {
+        let frame = frame(100, 40);
+        let mut out = Vec::new();
+
+        let (width, height) = frame.preview_rgba(30, 30, &mut out);
+        // Subsampled by 4 to fit the width
+        assert_eq!((width, height), (25, 10));
+        assert_eq!(out.len(), 25 * 10 * 4);
+        // RGBA of source pixel (8, 4)
+        let offset = (width as usize + 2) * 4;
+        assert_eq!(&out[offset..offset + 4], &[7, 4, 8, 255]);
+
+        // Small frames are copied at full size
+        assert_eq!(frame.preview_rgba(854, 480, &mut out), (100, 40));
+        assert_eq!(out.len(), 100 * 40 * 4);
+    }
+
+    #[test]
+    fn test_preview_reuses_buffer() {
+        let frame = frame(64, 64);
+        let mut out = Vec::new();
+        frame.preview_rgba(32, 32, &mut out);
+        let capacity = out.capacity();
+        let pointer = out.as_ptr();
+
+        frame.preview_rgba(32, 32, &mut out);
+        assert_eq!(out.capacity(), capacity);
+        assert_eq!(out.as_ptr(), pointer);
+    }
+
+    #[test]
+    fn test_preview_4k_is_cheap() {
+        let frame = CapturedFrame {
+            frame_number: 0,
+            pts_us: 0,
+            width: 3840,
+            height: 2160,
+            stride: 3840 * BYTES_PER_PIXEL,
+            data: vec![128; 3840 * 2160 * BYTES_PER_PIXEL].into(),
+        };
+        let (max_width, max_height) = DEFAULT_PREVIEW_SIZE;
+        let mut out = Vec::new();
+        frame.preview_rgba(max_width, max_height, &mut out);
+
+        let start = Instant::now();
+        let size = frame.preview_rgba(max_width, max_height, &mut out);
+        let elapsed = start.elapsed();
+
+        assert_eq!(size, (768, 432));
+        // Unoptimized builds are several times slower
+        let limit = if cfg!(debug_assertions) { 100 } else { 5 };
+        assert!(
+            elapsed < Duration::from_millis(limit),
+            "4K preview took {:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_frame_pool_reuses_buffers() {
+        let pool = FramePool::default();
+        let stride = 1920 * BYTES_PER_PIXEL;
+
+        for _ in 0..10 {
+            let mut data = pool.get(stride, 1080);
+            assert_eq!(data.len(), stride * 1080);
+            assert!(data.iter().all(|&byte| byte == 0));
+            data.fill(255);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.reuses, 9);
+    }
+
+    #[test]
+    fn test_frame_pool_cap() {
+        let pool = FramePool::new(2);
+        let in_flight: Vec<_> = (0..5).map(|_| pool.get(64, 64)).collect();
+        drop(in_flight);
+        assert_eq!(pool.stats().retained_buffers, 2);
+
+        // After a resolution change the old size makes way
+        let in_flight: Vec<_> = (0..2).map(|_| pool.get(256, 64)).collect();
+        drop(in_flight);
+        let stats = pool.stats();
+        assert_eq!(stats.retained_buffers, 2);
+        assert_eq!(stats.retained_bytes, 2 * 256 * 64);
+    }
+
+    #[test]
+    fn test_frame_returned_from_another_thread() {
+        let pool = FramePool::default();
+        let frame = CapturedFrame {
+            frame_number: 0,
+            pts_us: 0,
+            width: 64,
+            height: 64,
+            stride: 256,
+            data: pool.get(256, 64),
+        };
+
+        // Like a frame handed to the encoder thread
+        std::thread::spawn(move || drop(frame)).join().unwrap();
+        assert_eq!(pool.stats().retained_buffers, 1);
+        drop(pool.get(256, 64));
+        assert_eq!(pool.stats().reuses, 1);
+    }
+
+    #[test]
+    fn test_preview_throttle() {
+        let mut throttle = PreviewThrottle::new(10);
+        let start = Instant::now();
+
+        assert!(throttle.ready(start));
+        assert!(!throttle.ready(start + Duration::from_millis(50)));
+        assert!(throttle.ready(start + Duration::from_millis(100)));
+        assert!(!throttle.ready(start + Duration::from_millis(150)));
+        assert!(throttle.ready(start + Duration::from_millis(260)));
+    }
+}
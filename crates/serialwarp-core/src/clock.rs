@@ -0,0 +1,110 @@
+//! Relating source timestamps to the sink's clock
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// PING observations kept, so the estimate follows clock drift
+const CLOCK_SYNC_SAMPLES: usize = 16;
+
+/// Microseconds since the Unix epoch on this machine's clock
+pub fn unix_time_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Estimate of the source's clock relative to ours, from the timestamps in
+/// its PINGs.
+///
+/// A PING stamped at source time `t` that arrives at local time `now` gives
+/// `t - now`: the clock offset less the one-way delay. The delay is never
+/// negative and tiny over USB, so the largest recent value is the best
+/// estimate.
+#[derive(Debug, Clone, Default)]
+pub struct ClockSync {
+    samples: VecDeque<i64>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a PING stamped `source_us` received at `local_us`
+    pub fn observe(&mut self, source_us: u64, local_us: u64) {
+        if self.samples.len() == CLOCK_SYNC_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(source_us as i64 - local_us as i64);
+    }
+
+    /// Source clock minus local clock in microseconds, once a PING has
+    /// been seen
+    pub fn offset_us(&self) -> Option<i64> {
+        self.samples.iter().copied().max()
+    }
+
+    /// Time from `source_ts_us` on the source's clock to `local_us` on
+    /// ours. Before any PING the clocks are taken to agree, as they do
+    /// when both ends sync over NTP. `None` if the result would be
+    /// negative, i.e. the clocks are further apart than that.
+    pub fn latency(&self, source_ts_us: u64, local_us: u64) -> Option<Duration> {
+        let local_ts = source_ts_us as i64 - self.offset_us().unwrap_or(0);
+        let latency = local_us as i64 - local_ts;
+        (latency >= 0).then(|| Duration::from_micros(latency as u64))
+    }
+
+    /// Forget the estimate, e.g. when a different source connects
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_without_pings() {
+        let clock = ClockSync::new();
+        assert_eq!(clock.offset_us(), None);
+        assert_eq!(
+            clock.latency(1_000_000, 1_016_000),
+            Some(Duration::from_millis(16))
+        );
+        assert_eq!(clock.latency(1_016_000, 1_000_000), None);
+    }
+
+    #[test]
+    fn test_offset_from_least_delayed_ping() {
+        // The source's clock runs 5s ahead; pings take 100-900us to arrive
+        let mut clock = ClockSync::new();
+        for (sent, delay) in [(0, 900), (1_000_000, 100), (2_000_000, 400)] {
+            let source = 10_005_000_000 + sent;
+            clock.observe(source, source - 5_000_000_000 + delay);
+        }
+        assert_eq!(clock.offset_us(), Some(4_999_999_900));
+
+        // A frame captured 20ms ago, less the delay left in the estimate
+        let now = 10_000_000_000;
+        let captured = now + 5_000_000_000 - 20_000;
+        assert_eq!(
+            clock.latency(captured, now),
+            Some(Duration::from_micros(19_900))
+        );
+    }
+
+    #[test]
+    fn test_old_samples_expire() {
+        let mut clock = ClockSync::new();
+        clock.observe(1_000, 0);
+        for _ in 0..CLOCK_SYNC_SAMPLES {
+            clock.observe(500, 0);
+        }
+        assert_eq!(clock.offset_us(), Some(500));
+
+        clock.reset();
+        assert_eq!(clock.offset_us(), None);
+    }
+}
@@ -0,0 +1,1151 @@
+//! Codec-agnostic encoder and decoder interfaces, plus mock codecs for tests
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::error::{DecodeError, EncodeError};
+use crate::frame::{ColorSpace, DecodedFrame, EncodedFrame, FrameMetadata};
+use crate::resolution::Resolution;
+use crate::sei::LatencySei;
+
+/// Which encoder implementation to use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncoderBackend {
+    /// Hardware encoder if it can be created, otherwise software
+    #[default]
+    Auto,
+    /// macOS VideoToolbox hardware encoder
+    VideoToolbox,
+    /// libx264 software encoder
+    Software,
+}
+
+/// Default number of encoded frames buffered for the consumer
+pub const DEFAULT_ENCODER_OUTPUT_DEPTH: usize = 16;
+
+/// What to do with encoded output when the consumer falls behind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the frame and count it
+    DropAndCount,
+    /// Drop the frame, then drop everything up to a keyframe and force one on
+    /// the next input the output has room for, so the decoder never sees
+    /// frames referencing a gap
+    #[default]
+    ForceKeyframeAfterDrop,
+}
+
+/// How the encoder spends bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateControl {
+    /// Average bitrate in bits per second, whatever the content
+    ConstantBitrate { bps: u32 },
+    /// Quality from 0.0 (smallest) to 1.0 (best); the bitrate follows the
+    /// content, dropping to almost nothing on a static desktop
+    Quality { value: f32 },
+    /// Like `Quality`, but never above `max_bps`
+    ConstrainedQuality { value: f32, max_bps: u32 },
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        Self::ConstantBitrate { bps: 20_000_000 }
+    }
+}
+
+impl RateControl {
+    /// The bitrate an adaptive controller may adjust: the target in
+    /// constant bitrate mode, the ceiling in constrained quality mode, and
+    /// `None` when quality alone decides
+    pub fn bitrate(&self) -> Option<u32> {
+        match *self {
+            Self::ConstantBitrate { bps } => Some(bps),
+            Self::Quality { .. } => None,
+            Self::ConstrainedQuality { max_bps, .. } => Some(max_bps),
+        }
+    }
+
+    /// Quality target, if this is a quality mode
+    pub fn quality(&self) -> Option<f32> {
+        match *self {
+            Self::ConstantBitrate { .. } => None,
+            Self::Quality { value } | Self::ConstrainedQuality { value, .. } => Some(value),
+        }
+    }
+
+    /// The same mode with a new bitrate (or ceiling). Fails in quality mode,
+    /// which has no bitrate to change.
+    pub fn with_bitrate(self, bps: u32) -> Result<Self, EncodeError> {
+        match self {
+            Self::ConstantBitrate { .. } => Ok(Self::ConstantBitrate { bps }),
+            Self::Quality { .. } => Err(EncodeError::InvalidConfiguration(
+                "quality mode has no bitrate to change".to_string(),
+            )),
+            Self::ConstrainedQuality { value, .. } => Ok(Self::ConstrainedQuality {
+                value,
+                max_bps: bps,
+            }),
+        }
+    }
+
+    /// Reject a quality outside 0.0-1.0 or a zero bitrate
+    pub fn validate(&self) -> Result<(), EncodeError> {
+        if let Some(value) = self.quality() {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(EncodeError::InvalidConfiguration(format!(
+                    "quality {} is outside 0.0-1.0",
+                    value
+                )));
+            }
+        }
+        if self.bitrate() == Some(0) {
+            return Err(EncodeError::InvalidConfiguration(
+                "bitrate must be above zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Pixel layout of the frames passed to `VideoEncoder::encode_raw`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputPixelFormat {
+    /// 32-bit BGRA
+    #[default]
+    Bgra,
+    /// 8-bit 4:2:0: the luma plane, then the interleaved CbCr plane at half
+    /// height, both `stride` bytes per row. Already YUV, so the encoder
+    /// skips its RGB conversion.
+    Nv12,
+}
+
+impl InputPixelFormat {
+    /// Visible bytes per row and rows of each plane
+    pub fn planes(self, width: u32, height: u32) -> Vec<(usize, usize)> {
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            Self::Bgra => vec![(width * 4, height)],
+            // Odd sizes round the chroma up so the last column and row keep
+            // their samples
+            Self::Nv12 => vec![(width, height), ((width + 1) / 2 * 2, (height + 1) / 2)],
+        }
+    }
+
+    /// Bytes `encode_raw` needs for a frame with rows `stride` bytes apart;
+    /// the final row doesn't need its padding
+    pub fn frame_len(self, width: u32, height: u32, stride: usize) -> usize {
+        let planes = self.planes(width, height);
+        let rows: usize = planes.iter().map(|&(_, rows)| rows).sum();
+        match planes.last() {
+            Some(&(row_bytes, _)) if rows > 0 => stride * (rows - 1) + row_bytes,
+            _ => 0,
+        }
+    }
+
+    /// A black frame and its stride. `full_range` picks the YUV black level;
+    /// it makes no difference to BGRA.
+    pub fn black_frame(self, width: u32, height: u32, full_range: bool) -> (Vec<u8>, usize) {
+        let planes = self.planes(width, height);
+        let stride = planes
+            .iter()
+            .map(|&(row_bytes, _)| row_bytes)
+            .max()
+            .unwrap_or(0);
+        let mut data = Vec::with_capacity(self.frame_len(width, height, stride));
+        match self {
+            Self::Bgra => {
+                for _ in 0..width as usize * height as usize {
+                    data.extend_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+            Self::Nv12 => {
+                let black = if full_range { 0 } else { 16 };
+                data.resize(stride * height as usize, black);
+                data.resize(self.frame_len(width, height, stride), 128);
+            }
+        }
+        (data, stride)
+    }
+
+    /// Check a frame handed to the encoder is large enough for its size
+    pub fn check_input(
+        self,
+        len: usize,
+        stride: usize,
+        width: u32,
+        height: u32,
+    ) -> Result<(), EncodeError> {
+        let widest = self
+            .planes(width, height)
+            .iter()
+            .map(|&(row_bytes, _)| row_bytes)
+            .max()
+            .unwrap_or(0);
+        if stride < widest || len < self.frame_len(width, height, stride) {
+            return Err(EncodeError::InvalidInput(format!(
+                "{} bytes with stride {} is too small for {}x{} {:?}",
+                len, stride, width, height, self
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// H.264 profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum H264Profile {
+    /// No B-frames and CAVLC only; cheapest to decode
+    Baseline,
+    Main,
+    #[default]
+    High,
+}
+
+/// H.264 entropy coding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropyMode {
+    /// Faster to decode, roughly 10% larger
+    Cavlc,
+    Cabac,
+}
+
+/// Encoder configuration
+#[derive(Debug, Clone)]
+pub struct EncoderConfig {
+    /// Visible size of the input frames; odd sizes are coded at
+    /// `coded_size()`
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// Constant bitrate or a quality target
+    pub rate_control: RateControl,
+    /// Longest time between keyframes, whatever the frame rate
+    pub keyframe_interval: Duration,
+    pub backend: EncoderBackend,
+    /// Encoded frames buffered ahead of a consumer reading them asynchronously
+    pub output_depth: usize,
+    /// Handling of encoded frames that don't fit in the output buffer
+    pub drop_policy: DropPolicy,
+    /// Colors signaled in the bitstream so the decoder converts back with
+    /// the same matrix and range
+    pub color_space: ColorSpace,
+    pub profile: H264Profile,
+    /// Allow B-frames, trading latency for quality. Baseline never has them.
+    pub allow_bframes: bool,
+    /// Entropy coding, or `None` for the encoder's choice. Baseline is always
+    /// CAVLC.
+    pub entropy: Option<EntropyMode>,
+    /// Most frames the encoder may hold before emitting output, or `None`
+    /// for no limit
+    pub max_frame_delay: Option<u32>,
+    /// Layout of the frames passed to `encode_raw`
+    pub input_format: InputPixelFormat,
+    /// Add a `LatencySei` with the capture time to every access unit, so
+    /// the sink can measure latency from the stream itself
+    pub latency_sei: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            rate_control: RateControl::default(),
+            keyframe_interval: Duration::from_secs(2),
+            backend: EncoderBackend::Auto,
+            output_depth: DEFAULT_ENCODER_OUTPUT_DEPTH,
+            drop_policy: DropPolicy::default(),
+            color_space: ColorSpace::default(),
+            profile: H264Profile::default(),
+            allow_bframes: false,
+            entropy: None,
+            max_frame_delay: None,
+            input_format: InputPixelFormat::default(),
+            latency_sei: false,
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Check the settings before opening an encoder with them
+    pub fn validate(&self) -> Result<(), EncodeError> {
+        if !Resolution::new(self.width, self.height).is_valid() {
+            return Err(EncodeError::InvalidResolution {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.keyframe_interval.is_zero() {
+            return Err(EncodeError::InvalidConfiguration(
+                "keyframe interval must be above zero".to_string(),
+            ));
+        }
+        self.rate_control.validate()
+    }
+
+    /// Set the keyframe interval as a frame count at the configured `fps`,
+    /// as it was given before it became a duration
+    pub fn with_keyframe_interval_frames(mut self, frames: u32) -> Self {
+        let fps = self.fps.max(1) as u64;
+        self.keyframe_interval = Duration::from_micros(frames as u64 * 1_000_000 / fps);
+        self
+    }
+
+    /// The keyframe interval in frames at the configured `fps`, rounded to
+    /// the nearest frame and at least one, for encoders that only take a
+    /// GOP length
+    pub fn keyframe_interval_frames(&self) -> u32 {
+        let frames = (self.keyframe_interval.as_secs_f64() * self.fps as f64).round();
+        frames.clamp(1.0, u32::MAX as f64) as u32
+    }
+
+    /// Size the stream is coded at, with a padding column or row for odd
+    /// sizes
+    pub fn coded_size(&self) -> Resolution {
+        Resolution::new(self.width, self.height).coded()
+    }
+
+    /// A black frame in `input_format`, and its stride
+    pub fn black_frame(&self) -> (Vec<u8>, usize) {
+        self.input_format.black_frame(
+            self.width,
+            self.height,
+            self.color_space.matrix.is_full_range(),
+        )
+    }
+
+    /// Whether the encoder may emit B-frames, given the profile
+    pub fn bframes_enabled(&self) -> bool {
+        self.allow_bframes && self.profile != H264Profile::Baseline
+    }
+
+    /// Entropy coding to request, given the profile
+    pub fn entropy_mode(&self) -> Option<EntropyMode> {
+        match self.profile {
+            H264Profile::Baseline => Some(EntropyMode::Cavlc),
+            _ => self.entropy,
+        }
+    }
+}
+
+/// A video encoder producing Annex B access units with parameter sets
+/// in-band on every keyframe
+pub trait VideoEncoder: Send {
+    /// Submit a frame in `config().input_format` (`stride` bytes per row)
+    /// for encoding
+    fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError>;
+
+    /// Take the next encoded frame, if one is ready
+    fn next_frame(&mut self) -> Option<EncodedFrame>;
+
+    /// Drain all buffered frames so they become available from `next_frame`
+    fn flush(&mut self) -> Result<(), EncodeError>;
+
+    /// Change the target bitrate, or the ceiling in constrained quality
+    /// mode. Fails in quality mode.
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError>;
+
+    /// Switch to a new configuration (e.g. a resolution change). Buffered
+    /// frames are drained first and the next frame is a keyframe.
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError>;
+
+    /// Current configuration
+    fn config(&self) -> &EncoderConfig;
+}
+
+/// Something a decoder noticed about the stream while decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeEvent {
+    /// Output frames changed size, e.g. the source switched resolution
+    /// mid-stream. The first frame of the new size is in the same output.
+    ResolutionChanged { old: Resolution, new: Resolution },
+}
+
+/// What decoding one access unit produced
+#[derive(Debug, Default)]
+pub struct DecodeOutput {
+    /// Decoded pictures
+    pub frames: Vec<DecodedFrame>,
+    /// Changes seen in these pictures, in order
+    pub events: Vec<DecodeEvent>,
+}
+
+impl DecodeOutput {
+    /// Output of `frames`, with an event wherever a frame's size differs
+    /// from the one before it. `last_size` is the size of the decoder's
+    /// previous output frame, and is updated.
+    pub fn tracking_size(frames: Vec<DecodedFrame>, last_size: &mut Option<Resolution>) -> Self {
+        let mut events = Vec::new();
+        for frame in &frames {
+            let size = Resolution::new(frame.width, frame.height);
+            match last_size.replace(size) {
+                Some(old) if old != size => {
+                    events.push(DecodeEvent::ResolutionChanged { old, new: size })
+                }
+                _ => {}
+            }
+        }
+        Self { frames, events }
+    }
+}
+
+/// Frames a decoder leaves undecoded, e.g. to catch up after a stall. Each
+/// level skips everything the one before it does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipMode {
+    /// Decode every frame
+    #[default]
+    None,
+    /// Skip frames no other frame references, which leaves the rest intact
+    NonRef,
+    /// Skip everything but keyframes; the picture holds until the next one
+    NonKey,
+}
+
+/// A video decoder turning access units into YUV420P frames
+pub trait VideoDecoder {
+    /// Decode one access unit. May return zero, one, or multiple frames
+    /// depending on buffering.
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError>;
+
+    /// Flush the decoder and return any remaining frames
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError>;
+
+    /// Discard all decoder state before a new stream (e.g. after the source
+    /// reconnects or changes resolution). The size of the last frame out is
+    /// kept, so a new size after this is still reported.
+    fn reconfigure(&mut self) -> Result<(), DecodeError>;
+
+    /// Change which frames are skipped from the next access unit on.
+    /// Skipped frames produce no output. Decoders that can't skip decode
+    /// everything.
+    fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+        let _ = mode;
+        Ok(())
+    }
+}
+
+impl<D: VideoDecoder + ?Sized> VideoDecoder for Box<D> {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        (**self).decode(data, pts_us)
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        (**self).flush()
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        (**self).reconfigure()
+    }
+
+    fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+        (**self).set_skip_mode(mode)
+    }
+}
+
+/// Size of the header `NullEncoder` writes in place of a bitstream
+pub const NULL_FRAME_HEADER_SIZE: usize = 8;
+
+/// Encoder that skips compression: each frame is just its dimensions.
+///
+/// Pairs with `PassthroughDecoder` to run the pipeline without a real codec.
+#[derive(Debug)]
+pub struct NullEncoder {
+    config: EncoderConfig,
+    output: VecDeque<EncodedFrame>,
+    frame_number: u64,
+    force_keyframe: bool,
+    last_keyframe_pts_us: u64,
+}
+
+impl NullEncoder {
+    pub fn new(config: EncoderConfig) -> Self {
+        Self {
+            config,
+            output: VecDeque::new(),
+            frame_number: 0,
+            force_keyframe: true,
+            last_keyframe_pts_us: 0,
+        }
+    }
+}
+
+impl VideoEncoder for NullEncoder {
+    fn encode_raw(
+        &mut self,
+        input: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        self.config.input_format.check_input(
+            input.len(),
+            stride,
+            self.config.width,
+            self.config.height,
+        )?;
+
+        let interval = self.config.keyframe_interval.as_micros() as u64;
+        let is_keyframe = force_keyframe
+            || self.force_keyframe
+            || pts_us.saturating_sub(self.last_keyframe_pts_us) >= interval;
+        self.force_keyframe = false;
+        if is_keyframe {
+            self.last_keyframe_pts_us = pts_us;
+        }
+
+        let coded = self.config.coded_size();
+        let mut data = Vec::with_capacity(NULL_FRAME_HEADER_SIZE);
+        data.extend_from_slice(&coded.width.to_le_bytes());
+        data.extend_from_slice(&coded.height.to_le_bytes());
+
+        let metadata = FrameMetadata::new(self.frame_number, pts_us, pts_us, is_keyframe);
+        self.output.push_back(EncodedFrame::new(metadata, data));
+        self.frame_number += 1;
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Option<EncodedFrame> {
+        self.output.pop_front()
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        Ok(())
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+        self.config.rate_control = self.config.rate_control.with_bitrate(bitrate)?;
+        Ok(())
+    }
+
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+        self.config = config;
+        self.force_keyframe = true;
+        Ok(())
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+}
+
+/// Decoder for `NullEncoder` output: produces a mid-gray frame of the
+/// encoded dimensions
+#[derive(Debug, Default)]
+pub struct PassthroughDecoder {
+    frames_decoded: u64,
+    output_size: Option<Resolution>,
+}
+
+impl PassthroughDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames decoded since creation or the last `reconfigure`
+    pub fn frames_decoded(&self) -> u64 {
+        self.frames_decoded
+    }
+}
+
+impl VideoDecoder for PassthroughDecoder {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        if data.len() < NULL_FRAME_HEADER_SIZE {
+            return Err(DecodeError::InvalidFrameData);
+        }
+
+        let width = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+            return Err(DecodeError::InvalidFrameData);
+        }
+
+        let size = (width * height) as usize * 3 / 2;
+        let mut frame = DecodedFrame::new(
+            self.frames_decoded,
+            pts_us.max(0) as u64,
+            width,
+            height,
+            vec![128u8; size],
+        );
+        frame.source_timestamp_us =
+            LatencySei::find(&data[NULL_FRAME_HEADER_SIZE..]).map(|sei| sei.capture_ts_us);
+        self.frames_decoded += 1;
+        Ok(DecodeOutput::tracking_size(
+            vec![frame],
+            &mut self.output_size,
+        ))
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        Ok(Vec::new())
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        self.frames_decoded = 0;
+        Ok(())
+    }
+}
+
+/// Whether an Annex B access unit contains an IDR slice
+pub fn contains_idr(data: &[u8]) -> bool {
+    data.windows(4)
+        .any(|w| w[..3] == [0, 0, 1] && w[3] & 0x1F == 5)
+}
+
+/// Wraps a decoder so that a decode error doesn't smear corrupt output
+/// across the following frames.
+///
+/// After an error the inner decoder is flushed (output discarded) and every
+/// access unit up to the next IDR is dropped, since P-frames referencing the
+/// broken state would decode to garbage.
+#[derive(Debug)]
+pub struct ResilientDecoder<D> {
+    inner: D,
+    needs_keyframe: bool,
+    frames_dropped: u64,
+}
+
+impl<D: VideoDecoder> ResilientDecoder<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            needs_keyframe: false,
+            frames_dropped: 0,
+        }
+    }
+
+    /// Whether input is being dropped until a keyframe arrives. The caller
+    /// should ask the source for a keyframe while this is set.
+    pub fn needs_keyframe(&self) -> bool {
+        self.needs_keyframe
+    }
+
+    /// Access units dropped while waiting for a keyframe
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Decode an access unit whose keyframe status is already known
+    pub fn decode_frame(
+        &mut self,
+        data: &[u8],
+        pts_us: i64,
+        is_keyframe: bool,
+    ) -> Result<DecodeOutput, DecodeError> {
+        if self.needs_keyframe {
+            if !is_keyframe {
+                self.frames_dropped += 1;
+                return Ok(DecodeOutput::default());
+            }
+            self.needs_keyframe = false;
+        }
+
+        match self.inner.decode(data, pts_us) {
+            Ok(output) => Ok(output),
+            Err(e) => {
+                // Whatever the decoder still holds was produced from the
+                // broken state
+                let _ = self.inner.flush();
+                self.needs_keyframe = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<D: VideoDecoder> VideoDecoder for ResilientDecoder<D> {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        let is_keyframe = contains_idr(data);
+        self.decode_frame(data, pts_us, is_keyframe)
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        self.inner.flush()
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        self.needs_keyframe = false;
+        self.inner.reconfigure()
+    }
+
+    fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+        self.inner.set_skip_mode(mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::ColorMatrix;
+
+    fn config(width: u32, height: u32) -> EncoderConfig {
+        EncoderConfig {
+            width,
+            height,
+            keyframe_interval: Duration::from_millis(3),
+            ..Default::default()
+        }
+    }
+
+    fn encode(encoder: &mut NullEncoder, pts_us: u64) -> EncodedFrame {
+        let (width, height) = (encoder.config().width, encoder.config().height);
+        let bgra = vec![0u8; (width * height * 4) as usize];
+        encoder
+            .encode_raw(&bgra, width as usize * 4, pts_us, false)
+            .unwrap();
+        encoder.next_frame().unwrap()
+    }
+
+    #[test]
+    fn test_rate_control_validation() {
+        for valid in [
+            RateControl::default(),
+            RateControl::Quality { value: 0.0 },
+            RateControl::Quality { value: 1.0 },
+            RateControl::ConstrainedQuality {
+                value: 0.5,
+                max_bps: 1,
+            },
+        ] {
+            assert!(valid.validate().is_ok(), "{:?}", valid);
+        }
+        for invalid in [
+            RateControl::ConstantBitrate { bps: 0 },
+            RateControl::Quality { value: -0.1 },
+            RateControl::Quality { value: 1.5 },
+            RateControl::Quality { value: f32::NAN },
+            RateControl::ConstrainedQuality {
+                value: 0.5,
+                max_bps: 0,
+            },
+        ] {
+            assert!(
+                matches!(
+                    invalid.validate(),
+                    Err(EncodeError::InvalidConfiguration(_))
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_encoder_config_resolution() {
+        for (width, height, coded) in [
+            (1366, 768, (1366, 768)),
+            (1367, 769, (1368, 770)),
+            (1, 1, (2, 2)),
+        ] {
+            let encoder_config = config(width, height);
+            assert!(encoder_config.validate().is_ok());
+            let size = encoder_config.coded_size();
+            assert_eq!((size.width, size.height), coded);
+        }
+        for (width, height) in [(0, 1080), (1920, 0), (100_000, 1080)] {
+            assert!(matches!(
+                config(width, height).validate(),
+                Err(EncodeError::InvalidResolution { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_null_encoder_pads_odd_sizes() {
+        let mut encoder = NullEncoder::new(config(1367, 769));
+        let stride = 1367 * 4;
+        encoder
+            .encode_raw(&vec![0u8; stride * 769], stride, 0, false)
+            .unwrap();
+        let frame = encoder.next_frame().unwrap();
+
+        let decoded = PassthroughDecoder::new()
+            .decode(&frame.data, 0)
+            .unwrap()
+            .frames;
+        assert_eq!((decoded[0].width, decoded[0].height), (1368, 770));
+    }
+
+    #[test]
+    fn test_keyframe_interval_frames() {
+        let config = EncoderConfig::default().with_keyframe_interval_frames(120);
+        assert_eq!(config.keyframe_interval, Duration::from_secs(2));
+        assert_eq!(config.keyframe_interval_frames(), 120);
+
+        // The same duration is fewer frames at a lower rate
+        let config = EncoderConfig { fps: 30, ..config };
+        assert_eq!(config.keyframe_interval_frames(), 60);
+
+        let config = EncoderConfig {
+            keyframe_interval: Duration::from_micros(1),
+            ..config
+        };
+        assert_eq!(config.keyframe_interval_frames(), 1);
+
+        let config = EncoderConfig {
+            keyframe_interval: Duration::ZERO,
+            ..config
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(EncodeError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_bitrate_per_rate_control() {
+        let mut encoder = NullEncoder::new(EncoderConfig {
+            rate_control: RateControl::ConstrainedQuality {
+                value: 0.6,
+                max_bps: 8_000_000,
+            },
+            ..Default::default()
+        });
+        encoder.set_bitrate(4_000_000).unwrap();
+        assert_eq!(
+            encoder.config().rate_control,
+            RateControl::ConstrainedQuality {
+                value: 0.6,
+                max_bps: 4_000_000
+            }
+        );
+
+        let mut encoder = NullEncoder::new(EncoderConfig {
+            rate_control: RateControl::Quality { value: 0.6 },
+            ..Default::default()
+        });
+        assert!(encoder.set_bitrate(4_000_000).is_err());
+        assert_eq!(encoder.config().rate_control.bitrate(), None);
+    }
+
+    #[test]
+    fn test_baseline_implies_cavlc_without_bframes() {
+        let config = EncoderConfig {
+            profile: H264Profile::Baseline,
+            allow_bframes: true,
+            entropy: Some(EntropyMode::Cabac),
+            ..Default::default()
+        };
+        assert!(!config.bframes_enabled());
+        assert_eq!(config.entropy_mode(), Some(EntropyMode::Cavlc));
+
+        let config = EncoderConfig {
+            profile: H264Profile::Main,
+            ..config
+        };
+        assert!(config.bframes_enabled());
+        assert_eq!(config.entropy_mode(), Some(EntropyMode::Cabac));
+
+        let default = EncoderConfig::default();
+        assert!(!default.bframes_enabled());
+        assert_eq!(default.entropy_mode(), None);
+    }
+
+    #[test]
+    fn test_null_encoder_keyframes() {
+        let mut encoder = NullEncoder::new(config(64, 32));
+        let keyframes: Vec<bool> = (0..7)
+            .map(|i| encode(&mut encoder, i * 1000).metadata.is_keyframe)
+            .collect();
+        assert_eq!(keyframes, [true, false, false, true, false, false, true]);
+
+        // Reconfiguring forces a keyframe
+        encoder.reconfigure(config(32, 16)).unwrap();
+        let frame = encode(&mut encoder, 7000);
+        assert!(frame.metadata.is_keyframe);
+    }
+
+    #[test]
+    fn test_null_encoder_rejects_short_input() {
+        let mut encoder = NullEncoder::new(config(64, 32));
+        assert!(encoder.encode_raw(&[0u8; 16], 256, 0, false).is_err());
+        assert!(encoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn test_input_frame_sizes() {
+        let bgra = InputPixelFormat::Bgra;
+        assert_eq!(bgra.frame_len(1920, 1080, 1920 * 4), 1920 * 1080 * 4);
+        assert_eq!(bgra.frame_len(4, 2, 32), 32 + 16);
+
+        let nv12 = InputPixelFormat::Nv12;
+        assert_eq!(nv12.frame_len(1920, 1080, 1920), 1920 * 1080 * 3 / 2);
+        // 1080 luma rows and 540 chroma rows, the last without padding
+        assert_eq!(nv12.frame_len(1920, 1080, 2048), 2048 * 1619 + 1920);
+        // Odd sizes round the chroma plane up: 3 rows of 6 bytes
+        assert_eq!(nv12.planes(5, 5), vec![(5, 5), (6, 3)]);
+        assert_eq!(nv12.frame_len(5, 5, 8), 8 * 7 + 6);
+        assert_eq!(nv12.frame_len(0, 0, 0), 0);
+
+        assert!(nv12.check_input(1920 * 1620, 1920, 1920, 1080).is_ok());
+        assert!(nv12.check_input(1920 * 1620 - 1, 1920, 1920, 1080).is_err());
+        // The chroma row is wider than the luma row for odd widths
+        assert!(nv12.check_input(100, 5, 5, 5).is_err());
+        assert!(bgra.check_input(1920 * 1620, 1920, 1920, 1080).is_err());
+    }
+
+    #[test]
+    fn test_black_frames() {
+        let (bgra, stride) = InputPixelFormat::Bgra.black_frame(3, 2, false);
+        assert_eq!(stride, 12);
+        assert_eq!(bgra.len(), 24);
+        assert!(bgra.chunks(4).all(|pixel| pixel == [0, 0, 0, 255]));
+
+        // Luma rows of 5 and chroma rows of 6 share the wider stride
+        let (nv12, stride) = InputPixelFormat::Nv12.black_frame(5, 3, false);
+        assert_eq!(stride, 6);
+        assert_eq!(nv12.len(), InputPixelFormat::Nv12.frame_len(5, 3, 6));
+        assert!(nv12[..18].iter().all(|&y| y == 16));
+        assert!(nv12[18..].iter().all(|&c| c == 128));
+
+        let config = EncoderConfig {
+            input_format: InputPixelFormat::Nv12,
+            color_space: ColorSpace {
+                matrix: ColorMatrix::Bt709Full,
+                ..Default::default()
+            },
+            ..config(4, 4)
+        };
+        let (full, stride) = config.black_frame();
+        assert_eq!(full[..16], [0; 16]);
+        assert!(NullEncoder::new(config)
+            .encode_raw(&full, stride, 0, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_null_encoder_nv12_input() {
+        let mut encoder = NullEncoder::new(EncoderConfig {
+            input_format: InputPixelFormat::Nv12,
+            ..config(64, 32)
+        });
+        assert!(encoder.encode_raw(&[0u8; 64 * 48], 64, 0, false).is_ok());
+        assert!(encoder.encode_raw(&[0u8; 64 * 32], 64, 0, false).is_err());
+        assert_eq!(encoder.output.len(), 1);
+    }
+
+    #[test]
+    fn test_passthrough_roundtrip() {
+        let mut encoder = NullEncoder::new(config(64, 32));
+        let mut decoder = PassthroughDecoder::new();
+
+        let encoded = encode(&mut encoder, 5000);
+        let output = decoder.decode(&encoded.data, 5000).unwrap();
+        let decoded = &output.frames;
+        assert_eq!(decoded.len(), 1);
+        assert_eq!((decoded[0].width, decoded[0].height), (64, 32));
+        assert_eq!(decoded[0].pts_us, 5000);
+        assert_eq!(decoded[0].y_plane().len(), 64 * 32);
+        assert!(output.events.is_empty());
+
+        // Mixing in a resolution change works without reconfiguring the
+        // decoder, and is reported
+        encoder.reconfigure(config(32, 16)).unwrap();
+        let encoded = encode(&mut encoder, 6000);
+        let output = decoder.decode(&encoded.data, 6000).unwrap();
+        assert_eq!((output.frames[0].width, output.frames[0].height), (32, 16));
+        assert_eq!(
+            output.events,
+            [DecodeEvent::ResolutionChanged {
+                old: Resolution::new(64, 32),
+                new: Resolution::new(32, 16),
+            }]
+        );
+        assert_eq!(decoder.frames_decoded(), 2);
+    }
+
+    #[test]
+    fn test_decode_output_tracking_size() {
+        let frame = |width, height| DecodedFrame::new(0, 0, width, height, vec![0; 64 * 3 / 2]);
+        let mut last_size = None;
+
+        let output = DecodeOutput::tracking_size(vec![frame(4, 4), frame(4, 4)], &mut last_size);
+        assert!(output.events.is_empty());
+        assert_eq!(last_size, Some(Resolution::new(4, 4)));
+
+        // Back and forth within one output
+        let output = DecodeOutput::tracking_size(
+            vec![frame(8, 8), frame(4, 4), frame(4, 4)],
+            &mut last_size,
+        );
+        assert_eq!(output.frames.len(), 3);
+        assert_eq!(
+            output.events,
+            [
+                DecodeEvent::ResolutionChanged {
+                    old: Resolution::new(4, 4),
+                    new: Resolution::new(8, 8),
+                },
+                DecodeEvent::ResolutionChanged {
+                    old: Resolution::new(8, 8),
+                    new: Resolution::new(4, 4),
+                },
+            ]
+        );
+
+        // No frames leaves the size alone
+        assert!(DecodeOutput::tracking_size(Vec::new(), &mut last_size)
+            .events
+            .is_empty());
+        assert_eq!(last_size, Some(Resolution::new(4, 4)));
+    }
+
+    #[test]
+    fn test_passthrough_reads_latency_sei() {
+        let mut encoder = NullEncoder::new(config(64, 32));
+        let mut decoder = PassthroughDecoder::new();
+
+        let encoded = encode(&mut encoder, 5000);
+        assert_eq!(
+            decoder.decode(&encoded.data, 5000).unwrap().frames[0].source_timestamp_us,
+            None
+        );
+
+        let data = LatencySei::new(1_700_000_000_000_000, 1).insert_into(&encoded.data);
+        let decoded = decoder.decode(&data, 5000).unwrap().frames;
+        assert_eq!(decoded[0].source_timestamp_us, Some(1_700_000_000_000_000));
+        assert_eq!((decoded[0].width, decoded[0].height), (64, 32));
+    }
+
+    #[test]
+    fn test_passthrough_rejects_garbage() {
+        let mut decoder = PassthroughDecoder::new();
+        assert!(decoder.decode(&[1, 2, 3], 0).is_err());
+        assert!(decoder.decode(&[0u8; 8], 0).is_err());
+    }
+
+    const IDR: &[u8] = &[0, 0, 0, 1, 0x65, 0x88];
+    const P_FRAME: &[u8] = &[0, 0, 0, 1, 0x41, 0x9A];
+    const CORRUPT: &[u8] = &[0, 0, 0, 1, 0x41, 0xFF];
+
+    /// Decoder that fails on `CORRUPT` and then emits garbage (luma 0) for
+    /// every frame until it sees an IDR
+    #[derive(Default)]
+    struct FlakyDecoder {
+        broken: bool,
+        flushed: u32,
+        skip_mode: SkipMode,
+    }
+
+    impl VideoDecoder for FlakyDecoder {
+        fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+            if data == CORRUPT {
+                self.broken = true;
+                return Err(DecodeError::DecodingFailed("corrupt slice".to_string()));
+            }
+            if contains_idr(data) {
+                self.broken = false;
+            }
+            let luma = if self.broken { 0 } else { 128 };
+            Ok(DecodeOutput {
+                frames: vec![DecodedFrame::new(0, pts_us as u64, 2, 2, vec![luma; 6])],
+                events: Vec::new(),
+            })
+        }
+
+        fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+            self.flushed += 1;
+            Ok(vec![DecodedFrame::new(0, 0, 2, 2, vec![0; 6])])
+        }
+
+        fn reconfigure(&mut self) -> Result<(), DecodeError> {
+            self.broken = false;
+            Ok(())
+        }
+
+        fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+            self.skip_mode = mode;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_contains_idr() {
+        assert!(contains_idr(IDR));
+        assert!(contains_idr(&[&[0, 0, 1, 0x67, 0x42][..], IDR].concat()));
+        assert!(!contains_idr(P_FRAME));
+        assert!(!contains_idr(&[]));
+    }
+
+    #[test]
+    fn test_resilient_decoder_recovers_at_idr() {
+        let mut decoder = ResilientDecoder::new(FlakyDecoder::default());
+        let stream = [IDR, P_FRAME, CORRUPT, P_FRAME, P_FRAME, IDR, P_FRAME];
+
+        let mut output = Vec::new();
+        let mut errors = 0;
+        for (pts, data) in stream.iter().enumerate() {
+            match decoder.decode(data, pts as i64) {
+                Ok(decoded) => output.extend(decoded.frames),
+                Err(_) => {
+                    errors += 1;
+                    assert!(decoder.needs_keyframe());
+                }
+            }
+        }
+
+        assert_eq!(errors, 1);
+        assert!(!decoder.needs_keyframe());
+        assert_eq!(decoder.frames_dropped(), 2);
+        assert_eq!(decoder.inner().flushed, 1);
+
+        // Only clean frames made it out: before the error and from the IDR on
+        let pts: Vec<u64> = output.iter().map(|frame| frame.pts_us).collect();
+        assert_eq!(pts, [0, 1, 5, 6]);
+        assert!(output.iter().all(|frame| frame.y_plane()[0] == 128));
+    }
+
+    #[test]
+    fn test_resilient_decoder_explicit_keyframe_flag() {
+        let mut decoder = ResilientDecoder::new(FlakyDecoder::default());
+        assert!(decoder.decode_frame(CORRUPT, 0, false).is_err());
+
+        // Flagged as a keyframe by the caller, even without NAL parsing
+        assert!(decoder
+            .decode_frame(P_FRAME, 1, false)
+            .unwrap()
+            .frames
+            .is_empty());
+        assert_eq!(decoder.decode_frame(IDR, 2, true).unwrap().frames.len(), 1);
+        assert!(!decoder.needs_keyframe());
+    }
+
+    #[test]
+    fn test_skip_mode_reaches_inner_decoder() {
+        let mut decoder = ResilientDecoder::new(Box::new(FlakyDecoder::default()));
+        decoder.set_skip_mode(SkipMode::NonKey).unwrap();
+        assert_eq!(decoder.inner().skip_mode, SkipMode::NonKey);
+
+        // Decoders that can't skip accept any mode
+        let mut passthrough: Box<dyn VideoDecoder> = Box::new(PassthroughDecoder::new());
+        passthrough.set_skip_mode(SkipMode::NonRef).unwrap();
+        assert!(SkipMode::None < SkipMode::NonRef && SkipMode::NonRef < SkipMode::NonKey);
+    }
+
+    #[test]
+    fn test_codecs_as_trait_objects() {
+        let mut encoder: Box<dyn VideoEncoder> = Box::new(NullEncoder::new(config(64, 32)));
+        let mut decoder: Box<dyn VideoDecoder> = Box::new(PassthroughDecoder::new());
+
+        encoder
+            .encode_raw(&[0u8; 64 * 32 * 4], 256, 0, true)
+            .unwrap();
+        encoder.flush().unwrap();
+        let frame = encoder.next_frame().unwrap();
+        assert!(frame.metadata.is_keyframe);
+        assert_eq!(decoder.decode(&frame.data, 0).unwrap().frames.len(), 1);
+        assert!(decoder.flush().unwrap().is_empty());
+    }
+}
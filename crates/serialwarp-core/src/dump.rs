@@ -0,0 +1,346 @@
+//! Raw H.264 elementary stream dumps for encoder debugging
+//!
+//! Every frame's Annex B data is appended to a `.h264` file that plays in any
+//! H.264-aware tool, alongside a newline-delimited JSON index (`<file>.jsonl`)
+//! describing where each frame starts.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::frame::EncodedFrame;
+use crate::rotation::rotated_path;
+
+/// Default number of frames buffered between the caller and the writer thread
+pub const DEFAULT_DUMP_QUEUE_DEPTH: usize = 64;
+
+/// Stream dump configuration
+#[derive(Debug, Clone)]
+pub struct StreamDumpConfig {
+    /// Path of the first `.h264` file; rotated files get a `-N` suffix
+    pub path: PathBuf,
+    /// Start a new file at the next keyframe once this many bytes are written
+    pub max_file_bytes: Option<u64>,
+    /// Frames buffered before new frames are dropped
+    pub queue_depth: usize,
+}
+
+impl StreamDumpConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_file_bytes: None,
+            queue_depth: DEFAULT_DUMP_QUEUE_DEPTH,
+        }
+    }
+}
+
+/// Dump counters, returned when the dump finishes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamDumpStats {
+    /// Frames written to disk
+    pub frames_written: u64,
+    /// Frames dropped because the writer fell behind
+    pub frames_dropped: u64,
+    /// Files created
+    pub files_written: u64,
+}
+
+/// One line of the JSON index sidecar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpIndexEntry {
+    pub frame_number: u64,
+    pub pts_us: u64,
+    #[serde(rename = "keyframe")]
+    pub is_keyframe: bool,
+    /// Byte offset of the frame in its `.h264` file
+    pub offset: u64,
+    pub size: u64,
+}
+
+enum DumpMessage {
+    Frame(EncodedFrame),
+    Finish,
+}
+
+/// Appends encoded frames to an Annex B file from a background thread.
+///
+/// `write` never blocks: frames that don't fit in the queue are dropped and
+/// counted.
+pub struct StreamDump {
+    sender: SyncSender<DumpMessage>,
+    thread: Option<JoinHandle<io::Result<StreamDumpStats>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl StreamDump {
+    /// Create the first dump file and start the writer thread
+    pub fn start(config: StreamDumpConfig) -> io::Result<Self> {
+        let writer = DumpWriter::create(config.path, config.max_file_bytes)?;
+        let (sender, receiver) = mpsc::sync_channel(config.queue_depth.max(1));
+        let thread = std::thread::Builder::new()
+            .name("serialwarp-dump".to_string())
+            .spawn(move || writer.run(receiver))?;
+
+        Ok(Self {
+            sender,
+            thread: Some(thread),
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Queue a frame for writing
+    pub fn write(&self, frame: &EncodedFrame) {
+        match self.sender.try_send(DumpMessage::Frame(frame.clone())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Frames dropped so far
+    pub fn frames_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write out queued frames, flush the files and stop the writer thread
+    pub fn finish(mut self) -> io::Result<StreamDumpStats> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<StreamDumpStats> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(StreamDumpStats::default());
+        };
+
+        let _ = self.sender.send(DumpMessage::Finish);
+        let mut stats = thread
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "dump thread panicked"))??;
+        stats.frames_dropped = self.frames_dropped();
+        Ok(stats)
+    }
+}
+
+impl Drop for StreamDump {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Writer-thread state for the current `.h264` file and its index
+struct DumpWriter {
+    base_path: PathBuf,
+    max_file_bytes: Option<u64>,
+    stream: BufWriter<File>,
+    index: BufWriter<File>,
+    offset: u64,
+    stats: StreamDumpStats,
+}
+
+impl DumpWriter {
+    fn create(base_path: PathBuf, max_file_bytes: Option<u64>) -> io::Result<Self> {
+        let (stream, index) = open_files(&base_path)?;
+        Ok(Self {
+            base_path,
+            max_file_bytes,
+            stream,
+            index,
+            offset: 0,
+            stats: StreamDumpStats {
+                files_written: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn run(mut self, receiver: Receiver<DumpMessage>) -> io::Result<StreamDumpStats> {
+        while let Ok(DumpMessage::Frame(frame)) = receiver.recv() {
+            self.write_frame(&frame)?;
+        }
+
+        self.stream.flush()?;
+        self.index.flush()?;
+        Ok(self.stats)
+    }
+
+    fn write_frame(&mut self, frame: &EncodedFrame) -> io::Result<()> {
+        // Only rotate at keyframes so every file decodes on its own
+        let over_cap = self
+            .max_file_bytes
+            .is_some_and(|cap| self.offset > 0 && self.offset + frame.data.len() as u64 > cap);
+        if over_cap && frame.metadata.is_keyframe {
+            self.rotate()?;
+        }
+
+        let entry = DumpIndexEntry {
+            frame_number: frame.metadata.frame_number,
+            pts_us: frame.metadata.pts_us,
+            is_keyframe: frame.metadata.is_keyframe,
+            offset: self.offset,
+            size: frame.data.len() as u64,
+        };
+
+        self.stream.write_all(&frame.data)?;
+        serde_json::to_writer(&mut self.index, &entry)?;
+        self.index.write_all(b"\n")?;
+        self.offset += entry.size;
+        self.stats.frames_written += 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.stream.flush()?;
+        self.index.flush()?;
+
+        let path = rotated_path(&self.base_path, self.stats.files_written as usize);
+        let (stream, index) = open_files(&path)?;
+        self.stream = stream;
+        self.index = index;
+        self.offset = 0;
+        self.stats.files_written += 1;
+        Ok(())
+    }
+}
+
+fn open_files(path: &Path) -> io::Result<(BufWriter<File>, BufWriter<File>)> {
+    let stream = BufWriter::new(File::create(path)?);
+    let index = BufWriter::new(File::create(index_path(path))?);
+    Ok((stream, index))
+}
+
+/// Path of the JSON index sidecar for a dump file
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".jsonl");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::FrameMetadata;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("serialwarp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn synthetic_frame(frame_number: u64, is_keyframe: bool, len: usize) -> EncodedFrame {
+        // Annex B start code followed by a recognizable body
+        let mut data = vec![0, 0, 0, 1, if is_keyframe { 0x65 } else { 0x41 }];
+        data.resize(len, frame_number as u8);
+        EncodedFrame::new(
+            FrameMetadata::new(frame_number, frame_number * 16_667, 0, is_keyframe),
+            data,
+        )
+    }
+
+    fn read_index(path: &Path) -> Vec<DumpIndexEntry> {
+        std::fs::read_to_string(index_path(path))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_index_entry_roundtrip() {
+        let entry = DumpIndexEntry {
+            frame_number: 42,
+            pts_us: 700_014,
+            is_keyframe: true,
+            offset: 1024,
+            size: 512,
+        };
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(
+            json,
+            r#"{"frame_number":42,"pts_us":700014,"keyframe":true,"offset":1024,"size":512}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DumpIndexEntry>(&json).unwrap(),
+            entry
+        );
+    }
+
+    #[test]
+    fn test_dump_index_matches_stream() {
+        let dir = test_dir("dump");
+        let path = dir.join("stream.h264");
+        let frames = vec![
+            synthetic_frame(0, true, 300),
+            synthetic_frame(1, false, 40),
+            synthetic_frame(2, false, 75),
+        ];
+
+        let dump = StreamDump::start(StreamDumpConfig::new(&path)).unwrap();
+        for frame in &frames {
+            dump.write(frame);
+        }
+        let stats = dump.finish().unwrap();
+        assert_eq!(stats.frames_written, 3);
+        assert_eq!(stats.frames_dropped, 0);
+        assert_eq!(stats.files_written, 1);
+
+        let stream = std::fs::read(&path).unwrap();
+        let index = read_index(&path);
+        assert_eq!(index.len(), frames.len());
+        assert_eq!(
+            stream.len() as u64,
+            index.iter().map(|e| e.size).sum::<u64>()
+        );
+
+        for (entry, frame) in index.iter().zip(&frames) {
+            let start = entry.offset as usize;
+            let end = start + entry.size as usize;
+            assert_eq!(&stream[start..end], &frame.data[..]);
+            assert_eq!(entry.frame_number, frame.metadata.frame_number);
+            assert_eq!(entry.pts_us, frame.metadata.pts_us);
+            assert_eq!(entry.is_keyframe, frame.metadata.is_keyframe);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dump_rotates_at_keyframes() {
+        let dir = test_dir("dump-rotate");
+        let path = dir.join("stream.h264");
+        let config = StreamDumpConfig {
+            max_file_bytes: Some(100),
+            ..StreamDumpConfig::new(&path)
+        };
+
+        let dump = StreamDump::start(config).unwrap();
+        dump.write(&synthetic_frame(0, true, 60));
+        // Over the cap, but not a keyframe: stays in the first file
+        dump.write(&synthetic_frame(1, false, 60));
+        dump.write(&synthetic_frame(2, true, 60));
+        let stats = dump.finish().unwrap();
+        assert_eq!(stats.files_written, 2);
+
+        let first = read_index(&path);
+        assert_eq!(first.len(), 2);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 120);
+
+        let rotated = rotated_path(&path, 1);
+        assert_eq!(rotated, dir.join("stream-1.h264"));
+        let second = read_index(&rotated);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].frame_number, 2);
+        assert_eq!(second[0].offset, 0);
+        assert_eq!(std::fs::read(&rotated).unwrap()[4], 0x65);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -24,9 +24,18 @@ pub enum ProtocolError {
     #[error("invalid sequence number: expected {expected}, got {actual}")]
     InvalidSequence { expected: u32, actual: u32 },
 
+    #[error("packet type 0x{0:02X} must carry a CRC")]
+    CrcRequired(u8),
+
+    #[error("payload decompression failed: {0}")]
+    DecompressionFailed(String),
+
     #[error("unexpected packet type: expected {expected}, got {actual}")]
     UnexpectedPacketType { expected: &'static str, actual: u8 },
 
+    #[error("invalid resolution {width}x{height}")]
+    InvalidResolution { width: u32, height: u32 },
+
     #[error("handshake failed: {0}")]
     HandshakeFailed(String),
 
@@ -49,6 +58,18 @@ pub enum TransportError {
     #[error("USB error: {0}")]
     UsbError(String),
 
+    #[error("permission denied opening the USB device")]
+    PermissionDenied {
+        /// udev rule granting access to the device, on Linux
+        suggested_udev_rule: Option<String>,
+    },
+
+    #[error("no WinUSB driver is bound to {device}")]
+    DriverMissing { device: String },
+
+    #[error("USB device is in use by another program or driver")]
+    DeviceBusy,
+
     #[error("I/O error: {0}")]
     IoError(String),
 
@@ -59,8 +80,47 @@ pub enum TransportError {
     ChannelClosed,
 }
 
-/// Video encoding errors (macOS only)
-#[derive(Debug, Error)]
+impl TransportError {
+    /// What the user can do about this error, for errors with a known fix
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            TransportError::PermissionDenied {
+                suggested_udev_rule: Some(rule),
+            } => Some(format!(
+                "Allow access with a udev rule: save this line as \
+                 /etc/udev/rules.d/99-serialwarp.rules, run \
+                 `sudo udevadm control --reload-rules && sudo udevadm trigger`, \
+                 and replug the cable:\n{}",
+                rule
+            )),
+            TransportError::PermissionDenied {
+                suggested_udev_rule: None,
+            } => Some("Run with permission to access USB devices".to_string()),
+            TransportError::DriverMissing { device } => Some(format!(
+                "Install the WinUSB driver for {} with Zadig (https://zadig.akeo.ie): \
+                 Options > List All Devices, select the cable, choose WinUSB, and \
+                 click Install Driver",
+                device
+            )),
+            TransportError::DeviceBusy => Some(
+                "Close other programs using the cable, including other serialwarp \
+                 instances, and try again"
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Errors in a user-supplied USB device id
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DeviceIdError {
+    #[error("invalid USB device {0:?}: expected VID:PID in hex, e.g. 067b:27a1")]
+    Malformed(String),
+}
+
+/// Video encoding errors
+#[derive(Debug, Clone, Error)]
 pub enum EncodeError {
     #[error("encoder session creation failed with status: {0}")]
     SessionCreationFailed(i32),
@@ -86,8 +146,23 @@ pub enum EncodeError {
     #[error("invalid input: {0}")]
     InvalidInput(String),
 
+    #[error("encoder configuration invalid: {0}")]
+    InvalidConfiguration(String),
+
+    #[error("resolution {width}x{height} is outside 1x1 to {max}x{max}", max = crate::MAX_DIMENSION)]
+    InvalidResolution { width: u32, height: u32 },
+
     #[error("pixel buffer operation failed with status: {0}")]
     PixelBufferFailed(i32),
+
+    #[error("encoder backend unavailable: {0}")]
+    BackendUnavailable(String),
+
+    #[error("software encoder error: {0}")]
+    SoftwareEncoderFailed(String),
+
+    #[error("encoder output closed")]
+    OutputClosed,
 }
 
 /// Video decoding errors
@@ -115,6 +190,19 @@ pub enum DecodeError {
     FfmpegError(String),
 }
 
+/// Stream recording errors
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("failed to create recording: {0}")]
+    OutputFailed(String),
+
+    #[error("failed to write recording: {0}")]
+    WriteFailed(String),
+
+    #[error("recorder thread panicked")]
+    WriterPanicked,
+}
+
 /// Screen capture errors (macOS only)
 #[derive(Debug, Error)]
 pub enum CaptureError {
@@ -132,6 +220,14 @@ pub enum CaptureError {
 
     #[error("capture failed: {0}")]
     CaptureFailed(String),
+
+    #[error("capture update failed: {0}")]
+    UpdateFailed(String),
+
+    /// The system stopped capture, e.g. the display went away or permission
+    /// was revoked. Starting capture again may work.
+    #[error("capture stopped: {0}")]
+    Stopped(String),
 }
 
 /// Virtual display errors (macOS only)
@@ -170,4 +266,460 @@ pub enum RenderError {
 
     #[error("render failed: {0}")]
     RenderFailed(String),
+
+    #[error("invalid display index {index} ({available} displays available)")]
+    InvalidDisplay { index: usize, available: usize },
+
+    #[error("display query failed: {0}")]
+    DisplayQueryFailed(String),
+
+    #[error("invalid key binding: {0}")]
+    InvalidKeyBinding(String),
+
+    #[error("render thread stopped")]
+    Stopped,
+
+    #[error("windows can only be opened on the main thread on this platform")]
+    MainThreadOnly,
+}
+
+/// Streaming pipeline errors
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("pipeline already started")]
+    AlreadyStarted,
+
+    #[error(transparent)]
+    Capture(#[from] CaptureError),
+
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+}
+
+/// What went wrong, broadly, whichever crate the error came from. Kinds are
+/// stable, so callers can branch on them, e.g. to decide whether to retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Malformed or unexpected data from the other end
+    Protocol,
+    /// A packet failed its CRC, so the link is corrupting data
+    ChecksumMismatch,
+    /// The two ends couldn't agree on a stream
+    Handshake,
+    /// No supported device is attached
+    DeviceNotFound,
+    /// The link or the other end went away
+    Disconnected,
+    Timeout,
+    /// The OS won't let serialwarp use a device or capture the screen
+    PermissionDenied,
+    /// Another program has the device
+    DeviceBusy,
+    /// USB, serial, or file I/O failed
+    Io,
+    Encode,
+    Decode,
+    Capture,
+    /// Virtual display management
+    Display,
+    Render,
+    /// A request that doesn't fit the arguments, settings, or current state
+    InvalidInput,
+    Other,
+}
+
+impl ErrorKind {
+    /// Every kind, in declaration order
+    pub const ALL: [ErrorKind; 16] = [
+        ErrorKind::Protocol,
+        ErrorKind::ChecksumMismatch,
+        ErrorKind::Handshake,
+        ErrorKind::DeviceNotFound,
+        ErrorKind::Disconnected,
+        ErrorKind::Timeout,
+        ErrorKind::PermissionDenied,
+        ErrorKind::DeviceBusy,
+        ErrorKind::Io,
+        ErrorKind::Encode,
+        ErrorKind::Decode,
+        ErrorKind::Capture,
+        ErrorKind::Display,
+        ErrorKind::Render,
+        ErrorKind::InvalidInput,
+        ErrorKind::Other,
+    ];
+
+    /// Name used when serialized, e.g. `checksum_mismatch`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Protocol => "protocol",
+            ErrorKind::ChecksumMismatch => "checksum_mismatch",
+            ErrorKind::Handshake => "handshake",
+            ErrorKind::DeviceNotFound => "device_not_found",
+            ErrorKind::Disconnected => "disconnected",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::DeviceBusy => "device_busy",
+            ErrorKind::Io => "io",
+            ErrorKind::Encode => "encode",
+            ErrorKind::Decode => "decode",
+            ErrorKind::Capture => "capture",
+            ErrorKind::Display => "display",
+            ErrorKind::Render => "render",
+            ErrorKind::InvalidInput => "invalid_input",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// Whether the same operation may succeed if tried again unchanged:
+    /// the device may turn up, reconnect, or be released. Corrupt data, a
+    /// failed negotiation, or a missing permission won't fix themselves.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::DeviceNotFound
+                | ErrorKind::Disconnected
+                | ErrorKind::Timeout
+                | ErrorKind::DeviceBusy
+                | ErrorKind::Io
+        )
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Any serialwarp error, for code that works across crates, such as the
+/// binaries and apps.
+///
+/// Converts from every crate's error type, keeping the original as its
+/// source (see `downcast_ref`), and can be given context describing what
+/// was being attempted. Displays as the context followed by the error.
+pub struct SerialwarpError {
+    kind: ErrorKind,
+    context: Option<String>,
+    error: Box<dyn std::error::Error + Send + Sync + 'static>,
+}
+
+impl SerialwarpError {
+    /// An error with just a message
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            context: None,
+            error: message.into().into(),
+        }
+    }
+
+    /// Wrap any other error, e.g. from a dependency
+    pub fn with_source(
+        kind: ErrorKind,
+        error: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            context: None,
+            error: Box::new(error),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// See `ErrorKind::is_retryable`
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+
+    /// Say what was being attempted, e.g. "Failed to open USB transport".
+    /// Context added later goes in front.
+    pub fn context(mut self, context: impl Into<String>) -> Self {
+        let context = context.into();
+        self.context = Some(match self.context.take() {
+            Some(inner) => format!("{}: {}", context, inner),
+            None => context,
+        });
+        self
+    }
+
+    /// The error this was made from, if it's an `E`
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+}
+
+impl std::fmt::Display for SerialwarpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.context {
+            Some(context) => write!(f, "{}: {}", context, self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// The message and then each underlying cause, as printed when `main`
+/// returns an error
+impl std::fmt::Debug for SerialwarpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self, self.kind)?;
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            write!(f, "\n  caused by: {}", cause)?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SerialwarpError {
+    /// Causes below the wrapped error, which is already in the message
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// `{"kind": "timeout", "message": "..."}`, e.g. for a frontend
+#[cfg(feature = "serde")]
+impl serde::Serialize for SerialwarpError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("SerialwarpError", 2)?;
+        state.serialize_field("kind", self.kind.as_str())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Adds context to a failed result, converting its error to a
+/// `SerialwarpError`
+pub trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T, SerialwarpError>;
+
+    /// Like `context`, building the message only on failure
+    fn with_context<C: Into<String>>(self, f: impl FnOnce() -> C) -> Result<T, SerialwarpError>;
+}
+
+impl<T, E: Into<SerialwarpError>> Context<T> for Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T, SerialwarpError> {
+        self.map_err(|e| e.into().context(context))
+    }
+
+    fn with_context<C: Into<String>>(self, f: impl FnOnce() -> C) -> Result<T, SerialwarpError> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+impl ProtocolError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ProtocolError::ChecksumMismatch { .. } => ErrorKind::ChecksumMismatch,
+            ProtocolError::UnsupportedVersion(_) | ProtocolError::HandshakeFailed(_) => {
+                ErrorKind::Handshake
+            }
+            _ => ErrorKind::Protocol,
+        }
+    }
+}
+
+impl TransportError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TransportError::DeviceNotFound => ErrorKind::DeviceNotFound,
+            TransportError::Disconnected
+            | TransportError::ConnectionRefused
+            | TransportError::ChannelClosed => ErrorKind::Disconnected,
+            TransportError::Timeout { .. } => ErrorKind::Timeout,
+            TransportError::PermissionDenied { .. } | TransportError::DriverMissing { .. } => {
+                ErrorKind::PermissionDenied
+            }
+            TransportError::DeviceBusy => ErrorKind::DeviceBusy,
+            TransportError::UsbError(_) | TransportError::IoError(_) => ErrorKind::Io,
+        }
+    }
+}
+
+impl CaptureError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CaptureError::PermissionDenied => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Capture,
+        }
+    }
+}
+
+impl PipelineError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PipelineError::AlreadyStarted => ErrorKind::InvalidInput,
+            PipelineError::Capture(e) => e.kind(),
+            PipelineError::Encode(_) => ErrorKind::Encode,
+            PipelineError::Decode(_) => ErrorKind::Decode,
+            PipelineError::Transport(e) => e.kind(),
+            PipelineError::Protocol(e) => e.kind(),
+        }
+    }
+}
+
+macro_rules! impl_from_error {
+    ($($error:ty => $kind:expr),* $(,)?) => {
+        $(
+            impl From<$error> for SerialwarpError {
+                fn from(error: $error) -> Self {
+                    let kind: fn(&$error) -> ErrorKind = $kind;
+                    Self::with_source(kind(&error), error)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_error! {
+    ProtocolError => ProtocolError::kind,
+    TransportError => TransportError::kind,
+    DeviceIdError => |_| ErrorKind::InvalidInput,
+    EncodeError => |_| ErrorKind::Encode,
+    DecodeError => |_| ErrorKind::Decode,
+    RecordError => |_| ErrorKind::Io,
+    CaptureError => CaptureError::kind,
+    DisplayError => |_| ErrorKind::Display,
+    RenderError => |_| ErrorKind::Render,
+    PipelineError => PipelineError::kind,
+    std::io::Error => |_| ErrorKind::Io,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_kinds() {
+        let retryable: Vec<ErrorKind> = ErrorKind::ALL
+            .into_iter()
+            .filter(|kind| kind.is_retryable())
+            .collect();
+        assert_eq!(
+            retryable,
+            [
+                ErrorKind::DeviceNotFound,
+                ErrorKind::Disconnected,
+                ErrorKind::Timeout,
+                ErrorKind::DeviceBusy,
+                ErrorKind::Io,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kind_of_converted_errors() {
+        let cases: Vec<(SerialwarpError, ErrorKind, bool)> = vec![
+            (
+                TransportError::Timeout { duration_ms: 100 }.into(),
+                ErrorKind::Timeout,
+                true,
+            ),
+            (
+                TransportError::ChannelClosed.into(),
+                ErrorKind::Disconnected,
+                true,
+            ),
+            (
+                TransportError::DriverMissing {
+                    device: "dev".into(),
+                }
+                .into(),
+                ErrorKind::PermissionDenied,
+                false,
+            ),
+            (
+                ProtocolError::ChecksumMismatch {
+                    expected: 1,
+                    actual: 2,
+                }
+                .into(),
+                ErrorKind::ChecksumMismatch,
+                false,
+            ),
+            (
+                ProtocolError::UnsupportedVersion(9).into(),
+                ErrorKind::Handshake,
+                false,
+            ),
+            (
+                PipelineError::Transport(TransportError::DeviceBusy).into(),
+                ErrorKind::DeviceBusy,
+                true,
+            ),
+            (
+                CaptureError::PermissionDenied.into(),
+                ErrorKind::PermissionDenied,
+                false,
+            ),
+            (DecodeError::OpenFailed.into(), ErrorKind::Decode, false),
+            (
+                DeviceIdError::Malformed("x".into()).into(),
+                ErrorKind::InvalidInput,
+                false,
+            ),
+        ];
+        for (error, kind, retryable) in cases {
+            assert_eq!(error.kind(), kind, "{}", error);
+            assert_eq!(error.is_retryable(), retryable, "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_context_and_source_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "bad sector");
+        let result: Result<(), RecordError> = Err(RecordError::WriteFailed("disk".into()));
+        let error = result
+            .context("writing frame 3")
+            .map_err(|e| e.context("recording"))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Io);
+        assert_eq!(
+            error.to_string(),
+            "recording: writing frame 3: failed to write recording: disk"
+        );
+        assert!(matches!(
+            error.downcast_ref::<RecordError>(),
+            Some(RecordError::WriteFailed(_))
+        ));
+
+        let error = SerialwarpError::with_source(ErrorKind::Other, Wrapper(io));
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.to_string(), "bad sector");
+        assert!(format!("{:?}", error).ends_with("caused by: bad sector"));
+    }
+
+    #[derive(Debug, Error)]
+    #[error("wrapped")]
+    struct Wrapper(#[source] std::io::Error);
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let error = SerialwarpError::from(TransportError::DeviceNotFound).context("connecting");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "device_not_found",
+                "message": "connecting: device not found",
+            })
+        );
+    }
 }
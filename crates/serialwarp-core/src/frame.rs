@@ -1,5 +1,10 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{DecodeError, ProtocolError};
+use crate::pool::BufferPool;
 use crate::protocol::{FrameHeader, MAX_SEGMENT_SIZE};
 
 /// Metadata for a captured/encoded frame
@@ -23,33 +28,39 @@ impl FrameMetadata {
 }
 
 /// An encoded video frame ready for transmission
+///
+/// The data is reference counted, so segments and clones share it rather
+/// than copying.
 #[derive(Debug, Clone)]
 pub struct EncodedFrame {
     pub metadata: FrameMetadata,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 impl EncodedFrame {
-    pub fn new(metadata: FrameMetadata, data: Vec<u8>) -> Self {
-        Self { metadata, data }
+    pub fn new(metadata: FrameMetadata, data: impl Into<Bytes>) -> Self {
+        Self {
+            metadata,
+            data: data.into(),
+        }
     }
 
     /// Split frame into segments for transmission
-    /// Each segment is at most MAX_SEGMENT_SIZE bytes
+    /// Each segment is at most MAX_SEGMENT_SIZE bytes, sharing the frame's data
     ///
     /// # Panics
-    /// Panics if frame data exceeds ~4GB (u16::MAX * MAX_SEGMENT_SIZE)
+    /// Panics if frame data exceeds ~2GB (MAX_SEGMENT_COUNT * MAX_SEGMENT_SIZE)
     pub fn into_segments(self) -> Vec<FrameSegment> {
         let total_size = self.data.len();
         let segment_count = (total_size + MAX_SEGMENT_SIZE - 1) / MAX_SEGMENT_SIZE;
         let segment_count = segment_count.max(1);
 
-        // Validate segment count fits in u16
+        // Validate segment count leaves the parity flag free
         assert!(
-            segment_count <= u16::MAX as usize,
+            segment_count <= MAX_SEGMENT_COUNT as usize,
             "Frame too large: requires {} segments (max {})",
             segment_count,
-            u16::MAX
+            MAX_SEGMENT_COUNT
         );
         let segment_count = segment_count as u16;
 
@@ -68,7 +79,7 @@ impl EncodedFrame {
 
         for i in 0..segment_count {
             let segment_end = (offset + MAX_SEGMENT_SIZE).min(total_size);
-            let segment_data = self.data[offset..segment_end].to_vec();
+            let segment_data = self.data.slice(offset..segment_end);
 
             segments.push(FrameSegment {
                 metadata: self.metadata.clone(),
@@ -83,6 +94,156 @@ impl EncodedFrame {
 
         segments
     }
+
+    /// Split frame into segments like `into_segments`, following every
+    /// `group_size` data segments (and the last, shorter group) with an XOR
+    /// parity segment the reassembler can rebuild any one of them from
+    pub fn into_segments_with_parity(self, group_size: u16) -> Vec<FrameSegment> {
+        let data_segments = self.into_segments();
+        if group_size == 0 {
+            return data_segments;
+        }
+
+        let group_size = group_size as usize;
+        let group_count = (data_segments.len() + group_size - 1) / group_size;
+        let mut segments = Vec::with_capacity(data_segments.len() + group_count);
+        for (group, chunk) in data_segments.chunks(group_size).enumerate() {
+            let parity = ParityBlock::compute(group_size as u16, chunk);
+            let first = &chunk[0];
+            let parity = FrameSegment {
+                metadata: first.metadata.clone(),
+                frame_size: first.frame_size,
+                segment_index: FrameHeader::PARITY_FLAG | group as u16,
+                segment_count: first.segment_count,
+                data: parity.to_bytes().into(),
+            };
+            segments.extend_from_slice(chunk);
+            segments.push(parity);
+        }
+        segments
+    }
+}
+
+/// Most data segments in one frame; the top bit of a segment index marks
+/// parity segments
+pub const MAX_SEGMENT_COUNT: u16 = FrameHeader::PARITY_FLAG - 1;
+
+/// Default number of data segments covered by each parity segment
+pub const DEFAULT_FEC_GROUP_SIZE: u16 = 8;
+
+/// XOR parity over one group of a frame's data segments, sent as the data
+/// of a parity segment:
+///
+/// - group_size (u16) and the number of segments in this group (u16)
+/// - for each data segment, its length (u32) and CRC-32C (u32)
+/// - the XOR of the data segments, each zero-padded to the longest
+///
+/// The checksums let the reassembler tell a rebuilt segment from garbage
+/// produced by bad parity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParityBlock {
+    group_size: u16,
+    lengths: Vec<u32>,
+    checksums: Vec<u32>,
+    parity: Vec<u8>,
+}
+
+impl ParityBlock {
+    fn compute(group_size: u16, segments: &[FrameSegment]) -> Self {
+        let len = segments.iter().map(|s| s.data.len()).max().unwrap_or(0);
+        let mut parity = vec![0u8; len];
+        for segment in segments {
+            xor_into(&mut parity, &segment.data);
+        }
+        Self {
+            group_size,
+            lengths: segments.iter().map(|s| s.data.len() as u32).collect(),
+            checksums: segments.iter().map(|s| crc32c::crc32c(&s.data)).collect(),
+            parity,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.lengths.len() * 8 + self.parity.len());
+        buf.put_u16_le(self.group_size);
+        buf.put_u16_le(self.lengths.len() as u16);
+        for (length, checksum) in self.lengths.iter().zip(&self.checksums) {
+            buf.put_u32_le(*length);
+            buf.put_u32_le(*checksum);
+        }
+        buf.extend_from_slice(&self.parity);
+        buf
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 4 {
+            return Err(format!("parity block of {} bytes", data.len()));
+        }
+        let mut buf = data;
+        let group_size = buf.get_u16_le();
+        let count = buf.get_u16_le() as usize;
+        if group_size == 0 || count == 0 || count > group_size as usize {
+            return Err(format!(
+                "parity block covers {} segments in groups of {}",
+                count, group_size
+            ));
+        }
+        if buf.len() < count * 8 {
+            return Err(format!("parity block truncated at {} bytes", data.len()));
+        }
+
+        let mut lengths = Vec::with_capacity(count);
+        let mut checksums = Vec::with_capacity(count);
+        for _ in 0..count {
+            lengths.push(buf.get_u32_le());
+            checksums.push(buf.get_u32_le());
+        }
+        let longest = lengths.iter().copied().max().unwrap_or(0);
+        if longest as usize != buf.len() {
+            return Err(format!(
+                "parity is {} bytes, longest segment is {}",
+                buf.len(),
+                longest
+            ));
+        }
+
+        Ok(Self {
+            group_size,
+            lengths,
+            checksums,
+            parity: buf.to_vec(),
+        })
+    }
+
+    /// Data segment indices this block covers, given its group index
+    fn range(&self, group: u16) -> std::ops::Range<usize> {
+        let first = group as usize * self.group_size as usize;
+        first..first + self.lengths.len()
+    }
+
+    /// Rebuild the segment at `position` in the group from the others, or
+    /// `None` if the result doesn't match what the parity recorded
+    fn recover<'a>(
+        &self,
+        position: usize,
+        others: impl Iterator<Item = (usize, &'a [u8])>,
+    ) -> Option<Vec<u8>> {
+        let mut data = self.parity.clone();
+        for (other, segment) in others {
+            if segment.len() != self.lengths[other] as usize {
+                return None;
+            }
+            xor_into(&mut data, segment);
+        }
+        data.truncate(self.lengths[position] as usize);
+        (crc32c::crc32c(&data) == self.checksums[position]).then_some(data)
+    }
+}
+
+fn xor_into(parity: &mut [u8], data: &[u8]) {
+    for (p, d) in parity.iter_mut().zip(data) {
+        *p ^= d;
+    }
 }
 
 /// A segment of an encoded frame for transmission
@@ -92,32 +253,152 @@ pub struct FrameSegment {
     pub frame_size: u32,
     pub segment_index: u16,
     pub segment_count: u16,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 impl FrameSegment {
-    /// Create the FRAME packet payload (header + data)
-    pub fn to_payload(&self) -> Bytes {
-        let header = FrameHeader::new(
+    /// Whether this carries parity rather than frame data
+    pub fn is_parity(&self) -> bool {
+        self.segment_index & FrameHeader::PARITY_FLAG != 0
+    }
+
+    /// The frame header sent ahead of this segment's data
+    pub fn header(&self) -> FrameHeader {
+        FrameHeader::new(
             self.metadata.frame_number,
             self.metadata.pts_us,
             self.metadata.capture_ts_us,
             self.frame_size,
             self.segment_index,
             self.segment_count,
-        );
+        )
+    }
+
+    /// Size of the FRAME packet payload: header and data
+    pub fn payload_len(&self) -> usize {
+        FrameHeader::SIZE + self.data.len()
+    }
+
+    /// Create the FRAME packet payload (header + data)
+    pub fn to_payload(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.payload_len());
+        self.to_payload_into(&mut buf);
+        buf.freeze()
+    }
 
-        let mut buf = BytesMut::with_capacity(FrameHeader::SIZE + self.data.len());
-        buf.put(header.to_bytes());
+    /// Append the FRAME packet payload (header + data) to `buf`, reserving
+    /// room for it first
+    pub fn to_payload_into(&self, buf: &mut BytesMut) {
+        buf.reserve(self.payload_len());
+        self.header().write_to(buf);
         buf.put_slice(&self.data);
+    }
+
+    /// Like `to_payload`, but builds the payload in a buffer from `pool`
+    /// that goes back to it once the returned `Bytes` is dropped
+    pub fn to_payload_in(&self, pool: &BufferPool) -> Bytes {
+        let mut buf = pool.get(self.payload_len());
+        self.header().write_to(&mut *buf);
+        buf.extend_from_slice(&self.data);
         buf.freeze()
     }
+
+    /// The FRAME packet payload as its header and the segment's data,
+    /// without copying the data, for transports that can send several
+    /// buffers as one
+    pub fn payload_parts(&self) -> [Bytes; 2] {
+        [self.header().to_bytes(), self.data.clone()]
+    }
+}
+
+/// Default time a partially received frame may wait for its remaining segments
+/// (roughly two frame intervals at 60fps)
+pub const DEFAULT_FRAME_TIMEOUT: Duration = Duration::from_millis(33);
+
+/// Default number of incomplete frames tracked at once
+pub const DEFAULT_MAX_PENDING_FRAMES: usize = 4;
+
+/// Completed frames remembered so segments arriving after completion, such
+/// as trailing parity, are dropped instead of starting a new frame
+const RECENTLY_COMPLETED: usize = 16;
+
+/// Frame reassembler configuration
+#[derive(Debug, Clone)]
+pub struct ReassemblerConfig {
+    /// How long an incomplete frame is kept after its first segment arrives
+    pub frame_timeout: Duration,
+    /// Maximum number of incomplete frames held at once; the oldest is evicted beyond this
+    pub max_pending_frames: usize,
+}
+
+impl ReassemblerConfig {
+    /// Configuration with a timeout of two frame intervals at the given frame rate
+    pub fn for_fps(fps: u32) -> Self {
+        let fps = fps.max(1) as u64;
+        Self {
+            frame_timeout: Duration::from_micros(2_000_000 / fps),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for ReassemblerConfig {
+    fn default() -> Self {
+        Self {
+            frame_timeout: DEFAULT_FRAME_TIMEOUT,
+            max_pending_frames: DEFAULT_MAX_PENDING_FRAMES,
+        }
+    }
+}
+
+/// Reassembly counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReassemblerStats {
+    /// Frames fully reassembled
+    pub frames_completed: u64,
+    /// Incomplete frames dropped (timed out or pushed out by newer frames)
+    pub frames_evicted: u64,
+    /// Segments ignored because they were already received
+    pub duplicate_segments: u64,
+    /// Lost data segments rebuilt from parity
+    pub segments_recovered: u64,
+    /// Parity segments that were malformed or rebuilt a segment that
+    /// failed its checksum
+    pub parity_failures: u64,
+    /// Frames found missing more than parity could rebuild, see
+    /// [`FrameReassembler::take_unrecoverable`]
+    pub frames_unrecoverable: u64,
+    /// Frames whose segments were all received but didn't add up to their
+    /// `frame_size`, see [`FrameReassembler::take_rejected`]
+    pub frames_rejected: u64,
 }
 
 /// Reassembles frame segments into complete frames
+///
+/// Several frames may be in flight at once. Each incomplete frame has a deadline
+/// measured from its first segment; call [`FrameReassembler::reap`] periodically
+/// to evict frames whose deadline has passed.
+///
+/// Parity segments from [`EncodedFrame::into_segments_with_parity`] are used
+/// to rebuild a single missing data segment per group. A frame missing more
+/// than that is reported by [`FrameReassembler::take_unrecoverable`] once its
+/// last parity segment arrives, and evicted like any other incomplete frame.
 #[derive(Debug)]
 pub struct FrameReassembler {
-    pending: Option<PendingFrame>,
+    config: ReassemblerConfig,
+    /// Incomplete frames, ordered by arrival of their first segment
+    pending: Vec<PendingFrame>,
+    /// Frame numbers of the most recently completed frames
+    completed: VecDeque<u64>,
+    /// Frames evicted for capacity since the last `reap`
+    evicted: Vec<u64>,
+    /// Frames found unrecoverable since the last `take_unrecoverable`
+    unrecoverable: Vec<u64>,
+    /// Frames rejected on completion since the last `take_rejected`
+    rejected: Vec<u64>,
+    /// First to last segment of the most recently completed frame
+    last_assembly_time: Duration,
+    stats: ReassemblerStats,
 }
 
 #[derive(Debug)]
@@ -127,81 +408,398 @@ struct PendingFrame {
     capture_ts_us: u64,
     frame_size: u32,
     segment_count: u16,
-    received_segments: Vec<Option<Vec<u8>>>,
+    received_segments: Vec<Option<Bytes>>,
     received_count: u16,
+    /// Parity blocks by group, for groups that may still lose a segment
+    parity: Vec<(u16, ParityBlock)>,
+    /// Whether the parity segment of the last group, the last segment sent
+    /// for the frame, has arrived
+    last_parity: bool,
+    /// Whether the frame was reported unrecoverable
+    unrecoverable: bool,
+    /// When its first segment arrived
+    started: Instant,
+    deadline: Instant,
+}
+
+/// What a parity block did for its group
+enum Recovery {
+    /// Nothing to do yet, or the group is complete
+    None,
+    Recovered,
+    Failed,
+}
+
+impl PendingFrame {
+    /// Verify a segment header agrees with the first segment seen for this frame
+    fn check_consistent(&self, header: &FrameHeader) -> Result<(), ProtocolError> {
+        let mismatch = if header.segment_count != self.segment_count {
+            Some((
+                "segment_count",
+                header.segment_count as u64,
+                self.segment_count as u64,
+            ))
+        } else if header.frame_size != self.frame_size {
+            Some((
+                "frame_size",
+                header.frame_size as u64,
+                self.frame_size as u64,
+            ))
+        } else if header.pts_us != self.pts_us {
+            Some(("pts_us", header.pts_us, self.pts_us))
+        } else {
+            None
+        };
+
+        match mismatch {
+            Some((field, actual, expected)) => Err(ProtocolError::FrameReassemblyError(format!(
+                "frame {}: segment {} has {} {}, expected {}",
+                self.frame_number, header.segment_index, field, actual, expected
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Store a parity block for `group`, checking it fits the frame
+    fn add_parity(&mut self, group: u16, data: &[u8]) -> Result<bool, ProtocolError> {
+        let block = ParityBlock::parse(data).and_then(|block| {
+            let range = block.range(group);
+            let expected = (self.segment_count as usize)
+                .saturating_sub(range.start)
+                .min(block.group_size as usize);
+            if block.lengths.len() == expected {
+                Ok(block)
+            } else {
+                Err(format!(
+                    "group {} covers {} segments, expected {}",
+                    group,
+                    block.lengths.len(),
+                    expected
+                ))
+            }
+        });
+        let block = block.map_err(|e| {
+            ProtocolError::FrameReassemblyError(format!(
+                "frame {}: invalid parity: {}",
+                self.frame_number, e
+            ))
+        })?;
+
+        if self.parity.iter().any(|(g, _)| *g == group) {
+            return Ok(false);
+        }
+        if block.range(group).end == self.segment_count as usize {
+            self.last_parity = true;
+        }
+        self.parity.push((group, block));
+        Ok(true)
+    }
+
+    /// Rebuild the one missing segment of `group`, if there is exactly one
+    /// and its parity has arrived. A group with nothing missing, or whose
+    /// parity proved bad, no longer needs its parity.
+    fn recover(&mut self, group: u16) -> Recovery {
+        let Some(slot) = self.parity.iter().position(|(g, _)| *g == group) else {
+            return Recovery::None;
+        };
+        let block = &self.parity[slot].1;
+        let range = block.range(group);
+        let mut missing = range
+            .clone()
+            .filter(|&i| self.received_segments[i].is_none());
+        let position = match (missing.next(), missing.next()) {
+            (Some(position), None) => position,
+            (None, _) => {
+                self.parity.swap_remove(slot);
+                return Recovery::None;
+            }
+            (Some(_), Some(_)) => return Recovery::None,
+        };
+
+        let others = range.clone().filter_map(|i| {
+            self.received_segments[i]
+                .as_deref()
+                .map(|data| (i - range.start, data))
+        });
+        let recovered = block.recover(position - range.start, others);
+        self.parity.swap_remove(slot);
+        match recovered {
+            Some(data) => {
+                self.received_segments[position] = Some(data.into());
+                self.received_count += 1;
+                Recovery::Recovered
+            }
+            None => Recovery::Failed,
+        }
+    }
+
+    /// The parity group covering data segment `index`, if its parity is held
+    fn group_of(&self, index: u16) -> Option<u16> {
+        self.parity
+            .iter()
+            .find(|(group, block)| block.range(*group).contains(&(index as usize)))
+            .map(|(group, _)| *group)
+    }
 }
 
 impl FrameReassembler {
     pub fn new() -> Self {
-        Self { pending: None }
+        Self::with_config(ReassemblerConfig::default())
+    }
+
+    pub fn with_config(config: ReassemblerConfig) -> Self {
+        Self {
+            config,
+            pending: Vec::new(),
+            completed: VecDeque::with_capacity(RECENTLY_COMPLETED),
+            evicted: Vec::new(),
+            unrecoverable: Vec::new(),
+            rejected: Vec::new(),
+            last_assembly_time: Duration::ZERO,
+            stats: ReassemblerStats::default(),
+        }
     }
 
     /// Add a segment. Returns the complete frame if all segments have been received.
-    pub fn add_segment(&mut self, header: &FrameHeader, data: Vec<u8>) -> Option<EncodedFrame> {
-        // Check if this is a new frame
-        if self.pending.is_none()
-            || self.pending.as_ref().unwrap().frame_number != header.frame_number
-        {
-            // Start new frame reassembly
-            let mut received_segments = vec![None; header.segment_count as usize];
-            received_segments[header.segment_index as usize] = Some(data);
-
-            self.pending = Some(PendingFrame {
-                frame_number: header.frame_number,
-                pts_us: header.pts_us,
-                capture_ts_us: header.capture_ts_us,
-                frame_size: header.frame_size,
-                segment_count: header.segment_count,
-                received_segments,
-                received_count: 1,
-            });
+    ///
+    /// Segments that disagree with the first segment seen for the same frame, and
+    /// completed frames whose length does not match `frame_size`, are rejected with
+    /// [`ProtocolError::FrameReassemblyError`]. A frame rejected on completion is
+    /// gone, and reported by [`FrameReassembler::take_rejected`].
+    pub fn add_segment(
+        &mut self,
+        header: &FrameHeader,
+        data: Bytes,
+    ) -> Result<Option<EncodedFrame>, ProtocolError> {
+        self.add_segment_at(header, data, Instant::now())
+    }
+
+    /// Add a segment received at `now`. Returns the complete frame if all segments
+    /// have been received.
+    pub fn add_segment_at(
+        &mut self,
+        header: &FrameHeader,
+        data: Bytes,
+        now: Instant,
+    ) -> Result<Option<EncodedFrame>, ProtocolError> {
+        let parity_group = header.parity_group();
+        let position = parity_group.unwrap_or(header.segment_index);
+        if position >= header.segment_count {
+            return Err(ProtocolError::FrameReassemblyError(format!(
+                "frame {}: segment_index ({}) must be less than segment_count ({})",
+                header.frame_number, position, header.segment_count
+            )));
+        }
 
-            if header.segment_count == 1 {
-                return self.complete_frame();
+        // A frame usually completes before its last parity segment arrives,
+        // and can complete from parity before its last data segment does
+        if self.completed.contains(&header.frame_number) {
+            if parity_group.is_none() {
+                self.stats.duplicate_segments += 1;
             }
-            return None;
+            return Ok(None);
         }
 
-        // Add to existing frame
-        let pending = self.pending.as_mut().unwrap();
+        let index = match self
+            .pending
+            .iter()
+            .position(|p| p.frame_number == header.frame_number)
+        {
+            Some(index) => {
+                self.pending[index].check_consistent(header)?;
+                index
+            }
+            None => {
+                // Start new frame reassembly, making room if necessary
+                if self.pending.len() >= self.config.max_pending_frames.max(1) {
+                    let oldest = self.pending.remove(0);
+                    self.evicted.push(oldest.frame_number);
+                    self.stats.frames_evicted += 1;
+                    crate::metrics::reassembly_evictions(1);
+                }
 
-        if pending.received_segments[header.segment_index as usize].is_some() {
-            // Duplicate segment, ignore
-            return None;
-        }
+                self.pending.push(PendingFrame {
+                    frame_number: header.frame_number,
+                    pts_us: header.pts_us,
+                    capture_ts_us: header.capture_ts_us,
+                    frame_size: header.frame_size,
+                    segment_count: header.segment_count,
+                    received_segments: vec![None; header.segment_count as usize],
+                    received_count: 0,
+                    parity: Vec::new(),
+                    last_parity: false,
+                    unrecoverable: false,
+                    started: now,
+                    deadline: now + self.config.frame_timeout,
+                });
+                self.pending.len() - 1
+            }
+        };
 
-        pending.received_segments[header.segment_index as usize] = Some(data);
-        pending.received_count += 1;
+        let pending = &mut self.pending[index];
+        let group = match parity_group {
+            Some(group) => match pending.add_parity(group, &data) {
+                Ok(true) => Some(group),
+                Ok(false) => {
+                    self.stats.duplicate_segments += 1;
+                    return Ok(None);
+                }
+                Err(e) => {
+                    self.stats.parity_failures += 1;
+                    return Err(e);
+                }
+            },
+            None => {
+                let slot = &mut pending.received_segments[header.segment_index as usize];
+                if slot.is_some() {
+                    // Duplicate segment, ignore
+                    self.stats.duplicate_segments += 1;
+                    return Ok(None);
+                }
+
+                *slot = Some(data);
+                pending.received_count += 1;
+                pending.group_of(header.segment_index)
+            }
+        };
+
+        if let Some(group) = group {
+            match pending.recover(group) {
+                Recovery::None => {}
+                Recovery::Recovered => self.stats.segments_recovered += 1,
+                Recovery::Failed => self.stats.parity_failures += 1,
+            }
+        }
 
         if pending.received_count == pending.segment_count {
-            return self.complete_frame();
+            let pending = self.pending.remove(index);
+            self.last_assembly_time = now.saturating_duration_since(pending.started);
+            return self.complete_frame(pending).map(Some);
+        }
+
+        // Segments arrive in the order sent, so once the last parity is in,
+        // any still missing were lost, and each group has already rebuilt
+        // what it could
+        if pending.last_parity && !pending.unrecoverable {
+            pending.unrecoverable = true;
+            self.unrecoverable.push(pending.frame_number);
+            self.stats.frames_unrecoverable += 1;
         }
 
-        None
+        Ok(None)
     }
 
-    fn complete_frame(&mut self) -> Option<EncodedFrame> {
-        let pending = self.pending.take()?;
+    /// Frames found unrecoverable since the last call, oldest first: their
+    /// last parity segment arrived with segments still missing that parity
+    /// couldn't rebuild, so the caller can request a keyframe without
+    /// waiting for them to time out. They're held until evicted all the
+    /// same, and complete if the missing segments were only late.
+    pub fn take_unrecoverable(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.unrecoverable)
+    }
+
+    /// Frames rejected since the last call, oldest first: all their segments
+    /// arrived, but didn't add up to the frame's `frame_size`. Like evicted
+    /// frames they won't complete, so the caller can account for them.
+    pub fn take_rejected(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// Evict incomplete frames whose deadline is at or before `now`.
+    ///
+    /// Returns the frame numbers of every frame dropped since the last call (both
+    /// timed out and pushed out by newer frames), oldest first, so the caller can
+    /// request a keyframe.
+    pub fn reap(&mut self, now: Instant) -> Vec<u64> {
+        let mut evicted = std::mem::take(&mut self.evicted);
+        let capacity_evictions = evicted.len();
+
+        self.pending.retain(|p| {
+            if p.deadline <= now {
+                evicted.push(p.frame_number);
+                false
+            } else {
+                true
+            }
+        });
 
-        let mut data = Vec::with_capacity(pending.frame_size as usize);
-        for segment_data in pending.received_segments.into_iter().flatten() {
-            data.extend_from_slice(&segment_data);
+        let timed_out = (evicted.len() - capacity_evictions) as u64;
+        self.stats.frames_evicted += timed_out;
+        if timed_out > 0 {
+            crate::metrics::reassembly_evictions(timed_out);
         }
+        evicted.sort_unstable();
+        evicted
+    }
+
+    fn complete_frame(&mut self, pending: PendingFrame) -> Result<EncodedFrame, ProtocolError> {
+        let mut segments = pending.received_segments.into_iter().flatten();
+        // A single segment is the whole frame as it is
+        let data = if pending.segment_count == 1 {
+            segments.next().unwrap_or_default()
+        } else {
+            let mut data = BytesMut::with_capacity(pending.frame_size as usize);
+            for segment_data in segments {
+                data.extend_from_slice(&segment_data);
+            }
+            data.freeze()
+        };
 
-        Some(EncodedFrame::new(
+        // Done with either way, so its stragglers count as duplicates
+        if self.completed.len() == RECENTLY_COMPLETED {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(pending.frame_number);
+
+        if data.len() != pending.frame_size as usize {
+            self.rejected.push(pending.frame_number);
+            self.stats.frames_rejected += 1;
+            return Err(ProtocolError::FrameReassemblyError(format!(
+                "frame {}: reassembled {} bytes, header declared {}",
+                pending.frame_number,
+                data.len(),
+                pending.frame_size
+            )));
+        }
+
+        self.stats.frames_completed += 1;
+
+        // FRAME headers don't carry it, so read it off the access unit
+        let is_keyframe = crate::contains_idr(&data);
+        Ok(EncodedFrame::new(
             FrameMetadata::new(
                 pending.frame_number,
                 pending.pts_us,
                 pending.capture_ts_us,
-                false, // We don't track keyframe status during reassembly
+                is_keyframe,
             ),
             data,
         ))
     }
 
-    /// Clear any pending incomplete frame
+    /// Number of incomplete frames currently held
+    pub fn pending_frames(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Time from the first to the last segment of the most recently
+    /// completed frame
+    pub fn last_assembly_time(&self) -> Duration {
+        self.last_assembly_time
+    }
+
+    /// Reassembly counters
+    pub fn stats(&self) -> ReassemblerStats {
+        self.stats
+    }
+
+    /// Clear any pending incomplete frames
     pub fn reset(&mut self) {
-        self.pending = None;
+        self.pending.clear();
+        self.completed.clear();
+        self.evicted.clear();
+        self.unrecoverable.clear();
+        self.rejected.clear();
     }
 }
 
@@ -211,56 +809,395 @@ impl Default for FrameReassembler {
     }
 }
 
+/// Location of one image plane inside a `DecodedFrame` buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaneLayout {
+    /// Byte offset of the first row
+    pub offset: usize,
+    /// Bytes per row, including any padding
+    pub stride: usize,
+}
+
+/// YUV to RGB conversion: the matrix coefficients and the value range
+///
+/// Limited (video) range puts black at Y=16 and white at Y=235, with chroma
+/// in 16-240; full range uses all 256 levels. Most encoders emit limited
+/// range BT.601 for SD and BT.709 for HD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMatrix {
+    Bt601Limited,
+    Bt601Full,
+    #[default]
+    Bt709Limited,
+    Bt709Full,
+}
+
+/// Chromaticities of the red, green and blue primaries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorPrimaries {
+    #[default]
+    Bt709,
+    /// SMPTE 170M, the 525-line BT.601 primaries
+    Bt601,
+}
+
+/// Transfer function (gamma curve) of the encoded samples
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransferFunction {
+    #[default]
+    Bt709,
+    /// IEC 61966-2-1, as captured from most desktops
+    Srgb,
+}
+
+/// How a stream's samples map to colors, as signaled in the H.264 VUI
+///
+/// Defaults to BT.709 limited range, what hardware encoders use for HD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColorSpace {
+    pub primaries: ColorPrimaries,
+    pub transfer: TransferFunction,
+    /// YUV matrix and range
+    pub matrix: ColorMatrix,
+}
+
+/// 16.16 fixed-point conversion coefficients
+struct Coefficients {
+    y_offset: i32,
+    y: i32,
+    r_v: i32,
+    g_u: i32,
+    g_v: i32,
+    b_u: i32,
+}
+
+/// `ColorMatrix` conversion coefficients as floats, for converting on a GPU
+///
+/// Works on samples normalized to 0..1: subtract `y_offset` from luma and
+/// 128/255 from chroma, then `r = y * (luma) + r_v * v`,
+/// `g = y * (luma) - g_u * u - g_v * v` and `b = y * (luma) + b_u * u`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShaderCoefficients {
+    pub y_offset: f32,
+    pub y: f32,
+    pub r_v: f32,
+    pub g_u: f32,
+    pub g_v: f32,
+    pub b_u: f32,
+}
+
+impl ColorMatrix {
+    fn coefficients(self) -> Coefficients {
+        // y scales luma to full range; the rest are 2(1-Kr), 2Kb(1-Kb)/Kg,
+        // 2Kr(1-Kr)/Kg and 2(1-Kb), scaled for chroma range, times 65536
+        let (y_offset, y, r_v, g_u, g_v, b_u) = match self {
+            Self::Bt601Limited => (16, 76309, 104597, 25675, 53279, 132201),
+            Self::Bt601Full => (0, 65536, 91881, 22553, 46802, 116130),
+            Self::Bt709Limited => (16, 76309, 117489, 13975, 34925, 138438),
+            Self::Bt709Full => (0, 65536, 103206, 12276, 30679, 121609),
+        };
+        Coefficients {
+            y_offset,
+            y,
+            r_v,
+            g_u,
+            g_v,
+            b_u,
+        }
+    }
+
+    /// Whether this uses the BT.709 rather than the BT.601 coefficients
+    pub fn is_bt709(self) -> bool {
+        matches!(self, Self::Bt709Limited | Self::Bt709Full)
+    }
+
+    /// Whether samples use all 256 levels rather than video range
+    pub fn is_full_range(self) -> bool {
+        matches!(self, Self::Bt601Full | Self::Bt709Full)
+    }
+
+    /// Convert one YUV sample to RGB
+    pub fn yuv_to_rgb(self, y: u8, u: u8, v: u8) -> [u8; 3] {
+        self.coefficients().convert(y, u, v)
+    }
+
+    /// The same conversion as `yuv_to_rgb`, in floating point
+    pub fn shader_coefficients(self) -> ShaderCoefficients {
+        let c = self.coefficients();
+        let scale = |x: i32| x as f32 / 65536.0;
+        ShaderCoefficients {
+            y_offset: c.y_offset as f32 / 255.0,
+            y: scale(c.y),
+            r_v: scale(c.r_v),
+            g_u: scale(c.g_u),
+            g_v: scale(c.g_v),
+            b_u: scale(c.b_u),
+        }
+    }
+}
+
+impl Coefficients {
+    #[inline]
+    fn convert(&self, y: u8, u: u8, v: u8) -> [u8; 3] {
+        let luma = (y as i32 - self.y_offset) * self.y + (1 << 15);
+        let u = u as i32 - 128;
+        let v = v as i32 - 128;
+        let clamp = |x: i32| (x >> 16).clamp(0, 255) as u8;
+        [
+            clamp(luma + self.r_v * v),
+            clamp(luma - self.g_u * u - self.g_v * v),
+            clamp(luma + self.b_u * u),
+        ]
+    }
+}
+
 /// A decoded video frame ready for rendering
+///
+/// The YUV420P planes live in one reference-counted buffer, so cloning a
+/// frame doesn't copy pixels. Rows may be padded past the visible width; always index with
+/// the plane's stride.
 #[derive(Debug, Clone)]
 pub struct DecodedFrame {
+    /// On the sink, the number of the FRAME the picture was decoded from
     pub frame_number: u64,
     pub pts_us: u64,
     pub width: u32,
     pub height: u32,
-    /// YUV420P data: Y plane followed by U plane followed by V plane
-    yuv_data: Vec<u8>,
+    /// Colors as signaled by the stream; pass `color_space.matrix` to the
+    /// RGB conversions
+    pub color_space: ColorSpace,
+    /// Capture time from the stream's `LatencySei`, in microseconds since
+    /// the Unix epoch on the source's clock, if the source sent one
+    pub source_timestamp_us: Option<u64>,
+    buffer: Bytes,
+    /// Y, U and V plane layouts
+    planes: [PlaneLayout; 3],
 }
 
 impl DecodedFrame {
+    /// Create a frame from tightly packed YUV420P data: Y plane followed by
+    /// U plane followed by V plane
     pub fn new(frame_number: u64, pts_us: u64, width: u32, height: u32, yuv_data: Vec<u8>) -> Self {
+        let y_size = (width * height) as usize;
+        let chroma_width = ((width + 1) / 2) as usize;
+        let uv_size = chroma_width * ((height + 1) / 2) as usize;
+        let planes = [
+            PlaneLayout {
+                offset: 0,
+                stride: width as usize,
+            },
+            PlaneLayout {
+                offset: y_size,
+                stride: chroma_width,
+            },
+            PlaneLayout {
+                offset: y_size + uv_size,
+                stride: chroma_width,
+            },
+        ];
+
         Self {
             frame_number,
             pts_us,
             width,
             height,
-            yuv_data,
+            color_space: ColorSpace::default(),
+            source_timestamp_us: None,
+            buffer: yuv_data.into(),
+            planes,
+        }
+    }
+
+    /// Create a frame from YUV420P planes at arbitrary offsets and strides
+    /// within `buffer`, such as a decoder's padded output. The buffer may come
+    /// from a `BufferPool` via `PooledBuffer::freeze`.
+    ///
+    /// Fails if a stride is narrower than its plane or a plane runs past the
+    /// end of the buffer.
+    pub fn from_planes(
+        frame_number: u64,
+        pts_us: u64,
+        width: u32,
+        height: u32,
+        buffer: Bytes,
+        planes: [PlaneLayout; 3],
+    ) -> Result<Self, DecodeError> {
+        let frame = Self {
+            frame_number,
+            pts_us,
+            width,
+            height,
+            color_space: ColorSpace::default(),
+            source_timestamp_us: None,
+            buffer,
+            planes,
+        };
+
+        for plane in 0..3 {
+            let (row_bytes, rows) = frame.plane_size(plane);
+            let layout = frame.planes[plane];
+            if layout.stride < row_bytes {
+                return Err(DecodeError::InvalidFrameData);
+            }
+            let end = match rows {
+                0 => layout.offset,
+                _ => layout.offset + layout.stride * (rows - 1) + row_bytes,
+            };
+            if end > frame.buffer.len() {
+                return Err(DecodeError::InvalidFrameData);
+            }
+        }
+
+        Ok(frame)
+    }
+
+    /// Set the frame number, e.g. to that of the FRAME the picture was
+    /// decoded from
+    pub fn set_frame_number(&mut self, frame_number: u64) {
+        self.frame_number = frame_number;
+    }
+
+    /// Shrink the visible area to its top-left `width` x `height`, e.g. to
+    /// drop the column and row an odd-sized stream was padded with. Sizes
+    /// larger than the frame leave it as is.
+    pub fn crop(&mut self, width: u32, height: u32) {
+        self.width = self.width.min(width);
+        self.height = self.height.min(height);
+    }
+
+    /// Visible bytes per row and number of rows of a plane. Chroma rounds
+    /// up, so an odd last column or row has chroma of its own.
+    fn plane_size(&self, plane: usize) -> (usize, usize) {
+        match plane {
+            0 => (self.width as usize, self.height as usize),
+            _ => (
+                ((self.width + 1) / 2) as usize,
+                ((self.height + 1) / 2) as usize,
+            ),
         }
     }
 
+    /// Bytes of a plane from its first row through the end of its last
+    /// visible row
+    fn plane(&self, plane: usize) -> &[u8] {
+        let (row_bytes, rows) = self.plane_size(plane);
+        let layout = self.planes[plane];
+        let len = match rows {
+            0 => 0,
+            _ => layout.stride * (rows - 1) + row_bytes,
+        };
+        &self.buffer[layout.offset..layout.offset + len]
+    }
+
     /// Get the Y (luma) plane
     pub fn y_plane(&self) -> &[u8] {
-        let y_size = (self.width * self.height) as usize;
-        &self.yuv_data[..y_size]
+        self.plane(0)
     }
 
     /// Get the U (chroma-blue) plane
     pub fn u_plane(&self) -> &[u8] {
-        let y_size = (self.width * self.height) as usize;
-        let uv_size = y_size / 4;
-        &self.yuv_data[y_size..y_size + uv_size]
+        self.plane(1)
     }
 
     /// Get the V (chroma-red) plane
     pub fn v_plane(&self) -> &[u8] {
-        let y_size = (self.width * self.height) as usize;
-        let uv_size = y_size / 4;
-        &self.yuv_data[y_size + uv_size..]
+        self.plane(2)
     }
 
     /// Get stride for Y plane (bytes per row)
     pub fn y_stride(&self) -> usize {
-        self.width as usize
+        self.planes[0].stride
+    }
+
+    /// Get stride for U plane (bytes per row)
+    pub fn u_stride(&self) -> usize {
+        self.planes[1].stride
+    }
+
+    /// Get stride for V plane (bytes per row)
+    pub fn v_stride(&self) -> usize {
+        self.planes[2].stride
+    }
+
+    /// Chroma row or column for a luma one, or `None` if the chroma plane
+    /// is empty
+    fn chroma_index(luma: usize, chroma_len: usize) -> Option<usize> {
+        (chroma_len > 0).then(|| (luma / 2).min(chroma_len - 1))
     }
 
-    /// Get stride for U/V planes (bytes per row)
-    pub fn uv_stride(&self) -> usize {
-        (self.width / 2) as usize
+    /// RGB of the pixel at column `x`, row `y`
+    ///
+    /// # Panics
+    ///
+    /// If the pixel is outside the frame.
+    pub fn rgb_at(&self, x: usize, y: usize, color: ColorMatrix) -> [u8; 3] {
+        assert!(x < self.width as usize && y < self.height as usize);
+        let (chroma_width, chroma_height) = self.plane_size(1);
+        let luma = self.y_plane()[y * self.y_stride() + x];
+        let (u, v) = match (
+            Self::chroma_index(x, chroma_width),
+            Self::chroma_index(y, chroma_height),
+        ) {
+            (Some(cx), Some(cy)) => (
+                self.u_plane()[cy * self.u_stride() + cx],
+                self.v_plane()[cy * self.v_stride() + cx],
+            ),
+            _ => (128, 128),
+        };
+        color.yuv_to_rgb(luma, u, v)
+    }
+
+    /// Convert to tightly packed RGBA with opaque alpha
+    pub fn to_rgba(&self, color: ColorMatrix) -> Vec<u8> {
+        let mut rgba = vec![0; self.width as usize * self.height as usize * 4];
+        self.convert_into(color, &mut rgba, 4);
+        rgba
+    }
+
+    /// Convert into `rgb` as tightly packed 24-bit RGB, reusing the caller's
+    /// buffer across frames
+    ///
+    /// # Panics
+    ///
+    /// If `rgb` is shorter than `width * height * 3` bytes.
+    pub fn to_rgb_into(&self, color: ColorMatrix, rgb: &mut [u8]) {
+        let len = self.width as usize * self.height as usize * 3;
+        assert!(rgb.len() >= len, "RGB buffer too small for frame");
+        self.convert_into(color, &mut rgb[..len], 3);
+    }
+
+    /// Shared row loop for RGB and RGBA; a fourth channel is alpha
+    fn convert_into(&self, color: ColorMatrix, out: &mut [u8], channels: usize) {
+        let width = self.width as usize;
+        if width == 0 {
+            return;
+        }
+        let coefficients = color.coefficients();
+        let (chroma_width, chroma_height) = self.plane_size(1);
+        let (y_plane, u_plane, v_plane) = (self.y_plane(), self.u_plane(), self.v_plane());
+        // Stands in for the chroma planes when they're empty
+        let neutral = [128u8];
+
+        for (row, pixels) in out.chunks_exact_mut(width * channels).enumerate() {
+            let luma = &y_plane[row * self.y_stride()..][..width];
+            let (u_row, v_row) = match Self::chroma_index(row, chroma_height) {
+                Some(cy) if chroma_width > 0 => (
+                    &u_plane[cy * self.u_stride()..][..chroma_width],
+                    &v_plane[cy * self.v_stride()..][..chroma_width],
+                ),
+                _ => (&neutral[..], &neutral[..]),
+            };
+            let last = u_row.len() - 1;
+
+            for (col, (pixel, &y)) in pixels.chunks_exact_mut(channels).zip(luma).enumerate() {
+                let cx = (col / 2).min(last);
+                let [r, g, b] = coefficients.convert(y, u_row[cx], v_row[cx]);
+                pixel[..3].copy_from_slice(&[r, g, b]);
+                if channels == 4 {
+                    pixel[3] = 255;
+                }
+            }
+        }
     }
 }
 
@@ -323,7 +1260,9 @@ mod tests {
                 segment.segment_index,
                 segment.segment_count,
             );
-            let result = reassembler.add_segment(&header, segment.data.clone());
+            let result = reassembler
+                .add_segment(&header, segment.data.clone())
+                .unwrap();
             assert!(result.is_none());
         }
 
@@ -337,13 +1276,534 @@ mod tests {
             last.segment_index,
             last.segment_count,
         );
-        let result = reassembler.add_segment(&header, last.data.clone());
+        let result = reassembler.add_segment(&header, last.data.clone()).unwrap();
         assert!(result.is_some());
 
         let reassembled = result.unwrap();
         assert_eq!(reassembled.data, original_data);
     }
 
+    fn segment_header(segment: &FrameSegment) -> FrameHeader {
+        FrameHeader::new(
+            segment.metadata.frame_number,
+            segment.metadata.pts_us,
+            segment.metadata.capture_ts_us,
+            segment.frame_size,
+            segment.segment_index,
+            segment.segment_count,
+        )
+    }
+
+    fn multi_segment_frame(frame_number: u64) -> Vec<FrameSegment> {
+        let metadata = FrameMetadata::new(frame_number, frame_number * 1000, 0, false);
+        EncodedFrame::new(metadata, vec![7u8; 200_000]).into_segments()
+    }
+
+    /// A frame of five segments, the last one short, with distinct contents
+    fn parity_frame(group_size: u16) -> (Vec<u8>, Vec<FrameSegment>) {
+        let data: Vec<u8> = (0..4 * MAX_SEGMENT_SIZE + 1000)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect();
+        let metadata = FrameMetadata::new(9, 9000, 0, false);
+        let segments =
+            EncodedFrame::new(metadata, data.clone()).into_segments_with_parity(group_size);
+        (data, segments)
+    }
+
+    /// Feed `segments` to a fresh reassembler, returning the frame if it
+    /// completed
+    fn reassemble(
+        reassembler: &mut FrameReassembler,
+        segments: &[FrameSegment],
+    ) -> Option<EncodedFrame> {
+        let mut complete = None;
+        for segment in segments {
+            let result = reassembler
+                .add_segment(&segment_header(segment), segment.data.clone())
+                .unwrap();
+            complete = complete.or(result);
+        }
+        complete
+    }
+
+    #[test]
+    fn test_reassembled_keyframe_detected() {
+        let idr = [
+            &[0, 0, 0, 1, 0x67, 0x42][..],
+            &[0, 0, 1, 0x65, 0x88],
+            &[9; 70_000],
+        ]
+        .concat();
+        let p_frame = [&[0, 0, 0, 1, 0x41, 0x9a][..], &[9; 70_000]].concat();
+        let mut reassembler = FrameReassembler::new();
+        for (frame_number, data, keyframe) in [(1, idr, true), (2, p_frame, false)] {
+            // The sender's flag doesn't cross the link; the payload decides
+            let metadata = FrameMetadata::new(frame_number, frame_number * 1000, 0, !keyframe);
+            let segments = EncodedFrame::new(metadata, data).into_segments();
+            let frame = reassemble(&mut reassembler, &segments).unwrap();
+            assert_eq!(frame.metadata.is_keyframe, keyframe);
+        }
+    }
+
+    #[test]
+    fn test_parity_segment_layout() {
+        let (_, segments) = parity_frame(3);
+        let indices: Vec<_> = segments.iter().map(|s| s.segment_index).collect();
+        let parity = FrameHeader::PARITY_FLAG;
+        assert_eq!(indices, vec![0, 1, 2, parity, 3, 4, parity | 1]);
+        assert!(segments.iter().all(|s| s.segment_count == 5));
+        assert_eq!(segments.iter().filter(|s| s.is_parity()).count(), 2);
+
+        // Group size zero means no parity
+        let (_, segments) = parity_frame(0);
+        assert_eq!(segments.len(), 5);
+        assert!(!segments.iter().any(FrameSegment::is_parity));
+    }
+
+    #[test]
+    fn test_parity_recovers_any_lost_segment() {
+        for group_size in [1, 2, 3, 5] {
+            let (data, segments) = parity_frame(group_size);
+            let data_positions: Vec<_> = (0..segments.len())
+                .filter(|&i| !segments[i].is_parity())
+                .collect();
+
+            for &lost in &data_positions {
+                let mut survivors = segments.clone();
+                survivors.remove(lost);
+
+                // In order, and with the parity segments arriving first, in
+                // which case a group's last segment is rebuilt before it
+                // arrives whether it was lost or not
+                let mut reordered = survivors.clone();
+                reordered.sort_by_key(|s| !s.is_parity());
+                for (order, in_order) in [(survivors, true), (reordered, false)] {
+                    let mut reassembler = FrameReassembler::new();
+                    let frame = reassemble(&mut reassembler, &order)
+                        .unwrap_or_else(|| panic!("K={} lost {} not rebuilt", group_size, lost));
+                    assert_eq!(frame.data, data);
+                    let recovered = reassembler.stats().segments_recovered;
+                    assert!(recovered >= 1);
+                    assert!(!in_order || recovered == 1);
+                    assert_eq!(reassembler.stats().parity_failures, 0);
+                    if in_order {
+                        assert!(reassembler.take_unrecoverable().is_empty());
+                    }
+                    assert_eq!(reassembler.pending_frames(), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parity_recovers_one_loss_per_group() {
+        let (data, mut segments) = parity_frame(3);
+        // Segment 1 from the first group, segment 4 from the second
+        segments.remove(5);
+        segments.remove(1);
+
+        let mut reassembler = FrameReassembler::new();
+        let frame = reassemble(&mut reassembler, &segments).unwrap();
+        assert_eq!(frame.data, data);
+        assert_eq!(reassembler.stats().segments_recovered, 2);
+    }
+
+    #[test]
+    fn test_two_losses_in_a_group_are_unrecoverable() {
+        let (_, segments) = parity_frame(3);
+        for (a, b) in [(0, 1), (0, 2), (1, 2), (4, 5)] {
+            let survivors: Vec<_> = segments
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != a && *i != b)
+                .map(|(_, s)| s.clone())
+                .collect();
+
+            let start = Instant::now();
+            let mut reassembler = FrameReassembler::new();
+            assert!(reassemble(&mut reassembler, &survivors).is_none());
+            assert_eq!(reassembler.stats().segments_recovered, 0);
+
+            // Known lost once the last parity is in, so the sink can ask
+            // for a keyframe at once
+            assert_eq!(reassembler.take_unrecoverable(), vec![9]);
+            assert_eq!(reassembler.stats().frames_unrecoverable, 1);
+            assert_eq!(reassembler.pending_frames(), 1);
+
+            // And then times out like any incomplete frame
+            let evicted = reassembler.reap(start + DEFAULT_FRAME_TIMEOUT * 2);
+            assert_eq!(evicted, vec![9]);
+            assert!(reassembler.take_unrecoverable().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_corrupted_parity_is_not_trusted() {
+        let (data, segments) = parity_frame(3);
+        let lost = segments[1].clone();
+        let mut survivors = segments.clone();
+        survivors.remove(1);
+        // Flip a byte of the XOR data, past the lengths and checksums
+        let mut corrupted = survivors[2].data.to_vec();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        survivors[2].data = corrupted.into();
+
+        let mut reassembler = FrameReassembler::new();
+        assert!(reassemble(&mut reassembler, &survivors).is_none());
+        let stats = reassembler.stats();
+        assert_eq!(stats.segments_recovered, 0);
+        assert_eq!(stats.parity_failures, 1);
+
+        // The real segment still completes the frame
+        let frame = reassembler
+            .add_segment(&segment_header(&lost), lost.data)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.data, data);
+    }
+
+    #[test]
+    fn test_malformed_parity_rejected() {
+        let (_, segments) = parity_frame(3);
+        let mut reassembler = FrameReassembler::new();
+
+        let mut parity = segments[3].clone();
+        parity.data.truncate(10);
+        assert_rejected(reassembler.add_segment(&segment_header(&parity), parity.data));
+
+        // Claims to cover more segments than the group has
+        let mut parity = segments[6].clone();
+        let mut data = parity.data.to_vec();
+        data[2] = 3;
+        parity.data = data.into();
+        assert_rejected(reassembler.add_segment(&segment_header(&parity), parity.data));
+        assert_eq!(reassembler.stats().parity_failures, 2);
+    }
+
+    #[test]
+    fn test_trailing_parity_after_completion_ignored() {
+        let (data, segments) = parity_frame(3);
+        let mut reassembler = FrameReassembler::new();
+        let frame = reassemble(&mut reassembler, &segments[..6]).unwrap();
+        assert_eq!(frame.data, data);
+
+        let last = &segments[6];
+        assert!(last.is_parity());
+        let result = reassembler
+            .add_segment(&segment_header(last), last.data.clone())
+            .unwrap();
+        assert!(result.is_none());
+        assert_eq!(reassembler.pending_frames(), 0);
+        assert_eq!(reassembler.stats().duplicate_segments, 0);
+    }
+
+    #[test]
+    fn test_reap_evicts_expired_frames_in_order() {
+        let config = ReassemblerConfig {
+            frame_timeout: Duration::from_millis(30),
+            ..ReassemblerConfig::default()
+        };
+        let mut reassembler = FrameReassembler::with_config(config);
+        let t0 = Instant::now();
+
+        let frame1 = multi_segment_frame(1);
+        let frame2 = multi_segment_frame(2);
+        let frame3 = multi_segment_frame(3);
+
+        reassembler
+            .add_segment_at(&segment_header(&frame2[0]), frame2[0].data.clone(), t0)
+            .unwrap();
+        reassembler
+            .add_segment_at(
+                &segment_header(&frame1[0]),
+                frame1[0].data.clone(),
+                t0 + Duration::from_millis(5),
+            )
+            .unwrap();
+        reassembler
+            .add_segment_at(
+                &segment_header(&frame3[0]),
+                frame3[0].data.clone(),
+                t0 + Duration::from_millis(20),
+            )
+            .unwrap();
+        assert_eq!(reassembler.pending_frames(), 3);
+
+        // Nothing has expired yet
+        assert!(reassembler.reap(t0 + Duration::from_millis(29)).is_empty());
+
+        // Frames 1 and 2 expire together and are reported oldest first
+        let evicted = reassembler.reap(t0 + Duration::from_millis(40));
+        assert_eq!(evicted, vec![1, 2]);
+        assert_eq!(reassembler.pending_frames(), 1);
+
+        let evicted = reassembler.reap(t0 + Duration::from_millis(50));
+        assert_eq!(evicted, vec![3]);
+        assert_eq!(reassembler.pending_frames(), 0);
+        assert_eq!(reassembler.stats().frames_evicted, 3);
+    }
+
+    #[test]
+    fn test_segments_before_deadline_complete_frame() {
+        let mut reassembler = FrameReassembler::new();
+        let t0 = Instant::now();
+        let segments = multi_segment_frame(1);
+
+        let mut completed = None;
+        for (i, segment) in segments.iter().enumerate() {
+            let now = t0 + Duration::from_millis(i as u64);
+            completed = reassembler
+                .add_segment_at(&segment_header(segment), segment.data.clone(), now)
+                .unwrap();
+        }
+
+        assert!(completed.is_some());
+        assert!(reassembler.reap(t0 + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            reassembler.last_assembly_time(),
+            Duration::from_millis(segments.len() as u64 - 1)
+        );
+    }
+
+    #[test]
+    fn test_capacity_eviction_reported_by_reap() {
+        let config = ReassemblerConfig {
+            max_pending_frames: 2,
+            ..ReassemblerConfig::default()
+        };
+        let mut reassembler = FrameReassembler::with_config(config);
+        let t0 = Instant::now();
+
+        for frame_number in 1..=3 {
+            let segments = multi_segment_frame(frame_number);
+            reassembler
+                .add_segment_at(&segment_header(&segments[0]), segments[0].data.clone(), t0)
+                .unwrap();
+        }
+
+        assert_eq!(reassembler.pending_frames(), 2);
+        assert_eq!(reassembler.stats().frames_evicted, 1);
+        assert_eq!(reassembler.reap(t0), vec![1]);
+    }
+
+    #[test]
+    fn test_reassembler_stats() {
+        let mut reassembler = FrameReassembler::new();
+        let segments = multi_segment_frame(1);
+
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+        for segment in &segments[1..] {
+            reassembler
+                .add_segment(&segment_header(segment), segment.data.clone())
+                .unwrap();
+        }
+
+        let stats = reassembler.stats();
+        assert_eq!(stats.frames_completed, 1);
+        assert_eq!(stats.frames_evicted, 0);
+        assert_eq!(stats.duplicate_segments, 1);
+    }
+
+    fn assert_rejected(result: Result<Option<EncodedFrame>, ProtocolError>) {
+        assert!(matches!(
+            result,
+            Err(ProtocolError::FrameReassemblyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_segment_count_mismatch() {
+        let mut reassembler = FrameReassembler::new();
+        let segments = multi_segment_frame(1);
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+
+        let mut header = segment_header(&segments[1]);
+        header.segment_count += 1;
+        assert_rejected(reassembler.add_segment(&header, segments[1].data.clone()));
+    }
+
+    #[test]
+    fn test_rejects_frame_size_mismatch() {
+        let mut reassembler = FrameReassembler::new();
+        let segments = multi_segment_frame(1);
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+
+        let mut header = segment_header(&segments[1]);
+        header.frame_size -= 1;
+        assert_rejected(reassembler.add_segment(&header, segments[1].data.clone()));
+    }
+
+    #[test]
+    fn test_rejects_pts_mismatch() {
+        let mut reassembler = FrameReassembler::new();
+        let segments = multi_segment_frame(1);
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+
+        let mut header = segment_header(&segments[1]);
+        header.pts_us += 1;
+        assert_rejected(reassembler.add_segment(&header, segments[1].data.clone()));
+    }
+
+    #[test]
+    fn test_rejected_segment_does_not_break_frame() {
+        let mut reassembler = FrameReassembler::new();
+        let segments = multi_segment_frame(1);
+
+        let mut bad = segment_header(&segments[1]);
+        bad.frame_size += 1;
+
+        reassembler
+            .add_segment(&segment_header(&segments[0]), segments[0].data.clone())
+            .unwrap();
+        assert_rejected(reassembler.add_segment(&bad, segments[1].data.clone()));
+
+        let mut result = None;
+        for segment in &segments[1..] {
+            result = reassembler
+                .add_segment(&segment_header(segment), segment.data.clone())
+                .unwrap();
+        }
+        assert_eq!(result.unwrap().data.len(), 200_000);
+    }
+
+    #[test]
+    fn test_rejects_length_mismatch_on_completion() {
+        let mut reassembler = FrameReassembler::new();
+        let header = FrameHeader::new(1, 1000, 1000, 1024, 0, 1);
+        assert_rejected(reassembler.add_segment(&header, vec![0u8; 1000].into()));
+        assert_eq!(reassembler.stats().frames_completed, 0);
+        assert_eq!(reassembler.pending_frames(), 0);
+
+        // Reported once, and its segments sent again are duplicates
+        assert_eq!(reassembler.stats().frames_rejected, 1);
+        assert_eq!(reassembler.take_rejected(), vec![1]);
+        assert!(reassembler.take_rejected().is_empty());
+        let resent = reassembler.add_segment(&header, vec![0u8; 1000].into());
+        assert!(matches!(resent, Ok(None)));
+        assert!(reassembler.take_rejected().is_empty());
+        assert_eq!(reassembler.stats().duplicate_segments, 1);
+    }
+
+    #[test]
+    fn test_rejects_segment_index_out_of_range() {
+        let mut reassembler = FrameReassembler::new();
+        let header = FrameHeader::new(1, 1000, 1000, 1024, 2, 2);
+        assert_rejected(reassembler.add_segment(&header, vec![0u8; 512].into()));
+    }
+
+    #[test]
+    fn test_config_for_fps() {
+        let config = ReassemblerConfig::for_fps(30);
+        assert_eq!(config.frame_timeout, Duration::from_micros(66_666));
+    }
+
+    #[test]
+    fn test_pooled_payload_matches_and_reuses() {
+        let pool = BufferPool::default();
+        let metadata = FrameMetadata::new(3, 1000, 1000, true);
+        let segments = EncodedFrame::new(metadata, vec![7u8; 200_000]).into_segments();
+
+        for _ in 0..10 {
+            for segment in &segments {
+                assert_eq!(segment.to_payload_in(&pool), segment.to_payload());
+            }
+        }
+
+        // One buffer per size class (full and tail segments), reused after that
+        let stats = pool.stats();
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(stats.reuses, 10 * segments.len() as u64 - 2);
+    }
+
+    /// FRAME payloads built the way they were before segments shared the
+    /// frame's data: each segment copied out of the frame, then copied again
+    /// after its header
+    fn copied_payloads(metadata: &FrameMetadata, data: &[u8]) -> Vec<Vec<u8>> {
+        let chunks: Vec<Vec<u8>> = data.chunks(MAX_SEGMENT_SIZE).map(<[u8]>::to_vec).collect();
+        let count = chunks.len() as u16;
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let header = FrameHeader::new(
+                    metadata.frame_number,
+                    metadata.pts_us,
+                    metadata.capture_ts_us,
+                    data.len() as u32,
+                    index as u16,
+                    count,
+                );
+                let mut payload = header.to_bytes().to_vec();
+                payload.extend_from_slice(chunk);
+                payload
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shared_segments_match_copied_payloads() {
+        let pool = BufferPool::default();
+        let metadata = FrameMetadata::new(5, 5000, 4000, true);
+        for size in [1, 1000, MAX_SEGMENT_SIZE, 3 * MAX_SEGMENT_SIZE + 17] {
+            let data: Vec<u8> = (0..size).map(|i| (i * 31 + i / 257) as u8).collect();
+            let frame = EncodedFrame::new(metadata.clone(), data.clone());
+            let frame_data = frame.data.clone();
+            let segments = frame.into_segments();
+            let expected = copied_payloads(&metadata, &data);
+            assert_eq!(segments.len(), expected.len());
+
+            let mut all = BytesMut::new();
+            let mut offset = 0;
+            for (segment, expected) in segments.iter().zip(&expected) {
+                // Sliced out of the frame, not copied
+                assert_eq!(segment.data.as_ptr(), frame_data[offset..].as_ptr());
+                offset += segment.data.len();
+
+                assert_eq!(segment.payload_len(), expected.len());
+                assert_eq!(segment.to_payload(), expected);
+                assert_eq!(segment.to_payload_in(&pool), expected);
+                assert_eq!(segment.payload_parts().concat(), *expected);
+                segment.to_payload_into(&mut all);
+            }
+            assert_eq!(all, expected.concat());
+
+            // And back again, from slices of the payloads as the sink has them
+            let mut reassembler = FrameReassembler::new();
+            let mut result = None;
+            for payload in expected.into_iter().map(Bytes::from) {
+                let header = FrameHeader::parse(&payload).unwrap();
+                let data = payload.slice(FrameHeader::SIZE..);
+                result = reassembler.add_segment(&header, data).unwrap();
+            }
+            assert_eq!(result.unwrap().data, data);
+        }
+    }
+
+    #[test]
+    fn test_single_segment_frame_shares_payload() {
+        let metadata = FrameMetadata::new(1, 1000, 1000, true);
+        let payload = EncodedFrame::new(metadata, vec![9u8; 1000]).into_segments()[0].to_payload();
+        let header = FrameHeader::parse(&payload).unwrap();
+        let data = payload.slice(FrameHeader::SIZE..);
+
+        let frame = FrameReassembler::new()
+            .add_segment(&header, data.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.data.as_ptr(), data.as_ptr());
+    }
+
     #[test]
     fn test_decoded_frame_planes() {
         // 4x4 YUV420P frame
@@ -366,4 +1826,268 @@ mod tests {
         assert!(frame.u_plane().iter().all(|&b| b == 2));
         assert!(frame.v_plane().iter().all(|&b| b == 3));
     }
+
+    #[test]
+    fn test_decoded_frame_strided_planes() {
+        // 4x2 frame with rows padded to 8 bytes and chroma padded to 4
+        let mut buffer = vec![0xEEu8; 8 * 2 + 4 + 4];
+        buffer[..4].copy_from_slice(&[1, 2, 3, 4]);
+        buffer[8..12].copy_from_slice(&[5, 6, 7, 8]);
+        buffer[16..18].copy_from_slice(&[9, 10]);
+        buffer[20..22].copy_from_slice(&[11, 12]);
+        let planes = [
+            PlaneLayout {
+                offset: 0,
+                stride: 8,
+            },
+            PlaneLayout {
+                offset: 16,
+                stride: 4,
+            },
+            PlaneLayout {
+                offset: 20,
+                stride: 4,
+            },
+        ];
+
+        let frame = DecodedFrame::from_planes(1, 1000, 4, 2, buffer.into(), planes).unwrap();
+
+        assert_eq!(
+            (frame.y_stride(), frame.u_stride(), frame.v_stride()),
+            (8, 4, 4)
+        );
+        // The last row isn't padded, so the final plane may end at the buffer end
+        assert_eq!(frame.y_plane().len(), 8 + 4);
+        assert_eq!(&frame.y_plane()[..4], &[1, 2, 3, 4]);
+        assert_eq!(&frame.y_plane()[8..12], &[5, 6, 7, 8]);
+        assert_eq!(frame.u_plane(), &[9, 10]);
+        assert_eq!(frame.v_plane(), &[11, 12]);
+    }
+
+    #[test]
+    fn test_decoded_frame_invalid_layout() {
+        let planes = |stride| {
+            [
+                PlaneLayout { offset: 0, stride },
+                PlaneLayout {
+                    offset: 32,
+                    stride: 2,
+                },
+                PlaneLayout {
+                    offset: 36,
+                    stride: 2,
+                },
+            ]
+        };
+        let buffer = Bytes::from(vec![0u8; 40]);
+
+        // Stride narrower than the visible width
+        assert!(DecodedFrame::from_planes(0, 0, 4, 4, buffer.clone(), planes(3)).is_err());
+        // V plane runs past the buffer
+        let short = Bytes::from(vec![0u8; 37]);
+        assert!(DecodedFrame::from_planes(0, 0, 4, 4, short, planes(8)).is_err());
+        assert!(DecodedFrame::from_planes(0, 0, 4, 4, buffer, planes(8)).is_ok());
+    }
+
+    #[test]
+    fn test_decoded_frame_clone_shares_buffer() {
+        let mut frame = DecodedFrame::new(0, 0, 4, 4, vec![7u8; 24]);
+        frame.set_frame_number(42);
+        let copy = frame.clone();
+
+        assert_eq!(copy.frame_number, 42);
+        assert!(std::ptr::eq(frame.y_plane(), copy.y_plane()));
+    }
+
+    #[test]
+    fn test_color_matrix_reference_triplets() {
+        // Black, white and the primaries as each matrix encodes them
+        let cases = [
+            (ColorMatrix::Bt601Limited, [16, 128, 128], [0, 0, 0]),
+            (ColorMatrix::Bt601Limited, [235, 128, 128], [255, 255, 255]),
+            (ColorMatrix::Bt601Limited, [81, 90, 240], [255, 0, 0]),
+            (ColorMatrix::Bt601Limited, [145, 54, 34], [0, 255, 0]),
+            (ColorMatrix::Bt601Limited, [41, 240, 110], [0, 0, 255]),
+            (ColorMatrix::Bt601Full, [0, 128, 128], [0, 0, 0]),
+            (ColorMatrix::Bt601Full, [255, 128, 128], [255, 255, 255]),
+            (ColorMatrix::Bt601Full, [76, 85, 255], [255, 0, 0]),
+            (ColorMatrix::Bt601Full, [150, 44, 21], [0, 255, 0]),
+            (ColorMatrix::Bt601Full, [29, 255, 107], [0, 0, 255]),
+            (ColorMatrix::Bt709Limited, [63, 102, 240], [255, 0, 0]),
+            (ColorMatrix::Bt709Limited, [173, 42, 26], [0, 255, 0]),
+            (ColorMatrix::Bt709Limited, [32, 240, 118], [0, 0, 255]),
+            (ColorMatrix::Bt709Full, [54, 99, 255], [255, 0, 0]),
+            (ColorMatrix::Bt709Full, [182, 30, 12], [0, 255, 0]),
+            (ColorMatrix::Bt709Full, [18, 255, 116], [0, 0, 255]),
+        ];
+
+        for (color, [y, u, v], expected) in cases {
+            let rgb = color.yuv_to_rgb(y, u, v);
+            for (got, want) in rgb.iter().zip(expected) {
+                assert!(
+                    got.abs_diff(want) <= 2,
+                    "{color:?} {:?} -> {rgb:?}, expected {expected:?}",
+                    [y, u, v]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_shader_coefficients_match_fixed_point() {
+        for color in [
+            ColorMatrix::Bt601Limited,
+            ColorMatrix::Bt601Full,
+            ColorMatrix::Bt709Limited,
+            ColorMatrix::Bt709Full,
+        ] {
+            let c = color.shader_coefficients();
+            for [y, u, v] in [[16, 128, 128], [81, 90, 240], [145, 54, 34], [200, 180, 60]] {
+                let luma = y as f32 / 255.0 - c.y_offset;
+                let cb = u as f32 / 255.0 - 128.0 / 255.0;
+                let cr = v as f32 / 255.0 - 128.0 / 255.0;
+                let rgb = [
+                    c.y * luma + c.r_v * cr,
+                    c.y * luma - c.g_u * cb - c.g_v * cr,
+                    c.y * luma + c.b_u * cb,
+                ]
+                .map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+                let expected = color.yuv_to_rgb(y, u, v);
+                for (got, want) in rgb.iter().zip(expected) {
+                    assert!(
+                        got.abs_diff(want) <= 1,
+                        "{color:?} {:?} -> {rgb:?}, expected {expected:?}",
+                        [y, u, v]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_gray_levels_round_trip() {
+        for color in [
+            ColorMatrix::Bt601Limited,
+            ColorMatrix::Bt601Full,
+            ColorMatrix::Bt709Limited,
+            ColorMatrix::Bt709Full,
+        ] {
+            let limited = matches!(color, ColorMatrix::Bt601Limited | ColorMatrix::Bt709Limited);
+            let levels = if limited { 16..=235 } else { 0..=255 };
+            for y in levels {
+                let [r, g, b] = color.yuv_to_rgb(y, 128, 128);
+                assert!(r == g && g == b, "{color:?} gray {y} -> {:?}", [r, g, b]);
+
+                // Back to luma the way an encoder would
+                let luma = if limited {
+                    16 + (r as u32 * 219 + 127) / 255
+                } else {
+                    r as u32
+                };
+                assert!(luma.abs_diff(y as u32) <= 1, "{color:?} gray {y} -> {luma}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgb_conversions_agree() {
+        let yuv: Vec<u8> = (0..24u8).map(|i| i * 10).collect();
+        let frame = DecodedFrame::new(0, 0, 4, 4, yuv);
+        let color = ColorMatrix::Bt709Limited;
+
+        let rgba = frame.to_rgba(color);
+        let mut rgb = vec![0; 4 * 4 * 3 + 5];
+        frame.to_rgb_into(color, &mut rgb);
+
+        assert_eq!(rgba.len(), 4 * 4 * 4);
+        for (i, (rgba, rgb)) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3)).enumerate() {
+            assert_eq!(&rgba[..3], rgb);
+            assert_eq!(rgba[3], 255);
+            assert_eq!(frame.rgb_at(i % 4, i / 4, color), rgb);
+        }
+        // Past the frame is left alone
+        assert_eq!(&rgb[48..], &[0; 5]);
+    }
+
+    #[test]
+    fn test_rgb_odd_dimensions() {
+        // 5x3 with 3x2 chroma planes, padded rows
+        let mut buffer = vec![0u8; 40];
+        for row in 0..3 {
+            buffer[row * 8..row * 8 + 5].copy_from_slice(&[100; 5]);
+        }
+        let u = [[90, 200, 150], [110, 120, 130]];
+        let v = [[240, 60, 100], [128, 16, 200]];
+        for row in 0..2 {
+            buffer[24 + row * 4..][..3].copy_from_slice(&u[row]);
+            buffer[32 + row * 4..][..3].copy_from_slice(&v[row]);
+        }
+        let planes = [
+            PlaneLayout {
+                offset: 0,
+                stride: 8,
+            },
+            PlaneLayout {
+                offset: 24,
+                stride: 4,
+            },
+            PlaneLayout {
+                offset: 32,
+                stride: 4,
+            },
+        ];
+        let frame = DecodedFrame::from_planes(0, 0, 5, 3, buffer.into(), planes).unwrap();
+        let color = ColorMatrix::Bt601Limited;
+
+        // The last column and row have chroma of their own
+        let rgba = frame.to_rgba(color);
+        assert_eq!(rgba.len(), 5 * 3 * 4);
+        for row in 0..3 {
+            for col in 0..5 {
+                let expected = color.yuv_to_rgb(100, u[row / 2][col / 2], v[row / 2][col / 2]);
+                let pixel = &rgba[(row * 5 + col) * 4..][..3];
+                assert_eq!(pixel, expected, "pixel {col},{row}");
+                assert_eq!(frame.rgb_at(col, row, color), expected);
+            }
+        }
+
+        let frame = DecodedFrame::new(0, 0, 1, 1, vec![235, 128, 128]);
+        assert_eq!(frame.to_rgba(color), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_crop_padding() {
+        // A 1367x769 display coded at 1368x770
+        let (width, height) = (1368usize, 770usize);
+        let mut yuv = vec![0u8; width * height * 3 / 2];
+        for row in 0..height {
+            yuv[row * width..][..width - 1].fill(100);
+        }
+        let mut frame = DecodedFrame::new(0, 0, width as u32, height as u32, yuv);
+        frame.crop(1367, 769);
+        assert_eq!((frame.width, frame.height), (1367, 769));
+        assert_eq!(frame.y_stride(), width);
+        assert_eq!(
+            frame.u_plane().len(),
+            (width / 2) * (height / 2 - 1) + width / 2
+        );
+
+        // Only the padding column was dropped
+        let rgba = frame.to_rgba(ColorMatrix::Bt601Limited);
+        assert_eq!(rgba.len(), 1367 * 769 * 4);
+        assert!(rgba
+            .chunks_exact(4)
+            .all(|pixel| pixel[..3] == ColorMatrix::Bt601Limited.yuv_to_rgb(100, 0, 0)));
+
+        frame.crop(4000, 4000);
+        assert_eq!((frame.width, frame.height), (1367, 769));
+    }
+
+    #[test]
+    #[should_panic(expected = "RGB buffer too small")]
+    fn test_rgb_into_short_buffer() {
+        let frame = DecodedFrame::new(0, 0, 4, 4, vec![0; 24]);
+        frame.to_rgb_into(ColorMatrix::default(), &mut [0; 47]);
+    }
 }
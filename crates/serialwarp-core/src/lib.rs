@@ -4,12 +4,42 @@
 //! frame handling, and error types used by both the source (Mac) and
 //! sink (PC) applications.
 
+pub mod capture;
+pub mod clock;
+pub mod codec;
+#[cfg(feature = "dump")]
+pub mod dump;
 pub mod error;
 pub mod frame;
+pub mod metrics;
+pub mod pacing;
+pub mod pool;
 pub mod protocol;
+pub mod protocol_vectors;
+pub mod resolution;
+pub mod rotation;
+pub mod sei;
+pub mod sequence;
+pub mod throughput;
+pub mod timing;
+pub mod trace;
 pub mod usb;
 
+pub use capture::*;
+pub use clock::*;
+pub use codec::*;
+#[cfg(feature = "dump")]
+pub use dump::*;
 pub use error::*;
 pub use frame::*;
+pub use pacing::*;
+pub use pool::*;
 pub use protocol::*;
+pub use resolution::*;
+pub use rotation::*;
+pub use sei::*;
+pub use sequence::*;
+pub use throughput::*;
+pub use timing::*;
+pub use trace::*;
 pub use usb::*;
@@ -0,0 +1,161 @@
+//! Counters and histograms for the `metrics` facade
+//!
+//! The pipeline records through these functions, and an application picks
+//! where they go by installing a recorder, e.g. a Prometheus exporter.
+//! Without the `metrics` feature every function here is empty, so
+//! recording costs nothing.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+use crate::PacketType;
+
+/// Packets sent, labelled with `type`
+pub const PACKETS_SENT: &str = "serialwarp_packets_sent_total";
+/// Packets received, labelled with `type`
+pub const PACKETS_RECEIVED: &str = "serialwarp_packets_received_total";
+/// Frames taken from the frame source
+pub const FRAMES_CAPTURED: &str = "serialwarp_frames_captured_total";
+/// Frames output by the encoder
+pub const FRAMES_ENCODED: &str = "serialwarp_frames_encoded_total";
+/// Frames fully sent as FRAME packets
+pub const FRAMES_SENT: &str = "serialwarp_frames_sent_total";
+/// Pictures output by the decoder
+pub const FRAMES_DECODED: &str = "serialwarp_frames_decoded_total";
+/// Frames dropped anywhere along the way, labelled with a `reason` from
+/// `DropReason`
+pub const FRAMES_DROPPED: &str = "serialwarp_frames_dropped_total";
+/// Packets whose CRC didn't match
+pub const CRC_FAILURES: &str = "serialwarp_crc_failures_total";
+/// Incomplete frames the reassembler gave up on
+pub const REASSEMBLY_EVICTIONS: &str = "serialwarp_reassembly_evictions_total";
+/// Time the encoder took per frame, in seconds
+pub const ENCODE_TIME: &str = "serialwarp_encode_seconds";
+/// Time the decoder took per frame, in seconds
+pub const DECODE_TIME: &str = "serialwarp_decode_seconds";
+/// Capture to decode time, in seconds
+pub const LATENCY: &str = "serialwarp_latency_seconds";
+
+/// Why a frame was dropped, the `reason` label of `FRAMES_DROPPED`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Captured while the source had no credits, so never encoded
+    NoCredit,
+    /// Dropped by the encoder under load
+    Encoder,
+    /// Discarded by the decoder while it waited for a keyframe
+    AwaitingKeyframe,
+    /// Skipped by the decoder to catch up with a backlog
+    CatchUp,
+    /// Decoded, then discarded because the consumer fell behind
+    Late,
+}
+
+impl DropReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DropReason::NoCredit => "no_credit",
+            DropReason::Encoder => "encoder",
+            DropReason::AwaitingKeyframe => "awaiting_keyframe",
+            DropReason::CatchUp => "catch_up",
+            DropReason::Late => "late",
+        }
+    }
+}
+
+/// Register a description and unit for every metric, so an exporter can
+/// list them before they're first recorded
+pub fn describe() {
+    #[cfg(feature = "metrics")]
+    {
+        use ::metrics::{describe_counter, describe_histogram, Unit};
+
+        describe_counter!(PACKETS_SENT, "Packets sent, by type");
+        describe_counter!(PACKETS_RECEIVED, "Packets received, by type");
+        describe_counter!(FRAMES_CAPTURED, "Frames taken from the frame source");
+        describe_counter!(FRAMES_ENCODED, "Frames output by the encoder");
+        describe_counter!(FRAMES_SENT, "Frames fully sent");
+        describe_counter!(FRAMES_DECODED, "Pictures output by the decoder");
+        describe_counter!(FRAMES_DROPPED, "Frames dropped, by reason");
+        describe_counter!(CRC_FAILURES, "Packets whose CRC didn't match");
+        describe_counter!(
+            REASSEMBLY_EVICTIONS,
+            "Incomplete frames the reassembler gave up on"
+        );
+        describe_histogram!(ENCODE_TIME, Unit::Seconds, "Time to encode a frame");
+        describe_histogram!(DECODE_TIME, Unit::Seconds, "Time to decode a frame");
+        describe_histogram!(LATENCY, Unit::Seconds, "Capture to decode time");
+    }
+}
+
+#[inline]
+pub fn packet_sent(packet_type: PacketType) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(PACKETS_SENT, "type" => packet_type.name()).increment(1);
+}
+
+#[inline]
+pub fn packet_received(packet_type: PacketType) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(PACKETS_RECEIVED, "type" => packet_type.name()).increment(1);
+}
+
+#[inline]
+pub fn frame_captured() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(FRAMES_CAPTURED).increment(1);
+}
+
+#[inline]
+pub fn frame_encoded() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(FRAMES_ENCODED).increment(1);
+}
+
+#[inline]
+pub fn frame_sent() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(FRAMES_SENT).increment(1);
+}
+
+#[inline]
+pub fn frames_decoded(count: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(FRAMES_DECODED).increment(count);
+}
+
+#[inline]
+pub fn frames_dropped(reason: DropReason, count: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(FRAMES_DROPPED, "reason" => reason.as_str()).increment(count);
+}
+
+#[inline]
+pub fn crc_failure() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(CRC_FAILURES).increment(1);
+}
+
+#[inline]
+pub fn reassembly_evictions(count: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(REASSEMBLY_EVICTIONS).increment(count);
+}
+
+#[inline]
+pub fn encode_time(time: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(ENCODE_TIME).record(time);
+}
+
+#[inline]
+pub fn decode_time(time: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(DECODE_TIME).record(time);
+}
+
+#[inline]
+pub fn latency(latency: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(LATENCY).record(latency);
+}
@@ -0,0 +1,302 @@
+//! Presentation timing for decoded frames
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::frame::DecodedFrame;
+
+/// A pts step larger than this (or any step backwards) is treated as a stream
+/// discontinuity, e.g. after the source reconnects, and re-anchors the clock
+pub const PTS_DISCONTINUITY_US: u64 = 1_000_000;
+
+/// Default number of decoded frames held ahead of presentation
+pub const DEFAULT_PACER_DEPTH: usize = 3;
+
+/// Frame pacing counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacerStats {
+    /// Frames handed out for presentation
+    pub presented: u64,
+    /// Frames presented more than one interval after their scheduled time
+    pub late: u64,
+    /// Frames discarded because a newer frame was due by the time they
+    /// could be presented
+    pub dropped: u64,
+    /// Frames discarded unpresented because the queue was full
+    pub overflowed: u64,
+}
+
+/// Schedules decoded frames so inter-frame spacing follows their pts deltas.
+///
+/// The first frame anchors stream time (pts) to local time; every later frame is
+/// due at `anchor_local + (pts - anchor_pts)`.
+#[derive(Debug)]
+pub struct FramePacer {
+    frame_interval: Duration,
+    max_depth: usize,
+    /// (pts_us, local time) pair mapping stream time to local time
+    anchor: Option<(u64, Instant)>,
+    last_pts_us: Option<u64>,
+    queue: VecDeque<(Instant, DecodedFrame)>,
+    stats: PacerStats,
+}
+
+impl FramePacer {
+    /// Create a pacer for a stream at the given nominal frame rate
+    pub fn new(fps: u32) -> Self {
+        Self {
+            frame_interval: Duration::from_micros(1_000_000 / fps.max(1) as u64),
+            max_depth: DEFAULT_PACER_DEPTH,
+            anchor: None,
+            last_pts_us: None,
+            queue: VecDeque::with_capacity(DEFAULT_PACER_DEPTH),
+            stats: PacerStats::default(),
+        }
+    }
+
+    /// Limit how many frames may be queued ahead of presentation
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    /// Queue a decoded frame received at `now`.
+    ///
+    /// If the queue is full the oldest frame is dropped.
+    pub fn push(&mut self, frame: DecodedFrame, now: Instant) {
+        let discontinuity = match self.last_pts_us {
+            Some(last) => frame.pts_us < last || frame.pts_us - last > PTS_DISCONTINUITY_US,
+            None => true,
+        };
+        if discontinuity {
+            self.anchor = Some((frame.pts_us, now));
+        }
+        self.last_pts_us = Some(frame.pts_us);
+
+        let due = self.schedule(frame.pts_us, now);
+
+        if self.queue.len() >= self.max_depth {
+            self.queue.pop_front();
+            self.stats.overflowed += 1;
+        }
+        self.queue.push_back((due, frame));
+    }
+
+    /// Take the frame that should be on screen at `now`, if a new one is due.
+    ///
+    /// This is the newest due frame; due frames older than it would only be
+    /// on screen until it replaced them, so they are dropped.
+    pub fn next_due(&mut self, now: Instant) -> Option<DecodedFrame> {
+        let (due, _) = self.queue.front()?;
+        if *due > now {
+            return None;
+        }
+
+        while self
+            .queue
+            .get(1)
+            .is_some_and(|(next_due, _)| *next_due <= now)
+        {
+            self.queue.pop_front();
+            self.stats.dropped += 1;
+        }
+
+        let (due, frame) = self.queue.pop_front()?;
+        let lateness = now.duration_since(due);
+        if lateness > self.frame_interval {
+            // Nothing newer to show instead: present it, and shift the clock so
+            // following frames aren't all late by the same amount
+            self.stats.late += 1;
+            if let Some((_, local)) = self.anchor.as_mut() {
+                *local += lateness;
+            }
+            for (queued_due, _) in self.queue.iter_mut() {
+                *queued_due += lateness;
+            }
+        }
+
+        self.stats.presented += 1;
+        Some(frame)
+    }
+
+    /// When the next queued frame is due, for sleeping until then
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.front().map(|(due, _)| *due)
+    }
+
+    /// Number of frames waiting for presentation
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Pacing counters
+    pub fn stats(&self) -> PacerStats {
+        self.stats
+    }
+
+    /// Discard queued frames and the time anchor (e.g. after a reconnect)
+    pub fn reset(&mut self) {
+        self.queue.clear();
+        self.anchor = None;
+        self.last_pts_us = None;
+    }
+
+    fn schedule(&self, pts_us: u64, now: Instant) -> Instant {
+        match self.anchor {
+            Some((anchor_pts, anchor_local)) => {
+                anchor_local + Duration::from_micros(pts_us.saturating_sub(anchor_pts))
+            }
+            None => now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL_US: u64 = 16_667;
+
+    fn frame(pts_us: u64) -> DecodedFrame {
+        DecodedFrame::new(0, pts_us, 2, 2, vec![0u8; 6])
+    }
+
+    fn ms(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    #[test]
+    fn test_first_frame_is_due_immediately() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+        pacer.push(frame(5_000_000), t0);
+
+        assert_eq!(pacer.next_deadline(), Some(t0));
+        assert_eq!(pacer.next_due(t0).unwrap().pts_us, 5_000_000);
+    }
+
+    #[test]
+    fn test_spacing_follows_pts() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+
+        // Frames decoded in a burst are spread out by their pts deltas
+        for i in 0..3 {
+            pacer.push(frame(i * INTERVAL_US), t0);
+        }
+
+        assert_eq!(pacer.next_due(t0).unwrap().pts_us, 0);
+        assert!(pacer.next_due(t0 + ms(10)).is_none());
+        assert_eq!(
+            pacer.next_deadline(),
+            Some(t0 + Duration::from_micros(INTERVAL_US))
+        );
+        assert_eq!(pacer.next_due(t0 + ms(17)).unwrap().pts_us, INTERVAL_US);
+        assert!(pacer.next_due(t0 + ms(30)).is_none());
+        assert_eq!(pacer.next_due(t0 + ms(34)).unwrap().pts_us, 2 * INTERVAL_US);
+        assert_eq!(pacer.stats().dropped, 0);
+        assert_eq!(pacer.stats().late, 0);
+    }
+
+    #[test]
+    fn test_drops_late_frames_when_newer_is_due() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+        for i in 0..3 {
+            pacer.push(frame(i * INTERVAL_US), t0);
+        }
+
+        // Stalled for 40ms: frames 0 and 1 are stale, frame 2 is due
+        let presented = pacer.next_due(t0 + ms(40)).unwrap();
+        assert_eq!(presented.pts_us, 2 * INTERVAL_US);
+        assert_eq!(pacer.stats().dropped, 2);
+        assert_eq!(pacer.stats().presented, 1);
+        assert_eq!(pacer.queued(), 0);
+    }
+
+    #[test]
+    fn test_late_frame_shifts_schedule() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+        pacer.push(frame(0), t0);
+        pacer.next_due(t0).unwrap();
+
+        // Frame 1 arrives 50ms after it was due and nothing newer is queued
+        let arrival = t0 + Duration::from_micros(INTERVAL_US) + ms(50);
+        pacer.push(frame(INTERVAL_US), arrival);
+        assert!(pacer.next_due(arrival).is_some());
+        assert_eq!(pacer.stats().late, 1);
+
+        // Frame 2 is now scheduled relative to the shifted clock
+        pacer.push(frame(2 * INTERVAL_US), arrival);
+        let deadline = pacer.next_deadline().unwrap();
+        assert_eq!(deadline - arrival, Duration::from_micros(INTERVAL_US));
+    }
+
+    #[test]
+    fn test_pts_jump_reanchors() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+        pacer.push(frame(90_000_000), t0);
+        pacer.next_due(t0).unwrap();
+
+        // Source reconnected and restarted its clock
+        let t1 = t0 + ms(500);
+        pacer.push(frame(0), t1);
+        assert_eq!(pacer.next_deadline(), Some(t1));
+        assert_eq!(pacer.next_due(t1).unwrap().pts_us, 0);
+
+        // Forward jumps re-anchor too
+        let t2 = t1 + ms(20);
+        pacer.push(frame(10_000_000), t2);
+        assert_eq!(pacer.next_deadline(), Some(t2));
+        assert_eq!(pacer.stats().late, 0);
+    }
+
+    #[test]
+    fn test_queue_depth_bounded() {
+        let mut pacer = FramePacer::new(60).with_max_depth(2);
+        let t0 = Instant::now();
+        for i in 0..4 {
+            pacer.push(frame(i * INTERVAL_US), t0);
+        }
+
+        assert_eq!(pacer.queued(), 2);
+        assert_eq!(pacer.stats().overflowed, 2);
+        assert_eq!(pacer.stats().dropped, 0);
+        assert_eq!(pacer.next_due(t0 + ms(40)).unwrap().pts_us, 2 * INTERVAL_US);
+    }
+
+    #[test]
+    fn test_newest_due_frame_wins() {
+        let mut pacer = FramePacer::new(60).with_max_depth(4);
+        let t0 = Instant::now();
+
+        // Uneven pts: a burst of three frames 4ms apart, then a gap
+        for pts_us in [0, 4_000, 8_000, 40_000] {
+            pacer.push(frame(pts_us), t0);
+        }
+
+        // At 10ms the first three are due, none a whole interval late
+        assert_eq!(pacer.next_due(t0 + ms(10)).unwrap().pts_us, 8_000);
+        assert_eq!(pacer.stats().dropped, 2);
+        assert_eq!(pacer.stats().late, 0);
+
+        // The frame still ahead isn't touched
+        assert!(pacer.next_due(t0 + ms(20)).is_none());
+        assert_eq!(pacer.queued(), 1);
+        assert_eq!(pacer.next_due(t0 + ms(40)).unwrap().pts_us, 40_000);
+        assert_eq!(pacer.stats().presented, 2);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut pacer = FramePacer::new(60);
+        let t0 = Instant::now();
+        pacer.push(frame(0), t0);
+        pacer.reset();
+
+        assert_eq!(pacer.queued(), 0);
+        assert!(pacer.next_deadline().is_none());
+    }
+}
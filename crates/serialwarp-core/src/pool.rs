@@ -0,0 +1,397 @@
+//! Reusable byte buffers for frame data
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+/// Default cap on the bytes a pool keeps on its freelists (enough for a few
+/// 4K YUV frames plus segment payloads)
+pub const DEFAULT_POOL_MAX_RETAINED: usize = 64 * 1024 * 1024;
+
+/// Pool counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers that had to be freshly allocated
+    pub allocations: u64,
+    /// Buffers handed out from a freelist
+    pub reuses: u64,
+    /// Returned buffers freed because the pool was at its cap
+    pub discarded: u64,
+    /// Bytes of capacity currently held on the freelists
+    pub retained_bytes: usize,
+    /// Buffers currently held on the freelists
+    pub retained_buffers: usize,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    /// Free buffers keyed by capacity class (a power of two)
+    buckets: BTreeMap<usize, Vec<Vec<u8>>>,
+    max_retained_bytes: usize,
+    max_retained_buffers: usize,
+    stats: PoolStats,
+}
+
+/// A thread-safe freelist of byte buffers bucketed by power-of-two size.
+///
+/// Cloning is cheap and clones share the same freelists. Buffers go back to
+/// the pool when their `PooledBuffer` (or every `Bytes` made from it) drops.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+impl BufferPool {
+    /// Create a pool that retains at most `max_retained_bytes` of free buffers
+    pub fn new(max_retained_bytes: usize) -> Self {
+        Self::with_limits(max_retained_bytes, usize::MAX)
+    }
+
+    /// Create a pool that retains at most `max_retained_buffers` free
+    /// buffers, however big, and at most `max_retained_bytes` in total
+    pub fn with_limits(max_retained_bytes: usize, max_retained_buffers: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                buckets: BTreeMap::new(),
+                max_retained_bytes,
+                max_retained_buffers,
+                stats: PoolStats::default(),
+            })),
+        }
+    }
+
+    /// Get an empty buffer with capacity for at least `capacity` bytes
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        let class = size_class(capacity);
+        let mut inner = self.inner.lock().unwrap();
+
+        let reused = inner.buckets.get_mut(&class).and_then(Vec::pop);
+        let data = match reused {
+            Some(mut data) => {
+                inner.stats.reuses += 1;
+                inner.stats.retained_bytes -= data.capacity();
+                inner.stats.retained_buffers -= 1;
+                data.clear();
+                data
+            }
+            None => {
+                inner.stats.allocations += 1;
+                Vec::with_capacity(class)
+            }
+        };
+
+        PooledBuffer {
+            data,
+            pool: Some(self.clone()),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    /// Drop every free buffer
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.buckets.clear();
+        inner.stats.retained_bytes = 0;
+        inner.stats.retained_buffers = 0;
+    }
+
+    fn put(&self, data: Vec<u8>) {
+        let capacity = data.capacity();
+        if capacity == 0 {
+            return;
+        }
+        // File under the largest class the buffer can fully serve
+        let class = if capacity.is_power_of_two() {
+            capacity
+        } else {
+            size_class(capacity) / 2
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        if capacity > inner.max_retained_bytes || inner.max_retained_buffers == 0 {
+            inner.stats.discarded += 1;
+            return;
+        }
+
+        // Make room by evicting other sizes: after a resolution change the
+        // old frame size won't be requested again
+        while inner.stats.retained_bytes.saturating_add(capacity) > inner.max_retained_bytes
+            || inner.stats.retained_buffers >= inner.max_retained_buffers
+        {
+            let victim = inner
+                .buckets
+                .iter_mut()
+                .rev()
+                .filter(|(&size, _)| size != class)
+                .find_map(|(_, free)| free.pop());
+            match victim {
+                Some(victim) => {
+                    inner.stats.retained_bytes -= victim.capacity();
+                    inner.stats.retained_buffers -= 1;
+                    inner.stats.discarded += 1;
+                }
+                None => {
+                    inner.stats.discarded += 1;
+                    return;
+                }
+            }
+        }
+
+        inner.stats.retained_bytes += capacity;
+        inner.stats.retained_buffers += 1;
+        inner.buckets.entry(class).or_default().push(data);
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_MAX_RETAINED)
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+fn size_class(capacity: usize) -> usize {
+    capacity.max(1).next_power_of_two()
+}
+
+/// A buffer borrowed from a `BufferPool`, returned to it on drop
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Freeze into `Bytes`; the buffer returns to the pool once every clone
+    /// of the `Bytes` has dropped
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+
+    /// Take the buffer out of the pool's management
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// A buffer that belongs to no pool and is simply freed on drop
+impl From<Vec<u8>> for PooledBuffer {
+    fn from(data: Vec<u8>) -> Self {
+        Self { data, pool: None }
+    }
+}
+
+/// Copies into a buffer from the same pool, if there is one
+impl Clone for PooledBuffer {
+    fn clone(&self) -> Self {
+        match &self.pool {
+            Some(pool) => {
+                let mut copy = pool.get(self.data.len());
+                copy.extend_from_slice(&self.data);
+                copy
+            }
+            None => self.data.clone().into(),
+        }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.data.len())
+            .field("capacity", &self.data.capacity())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::default();
+
+        for _ in 0..10 {
+            let mut buffer = pool.get(1000);
+            assert!(buffer.is_empty());
+            assert!(buffer.capacity() >= 1000);
+            buffer.extend_from_slice(&[1; 1000]);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.reuses, 9);
+        assert_eq!(stats.retained_bytes, 1024);
+    }
+
+    #[test]
+    fn test_frozen_bytes_return_on_last_drop() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.get(64);
+        buffer.extend_from_slice(b"payload");
+
+        let bytes = buffer.freeze();
+        let clone = bytes.clone();
+        assert_eq!(&clone[..], b"payload");
+        drop(bytes);
+        assert_eq!(pool.stats().retained_bytes, 0);
+
+        drop(clone);
+        assert_eq!(pool.stats().retained_bytes, 64);
+        let _ = pool.get(64);
+        assert_eq!(pool.stats().reuses, 1);
+    }
+
+    #[test]
+    fn test_size_buckets() {
+        let pool = BufferPool::default();
+        drop(pool.get(100));
+
+        // A bigger request can't use the 128-byte buffer
+        drop(pool.get(1000));
+        assert_eq!(pool.stats().allocations, 2);
+
+        // A smaller one in the same class can
+        drop(pool.get(65));
+        assert_eq!(pool.stats().reuses, 1);
+    }
+
+    #[test]
+    fn test_cap_bounds_memory_across_resolution_changes() {
+        const CAP: usize = 16 * 1024 * 1024;
+        let pool = BufferPool::new(CAP);
+
+        // 1080p, 4K, then 720p YUV420P frames, several in flight at once
+        for (width, height) in [(1920, 1080), (3840, 2160), (1280, 720), (1920, 1080)] {
+            for _ in 0..5 {
+                let in_flight: Vec<_> = (0..3).map(|_| pool.get(width * height * 3 / 2)).collect();
+                drop(in_flight);
+                assert!(pool.stats().retained_bytes <= CAP);
+            }
+        }
+
+        // Old sizes were evicted to make room, so the current size is pooled
+        let stats = pool.stats();
+        assert!(stats.discarded > 0);
+        let before = stats.allocations;
+        drop(pool.get(1920 * 1080 * 3 / 2));
+        assert_eq!(pool.stats().allocations, before);
+    }
+
+    #[test]
+    fn test_buffer_cap() {
+        let pool = BufferPool::with_limits(usize::MAX, 2);
+        let in_flight: Vec<_> = (0..3).map(|_| pool.get(1000)).collect();
+        drop(in_flight);
+
+        let stats = pool.stats();
+        assert_eq!(stats.retained_buffers, 2);
+        assert_eq!(stats.discarded, 1);
+
+        // A new size evicts an old one rather than being turned away
+        drop(pool.get(5000));
+        let stats = pool.stats();
+        assert_eq!(stats.retained_buffers, 2);
+        assert_eq!(stats.retained_bytes, 1024 + 8192);
+    }
+
+    #[test]
+    fn test_clone_copies_into_pool() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.get(16);
+        buffer.extend_from_slice(b"frame");
+
+        let copy = buffer.clone();
+        assert_eq!(&copy[..], b"frame");
+        drop(buffer);
+        drop(copy);
+        assert_eq!(pool.stats().retained_buffers, 2);
+
+        let unpooled = PooledBuffer::from(b"frame".to_vec());
+        assert_eq!(&unpooled.clone()[..], b"frame");
+    }
+
+    #[test]
+    fn test_oversized_buffer_not_retained() {
+        let pool = BufferPool::new(1024);
+        drop(pool.get(4096));
+
+        let stats = pool.stats();
+        assert_eq!(stats.retained_bytes, 0);
+        assert_eq!(stats.discarded, 1);
+    }
+
+    #[test]
+    fn test_into_vec_detaches() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.get(16);
+        buffer.push(1);
+
+        assert_eq!(buffer.into_vec(), vec![1]);
+        assert_eq!(pool.stats().retained_bytes, 0);
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        let pool = BufferPool::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        pool.get(4096).push(0);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocations + stats.reuses, 400);
+        assert!(stats.allocations <= 4);
+    }
+}
@@ -1,6 +1,7 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::error::ProtocolError;
+use crate::resolution::Resolution;
 
 /// Protocol magic number "SWRP" in little-endian
 pub const MAGIC: u32 = 0x53575250;
@@ -17,6 +18,65 @@ pub const HEADER_SIZE: usize = 16;
 /// CRC size in bytes
 pub const CRC_SIZE: usize = 4;
 
+/// Largest payload a compressed packet may expand to
+pub const MAX_DECOMPRESSED_SIZE: usize = 4 * MAX_SEGMENT_SIZE;
+
+/// Payloads shorter than this are never compressed
+const MIN_COMPRESSIBLE_SIZE: usize = 64;
+
+/// Packet header flag bits
+pub mod packet_flags {
+    /// The packet has no trailing CRC. Only used once both ends have
+    /// advertised `capabilities::NO_CRC`, and never on handshake packets.
+    pub const NO_CRC: u16 = 0x0001;
+    /// The payload is LZ4 block compressed, preceded by its decompressed
+    /// size as a u32. Only used once both ends have advertised
+    /// `capabilities::LZ4`.
+    pub const COMPRESSED: u16 = 0x0002;
+    /// Which of several streams on the link the packet belongs to, in the
+    /// top four bits. Always 0 unless both ends have advertised
+    /// `capabilities::MULTI_STREAM`.
+    pub const STREAM_ID: u16 = 0xF000;
+    /// Position of `STREAM_ID` in the flags
+    pub const STREAM_ID_SHIFT: u32 = 12;
+}
+
+/// Most streams one link can carry, the values `packet_flags::STREAM_ID`
+/// can hold
+pub const MAX_STREAMS: usize = 16;
+
+/// HELLO capability bits
+pub mod capabilities {
+    pub const HIDPI: u32 = 0x01;
+    pub const AUDIO: u32 = 0x02;
+    /// The link already protects data, e.g. TCP or an authenticated
+    /// wrapper, so packets after the handshake may leave out the CRC
+    pub const NO_CRC: u32 = 0x04;
+    /// Packets after the handshake may have LZ4 compressed payloads
+    pub const LZ4: u32 = 0x08;
+    /// Frames may be followed by XOR parity segments, from which the sink
+    /// rebuilds one lost segment per group
+    pub const FEC: u32 = 0x10;
+    /// Several displays may be streamed at once, as many as the smaller of
+    /// the two HELLOs' `max_streams`. Each stream after the first is started
+    /// with a START of its own, and every packet is tagged with its stream
+    /// in `packet_flags::STREAM_ID`.
+    pub const MULTI_STREAM: u32 = 0x20;
+    /// A source that reconnects may skip START by sending back the resume
+    /// token from its last START_ACK, see `ResumeToken`
+    pub const RESUME: u32 = 0x40;
+    /// The source answers a non-fatal DECODER_FAILED ERROR with a keyframe,
+    /// so the sink may send one to ask for it
+    pub const KEYFRAME_REQUEST: u32 = 0x80;
+    /// The source takes CREDIT_UPDATE, so the sink may grow or shrink its
+    /// credit window mid-stream
+    pub const CREDIT_UPDATE: u32 = 0x100;
+    /// The source sends PAUSE when it stops sending frames for a while, e.g.
+    /// paused or with nothing on screen changing, and again when they
+    /// resume, so the sink doesn't take the quiet for a stall
+    pub const PAUSE: u32 = 0x200;
+}
+
 /// Packet types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -27,10 +87,15 @@ pub enum PacketType {
     StartAck = 0x04,
     Frame = 0x10,
     FrameAck = 0x11,
+    CreditUpdate = 0x12,
+    BandwidthProbe = 0x20,
+    BandwidthProbeAck = 0x21,
     Stop = 0x30,
     StopAck = 0x31,
+    Pause = 0x32,
     Ping = 0x40,
     Pong = 0x41,
+    Error = 0x50,
 }
 
 impl PacketType {
@@ -42,17 +107,58 @@ impl PacketType {
             0x04 => Ok(PacketType::StartAck),
             0x10 => Ok(PacketType::Frame),
             0x11 => Ok(PacketType::FrameAck),
+            0x12 => Ok(PacketType::CreditUpdate),
+            0x20 => Ok(PacketType::BandwidthProbe),
+            0x21 => Ok(PacketType::BandwidthProbeAck),
             0x30 => Ok(PacketType::Stop),
             0x31 => Ok(PacketType::StopAck),
+            0x32 => Ok(PacketType::Pause),
             0x40 => Ok(PacketType::Ping),
             0x41 => Ok(PacketType::Pong),
+            0x50 => Ok(PacketType::Error),
             _ => Err(ProtocolError::UnknownPacketType(value)),
         }
     }
+
+    /// Name as the protocol spells it, e.g. `FRAME_ACK`
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketType::Hello => "HELLO",
+            PacketType::HelloAck => "HELLO_ACK",
+            PacketType::Start => "START",
+            PacketType::StartAck => "START_ACK",
+            PacketType::Frame => "FRAME",
+            PacketType::FrameAck => "FRAME_ACK",
+            PacketType::CreditUpdate => "CREDIT_UPDATE",
+            PacketType::BandwidthProbe => "BANDWIDTH_PROBE",
+            PacketType::BandwidthProbeAck => "BANDWIDTH_PROBE_ACK",
+            PacketType::Stop => "STOP",
+            PacketType::StopAck => "STOP_ACK",
+            PacketType::Pause => "PAUSE",
+            PacketType::Ping => "PING",
+            PacketType::Pong => "PONG",
+            PacketType::Error => "ERROR",
+        }
+    }
+
+    /// Whether this is a control packet, which should not wait behind
+    /// frame data; everything but FRAME is
+    pub fn is_control(self) -> bool {
+        self != PacketType::Frame
+    }
+
+    /// Whether this is part of the HELLO/START handshake, whose packets
+    /// always carry a CRC
+    pub fn is_handshake(self) -> bool {
+        matches!(
+            self,
+            PacketType::Hello | PacketType::HelloAck | PacketType::Start | PacketType::StartAck
+        )
+    }
 }
 
 /// Packet header (16 bytes)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PacketHeader {
     pub magic: u32,
     pub version: u8,
@@ -106,6 +212,9 @@ impl PacketHeader {
 
         let packet_type = PacketType::from_u8(buf.get_u8())?;
         let flags = buf.get_u16_le();
+        if flags & packet_flags::NO_CRC != 0 && packet_type.is_handshake() {
+            return Err(ProtocolError::CrcRequired(packet_type as u8));
+        }
         let sequence = buf.get_u32_le();
         let payload_length = buf.get_u32_le();
 
@@ -118,10 +227,26 @@ impl PacketHeader {
             payload_length,
         })
     }
+
+    /// The stream this packet belongs to, 0 on a single-stream link
+    pub fn stream_id(&self) -> u8 {
+        ((self.flags & packet_flags::STREAM_ID) >> packet_flags::STREAM_ID_SHIFT) as u8
+    }
+
+    /// Whether a CRC follows the payload
+    pub fn has_crc(&self) -> bool {
+        self.flags & packet_flags::NO_CRC == 0
+    }
+
+    /// Size of the whole packet on the wire: header, payload and CRC if any
+    pub fn packet_size(&self) -> usize {
+        let crc_size = if self.has_crc() { CRC_SIZE } else { 0 };
+        HEADER_SIZE + self.payload_length as usize + crc_size
+    }
 }
 
 /// Complete packet with header and payload
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Packet {
     pub header: PacketHeader,
     pub payload: Bytes,
@@ -133,10 +258,76 @@ impl Packet {
         Self { header, payload }
     }
 
-    /// Parse a packet from raw bytes. Returns the packet and number of bytes consumed.
+    /// Create a packet whose payload is LZ4 compressed, if that makes it
+    /// meaningfully smaller (by at least an eighth). Otherwise, and for
+    /// handshake packets, this is the same as `new`.
+    ///
+    /// The payload of the returned packet is as sent, i.e. compressed;
+    /// `parse` returns packets with the payload decompressed.
+    pub fn new_compressed(
+        packet_type: PacketType,
+        flags: u16,
+        sequence: u32,
+        payload: Bytes,
+    ) -> Self {
+        if packet_type.is_handshake()
+            || payload.len() < MIN_COMPRESSIBLE_SIZE
+            || payload.len() > MAX_DECOMPRESSED_SIZE
+        {
+            return Self::new(packet_type, flags, sequence, payload);
+        }
+
+        let compressed = lz4_flex::block::compress_prepend_size(&payload);
+        if compressed.len() > payload.len() - payload.len() / 8 {
+            return Self::new(packet_type, flags, sequence, payload);
+        }
+        Self::new(
+            packet_type,
+            flags | packet_flags::COMPRESSED,
+            sequence,
+            compressed.into(),
+        )
+    }
+
+    /// Whether the payload is compressed, i.e. this packet came from
+    /// `new_compressed` and is not yet sent
+    pub fn is_compressed(&self) -> bool {
+        self.header.flags & packet_flags::COMPRESSED != 0
+    }
+
+    /// Leave the CRC off this packet, unless it's a handshake packet, which
+    /// always has one
+    pub fn without_crc(mut self) -> Self {
+        if !self.header.packet_type.is_handshake() {
+            self.header.flags |= packet_flags::NO_CRC;
+        }
+        self
+    }
+
+    /// Tag this packet as belonging to stream `stream_id`, which must be
+    /// below `MAX_STREAMS`
+    pub fn with_stream_id(mut self, stream_id: u8) -> Self {
+        assert!(
+            (stream_id as usize) < MAX_STREAMS,
+            "stream id {} out of range",
+            stream_id
+        );
+        self.header.flags = (self.header.flags & !packet_flags::STREAM_ID)
+            | (stream_id as u16) << packet_flags::STREAM_ID_SHIFT;
+        self
+    }
+
+    /// The stream this packet belongs to, 0 on a single-stream link
+    pub fn stream_id(&self) -> u8 {
+        self.header.stream_id()
+    }
+
+    /// Parse a packet from raw bytes, with or without a CRC as its header
+    /// says, and decompress its payload if needed. Returns the packet and
+    /// number of bytes consumed.
     pub fn parse(data: &[u8]) -> Result<(Self, usize), ProtocolError> {
-        let header = PacketHeader::parse(data)?;
-        let total_size = HEADER_SIZE + header.payload_length as usize + CRC_SIZE;
+        let mut header = PacketHeader::parse(data)?;
+        let total_size = header.packet_size();
 
         if data.len() < total_size {
             return Err(ProtocolError::BufferTooShort {
@@ -148,31 +339,27 @@ impl Packet {
         // Extract payload
         let payload_start = HEADER_SIZE;
         let payload_end = HEADER_SIZE + header.payload_length as usize;
-        let payload = Bytes::copy_from_slice(&data[payload_start..payload_end]);
-
-        // Verify CRC
-        let crc_offset = payload_end;
-        let expected_crc = u32::from_le_bytes([
-            data[crc_offset],
-            data[crc_offset + 1],
-            data[crc_offset + 2],
-            data[crc_offset + 3],
-        ]);
-        let actual_crc = crc32c::crc32c(&data[..payload_end]);
-
-        if expected_crc != actual_crc {
-            return Err(ProtocolError::ChecksumMismatch {
-                expected: expected_crc,
-                actual: actual_crc,
-            });
+        let payload = &data[payload_start..payload_end];
+        if header.has_crc() {
+            verify_crc(&data[..payload_end], &data[payload_end..total_size])?;
         }
 
+        let payload = if header.flags & packet_flags::COMPRESSED != 0 {
+            let payload = decompress(payload)?;
+            header.flags &= !packet_flags::COMPRESSED;
+            header.payload_length = payload.len() as u32;
+            payload
+        } else {
+            Bytes::copy_from_slice(payload)
+        };
+
         Ok((Self { header, payload }, total_size))
     }
 
-    /// Serialize packet to bytes (header + payload + CRC)
+    /// Serialize packet to bytes (header + payload + CRC, unless the
+    /// header's `NO_CRC` flag is set)
     pub fn to_bytes(&self) -> Bytes {
-        let total_size = HEADER_SIZE + self.payload.len() + CRC_SIZE;
+        let total_size = self.header.packet_size();
         let mut buf = BytesMut::with_capacity(total_size);
 
         // Write header
@@ -180,6 +367,9 @@ impl Packet {
 
         // Write payload
         buf.put(self.payload.clone());
+        if !self.header.has_crc() {
+            return buf.freeze();
+        }
 
         // Compute and write CRC over header + payload
         let crc = crc32c::crc32c(&buf[..]);
@@ -188,6 +378,36 @@ impl Packet {
         buf.freeze()
     }
 
+    /// Serialize like `to_bytes`, but as buffers to send one after another
+    /// without joining them: the header, the payload, then the CRC unless
+    /// the header's `NO_CRC` flag is set
+    pub fn to_parts(&self) -> Vec<Bytes> {
+        Self::parts_with_payload(self.header.clone(), std::slice::from_ref(&self.payload))
+    }
+
+    /// Like `to_parts`, for a packet with `header` whose payload is the
+    /// `payload` buffers joined, e.g. a frame header and the segment data
+    /// after it. The header's `payload_length` is set from them.
+    ///
+    /// Only the header and CRC are newly allocated; the payload buffers
+    /// are shared, and can't be compressed.
+    pub fn parts_with_payload(mut header: PacketHeader, payload: &[Bytes]) -> Vec<Bytes> {
+        header.payload_length = payload.iter().map(Bytes::len).sum::<usize>() as u32;
+        let header_bytes = header.to_bytes();
+
+        let mut parts = Vec::with_capacity(payload.len() + 2);
+        let mut crc = crc32c::crc32c(&header_bytes);
+        parts.push(header_bytes);
+        for part in payload {
+            crc = crc32c::crc32c_append(crc, part);
+            parts.push(part.clone());
+        }
+        if header.has_crc() {
+            parts.push(Bytes::copy_from_slice(&crc.to_le_bytes()));
+        }
+        parts
+    }
+
     /// Get the packet type
     pub fn packet_type(&self) -> PacketType {
         self.header.packet_type
@@ -199,23 +419,297 @@ impl Packet {
     }
 }
 
-/// HELLO payload (28 bytes)
-#[derive(Debug, Clone)]
+/// Check `crc`, the little-endian CRC32C that follows `data`
+fn verify_crc(data: &[u8], crc: &[u8]) -> Result<(), ProtocolError> {
+    let expected = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+    let actual = crc32c::crc32c(data);
+    if expected != actual {
+        crate::metrics::crc_failure();
+        return Err(ProtocolError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Decompress a `COMPRESSED` payload, refusing any that claims to expand
+/// past `MAX_DECOMPRESSED_SIZE`
+fn decompress(payload: &[u8]) -> Result<Bytes, ProtocolError> {
+    if payload.len() < 4 {
+        return Err(ProtocolError::DecompressionFailed(
+            "missing decompressed size".to_string(),
+        ));
+    }
+    let size = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    if size > MAX_DECOMPRESSED_SIZE {
+        return Err(ProtocolError::DecompressionFailed(format!(
+            "{} bytes exceeds the {} byte limit",
+            size, MAX_DECOMPRESSED_SIZE
+        )));
+    }
+
+    let mut decompressed = vec![0; size];
+    let written = lz4_flex::block::decompress_into(&payload[4..], &mut decompressed)
+        .map_err(|e| ProtocolError::DecompressionFailed(e.to_string()))?;
+    if written != size {
+        return Err(ProtocolError::DecompressionFailed(format!(
+            "expected {} bytes, got {}",
+            size, written
+        )));
+    }
+    Ok(decompressed.into())
+}
+
+/// Splits a stream of bytes back into packets, for links where one read can
+/// hold several packets or only part of one
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    buffer: BytesMut,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append bytes read from the link
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes held that don't form a complete packet yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Take the next complete packet's bytes, header through CRC if it has
+    /// one, without checking the CRC. Returns `Ok(None)` until the whole packet has
+    /// arrived. An invalid header leaves no way to find the next packet, so
+    /// everything buffered is dropped along with the error.
+    pub fn next_packet_bytes(&mut self) -> Result<Option<Bytes>, ProtocolError> {
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header = match PacketHeader::parse(&self.buffer) {
+            Ok(header) => header,
+            Err(e) => {
+                self.buffer.clear();
+                return Err(e);
+            }
+        };
+        let total_size = header.packet_size();
+        if self.buffer.len() < total_size {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buffer.split_to(total_size).freeze()))
+    }
+
+    /// Take and parse the next complete packet. A CRC mismatch drops only
+    /// that packet.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, ProtocolError> {
+        match self.next_packet_bytes()? {
+            Some(bytes) => Packet::parse(&bytes).map(|(packet, _)| Some(packet)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Who a peer says it is, sent in the identity extension after HELLO's
+/// fixed fields. Each field is at most `MAX_FIELD_LEN` bytes of UTF-8 and
+/// empty if the peer left it out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    pub host_name: String,
+    /// Operating system and architecture, e.g. "macos-aarch64"
+    pub platform: String,
+    pub app_version: String,
+}
+
+impl PeerIdentity {
+    /// Longest field in bytes; longer ones are cut short
+    pub const MAX_FIELD_LEN: usize = 64;
+
+    const HOST_NAME: u8 = 1;
+    const PLATFORM: u8 = 2;
+    const APP_VERSION: u8 = 3;
+
+    pub fn new(
+        host_name: impl Into<String>,
+        platform: impl Into<String>,
+        app_version: impl Into<String>,
+    ) -> Self {
+        Self {
+            host_name: truncate_field(host_name.into()),
+            platform: truncate_field(platform.into()),
+            app_version: truncate_field(app_version.into()),
+        }
+    }
+
+    /// Whether the peer sent none of the fields
+    pub fn is_empty(&self) -> bool {
+        self.host_name.is_empty() && self.platform.is_empty() && self.app_version.is_empty()
+    }
+
+    /// Append the fields that are set as type, length, value entries
+    fn put(&self, buf: &mut BytesMut) {
+        for (field, value) in [
+            (Self::HOST_NAME, &self.host_name),
+            (Self::PLATFORM, &self.platform),
+            (Self::APP_VERSION, &self.app_version),
+        ] {
+            let value = &value.as_bytes()[..floor_char_boundary(value, Self::MAX_FIELD_LEN)];
+            if !value.is_empty() {
+                buf.put_u8(field);
+                buf.put_u8(value.len() as u8);
+                buf.put_slice(value);
+            }
+        }
+    }
+
+    /// Read the entries in `buf`. Unknown ones are skipped, invalid UTF-8 is
+    /// replaced, and an entry cut off by the end of the payload is dropped.
+    fn parse(mut buf: &[u8]) -> Self {
+        let mut identity = Self::default();
+        while buf.remaining() >= 2 {
+            let field = buf.get_u8();
+            let len = buf.get_u8() as usize;
+            if len > buf.remaining() {
+                break;
+            }
+            let value = truncate_field(String::from_utf8_lossy(&buf[..len]).into_owned());
+            buf.advance(len);
+            match field {
+                Self::HOST_NAME => identity.host_name = value,
+                Self::PLATFORM => identity.platform = value,
+                Self::APP_VERSION => identity.app_version = value,
+                _ => {}
+            }
+        }
+        identity
+    }
+}
+
+impl std::fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let host_name = if self.host_name.is_empty() {
+            "unknown host"
+        } else {
+            &self.host_name
+        };
+        write!(f, "{}", host_name)?;
+        match (self.platform.is_empty(), self.app_version.is_empty()) {
+            (true, true) => Ok(()),
+            (false, true) => write!(f, " ({})", self.platform),
+            (true, false) => write!(f, " (version {})", self.app_version),
+            (false, false) => write!(f, " ({}, version {})", self.platform, self.app_version),
+        }
+    }
+}
+
+/// Cut `value` to at most `PeerIdentity::MAX_FIELD_LEN` bytes, keeping whole
+/// characters
+fn truncate_field(mut value: String) -> String {
+    value.truncate(floor_char_boundary(&value, PeerIdentity::MAX_FIELD_LEN));
+    value
+}
+
+/// Largest char boundary in `value` at or before `index`
+fn floor_char_boundary(value: &str, index: usize) -> usize {
+    if index >= value.len() {
+        return value.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|&i| value.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// Opaque token the sink gives out in START_ACK. A source that reconnects
+/// sends it back in HELLO, and if the sink still holds the session it
+/// names, streaming picks up with the same parameters without a START.
+///
+/// It only tells sessions apart; anyone on the link can read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResumeToken(pub [u8; 16]);
+
+impl ResumeToken {
+    pub const SIZE: usize = 16;
+
+    /// A new random token
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; Self::SIZE];
+        getrandom::getrandom(&mut bytes).expect("no random source");
+        Self(bytes)
+    }
+
+    /// The token in a type, length, value entry, if `value` is one
+    fn from_entry(value: &[u8]) -> Option<Self> {
+        value.try_into().ok().map(Self)
+    }
+
+    fn put_entry(&self, buf: &mut BytesMut, entry: u8) {
+        buf.put_u8(entry);
+        buf.put_u8(Self::SIZE as u8);
+        buf.put_slice(&self.0);
+    }
+}
+
+impl std::fmt::Display for ResumeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Value of the first type, length, value entry in `buf` of type `entry`,
+/// skipping any others
+fn find_entry(mut buf: &[u8], entry: u8) -> Option<&[u8]> {
+    while buf.remaining() >= 2 {
+        let field = buf.get_u8();
+        let len = buf.get_u8() as usize;
+        if len > buf.remaining() {
+            return None;
+        }
+        if field == entry {
+            return Some(&buf[..len]);
+        }
+        buf.advance(len);
+    }
+    None
+}
+
+/// HELLO payload (28 bytes, then the peer's identity)
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HelloPayload {
     pub software_version: u16,
     pub min_protocol_version: u16,
     pub max_protocol_version: u16,
-    pub reserved1: u16,
+    /// Largest scale, in pixels per point, the sender can capture or show;
+    /// 0 from peers that predate it, meaning 1. See `max_scale()`.
+    pub max_scale: u16,
     pub max_width: u32,
     pub max_height: u32,
     pub max_fps_fixed: u32, // Fixed-point 16.16
     pub capabilities: u32,
-    pub reserved2: u32,
+    /// Most streams the sender can send or show at once, used when both
+    /// ends have `capabilities::MULTI_STREAM`; 0, as from peers that
+    /// predate it, means 1. See `max_streams()`.
+    pub max_streams: u32,
+    /// Empty from peers that predate it
+    pub identity: PeerIdentity,
+    /// From a source asking to resume a session, after the identity
+    pub resume_token: Option<ResumeToken>,
 }
 
 impl HelloPayload {
+    /// Fixed fields, all a peer without the identity sends
     pub const SIZE: usize = 28;
 
+    /// Entry type of the resume token, following the identity's
+    const RESUME_TOKEN: u8 = 0x10;
+
     pub fn new(
         software_version: u16,
         max_width: u32,
@@ -227,12 +721,14 @@ impl HelloPayload {
             software_version,
             min_protocol_version: PROTOCOL_VERSION as u16,
             max_protocol_version: PROTOCOL_VERSION as u16,
-            reserved1: 0,
+            max_scale: 1,
             max_width,
             max_height,
             max_fps_fixed: max_fps << 16, // Convert to fixed 16.16
             capabilities,
-            reserved2: 0,
+            max_streams: 0,
+            identity: PeerIdentity::default(),
+            resume_token: None,
         }
     }
 
@@ -241,12 +737,16 @@ impl HelloPayload {
         buf.put_u16_le(self.software_version);
         buf.put_u16_le(self.min_protocol_version);
         buf.put_u16_le(self.max_protocol_version);
-        buf.put_u16_le(self.reserved1);
+        buf.put_u16_le(self.max_scale);
         buf.put_u32_le(self.max_width);
         buf.put_u32_le(self.max_height);
         buf.put_u32_le(self.max_fps_fixed);
         buf.put_u32_le(self.capabilities);
-        buf.put_u32_le(self.reserved2);
+        buf.put_u32_le(self.max_streams);
+        self.identity.put(&mut buf);
+        if let Some(token) = &self.resume_token {
+            token.put_entry(&mut buf, Self::RESUME_TOKEN);
+        }
         buf.freeze()
     }
 
@@ -263,34 +763,109 @@ impl HelloPayload {
             software_version: buf.get_u16_le(),
             min_protocol_version: buf.get_u16_le(),
             max_protocol_version: buf.get_u16_le(),
-            reserved1: buf.get_u16_le(),
+            max_scale: buf.get_u16_le(),
             max_width: buf.get_u32_le(),
             max_height: buf.get_u32_le(),
             max_fps_fixed: buf.get_u32_le(),
             capabilities: buf.get_u32_le(),
-            reserved2: buf.get_u32_le(),
+            max_streams: buf.get_u32_le(),
+            identity: PeerIdentity::parse(buf),
+            resume_token: find_entry(buf, Self::RESUME_TOKEN).and_then(ResumeToken::from_entry),
         })
     }
 
+    /// Advertise a largest scale other than 1
+    pub fn with_max_scale(mut self, max_scale: u16) -> Self {
+        self.max_scale = max_scale;
+        self
+    }
+
+    /// Advertise sending or showing more than one stream at once
+    pub fn with_max_streams(mut self, max_streams: u8) -> Self {
+        self.max_streams = max_streams as u32;
+        self
+    }
+
+    /// Say who is sending the HELLO
+    pub fn with_identity(mut self, identity: PeerIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Ask to resume the session `token` was given out for
+    pub fn with_resume_token(mut self, token: Option<ResumeToken>) -> Self {
+        self.resume_token = token;
+        self
+    }
+
     /// Get max FPS as integer (extracts whole part from fixed 16.16)
     pub fn max_fps(&self) -> u32 {
         self.max_fps_fixed >> 16
     }
 
+    /// Largest scale the sender handles, at least 1
+    pub fn max_scale(&self) -> u16 {
+        self.max_scale.max(1)
+    }
+
+    /// Most streams the sender handles at once, from 1 to `MAX_STREAMS`
+    pub fn max_streams(&self) -> u8 {
+        self.max_streams.clamp(1, MAX_STREAMS as u32) as u8
+    }
+
     /// Check if HiDPI capability is set
     pub fn supports_hidpi(&self) -> bool {
-        self.capabilities & 0x01 != 0
+        self.capabilities & capabilities::HIDPI != 0
     }
 
     /// Check if audio capability is set
     pub fn supports_audio(&self) -> bool {
-        self.capabilities & 0x02 != 0
+        self.capabilities & capabilities::AUDIO != 0
+    }
+
+    /// Check if the sender can leave CRCs off packets after the handshake
+    pub fn supports_no_crc(&self) -> bool {
+        self.capabilities & capabilities::NO_CRC != 0
+    }
+
+    /// Check if the sender can decompress LZ4 payloads
+    pub fn supports_lz4(&self) -> bool {
+        self.capabilities & capabilities::LZ4 != 0
+    }
+
+    /// Check if the sender understands parity segments
+    pub fn supports_fec(&self) -> bool {
+        self.capabilities & capabilities::FEC != 0
+    }
+
+    /// Check if the sender can resume a session
+    pub fn supports_resume(&self) -> bool {
+        self.capabilities & capabilities::RESUME != 0
+    }
+
+    /// Check if the sender sends or answers keyframe requests
+    pub fn supports_keyframe_request(&self) -> bool {
+        self.capabilities & capabilities::KEYFRAME_REQUEST != 0
+    }
+
+    /// Check if the sender sends or takes CREDIT_UPDATE
+    pub fn supports_credit_update(&self) -> bool {
+        self.capabilities & capabilities::CREDIT_UPDATE != 0
+    }
+
+    /// Check if the sender sends or takes PAUSE
+    pub fn supports_pause(&self) -> bool {
+        self.capabilities & capabilities::PAUSE != 0
     }
 }
 
-/// START payload (24 bytes)
-#[derive(Debug, Clone)]
+/// START payload (24 bytes, then 8 for the display size)
+///
+/// Sizes are in pixels. `scale` says how many pixels make a point on the
+/// source's display, so the sink can size its window in points.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StartPayload {
+    /// Coded size, always even
     pub width: u32,
     pub height: u32,
     pub fps_fixed: u32, // Fixed-point 16.16
@@ -300,16 +875,27 @@ pub struct StartPayload {
     pub audio_sample_rate: u16,
     pub audio_channels: u8,
     pub audio_bits: u8,
-    pub reserved: u16,
+    /// Pixels per point; 0 from peers that predate it, meaning 1. See
+    /// `scale()`.
+    pub scale: u16,
+    /// Visible size; the coded frame is cropped to this. Equal to the coded
+    /// size when a peer leaves out the extension.
+    pub display_width: u32,
+    pub display_height: u32,
 }
 
 impl StartPayload {
+    /// Base payload, all a peer without the display size sends
     pub const SIZE: usize = 24;
+    /// Base payload plus the display size
+    pub const EXTENDED_SIZE: usize = 32;
 
+    /// START for a `width` x `height` display, coded at the next even size
     pub fn new(width: u32, height: u32, fps: u32, bitrate_bps: u32) -> Self {
+        let coded = Resolution::new(width, height).coded();
         Self {
-            width,
-            height,
+            width: coded.width,
+            height: coded.height,
             fps_fixed: fps << 16,
             bitrate_bps,
             pixel_format: 0, // NV12
@@ -317,12 +903,14 @@ impl StartPayload {
             audio_sample_rate: 0,
             audio_channels: 0,
             audio_bits: 0,
-            reserved: 0,
+            scale: 1,
+            display_width: width,
+            display_height: height,
         }
     }
 
     pub fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        let mut buf = BytesMut::with_capacity(Self::EXTENDED_SIZE);
         buf.put_u32_le(self.width);
         buf.put_u32_le(self.height);
         buf.put_u32_le(self.fps_fixed);
@@ -332,7 +920,9 @@ impl StartPayload {
         buf.put_u16_le(self.audio_sample_rate);
         buf.put_u8(self.audio_channels);
         buf.put_u8(self.audio_bits);
-        buf.put_u16_le(self.reserved);
+        buf.put_u16_le(self.scale);
+        buf.put_u32_le(self.display_width);
+        buf.put_u32_le(self.display_height);
         buf.freeze()
     }
 
@@ -354,13 +944,24 @@ impl StartPayload {
         let audio_sample_rate = buf.get_u16_le();
         let audio_channels = buf.get_u8();
         let audio_bits = buf.get_u8();
-        let reserved = buf.get_u16_le();
-
-        // Validate dimensions
-        if width == 0 || height == 0 {
-            return Err(ProtocolError::InvalidPayloadLength {
-                expected: 1, // minimum dimension
-                actual: 0,
+        let scale = buf.get_u16_le();
+        let (display_width, display_height) = if buf.remaining() >= 8 {
+            (buf.get_u32_le(), buf.get_u32_le())
+        } else {
+            (width, height)
+        };
+
+        // The coded size must be a valid even size, and the display size
+        // at most one padding column and row smaller
+        let coded = Resolution::new(width, height);
+        let display = Resolution::new(display_width, display_height);
+        if !coded.is_valid() || coded.is_padded() {
+            return Err(ProtocolError::InvalidResolution { width, height });
+        }
+        if !display.is_valid() || display.coded() != coded {
+            return Err(ProtocolError::InvalidResolution {
+                width: display_width,
+                height: display_height,
             });
         }
 
@@ -374,33 +975,68 @@ impl StartPayload {
             audio_sample_rate,
             audio_channels,
             audio_bits,
-            reserved,
+            scale,
+            display_width,
+            display_height,
         })
     }
 
+    /// Size the stream is coded at
+    pub fn coded_size(&self) -> Resolution {
+        Resolution::new(self.width, self.height)
+    }
+
+    /// Size the sink should show, with any padding cropped off
+    pub fn display_size(&self) -> Resolution {
+        Resolution::new(self.display_width, self.display_height)
+    }
+
+    /// Mark the stream as captured at `scale` pixels per point
+    pub fn with_scale(mut self, scale: u16) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Pixels per point, at least 1
+    pub fn scale(&self) -> u16 {
+        self.scale.max(1)
+    }
+
+    /// The display size in points, for sizing the sink's window
+    pub fn point_size(&self) -> Resolution {
+        self.display_size().to_points(self.scale())
+    }
+
     /// Get FPS as integer
     pub fn fps(&self) -> u32 {
         self.fps_fixed >> 16
     }
 }
 
-/// START_ACK payload (4 bytes)
-#[derive(Debug, Clone)]
+/// START_ACK payload (4 bytes, then the resume token if the sink gives one)
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StartAckPayload {
     pub status: u8,
     pub reserved: u8,
     pub initial_credits: u16,
+    /// For the source to resume this session with, if both ends support
+    /// `capabilities::RESUME`
+    pub resume_token: Option<ResumeToken>,
 }
 
 impl StartAckPayload {
     pub const SIZE: usize = 4;
     pub const DEFAULT_CREDITS: u16 = 8;
 
+    /// Entry type of the resume token
+    const RESUME_TOKEN: u8 = 1;
+
     pub fn new(status: u8, initial_credits: u16) -> Self {
         Self {
             status,
             reserved: 0,
             initial_credits,
+            resume_token: None,
         }
     }
 
@@ -408,11 +1044,20 @@ impl StartAckPayload {
         Self::new(0, initial_credits)
     }
 
+    /// Give the source a token to resume this session with
+    pub fn with_resume_token(mut self, token: Option<ResumeToken>) -> Self {
+        self.resume_token = token;
+        self
+    }
+
     pub fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        let mut buf = BytesMut::with_capacity(Self::SIZE + 2 + ResumeToken::SIZE);
         buf.put_u8(self.status);
         buf.put_u8(self.reserved);
         buf.put_u16_le(self.initial_credits);
+        if let Some(token) = &self.resume_token {
+            token.put_entry(&mut buf, Self::RESUME_TOKEN);
+        }
         buf.freeze()
     }
 
@@ -429,6 +1074,7 @@ impl StartAckPayload {
             status: buf.get_u8(),
             reserved: buf.get_u8(),
             initial_credits: buf.get_u16_le(),
+            resume_token: find_entry(buf, Self::RESUME_TOKEN).and_then(ResumeToken::from_entry),
         })
     }
 
@@ -438,7 +1084,7 @@ impl StartAckPayload {
 }
 
 /// FRAME header (32 bytes, precedes encoded data)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameHeader {
     pub frame_number: u64,
     pub pts_us: u64,
@@ -450,6 +1096,9 @@ pub struct FrameHeader {
 
 impl FrameHeader {
     pub const SIZE: usize = 32;
+    /// Set in `segment_index` on a parity segment, whose low bits are then
+    /// the index of the parity group rather than of a data segment
+    pub const PARITY_FLAG: u16 = 0x8000;
 
     pub fn new(
         frame_number: u64,
@@ -471,13 +1120,18 @@ impl FrameHeader {
 
     pub fn to_bytes(&self) -> Bytes {
         let mut buf = BytesMut::with_capacity(Self::SIZE);
+        self.write_to(&mut buf);
+        buf.freeze()
+    }
+
+    /// Append the header's `SIZE` bytes to `buf`
+    pub fn write_to(&self, buf: &mut impl BufMut) {
         buf.put_u64_le(self.frame_number);
         buf.put_u64_le(self.pts_us);
         buf.put_u64_le(self.capture_ts_us);
         buf.put_u32_le(self.frame_size);
         buf.put_u16_le(self.segment_index);
         buf.put_u16_le(self.segment_count);
-        buf.freeze()
     }
 
     pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
@@ -502,30 +1156,46 @@ impl FrameHeader {
                 "segment_count cannot be zero".to_string(),
             ));
         }
-        if segment_index >= segment_count {
-            return Err(ProtocolError::FrameReassemblyError(format!(
-                "segment_index ({}) must be less than segment_count ({})",
-                segment_index, segment_count
-            )));
-        }
-
-        Ok(Self {
+        let header = Self {
             frame_number,
             pts_us,
             capture_ts_us,
             frame_size,
             segment_index,
             segment_count,
-        })
+        };
+        // There is at most one parity group per data segment
+        let position = header.parity_group().unwrap_or(segment_index);
+        if position >= segment_count {
+            return Err(ProtocolError::FrameReassemblyError(format!(
+                "segment_index ({}) must be less than segment_count ({})",
+                position, segment_count
+            )));
+        }
+
+        Ok(header)
+    }
+
+    /// Whether this heads a parity segment rather than frame data
+    pub fn is_parity(&self) -> bool {
+        self.segment_index & Self::PARITY_FLAG != 0
+    }
+
+    /// The parity group this segment covers, if it is a parity segment
+    pub fn parity_group(&self) -> Option<u16> {
+        self.is_parity()
+            .then_some(self.segment_index & !Self::PARITY_FLAG)
     }
 }
 
 /// FRAME_ACK payload (16 bytes)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameAckPayload {
     pub frame_number: u64,
     pub decode_time_us: u32,
     pub credits_returned: u16,
+    /// Zero in a single ack; an extended FRAME_ACK stores its entry count
+    /// here (see `FrameAckBatchPayload`)
     pub reserved: u16,
 }
 
@@ -568,61 +1238,129 @@ impl FrameAckPayload {
     }
 }
 
-/// PING payload (8 bytes)
-#[derive(Debug, Clone)]
-pub struct PingPayload {
-    pub timestamp_us: u64,
+/// One acknowledged frame in a `FrameAckBatchPayload`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameAckEntry {
+    pub frame_number: u64,
+    pub decode_time_us: u32,
 }
 
-impl PingPayload {
-    pub const SIZE: usize = 8;
+/// FRAME_ACK payload acknowledging several frames at once.
+///
+/// The first 16 bytes are laid out like `FrameAckPayload`, with the first
+/// entry, the total credits returned, and the entry count in place of the
+/// reserved field. Each further entry follows as 12 bytes: frame number
+/// (u64) and decode time (u32). A batch of one entry is written with a
+/// count of zero, so it is byte for byte a single ack, and `parse` accepts
+/// either form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameAckBatchPayload {
+    pub entries: Vec<FrameAckEntry>,
+    pub credits_returned: u16,
+}
 
-    pub fn new(timestamp_us: u64) -> Self {
-        Self { timestamp_us }
+impl FrameAckBatchPayload {
+    /// Size of each entry after the first
+    pub const ENTRY_SIZE: usize = 12;
+    /// Most entries a single FRAME_ACK can carry
+    pub const MAX_ENTRIES: usize = u16::MAX as usize;
+
+    pub fn new(entries: Vec<FrameAckEntry>, credits_returned: u16) -> Self {
+        Self {
+            entries,
+            credits_returned,
+        }
     }
 
+    /// Panics if there are no entries or more than `MAX_ENTRIES`; credits
+    /// without an acknowledged frame go in a CREDIT_UPDATE instead
     pub fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(Self::SIZE);
-        buf.put_u64_le(self.timestamp_us);
+        let (first, rest) = self
+            .entries
+            .split_first()
+            .expect("FRAME_ACK needs at least one entry");
+        assert!(self.entries.len() <= Self::MAX_ENTRIES);
+        let count = if rest.is_empty() {
+            0
+        } else {
+            self.entries.len() as u16
+        };
+
+        let mut buf =
+            BytesMut::with_capacity(FrameAckPayload::SIZE + rest.len() * Self::ENTRY_SIZE);
+        buf.put_u64_le(first.frame_number);
+        buf.put_u32_le(first.decode_time_us);
+        buf.put_u16_le(self.credits_returned);
+        buf.put_u16_le(count);
+        for entry in rest {
+            buf.put_u64_le(entry.frame_number);
+            buf.put_u32_le(entry.decode_time_us);
+        }
         buf.freeze()
     }
 
     pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
-        if data.len() < Self::SIZE {
+        let head = FrameAckPayload::parse(data)?;
+        let count = (head.reserved as usize).max(1);
+        let expected = FrameAckPayload::SIZE + (count - 1) * Self::ENTRY_SIZE;
+        if data.len() < expected {
             return Err(ProtocolError::InvalidPayloadLength {
-                expected: Self::SIZE,
+                expected,
                 actual: data.len(),
             });
         }
 
-        let mut buf = data;
+        let mut entries = Vec::with_capacity(count);
+        entries.push(FrameAckEntry {
+            frame_number: head.frame_number,
+            decode_time_us: head.decode_time_us,
+        });
+        let mut buf = &data[FrameAckPayload::SIZE..expected];
+        while !buf.is_empty() {
+            entries.push(FrameAckEntry {
+                frame_number: buf.get_u64_le(),
+                decode_time_us: buf.get_u32_le(),
+            });
+        }
         Ok(Self {
-            timestamp_us: buf.get_u64_le(),
+            entries,
+            credits_returned: head.credits_returned,
         })
     }
 }
 
-/// PONG payload (16 bytes)
-#[derive(Debug, Clone)]
-pub struct PongPayload {
-    pub ping_timestamp_us: u64,
-    pub pong_timestamp_us: u64,
+impl From<FrameAckPayload> for FrameAckBatchPayload {
+    fn from(ack: FrameAckPayload) -> Self {
+        Self::new(
+            vec![FrameAckEntry {
+                frame_number: ack.frame_number,
+                decode_time_us: ack.decode_time_us,
+            }],
+            ack.credits_returned,
+        )
+    }
 }
 
-impl PongPayload {
-    pub const SIZE: usize = 16;
+/// CREDIT_UPDATE payload (8 bytes): the sink resizing the credit window
+/// outside of any FRAME_ACK
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditUpdatePayload {
+    /// Credits to add to the window, or to take away if negative
+    pub delta: i32,
+    pub reserved: u32,
+}
 
-    pub fn new(ping_timestamp_us: u64, pong_timestamp_us: u64) -> Self {
-        Self {
-            ping_timestamp_us,
-            pong_timestamp_us,
-        }
+impl CreditUpdatePayload {
+    pub const SIZE: usize = 8;
+
+    pub fn new(delta: i32) -> Self {
+        Self { delta, reserved: 0 }
     }
 
     pub fn to_bytes(&self) -> Bytes {
         let mut buf = BytesMut::with_capacity(Self::SIZE);
-        buf.put_u64_le(self.ping_timestamp_us);
-        buf.put_u64_le(self.pong_timestamp_us);
+        buf.put_i32_le(self.delta);
+        buf.put_u32_le(self.reserved);
         buf.freeze()
     }
 
@@ -636,30 +1374,790 @@ impl PongPayload {
 
         let mut buf = data;
         Ok(Self {
-            ping_timestamp_us: buf.get_u64_le(),
-            pong_timestamp_us: buf.get_u64_le(),
+            delta: buf.get_i32_le(),
+            reserved: buf.get_u32_le(),
         })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// BANDWIDTH_PROBE payload (16 bytes, then padding)
+///
+/// A burst of `count` probes, numbered by `index`, lets the receiver time
+/// how fast the link delivers them. The padding is noise, so compression
+/// can't shrink it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthProbePayload {
+    /// Tells one measurement's probes from another's
+    pub probe_id: u16,
+    /// See `BandwidthProbePayload::REVERSE`
+    pub flags: u16,
+    pub index: u32,
+    /// Probes in the burst; 0 for a lone probe acked at once
+    pub count: u32,
+    /// Bytes of padding after the fixed fields
+    pub padding: u32,
+}
 
-    #[test]
-    fn test_packet_roundtrip() {
-        let payload = HelloPayload::new(1, 3840, 2160, 60, 0x03);
-        let packet = Packet::new(PacketType::Hello, 0, 1, payload.to_bytes());
-        let bytes = packet.to_bytes();
+impl BandwidthProbePayload {
+    pub const SIZE: usize = 16;
+    /// Asks the receiver to send a burst of `count` probes with `padding`
+    /// bytes each back, rather than to ack. A request has no padding itself.
+    pub const REVERSE: u16 = 0x0001;
 
-        let (parsed, consumed) = Packet::parse(&bytes).unwrap();
-        assert_eq!(consumed, bytes.len());
-        assert_eq!(parsed.header.packet_type, PacketType::Hello);
-        assert_eq!(parsed.header.sequence, 1);
-        assert_eq!(parsed.payload, payload.to_bytes());
+    pub fn new(probe_id: u16, index: u32, count: u32, padding: u32) -> Self {
+        Self {
+            probe_id,
+            flags: 0,
+            index,
+            count,
+            padding,
+        }
     }
 
-    #[test]
+    /// A request for the receiver to send a burst back
+    pub fn reverse(probe_id: u16, count: u32, padding: u32) -> Self {
+        Self {
+            flags: Self::REVERSE,
+            ..Self::new(probe_id, 0, count, padding)
+        }
+    }
+
+    pub fn is_reverse(&self) -> bool {
+        self.flags & Self::REVERSE != 0
+    }
+
+    /// Whether this is the last probe of its burst, after which the
+    /// receiver acks
+    pub fn is_last(&self) -> bool {
+        self.index.saturating_add(1) >= self.count
+    }
+
+    /// Bytes of padding actually sent with this probe
+    fn padding_sent(&self) -> usize {
+        if self.is_reverse() {
+            0
+        } else {
+            self.padding as usize
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE + self.padding_sent());
+        buf.put_u16_le(self.probe_id);
+        buf.put_u16_le(self.flags);
+        buf.put_u32_le(self.index);
+        buf.put_u32_le(self.count);
+        buf.put_u32_le(self.padding);
+        // xorshift, seeded by the index so each probe's noise differs
+        let mut state = 0x9E37_79B9_7F4A_7C15u64 ^ self.index as u64;
+        for _ in 0..self.padding_sent() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            buf.put_u8(state as u8);
+        }
+        buf.freeze()
+    }
+
+    /// Parse the fixed fields; the padding is only checked for length
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        let probe = Self {
+            probe_id: buf.get_u16_le(),
+            flags: buf.get_u16_le(),
+            index: buf.get_u32_le(),
+            count: buf.get_u32_le(),
+            padding: buf.get_u32_le(),
+        };
+        if buf.len() != probe.padding_sent() {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE + probe.padding_sent(),
+                actual: data.len(),
+            });
+        }
+        Ok(probe)
+    }
+}
+
+/// BANDWIDTH_PROBE_ACK payload (24 bytes): what arrived of a burst, sent
+/// once its last probe is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthProbeAckPayload {
+    pub probe_id: u16,
+    pub reserved: u16,
+    pub packets_received: u32,
+    /// Size on the wire of the probes received, headers included
+    pub bytes_received: u64,
+    /// Microseconds from the first probe arriving to the last, on the
+    /// receiver's clock
+    pub elapsed_us: u64,
+}
+
+impl BandwidthProbeAckPayload {
+    pub const SIZE: usize = 24;
+
+    pub fn new(probe_id: u16, packets_received: u32, bytes_received: u64, elapsed_us: u64) -> Self {
+        Self {
+            probe_id,
+            reserved: 0,
+            packets_received,
+            bytes_received,
+            elapsed_us,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        buf.put_u16_le(self.probe_id);
+        buf.put_u16_le(self.reserved);
+        buf.put_u32_le(self.packets_received);
+        buf.put_u64_le(self.bytes_received);
+        buf.put_u64_le(self.elapsed_us);
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        Ok(Self {
+            probe_id: buf.get_u16_le(),
+            reserved: buf.get_u16_le(),
+            packets_received: buf.get_u32_le(),
+            bytes_received: buf.get_u64_le(),
+            elapsed_us: buf.get_u64_le(),
+        })
+    }
+}
+
+/// Reason carried in a STOP packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The user stopped the stream
+    UserRequested,
+    /// The sender hit an error it cannot recover from
+    Error,
+    /// The stream is being reconfigured and will restart shortly
+    Reconfigure,
+    /// The sender application is exiting
+    Shutdown,
+    /// A reason added after this build; the stream stops all the same
+    Unknown(u8),
+}
+
+impl StopReason {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x00 => StopReason::UserRequested,
+            0x01 => StopReason::Error,
+            0x02 => StopReason::Reconfigure,
+            0x03 => StopReason::Shutdown,
+            other => StopReason::Unknown(other),
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            StopReason::UserRequested => 0x00,
+            StopReason::Error => 0x01,
+            StopReason::Reconfigure => 0x02,
+            StopReason::Shutdown => 0x03,
+            StopReason::Unknown(value) => value,
+        }
+    }
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            StopReason::UserRequested => "user requested",
+            StopReason::Error => "error",
+            StopReason::Reconfigure => "reconfigure",
+            StopReason::Shutdown => "shutdown",
+            StopReason::Unknown(value) => return write!(f, "unknown (0x{:02X})", value),
+        };
+        f.write_str(s)
+    }
+}
+
+/// STOP payload (4 bytes, optional)
+///
+/// Older peers send STOP with an empty payload, which parses as a user request
+/// without a reconnect hint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopPayload {
+    pub reason: StopReason,
+    /// The sender expects to reconnect; the receiver should stay ready
+    pub reconnect_hint: bool,
+    pub reserved: u16,
+}
+
+impl StopPayload {
+    pub const SIZE: usize = 4;
+
+    pub fn new(reason: StopReason, reconnect_hint: bool) -> Self {
+        Self {
+            reason,
+            reconnect_hint,
+            reserved: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        buf.put_u8(self.reason.as_u8());
+        buf.put_u8(self.reconnect_hint as u8);
+        buf.put_u16_le(self.reserved);
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.is_empty() {
+            return Ok(Self::new(StopReason::UserRequested, false));
+        }
+
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        Ok(Self {
+            reason: StopReason::from_u8(buf.get_u8()),
+            reconnect_hint: buf.get_u8() != 0,
+            reserved: buf.get_u16_le(),
+        })
+    }
+}
+
+/// PING payload (8 bytes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingPayload {
+    pub timestamp_us: u64,
+}
+
+impl PingPayload {
+    pub const SIZE: usize = 8;
+
+    pub fn new(timestamp_us: u64) -> Self {
+        Self { timestamp_us }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        buf.put_u64_le(self.timestamp_us);
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        Ok(Self {
+            timestamp_us: buf.get_u64_le(),
+        })
+    }
+}
+
+/// PONG payload (16 bytes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PongPayload {
+    pub ping_timestamp_us: u64,
+    pub pong_timestamp_us: u64,
+}
+
+impl PongPayload {
+    pub const SIZE: usize = 16;
+
+    pub fn new(ping_timestamp_us: u64, pong_timestamp_us: u64) -> Self {
+        Self {
+            ping_timestamp_us,
+            pong_timestamp_us,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        buf.put_u64_le(self.ping_timestamp_us);
+        buf.put_u64_le(self.pong_timestamp_us);
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        Ok(Self {
+            ping_timestamp_us: buf.get_u64_le(),
+            pong_timestamp_us: buf.get_u64_le(),
+        })
+    }
+}
+
+/// PAUSE payload (4 bytes): the source stopping or starting to send frames
+/// while the session carries on, see `capabilities::PAUSE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PausePayload {
+    /// Whether frames stop, rather than start again
+    pub paused: bool,
+}
+
+impl PausePayload {
+    pub const SIZE: usize = 4;
+
+    pub fn new(paused: bool) -> Self {
+        Self { paused }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE);
+        buf.put_u8(self.paused as u8);
+        buf.put_bytes(0, 3); // reserved
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        Ok(Self {
+            paused: data[0] != 0,
+        })
+    }
+}
+
+/// Error codes carried in ERROR packets
+pub mod error_codes {
+    /// Unclassified failure
+    pub const INTERNAL: u16 = 0x0001;
+    /// The source's encoder failed
+    pub const ENCODER_FAILED: u16 = 0x0002;
+    /// The sink's decoder failed
+    pub const DECODER_FAILED: u16 = 0x0003;
+    /// A required OS permission (screen recording, USB access) was denied
+    pub const PERMISSION_DENIED: u16 = 0x0004;
+    /// The requested codec or pixel format is not supported
+    pub const UNSUPPORTED_CODEC: u16 = 0x0005;
+
+    /// Human-readable name for an error code
+    pub fn name(code: u16) -> &'static str {
+        match code {
+            INTERNAL => "INTERNAL",
+            ENCODER_FAILED => "ENCODER_FAILED",
+            DECODER_FAILED => "DECODER_FAILED",
+            PERMISSION_DENIED => "PERMISSION_DENIED",
+            UNSUPPORTED_CODEC => "UNSUPPORTED_CODEC",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+/// ERROR payload (4 bytes + message)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorPayload {
+    pub code: u16,
+    /// The sender is tearing down the session
+    pub fatal: bool,
+    /// Short UTF-8 description, at most `MAX_MESSAGE_LEN` bytes
+    pub message: String,
+}
+
+impl ErrorPayload {
+    /// Size of the fixed part preceding the message
+    pub const SIZE: usize = 4;
+    pub const MAX_MESSAGE_LEN: usize = 255;
+
+    /// Create an ERROR payload, truncating the message to `MAX_MESSAGE_LEN` bytes
+    pub fn new(code: u16, fatal: bool, message: &str) -> Self {
+        let mut end = message.len().min(Self::MAX_MESSAGE_LEN);
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        Self {
+            code,
+            fatal,
+            message: message[..end].to_string(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::SIZE + self.message.len());
+        buf.put_u16_le(self.code);
+        buf.put_u8(self.fatal as u8);
+        buf.put_u8(self.message.len() as u8);
+        buf.put_slice(self.message.as_bytes());
+        buf.freeze()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < Self::SIZE {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE,
+                actual: data.len(),
+            });
+        }
+
+        let mut buf = data;
+        let code = buf.get_u16_le();
+        let fatal = buf.get_u8() != 0;
+        let message_len = buf.get_u8() as usize;
+
+        if buf.len() < message_len {
+            return Err(ProtocolError::InvalidPayloadLength {
+                expected: Self::SIZE + message_len,
+                actual: data.len(),
+            });
+        }
+
+        Ok(Self {
+            code,
+            fatal,
+            message: String::from_utf8_lossy(&buf[..message_len]).into_owned(),
+        })
+    }
+
+    /// Name of the error code
+    pub fn code_name(&self) -> &'static str {
+        error_codes::name(self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let payload = HelloPayload::new(1, 3840, 2160, 60, 0x03);
+        let packet = Packet::new(PacketType::Hello, 0, 1, payload.to_bytes());
+        let bytes = packet.to_bytes();
+
+        let (parsed, consumed) = Packet::parse(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(parsed.header.packet_type, PacketType::Hello);
+        assert_eq!(parsed.header.sequence, 1);
+        assert_eq!(parsed.payload, payload.to_bytes());
+    }
+
+    #[test]
+    fn test_packet_roundtrip_without_crc() {
+        let payload = Bytes::from(vec![0xAB; 100]);
+        let packet = Packet::new(PacketType::Frame, 0, 3, payload.clone()).without_crc();
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE + payload.len());
+
+        let (parsed, consumed) = Packet::parse(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert!(!parsed.header.has_crc());
+        assert_eq!(parsed.sequence(), 3);
+        assert_eq!(parsed.payload, payload);
+    }
+
+    #[test]
+    fn test_packet_parts_join_to_bytes() {
+        let payload = Bytes::from((0..1000).map(|i| i as u8).collect::<Vec<_>>());
+        let pieces = [
+            payload.slice(..32),
+            payload.slice(32..40),
+            payload.slice(40..),
+        ];
+        for packet in [
+            Packet::new(PacketType::Frame, 0, 7, payload.clone()),
+            Packet::new(PacketType::Frame, 0, 7, payload.clone()).without_crc(),
+            Packet::new(PacketType::Ping, 0, 8, payload.clone()).with_stream_id(3),
+        ] {
+            let parts = packet.to_parts();
+            assert_eq!(parts.len(), if packet.header.has_crc() { 3 } else { 2 });
+            assert_eq!(parts.concat(), packet.to_bytes());
+
+            // The payload in pieces, none of them copied
+            let parts = Packet::parts_with_payload(packet.header.clone(), &pieces);
+            assert_eq!(parts.concat(), packet.to_bytes());
+            assert_eq!(parts[2].as_ptr(), pieces[1].as_ptr());
+        }
+    }
+
+    #[test]
+    fn test_stream_id_roundtrip() {
+        let payload = Bytes::from(vec![0xAB; 100]);
+        let packet = Packet::new_compressed(PacketType::Frame, 0, 3, payload.clone())
+            .without_crc()
+            .with_stream_id(15);
+        assert_eq!(packet.stream_id(), 15);
+
+        let (parsed, _) = Packet::parse(&packet.to_bytes()).unwrap();
+        assert_eq!(parsed.stream_id(), 15);
+        assert!(!parsed.header.has_crc());
+        assert_eq!(parsed.payload, payload);
+
+        // Untagged packets are stream 0, and retagging replaces the id
+        let packet = Packet::new(PacketType::FrameAck, 0, 0, Bytes::new());
+        assert_eq!(packet.stream_id(), 0);
+        assert_eq!(packet.with_stream_id(9).with_stream_id(2).stream_id(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "stream id 16 out of range")]
+    fn test_stream_id_range() {
+        let _ = Packet::new(PacketType::Frame, 0, 0, Bytes::new()).with_stream_id(16);
+    }
+
+    #[test]
+    fn test_handshake_packets_keep_crc() {
+        let hello = HelloPayload::new(1, 1920, 1080, 60, capabilities::NO_CRC);
+        let packet = Packet::new(PacketType::Hello, 0, 0, hello.to_bytes()).without_crc();
+        assert!(packet.header.has_crc());
+        assert_eq!(
+            packet.to_bytes().len(),
+            HEADER_SIZE + HelloPayload::SIZE + CRC_SIZE
+        );
+
+        // A handshake header claiming no CRC is rejected
+        let header = PacketHeader::new(PacketType::Start, packet_flags::NO_CRC, 0, 0);
+        assert!(matches!(
+            PacketHeader::parse(&header.to_bytes()),
+            Err(ProtocolError::CrcRequired(0x03))
+        ));
+    }
+
+    #[test]
+    fn test_decoder_mixed_crc_stream() {
+        let hello = HelloPayload::new(1, 1920, 1080, 60, capabilities::NO_CRC);
+        let packets = [
+            Packet::new(PacketType::Hello, 0, 0, hello.to_bytes()),
+            Packet::new(PacketType::Frame, 0, 1, vec![0; 50].into()).without_crc(),
+            Packet::new(
+                PacketType::FrameAck,
+                0,
+                2,
+                FrameAckPayload::new(0, 0, 1).to_bytes(),
+            ),
+            Packet::new(PacketType::Frame, 0, 3, vec![1; 7].into()).without_crc(),
+        ];
+        let mut stream = BytesMut::new();
+        for packet in &packets {
+            stream.put(packet.to_bytes());
+        }
+
+        // Fed a byte at a time, so every split point is exercised
+        let mut decoder = PacketDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in stream.iter() {
+            decoder.push(&[*byte]);
+            while let Some(packet) = decoder.next_packet().unwrap() {
+                decoded.push(packet);
+            }
+        }
+
+        assert_eq!(decoded.len(), packets.len());
+        for (decoded, packet) in decoded.iter().zip(&packets) {
+            assert_eq!(decoded.sequence(), packet.sequence());
+            assert_eq!(decoded.header.has_crc(), packet.header.has_crc());
+            assert_eq!(decoded.payload, packet.payload);
+        }
+    }
+
+    /// Bytes from a xorshift generator, which LZ4 can't shrink
+    fn noise(len: usize) -> Bytes {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    #[test]
+    fn test_compressed_roundtrip() {
+        let payload = Bytes::from(vec![0x42; 16 * 1024]);
+        let packet = Packet::new_compressed(PacketType::Frame, 0, 5, payload.clone());
+        assert!(packet.is_compressed());
+        let bytes = packet.to_bytes();
+        assert!(bytes.len() < payload.len() / 10);
+
+        let (parsed, consumed) = Packet::parse(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert!(!parsed.is_compressed());
+        assert_eq!(parsed.header.payload_length as usize, payload.len());
+        assert_eq!(parsed.payload, payload);
+
+        // Without a CRC too
+        let bytes = Packet::new_compressed(PacketType::Frame, 0, 6, payload.clone())
+            .without_crc()
+            .to_bytes();
+        assert_eq!(Packet::parse(&bytes).unwrap().0.payload, payload);
+    }
+
+    #[test]
+    fn test_incompressible_payload_sent_as_is() {
+        let payload = noise(4096);
+        let packet = Packet::new_compressed(PacketType::Frame, 0, 1, payload.clone());
+        assert!(!packet.is_compressed());
+        assert_eq!(packet.payload, payload);
+        assert_eq!(
+            packet.to_bytes(),
+            Packet::new(PacketType::Frame, 0, 1, payload).to_bytes()
+        );
+
+        // Too short to be worth it, or part of the handshake
+        let short = Packet::new_compressed(PacketType::Ping, 0, 0, vec![0; 8].into());
+        assert!(!short.is_compressed());
+        let hello = Packet::new_compressed(PacketType::Hello, 0, 0, vec![0; 1024].into());
+        assert!(!hello.is_compressed());
+    }
+
+    #[test]
+    fn test_decompressed_size_is_capped() {
+        let mut payload = BytesMut::new();
+        payload.put_u32_le(MAX_DECOMPRESSED_SIZE as u32 + 1);
+        payload.put(&lz4_flex::block::compress(&[0; 64])[..]);
+        let packet = Packet::new(
+            PacketType::Frame,
+            packet_flags::COMPRESSED,
+            0,
+            payload.freeze(),
+        );
+        assert!(matches!(
+            Packet::parse(&packet.to_bytes()),
+            Err(ProtocolError::DecompressionFailed(_))
+        ));
+
+        // A size that doesn't match the data is rejected as well
+        let mut payload = BytesMut::new();
+        payload.put_u32_le(1000);
+        payload.put(&lz4_flex::block::compress(&[0; 64])[..]);
+        let packet = Packet::new(
+            PacketType::Frame,
+            packet_flags::COMPRESSED,
+            0,
+            payload.freeze(),
+        );
+        assert!(Packet::parse(&packet.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_decoder_decompresses() {
+        let flat = Bytes::from(vec![7; 8192]);
+        let packets = [
+            Packet::new_compressed(PacketType::Frame, 0, 1, flat.clone()),
+            Packet::new_compressed(PacketType::Frame, 0, 2, noise(512)),
+            Packet::new_compressed(PacketType::Frame, 0, 3, flat.clone()).without_crc(),
+        ];
+        let mut decoder = PacketDecoder::new();
+        for packet in &packets {
+            decoder.push(&packet.to_bytes());
+        }
+
+        assert_eq!(decoder.next_packet().unwrap().unwrap().payload, flat);
+        assert_eq!(decoder.next_packet().unwrap().unwrap().payload, noise(512));
+        assert_eq!(decoder.next_packet().unwrap().unwrap().payload, flat);
+        assert!(decoder.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_is_control() {
+        assert!(!PacketType::Frame.is_control());
+        for packet_type in [
+            PacketType::Hello,
+            PacketType::FrameAck,
+            PacketType::CreditUpdate,
+            PacketType::Stop,
+            PacketType::Pause,
+            PacketType::Ping,
+            PacketType::Pong,
+            PacketType::Error,
+        ] {
+            assert!(packet_type.is_control(), "{:?}", packet_type);
+        }
+    }
+
+    #[test]
+    fn test_decoder_splits_and_joins_reads() {
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(7).to_bytes());
+        let pong = Packet::new(PacketType::Pong, 0, 2, PongPayload::new(7, 8).to_bytes());
+        let mut stream = BytesMut::new();
+        stream.put(ping.to_bytes());
+        stream.put(pong.to_bytes());
+        stream.put(ping.to_bytes());
+
+        // Both whole packets in one read, then the last in two pieces
+        let split = 2 * ping.to_bytes().len() + pong.to_bytes().len() - 5;
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&stream[..split]);
+        assert_eq!(decoder.next_packet().unwrap().unwrap().sequence(), 1);
+        assert_eq!(decoder.next_packet().unwrap().unwrap().sequence(), 2);
+        assert!(decoder.next_packet().unwrap().is_none());
+        assert!(decoder.buffered() > 0);
+
+        decoder.push(&stream[split..]);
+        let last = decoder.next_packet().unwrap().unwrap();
+        assert_eq!(last.packet_type(), PacketType::Ping);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_recovers_after_bad_crc() {
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(7).to_bytes());
+        let mut corrupt = BytesMut::from(&ping.to_bytes()[..]);
+        corrupt[HEADER_SIZE] ^= 0xFF;
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&corrupt);
+        decoder.push(&ping.to_bytes());
+        assert!(matches!(
+            decoder.next_packet(),
+            Err(ProtocolError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(decoder.next_packet().unwrap().unwrap().sequence(), 1);
+    }
+
+    #[test]
+    fn test_decoder_drops_stream_after_bad_header() {
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(7).to_bytes());
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&[0u8; HEADER_SIZE]);
+        decoder.push(&ping.to_bytes());
+        assert!(matches!(
+            decoder.next_packet(),
+            Err(ProtocolError::InvalidMagic(0))
+        ));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
     fn test_invalid_magic() {
         let mut bytes = BytesMut::new();
         bytes.put_u32_le(0x12345678); // Wrong magic
@@ -671,7 +2169,10 @@ mod tests {
         bytes.put_u32_le(0); // CRC (will be wrong anyway)
 
         let result = Packet::parse(&bytes);
-        assert!(matches!(result, Err(ProtocolError::InvalidMagic(0x12345678))));
+        assert!(matches!(
+            result,
+            Err(ProtocolError::InvalidMagic(0x12345678))
+        ));
     }
 
     #[test]
@@ -696,6 +2197,9 @@ mod tests {
         assert_eq!(payload.max_fps(), 60);
         assert!(payload.supports_hidpi());
         assert!(payload.supports_audio());
+        assert!(!payload.supports_no_crc());
+        assert!(!payload.supports_lz4());
+        assert!(!payload.supports_fec());
 
         let bytes = payload.to_bytes();
         let parsed = HelloPayload::parse(&bytes).unwrap();
@@ -703,6 +2207,119 @@ mod tests {
         assert_eq!(parsed.max_width, 3840);
         assert_eq!(parsed.max_height, 2160);
         assert_eq!(parsed.max_fps(), 60);
+        assert_eq!(parsed.max_scale(), 1);
+        assert_eq!(parsed.max_streams(), 1);
+
+        let parsed = HelloPayload::parse(&payload.clone().with_max_scale(2).to_bytes()).unwrap();
+        assert_eq!(parsed.max_scale(), 2);
+
+        let parsed = HelloPayload::parse(&payload.with_max_streams(4).to_bytes()).unwrap();
+        assert_eq!(parsed.max_streams(), 4);
+        // More than a link can carry is taken in range
+        let mut hello = HelloPayload::new(1, 1920, 1080, 60, 0);
+        hello.max_streams = 100;
+        assert_eq!(hello.max_streams() as usize, MAX_STREAMS);
+    }
+
+    #[test]
+    fn test_hello_identity_roundtrip() {
+        let identity = PeerIdentity::new("studio.local", "macos-aarch64", "0.0.1");
+        let hello = HelloPayload::new(1, 3840, 2160, 60, 0).with_identity(identity.clone());
+        let bytes = hello.to_bytes();
+        let parsed = HelloPayload::parse(&bytes).unwrap();
+        assert_eq!(parsed, hello);
+        assert_eq!(
+            parsed.identity.to_string(),
+            "studio.local (macos-aarch64, version 0.0.1)"
+        );
+
+        // Peers that predate it send only the fixed fields, and empty fields
+        // aren't sent at all
+        let parsed = HelloPayload::parse(&bytes[..HelloPayload::SIZE]).unwrap();
+        assert!(parsed.identity.is_empty());
+        assert_eq!(parsed.identity.to_string(), "unknown host");
+        let partial = PeerIdentity::new("", "windows-x86_64", "");
+        let bytes = HelloPayload::new(1, 1920, 1080, 60, 0)
+            .with_identity(partial.clone())
+            .to_bytes();
+        assert_eq!(bytes.len(), HelloPayload::SIZE + 2 + "windows-x86_64".len());
+        assert_eq!(HelloPayload::parse(&bytes).unwrap().identity, partial);
+    }
+
+    #[test]
+    fn test_hello_identity_truncated() {
+        // Cut to 64 bytes without splitting the two-byte characters
+        let long = "é".repeat(40);
+        let identity = PeerIdentity::new(long.clone(), "x".repeat(100), "1.0");
+        assert_eq!(identity.host_name, "é".repeat(32));
+        assert_eq!(identity.platform.len(), PeerIdentity::MAX_FIELD_LEN);
+
+        // Set directly, it's still cut short on the wire
+        let mut hello = HelloPayload::new(1, 1920, 1080, 60, 0);
+        hello.identity.host_name = long;
+        let parsed = HelloPayload::parse(&hello.to_bytes()).unwrap();
+        assert_eq!(parsed.identity.host_name, "é".repeat(32));
+
+        // An entry cut off by the end of the payload is dropped, and the
+        // ones before it kept
+        let hello = HelloPayload::new(1, 1920, 1080, 60, 0).with_identity(PeerIdentity::new(
+            "host",
+            "linux-x86_64",
+            "1.0",
+        ));
+        let bytes = hello.to_bytes();
+        let parsed = HelloPayload::parse(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            parsed.identity,
+            PeerIdentity::new("host", "linux-x86_64", "")
+        );
+    }
+
+    #[test]
+    fn test_hello_identity_lenient() {
+        let mut bytes = BytesMut::from(&HelloPayload::new(1, 1920, 1080, 60, 0).to_bytes()[..]);
+        // Invalid UTF-8 is replaced, an unknown entry skipped, and an
+        // over-long value from a peer cut short
+        bytes.put_slice(&[1, 4, b'h', 0xff, b's', b't']);
+        bytes.put_slice(&[9, 2, 0, 0]);
+        bytes.put_slice(&[3, 100]);
+        bytes.put_slice(&[b'7'; 100]);
+        let identity = HelloPayload::parse(&bytes).unwrap().identity;
+        assert_eq!(identity.host_name, "h\u{fffd}st");
+        assert_eq!(identity.platform, "");
+        assert_eq!(identity.app_version, "7".repeat(64));
+    }
+
+    #[test]
+    fn test_resume_token_roundtrip() {
+        let token = ResumeToken::generate();
+        assert_ne!(token, ResumeToken::generate());
+        assert_eq!(ResumeToken([0xab; 16]).to_string(), "ab".repeat(16));
+
+        // In HELLO it follows the identity, which is read as before
+        let identity = PeerIdentity::new("studio.local", "macos-aarch64", "0.0.1");
+        let hello = HelloPayload::new(1, 1920, 1080, 60, capabilities::RESUME)
+            .with_identity(identity.clone())
+            .with_resume_token(Some(token));
+        let parsed = HelloPayload::parse(&hello.to_bytes()).unwrap();
+        assert_eq!(parsed, hello);
+        assert!(parsed.supports_resume());
+        assert_eq!(parsed.identity, identity);
+
+        let ack = StartAckPayload::ok(8).with_resume_token(Some(token));
+        let bytes = ack.to_bytes();
+        assert_eq!(bytes.len(), StartAckPayload::SIZE + 2 + ResumeToken::SIZE);
+        assert_eq!(StartAckPayload::parse(&bytes).unwrap(), ack);
+
+        // Peers that predate it send neither, and a cut off token is dropped
+        let parsed = StartAckPayload::parse(&bytes[..StartAckPayload::SIZE]).unwrap();
+        assert_eq!(parsed, StartAckPayload::ok(8));
+        let parsed = StartAckPayload::parse(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(parsed.resume_token, None);
+        let bytes = hello.to_bytes();
+        let parsed = HelloPayload::parse(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(parsed.resume_token, None);
+        assert_eq!(parsed.identity, identity);
     }
 
     #[test]
@@ -716,6 +2333,72 @@ mod tests {
         assert_eq!(parsed.height, 1080);
         assert_eq!(parsed.fps(), 60);
         assert_eq!(parsed.bitrate_bps, 20_000_000);
+        assert_eq!(parsed.display_size(), Resolution::new(1920, 1080));
+        assert_eq!(parsed.scale(), 1);
+        assert_eq!(parsed.point_size(), Resolution::new(1920, 1080));
+    }
+
+    #[test]
+    fn test_start_payload_scale() {
+        let payload = StartPayload::new(2880, 1800, 60, 20_000_000).with_scale(2);
+        let parsed = StartPayload::parse(&payload.to_bytes()).unwrap();
+        assert_eq!(parsed.coded_size(), Resolution::new(2880, 1800));
+        assert_eq!(parsed.scale(), 2);
+        assert_eq!(parsed.point_size(), Resolution::new(1440, 900));
+
+        // Peers that predate the scale leave it 0
+        let mut payload = StartPayload::new(1920, 1080, 60, 20_000_000);
+        payload.scale = 0;
+        let parsed = StartPayload::parse(&payload.to_bytes()).unwrap();
+        assert_eq!(parsed.scale(), 1);
+        assert_eq!(parsed.point_size(), parsed.display_size());
+    }
+
+    #[test]
+    fn test_start_payload_sizes() {
+        for (width, height, coded) in [
+            (1366, 768, (1366, 768)),
+            (1367, 769, (1368, 770)),
+            (1, 1, (2, 2)),
+        ] {
+            let bytes = StartPayload::new(width, height, 60, 1_000_000).to_bytes();
+            assert_eq!(bytes.len(), StartPayload::EXTENDED_SIZE);
+            let parsed = StartPayload::parse(&bytes).unwrap();
+            assert_eq!((parsed.width, parsed.height), coded);
+            assert_eq!(parsed.display_size(), Resolution::new(width, height));
+        }
+    }
+
+    #[test]
+    fn test_start_payload_without_display_size() {
+        let bytes = StartPayload::new(1366, 768, 60, 1_000_000).to_bytes();
+        let parsed = StartPayload::parse(&bytes[..StartPayload::SIZE]).unwrap();
+        assert_eq!(parsed.display_size(), parsed.coded_size());
+    }
+
+    #[test]
+    fn test_start_payload_rejects_bad_sizes() {
+        let mut start = StartPayload::new(1920, 1080, 60, 1_000_000);
+        start.width = 0;
+        assert!(matches!(
+            StartPayload::parse(&start.to_bytes()),
+            Err(ProtocolError::InvalidResolution { .. })
+        ));
+
+        // Odd coded sizes and display sizes that don't pad to the coded one
+        for (coded, display) in [
+            ((1367, 769), (1367, 769)),
+            ((1920, 1080), (1280, 720)),
+            ((2, 2), (0, 0)),
+        ] {
+            let mut start = StartPayload::new(1920, 1080, 60, 1_000_000);
+            (start.width, start.height) = coded;
+            (start.display_width, start.display_height) = display;
+            assert!(matches!(
+                StartPayload::parse(&start.to_bytes()),
+                Err(ProtocolError::InvalidResolution { .. })
+            ));
+        }
     }
 
     #[test]
@@ -728,6 +2411,21 @@ mod tests {
         assert_eq!(parsed.segment_count, 2);
     }
 
+    #[test]
+    fn test_frame_header_parity_segment() {
+        let header = FrameHeader::new(1, 0, 0, 65536, FrameHeader::PARITY_FLAG | 1, 2);
+        let parsed = FrameHeader::parse(&header.to_bytes()).unwrap();
+        assert!(parsed.is_parity());
+        assert_eq!(parsed.parity_group(), Some(1));
+
+        let data = FrameHeader::new(1, 0, 0, 65536, 1, 2);
+        assert_eq!(data.parity_group(), None);
+
+        // No more parity groups than data segments
+        let header = FrameHeader::new(1, 0, 0, 65536, FrameHeader::PARITY_FLAG | 2, 2);
+        assert!(FrameHeader::parse(&header.to_bytes()).is_err());
+    }
+
     #[test]
     fn test_frame_ack_payload() {
         let payload = FrameAckPayload::new(42, 500, 2);
@@ -737,4 +2435,150 @@ mod tests {
         assert_eq!(parsed.decode_time_us, 500);
         assert_eq!(parsed.credits_returned, 2);
     }
+
+    #[test]
+    fn test_frame_ack_batch_payload() {
+        let entries: Vec<_> = (10..14)
+            .map(|frame_number| FrameAckEntry {
+                frame_number,
+                decode_time_us: frame_number as u32 * 100,
+            })
+            .collect();
+        let batch = FrameAckBatchPayload::new(entries, 4);
+        let bytes = batch.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            FrameAckPayload::SIZE + 3 * FrameAckBatchPayload::ENTRY_SIZE
+        );
+        assert_eq!(FrameAckBatchPayload::parse(&bytes).unwrap(), batch);
+
+        // A reader that only knows single acks still sees the first frame
+        // and the total credits
+        let head = FrameAckPayload::parse(&bytes).unwrap();
+        assert_eq!((head.frame_number, head.credits_returned), (10, 4));
+
+        // Truncated entries are rejected
+        assert!(FrameAckBatchPayload::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_frame_ack_batch_single_entry() {
+        let single = FrameAckPayload::new(42, 500, 2).to_bytes();
+        let batch = FrameAckBatchPayload::parse(&single).unwrap();
+        assert_eq!(batch, FrameAckPayload::new(42, 500, 2).into());
+        assert_eq!(batch.to_bytes(), single);
+    }
+
+    #[test]
+    fn test_credit_update_payload() {
+        for delta in [3, -2] {
+            let bytes = CreditUpdatePayload::new(delta).to_bytes();
+            let packet = Packet::new(PacketType::CreditUpdate, 0, 1, bytes);
+            let (parsed, _) = Packet::parse(&packet.to_bytes()).unwrap();
+            assert_eq!(parsed.packet_type(), PacketType::CreditUpdate);
+            assert_eq!(
+                CreditUpdatePayload::parse(&parsed.payload).unwrap().delta,
+                delta
+            );
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_probe_payload() {
+        let probe = BandwidthProbePayload::new(7, 3, 4, 4096);
+        let bytes = probe.to_bytes();
+        assert_eq!(bytes.len(), BandwidthProbePayload::SIZE + 4096);
+        assert_eq!(BandwidthProbePayload::parse(&bytes).unwrap(), probe);
+        assert!(probe.is_last() && !probe.is_reverse());
+
+        // The padding doesn't compress, so the link carries all of it
+        let packet = Packet::new_compressed(PacketType::BandwidthProbe, 0, 1, bytes.clone());
+        assert!(!packet.is_compressed());
+
+        // Padding missing or extra is caught; a reverse request has none
+        assert!(BandwidthProbePayload::parse(&bytes[..bytes.len() - 1]).is_err());
+        let reverse = BandwidthProbePayload::reverse(7, 32, MAX_SEGMENT_SIZE as u32);
+        let bytes = reverse.to_bytes();
+        assert_eq!(bytes.len(), BandwidthProbePayload::SIZE);
+        let parsed = BandwidthProbePayload::parse(&bytes).unwrap();
+        assert!(parsed.is_reverse());
+        assert_eq!(
+            (parsed.count, parsed.padding),
+            (32, MAX_SEGMENT_SIZE as u32)
+        );
+
+        let ack = BandwidthProbeAckPayload::new(7, 31, 2_000_000, 50_000);
+        let bytes = ack.to_bytes();
+        assert_eq!(bytes.len(), BandwidthProbeAckPayload::SIZE);
+        assert_eq!(BandwidthProbeAckPayload::parse(&bytes).unwrap(), ack);
+    }
+
+    #[test]
+    fn test_error_payload() {
+        let payload = ErrorPayload::new(error_codes::DECODER_FAILED, true, "decoder died");
+        let packet = Packet::new(PacketType::Error, 0, 7, payload.to_bytes());
+        let (parsed_packet, _) = Packet::parse(&packet.to_bytes()).unwrap();
+        assert_eq!(parsed_packet.packet_type(), PacketType::Error);
+
+        let parsed = ErrorPayload::parse(&parsed_packet.payload).unwrap();
+        assert_eq!(parsed.code, error_codes::DECODER_FAILED);
+        assert!(parsed.fatal);
+        assert_eq!(parsed.message, "decoder died");
+        assert_eq!(parsed.code_name(), "DECODER_FAILED");
+    }
+
+    #[test]
+    fn test_error_payload_truncates_message() {
+        let long = "é".repeat(200); // 400 bytes
+        let payload = ErrorPayload::new(error_codes::INTERNAL, false, &long);
+        assert!(payload.message.len() <= ErrorPayload::MAX_MESSAGE_LEN);
+        assert!(payload.message.chars().all(|c| c == 'é'));
+
+        let parsed = ErrorPayload::parse(&payload.to_bytes()).unwrap();
+        assert_eq!(parsed.message, payload.message);
+        assert!(!parsed.fatal);
+    }
+
+    #[test]
+    fn test_error_payload_short_message() {
+        let mut bytes = ErrorPayload::new(error_codes::INTERNAL, false, "oops")
+            .to_bytes()
+            .to_vec();
+        bytes.truncate(6);
+        assert!(matches!(
+            ErrorPayload::parse(&bytes),
+            Err(ProtocolError::InvalidPayloadLength { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stop_payload() {
+        let payload = StopPayload::new(StopReason::Reconfigure, true);
+        let packet = Packet::new(PacketType::Stop, 0, 3, payload.to_bytes());
+        let (parsed_packet, _) = Packet::parse(&packet.to_bytes()).unwrap();
+
+        let parsed = StopPayload::parse(&parsed_packet.payload).unwrap();
+        assert_eq!(parsed.reason, StopReason::Reconfigure);
+        assert!(parsed.reconnect_hint);
+    }
+
+    #[test]
+    fn test_stop_payload_legacy_empty() {
+        let packet = Packet::new(PacketType::Stop, 0, 3, Bytes::new());
+        let (parsed_packet, _) = Packet::parse(&packet.to_bytes()).unwrap();
+
+        let parsed = StopPayload::parse(&parsed_packet.payload).unwrap();
+        assert_eq!(parsed.reason, StopReason::UserRequested);
+        assert!(!parsed.reconnect_hint);
+    }
+
+    #[test]
+    fn test_stop_payload_unknown_reason() {
+        // A newer peer's reason still stops the stream, and is kept as sent
+        let parsed = StopPayload::parse(&[0x7F, 1, 0, 0]).unwrap();
+        assert_eq!(parsed.reason, StopReason::Unknown(0x7F));
+        assert!(parsed.reconnect_hint);
+        assert_eq!(parsed.reason.to_string(), "unknown (0x7F)");
+        assert_eq!(&parsed.to_bytes()[..], [0x7F, 1, 0, 0]);
+    }
 }
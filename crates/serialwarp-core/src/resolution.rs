@@ -0,0 +1,132 @@
+//! Frame dimensions shared by capture, encode, the protocol and decode
+
+/// Largest width or height accepted anywhere in the pipeline, the most
+/// H.264 level 6.2 allows
+pub const MAX_DIMENSION: u32 = 8192;
+
+/// A frame size in pixels.
+///
+/// On a HiDPI display a point covers `scale` x `scale` pixels. Capture,
+/// encode and the protocol's sizes are always pixels; only window sizes are
+/// points, converted with `to_points` and `to_pixels`.
+///
+/// 4:2:0 chroma covers 2x2 blocks of pixels, so streams are coded at an
+/// even size. An odd visible size is padded out to `coded()` by repeating
+/// its last column and row, and the sink crops the padding back off using
+/// the visible size sent in START.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// Whether both dimensions are between 1 and `MAX_DIMENSION`
+    pub fn is_valid(self) -> bool {
+        (1..=MAX_DIMENSION).contains(&self.width) && (1..=MAX_DIMENSION).contains(&self.height)
+    }
+
+    /// The size rounded up to even, as the stream is coded
+    pub fn coded(self) -> Self {
+        Self {
+            width: self.width.saturating_add(self.width % 2),
+            height: self.height.saturating_add(self.height % 2),
+        }
+    }
+
+    /// Whether coding this size needs a padding column or row
+    pub fn is_padded(self) -> bool {
+        self.coded() != self
+    }
+
+    /// The size in points at `scale` pixels per point, rounded up so the
+    /// points cover every pixel. A scale of 0 is taken as 1.
+    pub fn to_points(self, scale: u16) -> Self {
+        let scale = u32::from(scale.max(1));
+        let round_up = |pixels: u32| pixels / scale + u32::from(pixels % scale != 0);
+        Self {
+            width: round_up(self.width),
+            height: round_up(self.height),
+        }
+    }
+
+    /// The size in pixels of this many points at `scale` pixels per point.
+    /// A scale of 0 is taken as 1.
+    pub fn to_pixels(self, scale: u16) -> Self {
+        let scale = u32::from(scale.max(1));
+        Self {
+            width: self.width.saturating_mul(scale),
+            height: self.height.saturating_mul(scale),
+        }
+    }
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_even_size_unchanged() {
+        let size = Resolution::new(1366, 768);
+        assert!(size.is_valid());
+        assert_eq!(size.coded(), size);
+        assert!(!size.is_padded());
+    }
+
+    #[test]
+    fn test_odd_size_padded() {
+        let size = Resolution::new(1367, 769);
+        assert!(size.is_valid());
+        assert_eq!(size.coded(), Resolution::new(1368, 770));
+        assert!(size.is_padded());
+
+        let size = Resolution::new(1, 1);
+        assert!(size.is_valid());
+        assert_eq!(size.coded(), Resolution::new(2, 2));
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        assert!(!Resolution::new(0, 1080).is_valid());
+        assert!(!Resolution::new(1920, 0).is_valid());
+        assert!(!Resolution::new(MAX_DIMENSION + 1, 1080).is_valid());
+        assert!(!Resolution::new(u32::MAX, u32::MAX).is_valid());
+        assert!(Resolution::new(MAX_DIMENSION, MAX_DIMENSION).is_valid());
+    }
+
+    #[test]
+    fn test_points_and_pixels() {
+        let pixels = Resolution::new(2880, 1800);
+        assert_eq!(pixels.to_points(2), Resolution::new(1440, 900));
+        assert_eq!(pixels.to_points(2).to_pixels(2), pixels);
+        assert_eq!(pixels.to_points(1), pixels);
+        assert_eq!(pixels.to_points(0), pixels);
+        assert_eq!(pixels.to_pixels(0), pixels);
+        assert_eq!(
+            Resolution::new(1440, 900).to_pixels(3),
+            Resolution::new(4320, 2700)
+        );
+    }
+
+    #[test]
+    fn test_odd_pixels_round_up_to_points() {
+        // The last point is only partly covered, but still has to be shown
+        let pixels = Resolution::new(1367, 769);
+        assert_eq!(pixels.to_points(2), Resolution::new(684, 385));
+        assert_eq!(pixels.to_points(2).to_pixels(2), Resolution::new(1368, 770));
+        assert_eq!(
+            Resolution::new(u32::MAX, 1).to_pixels(2),
+            Resolution::new(u32::MAX, 2)
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! File naming for outputs split across several files
+
+use std::path::{Path, PathBuf};
+
+/// Path of the `index`th file of a split output: `out.mp4`, `out-1.mp4`, `out-2.mp4`, ...
+pub fn rotated_path(base: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return base.to_path_buf();
+    }
+
+    let stem = base
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match base.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, index),
+    };
+
+    base.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotated_path() {
+        let base = Path::new("/tmp/recordings/out.mp4");
+        assert_eq!(
+            rotated_path(base, 0),
+            PathBuf::from("/tmp/recordings/out.mp4")
+        );
+        assert_eq!(
+            rotated_path(base, 1),
+            PathBuf::from("/tmp/recordings/out-1.mp4")
+        );
+        assert_eq!(
+            rotated_path(base, 12),
+            PathBuf::from("/tmp/recordings/out-12.mp4")
+        );
+    }
+
+    #[test]
+    fn test_rotated_path_without_extension() {
+        assert_eq!(
+            rotated_path(Path::new("capture"), 2),
+            PathBuf::from("capture-2")
+        );
+    }
+}
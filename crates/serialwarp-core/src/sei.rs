@@ -0,0 +1,315 @@
+//! Capture timestamps carried in the H.264 bitstream.
+//!
+//! The source adds an unregistered user data SEI message with the capture
+//! time and frame number to each access unit, and the sink reads it back
+//! after decoding. Latency can then be measured from the stream alone, even
+//! after it has been recorded or re-muxed.
+
+/// Identifies serialwarp's latency message among other unregistered user
+/// data
+pub const LATENCY_SEI_UUID: [u8; 16] = [
+    0x5e, 0x71, 0xa1, 0x3c, 0x8d, 0x42, 0x4b, 0x0f, 0x9a, 0x27, 0xd4, 0x61, 0x0b, 0xe8, 0x35, 0xc2,
+];
+
+const NAL_TYPE_SEI: u8 = 6;
+/// `user_data_unregistered` SEI payload type
+const PAYLOAD_TYPE_UNREGISTERED: u32 = 5;
+/// UUID, capture timestamp, frame number
+const PAYLOAD_SIZE: usize = 16 + 8 + 8;
+/// `rbsp_stop_one_bit` and alignment
+const RBSP_TRAILING: u8 = 0x80;
+
+/// Capture time and frame number of an access unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySei {
+    /// When the frame was captured, in microseconds since the Unix epoch on
+    /// the source's clock
+    pub capture_ts_us: u64,
+    pub frame_number: u64,
+}
+
+impl LatencySei {
+    pub fn new(capture_ts_us: u64, frame_number: u64) -> Self {
+        Self {
+            capture_ts_us,
+            frame_number,
+        }
+    }
+
+    /// The SEI NAL unit, with a 4-byte start code
+    pub fn to_nal(&self) -> Vec<u8> {
+        let mut rbsp = Vec::with_capacity(2 + PAYLOAD_SIZE + 1);
+        rbsp.push(PAYLOAD_TYPE_UNREGISTERED as u8);
+        rbsp.push(PAYLOAD_SIZE as u8);
+        rbsp.extend_from_slice(&LATENCY_SEI_UUID);
+        rbsp.extend_from_slice(&self.capture_ts_us.to_be_bytes());
+        rbsp.extend_from_slice(&self.frame_number.to_be_bytes());
+        rbsp.push(RBSP_TRAILING);
+
+        let mut nal = vec![0, 0, 0, 1, NAL_TYPE_SEI];
+        nal.extend_from_slice(&add_emulation_prevention(&rbsp));
+        nal
+    }
+
+    /// Read the latency message from a NAL unit (without its start code).
+    /// `None` unless it's an SEI NAL unit holding one.
+    pub fn parse_nal(nal: &[u8]) -> Option<Self> {
+        let (&header, payload) = nal.split_first()?;
+        if header & 0x1F != NAL_TYPE_SEI {
+            return None;
+        }
+
+        let rbsp = remove_emulation_prevention(payload);
+        let mut rest = rbsp.as_slice();
+        while more_rbsp_data(rest) {
+            let payload_type = read_sei_value(&mut rest)?;
+            let size = read_sei_value(&mut rest)? as usize;
+            if rest.len() < size {
+                return None;
+            }
+            let (message, tail) = rest.split_at(size);
+            rest = tail;
+
+            if payload_type == PAYLOAD_TYPE_UNREGISTERED
+                && size == PAYLOAD_SIZE
+                && message[..16] == LATENCY_SEI_UUID
+            {
+                let capture_ts_us = u64::from_be_bytes(message[16..24].try_into().unwrap());
+                let frame_number = u64::from_be_bytes(message[24..32].try_into().unwrap());
+                return Some(Self::new(capture_ts_us, frame_number));
+            }
+        }
+        None
+    }
+
+    /// Find the latency message in an Annex B access unit
+    pub fn find(access_unit: &[u8]) -> Option<Self> {
+        nal_units(access_unit)
+            .into_iter()
+            .find_map(|(_, nal)| Self::parse_nal(nal))
+    }
+
+    /// The access unit with this message added before its first slice, as
+    /// SEI must come, or at the end if it has none
+    pub fn insert_into(&self, access_unit: &[u8]) -> Vec<u8> {
+        let sei = self.to_nal();
+        let at = nal_units(access_unit)
+            .into_iter()
+            .find(|(_, nal)| is_slice(nal))
+            .map_or(access_unit.len(), |(start, _)| start);
+
+        let mut out = Vec::with_capacity(access_unit.len() + sei.len());
+        out.extend_from_slice(&access_unit[..at]);
+        out.extend_from_slice(&sei);
+        out.extend_from_slice(&access_unit[at..]);
+        out
+    }
+}
+
+/// Escape RBSP bytes for a NAL unit: a 0x03 goes after every two zero bytes
+/// that are followed by a byte of 0x03 or less, and after trailing zeros
+pub fn add_emulation_prevention(rbsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rbsp.len() + rbsp.len() / 64 + 1);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros >= 2 && byte <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        out.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    if zeros > 0 {
+        out.push(3);
+    }
+    out
+}
+
+/// Undo `add_emulation_prevention`: drop the 0x03 following each pair of
+/// zero bytes
+pub fn remove_emulation_prevention(ebsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(ebsp.len());
+    let mut zeros = 0;
+    for &byte in ebsp {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        out.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    out
+}
+
+/// NAL units of an Annex B stream, each with the offset of its start code
+fn nal_units(data: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            let start_code = if i > 0 && data[i - 1] == 0 { i - 1 } else { i };
+            starts.push((start_code, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &(start_code, nal))| {
+            let mut end = starts.get(index + 1).map_or(data.len(), |&(next, _)| next);
+            // Zero bytes between NAL units belong to neither
+            while end > nal && data[end - 1] == 0 {
+                end -= 1;
+            }
+            (start_code, &data[nal..end])
+        })
+        .collect()
+}
+
+/// Whether a NAL unit holds a coded slice, IDR or not
+fn is_slice(nal: &[u8]) -> bool {
+    nal.first()
+        .is_some_and(|header| (1..=5).contains(&(header & 0x1F)))
+}
+
+/// Whether anything but the trailing bits is left
+fn more_rbsp_data(rest: &[u8]) -> bool {
+    match rest.split_first() {
+        None => false,
+        Some((&RBSP_TRAILING, tail)) => tail.iter().any(|&byte| byte != 0),
+        Some(_) => true,
+    }
+}
+
+/// An SEI payload type or size: 0xFF bytes each add 255 to the final byte
+fn read_sei_value(rest: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    loop {
+        let (&byte, tail) = rest.split_first()?;
+        *rest = tail;
+        value = value.checked_add(byte as u32)?;
+        if byte != 0xFF {
+            return Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether escaped bytes contain a start code prefix, which would split
+    /// the NAL unit
+    fn has_start_code(ebsp: &[u8]) -> bool {
+        ebsp.windows(3).any(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for (capture_ts_us, frame_number) in [
+            (1_700_000_000_000_000, 42),
+            (0, 0),
+            (u64::MAX, u64::MAX),
+            (0x0000_0300_0001_0002, 0x0000_0000_0000_0003),
+        ] {
+            let sei = LatencySei::new(capture_ts_us, frame_number);
+            let nal = sei.to_nal();
+            assert_eq!(nal[..5], [0, 0, 0, 1, NAL_TYPE_SEI]);
+            assert!(!has_start_code(&nal[4..]), "{:02x?}", nal);
+            assert_eq!(LatencySei::parse_nal(&nal[4..]), Some(sei));
+            assert_eq!(LatencySei::find(&nal), Some(sei));
+        }
+    }
+
+    #[test]
+    fn test_zero_timestamps_escaped() {
+        // Sixteen zero bytes in a row take an escape before every other
+        // zero after the first two
+        let nal = LatencySei::new(0, 0).to_nal();
+        let escapes = nal[5..].windows(3).filter(|w| w == &[0, 0, 3]).count();
+        assert_eq!(escapes, 7);
+        assert_eq!(nal.len(), 5 + 2 + PAYLOAD_SIZE + 1 + escapes);
+    }
+
+    #[test]
+    fn test_emulation_prevention() {
+        let cases: [(&[u8], &[u8]); 7] = [
+            (&[0, 0, 0], &[0, 0, 3, 0, 3]),
+            (&[0, 0, 1], &[0, 0, 3, 1]),
+            (&[0, 0, 2], &[0, 0, 3, 2]),
+            (&[0, 0, 3], &[0, 0, 3, 3]),
+            (&[0, 0, 4], &[0, 0, 4]),
+            (&[0, 0, 0, 0, 0], &[0, 0, 3, 0, 0, 3, 0, 3]),
+            (&[1, 0, 0, 3, 0, 0, 0x80], &[1, 0, 0, 3, 3, 0, 0, 0x80]),
+        ];
+        for (rbsp, ebsp) in cases {
+            assert_eq!(add_emulation_prevention(rbsp), ebsp, "{:02x?}", rbsp);
+            assert_eq!(remove_emulation_prevention(ebsp)[..rbsp.len()], *rbsp);
+        }
+    }
+
+    #[test]
+    fn test_insert_before_first_slice() {
+        let sps = [0, 0, 0, 1, 0x67, 0x64, 0x00, 0x1f];
+        let pps = [0, 0, 0, 1, 0x68, 0xee, 0x3c, 0x80];
+        let idr = [0, 0, 1, 0x65, 0x88, 0x84, 0x00, 0x33];
+        let access_unit = [&sps[..], &pps, &idr].concat();
+
+        let sei = LatencySei::new(1234, 5);
+        let out = sei.insert_into(&access_unit);
+        let expected = [&sps[..], &pps, &sei.to_nal(), &idr].concat();
+        assert_eq!(out, expected);
+        assert_eq!(LatencySei::find(&out), Some(sei));
+        assert!(crate::contains_idr(&out));
+    }
+
+    #[test]
+    fn test_insert_without_slices_appends() {
+        let sei = LatencySei::new(1, 2);
+        let data = [1, 2, 3, 4];
+        let out = sei.insert_into(&data);
+        assert_eq!(out[..4], data);
+        assert_eq!(LatencySei::find(&out[4..]), Some(sei));
+    }
+
+    #[test]
+    fn test_other_sei_messages_skipped() {
+        // A recovery point message, then ours with a 255+ byte message of
+        // another kind before it
+        let mut rbsp = vec![6, 1, 0x84];
+        rbsp.extend_from_slice(&[5, 0xFF, 1]);
+        rbsp.extend(std::iter::repeat(0xAB).take(256));
+        let ours = LatencySei::new(99, 7).to_nal();
+        let ours = remove_emulation_prevention(&ours[5..]);
+        rbsp.extend_from_slice(&ours);
+
+        let mut nal = vec![NAL_TYPE_SEI];
+        nal.extend_from_slice(&add_emulation_prevention(&rbsp));
+        assert_eq!(LatencySei::parse_nal(&nal), Some(LatencySei::new(99, 7)));
+    }
+
+    #[test]
+    fn test_rejects_other_data() {
+        // Not an SEI NAL unit
+        let mut nal = LatencySei::new(1, 2).to_nal();
+        nal[4] = 0x65;
+        assert_eq!(LatencySei::parse_nal(&nal[4..]), None);
+
+        // Someone else's UUID
+        let mut nal = LatencySei::new(1, 2).to_nal();
+        nal[7] ^= 0xFF;
+        assert_eq!(LatencySei::parse_nal(&nal[4..]), None);
+
+        // Truncated
+        let nal = LatencySei::new(1, 2).to_nal();
+        for len in 4..nal.len() - 1 {
+            assert_eq!(LatencySei::parse_nal(&nal[4..len]), None);
+        }
+
+        assert_eq!(LatencySei::find(&[]), None);
+        assert_eq!(LatencySei::find(&[0, 0, 1]), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Packet sequence numbers, and comparing them across the wrap
+//!
+//! Sequence numbers are u32 and wrap after a few hours of streaming, so
+//! they're compared with serial number arithmetic (RFC 1982): `b` follows
+//! `a` if it's less than half the number space ahead, wrapping or not.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Signed steps from sequence number `from` forward to `to`, e.g. 2 from
+/// `u32::MAX` to 1. Numbers exactly half the space apart give `i32::MIN`.
+pub fn seq_distance(from: u32, to: u32) -> i32 {
+    to.wrapping_sub(from) as i32
+}
+
+/// Whether sequence number `a` comes before `b`. Numbers exactly half the
+/// space apart are unordered: neither comes before the other.
+pub fn seq_less_than(a: u32, b: u32) -> bool {
+    seq_distance(a, b) > 0
+}
+
+/// How far back `SequenceTracker` tells a late packet from a duplicate
+const TRACKED_WINDOW: u32 = 64;
+
+/// Hands out the sequence numbers for packets sent on one transport,
+/// wrapping after `u32::MAX`. Every task sending on the transport shares
+/// one, so no number is used twice.
+#[derive(Debug, Default)]
+pub struct SequenceGenerator {
+    next: AtomicU32,
+}
+
+impl SequenceGenerator {
+    /// Number packets from `first`
+    pub fn new(first: u32) -> Self {
+        Self {
+            next: AtomicU32::new(first),
+        }
+    }
+
+    /// Take the next sequence number
+    pub fn next(&self) -> u32 {
+        // fetch_add wraps on overflow
+        self.next.fetch_add(1, Ordering::AcqRel)
+    }
+
+    /// The number `next` will return, without taking it
+    pub fn peek(&self) -> u32 {
+        self.next.load(Ordering::Acquire)
+    }
+
+    /// Number packets from `next` on, e.g. after a new handshake
+    pub fn reset(&self, next: u32) {
+        self.next.store(next, Ordering::Release);
+    }
+}
+
+/// What a received sequence number was, to `SequenceTracker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceStatus {
+    /// The first number seen
+    First,
+    /// The one after the highest so far
+    Next,
+    /// Ahead of the next expected, with this many skipped
+    Gap(u32),
+    /// Behind the highest, and not seen before: reordered
+    Late,
+    /// Seen before
+    Duplicate,
+}
+
+/// Counters kept by `SequenceTracker`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    pub received: u64,
+    /// Numbers skipped and not seen since
+    pub missing: u64,
+    /// Numbers seen after a later one
+    pub reordered: u64,
+    pub duplicates: u64,
+}
+
+/// Follows the sequence numbers received on a transport to spot gaps,
+/// reordering, and duplicates, carrying on across the wrap
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    /// Highest number seen, in serial order
+    highest: Option<u32>,
+    /// Bit n is set if `highest - n` has been seen
+    seen: u64,
+    stats: SequenceStats,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received sequence number. One too far behind the highest to
+    /// tell from a duplicate is taken as late.
+    pub fn observe(&mut self, sequence: u32) -> SequenceStatus {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.seen = 1;
+            self.stats.received += 1;
+            return SequenceStatus::First;
+        };
+
+        let distance = seq_distance(highest, sequence);
+        if distance == 0 {
+            self.stats.duplicates += 1;
+            return SequenceStatus::Duplicate;
+        }
+        if distance > 0 {
+            let ahead = distance as u32;
+            self.seen = if ahead < TRACKED_WINDOW {
+                (self.seen << ahead) | 1
+            } else {
+                1
+            };
+            self.highest = Some(sequence);
+            self.stats.received += 1;
+            self.stats.missing += (ahead - 1) as u64;
+            return match ahead {
+                1 => SequenceStatus::Next,
+                _ => SequenceStatus::Gap(ahead - 1),
+            };
+        }
+
+        let behind = distance.unsigned_abs();
+        if behind < TRACKED_WINDOW {
+            let bit = 1 << behind;
+            if self.seen & bit != 0 {
+                self.stats.duplicates += 1;
+                return SequenceStatus::Duplicate;
+            }
+            self.seen |= bit;
+        }
+        self.stats.received += 1;
+        self.stats.reordered += 1;
+        self.stats.missing = self.stats.missing.saturating_sub(1);
+        SequenceStatus::Late
+    }
+
+    /// Highest number seen so far, in serial order
+    pub fn highest(&self) -> Option<u32> {
+        self.highest
+    }
+
+    pub fn stats(&self) -> SequenceStats {
+        self.stats
+    }
+
+    /// Forget everything, e.g. when the peer starts numbering afresh
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sequence_wraps() {
+        let sequence = SequenceGenerator::new(u32::MAX - 1);
+        assert_eq!(sequence.next(), u32::MAX - 1);
+        assert_eq!(sequence.next(), u32::MAX);
+        assert_eq!(sequence.peek(), 0);
+        assert_eq!(sequence.next(), 0);
+
+        sequence.reset(7);
+        assert_eq!(sequence.next(), 7);
+    }
+
+    #[test]
+    fn test_sequence_unique_across_threads() {
+        let sequence = Arc::new(SequenceGenerator::new(100));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let sequence = Arc::clone(&sequence);
+                std::thread::spawn(move || (0..1000).map(|_| sequence.next()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        let mut numbers: Vec<u32> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (100..4100).collect::<Vec<_>>());
+    }
+
+    /// Numbers either side of the wrap, and around the middle of the space
+    fn boundary_numbers() -> impl Iterator<Item = u32> {
+        (u32::MAX - 300..=u32::MAX)
+            .chain(0..=300)
+            .chain((1 << 31) - 300..=(1 << 31) + 300)
+    }
+
+    #[test]
+    fn test_seq_distance_across_wrap() {
+        assert_eq!(seq_distance(u32::MAX, 0), 1);
+        assert_eq!(seq_distance(u32::MAX, 1), 2);
+        assert_eq!(seq_distance(1, u32::MAX), -2);
+        assert_eq!(seq_distance(5, 5), 0);
+        assert_eq!(seq_distance(0, 1 << 31), i32::MIN);
+        assert_eq!(seq_distance(1 << 31, 0), i32::MIN);
+
+        for a in boundary_numbers() {
+            for step in -200i32..=200 {
+                let b = a.wrapping_add(step as u32);
+                assert_eq!(seq_distance(a, b), step, "{} to {}", a, b);
+                assert_eq!(seq_less_than(a, b), step > 0, "{} < {}", a, b);
+                assert_eq!(seq_less_than(b, a), step < 0, "{} < {}", b, a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seq_less_than_half_space() {
+        for a in boundary_numbers() {
+            let far = a.wrapping_add(1 << 31);
+            // Exactly half the space apart: unordered
+            assert!(!seq_less_than(a, far));
+            assert!(!seq_less_than(far, a));
+            // Just short of half ahead, and just past it (so behind)
+            assert!(seq_less_than(a, far.wrapping_sub(1)));
+            assert!(seq_less_than(far.wrapping_add(1), a));
+        }
+    }
+
+    #[test]
+    fn test_tracker_in_order_across_wrap() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.observe(u32::MAX - 1), SequenceStatus::First);
+        assert_eq!(tracker.observe(u32::MAX), SequenceStatus::Next);
+        assert_eq!(tracker.observe(0), SequenceStatus::Next);
+        assert_eq!(tracker.observe(1), SequenceStatus::Next);
+        assert_eq!(tracker.highest(), Some(1));
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 4,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_tracker_gap_across_wrap() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(u32::MAX - 2);
+        // MAX - 1, MAX, 0 and 1 skipped
+        assert_eq!(tracker.observe(2), SequenceStatus::Gap(4));
+        assert_eq!(tracker.stats().missing, 4);
+    }
+
+    #[test]
+    fn test_tracker_reordering_straddling_wrap() {
+        let mut tracker = SequenceTracker::new();
+        let order = [u32::MAX - 2, u32::MAX, 1, u32::MAX - 1, 0, 3, 2, u32::MAX];
+        let statuses: Vec<SequenceStatus> = order.iter().map(|&n| tracker.observe(n)).collect();
+        assert_eq!(
+            statuses,
+            [
+                SequenceStatus::First,
+                SequenceStatus::Gap(1),
+                SequenceStatus::Gap(1),
+                SequenceStatus::Late,
+                SequenceStatus::Late,
+                SequenceStatus::Gap(1),
+                SequenceStatus::Late,
+                SequenceStatus::Duplicate,
+            ]
+        );
+        assert_eq!(tracker.highest(), Some(3));
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 7,
+                missing: 0,
+                reordered: 3,
+                duplicates: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_tracker_far_behind_is_late() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(10);
+        assert_eq!(
+            tracker.observe(10 + TRACKED_WINDOW + 5),
+            SequenceStatus::Gap(68)
+        );
+        // Beyond the window, so can't be told from a duplicate
+        assert_eq!(tracker.observe(10), SequenceStatus::Late);
+        assert_eq!(tracker.observe(11), SequenceStatus::Late);
+
+        tracker.reset();
+        assert_eq!(tracker.observe(10), SequenceStatus::First);
+    }
+
+    /// A long stream, numbered by a `SequenceGenerator` from well before the
+    /// wrap to well after it, losing, swapping, and repeating some packets
+    #[test]
+    fn test_long_run_past_wrap() {
+        const PACKETS: usize = 100_000;
+
+        let generator = SequenceGenerator::new(u32::MAX - PACKETS as u32 / 2);
+        let sent: Vec<u32> = (0..PACKETS).map(|_| generator.next()).collect();
+        assert_eq!(generator.peek(), PACKETS as u32 / 2 - 1);
+
+        // Every 97th packet lost, every 101st sent twice, and every 89th
+        // sent after the one following it. The first isn't lost, as there'd
+        // be no telling.
+        let is_lost = |index: usize| index % 97 == 96;
+        let (mut lost, mut repeated, mut swapped) = (0, 0, 0);
+        let mut wire = Vec::new();
+        let mut index = 0;
+        while index < PACKETS {
+            if index % 89 == 0 && !is_lost(index) && !is_lost(index + 1) {
+                wire.extend([sent[index + 1], sent[index]]);
+                swapped += 1;
+                index += 2;
+                continue;
+            }
+            if is_lost(index) {
+                lost += 1;
+            } else {
+                wire.push(sent[index]);
+                if index % 101 == 0 {
+                    wire.push(sent[index]);
+                    repeated += 1;
+                }
+            }
+            index += 1;
+        }
+
+        let mut tracker = SequenceTracker::new();
+        for &sequence in &wire {
+            tracker.observe(sequence);
+        }
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: (PACKETS - lost) as u64,
+                missing: lost as u64,
+                reordered: swapped,
+                duplicates: repeated,
+            }
+        );
+        assert_eq!(tracker.highest(), sent.last().copied());
+    }
+}
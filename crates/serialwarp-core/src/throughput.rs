@@ -0,0 +1,126 @@
+//! Link throughput over a rolling window
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Default span of samples averaged by `ThroughputMeter`
+pub const DEFAULT_THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Rate of a growing byte counter, e.g. `TransportStats::bytes_received`,
+/// averaged over the most recent samples
+#[derive(Debug, Clone)]
+pub struct ThroughputMeter {
+    window: Duration,
+    /// (time, counter total) pairs, oldest first
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ThroughputMeter {
+    /// Create a meter averaging over `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record the counter's total at `now`. A total lower than the last
+    /// one means the counter was reset, e.g. by a new connection, and
+    /// starts the window over.
+    pub fn sample(&mut self, now: Instant, total_bytes: u64) {
+        if self
+            .samples
+            .back()
+            .is_some_and(|&(_, last)| total_bytes < last)
+        {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, total_bytes));
+
+        // Keep the newest sample that is at least a window old, so the rate
+        // always spans a full window once there is enough history
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second across the window; zero until two samples exist
+    pub fn bytes_per_second(&self) -> f64 {
+        let (Some(&(first_time, first)), Some(&(last_time, last))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return 0.0;
+        };
+        let elapsed = last_time.duration_since(first_time).as_secs_f64();
+        if elapsed > 0.0 {
+            (last - first) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Bits per second across the window
+    pub fn bits_per_second(&self) -> f64 {
+        self.bytes_per_second() * 8.0
+    }
+
+    /// Forget all samples
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+impl Default for ThroughputMeter {
+    fn default() -> Self {
+        Self::new(DEFAULT_THROUGHPUT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_rate_without_two_samples() {
+        let mut meter = ThroughputMeter::default();
+        assert_eq!(meter.bytes_per_second(), 0.0);
+        meter.sample(Instant::now(), 1000);
+        assert_eq!(meter.bytes_per_second(), 0.0);
+    }
+
+    #[test]
+    fn test_steady_rate() {
+        let mut meter = ThroughputMeter::default();
+        let start = Instant::now();
+        for second in 0..4 {
+            meter.sample(start + Duration::from_secs(second), second * 125_000);
+        }
+        assert_eq!(meter.bytes_per_second(), 125_000.0);
+        assert_eq!(meter.bits_per_second(), 1_000_000.0);
+    }
+
+    #[test]
+    fn test_window_forgets_old_rate() {
+        let mut meter = ThroughputMeter::new(Duration::from_secs(2));
+        let start = Instant::now();
+        let mut total = 0;
+        for second in 0..10 {
+            meter.sample(start + Duration::from_secs(second), total);
+            total += if second < 5 { 1000 } else { 100 };
+        }
+        // Only the slower last seconds are in the window
+        assert_eq!(meter.bytes_per_second(), 100.0);
+    }
+
+    #[test]
+    fn test_counter_reset_restarts_window() {
+        let mut meter = ThroughputMeter::default();
+        let start = Instant::now();
+        meter.sample(start, 0);
+        meter.sample(start + Duration::from_secs(1), 1_000_000);
+        meter.sample(start + Duration::from_secs(2), 10);
+        assert_eq!(meter.bytes_per_second(), 0.0);
+        meter.sample(start + Duration::from_secs(3), 510);
+        assert_eq!(meter.bytes_per_second(), 500.0);
+    }
+}
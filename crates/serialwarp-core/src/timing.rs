@@ -0,0 +1,353 @@
+//! Distributions of frame timings, for jitter an average hides
+
+use std::fmt;
+use std::time::Duration;
+
+/// Buckets each power of two of microseconds is split into; a bucket spans
+/// at most an eighth of its lower bound
+pub const BUCKETS_PER_OCTAVE: usize = 8;
+
+/// Powers of two of microseconds covered, 1 µs up to about 134 s
+const OCTAVES: usize = 27;
+
+/// Bucket 0 holds anything under a microsecond; the last also holds
+/// anything too long for the rest
+const BUCKETS: usize = 1 + OCTAVES * BUCKETS_PER_OCTAVE;
+
+/// Bits of a value below its highest that pick the bucket in its octave
+const SUB_BITS: u32 = BUCKETS_PER_OCTAVE.trailing_zeros();
+
+/// Counts of durations in fixed log-scale buckets. Recording is a few
+/// integer operations with no allocation, so it's cheap enough for every
+/// frame; percentiles are interpolated within a bucket, so they're accurate
+/// to within an eighth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl TimingHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a duration
+    pub fn record(&mut self, duration: Duration) {
+        self.buckets[bucket_index(duration)] += 1;
+        self.min = if self.count == 0 {
+            duration
+        } else {
+            self.min.min(duration)
+        };
+        self.max = self.max.max(duration);
+        self.sum = self.sum.saturating_add(duration);
+        self.count += 1;
+    }
+
+    /// Durations recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Shortest duration recorded, exactly
+    pub fn min(&self) -> Option<Duration> {
+        (!self.is_empty()).then_some(self.min)
+    }
+
+    /// Longest duration recorded, exactly
+    pub fn max(&self) -> Option<Duration> {
+        (!self.is_empty()).then_some(self.max)
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (!self.is_empty())
+            .then(|| Duration::from_nanos((self.sum.as_nanos() / self.count as u128) as u64))
+    }
+
+    /// The duration `p` percent of those recorded are at or under, `p`
+    /// being 0 to 100. Values are taken to be spread evenly through their
+    /// bucket, and the answer never falls outside the recorded range.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        }
+        let rank = (p.clamp(0.0, 100.0) / 100.0) * self.count as f64;
+        let mut below = 0;
+        let mut estimate = self.max;
+        for (index, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if (below + count) as f64 >= rank {
+                let (low, high) = bucket_bounds_us(index);
+                let fraction = (rank - below as f64) / count as f64;
+                let us = low + fraction * (high - low);
+                estimate = Duration::from_nanos((us * 1000.0).round() as u64);
+                break;
+            }
+            below += count;
+        }
+        Some(estimate.clamp(self.min, self.max))
+    }
+
+    /// The median, 95th and 99th percentiles
+    pub fn percentiles(&self) -> Option<TimingPercentiles> {
+        Some(TimingPercentiles {
+            p50: self.percentile(50.0)?,
+            p95: self.percentile(95.0)?,
+            p99: self.percentile(99.0)?,
+        })
+    }
+
+    /// Add everything recorded in `other`, as if it had been recorded here
+    pub fn merge(&mut self, other: &TimingHistogram) {
+        if other.is_empty() {
+            return;
+        }
+        for (bucket, &count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.min = if self.is_empty() {
+            other.min
+        } else {
+            self.min.min(other.min)
+        };
+        self.max = self.max.max(other.max);
+        self.sum = self.sum.saturating_add(other.sum);
+        self.count += other.count;
+    }
+
+    /// Forget everything recorded
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Default for TimingHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: Duration::ZERO,
+            min: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+/// Bucket a duration falls in
+fn bucket_index(duration: Duration) -> usize {
+    let us = duration.as_micros().min(u64::MAX as u128) as u64;
+    if us == 0 {
+        return 0;
+    }
+    let octave = 63 - us.leading_zeros();
+    // The bits after the highest, shifted up first for octaves that have
+    // fewer of them than there are buckets
+    let sub = ((us as u128) << SUB_BITS >> octave) as usize & (BUCKETS_PER_OCTAVE - 1);
+    (1 + octave as usize * BUCKETS_PER_OCTAVE + sub).min(BUCKETS - 1)
+}
+
+/// Lower and upper bound of a bucket, in microseconds
+fn bucket_bounds_us(index: usize) -> (f64, f64) {
+    if index == 0 {
+        return (0.0, 1.0);
+    }
+    let octave = (index - 1) / BUCKETS_PER_OCTAVE;
+    let sub = (index - 1) % BUCKETS_PER_OCTAVE;
+    let base = (1u64 << octave) as f64;
+    let width = base / BUCKETS_PER_OCTAVE as f64;
+    let low = base + sub as f64 * width;
+    (low, low + width)
+}
+
+/// Median, 95th and 99th percentile of a `TimingHistogram`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimingPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl fmt::Display for TimingPercentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "p50={:.1}ms p95={:.1}ms p99={:.1}ms",
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.p99)
+        )
+    }
+}
+
+/// A `TimingHistogram` over a rolling stats window: durations go into the
+/// current window, and queries cover it and the one before, so they never
+/// rest on just the few samples of a window that has only just started
+#[derive(Debug, Clone, Default)]
+pub struct TimingWindow {
+    current: TimingHistogram,
+    previous: TimingHistogram,
+}
+
+impl TimingWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        self.current.record(duration);
+    }
+
+    /// Everything recorded in the current window alone
+    pub fn current(&self) -> &TimingHistogram {
+        &self.current
+    }
+
+    /// Everything recorded in the current and previous windows
+    pub fn histogram(&self) -> TimingHistogram {
+        let mut histogram = self.previous.clone();
+        histogram.merge(&self.current);
+        histogram
+    }
+
+    pub fn percentiles(&self) -> Option<TimingPercentiles> {
+        self.histogram().percentiles()
+    }
+
+    /// Start a new window; the current one becomes the previous
+    pub fn rotate(&mut self) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    /// Forget both windows
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.previous.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us(us: u64) -> Duration {
+        Duration::from_micros(us)
+    }
+
+    #[test]
+    fn test_bucket_boundaries() {
+        assert_eq!(bucket_index(Duration::from_nanos(999)), 0);
+        assert_eq!(bucket_index(us(1)), 1);
+        // Small octaves have fewer values than buckets, so some stay empty
+        assert_eq!(bucket_index(us(2)), 1 + BUCKETS_PER_OCTAVE);
+        assert_eq!(bucket_index(us(3)), 1 + BUCKETS_PER_OCTAVE + 4);
+
+        // From 8 µs on, each value in an octave has its own eighth
+        assert_eq!(bucket_index(us(8)), 1 + 3 * BUCKETS_PER_OCTAVE);
+        assert_eq!(bucket_index(us(15)), 1 + 3 * BUCKETS_PER_OCTAVE + 7);
+        assert_eq!(bucket_index(us(16)), 1 + 4 * BUCKETS_PER_OCTAVE);
+
+        // A bucket holds its lower bound but not its upper
+        for value in [1, 5, 100, 1023, 1024, 1151, 1152, 16_667, 33_333, 1 << 26] {
+            let (low, high) = bucket_bounds_us(bucket_index(us(value)));
+            assert!(low <= value as f64 && (value as f64) < high, "{}", value);
+        }
+        assert_eq!(bucket_index(us(1151)), bucket_index(us(1024)));
+        assert_eq!(bucket_index(us(1152)), bucket_index(us(1024)) + 1);
+
+        // Anything too long lands in the last bucket
+        assert_eq!(bucket_index(Duration::from_secs(3600)), BUCKETS - 1);
+        assert_eq!(bucket_index(Duration::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_empty() {
+        let histogram = TimingHistogram::new();
+        assert!(histogram.is_empty());
+        assert_eq!(histogram.percentile(50.0), None);
+        assert_eq!(histogram.percentiles(), None);
+        assert_eq!(histogram.mean(), None);
+    }
+
+    #[test]
+    fn test_single_value() {
+        let mut histogram = TimingHistogram::new();
+        histogram.record(us(16_667));
+        // Clamped to what was recorded, rather than the bucket's bounds
+        for p in [0.0, 50.0, 99.0, 100.0] {
+            assert_eq!(histogram.percentile(p), Some(us(16_667)));
+        }
+        assert_eq!(histogram.mean(), Some(us(16_667)));
+    }
+
+    #[test]
+    fn test_percentile_interpolation() {
+        // Four values in the [1024, 1152) µs bucket: the median is taken
+        // halfway through it
+        let mut histogram = TimingHistogram::new();
+        for value in [1024, 1050, 1100, 1150] {
+            histogram.record(us(value));
+        }
+        assert_eq!(histogram.percentile(50.0), Some(us(1088)));
+        assert_eq!(histogram.percentile(25.0), Some(us(1056)));
+        assert_eq!(histogram.percentile(100.0), Some(us(1150)));
+        assert_eq!(histogram.min(), Some(us(1024)));
+
+        // A spike in one frame in ten shows at p95 but not p50
+        let mut histogram = TimingHistogram::new();
+        for _ in 0..90 {
+            histogram.record(us(1000));
+        }
+        for _ in 0..10 {
+            histogram.record(us(8000));
+        }
+        let percentiles = histogram.percentiles().unwrap();
+        assert_eq!(percentiles.p50, us(1000));
+        // Halfway through the [7680, 8192) µs bucket
+        assert_eq!(percentiles.p95, us(7936));
+        assert_eq!(percentiles.p99, us(8000));
+        assert_eq!(histogram.mean(), Some(us(1700)));
+        assert_eq!(percentiles.to_string(), "p50=1.0ms p95=7.9ms p99=8.0ms");
+    }
+
+    #[test]
+    fn test_merge_across_window_reset() {
+        let values: Vec<u64> = (0..200).map(|i| 15_000 + (i * 37) % 4000).collect();
+        let mut whole = TimingHistogram::new();
+        let mut window = TimingWindow::new();
+        for (i, &value) in values.iter().enumerate() {
+            if i == 120 {
+                window.rotate();
+            }
+            whole.record(us(value));
+            window.record(us(value));
+        }
+
+        // Across the reset, the two windows together are as if one
+        assert_eq!(window.current().count(), 80);
+        assert_eq!(window.histogram(), whole);
+        assert_eq!(window.percentiles(), whole.percentiles());
+
+        // Merging into an empty histogram copies it
+        let mut merged = TimingHistogram::new();
+        merged.merge(&whole);
+        assert_eq!(merged, whole);
+
+        // A second reset drops the first window
+        window.rotate();
+        assert_eq!(window.histogram().count(), 80);
+        window.rotate();
+        assert!(window.histogram().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Per-frame stage timings, for following single frames through the
+//! pipeline
+
+use std::fmt;
+use std::time::Duration;
+
+/// Whether frame `frame_number` is one of every `every` frames traced in
+/// full; 0 traces none
+pub fn is_traced(frame_number: u64, every: u32) -> bool {
+    every != 0 && frame_number % every as u64 == 0
+}
+
+/// How long each stage took for one frame, in the order the frame went
+/// through them.
+///
+/// Displays as a single line that's easy to grep for, e.g.
+/// `frame=42 capture=0.812ms encode=2.405ms send=0.351ms total=3.568ms`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameTrace {
+    pub frame_number: u64,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl FrameTrace {
+    pub fn new(frame_number: u64) -> Self {
+        Self {
+            frame_number,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Record that the frame spent `time` in `stage`
+    pub fn stage(&mut self, stage: &'static str, time: Duration) {
+        self.stages.push((stage, time));
+    }
+
+    /// Time spent in `stage`, if it was recorded
+    pub fn get(&self, stage: &str) -> Option<Duration> {
+        self.stages
+            .iter()
+            .find(|(name, _)| *name == stage)
+            .map(|(_, time)| *time)
+    }
+
+    /// Stages recorded so far, in order
+    pub fn stages(&self) -> &[(&'static str, Duration)] {
+        &self.stages
+    }
+
+    /// Time spent in all stages
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, time)| *time).sum()
+    }
+}
+
+impl fmt::Display for FrameTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame={}", self.frame_number)?;
+        for (stage, time) in &self.stages {
+            write!(f, " {}={:.3}ms", stage, time.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, " total={:.3}ms", self.total().as_secs_f64() * 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_nth_frame_traced() {
+        let traced: Vec<u64> = (0..10).filter(|&n| is_traced(n, 4)).collect();
+        assert_eq!(traced, vec![0, 4, 8]);
+        assert!((0..10).all(|n| is_traced(n, 1)));
+        assert!((0..10).all(|n| !is_traced(n, 0)));
+    }
+
+    #[test]
+    fn test_display_is_one_line() {
+        let mut trace = FrameTrace::new(42);
+        trace.stage("capture", Duration::from_micros(812));
+        trace.stage("encode", Duration::from_micros(2405));
+        trace.stage("send", Duration::from_micros(351));
+
+        assert_eq!(
+            trace.to_string(),
+            "frame=42 capture=0.812ms encode=2.405ms send=0.351ms total=3.568ms"
+        );
+        assert_eq!(trace.get("encode"), Some(Duration::from_micros(2405)));
+        assert_eq!(trace.get("decode"), None);
+    }
+
+    #[test]
+    fn test_display_without_stages() {
+        assert_eq!(FrameTrace::new(7).to_string(), "frame=7 total=0.000ms");
+    }
+}
@@ -1,5 +1,16 @@
 //! USB device definitions for supported link cables
 
+use std::str::FromStr;
+
+use crate::error::DeviceIdError;
+
+/// Environment variable listing extra devices to accept, as comma-separated
+/// `VID:PID` pairs in hex, e.g. `067b:27a2,1234:5678`
+pub const EXTRA_DEVICES_ENV: &str = "SERIALWARP_EXTRA_DEVICES";
+
+/// Name given to devices added by id alone
+pub const EXTRA_DEVICE_NAME: &str = "User-listed device";
+
 /// USB device identification
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UsbDeviceId {
@@ -18,6 +29,45 @@ impl UsbDeviceId {
     }
 }
 
+impl UsbDeviceId {
+    /// udev rule letting the logged-in user open this device on Linux
+    pub fn udev_rule(&self) -> String {
+        format!(
+            "SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", \
+             MODE=\"0660\", TAG+=\"uaccess\"",
+            self.vendor_id, self.product_id
+        )
+    }
+}
+
+/// Parses `VID:PID` in hex, with or without a `0x` prefix. The device is
+/// named `EXTRA_DEVICE_NAME`.
+impl FromStr for UsbDeviceId {
+    type Err = DeviceIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || DeviceIdError::Malformed(s.to_string());
+        let hex = |part: &str| {
+            let part = part.trim();
+            let digits = part
+                .strip_prefix("0x")
+                .or_else(|| part.strip_prefix("0X"))
+                .unwrap_or(part);
+            if digits.is_empty() || digits.len() > 4 {
+                return Err(malformed());
+            }
+            u16::from_str_radix(digits, 16).map_err(|_| malformed())
+        };
+
+        let (vendor_id, product_id) = s.split_once(':').ok_or_else(malformed)?;
+        Ok(Self::new(
+            hex(vendor_id)?,
+            hex(product_id)?,
+            EXTRA_DEVICE_NAME,
+        ))
+    }
+}
+
 /// Supported USB link cable devices
 pub const SUPPORTED_USB_DEVICES: &[UsbDeviceId] = &[
     UsbDeviceId::new(0x067B, 0x27A1, "Prolific PL27A1"),
@@ -39,6 +89,80 @@ pub fn find_supported_device(vendor_id: u16, product_id: u16) -> Option<&'static
         .find(|d| d.vendor_id == vendor_id && d.product_id == product_id)
 }
 
+/// Devices a USB transport will open: the built-in `SUPPORTED_USB_DEVICES`
+/// plus any registered at runtime, e.g. other PL27A1-based bridges with
+/// custom product ids
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceRegistry {
+    devices: Vec<UsbDeviceId>,
+}
+
+impl DeviceRegistry {
+    /// A registry of the built-in devices
+    pub fn new() -> Self {
+        Self {
+            devices: SUPPORTED_USB_DEVICES.to_vec(),
+        }
+    }
+
+    /// The built-in devices plus any listed in `EXTRA_DEVICES_ENV`
+    pub fn from_env() -> Result<Self, DeviceIdError> {
+        let mut registry = Self::new();
+        if let Ok(list) = std::env::var(EXTRA_DEVICES_ENV) {
+            registry.register_list(&list)?;
+        }
+        Ok(registry)
+    }
+
+    /// Add a device. Returns false if one with the same VID/PID is already
+    /// registered, which is kept.
+    pub fn register(&mut self, device: UsbDeviceId) -> bool {
+        if self.is_supported(device.vendor_id, device.product_id) {
+            return false;
+        }
+        self.devices.push(device);
+        true
+    }
+
+    /// Add every device in a comma-separated `VID:PID` list, returning how
+    /// many were new. Nothing is added if any entry is malformed.
+    pub fn register_list(&mut self, list: &str) -> Result<usize, DeviceIdError> {
+        let devices = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<UsbDeviceId>, _>>()?;
+        Ok(devices
+            .into_iter()
+            .filter(|device| self.register(*device))
+            .count())
+    }
+
+    /// Check if a USB device with the given VID/PID is registered
+    pub fn is_supported(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.find(vendor_id, product_id).is_some()
+    }
+
+    /// Find a registered device by VID/PID
+    pub fn find(&self, vendor_id: u16, product_id: u16) -> Option<&UsbDeviceId> {
+        self.devices
+            .iter()
+            .find(|d| d.vendor_id == vendor_id && d.product_id == product_id)
+    }
+
+    /// Registered devices, built-ins first
+    pub fn devices(&self) -> &[UsbDeviceId] {
+        &self.devices
+    }
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,4 +180,96 @@ mod tests {
         let device = find_supported_device(0x067B, 0x27A1).unwrap();
         assert_eq!(device.name, "Prolific PL27A1");
     }
+
+    #[test]
+    fn test_udev_rule() {
+        let device = find_supported_device(0x067B, 0x27A1).unwrap();
+        assert_eq!(
+            device.udev_rule(),
+            r#"SUBSYSTEM=="usb", ATTR{idVendor}=="067b", ATTR{idProduct}=="27a1", MODE="0660", TAG+="uaccess""#
+        );
+    }
+
+    #[test]
+    fn test_parse_device_id() {
+        for input in ["067b:27a2", "0x067B:0x27A2", " 67b : 27A2 "] {
+            let device: UsbDeviceId = input.parse().unwrap();
+            assert_eq!((device.vendor_id, device.product_id), (0x067B, 0x27A2));
+            assert_eq!(device.name, EXTRA_DEVICE_NAME);
+        }
+        for input in [
+            "",
+            "067b",
+            "067b:",
+            ":27a2",
+            "067b:27a2:1",
+            "xyz:27a2",
+            "10000:1",
+            "0x:1",
+        ] {
+            assert_eq!(
+                input.parse::<UsbDeviceId>(),
+                Err(DeviceIdError::Malformed(input.to_string())),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_registry_starts_with_builtins() {
+        let registry = DeviceRegistry::new();
+        assert_eq!(registry.devices(), SUPPORTED_USB_DEVICES);
+        assert!(registry.is_supported(0x2109, 0x0822));
+        assert!(!registry.is_supported(0x067B, 0x27A2));
+    }
+
+    #[test]
+    fn test_registry_register() {
+        let mut registry = DeviceRegistry::new();
+        assert!(registry.register(UsbDeviceId::new(0x067B, 0x27A2, "Custom PL27A1")));
+        assert_eq!(registry.find(0x067B, 0x27A2).unwrap().name, "Custom PL27A1");
+
+        // Duplicates keep the first entry, built-ins included
+        assert!(!registry.register(UsbDeviceId::new(0x067B, 0x27A2, "Again")));
+        assert!(!registry.register(UsbDeviceId::new(0x067B, 0x27A1, "Renamed")));
+        assert_eq!(
+            registry.find(0x067B, 0x27A1).unwrap().name,
+            "Prolific PL27A1"
+        );
+        assert_eq!(registry.devices().len(), SUPPORTED_USB_DEVICES.len() + 1);
+    }
+
+    #[test]
+    fn test_registry_register_list() {
+        let mut registry = DeviceRegistry::new();
+        let added = registry
+            .register_list("067b:27a2, 1234:5678,,067B:27A1,1234:5678,")
+            .unwrap();
+        assert_eq!(added, 2);
+        assert!(registry.is_supported(0x067B, 0x27A2));
+        assert!(registry.is_supported(0x1234, 0x5678));
+        assert_eq!(registry.devices().len(), SUPPORTED_USB_DEVICES.len() + 2);
+
+        // One bad entry rejects the whole list
+        assert_eq!(
+            registry.register_list("abcd:0001,bogus"),
+            Err(DeviceIdError::Malformed("bogus".to_string()))
+        );
+        assert!(!registry.is_supported(0xABCD, 0x0001));
+        assert_eq!(registry.register_list("").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_registry_from_env() {
+        std::env::set_var(EXTRA_DEVICES_ENV, "abcd:ef01");
+        let registry = DeviceRegistry::from_env();
+        std::env::set_var(EXTRA_DEVICES_ENV, "abcd");
+        let malformed = DeviceRegistry::from_env();
+        std::env::remove_var(EXTRA_DEVICES_ENV);
+
+        assert!(registry.unwrap().is_supported(0xABCD, 0xEF01));
+        assert!(malformed.is_err());
+        assert_eq!(DeviceRegistry::from_env().unwrap(), DeviceRegistry::new());
+    }
 }
@@ -0,0 +1,217 @@
+//! Splitting of H.264 Annex B byte streams into access units
+
+/// NAL unit types that matter for access unit boundaries (ITU-T H.264 7.4.1.2.3)
+mod nal_type {
+    pub const SLICE: u8 = 1;
+    pub const IDR_SLICE: u8 = 5;
+    pub const SEI: u8 = 6;
+    pub const SPS: u8 = 7;
+    pub const PPS: u8 = 8;
+    pub const AUD: u8 = 9;
+}
+
+/// Splits an Annex B elementary stream into access units (one coded picture
+/// plus its parameter sets and SEI each).
+///
+/// Feed bytes in arbitrary chunks with [`push`](Self::push); completed access
+/// units are returned as soon as the start of the next one is seen. Call
+/// [`finish`](Self::finish) at end of stream for the rest. Each returned
+/// access unit keeps its start codes, so it can be passed straight to
+/// `Decoder::decode`.
+#[derive(Debug, Default)]
+pub struct AnnexBSplitter {
+    /// Bytes not yet split into complete NAL units
+    buffer: Vec<u8>,
+    /// NAL units of the access unit being assembled
+    current: Vec<u8>,
+    /// Whether `current` already holds a slice
+    current_has_slice: bool,
+}
+
+impl AnnexBSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add stream bytes and return any access units completed by them
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let starts = find_start_codes(&self.buffer);
+        if starts.len() < 2 {
+            return Vec::new();
+        }
+
+        // Every NAL except the last is known to be complete
+        let mut access_units = Vec::new();
+        let last = starts[starts.len() - 1];
+        for window in starts.windows(2) {
+            let nal = self.buffer[window[0]..window[1]].to_vec();
+            if let Some(access_unit) = self.add_nal(nal) {
+                access_units.push(access_unit);
+            }
+        }
+        self.buffer.drain(..last);
+
+        access_units
+    }
+
+    /// Flush the remaining access units at end of stream
+    pub fn finish(&mut self) -> Vec<Vec<u8>> {
+        let rest = std::mem::take(&mut self.buffer);
+        let mut access_units = Vec::new();
+        if let Some(&start) = find_start_codes(&rest).first() {
+            access_units.extend(self.add_nal(rest[start..].to_vec()));
+        }
+
+        let last = std::mem::take(&mut self.current);
+        self.current_has_slice = false;
+        if !last.is_empty() {
+            access_units.push(last);
+        }
+        access_units
+    }
+
+    /// Append a NAL unit (with its start code) to the current access unit,
+    /// returning the previous access unit if this NAL begins a new one
+    fn add_nal(&mut self, nal: Vec<u8>) -> Option<Vec<u8>> {
+        let Some((nal_type, payload)) = parse_nal(&nal) else {
+            // Start code with no header byte: nothing to decode
+            return None;
+        };
+
+        let is_slice = matches!(nal_type, nal_type::SLICE | nal_type::IDR_SLICE);
+        let starts_new_unit = self.current_has_slice
+            && match nal_type {
+                nal_type::AUD | nal_type::SEI | nal_type::SPS | nal_type::PPS => true,
+                14..=18 => true,
+                // first_mb_in_slice == 0 is coded as a single '1' bit
+                _ if is_slice => payload.first().is_some_and(|byte| byte & 0x80 != 0),
+                _ => false,
+            };
+
+        let completed = if starts_new_unit {
+            self.current_has_slice = false;
+            Some(std::mem::take(&mut self.current))
+        } else {
+            None
+        };
+
+        self.current.extend_from_slice(&nal);
+        self.current_has_slice |= is_slice;
+        completed
+    }
+}
+
+/// Split a complete Annex B stream into access units
+pub fn split_access_units(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut splitter = AnnexBSplitter::new();
+    let mut access_units = splitter.push(data);
+    access_units.extend(splitter.finish());
+    access_units
+}
+
+/// Offsets of every start code (3- or 4-byte) in `data`
+fn find_start_codes(data: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            // Fold a leading zero byte into a 4-byte start code
+            let start = if i > 0 && data[i - 1] == 0 { i - 1 } else { i };
+            starts.push(start);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+}
+
+/// NAL unit type and the bytes after the NAL header
+fn parse_nal(nal: &[u8]) -> Option<(u8, &[u8])> {
+    let header = if nal.starts_with(&[0, 0, 0, 1]) { 4 } else { 3 };
+    let header_byte = *nal.get(header)?;
+    Some((header_byte & 0x1F, &nal[header + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUD: &[u8] = &[0, 0, 0, 1, 0x09, 0xF0];
+    const SPS: &[u8] = &[0, 0, 0, 1, 0x67, 0x42, 0x00, 0x1F];
+    const PPS: &[u8] = &[0, 0, 0, 1, 0x68, 0xCE, 0x38, 0x80];
+    /// IDR slice with first_mb_in_slice == 0
+    const IDR: &[u8] = &[0, 0, 1, 0x65, 0x88, 0x84, 0x00];
+    /// Non-IDR slice with first_mb_in_slice == 0
+    const P_SLICE: &[u8] = &[0, 0, 0, 1, 0x41, 0x9A, 0x02];
+    /// Second slice of the same picture (first_mb_in_slice != 0)
+    const P_SLICE_CONT: &[u8] = &[0, 0, 0, 1, 0x41, 0x0D, 0x02];
+
+    fn concat(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn test_split_by_first_slice() {
+        let stream = concat(&[SPS, PPS, IDR, P_SLICE, P_SLICE]);
+        let units = split_access_units(&stream);
+        assert_eq!(
+            units,
+            vec![concat(&[SPS, PPS, IDR]), P_SLICE.to_vec(), P_SLICE.to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_split_by_aud() {
+        let stream = concat(&[AUD, SPS, PPS, IDR, AUD, P_SLICE]);
+        let units = split_access_units(&stream);
+        assert_eq!(
+            units,
+            vec![concat(&[AUD, SPS, PPS, IDR]), concat(&[AUD, P_SLICE])]
+        );
+    }
+
+    #[test]
+    fn test_multi_slice_picture_stays_together() {
+        let stream = concat(&[P_SLICE, P_SLICE_CONT, P_SLICE]);
+        let units = split_access_units(&stream);
+        assert_eq!(
+            units,
+            vec![concat(&[P_SLICE, P_SLICE_CONT]), P_SLICE.to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_chunked_input_matches_whole() {
+        let stream = concat(&[AUD, SPS, PPS, IDR, AUD, P_SLICE, P_SLICE_CONT, AUD, P_SLICE]);
+        let expected = split_access_units(&stream);
+
+        // Feed one byte at a time so start codes straddle chunk boundaries
+        let mut splitter = AnnexBSplitter::new();
+        let mut units = Vec::new();
+        for byte in &stream {
+            units.extend(splitter.push(std::slice::from_ref(byte)));
+        }
+        units.extend(splitter.finish());
+
+        assert_eq!(units, expected);
+        assert_eq!(units.len(), 3);
+    }
+
+    #[test]
+    fn test_leading_garbage_and_empty_input() {
+        assert!(split_access_units(&[]).is_empty());
+        assert!(split_access_units(&[0x12, 0x34]).is_empty());
+
+        let stream = concat(&[&[0xFF, 0xEE], IDR]);
+        assert_eq!(split_access_units(&stream), vec![IDR.to_vec()]);
+    }
+
+    #[test]
+    fn test_find_start_codes() {
+        let data = [0, 0, 1, 0x65, 0, 0, 0, 1, 0x41, 0x00, 0x00, 0x01];
+        assert_eq!(find_start_codes(&data), vec![0, 4, 9]);
+    }
+}
@@ -2,21 +2,90 @@
 //!
 //! This crate provides video decoding functionality for the sink application.
 
-use serialwarp_core::{DecodeError, DecodedFrame};
+use ffmpeg_next::codec::{threading, Flags};
+use ffmpeg_next::util::color;
+use ffmpeg_next::Discard;
+use serialwarp_core::{
+    BufferPool, ColorMatrix, ColorPrimaries, ColorSpace, DecodeError, DecodeOutput, DecodedFrame,
+    LatencySei, PlaneLayout, Resolution, SkipMode, TransferFunction, VideoDecoder,
+};
+
+mod annexb;
+mod recorder;
+
+pub use annexb::{split_access_units, AnnexBSplitter};
+pub use recorder::{RecorderStats, StreamRecorder, RECORDER_QUEUE_DEPTH};
+
+/// Most threads a decoder uses when `DecoderConfig::thread_count` is None
+pub const MAX_AUTO_THREADS: usize = 8;
+
+/// How the decoder spreads work over its threads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThreadingMode {
+    /// Threads share the slices of each frame, adding no latency. Only
+    /// helps streams encoded with several slices per frame.
+    #[default]
+    Slice,
+    /// Threads decode consecutive frames at once. Fastest, but every thread
+    /// past the first holds back one more frame before any comes out.
+    Frame,
+    /// Let FFmpeg choose, which is frame threading wherever it can be used
+    Auto,
+}
 
 /// Decoder configuration
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct DecoderConfig {
-    /// Number of threads to use for decoding (None = auto)
+    /// Number of threads to use for decoding (None = one per core, up to
+    /// `MAX_AUTO_THREADS`)
     pub thread_count: Option<usize>,
+    /// How work is split over the threads
+    pub threading: ThreadingMode,
+    /// Output each frame as soon as it's decoded (`AV_CODEC_FLAG_LOW_DELAY`)
+    /// and allow shortcuts that aren't bit exact (`AV_CODEC_FLAG2_FAST`).
+    /// FFmpeg never frame threads with this set, so `ThreadingMode::Frame`
+    /// falls back to one thread.
+    pub low_latency: bool,
+    /// Pool for decoded frame buffers; a frame's buffer is reused once the
+    /// frame and all its clones are dropped
+    pub buffer_pool: BufferPool,
+}
+
+impl Default for DecoderConfig {
+    fn default() -> Self {
+        Self {
+            thread_count: None,
+            threading: ThreadingMode::Slice,
+            low_latency: true,
+            buffer_pool: BufferPool::default(),
+        }
+    }
+}
+
+impl DecoderConfig {
+    /// Threads the decoder will use
+    pub fn resolved_thread_count(&self) -> usize {
+        self.thread_count
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map_or(1, |cores| cores.get())
+                    .min(MAX_AUTO_THREADS)
+            })
+            .max(1)
+    }
 }
 
 /// H.264 video decoder
 pub struct Decoder {
+    config: DecoderConfig,
     decoder: ffmpeg_next::decoder::Video,
     scaler: Option<ffmpeg_next::software::scaling::Context>,
     width: u32,
     height: u32,
+    /// Size of the last frame out, kept across `reset` so a stream that
+    /// comes back at another size still reports the change
+    output_size: Option<Resolution>,
+    skip_mode: SkipMode,
 }
 
 impl Decoder {
@@ -27,37 +96,75 @@ impl Decoder {
         let codec = ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::H264)
             .ok_or(DecodeError::CodecNotFound)?;
 
-        let mut context =
-            ffmpeg_next::codec::Context::new_with_codec(codec).decoder().video().map_err(
-                |e| DecodeError::FfmpegError(format!("Failed to create decoder context: {}", e)),
-            )?;
-
-        // Configure threading
-        if let Some(thread_count) = config.thread_count {
-            unsafe {
-                (*context.as_mut_ptr()).thread_count = thread_count as i32;
-            }
-        }
+        // Threading and flags are read when the codec opens, so they're set
+        // on the context before it becomes a decoder
+        let mut context = ffmpeg_next::codec::Context::new_with_codec(codec);
+        Self::configure(&mut context, &config);
+        let context = context.decoder().video().map_err(|e| {
+            DecodeError::FfmpegError(format!("Failed to create decoder context: {}", e))
+        })?;
 
         Ok(Self {
+            config,
             decoder: context,
             scaler: None,
             width: 0,
             height: 0,
+            output_size: None,
+            skip_mode: SkipMode::None,
         })
     }
 
+    fn configure(context: &mut ffmpeg_next::codec::Context, config: &DecoderConfig) {
+        let count = config.resolved_thread_count();
+        let threads = |kind| {
+            let mut threading = threading::Config::kind(kind);
+            threading.count = count;
+            threading
+        };
+        match config.threading {
+            ThreadingMode::Slice => context.set_threading(threads(threading::Type::Slice)),
+            ThreadingMode::Frame => context.set_threading(threads(threading::Type::Frame)),
+            // ffmpeg-next can only set one thread type at a time
+            ThreadingMode::Auto => unsafe {
+                let context = context.as_mut_ptr();
+                (*context).thread_type =
+                    ffmpeg_next::ffi::FF_THREAD_FRAME | ffmpeg_next::ffi::FF_THREAD_SLICE;
+                (*context).thread_count = count as i32;
+            },
+        }
+
+        if config.low_latency {
+            context.set_flags(Flags::LOW_DELAY);
+            // No safe setter for flags2
+            unsafe {
+                (*context.as_mut_ptr()).flags2 |= ffmpeg_next::ffi::AV_CODEC_FLAG2_FAST as i32;
+            }
+        }
+    }
+
     /// Decode H.264 data and return decoded frames
     ///
     /// May return zero, one, or multiple frames depending on buffering.
-    pub fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<Vec<DecodedFrame>, DecodeError> {
+    /// Frames carry the capture time from the access unit's `LatencySei`,
+    /// if it has one; serialwarp streams have no reordering, so the
+    /// pictures out are the ones this access unit produced. A picture at a
+    /// different size from the last one out comes with a
+    /// `DecodeEvent::ResolutionChanged`.
+    pub fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
         let packet = ffmpeg_next::Packet::copy(data);
 
         self.decoder
             .send_packet(&packet)
             .map_err(|e| DecodeError::DecodingFailed(e.to_string()))?;
 
-        self.receive_frames(pts_us)
+        let mut frames = self.receive_frames(pts_us)?;
+        if let Some(sei) = LatencySei::find(data) {
+            for frame in &mut frames {
+                frame.source_timestamp_us = Some(sei.capture_ts_us);
+            }
+        }
+        Ok(DecodeOutput::tracking_size(frames, &mut self.output_size))
     }
 
     /// Flush the decoder and return any remaining frames
@@ -66,7 +173,39 @@ impl Decoder {
             .send_eof()
             .map_err(|e| DecodeError::DecodingFailed(e.to_string()))?;
 
-        self.receive_frames(0)
+        let frames = self.receive_frames(0)?;
+        if let Some(last) = frames.last() {
+            self.output_size = Some(Resolution::new(last.width, last.height));
+        }
+        Ok(frames)
+    }
+
+    /// Flush the decoder, returning any remaining frames, and start over
+    /// with a fresh one using the same configuration and skip mode. For
+    /// when the stream breaks off, e.g. the source reconnects.
+    pub fn reset(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        let frames = self.flush()?;
+        let (output_size, skip_mode) = (self.output_size, self.skip_mode);
+        *self = Decoder::new(self.config.clone())?;
+        self.output_size = output_size;
+        self.set_skip_mode(skip_mode);
+        Ok(frames)
+    }
+
+    /// Leave frames undecoded from the next access unit on, through
+    /// FFmpeg's `skip_frame`. Skipped frames produce no output.
+    pub fn set_skip_mode(&mut self, mode: SkipMode) {
+        self.skip_mode = mode;
+        self.decoder.skip_frame(match mode {
+            SkipMode::None => Discard::Default,
+            SkipMode::NonRef => Discard::NonReference,
+            SkipMode::NonKey => Discard::NonKey,
+        });
+    }
+
+    /// Frames currently skipped
+    pub fn skip_mode(&self) -> SkipMode {
+        self.skip_mode
     }
 
     fn receive_frames(&mut self, pts_us: i64) -> Result<Vec<DecodedFrame>, DecodeError> {
@@ -89,12 +228,12 @@ impl Decoder {
         let width = frame.width();
         let height = frame.height();
 
-        // Recreate scaler if dimensions changed or if format is not YUV420P
-        let needs_conversion = frame.format() != ffmpeg_next::format::Pixel::YUV420P
-            || self.width != width
-            || self.height != height;
-
-        if needs_conversion {
+        // Only non-YUV420P output (e.g. 4:4:4 or high bit depth streams)
+        // goes through the scaler; recreate it if the size changed
+        let needs_conversion = frame.format() != ffmpeg_next::format::Pixel::YUV420P;
+        if !needs_conversion {
+            self.scaler = None;
+        } else if self.scaler.is_none() || self.width != width || self.height != height {
             self.scaler = Some(
                 ffmpeg_next::software::scaling::Context::get(
                     frame.format(),
@@ -107,70 +246,133 @@ impl Decoder {
                 )
                 .map_err(|e| DecodeError::FfmpegError(format!("Failed to create scaler: {}", e)))?,
             );
-            self.width = width;
-            self.height = height;
         }
+        self.width = width;
+        self.height = height;
 
-        // Convert to YUV420P if needed
-        let yuv_frame = if let Some(ref mut scaler) = self.scaler {
-            let mut output = ffmpeg_next::frame::Video::empty();
-            scaler
-                .run(frame, &mut output)
-                .map_err(|_| DecodeError::ConversionFailed)?;
-            output
-        } else {
-            frame.clone()
+        let converted;
+        let yuv_frame = match self.scaler.as_mut() {
+            Some(scaler) => {
+                let mut output = ffmpeg_next::frame::Video::empty();
+                scaler
+                    .run(frame, &mut output)
+                    .map_err(|_| DecodeError::ConversionFailed)?;
+                converted = output;
+                &converted
+            }
+            None => frame,
         };
 
-        // Copy plane data to contiguous buffer
-        let y_size = (width * height) as usize;
-        let uv_size = y_size / 4;
-        let total_size = y_size + uv_size * 2;
-
-        let mut yuv_data = Vec::with_capacity(total_size);
-
-        // Copy Y plane
-        let y_stride = yuv_frame.stride(0);
-        for row in 0..height as usize {
-            let start = row * y_stride;
-            let end = start + width as usize;
-            yuv_data.extend_from_slice(&yuv_frame.data(0)[start..end]);
-        }
+        // The decoder reuses its frame buffers, so the planes have to be
+        // copied out. Each one is copied whole at its native stride into a
+        // pooled buffer rather than repacked row by row. Chroma rounds up
+        // for odd sizes, as in `DecodedFrame`.
+        let (chroma_width, chroma_height) = ((width as usize + 1) / 2, (height as usize + 1) / 2);
+        let plane_rows = [height as usize, chroma_height, chroma_height];
+        let plane_widths = [width as usize, chroma_width, chroma_width];
+        let mut planes = [PlaneLayout {
+            offset: 0,
+            stride: 0,
+        }; 3];
+        let mut plane_data: [&[u8]; 3] = [&[]; 3];
+        let mut offset = 0;
+        for (index, (layout, plane)) in planes.iter_mut().zip(&mut plane_data).enumerate() {
+            let stride = yuv_frame.stride(index);
+            let len = match plane_rows[index] {
+                0 => 0,
+                rows => stride * (rows - 1) + plane_widths[index],
+            };
+            let data = yuv_frame.data(index);
+            if data.len() < len {
+                return Err(DecodeError::InvalidFrameData);
+            }
 
-        // Copy U plane
-        let u_stride = yuv_frame.stride(1);
-        let uv_height = height as usize / 2;
-        let uv_width = width as usize / 2;
-        for row in 0..uv_height {
-            let start = row * u_stride;
-            let end = start + uv_width;
-            yuv_data.extend_from_slice(&yuv_frame.data(1)[start..end]);
+            *layout = PlaneLayout { offset, stride };
+            *plane = &data[..len];
+            offset += len;
         }
 
-        // Copy V plane
-        let v_stride = yuv_frame.stride(2);
-        for row in 0..uv_height {
-            let start = row * v_stride;
-            let end = start + uv_width;
-            yuv_data.extend_from_slice(&yuv_frame.data(2)[start..end]);
+        let mut buffer = self.config.buffer_pool.get(offset);
+        for plane in plane_data {
+            buffer.extend_from_slice(plane);
         }
 
         // Use frame PTS if available, otherwise use provided pts_us
         let frame_pts = frame.pts().map(|p| p as u64).unwrap_or(pts_us as u64);
 
-        Ok(DecodedFrame::new(
+        let mut decoded = DecodedFrame::from_planes(
             0, // Frame number will be set by caller
             frame_pts,
             width,
             height,
-            yuv_data,
-        ))
+            buffer.freeze(),
+            planes,
+        )?;
+        decoded.color_space = color_space(
+            frame.color_space(),
+            frame.color_range(),
+            frame.color_primaries(),
+            frame.color_transfer_characteristic(),
+        );
+        Ok(decoded)
+    }
+}
+
+/// Map the stream's VUI color description to the ones serialwarp converts
+/// with. Anything unspecified or unsupported falls back to BT.709 limited.
+fn color_space(
+    space: color::Space,
+    range: color::Range,
+    primaries: color::Primaries,
+    transfer: color::TransferCharacteristic,
+) -> ColorSpace {
+    let bt601 = matches!(space, color::Space::BT470BG | color::Space::SMPTE170M);
+    let full_range = range == color::Range::JPEG;
+    let matrix = match (bt601, full_range) {
+        (true, false) => ColorMatrix::Bt601Limited,
+        (true, true) => ColorMatrix::Bt601Full,
+        (false, false) => ColorMatrix::Bt709Limited,
+        (false, true) => ColorMatrix::Bt709Full,
+    };
+    let primaries = match primaries {
+        color::Primaries::BT470BG | color::Primaries::SMPTE170M => ColorPrimaries::Bt601,
+        _ => ColorPrimaries::Bt709,
+    };
+    let transfer = match transfer {
+        color::TransferCharacteristic::IEC61966_2_1 => TransferFunction::Srgb,
+        _ => TransferFunction::Bt709,
+    };
+
+    ColorSpace {
+        primaries,
+        transfer,
+        matrix,
+    }
+}
+
+impl VideoDecoder for Decoder {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        Decoder::decode(self, data, pts_us)
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        Decoder::flush(self)
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        self.reset().map(drop)
+    }
+
+    fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+        Decoder::set_skip_mode(self, mode);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serialwarp_core::DecodeEvent;
 
     #[test]
     fn test_decoder_creation() {
@@ -187,5 +389,183 @@ mod tests {
     fn test_decoder_config_default() {
         let config = DecoderConfig::default();
         assert!(config.thread_count.is_none());
+        assert_eq!(config.threading, ThreadingMode::Slice);
+        assert!(config.low_latency);
+    }
+
+    #[test]
+    fn test_resolved_thread_count() {
+        let auto = DecoderConfig::default().resolved_thread_count();
+        assert!((1..=MAX_AUTO_THREADS).contains(&auto));
+
+        let config = DecoderConfig {
+            thread_count: Some(12),
+            ..Default::default()
+        };
+        assert_eq!(config.resolved_thread_count(), 12);
+        let config = DecoderConfig {
+            thread_count: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(config.resolved_thread_count(), 1);
+    }
+
+    #[test]
+    fn test_context_configured() {
+        use ffmpeg_next::ffi::{AV_CODEC_FLAG2_FAST, FF_THREAD_FRAME, FF_THREAD_SLICE};
+
+        let cases = [
+            (ThreadingMode::Slice, true, FF_THREAD_SLICE),
+            (ThreadingMode::Frame, false, FF_THREAD_FRAME),
+            (ThreadingMode::Auto, true, FF_THREAD_FRAME | FF_THREAD_SLICE),
+        ];
+        for (threading, low_latency, thread_type) in cases {
+            let config = DecoderConfig {
+                thread_count: Some(3),
+                threading,
+                low_latency,
+                ..Default::default()
+            };
+            let decoder = match Decoder::new(config) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    eprintln!("Skipping, no FFmpeg H.264 decoder: {:?}", e);
+                    return;
+                }
+            };
+
+            let context = unsafe { &*decoder.decoder.as_ptr() };
+            assert_eq!(context.thread_type, thread_type, "{threading:?}");
+            assert_eq!(context.thread_count, 3, "{threading:?}");
+            let flags = Flags::from_bits_truncate(context.flags as u32);
+            assert_eq!(flags.contains(Flags::LOW_DELAY), low_latency);
+            let fast = context.flags2 & AV_CODEC_FLAG2_FAST as i32 != 0;
+            assert_eq!(fast, low_latency);
+        }
+    }
+
+    /// Two 32x32 IDRs, then a new SPS and two 64x48 IDRs, all I_PCM
+    const RESOLUTION_SWITCH: &[u8] = include_bytes!("../tests/fixtures/resolution_switch.h264");
+
+    #[test]
+    fn test_resolution_change_reported() {
+        let mut decoder = match Decoder::new(DecoderConfig::default()) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                eprintln!("Skipping, no FFmpeg H.264 decoder: {:?}", e);
+                return;
+            }
+        };
+
+        let mut sizes = Vec::new();
+        let mut events = Vec::new();
+        for (pts, access_unit) in split_access_units(RESOLUTION_SWITCH).iter().enumerate() {
+            let output = decoder.decode(access_unit, pts as i64).unwrap();
+            sizes.extend(output.frames.iter().map(|f| (f.width, f.height)));
+            events.extend(output.events);
+        }
+        sizes.extend(decoder.flush().unwrap().iter().map(|f| (f.width, f.height)));
+
+        assert_eq!(sizes, [(32, 32), (32, 32), (64, 48), (64, 48)]);
+        assert_eq!(
+            events,
+            [DecodeEvent::ResolutionChanged {
+                old: Resolution::new(32, 32),
+                new: Resolution::new(64, 48),
+            }]
+        );
+    }
+
+    /// IDR, P, P, IDR, P at 32x32; the P frames are all skipped macroblocks
+    const KEYFRAMES: &[u8] = include_bytes!("../tests/fixtures/keyframes.h264");
+
+    #[test]
+    fn test_skip_mode() {
+        let cases = [
+            (SkipMode::None, vec![0, 1, 2, 3, 4]),
+            // Every frame is a reference, so none can go
+            (SkipMode::NonRef, vec![0, 1, 2, 3, 4]),
+            (SkipMode::NonKey, vec![0, 3]),
+        ];
+        for (mode, expected) in cases {
+            let mut decoder = match Decoder::new(DecoderConfig::default()) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    eprintln!("Skipping, no FFmpeg H.264 decoder: {:?}", e);
+                    return;
+                }
+            };
+            decoder.set_skip_mode(mode);
+
+            let mut pts = Vec::new();
+            for (index, access_unit) in split_access_units(KEYFRAMES).iter().enumerate() {
+                let output = decoder.decode(access_unit, index as i64).unwrap();
+                pts.extend(output.frames.iter().map(|frame| frame.pts_us));
+            }
+            pts.extend(decoder.flush().unwrap().iter().map(|frame| frame.pts_us));
+            assert_eq!(pts, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_reset_keeps_config_and_output_size() {
+        let config = DecoderConfig {
+            thread_count: Some(2),
+            ..Default::default()
+        };
+        let mut decoder = match Decoder::new(config) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                eprintln!("Skipping, no FFmpeg H.264 decoder: {:?}", e);
+                return;
+            }
+        };
+
+        let access_units = split_access_units(RESOLUTION_SWITCH);
+        decoder.decode(&access_units[0], 0).unwrap();
+        decoder.reset().unwrap();
+        assert_eq!(decoder.config.thread_count, Some(2));
+        assert_eq!(unsafe { (*decoder.decoder.as_ptr()).thread_count }, 2);
+
+        // The fresh decoder still knows the last size out
+        let mut events = Vec::new();
+        for (pts, access_unit) in access_units[2..].iter().enumerate() {
+            events.extend(decoder.decode(access_unit, pts as i64).unwrap().events);
+        }
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn test_color_space_mapping() {
+        let unspecified = color_space(
+            color::Space::Unspecified,
+            color::Range::Unspecified,
+            color::Primaries::Unspecified,
+            color::TransferCharacteristic::Unspecified,
+        );
+        assert_eq!(unspecified, ColorSpace::default());
+
+        let sd_full = color_space(
+            color::Space::SMPTE170M,
+            color::Range::JPEG,
+            color::Primaries::SMPTE170M,
+            color::TransferCharacteristic::IEC61966_2_1,
+        );
+        assert_eq!(
+            sd_full,
+            ColorSpace {
+                primaries: ColorPrimaries::Bt601,
+                transfer: TransferFunction::Srgb,
+                matrix: ColorMatrix::Bt601Full,
+            }
+        );
+
+        let hd = color_space(
+            color::Space::BT709,
+            color::Range::MPEG,
+            color::Primaries::BT709,
+            color::TransferCharacteristic::BT709,
+        );
+        assert_eq!(hd.matrix, ColorMatrix::Bt709Limited);
     }
 }
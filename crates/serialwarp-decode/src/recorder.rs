@@ -0,0 +1,297 @@
+//! Recording of the received H.264 stream to a container file
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use ffmpeg_next::{codec, ffi, format, Packet, Rational};
+use serialwarp_core::{rotated_path, EncodedFrame, RecordError};
+
+/// Frames buffered between the decode path and the writer thread
+pub const RECORDER_QUEUE_DEPTH: usize = 64;
+
+/// Packet timestamps are microseconds, matching frame pts
+const MICROSECONDS: Rational = Rational(1, 1_000_000);
+
+/// Recording counters, returned when the recorder finishes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecorderStats {
+    /// Frames muxed into a file
+    pub frames_written: u64,
+    /// Frames dropped because the writer fell behind
+    pub frames_dropped: u64,
+    /// Files created (a resolution change starts a new file)
+    pub files_written: u64,
+}
+
+enum RecorderMessage {
+    Frame {
+        data: Vec<u8>,
+        pts_us: u64,
+        is_keyframe: bool,
+        width: u32,
+        height: u32,
+    },
+    Finish,
+}
+
+/// Muxes reassembled Annex B access units into an MP4 or MKV file.
+///
+/// Writing happens on a dedicated thread so recording never blocks decoding;
+/// frames that don't fit in the queue are dropped and counted. The container
+/// is chosen from the file extension. Recording starts at the first keyframe,
+/// whose in-band SPS/PPS the muxer uses for the codec configuration.
+pub struct StreamRecorder {
+    sender: SyncSender<RecorderMessage>,
+    thread: Option<JoinHandle<Result<RecorderStats, RecordError>>>,
+    dropped: Arc<AtomicU64>,
+    /// A frame was dropped, so skip until the next keyframe
+    needs_keyframe: bool,
+}
+
+impl StreamRecorder {
+    /// Start a recorder writing to `path`
+    pub fn start(path: impl Into<PathBuf>) -> Result<Self, RecordError> {
+        ffmpeg_next::init().map_err(|e| RecordError::OutputFailed(e.to_string()))?;
+
+        let path = path.into();
+        let (sender, receiver) = mpsc::sync_channel(RECORDER_QUEUE_DEPTH);
+        let thread = std::thread::Builder::new()
+            .name("serialwarp-recorder".to_string())
+            .spawn(move || write_loop(&path, receiver))
+            .map_err(|e| RecordError::OutputFailed(e.to_string()))?;
+
+        Ok(Self {
+            sender,
+            thread: Some(thread),
+            dropped: Arc::new(AtomicU64::new(0)),
+            needs_keyframe: false,
+        })
+    }
+
+    /// Queue a reassembled frame of the given resolution for writing
+    pub fn record(&mut self, frame: &EncodedFrame, width: u32, height: u32) {
+        if self.needs_keyframe && !frame.metadata.is_keyframe {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let message = RecorderMessage::Frame {
+            data: frame.data.to_vec(),
+            pts_us: frame.metadata.pts_us,
+            is_keyframe: frame.metadata.is_keyframe,
+            width,
+            height,
+        };
+
+        match self.sender.try_send(message) {
+            Ok(()) => self.needs_keyframe = false,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                // Later frames reference this one; wait for a clean restart point
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.needs_keyframe = true;
+            }
+        }
+    }
+
+    /// Frames dropped so far
+    pub fn frames_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Flush queued frames, finalize the file and stop the writer thread
+    pub fn finish(mut self) -> Result<RecorderStats, RecordError> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<RecorderStats, RecordError> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(RecorderStats::default());
+        };
+
+        // Blocking send so the finish marker isn't lost behind a full queue
+        let _ = self.sender.send(RecorderMessage::Finish);
+        let mut stats = thread.join().map_err(|_| RecordError::WriterPanicked)??;
+        stats.frames_dropped = self.frames_dropped();
+        Ok(stats)
+    }
+}
+
+impl Drop for StreamRecorder {
+    fn drop(&mut self) {
+        // Finalize the container even if `finish` was never called
+        let _ = self.stop();
+    }
+}
+
+/// An open container file
+struct OutputFile {
+    output: format::context::Output,
+    width: u32,
+    height: u32,
+    first_pts_us: u64,
+}
+
+impl OutputFile {
+    fn create(
+        path: &Path,
+        width: u32,
+        height: u32,
+        first_pts_us: u64,
+    ) -> Result<Self, RecordError> {
+        let mut output =
+            format::output(&path).map_err(|e| RecordError::OutputFailed(e.to_string()))?;
+
+        {
+            let mut stream = output
+                .add_stream(codec::Id::H264)
+                .map_err(|e| RecordError::OutputFailed(e.to_string()))?;
+            stream.set_time_base(MICROSECONDS);
+
+            let mut parameters = codec::Parameters::new();
+            unsafe {
+                let parameters = parameters.as_mut_ptr();
+                (*parameters).codec_type = ffi::AVMediaType::AVMEDIA_TYPE_VIDEO;
+                (*parameters).codec_id = ffi::AVCodecID::AV_CODEC_ID_H264;
+                (*parameters).width = width as i32;
+                (*parameters).height = height as i32;
+            }
+            stream.set_parameters(parameters);
+        }
+
+        output
+            .write_header()
+            .map_err(|e| RecordError::OutputFailed(e.to_string()))?;
+
+        Ok(Self {
+            output,
+            width,
+            height,
+            first_pts_us,
+        })
+    }
+
+    fn write(&mut self, data: &[u8], pts_us: u64, is_keyframe: bool) -> Result<(), RecordError> {
+        // The stream has no B-frames, so decode order matches presentation order
+        let ts = pts_us.saturating_sub(self.first_pts_us) as i64;
+        let time_base = self
+            .output
+            .stream(0)
+            .map(|stream| stream.time_base())
+            .unwrap_or(MICROSECONDS);
+
+        let mut packet = Packet::copy(data);
+        packet.set_stream(0);
+        packet.set_pts(Some(ts));
+        packet.set_dts(Some(ts));
+        if is_keyframe {
+            packet.set_flags(codec::packet::Flags::KEY);
+        }
+        packet.rescale_ts(MICROSECONDS, time_base);
+
+        packet
+            .write_interleaved(&mut self.output)
+            .map_err(|e| RecordError::WriteFailed(e.to_string()))
+    }
+
+    fn close(mut self) -> Result<(), RecordError> {
+        self.output
+            .write_trailer()
+            .map_err(|e| RecordError::WriteFailed(e.to_string()))
+    }
+}
+
+fn write_loop(
+    path: &Path,
+    receiver: Receiver<RecorderMessage>,
+) -> Result<RecorderStats, RecordError> {
+    let mut stats = RecorderStats::default();
+    let mut current: Option<OutputFile> = None;
+
+    // A closed channel (recorder dropped without a marker) also finishes the file
+    while let Ok(RecorderMessage::Frame {
+        data,
+        pts_us,
+        is_keyframe,
+        width,
+        height,
+    }) = receiver.recv()
+    {
+        let resolution_changed = current
+            .as_ref()
+            .is_some_and(|file| file.width != width || file.height != height);
+
+        if resolution_changed {
+            // The old file can't hold the new resolution; start a new one at
+            // the next keyframe
+            if let Some(file) = current.take() {
+                file.close()?;
+            }
+        }
+
+        if current.is_none() {
+            if !is_keyframe {
+                continue;
+            }
+            let file_path = rotated_path(path, stats.files_written as usize);
+            current = Some(OutputFile::create(&file_path, width, height, pts_us)?);
+            stats.files_written += 1;
+        }
+
+        if let Some(file) = current.as_mut() {
+            file.write(&data, pts_us, is_keyframe)?;
+            stats.frames_written += 1;
+        }
+    }
+
+    if let Some(file) = current.take() {
+        file.close()?;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_access_units;
+    use serialwarp_core::{FrameMetadata, FrameReassembler};
+
+    /// IDR, P, P, IDR, P at 32x32
+    const KEYFRAMES: &[u8] = include_bytes!("../tests/fixtures/keyframes.h264");
+
+    #[test]
+    fn test_records_reassembled_frames() {
+        let path =
+            std::env::temp_dir().join(format!("serialwarp-recorder-{}.mkv", std::process::id()));
+        let mut recorder = match StreamRecorder::start(&path) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                eprintln!("Skipping, no FFmpeg muxer: {:?}", e);
+                return;
+            }
+        };
+
+        // As the sink gets them: segmented, sent, and put back together,
+        // with no keyframe flag from the source
+        let mut reassembler = FrameReassembler::new();
+        for (index, access_unit) in split_access_units(KEYFRAMES).into_iter().enumerate() {
+            let metadata = FrameMetadata::new(index as u64, index as u64 * 16_667, 0, false);
+            let mut frame = None;
+            for segment in EncodedFrame::new(metadata, access_unit).into_segments() {
+                let header = segment.header();
+                frame = reassembler.add_segment(&header, segment.data).unwrap();
+            }
+            recorder.record(&frame.unwrap(), 32, 32);
+        }
+
+        let stats = recorder.finish().unwrap();
+        let written = std::fs::metadata(&path).map(|metadata| metadata.len());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(stats.frames_written, 5);
+        assert_eq!(stats.files_written, 1);
+        assert!(written.unwrap() > KEYFRAMES.len() as u64 / 2);
+    }
+}
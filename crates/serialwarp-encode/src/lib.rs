@@ -0,0 +1,77 @@
+//! serialwarp-encode - H.264 video encoders
+//!
+//! This crate provides encoder backend selection for the source pipeline, a
+//! cross-platform software implementation of `VideoEncoder`, and a bounded
+//! async output stream for any encoder.
+
+use serialwarp_core::EncodeError;
+
+pub use serialwarp_core::{DropPolicy, EncoderBackend, EncoderConfig, VideoEncoder};
+
+mod output;
+
+pub use output::{Encoder, EncoderOutput, EncoderStats};
+
+#[cfg(feature = "software")]
+mod software;
+
+#[cfg(feature = "software")]
+pub use software::SoftwareEncoder;
+
+/// Create an encoder for `config.backend`.
+///
+/// `Auto` falls back to the software encoder when the hardware encoder
+/// can't be created.
+pub fn create_encoder(config: EncoderConfig) -> Result<Box<dyn VideoEncoder>, EncodeError> {
+    match config.backend {
+        EncoderBackend::VideoToolbox => create_videotoolbox(&config),
+        EncoderBackend::Software => create_software(config),
+        EncoderBackend::Auto => match create_videotoolbox(&config) {
+            Ok(encoder) => Ok(encoder),
+            Err(_) => create_software(config),
+        },
+    }
+}
+
+fn create_videotoolbox(_config: &EncoderConfig) -> Result<Box<dyn VideoEncoder>, EncodeError> {
+    // The VideoToolbox encoder lives in the macOS capture app
+    Err(EncodeError::BackendUnavailable(
+        "VideoToolbox is not part of this build".to_string(),
+    ))
+}
+
+#[cfg(feature = "software")]
+fn create_software(config: EncoderConfig) -> Result<Box<dyn VideoEncoder>, EncodeError> {
+    Ok(Box::new(SoftwareEncoder::new(config)?))
+}
+
+#[cfg(not(feature = "software"))]
+fn create_software(_config: EncoderConfig) -> Result<Box<dyn VideoEncoder>, EncodeError> {
+    Err(EncodeError::BackendUnavailable(
+        "built without the `software` feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoder_config_default() {
+        let config = EncoderConfig::default();
+        assert_eq!(config.backend, EncoderBackend::Auto);
+        assert_eq!(config.fps, 60);
+    }
+
+    #[test]
+    fn test_videotoolbox_unavailable() {
+        let config = EncoderConfig {
+            backend: EncoderBackend::VideoToolbox,
+            ..Default::default()
+        };
+        assert!(matches!(
+            create_encoder(config),
+            Err(EncodeError::BackendUnavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,453 @@
+//! Bounded, asynchronous encoder output
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use serialwarp_core::{
+    unix_time_us, DropPolicy, EncodeError, EncodedFrame, LatencySei, VideoEncoder,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+/// Encoder output counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderStats {
+    /// Frames produced by the encoder
+    pub frames_encoded: u64,
+    /// Frames delivered to the output stream
+    pub frames_sent: u64,
+    /// Frames discarded because the consumer was behind (or, with
+    /// `ForceKeyframeAfterDrop`, because they followed a discarded frame)
+    pub frames_dropped: u64,
+    /// Keyframes forced to recover from a drop
+    pub keyframes_forced: u64,
+    /// Presentation time between the last keyframe and the latest frame
+    pub us_since_keyframe: u64,
+    /// Times the encoder went more than twice `EncoderConfig::keyframe_interval`
+    /// without a keyframe
+    pub keyframe_interval_misses: u64,
+}
+
+/// Inputs whose capture time is remembered for the latency SEI; more than
+/// any encoder holds back
+const MAX_PENDING_INPUTS: usize = 64;
+
+/// Drives a `VideoEncoder` and delivers its output to an `EncoderOutput`
+/// stream through a channel of `EncoderConfig::output_depth` frames.
+///
+/// With `EncoderConfig::latency_sei` set, each access unit gets a
+/// `LatencySei` holding the wall-clock time its input reached `encode_raw`.
+///
+/// Input is never blocked by a slow consumer; when the channel is full the
+/// encoded frame is dropped according to `EncoderConfig::drop_policy`.
+pub struct Encoder {
+    inner: Box<dyn VideoEncoder>,
+    sender: mpsc::Sender<Result<EncodedFrame, EncodeError>>,
+    policy: DropPolicy,
+    /// Set after a drop: output is discarded until a keyframe gets through,
+    /// and the next input the output has room for is forced to be one
+    awaiting_keyframe: bool,
+    /// Set once a keyframe has been forced, until one comes out of the
+    /// encoder, so a single recovery doesn't force several
+    keyframe_forced: bool,
+    /// Presentation time of the last keyframe out of the encoder
+    last_keyframe_pts_us: Option<u64>,
+    /// Set once the current gap between keyframes has been reported
+    interval_missed: bool,
+    /// Presentation time and capture time of inputs not yet output, for
+    /// the latency SEI
+    pending_inputs: VecDeque<(u64, u64)>,
+    stats: Arc<Mutex<EncoderStats>>,
+}
+
+impl Encoder {
+    /// Wrap `inner`, returning the encoder and the stream of its output
+    pub fn new(inner: Box<dyn VideoEncoder>) -> (Self, EncoderOutput) {
+        let config = inner.config();
+        let (sender, receiver) = mpsc::channel(config.output_depth.max(1));
+        let policy = config.drop_policy;
+
+        let encoder = Self {
+            inner,
+            sender,
+            policy,
+            awaiting_keyframe: false,
+            keyframe_forced: false,
+            last_keyframe_pts_us: None,
+            interval_missed: false,
+            pending_inputs: VecDeque::new(),
+            stats: Arc::new(Mutex::new(EncoderStats::default())),
+        };
+        (encoder, EncoderOutput { receiver })
+    }
+
+    /// Encode a frame in the encoder's input format and queue whatever output
+    /// is ready.
+    ///
+    /// Errors from the underlying encoder are returned and also delivered on
+    /// the output stream.
+    pub fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        if self.inner.config().latency_sei {
+            if self.pending_inputs.len() == MAX_PENDING_INPUTS {
+                self.pending_inputs.pop_front();
+            }
+            self.pending_inputs.push_back((pts_us, unix_time_us()));
+        }
+
+        // While the output is still full the keyframe would only be dropped
+        let recovering =
+            self.awaiting_keyframe && !self.keyframe_forced && self.sender.capacity() > 0;
+        if let Err(e) = self
+            .inner
+            .encode_raw(data, stride, pts_us, force_keyframe || recovering)
+        {
+            let _ = self.sender.try_send(Err(e.clone()));
+            return Err(e);
+        }
+
+        if recovering {
+            self.keyframe_forced = true;
+            self.stats.lock().unwrap().keyframes_forced += 1;
+        }
+        self.send_output()
+    }
+
+    /// Flush the underlying encoder and queue its remaining output
+    pub fn flush(&mut self) -> Result<(), EncodeError> {
+        if let Err(e) = self.inner.flush() {
+            let _ = self.sender.try_send(Err(e.clone()));
+            return Err(e);
+        }
+        self.send_output()
+    }
+
+    /// The underlying encoder, e.g. to change its bitrate
+    pub fn inner_mut(&mut self) -> &mut dyn VideoEncoder {
+        self.inner.as_mut()
+    }
+
+    pub fn stats(&self) -> EncoderStats {
+        *self.stats.lock().unwrap()
+    }
+
+    fn send_output(&mut self) -> Result<(), EncodeError> {
+        let mut stats = self.stats.lock().unwrap();
+
+        while let Some(mut frame) = self.inner.next_frame() {
+            stats.frames_encoded += 1;
+
+            if self.inner.config().latency_sei {
+                // Output may be reordered, so match the input by pts
+                let capture_ts_us = match self
+                    .pending_inputs
+                    .iter()
+                    .position(|&(pts_us, _)| pts_us == frame.metadata.pts_us)
+                {
+                    Some(index) => self.pending_inputs.remove(index).unwrap().1,
+                    None => frame.metadata.capture_ts_us,
+                };
+                let sei = LatencySei::new(capture_ts_us, frame.metadata.frame_number);
+                frame.data = sei.insert_into(&frame.data).into();
+            }
+
+            let pts_us = frame.metadata.pts_us;
+            if frame.metadata.is_keyframe {
+                self.keyframe_forced = false;
+                self.last_keyframe_pts_us = Some(pts_us);
+                self.interval_missed = false;
+                stats.us_since_keyframe = 0;
+            } else if let Some(last) = self.last_keyframe_pts_us {
+                stats.us_since_keyframe = pts_us.saturating_sub(last);
+                let target = self.inner.config().keyframe_interval.as_micros() as u64;
+                if !self.interval_missed && stats.us_since_keyframe > target.saturating_mul(2) {
+                    self.interval_missed = true;
+                    stats.keyframe_interval_misses += 1;
+                    warn!(
+                        "No keyframe for {}ms, more than twice the {}ms interval",
+                        stats.us_since_keyframe / 1000,
+                        target / 1000
+                    );
+                }
+            }
+
+            if self.awaiting_keyframe && !frame.metadata.is_keyframe {
+                stats.frames_dropped += 1;
+                continue;
+            }
+
+            match self.sender.try_send(Ok(frame)) {
+                Ok(()) => {
+                    stats.frames_sent += 1;
+                    self.awaiting_keyframe = false;
+                }
+                Err(TrySendError::Full(_)) => {
+                    stats.frames_dropped += 1;
+                    if self.policy == DropPolicy::ForceKeyframeAfterDrop {
+                        self.awaiting_keyframe = true;
+                    }
+                }
+                Err(TrySendError::Closed(_)) => return Err(EncodeError::OutputClosed),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream of encoded frames from an `Encoder`; ends when the encoder drops
+pub struct EncoderOutput {
+    receiver: mpsc::Receiver<Result<EncodedFrame, EncodeError>>,
+}
+
+impl Stream for EncoderOutput {
+    type Item = Result<EncodedFrame, EncodeError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::{EncoderConfig, NullEncoder};
+    use std::future::poll_fn;
+    use std::time::Duration;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 32;
+
+    fn encoder(depth: usize, policy: DropPolicy) -> (Encoder, EncoderOutput) {
+        Encoder::new(Box::new(NullEncoder::new(EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            keyframe_interval: Duration::from_secs(60),
+            output_depth: depth,
+            drop_policy: policy,
+            ..Default::default()
+        })))
+    }
+
+    fn encode(encoder: &mut Encoder, pts_us: u64) {
+        let bgra = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+        encoder
+            .encode_raw(&bgra, WIDTH as usize * 4, pts_us, false)
+            .unwrap();
+    }
+
+    async fn next(output: &mut EncoderOutput) -> Option<EncodedFrame> {
+        poll_fn(|cx| Pin::new(&mut *output).poll_next(cx))
+            .await
+            .map(Result::unwrap)
+    }
+
+    #[tokio::test]
+    async fn test_drop_and_count() {
+        let (mut encoder, mut output) = encoder(2, DropPolicy::DropAndCount);
+        for pts in 0..10 {
+            encode(&mut encoder, pts);
+        }
+
+        let stats = encoder.stats();
+        assert_eq!(stats.frames_encoded, 10);
+        assert_eq!(stats.frames_sent, 2);
+        assert_eq!(stats.frames_dropped, 8);
+        assert_eq!(stats.keyframes_forced, 0);
+
+        assert_eq!(next(&mut output).await.unwrap().metadata.frame_number, 0);
+        assert_eq!(next(&mut output).await.unwrap().metadata.frame_number, 1);
+
+        // Delivery resumes with whatever comes next, keyframe or not
+        encode(&mut encoder, 10);
+        let frame = next(&mut output).await.unwrap();
+        assert_eq!(frame.metadata.frame_number, 10);
+        assert!(!frame.metadata.is_keyframe);
+    }
+
+    #[tokio::test]
+    async fn test_force_keyframe_after_drop() {
+        let (mut encoder, mut output) = encoder(2, DropPolicy::ForceKeyframeAfterDrop);
+        for pts in 0..10 {
+            encode(&mut encoder, pts);
+        }
+        assert_eq!(encoder.stats().frames_dropped, 8);
+
+        assert!(next(&mut output).await.unwrap().metadata.is_keyframe);
+        assert!(!next(&mut output).await.unwrap().metadata.is_keyframe);
+
+        // The first frame after the gap is a forced keyframe
+        encode(&mut encoder, 10);
+        encode(&mut encoder, 11);
+        let frame = next(&mut output).await.unwrap();
+        assert_eq!(frame.metadata.frame_number, 10);
+        assert!(frame.metadata.is_keyframe);
+        assert!(!next(&mut output).await.unwrap().metadata.is_keyframe);
+        // Only that one, not one per input while the output was full
+        assert_eq!(encoder.stats().keyframes_forced, 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_never_sees_gap_without_keyframe() {
+        let (mut encoder, mut output) = encoder(3, DropPolicy::ForceKeyframeAfterDrop);
+
+        let consumer = tokio::spawn(async move {
+            let mut frames = Vec::new();
+            while let Some(frame) = next(&mut output).await {
+                frames.push(frame.metadata);
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+            frames
+        });
+
+        for pts in 0..200 {
+            encode(&mut encoder, pts);
+            tokio::time::sleep(Duration::from_micros(200)).await;
+        }
+        let stats = encoder.stats();
+        drop(encoder);
+        let frames = consumer.await.unwrap();
+
+        assert!(stats.frames_dropped > 0);
+        assert_eq!(frames.len() as u64, stats.frames_sent);
+        assert_eq!(stats.frames_sent + stats.frames_dropped, 200);
+        for pair in frames.windows(2) {
+            if pair[1].frame_number != pair[0].frame_number + 1 {
+                assert!(
+                    pair[1].is_keyframe,
+                    "gap before frame {}",
+                    pair[1].frame_number
+                );
+            }
+        }
+    }
+
+    /// Passes frames through, only making keyframes when forced, like an
+    /// encoder ignoring its keyframe interval
+    struct StubbornEncoder(NullEncoder);
+
+    impl VideoEncoder for StubbornEncoder {
+        fn encode_raw(
+            &mut self,
+            data: &[u8],
+            stride: usize,
+            pts_us: u64,
+            force_keyframe: bool,
+        ) -> Result<(), EncodeError> {
+            self.0.encode_raw(data, stride, pts_us, force_keyframe)
+        }
+
+        fn next_frame(&mut self) -> Option<EncodedFrame> {
+            let mut frame = self.0.next_frame()?;
+            frame.metadata.is_keyframe = frame.metadata.frame_number == 0;
+            Some(frame)
+        }
+
+        fn flush(&mut self) -> Result<(), EncodeError> {
+            self.0.flush()
+        }
+
+        fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+            self.0.set_bitrate(bitrate)
+        }
+
+        fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+            self.0.reconfigure(config)
+        }
+
+        fn config(&self) -> &EncoderConfig {
+            self.0.config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_time_since_keyframe() {
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            keyframe_interval: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let (mut encoder, _output) = Encoder::new(Box::new(NullEncoder::new(config.clone())));
+        for pts in [0, 50_000, 100_000, 150_000] {
+            encode(&mut encoder, pts);
+        }
+        let stats = encoder.stats();
+        assert_eq!(stats.us_since_keyframe, 50_000);
+        assert_eq!(stats.keyframe_interval_misses, 0);
+
+        // An encoder that ignores the interval is caught past twice of it,
+        // and counted once per gap
+        let (mut encoder, _output) =
+            Encoder::new(Box::new(StubbornEncoder(NullEncoder::new(EncoderConfig {
+                output_depth: 16,
+                ..config
+            }))));
+        for pts in (0..=300_000).step_by(50_000) {
+            encode(&mut encoder, pts);
+        }
+        let stats = encoder.stats();
+        assert_eq!(stats.us_since_keyframe, 300_000);
+        assert_eq!(stats.keyframe_interval_misses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_latency_sei() {
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            latency_sei: true,
+            ..Default::default()
+        };
+        let (mut encoder, mut output) = Encoder::new(Box::new(NullEncoder::new(config.clone())));
+        let before = unix_time_us();
+        encode(&mut encoder, 0);
+        encode(&mut encoder, 16_666);
+
+        for frame_number in 0..2 {
+            let frame = next(&mut output).await.unwrap();
+            let sei = LatencySei::find(&frame.data[8..]).unwrap();
+            assert_eq!(sei.frame_number, frame_number);
+            assert!(sei.capture_ts_us >= before && sei.capture_ts_us <= unix_time_us());
+        }
+        assert!(encoder.pending_inputs.is_empty());
+
+        // Off by default
+        let (mut encoder, mut output) = Encoder::new(Box::new(NullEncoder::new(EncoderConfig {
+            latency_sei: false,
+            ..config
+        })));
+        encode(&mut encoder, 0);
+        let frame = next(&mut output).await.unwrap();
+        assert_eq!(frame.data.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_errors_reach_stream() {
+        let (mut encoder, mut output) = encoder(2, DropPolicy::default());
+        assert!(encoder.encode_raw(&[0; 4], 4, 0, false).is_err());
+
+        let item = poll_fn(|cx| Pin::new(&mut output).poll_next(cx)).await;
+        assert!(matches!(item, Some(Err(EncodeError::InvalidInput(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_closed_output() {
+        let (mut encoder, output) = encoder(2, DropPolicy::default());
+        drop(output);
+
+        let bgra = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+        assert!(matches!(
+            encoder.encode_raw(&bgra, WIDTH as usize * 4, 0, false),
+            Err(EncodeError::OutputClosed)
+        ));
+    }
+}
@@ -0,0 +1,675 @@
+//! libx264 software encoder via FFmpeg
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling;
+use ffmpeg_next::util::color;
+use ffmpeg_next::{codec, encoder, ffi, frame, picture, Dictionary, Packet, Rational};
+use serialwarp_core::{
+    ColorMatrix, ColorPrimaries, EncodeError, EncodedFrame, EncoderConfig, EntropyMode,
+    FrameMetadata, H264Profile, InputPixelFormat, RateControl, TransferFunction, VideoEncoder,
+};
+
+/// Encoder timestamps are microseconds, matching frame pts
+const MICROSECONDS: Rational = Rational(1, 1_000_000);
+
+/// B-frames between references when the config allows them; each one
+/// delays output by a frame
+const MAX_BFRAMES: u32 = 2;
+
+fn ffmpeg_error(e: ffmpeg_next::Error) -> EncodeError {
+    EncodeError::SoftwareEncoderFailed(e.to_string())
+}
+
+/// Software H.264 encoder tuned for low latency: no lookahead and no
+/// B-frames unless the config allows them, Annex B output with SPS/PPS
+/// repeated on every keyframe.
+pub struct SoftwareEncoder {
+    config: EncoderConfig,
+    encoder: encoder::video::Encoder,
+    /// BGRA to YUV conversion; NV12 input goes to libx264 as is
+    scaler: Option<scaling::Context>,
+    output: VecDeque<EncodedFrame>,
+    frame_number: u64,
+}
+
+// The FFmpeg contexts are only ever used from the thread that owns the encoder
+unsafe impl Send for SoftwareEncoder {}
+
+impl SoftwareEncoder {
+    pub fn new(config: EncoderConfig) -> Result<Self, EncodeError> {
+        config.validate()?;
+        ffmpeg_next::init().map_err(ffmpeg_error)?;
+
+        let encoder = open_encoder(&config)?;
+        let scaler = open_scaler(&config)?;
+
+        Ok(Self {
+            config,
+            encoder,
+            scaler,
+            output: VecDeque::new(),
+            frame_number: 0,
+        })
+    }
+
+    /// Move every packet the encoder has ready into the output queue
+    fn drain_packets(&mut self) {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            let Some(data) = packet.data() else {
+                continue;
+            };
+            let pts_us = packet.pts().unwrap_or(0).max(0) as u64;
+            let metadata = FrameMetadata::new(self.frame_number, pts_us, pts_us, packet.is_key());
+            self.output
+                .push_back(EncodedFrame::new(metadata, data.to_vec()));
+            self.frame_number += 1;
+        }
+    }
+}
+
+impl VideoEncoder for SoftwareEncoder {
+    fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        let format = self.config.input_format;
+        format.check_input(data.len(), stride, self.config.width, self.config.height)?;
+
+        let pixel = match format {
+            InputPixelFormat::Bgra => Pixel::BGRA,
+            InputPixelFormat::Nv12 => Pixel::NV12,
+        };
+        let coded = self.config.coded_size();
+        let mut input = frame::Video::new(pixel, coded.width, coded.height);
+        // The planes follow each other in `data`, all at the caller's stride.
+        // Odd sizes are padded out to the coded size by repeating the last
+        // column and row.
+        let visible = format.planes(self.config.width, self.config.height);
+        let padded = format.planes(coded.width, coded.height);
+        let mut offset = 0;
+        for (plane, (&(row_bytes, rows), &(padded_row_bytes, padded_rows))) in
+            visible.iter().zip(&padded).enumerate()
+        {
+            let input_stride = input.stride(plane);
+            let input_data = input.data_mut(plane);
+            for row in 0..padded_rows {
+                let src = &data[offset + row.min(rows - 1) * stride..][..row_bytes];
+                let dst = &mut input_data[row * input_stride..][..padded_row_bytes];
+                dst[..row_bytes].copy_from_slice(src);
+                // At most one pixel of padding, the size of the one before it
+                let pad = padded_row_bytes - row_bytes;
+                dst.copy_within(row_bytes - pad..row_bytes, row_bytes);
+            }
+            offset += rows * stride;
+        }
+
+        let mut yuv = match self.scaler.as_mut() {
+            Some(scaler) => {
+                let mut yuv = frame::Video::empty();
+                scaler.run(&input, &mut yuv).map_err(ffmpeg_error)?;
+                yuv
+            }
+            None => input,
+        };
+        yuv.set_pts(Some(pts_us as i64));
+        if force_keyframe {
+            yuv.set_kind(picture::Type::I);
+        }
+
+        self.encoder.send_frame(&yuv).map_err(ffmpeg_error)?;
+        self.drain_packets();
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Option<EncodedFrame> {
+        self.output.pop_front()
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        self.encoder.send_eof().map_err(ffmpeg_error)?;
+        self.drain_packets();
+
+        // A drained encoder can't take more input; start a fresh one
+        self.encoder = open_encoder(&self.config)?;
+        Ok(())
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+        // FFmpeg hands libx264 the new limits with its next frame, which
+        // retargets the running session without a keyframe
+        let rate_control = self.config.rate_control.with_bitrate(bitrate)?;
+        set_rate_limits(&mut self.encoder, rate_control);
+        self.config.rate_control = rate_control;
+        Ok(())
+    }
+
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+        config.validate()?;
+
+        self.encoder.send_eof().map_err(ffmpeg_error)?;
+        self.drain_packets();
+
+        self.scaler = open_scaler(&config)?;
+        self.encoder = open_encoder(&config)?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+}
+
+fn open_scaler(config: &EncoderConfig) -> Result<Option<scaling::Context>, EncodeError> {
+    if config.input_format == InputPixelFormat::Nv12 {
+        return Ok(None);
+    }
+    let coded = config.coded_size();
+    let mut scaler = scaling::Context::get(
+        Pixel::BGRA,
+        coded.width,
+        coded.height,
+        Pixel::YUV420P,
+        coded.width,
+        coded.height,
+        scaling::Flags::BILINEAR,
+    )
+    .map_err(ffmpeg_error)?;
+
+    // swscale converts with BT.601 limited range unless told otherwise
+    let matrix = config.color_space.matrix;
+    let coefficients = if matrix.is_bt709() {
+        ffi::SWS_CS_ITU709
+    } else {
+        ffi::SWS_CS_ITU601
+    };
+    let status = unsafe {
+        let table = ffi::sws_getCoefficients(coefficients as i32);
+        ffi::sws_setColorspaceDetails(
+            scaler.as_mut_ptr(),
+            table,
+            1,
+            table,
+            matrix.is_full_range() as i32,
+            0,
+            1 << 16,
+            1 << 16,
+        )
+    };
+    if status < 0 {
+        return Err(EncodeError::SoftwareEncoderFailed(format!(
+            "swscale can't convert to {:?}",
+            matrix
+        )));
+    }
+    Ok(Some(scaler))
+}
+
+/// x264's constant rate factor for a 0.0-1.0 quality: 51 is the worst
+/// it has, 0 lossless
+fn crf(quality: f32) -> f32 {
+    (1.0 - quality) * 51.0
+}
+
+/// Set the bitrate limits `rate_control` has, as codec context fields
+/// rather than x264 parameters so `set_bitrate` can change them on a
+/// running encoder
+fn set_rate_limits(video: &mut encoder::video::Video, rate_control: RateControl) {
+    let max_bps = match rate_control {
+        RateControl::ConstantBitrate { bps } => {
+            video.set_bit_rate(bps as usize);
+            bps
+        }
+        RateControl::ConstrainedQuality { max_bps, .. } => max_bps,
+        RateControl::Quality { .. } => return,
+    };
+    // A one second VBV buffer caps the bitrate without smoothing it; x264
+    // ignores a maximum rate without one
+    video.set_max_bit_rate(max_bps as usize);
+    unsafe {
+        (*video.as_mut_ptr()).rc_buffer_size = max_bps.min(i32::MAX as u32) as i32;
+    }
+}
+
+/// Set one of the encoder's private options, naming it if it's rejected
+fn set_option(
+    video: &mut encoder::video::Video,
+    name: &str,
+    value: &str,
+) -> Result<(), EncodeError> {
+    let key = CString::new(name).expect("option names have no NUL");
+    let value = CString::new(value).expect("option values have no NUL");
+    let status = unsafe {
+        ffi::av_opt_set(
+            (*video.as_mut_ptr()).priv_data,
+            key.as_ptr(),
+            value.as_ptr(),
+            0,
+        )
+    };
+    if status < 0 {
+        return Err(EncodeError::PropertySetFailed {
+            property: name.to_string(),
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Set the VUI color description the decoder reads back
+fn set_color_space(video: &mut encoder::video::Video, config: &EncoderConfig) {
+    let color_space = config.color_space;
+    video.set_colorspace(match color_space.matrix {
+        ColorMatrix::Bt601Limited | ColorMatrix::Bt601Full => color::Space::SMPTE170M,
+        ColorMatrix::Bt709Limited | ColorMatrix::Bt709Full => color::Space::BT709,
+    });
+    video.set_color_range(if color_space.matrix.is_full_range() {
+        color::Range::JPEG
+    } else {
+        color::Range::MPEG
+    });
+
+    let primaries = match color_space.primaries {
+        ColorPrimaries::Bt709 => color::Primaries::BT709,
+        ColorPrimaries::Bt601 => color::Primaries::SMPTE170M,
+    };
+    let transfer = match color_space.transfer {
+        TransferFunction::Bt709 => color::TransferCharacteristic::BT709,
+        TransferFunction::Srgb => color::TransferCharacteristic::IEC61966_2_1,
+    };
+    // ffmpeg-next has no setters for these two
+    unsafe {
+        let context = video.as_mut_ptr();
+        (*context).color_primaries = primaries.into();
+        (*context).color_trc = transfer.into();
+    }
+}
+
+fn open_encoder(config: &EncoderConfig) -> Result<encoder::video::Encoder, EncodeError> {
+    let codec = encoder::find_by_name("libx264")
+        .or_else(|| encoder::find(codec::Id::H264))
+        .ok_or_else(|| EncodeError::BackendUnavailable("no H.264 encoder in FFmpeg".to_string()))?;
+
+    let mut video = codec::Context::new_with_codec(codec)
+        .encoder()
+        .video()
+        .map_err(ffmpeg_error)?;
+    let coded = config.coded_size();
+    video.set_width(coded.width);
+    video.set_height(coded.height);
+    video.set_format(match config.input_format {
+        InputPixelFormat::Bgra => Pixel::YUV420P,
+        InputPixelFormat::Nv12 => Pixel::NV12,
+    });
+    video.set_time_base(MICROSECONDS);
+    video.set_frame_rate(Some(Rational(config.fps as i32, 1)));
+    set_rate_limits(&mut video, config.rate_control);
+    if let Some(quality) = config.rate_control.quality() {
+        set_option(&mut video, "crf", &format!("{:.1}", crf(quality)))?;
+    }
+    video.set_gop(config.keyframe_interval_frames());
+    set_color_space(&mut video, config);
+
+    let bframes = if config.bframes_enabled() {
+        config
+            .max_frame_delay
+            .unwrap_or(MAX_BFRAMES)
+            .min(MAX_BFRAMES)
+    } else {
+        0
+    };
+    video.set_max_b_frames(bframes as usize);
+    let profile = match config.profile {
+        H264Profile::Baseline => "baseline",
+        H264Profile::Main => "main",
+        H264Profile::High => "high",
+    };
+    set_option(&mut video, "profile", profile)?;
+    if let Some(entropy) = config.entropy_mode() {
+        let coder = match entropy {
+            EntropyMode::Cavlc => "cavlc",
+            EntropyMode::Cabac => "cabac",
+        };
+        set_option(&mut video, "coder", coder)?;
+    }
+
+    let mut options = Dictionary::new();
+    options.set("preset", "ultrafast");
+    options.set("tune", "zerolatency");
+    // Annex B start codes, with SPS/PPS repeated before every IDR
+    options.set("x264-params", "annexb=1:repeat-headers=1");
+
+    video.open_with(options).map_err(ffmpeg_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::{ColorSpace, EntropyMode, H264Profile};
+    use serialwarp_decode::{Decoder, DecoderConfig};
+    use serialwarp_testsrc::{
+        read_counter, FrameSource, TestPatternConfig, TestPatternSource, COUNTER_BLOCK,
+    };
+
+    const WIDTH: u32 = 640;
+    const HEIGHT: u32 = 360;
+
+    #[test]
+    fn test_software_roundtrip() {
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 30,
+            rate_control: RateControl::ConstantBitrate { bps: 4_000_000 },
+            ..Default::default()
+        };
+        let mut encoder = match SoftwareEncoder::new(config) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                // FFmpeg may be built without libx264; nothing to test then
+                eprintln!("Software encoder unavailable: {}", e);
+                return;
+            }
+        };
+        let mut source = TestPatternSource::new(TestPatternConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 30,
+            paced: false,
+        })
+        .unwrap();
+        let mut decoder = Decoder::new(DecoderConfig::default()).unwrap();
+
+        let mut encoded = Vec::new();
+        for i in 0..5 {
+            let frame = source.next_frame().unwrap();
+            encoder
+                .encode_raw(&frame.data, frame.stride, frame.pts_us, i == 0)
+                .unwrap();
+            encoded.extend(std::iter::from_fn(|| encoder.next_frame()));
+        }
+        encoder.flush().unwrap();
+        encoded.extend(std::iter::from_fn(|| encoder.next_frame()));
+
+        assert_eq!(encoded.len(), 5);
+        assert!(encoded[0].metadata.is_keyframe);
+        assert!(serialwarp_core::contains_idr(&encoded[0].data));
+        assert!(
+            encoded[0].data.starts_with(&[0, 0, 0, 1]) || encoded[0].data.starts_with(&[0, 0, 1])
+        );
+
+        let mut decoded = Vec::new();
+        for frame in &encoded {
+            decoded.extend(
+                decoder
+                    .decode(&frame.data, frame.metadata.pts_us as i64)
+                    .unwrap()
+                    .frames,
+            );
+        }
+        decoded.extend(decoder.flush().unwrap());
+        assert_eq!(decoded.len(), 5);
+
+        for (expected, frame) in decoded.iter().enumerate() {
+            assert_eq!((frame.width, frame.height), (WIDTH, HEIGHT));
+
+            let luma = |x: u32, y: u32| frame.y_plane()[y as usize * frame.y_stride() + x as usize];
+            assert_eq!(read_counter(luma), expected as u32);
+
+            // 75% white bar (limited range luma ~180), below the counter strip
+            let bar = luma(10, COUNTER_BLOCK * 4);
+            assert!((165..=195).contains(&bar), "bar luma {}", bar);
+        }
+    }
+
+    #[test]
+    fn test_color_space_signaled() {
+        let color_space = ColorSpace {
+            primaries: ColorPrimaries::Bt601,
+            transfer: TransferFunction::Srgb,
+            matrix: ColorMatrix::Bt601Full,
+        };
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 30,
+            color_space,
+            ..Default::default()
+        };
+        let mut encoder = match SoftwareEncoder::new(config) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                eprintln!("Software encoder unavailable: {}", e);
+                return;
+            }
+        };
+        let mut source = TestPatternSource::new(TestPatternConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 30,
+            paced: false,
+        })
+        .unwrap();
+        let mut decoder = Decoder::new(DecoderConfig::default()).unwrap();
+
+        let frame = source.next_frame().unwrap();
+        encoder
+            .encode_raw(&frame.data, frame.stride, frame.pts_us, true)
+            .unwrap();
+        encoder.flush().unwrap();
+        let encoded = encoder.next_frame().unwrap();
+
+        let mut decoded = decoder.decode(&encoded.data, 0).unwrap().frames;
+        decoded.extend(decoder.flush().unwrap());
+        assert_eq!(decoded[0].color_space, color_space);
+    }
+
+    #[test]
+    fn test_each_profile_opens() {
+        for profile in [H264Profile::Baseline, H264Profile::Main, H264Profile::High] {
+            for (allow_bframes, entropy) in [(false, None), (true, Some(EntropyMode::Cabac))] {
+                let config = EncoderConfig {
+                    width: WIDTH,
+                    height: HEIGHT,
+                    profile,
+                    allow_bframes,
+                    entropy,
+                    max_frame_delay: Some(1),
+                    ..Default::default()
+                };
+                if let Err(e) = SoftwareEncoder::new(config) {
+                    // Nothing to test without libx264
+                    if matches!(e, EncodeError::BackendUnavailable(_)) {
+                        return;
+                    }
+                    panic!("{:?} with B-frames {}: {}", profile, allow_bframes, e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quality_modes() {
+        for rate_control in [
+            RateControl::Quality { value: 0.7 },
+            RateControl::ConstrainedQuality {
+                value: 0.7,
+                max_bps: 2_000_000,
+            },
+        ] {
+            let config = EncoderConfig {
+                width: WIDTH,
+                height: HEIGHT,
+                rate_control,
+                ..Default::default()
+            };
+            let mut encoder = match SoftwareEncoder::new(config) {
+                Ok(encoder) => encoder,
+                Err(e) => {
+                    eprintln!("Software encoder unavailable: {}", e);
+                    return;
+                }
+            };
+            let frame = vec![0x80; WIDTH as usize * HEIGHT as usize * 4];
+            encoder
+                .encode_raw(&frame, WIDTH as usize * 4, 0, true)
+                .unwrap();
+
+            let changed = encoder.set_bitrate(1_000_000);
+            assert_eq!(changed.is_ok(), rate_control.bitrate().is_some());
+            if changed.is_ok() {
+                assert_eq!(encoder.config().rate_control.bitrate(), Some(1_000_000));
+            }
+
+            // The running session is retargeted rather than restarted, so
+            // the next frame needs no keyframe
+            encoder
+                .encode_raw(&frame, WIDTH as usize * 4, 33_333, false)
+                .unwrap();
+            encoder.flush().unwrap();
+            assert!(encoder.next_frame().unwrap().metadata.is_keyframe);
+            assert!(!encoder.next_frame().unwrap().metadata.is_keyframe);
+        }
+    }
+
+    #[test]
+    fn test_nv12_input() {
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            input_format: InputPixelFormat::Nv12,
+            ..Default::default()
+        };
+        let mut encoder = match SoftwareEncoder::new(config) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                eprintln!("Software encoder unavailable: {}", e);
+                return;
+            }
+        };
+
+        // Padded rows: a luma plane at 120 over neutral chroma
+        let stride = WIDTH as usize + 64;
+        let luma_len = stride * HEIGHT as usize;
+        let mut frame = vec![0x80; luma_len + stride * HEIGHT as usize / 2];
+        frame[..luma_len].fill(120);
+        encoder.encode_raw(&frame, stride, 0, true).unwrap();
+        assert!(encoder
+            .encode_raw(&frame[..luma_len], stride, 1, false)
+            .is_err());
+        encoder.flush().unwrap();
+        let encoded = encoder.next_frame().unwrap();
+        assert!(encoded.metadata.is_keyframe);
+
+        let mut decoder = Decoder::new(DecoderConfig::default()).unwrap();
+        let mut decoded = decoder.decode(&encoded.data, 0).unwrap().frames;
+        decoded.extend(decoder.flush().unwrap());
+        let frame = &decoded[0];
+        assert_eq!((frame.width, frame.height), (WIDTH, HEIGHT));
+        let luma = frame.y_plane()[frame.y_stride() * 100 + 100];
+        assert!((115..=125).contains(&luma), "luma {}", luma);
+    }
+
+    #[test]
+    fn test_odd_sizes_padded() {
+        for (width, height) in [(1366, 768), (1367, 769), (1, 1)] {
+            let config = EncoderConfig {
+                width,
+                height,
+                ..Default::default()
+            };
+            let mut encoder = match SoftwareEncoder::new(config) {
+                Ok(encoder) => encoder,
+                Err(e) => {
+                    eprintln!("Software encoder unavailable: {}", e);
+                    return;
+                }
+            };
+            // Mid-gray with a white last column and row, which the padding
+            // should repeat
+            let stride = width as usize * 4;
+            let mut frame = vec![0x80; stride * height as usize];
+            for row in frame.chunks_exact_mut(stride) {
+                row[stride - 4..].fill(0xFF);
+            }
+            frame[stride * (height as usize - 1)..].fill(0xFF);
+            encoder.encode_raw(&frame, stride, 0, true).unwrap();
+            encoder.flush().unwrap();
+            let encoded = encoder.next_frame().unwrap();
+
+            let mut decoder = Decoder::new(DecoderConfig::default()).unwrap();
+            let mut decoded = decoder.decode(&encoded.data, 0).unwrap().frames;
+            decoded.extend(decoder.flush().unwrap());
+            let frame = &decoded[0];
+            let coded = (width + width % 2, height + height % 2);
+            assert_eq!((frame.width, frame.height), coded);
+
+            let corner =
+                frame.y_plane()[frame.y_stride() * (coded.1 as usize - 1) + coded.0 as usize - 1];
+            assert!(corner > 200, "{}x{} padding luma {}", width, height, corner);
+        }
+    }
+
+    #[test]
+    fn test_create_encode_drop_loop() {
+        // Dropping an encoder with frames still inside must free everything
+        // and leave nothing behind for the next; run under AddressSanitizer
+        // to catch what this alone can't
+        let config = EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            ..Default::default()
+        };
+        let frame = vec![0x80; WIDTH as usize * HEIGHT as usize * 4];
+        for i in 0..32u64 {
+            let mut encoder = match SoftwareEncoder::new(config.clone()) {
+                Ok(encoder) => encoder,
+                Err(e) => {
+                    eprintln!("Software encoder unavailable: {}", e);
+                    return;
+                }
+            };
+            for n in 0..3 {
+                encoder
+                    .encode_raw(&frame, WIDTH as usize * 4, n * 33_333, n == 0)
+                    .unwrap();
+            }
+            // Every other one is dropped without taking its output
+            if i % 2 == 0 {
+                encoder.flush().unwrap();
+                assert!(encoder.next_frame().unwrap().metadata.is_keyframe);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_zero_size() {
+        let config = EncoderConfig {
+            width: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            SoftwareEncoder::new(config),
+            Err(EncodeError::InvalidResolution { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_quality_out_of_range() {
+        let config = EncoderConfig {
+            rate_control: RateControl::Quality { value: 1.2 },
+            ..Default::default()
+        };
+        assert!(matches!(
+            SoftwareEncoder::new(config),
+            Err(EncodeError::InvalidConfiguration(_))
+        ));
+    }
+}
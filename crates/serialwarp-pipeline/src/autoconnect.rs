@@ -0,0 +1,352 @@
+//! Connecting automatically when a supported device is plugged in
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serialwarp_transport::{DeviceEvent, DeviceWatch};
+
+/// First retry delay after a failed attempt
+pub const DEFAULT_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+
+/// Longest delay between attempts
+pub const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Delay before each connection attempt: none at first, then doubling from
+/// `initial` with each consecutive failure up to `max`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(DEFAULT_BACKOFF_INITIAL, DEFAULT_BACKOFF_MAX)
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            failures: 0,
+        }
+    }
+
+    /// Consecutive failed attempts
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// How long to wait before the next attempt
+    pub fn delay(&self) -> Duration {
+        match self.failures {
+            0 => Duration::ZERO,
+            failures => self
+                .initial
+                .checked_mul(1 << (failures - 1).min(31))
+                .map_or(self.max, |delay| delay.min(self.max)),
+        }
+    }
+
+    /// Record a failed attempt, returning the delay before the next one
+    pub fn fail(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.delay()
+    }
+
+    /// Record a successful attempt
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Reports whether a supported device is plugged in
+#[async_trait]
+pub trait DeviceWatcher: Send {
+    /// Whether a supported device is attached now
+    fn is_attached(&self) -> bool;
+
+    /// Wait until a supported device is attached, returning at once if one
+    /// already is. `false` if the watcher has stopped.
+    async fn wait_attached(&mut self) -> bool;
+}
+
+#[async_trait]
+impl DeviceWatcher for DeviceWatch {
+    fn is_attached(&self) -> bool {
+        DeviceWatch::is_attached(self)
+    }
+
+    async fn wait_attached(&mut self) -> bool {
+        while !DeviceWatch::is_attached(self) {
+            match self.next_event().await {
+                Some(DeviceEvent::Attached(_)) => return true,
+                Some(DeviceEvent::Detached(_)) => {}
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Progress of `auto_connect`, for status reporting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoConnectEvent {
+    /// Waiting to retry after a failure
+    Waiting {
+        delay: Duration,
+    },
+    /// Attempting to connect; `attempt` counts from 1 since the last success
+    Connecting {
+        attempt: u32,
+    },
+    Connected,
+    /// The attempt failed; the next one waits `retry_in`
+    Failed {
+        error: String,
+        retry_in: Duration,
+    },
+}
+
+/// Wait for a supported device and call `connect` until it succeeds,
+/// backing off after each failure so a device that can't be opened isn't
+/// retried in a tight loop. The backoff carries over between calls and is
+/// only reset by a success.
+///
+/// Returns what `connect` produced, or `None` if the watcher stops first.
+pub async fn auto_connect<W, F, Fut, T, E>(
+    watcher: &mut W,
+    backoff: &mut Backoff,
+    mut connect: F,
+    mut on_event: impl FnMut(AutoConnectEvent),
+) -> Option<T>
+where
+    W: DeviceWatcher + ?Sized,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    loop {
+        if !watcher.wait_attached().await {
+            return None;
+        }
+
+        let delay = backoff.delay();
+        if !delay.is_zero() {
+            on_event(AutoConnectEvent::Waiting { delay });
+            tokio::time::sleep(delay).await;
+            if !watcher.is_attached() {
+                continue;
+            }
+        }
+
+        on_event(AutoConnectEvent::Connecting {
+            attempt: backoff.failures() + 1,
+        });
+        match connect().await {
+            Ok(connected) => {
+                backoff.reset();
+                on_event(AutoConnectEvent::Connected);
+                return Some(connected);
+            }
+            Err(e) => {
+                let retry_in = backoff.fail();
+                tracing::warn!("Auto-connect failed: {}; retrying in {:?}", e, retry_in);
+                on_event(AutoConnectEvent::Failed {
+                    error: e.to_string(),
+                    retry_in,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    /// Plugged in for each of `plugged`, given as (from, until) seconds
+    /// after `start`; the watch ends after the last
+    struct MockWatcher {
+        start: Instant,
+        plugged: Vec<(u64, u64)>,
+    }
+
+    impl MockWatcher {
+        fn new(plugged: impl IntoIterator<Item = (u64, u64)>) -> Self {
+            Self {
+                start: Instant::now(),
+                plugged: plugged.into_iter().collect(),
+            }
+        }
+
+        fn at(&self, secs: u64) -> Instant {
+            self.start + Duration::from_secs(secs)
+        }
+    }
+
+    #[async_trait]
+    impl DeviceWatcher for MockWatcher {
+        fn is_attached(&self) -> bool {
+            let now = Instant::now();
+            self.plugged
+                .iter()
+                .any(|&(from, until)| self.at(from) <= now && now < self.at(until))
+        }
+
+        async fn wait_attached(&mut self) -> bool {
+            let now = Instant::now();
+            let next = self
+                .plugged
+                .iter()
+                .find(|&&(_, until)| now < self.at(until))
+                .map(|&(from, _)| self.at(from));
+            match next {
+                Some(from) => {
+                    tokio::time::sleep_until(from).await;
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(backoff.delay(), Duration::ZERO);
+
+        let delays: Vec<u64> = (0..6).map(|_| backoff.fail().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(backoff.failures(), 6);
+
+        backoff.reset();
+        assert_eq!(backoff.delay(), Duration::ZERO);
+        assert_eq!(backoff.fail(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_backoff_never_overflows() {
+        let mut backoff = Backoff::default();
+        for _ in 0..100 {
+            backoff.fail();
+        }
+        assert_eq!(backoff.delay(), DEFAULT_BACKOFF_MAX);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_with_backoff_until_connected() {
+        let mut watcher = MockWatcher::new([(0, 3600)]);
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(4));
+        let mut events = Vec::new();
+        let mut attempts = Vec::new();
+        let start = watcher.start;
+
+        let result = auto_connect(
+            &mut watcher,
+            &mut backoff,
+            || {
+                attempts.push(start.elapsed().as_secs());
+                let attempt = attempts.len();
+                async move {
+                    if attempt < 5 {
+                        Err("device busy")
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            |event| events.push(event),
+        )
+        .await;
+
+        assert_eq!(result, Some(5));
+        // Waits of 1, 2, 4 and then the 4s cap between attempts
+        assert_eq!(attempts, [0, 1, 3, 7, 11]);
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(
+            events[..3],
+            [
+                AutoConnectEvent::Connecting { attempt: 1 },
+                AutoConnectEvent::Failed {
+                    error: "device busy".to_string(),
+                    retry_in: Duration::from_secs(1),
+                },
+                AutoConnectEvent::Waiting {
+                    delay: Duration::from_secs(1)
+                },
+            ]
+        );
+        assert_eq!(events.last(), Some(&AutoConnectEvent::Connected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_for_replug() {
+        // Unplugged during the wait after the first failure, plugged back
+        // in at 10s
+        let mut watcher = MockWatcher::new([(0, 1), (10, 3600)]);
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(60));
+        let mut attempts = Vec::new();
+        let start = watcher.start;
+
+        let result = auto_connect(
+            &mut watcher,
+            &mut backoff,
+            || {
+                attempts.push(start.elapsed().as_secs());
+                let attempt = attempts.len();
+                async move {
+                    match attempt {
+                        1 => Err("no response"),
+                        _ => Ok(()),
+                    }
+                }
+            },
+            |_| {},
+        )
+        .await;
+
+        assert_eq!(result, Some(()));
+        // The backoff delay is still served after the replug
+        assert_eq!(attempts, [0, 12]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ends_when_watcher_stops() {
+        let mut watcher = MockWatcher::new([(0, 1)]);
+        let mut backoff = Backoff::default();
+        let mut attempts = 0;
+
+        let result = auto_connect(
+            &mut watcher,
+            &mut backoff,
+            || {
+                attempts += 1;
+                async { Err::<(), _>("no response") }
+            },
+            |_| {},
+        )
+        .await;
+
+        assert_eq!(result, None);
+        assert_eq!(attempts, 1);
+        // The failure still counts towards the next call
+        assert_eq!(backoff.failures(), 1);
+
+        let mut unplugged = MockWatcher::new([]);
+        let result = auto_connect(
+            &mut unplugged,
+            &mut backoff,
+            || async { Ok::<_, String>(()) },
+            |_| panic!("no events without a device"),
+        )
+        .await;
+        assert_eq!(result, None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Both ends of the credit window: the source's credits and the sink's
+//! policy for returning them
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+/// The source's credits. Frames take them without waiting, and anything
+/// that needs to know when they run out or come back waits on the gate.
+#[derive(Debug, Default)]
+pub(crate) struct CreditGate {
+    /// Negative while the sink has shrunk the window below what's already
+    /// in flight, until enough acks come back
+    credits: AtomicI64,
+    returned: Notify,
+    exhausted: Notify,
+}
+
+impl CreditGate {
+    pub fn new(credits: i64) -> Self {
+        Self {
+            credits: AtomicI64::new(credits),
+            ..Default::default()
+        }
+    }
+
+    /// Take one credit if any are available
+    pub fn try_take(&self) -> bool {
+        let taken = self
+            .credits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |credits| {
+                (credits > 0).then_some(credits - 1)
+            })
+            .is_ok();
+        if !taken {
+            self.exhausted.notify_waiters();
+        }
+        taken
+    }
+
+    /// Add `credits`, or take them away if negative, waking anything
+    /// waiting for them if that leaves some available
+    pub fn add(&self, credits: i64) {
+        if self.credits.fetch_add(credits, Ordering::AcqRel) + credits > 0 {
+            self.returned.notify_waiters();
+        }
+    }
+
+    pub fn available(&self) -> i64 {
+        self.credits.load(Ordering::Acquire)
+    }
+
+    /// Wait until a frame is refused a credit
+    pub async fn exhausted(&self) {
+        self.exhausted.notified().await;
+    }
+
+    /// Wait up to `timeout` for credits to be available, returning whether
+    /// they are
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let returned = self.returned.notified();
+            tokio::pin!(returned);
+            // Registered before checking, so credits added in between
+            // still wake it
+            returned.as_mut().enable();
+            if self.available() > 0 {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, returned).await.is_err() {
+                return self.available() > 0;
+            }
+        }
+    }
+}
+
+/// How the sink returns credits. Each acked frame's credits go back at once
+/// while the decoded queue has room; while it's full they're withheld, up
+/// to a limit, and granted back once the consumer has drained it to half.
+/// The sink also counts the credits it thinks the source holds, from the
+/// window it granted, what it has returned and the frames that used them.
+#[derive(Debug)]
+pub(crate) struct CreditGrants {
+    /// Credits the source holds, as far as the sink can tell
+    source_credits: i64,
+    withheld: u32,
+    /// Most credits withheld at once; 0 never withholds any
+    max_withheld: u32,
+    /// Times `source_credits` ran out
+    starvations: u64,
+}
+
+impl CreditGrants {
+    pub fn new(initial_credits: u16, max_withheld: u32) -> Self {
+        Self {
+            source_credits: initial_credits as i64,
+            withheld: 0,
+            // Always leave the source a credit to send with
+            max_withheld: max_withheld.min(initial_credits.saturating_sub(1) as u32),
+            starvations: 0,
+        }
+    }
+
+    /// Start over with a new stream's window, keeping the counters
+    pub fn reset(&mut self, initial_credits: u16, max_withheld: u32) {
+        let starvations = self.starvations;
+        *self = Self::new(initial_credits, max_withheld);
+        self.starvations = starvations;
+    }
+
+    /// A frame used up one of the source's credits, whether it arrived or
+    /// was given up on
+    pub fn frame_used(&mut self) {
+        self.source_credits -= 1;
+        if self.source_credits == 0 {
+            self.starvations += 1;
+        }
+    }
+
+    /// Of `credits` being acked, how many to return now, with room for
+    /// `headroom` more frames in the decoded queue. The rest are withheld.
+    pub fn ack(&mut self, credits: u16, headroom: usize) -> u16 {
+        let withhold = if headroom == 0 {
+            (self.max_withheld - self.withheld).min(credits as u32) as u16
+        } else {
+            0
+        };
+        self.withheld += withhold as u32;
+        let returned = credits - withhold;
+        self.source_credits += returned as i64;
+        returned
+    }
+
+    /// Credits to grant back now that the decoded queue has room for
+    /// `headroom` of its `depth` frames, if it has drained enough
+    pub fn drained(&self, headroom: usize, depth: usize) -> Option<u32> {
+        (self.withheld > 0 && headroom * 2 >= depth).then_some(self.withheld)
+    }
+
+    /// The window was resized by `delta`; growing it grants back withheld
+    /// credits first
+    pub fn updated(&mut self, delta: i32) {
+        self.source_credits += delta as i64;
+        if delta > 0 {
+            self.withheld = self.withheld.saturating_sub(delta as u32);
+        }
+    }
+
+    pub fn source_credits(&self) -> i64 {
+        self.source_credits
+    }
+
+    pub fn withheld(&self) -> u32 {
+        self.withheld
+    }
+
+    pub fn starvations(&self) -> u64 {
+        self.starvations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gate_wakes_on_returned_credits() {
+        let gate = Arc::new(CreditGate::new(1));
+        assert!(gate.try_take());
+        assert!(!gate.try_take());
+        assert!(!gate.wait(Duration::from_millis(100)).await);
+
+        let returned = tokio::spawn({
+            let gate = Arc::clone(&gate);
+            async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                gate.add(1);
+            }
+        });
+        let start = tokio::time::Instant::now();
+        assert!(gate.wait(Duration::from_secs(1)).await);
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+        returned.await.unwrap();
+        assert!(gate.try_take());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gate_waits_out_shrunk_window() {
+        let gate = Arc::new(CreditGate::new(2));
+        // Shrunk below what's in flight, so one credit back isn't enough
+        gate.add(-4);
+        let waiting = tokio::spawn({
+            let gate = Arc::clone(&gate);
+            async move { gate.wait(Duration::from_secs(1)).await }
+        });
+        tokio::task::yield_now().await;
+        gate.add(1);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        gate.add(2);
+        assert!(waiting.await.unwrap());
+        assert_eq!(gate.available(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gate_reports_exhaustion() {
+        let gate = Arc::new(CreditGate::new(0));
+        let exhausted = tokio::spawn({
+            let gate = Arc::clone(&gate);
+            async move { gate.exhausted().await }
+        });
+        while !exhausted.is_finished() {
+            assert!(!gate.try_take());
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn test_grants_return_credits_with_room() {
+        let mut grants = CreditGrants::new(8, 3);
+        for _ in 0..4 {
+            grants.frame_used();
+        }
+        assert_eq!(grants.ack(4, 2), 4);
+        assert_eq!(grants.source_credits(), 8);
+        assert_eq!(grants.withheld(), 0);
+        assert_eq!(grants.drained(3, 3), None);
+    }
+
+    #[test]
+    fn test_grants_withhold_while_full() {
+        let mut grants = CreditGrants::new(8, 3);
+        for _ in 0..5 {
+            grants.frame_used();
+        }
+        // Full queue: withheld up to the limit, the rest returned
+        assert_eq!(grants.ack(2, 0), 0);
+        assert_eq!(grants.ack(2, 0), 1);
+        assert_eq!(grants.ack(1, 0), 1);
+        assert_eq!(grants.withheld(), 3);
+        assert_eq!(grants.source_credits(), 5);
+
+        // Not drained enough yet, then granted back all at once
+        assert_eq!(grants.drained(1, 4), None);
+        assert_eq!(grants.drained(2, 4), Some(3));
+        grants.updated(3);
+        assert_eq!(grants.withheld(), 0);
+        assert_eq!(grants.source_credits(), 8);
+        assert_eq!(grants.drained(4, 4), None);
+    }
+
+    #[test]
+    fn test_grants_leave_source_a_credit() {
+        let mut grants = CreditGrants::new(2, 10);
+        grants.frame_used();
+        grants.frame_used();
+        assert_eq!(grants.starvations(), 1);
+        assert_eq!(grants.ack(2, 0), 1);
+        assert_eq!(grants.source_credits(), 1);
+
+        // Never withholding disables the policy
+        let mut grants = CreditGrants::new(8, 0);
+        grants.frame_used();
+        assert_eq!(grants.ack(1, 0), 1);
+        assert_eq!(grants.withheld(), 0);
+    }
+
+    #[test]
+    fn test_grants_reset_keeps_counters() {
+        let mut grants = CreditGrants::new(1, 0);
+        grants.frame_used();
+        grants.reset(4, 2);
+        assert_eq!(grants.starvations(), 1);
+        assert_eq!(grants.source_credits(), 4);
+    }
+}
@@ -0,0 +1,801 @@
+//! HELLO/START handshake, from either end
+
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serialwarp_core::{
+    capabilities, metrics, HelloPayload, Packet, PacketType, PeerIdentity, PipelineError,
+    ProtocolError, Resolution, ResumeToken, StartAckPayload, StartPayload, MAX_STREAMS,
+};
+use serialwarp_transport::Transport;
+use tracing::{info, warn};
+
+/// How long a sink keeps a session for its source to resume, from when
+/// the source left
+pub const DEFAULT_RESUME_EXPIRY: Duration = Duration::from_secs(30);
+
+/// What the source advertises and requests during the handshake
+#[derive(Debug, Clone)]
+pub struct SourceHandshake {
+    pub software_version: u16,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_fps: u32,
+    /// HELLO capability bits, see `capabilities`
+    pub capabilities: u32,
+    /// Stream requested in START. This is the visible size in pixels; odd
+    /// sizes are coded a column or row larger and cropped by the sink.
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_bps: u32,
+    /// Pixels per point on the captured display, e.g. 2 for a Retina mode.
+    /// The sink is told a scale no larger than its HELLO_ACK allows.
+    pub scale: u16,
+    /// Sent to the sink in HELLO
+    pub identity: PeerIdentity,
+    /// An earlier session to ask the sink to resume instead of sending
+    /// START. Only asked for if the stream requested above is the same,
+    /// and there are no `extra_streams`.
+    pub resume: Option<ResumeSession>,
+    /// Streams to start after the one above, e.g. for more displays, as
+    /// many as the sink can show. Needs `capabilities::MULTI_STREAM` at
+    /// both ends; each is tagged with its place in the list plus one.
+    pub extra_streams: Vec<StreamRequest>,
+}
+
+/// A stream the source asks to start after the first, see
+/// `SourceHandshake::extra_streams`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamRequest {
+    /// Visible size in pixels, as for the first stream
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_bps: u32,
+    /// Pixels per point on the captured display
+    pub scale: u16,
+}
+
+/// A stream started by the handshake after the first, from either end
+#[derive(Debug, Clone)]
+pub struct ExtraStream {
+    /// Tag of the stream's packets, from 1 up
+    pub stream_id: u8,
+    /// The stream's START
+    pub start: StartPayload,
+    /// Credits granted in the stream's START_ACK
+    pub initial_credits: u16,
+    /// Pixels per point: as sent in START, or on the sink limited to its
+    /// `max_scale`
+    pub scale: u16,
+}
+
+impl Default for SourceHandshake {
+    fn default() -> Self {
+        Self {
+            software_version: 1,
+            max_width: 3840,
+            max_height: 2160,
+            max_fps: 60,
+            // SourcePipeline answers both, can say when it pauses, and
+            // tags its packets with `SourcePipelineConfig::stream_id`
+            capabilities: capabilities::KEYFRAME_REQUEST
+                | capabilities::CREDIT_UPDATE
+                | capabilities::PAUSE
+                | capabilities::MULTI_STREAM,
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            bitrate_bps: 20_000_000,
+            scale: 1,
+            identity: local_identity(),
+            resume: None,
+            extra_streams: Vec::new(),
+        }
+    }
+}
+
+/// What a source keeps of a started session to resume it after a
+/// reconnect, see `StartedStream::resume_session`
+#[derive(Debug, Clone)]
+pub struct ResumeSession {
+    /// From the sink's START_ACK
+    pub token: ResumeToken,
+    /// START as sent for the session
+    pub start: StartPayload,
+    /// Credits granted in START_ACK
+    pub initial_credits: u16,
+    /// Bitrate the source had settled on when it left, resumed in place of
+    /// START's
+    pub bitrate_bps: u32,
+}
+
+/// Result of a handshake accepted by the sink
+#[derive(Debug, Clone)]
+pub struct StartedStream {
+    /// The sink's HELLO_ACK, including who it says it is
+    pub hello_ack: HelloPayload,
+    /// Credits granted in START_ACK
+    pub initial_credits: u16,
+    /// Next outgoing sequence number
+    pub sequence: u32,
+    /// Whether packets after the handshake carry a CRC
+    pub crc: bool,
+    /// Whether packets after the handshake may be compressed
+    pub compression: bool,
+    /// Whether frames may be sent with parity segments
+    pub fec: bool,
+    /// Whether the sink takes PAUSE, see `SourcePipelineConfig::pause_packets`
+    pub pause: bool,
+    /// Scale sent in START
+    pub scale: u16,
+    /// START as sent, or as sent for the resumed session
+    pub start: StartPayload,
+    /// Bitrate to start encoding at: START's, or the resumed session's last
+    pub bitrate_bps: u32,
+    /// Whether the sink resumed `SourceHandshake::resume` rather than
+    /// taking a START. The stream still starts with a keyframe, as every
+    /// `SourcePipeline` does.
+    pub resumed: bool,
+    /// From START_ACK, for resuming this session after a reconnect
+    pub resume_token: Option<ResumeToken>,
+    /// The `SourceHandshake::extra_streams` the sink took, in order
+    pub extra_streams: Vec<ExtraStream>,
+}
+
+impl StartedStream {
+    /// What to keep to resume this session, if the sink gave a token.
+    /// `bitrate_bps` is the bitrate the source last encoded at.
+    pub fn resume_session(&self, bitrate_bps: u32) -> Option<ResumeSession> {
+        Some(ResumeSession {
+            token: self.resume_token?,
+            start: self.start.clone(),
+            initial_credits: self.initial_credits,
+            bitrate_bps,
+        })
+    }
+}
+
+impl SourceHandshake {
+    /// Send HELLO and START and wait for the sink to acknowledge both.
+    /// Outgoing packets are numbered from `sequence`.
+    ///
+    /// With `resume` set, HELLO carries its token, and if the sink echoes it
+    /// in HELLO_ACK the session picks up without a START.
+    ///
+    /// Each of `extra_streams` the sink can show is then started with its
+    /// own START and START_ACK, tagged with its stream id.
+    pub async fn connect(
+        &self,
+        transport: &dyn Transport,
+        mut sequence: u32,
+    ) -> Result<StartedStream, PipelineError> {
+        let resume = self.resume.as_ref().filter(|resume| {
+            let start = &resume.start;
+            let same = self.capabilities & capabilities::RESUME != 0
+                && self.extra_streams.is_empty()
+                && start.display_size() == Resolution::new(self.width, self.height)
+                && start.fps() == self.fps
+                && start.scale() <= self.scale.max(1);
+            if !same {
+                info!("Stream changed since the last session, not resuming it");
+            }
+            same
+        });
+        let hello = HelloPayload::new(
+            self.software_version,
+            self.max_width,
+            self.max_height,
+            self.max_fps,
+            self.capabilities,
+        )
+        .with_max_scale(self.scale)
+        .with_max_streams((1 + self.extra_streams.len()).min(MAX_STREAMS) as u8)
+        .with_identity(self.identity.clone())
+        .with_resume_token(resume.map(|resume| resume.token));
+        send(
+            transport,
+            PacketType::Hello,
+            &mut sequence,
+            hello.to_bytes(),
+        )
+        .await?;
+        info!("Sent HELLO, waiting for HELLO_ACK...");
+
+        let ack = receive_packet(transport).await?;
+        expect(&ack, PacketType::HelloAck, "HELLO_ACK")?;
+        let hello_ack = HelloPayload::parse(&ack.payload)?;
+        info!(
+            "Received HELLO_ACK from {}: max {}x{} @ {}fps",
+            hello_ack.identity,
+            hello_ack.max_width,
+            hello_ack.max_height,
+            hello_ack.max_fps()
+        );
+
+        let crc = !both_support(capabilities::NO_CRC, self.capabilities, &hello_ack);
+        let compression = both_support(capabilities::LZ4, self.capabilities, &hello_ack);
+        let fec = both_support(capabilities::FEC, self.capabilities, &hello_ack);
+        let pause = both_support(capabilities::PAUSE, self.capabilities, &hello_ack);
+        if let Some(resume) = resume.filter(|resume| hello_ack.resume_token == Some(resume.token)) {
+            info!(
+                "Resumed session: {} @{}x @ {}fps, {} bps",
+                resume.start.display_size(),
+                resume.start.scale(),
+                resume.start.fps(),
+                resume.bitrate_bps
+            );
+            return Ok(StartedStream {
+                hello_ack,
+                initial_credits: resume.initial_credits,
+                sequence,
+                crc,
+                compression,
+                fec,
+                pause,
+                scale: resume.start.scale(),
+                start: resume.start.clone(),
+                bitrate_bps: resume.bitrate_bps,
+                resumed: true,
+                resume_token: Some(resume.token),
+                extra_streams: Vec::new(),
+            });
+        }
+        if resume.is_some() {
+            info!("Sink didn't resume the session, starting a new one");
+        }
+
+        // A sink that can't show this scale gets the pixels one to a point
+        let scale = self.scale.max(1).min(hello_ack.max_scale());
+        let start = StartPayload::new(self.width, self.height, self.fps, self.bitrate_bps)
+            .with_scale(scale);
+        send(
+            transport,
+            PacketType::Start,
+            &mut sequence,
+            start.to_bytes(),
+        )
+        .await?;
+        info!(
+            "Sent START: {}x{} @{}x @ {}fps, {} bps",
+            self.width, self.height, scale, self.fps, self.bitrate_bps
+        );
+
+        let start_ack = receive_start_ack(transport, 0).await?;
+        info!(
+            "Received START_ACK with {} credits",
+            start_ack.initial_credits
+        );
+
+        let streams = stream_count(self.capabilities, &hello_ack, 1 + self.extra_streams.len());
+        if streams < 1 + self.extra_streams.len() {
+            info!(
+                "Sink shows {} of the {} streams asked for",
+                streams,
+                1 + self.extra_streams.len()
+            );
+        }
+        let mut extra_streams = Vec::with_capacity(streams - 1);
+        for (stream_id, request) in (1..).zip(&self.extra_streams[..streams - 1]) {
+            let scale = request.scale.max(1).min(hello_ack.max_scale());
+            let start = StartPayload::new(
+                request.width,
+                request.height,
+                request.fps,
+                request.bitrate_bps,
+            )
+            .with_scale(scale);
+            send_tagged(
+                transport,
+                PacketType::Start,
+                stream_id,
+                &mut sequence,
+                start.to_bytes(),
+            )
+            .await?;
+            let start_ack = receive_start_ack(transport, stream_id).await?;
+            info!(
+                "Started stream {}: {}x{} @{}x @ {}fps, {} bps, {} credits",
+                stream_id,
+                request.width,
+                request.height,
+                scale,
+                request.fps,
+                request.bitrate_bps,
+                start_ack.initial_credits
+            );
+            extra_streams.push(ExtraStream {
+                stream_id,
+                start,
+                initial_credits: start_ack.initial_credits,
+                scale,
+            });
+        }
+
+        Ok(StartedStream {
+            hello_ack,
+            initial_credits: start_ack.initial_credits,
+            sequence,
+            crc,
+            compression,
+            fec,
+            pause,
+            scale,
+            start,
+            bitrate_bps: self.bitrate_bps,
+            resumed: false,
+            resume_token: start_ack.resume_token,
+            extra_streams,
+        })
+    }
+}
+
+/// What the sink advertises during the handshake
+#[derive(Debug, Clone)]
+pub struct SinkHandshake {
+    pub software_version: u16,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_fps: u32,
+    /// HELLO capability bits, see `capabilities`
+    pub capabilities: u32,
+    /// Credits granted to the source in START_ACK
+    pub initial_credits: u16,
+    /// Largest scale, in pixels per point, the sink's window can show
+    pub max_scale: u16,
+    /// Most streams the sink can show at once, each in a window of its
+    /// own; more than 1 needs `capabilities::MULTI_STREAM`
+    pub max_streams: u8,
+    /// Sent to the source in HELLO_ACK
+    pub identity: PeerIdentity,
+    /// How long after `suspend` the last session can be resumed; `None`
+    /// gives out no resume tokens
+    pub resume_expiry: Option<Duration>,
+    /// The last session started, for its source to resume
+    pub sessions: ResumableSessions,
+}
+
+/// The session a sink last started, kept for its source to resume after a
+/// reconnect. Clones share it.
+#[derive(Debug, Clone, Default)]
+pub struct ResumableSessions {
+    last: Arc<Mutex<Option<ResumableSession>>>,
+}
+
+#[derive(Debug, Clone)]
+struct ResumableSession {
+    token: ResumeToken,
+    start: StartPayload,
+    /// When the session started or the source last left it
+    since: Instant,
+}
+
+impl ResumableSessions {
+    /// Token of the session kept, expired or not
+    pub fn token(&self) -> Option<ResumeToken> {
+        self.lock().as_ref().map(|session| session.token)
+    }
+
+    /// Forget the session, so the next source does a full handshake
+    pub fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<ResumableSession>> {
+        self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for SinkHandshake {
+    fn default() -> Self {
+        Self {
+            software_version: 1,
+            max_width: 3840,
+            max_height: 2160,
+            max_fps: 60,
+            // Parity segments are handled by every FrameReassembler, and
+            // SinkPipeline keeps to its `SinkPipelineConfig::stream_id`
+            capabilities: capabilities::HIDPI
+                | capabilities::AUDIO
+                | capabilities::FEC
+                | capabilities::RESUME
+                | capabilities::KEYFRAME_REQUEST
+                | capabilities::CREDIT_UPDATE
+                | capabilities::PAUSE
+                | capabilities::MULTI_STREAM,
+            initial_credits: StartAckPayload::DEFAULT_CREDITS,
+            max_scale: 2,
+            max_streams: 1,
+            identity: local_identity(),
+            resume_expiry: Some(DEFAULT_RESUME_EXPIRY),
+            sessions: ResumableSessions::default(),
+        }
+    }
+}
+
+/// Result of a completed handshake
+#[derive(Debug, Clone)]
+pub struct NegotiatedStream {
+    /// The source's HELLO, including who it says it is
+    pub hello: HelloPayload,
+    /// Stream parameters from START
+    pub start: StartPayload,
+    /// Next outgoing sequence number
+    pub sequence: u32,
+    /// Whether packets after the handshake carry a CRC
+    pub crc: bool,
+    /// Whether packets after the handshake may be compressed
+    pub compression: bool,
+    /// Whether frames may arrive with parity segments
+    pub fec: bool,
+    /// Whether the source can be asked for a keyframe
+    pub keyframe_requests: bool,
+    /// Whether the source's credit window can be resized with CREDIT_UPDATE
+    pub credit_updates: bool,
+    /// Pixels per point to show the stream at: START's scale, limited to
+    /// the sink's `max_scale`
+    pub scale: u16,
+    /// Whether this resumed the last session rather than taking a START, in
+    /// which case `start` is that session's. The source still starts with
+    /// a keyframe.
+    pub resumed: bool,
+    /// Streams the source started after the first, each to be shown in a
+    /// window of its own
+    pub extra_streams: Vec<ExtraStream>,
+}
+
+impl NegotiatedStream {
+    /// Size of the visible frame in points, for sizing a window; the
+    /// frame's textures stay `start.display_size()` pixels
+    pub fn point_size(&self) -> Resolution {
+        self.start.display_size().to_points(self.scale)
+    }
+}
+
+impl ExtraStream {
+    /// Visible size of the stream in points, as its window is sized
+    pub fn point_size(&self) -> Resolution {
+        self.start.display_size().to_points(self.scale)
+    }
+}
+
+impl SinkHandshake {
+    /// Note that the source has left the stream, e.g. with a STOP hinting
+    /// it will reconnect; the session can be resumed for `resume_expiry`
+    /// from now
+    pub fn suspend(&self) {
+        if let Some(session) = self.sessions.lock().as_mut() {
+            session.since = Instant::now();
+        }
+    }
+
+    /// Wait for HELLO, then complete the handshake. Outgoing packets are
+    /// numbered from `sequence`.
+    pub async fn accept(
+        &self,
+        transport: &dyn Transport,
+        sequence: u32,
+    ) -> Result<NegotiatedStream, PipelineError> {
+        info!("Waiting for HELLO...");
+        let hello = receive_packet(transport).await?;
+        self.accept_hello(transport, hello, sequence).await
+    }
+
+    /// Complete the handshake after `hello` has been received, e.g. when the
+    /// source reconnects mid-session. A source asking to resume the last
+    /// session, before it expires, skips START. A source streaming several
+    /// displays then sends a START for each one after the first, up to
+    /// `max_streams` in all.
+    pub async fn accept_hello(
+        &self,
+        transport: &dyn Transport,
+        hello: Packet,
+        mut sequence: u32,
+    ) -> Result<NegotiatedStream, PipelineError> {
+        expect(&hello, PacketType::Hello, "HELLO")?;
+        let hello = HelloPayload::parse(&hello.payload)?;
+        info!(
+            "Received HELLO from {}: max {}x{} @ {}fps",
+            hello.identity,
+            hello.max_width,
+            hello.max_height,
+            hello.max_fps()
+        );
+
+        let resuming = self.take_resumable(&hello);
+        let ack = HelloPayload::new(
+            self.software_version,
+            self.max_width,
+            self.max_height,
+            self.max_fps,
+            self.capabilities,
+        )
+        .with_max_scale(self.max_scale)
+        .with_max_streams(self.max_streams)
+        .with_identity(self.identity.clone())
+        .with_resume_token(resuming.as_ref().map(|session| session.token));
+        send(
+            transport,
+            PacketType::HelloAck,
+            &mut sequence,
+            ack.to_bytes(),
+        )
+        .await?;
+        info!("Sent HELLO_ACK");
+
+        let crc = !both_support(capabilities::NO_CRC, self.capabilities, &hello);
+        let compression = both_support(capabilities::LZ4, self.capabilities, &hello);
+        let fec = both_support(capabilities::FEC, self.capabilities, &hello);
+        let keyframe_requests =
+            both_support(capabilities::KEYFRAME_REQUEST, self.capabilities, &hello);
+        let credit_updates = both_support(capabilities::CREDIT_UPDATE, self.capabilities, &hello);
+        if let Some(session) = resuming {
+            let start = session.start.clone();
+            info!(
+                "Resumed session {}: {} @{}x @ {}fps",
+                session.token,
+                start.display_size(),
+                start.scale(),
+                start.fps()
+            );
+            *self.sessions.lock() = Some(ResumableSession {
+                since: Instant::now(),
+                ..session
+            });
+            let scale = start.scale().min(self.max_scale.max(1));
+            return Ok(NegotiatedStream {
+                hello,
+                start,
+                sequence,
+                crc,
+                compression,
+                fec,
+                keyframe_requests,
+                credit_updates,
+                scale,
+                resumed: true,
+                extra_streams: Vec::new(),
+            });
+        }
+
+        info!("Waiting for START...");
+        let start = receive_packet(transport).await?;
+        expect(&start, PacketType::Start, "START")?;
+        let start = StartPayload::parse(&start.payload)?;
+        info!(
+            "Received START: {} (coded {}) @{}x @ {}fps, {} bps",
+            start.display_size(),
+            start.coded_size(),
+            start.scale(),
+            start.fps(),
+            start.bitrate_bps
+        );
+
+        // A new session replaces any kept before. Only single streams are
+        // resumed.
+        let streams = stream_count(self.capabilities, &hello, self.max_streams.max(1) as usize);
+        let token = if streams == 1 {
+            self.issue_token(&hello, &start)
+        } else {
+            self.sessions.clear();
+            None
+        };
+        let ack = StartAckPayload::ok(self.initial_credits).with_resume_token(token);
+        send(
+            transport,
+            PacketType::StartAck,
+            &mut sequence,
+            ack.to_bytes(),
+        )
+        .await?;
+        info!("Sent START_ACK with {} credits", self.initial_credits);
+
+        let mut extra_streams = Vec::with_capacity(streams - 1);
+        for stream_id in 1..streams as u8 {
+            let packet = receive_packet(transport).await?;
+            expect(&packet, PacketType::Start, "START")?;
+            expect_stream(&packet, stream_id)?;
+            let start = StartPayload::parse(&packet.payload)?;
+            let ack = StartAckPayload::ok(self.initial_credits);
+            send_tagged(
+                transport,
+                PacketType::StartAck,
+                stream_id,
+                &mut sequence,
+                ack.to_bytes(),
+            )
+            .await?;
+            info!(
+                "Started stream {}: {} @{}x @ {}fps, {} bps",
+                stream_id,
+                start.display_size(),
+                start.scale(),
+                start.fps(),
+                start.bitrate_bps
+            );
+            extra_streams.push(ExtraStream {
+                stream_id,
+                scale: start.scale().min(self.max_scale.max(1)),
+                start,
+                initial_credits: self.initial_credits,
+            });
+        }
+
+        let scale = start.scale().min(self.max_scale.max(1));
+        Ok(NegotiatedStream {
+            hello,
+            start,
+            sequence,
+            crc,
+            compression,
+            fec,
+            keyframe_requests,
+            credit_updates,
+            scale,
+            resumed: false,
+            extra_streams,
+        })
+    }
+
+    /// The kept session `hello` asks to resume, if both ends support it and
+    /// it hasn't expired
+    fn take_resumable(&self, hello: &HelloPayload) -> Option<ResumableSession> {
+        let token = hello.resume_token?;
+        let expiry = self.resume_expiry?;
+        if !both_support(capabilities::RESUME, self.capabilities, hello) {
+            return None;
+        }
+        let session = self.sessions.lock().clone();
+        match session {
+            Some(session) if session.token != token => {
+                warn!("Unknown resume token {}, doing a full handshake", token);
+                None
+            }
+            Some(session) if session.since.elapsed() >= expiry => {
+                warn!(
+                    "Resume token {} expired {:.1}s ago, doing a full handshake",
+                    token,
+                    (session.since.elapsed() - expiry).as_secs_f64()
+                );
+                self.sessions.clear();
+                None
+            }
+            Some(session) => Some(session),
+            None => {
+                warn!("No session to resume, doing a full handshake");
+                None
+            }
+        }
+    }
+
+    /// Keep the session `start` begins and give its token, if both ends
+    /// support resuming
+    fn issue_token(&self, hello: &HelloPayload, start: &StartPayload) -> Option<ResumeToken> {
+        if self.resume_expiry.is_none()
+            || !both_support(capabilities::RESUME, self.capabilities, hello)
+        {
+            self.sessions.clear();
+            return None;
+        }
+        let token = ResumeToken::generate();
+        *self.sessions.lock() = Some(ResumableSession {
+            token,
+            start: start.clone(),
+            since: Instant::now(),
+        });
+        Some(token)
+    }
+}
+
+/// This machine's host name and platform and this build's version, as
+/// both ends send by default. A frontend versioned on its own replaces
+/// `app_version` with its own.
+pub fn local_identity() -> PeerIdentity {
+    static HOST_NAME: OnceLock<String> = OnceLock::new();
+    let host_name = HOST_NAME.get_or_init(|| {
+        std::process::Command::new("hostname")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default()
+    });
+    PeerIdentity::new(
+        host_name.as_str(),
+        format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Whether both ends advertised `capability`
+fn both_support(capability: u32, ours: u32, theirs: &HelloPayload) -> bool {
+    ours & theirs.capabilities & capability != 0
+}
+
+/// Streams to start: as many as `wanted`, up to what the peer can take,
+/// or just the one without `capabilities::MULTI_STREAM` at both ends
+fn stream_count(ours: u32, theirs: &HelloPayload, wanted: usize) -> usize {
+    if both_support(capabilities::MULTI_STREAM, ours, theirs) {
+        wanted.clamp(1, theirs.max_streams() as usize)
+    } else {
+        1
+    }
+}
+
+/// Fail unless `packet` is tagged with `stream_id`
+fn expect_stream(packet: &Packet, stream_id: u8) -> Result<(), ProtocolError> {
+    if packet.stream_id() != stream_id {
+        return Err(ProtocolError::HandshakeFailed(format!(
+            "{:?} for stream {} where stream {} was expected",
+            packet.packet_type(),
+            packet.stream_id(),
+            stream_id
+        )));
+    }
+    Ok(())
+}
+
+/// Wait for the START_ACK of stream `stream_id`, failing if it rejects it
+async fn receive_start_ack(
+    transport: &dyn Transport,
+    stream_id: u8,
+) -> Result<StartAckPayload, PipelineError> {
+    let ack = receive_packet(transport).await?;
+    expect(&ack, PacketType::StartAck, "START_ACK")?;
+    expect_stream(&ack, stream_id)?;
+    let start_ack = StartAckPayload::parse(&ack.payload)?;
+    if !start_ack.is_ok() {
+        return Err(ProtocolError::HandshakeFailed(format!(
+            "START rejected with status {}",
+            start_ack.status
+        ))
+        .into());
+    }
+    Ok(start_ack)
+}
+
+fn expect(
+    packet: &Packet,
+    packet_type: PacketType,
+    name: &'static str,
+) -> Result<(), ProtocolError> {
+    if packet.packet_type() != packet_type {
+        return Err(ProtocolError::UnexpectedPacketType {
+            expected: name,
+            actual: packet.packet_type() as u8,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) async fn send(
+    transport: &dyn Transport,
+    packet_type: PacketType,
+    sequence: &mut u32,
+    payload: bytes::Bytes,
+) -> Result<(), PipelineError> {
+    send_tagged(transport, packet_type, 0, sequence, payload).await
+}
+
+/// Like `send`, for stream `stream_id`
+async fn send_tagged(
+    transport: &dyn Transport,
+    packet_type: PacketType,
+    stream_id: u8,
+    sequence: &mut u32,
+    payload: bytes::Bytes,
+) -> Result<(), PipelineError> {
+    let packet = Packet::new(packet_type, 0, *sequence, payload).with_stream_id(stream_id);
+    *sequence = sequence.wrapping_add(1);
+    transport.send(packet.to_bytes()).await?;
+    metrics::packet_sent(packet_type);
+    Ok(())
+}
+
+pub(crate) async fn receive_packet(transport: &dyn Transport) -> Result<Packet, PipelineError> {
+    let data = transport.recv().await?;
+    let (packet, _) = Packet::parse(&data)?;
+    metrics::packet_received(packet.packet_type());
+    Ok(packet)
+}
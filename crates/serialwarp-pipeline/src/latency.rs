@@ -0,0 +1,205 @@
+//! Rolling glass-to-glass latency statistics
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Default span of a `LatencyWindow`
+pub const DEFAULT_LATENCY_WINDOW: Duration = Duration::from_secs(5);
+
+/// Where one presented frame's time went, from capture on the source to
+/// presentation on the sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStages {
+    /// Capture until the frame's first segment arrived, so including the
+    /// source's encode and send: whatever the sink's own stages leave
+    pub transport: Duration,
+    /// First segment until the frame was reassembled
+    pub reassembly: Duration,
+    pub decode: Duration,
+    /// Decoded until the consumer took the frame
+    pub queue: Duration,
+    /// Taken by the consumer until it was presented, e.g. pacing
+    pub present: Duration,
+}
+
+impl LatencyStages {
+    /// Capture to presentation
+    pub fn total(&self) -> Duration {
+        self.transport + self.reassembly + self.decode + self.queue + self.present
+    }
+}
+
+/// Latency of the frames presented during a `LatencyWindow`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyReport {
+    /// Frames the figures cover
+    pub samples: usize,
+    pub average: Duration,
+    /// 95th percentile, nearest rank
+    pub p95: Duration,
+    pub max: Duration,
+    /// Average of each stage
+    pub stages: LatencyStages,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "latency avg={:.1}ms p95={:.1}ms max={:.1}ms over {} frames \
+             (transport={:.1}ms reassembly={:.1}ms decode={:.1}ms queue={:.1}ms present={:.1}ms)",
+            ms(self.average),
+            ms(self.p95),
+            ms(self.max),
+            self.samples,
+            ms(self.stages.transport),
+            ms(self.stages.reassembly),
+            ms(self.stages.decode),
+            ms(self.stages.queue),
+            ms(self.stages.present),
+        )
+    }
+}
+
+/// Latency samples from the last `window` of time
+#[derive(Debug, Clone)]
+pub struct LatencyWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, LatencyStages)>,
+}
+
+impl LatencyWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Add a frame presented at `now`
+    pub fn record(&mut self, now: Instant, stages: LatencyStages) {
+        self.expire(now);
+        self.samples.push_back((now, stages));
+    }
+
+    /// Statistics over the frames presented in the window up to `now`, or
+    /// `None` if there were none
+    pub fn report(&mut self, now: Instant) -> Option<LatencyReport> {
+        self.expire(now);
+        let samples = self.samples.len();
+        if samples == 0 {
+            return None;
+        }
+
+        let mut totals: Vec<Duration> = self.samples.iter().map(|(_, s)| s.total()).collect();
+        totals.sort_unstable();
+        let p95_rank = (samples * 95 + 99) / 100;
+        let average = |stage: fn(&LatencyStages) -> Duration| {
+            self.samples.iter().map(|(_, s)| stage(s)).sum::<Duration>() / samples as u32
+        };
+
+        Some(LatencyReport {
+            samples,
+            average: totals.iter().sum::<Duration>() / samples as u32,
+            p95: totals[p95_rank - 1],
+            max: totals[samples - 1],
+            stages: LatencyStages {
+                transport: average(|s| s.transport),
+                reassembly: average(|s| s.reassembly),
+                decode: average(|s| s.decode),
+                queue: average(|s| s.queue),
+                present: average(|s| s.present),
+            },
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) > self.window)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
+
+impl Default for LatencyWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_LATENCY_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn sample(transport_ms: u64) -> LatencyStages {
+        LatencyStages {
+            transport: ms(transport_ms),
+            reassembly: ms(1),
+            decode: ms(2),
+            queue: ms(3),
+            present: ms(4),
+        }
+    }
+
+    #[test]
+    fn test_report_statistics() {
+        let start = Instant::now();
+        let mut window = LatencyWindow::new(Duration::from_secs(10));
+        // Totals of 11..=110ms
+        for transport in 1..=100 {
+            window.record(start, sample(transport));
+        }
+
+        let report = window.report(start).unwrap();
+        assert_eq!(report.samples, 100);
+        assert_eq!(report.average, Duration::from_micros(60_500));
+        assert_eq!(report.p95, ms(105));
+        assert_eq!(report.max, ms(110));
+        assert_eq!(report.stages.transport, Duration::from_micros(50_500));
+        assert_eq!(report.stages.decode, ms(2));
+        assert_eq!(report.stages.present, ms(4));
+    }
+
+    #[test]
+    fn test_p95_of_few_samples() {
+        let start = Instant::now();
+        let mut window = LatencyWindow::default();
+        window.record(start, sample(10));
+        assert_eq!(window.report(start).unwrap().p95, ms(20));
+
+        // With fewer than 20 samples the 95th percentile is the largest
+        for transport in [30, 20, 40] {
+            window.record(start, sample(transport));
+        }
+        let report = window.report(start).unwrap();
+        assert_eq!(report.p95, ms(50));
+        assert_eq!(report.max, ms(50));
+    }
+
+    #[test]
+    fn test_old_samples_expire() {
+        let start = Instant::now();
+        let mut window = LatencyWindow::new(Duration::from_secs(1));
+        window.record(start, sample(100));
+        window.record(start + ms(600), sample(10));
+
+        assert_eq!(window.report(start + ms(1000)).unwrap().samples, 2);
+        let report = window.report(start + ms(1100)).unwrap();
+        assert_eq!(report.samples, 1);
+        assert_eq!(report.max, ms(20));
+        assert_eq!(window.report(start + ms(1700)), None);
+    }
+}
@@ -0,0 +1,43 @@
+//! serialwarp-pipeline - Streaming loops shared by the source and sink
+//!
+//! This crate owns the credit-gated capture → encode → send loop, the
+//! receive → decode → acknowledge loop, and both ends of the handshake so every
+//! frontend drives the same implementation.
+
+mod autoconnect;
+mod credit;
+mod handshake;
+mod latency;
+mod pacing;
+mod probe;
+mod sender;
+mod sink;
+mod source;
+mod stats_export;
+mod trace;
+mod watchdog;
+
+pub use autoconnect::{
+    auto_connect, AutoConnectEvent, Backoff, DeviceWatcher, DEFAULT_BACKOFF_INITIAL,
+    DEFAULT_BACKOFF_MAX,
+};
+pub use handshake::{
+    local_identity, ExtraStream, NegotiatedStream, ResumableSessions, ResumeSession, SinkHandshake,
+    SourceHandshake, StartedStream, StreamRequest, DEFAULT_RESUME_EXPIRY,
+};
+pub use latency::{LatencyReport, LatencyStages, LatencyWindow, DEFAULT_LATENCY_WINDOW};
+pub use pacing::{PacingConfig, DEFAULT_PACING_UTILIZATION, DEFAULT_UNPACED_LINK_BPS};
+pub use probe::{BandwidthEstimate, BandwidthProbe, DEFAULT_PROBE_PACKETS, DEFAULT_PROBE_TIMEOUT};
+pub use sink::{SinkOutput, SinkPipeline, SinkPipelineConfig, SinkStats};
+pub use source::{
+    KeyframeReason, SourceEvent, SourcePipeline, SourcePipelineConfig, SourceStats,
+    DEFAULT_STARVATION_TIMEOUT, DEFAULT_TIMING_WINDOW,
+};
+pub use stats_export::{
+    SessionStats, StatsExportConfig, StatsExporter, StatsFormat, StatsRow, DEFAULT_STATS_INTERVAL,
+    STATS_COLUMNS,
+};
+pub use trace::FRAME_TRACE_TARGET;
+pub use watchdog::{
+    SourceStall, SourceWatchdog, StallCause, StallEvent, StallWatchdog, DEFAULT_STALL_TIMEOUT,
+};
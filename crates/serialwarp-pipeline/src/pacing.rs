@@ -0,0 +1,244 @@
+//! Spreading each frame's packets over part of the frame interval
+//!
+//! Sent back to back, a keyframe's segments hold the link for as long as
+//! they take, and on bridge chips that can't read while writing the sink's
+//! acks wait behind them. Pacing lets the packets out at a rate that takes
+//! `utilization` of the frame interval, so the gaps between them are left
+//! for the other direction.
+
+use std::time::Duration;
+
+use serialwarp_core::{FrameHeader, CRC_SIZE, HEADER_SIZE, MAX_SEGMENT_SIZE};
+use tokio::time::Instant;
+
+/// Default fraction of the frame interval a frame's packets are spread over
+pub const DEFAULT_PACING_UTILIZATION: f64 = 0.7;
+
+/// Default link throughput in bits per second at or above which frames go
+/// unpaced: faster than USB 2.0 bulk transfers get in practice
+pub const DEFAULT_UNPACED_LINK_BPS: u64 = 400_000_000;
+
+/// Largest FRAME packet, which the bucket holds enough for so a frame's
+/// first packet never waits
+const MAX_PACKET_SIZE: usize = HEADER_SIZE + FrameHeader::SIZE + MAX_SEGMENT_SIZE + CRC_SIZE;
+
+/// Weight of the newest send in the link throughput estimate
+const THROUGHPUT_SMOOTHING: f64 = 0.2;
+
+/// How the source paces FRAME packets, see `SourcePipelineConfig::pacing`
+#[derive(Debug, Clone, Copy)]
+pub struct PacingConfig {
+    /// Fraction of the frame interval each frame's packets are spread
+    /// over, e.g. 0.7 to keep the link 70% busy at most
+    pub utilization: f64,
+    /// Link throughput in bits per second, as measured from how long sends
+    /// take, at or above which frames go unpaced. A burst on a link that
+    /// fast is over before it can hold anything up.
+    pub unpaced_link_bps: u64,
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        Self {
+            utilization: DEFAULT_PACING_UTILIZATION,
+            unpaced_link_bps: DEFAULT_UNPACED_LINK_BPS,
+        }
+    }
+}
+
+/// A token bucket on bytes, refilled for each frame at the rate that sends
+/// it within `utilization` of its interval, and never slower than the
+/// stream's bitrate needs at that utilization
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    config: PacingConfig,
+    /// Bytes per second for the frame being sent; `None` while unpaced
+    rate: Option<f64>,
+    /// Bytes that may go out now, negative while a wait is paying them off
+    tokens: f64,
+    refilled: Instant,
+    /// When the next frame is due; the rest of a frame still going out
+    /// then is sent unpaced
+    deadline: Instant,
+    /// Link throughput in bytes per second, smoothed
+    throughput: Option<f64>,
+}
+
+impl Pacer {
+    pub fn new(config: PacingConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            rate: None,
+            tokens: 0.0,
+            refilled: now,
+            deadline: now,
+            throughput: None,
+        }
+    }
+
+    /// Start sending a frame whose packets total `bytes`, due to be followed
+    /// by the next one after `interval`. Returns whether it's paced, which
+    /// it isn't on a link measured at `unpaced_link_bps` or faster.
+    pub fn start_frame(
+        &mut self,
+        bytes: usize,
+        interval: Duration,
+        bitrate_bps: Option<u32>,
+        now: Instant,
+    ) -> bool {
+        let fast_link = self
+            .throughput
+            .is_some_and(|throughput| throughput * 8.0 >= self.config.unpaced_link_bps as f64);
+        let utilization = self.config.utilization.clamp(0.01, 1.0);
+        if fast_link || interval.is_zero() {
+            self.rate = None;
+            return false;
+        }
+
+        let spread = bytes as f64 / (interval.as_secs_f64() * utilization);
+        let stream = bitrate_bps.map_or(0.0, |bps| bps as f64 / 8.0 / utilization);
+        self.rate = Some(spread.max(stream));
+        self.tokens = MAX_PACKET_SIZE as f64;
+        self.refilled = now;
+        self.deadline = now + interval;
+        true
+    }
+
+    /// How long to wait before sending a packet of `bytes`, which are taken
+    /// from the bucket; zero while unpaced. No wait runs past the deadline.
+    pub fn delay(&mut self, bytes: usize, now: Instant) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        if now >= self.deadline {
+            self.rate = None;
+            return Duration::ZERO;
+        }
+
+        let refill = rate * now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + refill).min(MAX_PACKET_SIZE as f64);
+        self.refilled = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / rate).min(self.deadline - now)
+    }
+
+    /// Send the rest of the frame unpaced, e.g. because the next one is
+    /// already waiting
+    pub fn bypass(&mut self) {
+        self.rate = None;
+    }
+
+    /// Whether the frame being sent is still paced
+    pub fn is_pacing(&self) -> bool {
+        self.rate.is_some()
+    }
+
+    /// Record that sending `bytes` took `elapsed`, to measure the link.
+    /// Sends that took no measurable time say nothing about it.
+    pub fn record_send(&mut self, bytes: usize, elapsed: Duration) {
+        if elapsed.is_zero() {
+            return;
+        }
+        let sample = bytes as f64 / elapsed.as_secs_f64();
+        self.throughput = Some(match self.throughput {
+            Some(throughput) => throughput + THROUGHPUT_SMOOTHING * (sample - throughput),
+            None => sample,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_micros(16_667);
+
+    fn assert_near(actual: Duration, expected: Duration, tolerance: Duration) {
+        let difference = actual.max(expected) - actual.min(expected);
+        assert!(
+            difference <= tolerance,
+            "{:?}, expected {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_frame_spread_over_utilization() {
+        let mut pacer = Pacer::new(PacingConfig::default());
+        let start = Instant::now();
+        let bytes = 8 * MAX_PACKET_SIZE;
+        assert!(pacer.start_frame(bytes, INTERVAL, None, start));
+
+        // The first packet at once, then one every eighth of 70% of the
+        // interval
+        let mut now = start;
+        let mut waits = Vec::new();
+        for _ in 0..8 {
+            let wait = pacer.delay(MAX_PACKET_SIZE, now);
+            waits.push(wait);
+            now += wait;
+        }
+        assert_eq!(waits[0], Duration::ZERO);
+        let spacing = INTERVAL.mul_f64(DEFAULT_PACING_UTILIZATION) / 8;
+        for &wait in &waits[1..] {
+            assert_near(wait, spacing, Duration::from_micros(2));
+        }
+    }
+
+    #[test]
+    fn test_bitrate_sets_lowest_rate() {
+        let mut pacer = Pacer::new(PacingConfig::default());
+        let now = Instant::now();
+        // 2 KB per frame alone would trickle out; 20 Mbps at 70% is
+        // about 3.6 MB/s
+        pacer.start_frame(2 * 1024, INTERVAL, Some(20_000_000), now);
+        pacer.delay(MAX_PACKET_SIZE, now);
+        let wait = pacer.delay(35_714, now);
+        assert_near(wait, Duration::from_millis(10), Duration::from_micros(10));
+    }
+
+    #[test]
+    fn test_never_waits_past_deadline() {
+        let mut pacer = Pacer::new(PacingConfig {
+            utilization: 1.0,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        pacer.start_frame(MAX_PACKET_SIZE, INTERVAL, None, start);
+        pacer.delay(MAX_PACKET_SIZE, start);
+        // More than the frame said, so the bucket would have it wait past
+        // the next frame
+        let now = start + INTERVAL / 2;
+        assert_eq!(pacer.delay(4 * MAX_PACKET_SIZE, now), INTERVAL / 2);
+        assert_eq!(
+            pacer.delay(MAX_PACKET_SIZE, start + INTERVAL),
+            Duration::ZERO
+        );
+        assert!(!pacer.is_pacing());
+    }
+
+    #[test]
+    fn test_fast_link_unpaced() {
+        let mut pacer = Pacer::new(PacingConfig::default());
+        let now = Instant::now();
+        pacer.record_send(0, Duration::ZERO);
+        assert_eq!(pacer.throughput, None);
+
+        // 40 MB/s: a USB 2.0 cable, paced
+        pacer.record_send(64 * 1024, Duration::from_micros(1638));
+        assert!(pacer.start_frame(500 * 1024, INTERVAL, None, now));
+        // Then measured at over 1 Gbps, as on USB 3.0
+        for _ in 0..20 {
+            pacer.record_send(64 * 1024, Duration::from_micros(100));
+        }
+        assert!(pacer.throughput.unwrap() * 8.0 > 1e9);
+        assert!(!pacer.start_frame(500 * 1024, INTERVAL, None, now));
+        assert_eq!(pacer.delay(MAX_PACKET_SIZE, now), Duration::ZERO);
+        assert_eq!(pacer.delay(MAX_PACKET_SIZE, now), Duration::ZERO);
+    }
+}
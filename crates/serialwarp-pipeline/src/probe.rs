@@ -0,0 +1,377 @@
+//! Measuring the link before streaming
+//!
+//! After START_ACK the source can send a burst of BANDWIDTH_PROBE packets
+//! for the sink to time and ack, then ask for a burst back and time that
+//! itself. The clock starts when a burst's first probe arrives, so each
+//! estimate is the bytes after it over the time to the last one.
+
+use std::time::Duration;
+
+use serialwarp_core::{
+    BandwidthProbeAckPayload, BandwidthProbePayload, Packet, PacketType, PipelineError,
+    TransportError, MAX_SEGMENT_SIZE,
+};
+use serialwarp_transport::Transport;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::handshake::{receive_packet, send};
+
+/// Default number of probes sent each way
+pub const DEFAULT_PROBE_PACKETS: u32 = 32;
+
+/// Default wait for each of the sink's replies
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most probes a sink sends back for one request
+const MAX_REVERSE_PACKETS: u32 = 1024;
+
+/// How the source measures the link, see `BandwidthProbe::run`
+#[derive(Debug, Clone)]
+pub struct BandwidthProbe {
+    /// Probes sent each way, at least 2
+    pub packets: u32,
+    /// Padding in each probe, at most `MAX_SEGMENT_SIZE`. The default makes
+    /// them as large as the largest FRAME packets.
+    pub packet_size: usize,
+    /// How long to wait for each of the sink's replies
+    pub timeout: Duration,
+}
+
+impl Default for BandwidthProbe {
+    fn default() -> Self {
+        Self {
+            packets: DEFAULT_PROBE_PACKETS,
+            packet_size: MAX_SEGMENT_SIZE,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+        }
+    }
+}
+
+/// What `BandwidthProbe::run` measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthEstimate {
+    /// Bits per second from the source to the sink, as the sink timed
+    /// them; `u64::MAX` on a link too fast to time
+    pub send_bps: u64,
+    /// Bits per second from the sink to the source
+    pub receive_bps: u64,
+    /// Time for a lone probe to be acked
+    pub round_trip: Duration,
+    /// Next outgoing sequence number
+    pub sequence: u32,
+}
+
+impl BandwidthEstimate {
+    /// Highest bitrate to stream at that keeps the link at most
+    /// `utilization` busy, as a ceiling for the encoder's bitrate
+    pub fn max_bitrate(&self, utilization: f64) -> u32 {
+        let bps = self.send_bps as f64 * utilization.clamp(0.0, 1.0);
+        bps.min(u32::MAX as f64) as u32
+    }
+}
+
+impl BandwidthProbe {
+    /// Measure the link both ways. Run by the source after START_ACK and
+    /// before streaming, while nothing else reads the transport. Outgoing
+    /// packets are numbered from `sequence`.
+    pub async fn run(
+        &self,
+        transport: &dyn Transport,
+        mut sequence: u32,
+    ) -> Result<BandwidthEstimate, PipelineError> {
+        let probe_id = sequence as u16;
+        let packets = self.packets.max(2);
+        let padding = self.packet_size.min(MAX_SEGMENT_SIZE) as u32;
+
+        // A lone probe first, so the burst starts with the sink reading
+        let start = Instant::now();
+        let probe = BandwidthProbePayload::new(probe_id, 0, 0, 0);
+        send(
+            transport,
+            PacketType::BandwidthProbe,
+            &mut sequence,
+            probe.to_bytes(),
+        )
+        .await?;
+        self.receive_ack(transport, probe_id).await?;
+        let round_trip = start.elapsed();
+
+        for index in 0..packets {
+            let probe = BandwidthProbePayload::new(probe_id, index, packets, padding);
+            send(
+                transport,
+                PacketType::BandwidthProbe,
+                &mut sequence,
+                probe.to_bytes(),
+            )
+            .await?;
+        }
+        let ack = self.receive_ack(transport, probe_id).await?;
+        let send_bps = bits_per_second(
+            ack.bytes_received,
+            ack.packets_received,
+            Duration::from_micros(ack.elapsed_us),
+        );
+
+        let request = BandwidthProbePayload::reverse(probe_id, packets, padding);
+        send(
+            transport,
+            PacketType::BandwidthProbe,
+            &mut sequence,
+            request.to_bytes(),
+        )
+        .await?;
+        let burst = self
+            .reply(async {
+                let mut burst = Burst::default();
+                loop {
+                    let packet = receive_packet(transport).await?;
+                    if let Some(probe) = probe_reply(&packet, probe_id) {
+                        burst.add(packet.header.packet_size(), Instant::now());
+                        if probe.is_last() {
+                            return Ok(burst);
+                        }
+                    }
+                }
+            })
+            .await?;
+        let receive_bps = bits_per_second(burst.bytes, burst.packets, burst.elapsed());
+
+        info!(
+            "Bandwidth probe: {:.1} Mbps to the sink, {:.1} Mbps back, {:?} round trip",
+            send_bps as f64 / 1e6,
+            receive_bps as f64 / 1e6,
+            round_trip
+        );
+        Ok(BandwidthEstimate {
+            send_bps,
+            receive_bps,
+            round_trip,
+            sequence,
+        })
+    }
+
+    /// Wait for the sink's ack of `probe_id`, skipping anything else
+    async fn receive_ack(
+        &self,
+        transport: &dyn Transport,
+        probe_id: u16,
+    ) -> Result<BandwidthProbeAckPayload, PipelineError> {
+        self.reply(async {
+            loop {
+                let packet = receive_packet(transport).await?;
+                if packet.packet_type() != PacketType::BandwidthProbeAck {
+                    continue;
+                }
+                let ack = BandwidthProbeAckPayload::parse(&packet.payload)?;
+                if ack.probe_id == probe_id {
+                    return Ok(ack);
+                }
+            }
+        })
+        .await
+    }
+
+    /// Wait up to `timeout` for a reply from the sink
+    async fn reply<T>(
+        &self,
+        reply: impl std::future::Future<Output = Result<T, PipelineError>>,
+    ) -> Result<T, PipelineError> {
+        tokio::time::timeout(self.timeout, reply)
+            .await
+            .map_err(|_| TransportError::Timeout {
+                duration_ms: self.timeout.as_millis() as u64,
+            })?
+    }
+}
+
+/// `packet` as a probe of the burst `probe_id`, if it's one
+fn probe_reply(packet: &Packet, probe_id: u16) -> Option<BandwidthProbePayload> {
+    if packet.packet_type() != PacketType::BandwidthProbe {
+        return None;
+    }
+    BandwidthProbePayload::parse(&packet.payload)
+        .ok()
+        .filter(|probe| probe.probe_id == probe_id && !probe.is_reverse())
+}
+
+/// Bits per second for `packets` totalling `bytes` that arrived `elapsed`
+/// apart, first to last. The first one was in before the clock started, so
+/// its share of the bytes doesn't count. Zero if fewer than two arrived.
+fn bits_per_second(bytes: u64, packets: u32, elapsed: Duration) -> u64 {
+    if packets < 2 {
+        return 0;
+    }
+    if elapsed.is_zero() {
+        return u64::MAX;
+    }
+    let timed = bytes as f64 * (packets - 1) as f64 / packets as f64;
+    (timed * 8.0 / elapsed.as_secs_f64()).min(u64::MAX as f64) as u64
+}
+
+/// The probes of one burst so far
+#[derive(Debug, Default)]
+struct Burst {
+    packets: u32,
+    /// Size on the wire
+    bytes: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Burst {
+    fn add(&mut self, bytes: usize, now: Instant) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    /// Time from the first probe arriving to the last
+    fn elapsed(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// The sink's side: timing bursts and acking them, and sending bursts back
+#[derive(Debug, Default)]
+pub(crate) struct ProbeResponder {
+    probe_id: u16,
+    burst: Burst,
+}
+
+impl ProbeResponder {
+    /// Note a probe of `bytes` on the wire arriving at `now`. Returns the
+    /// ack to send once the last of its burst is in.
+    pub fn received(
+        &mut self,
+        probe: &BandwidthProbePayload,
+        bytes: usize,
+        now: Instant,
+    ) -> Option<BandwidthProbeAckPayload> {
+        if probe.probe_id != self.probe_id {
+            self.probe_id = probe.probe_id;
+            self.burst = Burst::default();
+        }
+        self.burst.add(bytes, now);
+        if !probe.is_last() {
+            return None;
+        }
+        let burst = std::mem::take(&mut self.burst);
+        Some(BandwidthProbeAckPayload::new(
+            probe.probe_id,
+            burst.packets,
+            burst.bytes,
+            burst.elapsed().as_micros() as u64,
+        ))
+    }
+
+    /// The probes to send back for a reverse `request`, limited so a peer
+    /// can't ask for more than a short burst of at most segment-sized ones
+    pub fn reverse_burst(
+        request: &BandwidthProbePayload,
+    ) -> impl Iterator<Item = BandwidthProbePayload> {
+        let count = request.count.min(MAX_REVERSE_PACKETS);
+        let padding = request.padding.min(MAX_SEGMENT_SIZE as u32);
+        let probe_id = request.probe_id;
+        (0..count).map(move |index| BandwidthProbePayload::new(probe_id, index, count, padding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::PassthroughDecoder;
+    use serialwarp_transport::MockTransport;
+
+    use crate::sink::{SinkPipeline, SinkPipelineConfig};
+
+    /// Run `probe` against a sink pipeline over a pair of `transports`
+    async fn probe_sink(
+        probe: &BandwidthProbe,
+        (source, sink): (MockTransport, MockTransport),
+    ) -> Result<BandwidthEstimate, PipelineError> {
+        let mut sink = SinkPipeline::new(
+            sink.split(),
+            Box::new(PassthroughDecoder::new()),
+            SinkPipelineConfig::default(),
+            0,
+        );
+        let probing = async {
+            let estimate = probe.run(&source, 5).await;
+            source.close().await;
+            estimate
+        };
+        let (estimate, ()) = tokio::join!(probing, async { while sink.recv().await.is_ok() {} });
+        estimate
+    }
+
+    fn assert_near(actual: u64, expected: u64) {
+        let difference = actual.max(expected) - actual.min(expected);
+        assert!(
+            difference <= expected / 50,
+            "{} bps, expected {}",
+            actual,
+            expected
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_measures_shaped_link() {
+        // 25 MB/s, a cable too slow for 4K60
+        let link = MockTransport::pair_with_bandwidth(25_000_000);
+        let estimate = probe_sink(&BandwidthProbe::default(), link).await.unwrap();
+        assert_near(estimate.send_bps, 200_000_000);
+        assert_near(estimate.receive_bps, 200_000_000);
+        // Two tiny packets, delivered on the next timer ticks
+        assert!(estimate.round_trip <= Duration::from_millis(2));
+        // The lone probe, the burst and the request for one back
+        assert_eq!(estimate.sequence, 5 + 1 + DEFAULT_PROBE_PACKETS + 1);
+        assert_near(estimate.max_bitrate(0.7) as u64, 140_000_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_small_packets() {
+        // Headers count too: 100 bytes of padding is 136 on the wire, so
+        // at 100 KB/s each probe takes 1.36ms
+        let probe = BandwidthProbe {
+            packets: 100,
+            packet_size: 100,
+            ..Default::default()
+        };
+        let link = MockTransport::pair_with_bandwidth(100_000);
+        let estimate = probe_sink(&probe, link).await.unwrap();
+        assert_near(estimate.send_bps, 800_000);
+        assert_near(estimate.receive_bps, 800_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_times_out_without_sink() {
+        let (source, _sink) = MockTransport::pair();
+        let probe = BandwidthProbe {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        assert!(matches!(
+            probe.run(&source, 0).await,
+            Err(PipelineError::Transport(TransportError::Timeout {
+                duration_ms: 50
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_reverse_burst_limited() {
+        let request = BandwidthProbePayload::reverse(3, 1_000_000, u32::MAX);
+        let burst: Vec<_> = ProbeResponder::reverse_burst(&request).collect();
+        assert_eq!(burst.len(), MAX_REVERSE_PACKETS as usize);
+        assert!(burst.iter().all(|probe| probe.probe_id == 3
+            && probe.padding == MAX_SEGMENT_SIZE as u32
+            && !probe.is_reverse()));
+        assert!(burst.last().unwrap().is_last());
+    }
+}
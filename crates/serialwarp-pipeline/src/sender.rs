@@ -0,0 +1,227 @@
+//! Numbering and sending packets from any task
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use serialwarp_core::{metrics, Packet, PacketType, SequenceGenerator, TransportError};
+use serialwarp_transport::TransportSender;
+use tokio::sync::Mutex;
+
+/// How every packet a `PacketSender` sends is built
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PacketOptions {
+    pub compression: bool,
+    pub crc: bool,
+    pub stream_id: u8,
+}
+
+/// The sending half of a transport and its sequence numbers. A packet takes
+/// its number while holding the transport, so however many tasks send, the
+/// numbers go out in order with none skipped; a failed send still uses one.
+pub(crate) struct PacketSender {
+    transport: Mutex<Box<dyn TransportSender>>,
+    sequence: Arc<SequenceGenerator>,
+    options: PacketOptions,
+}
+
+impl PacketSender {
+    /// Number packets from `first_sequence`
+    pub fn new(
+        transport: Box<dyn TransportSender>,
+        first_sequence: u32,
+        options: PacketOptions,
+    ) -> Self {
+        Self::sharing(
+            transport,
+            Arc::new(SequenceGenerator::new(first_sequence)),
+            options,
+        )
+    }
+
+    /// Number packets from `sequence`, shared with the other senders on the
+    /// same transport
+    pub fn sharing(
+        transport: Box<dyn TransportSender>,
+        sequence: Arc<SequenceGenerator>,
+        options: PacketOptions,
+    ) -> Self {
+        Self {
+            transport: Mutex::new(transport),
+            sequence,
+            options,
+        }
+    }
+
+    /// Send a packet with the next sequence number, returning its size on
+    /// the wire
+    pub async fn send(
+        &self,
+        packet_type: PacketType,
+        payload: Bytes,
+    ) -> Result<u64, TransportError> {
+        let transport = self.transport.lock().await;
+        let bytes = self.packet(packet_type, payload).to_bytes();
+        let len = bytes.len() as u64;
+        transport.send(bytes).await?;
+        metrics::packet_sent(packet_type);
+        Ok(len)
+    }
+
+    /// Send a packet whose payload is the `payload` buffers joined, handing
+    /// the transport the packet in parts rather than copying them together.
+    /// A compressed payload has to be in one piece, so with compression on
+    /// this joins them and sends as `send` does.
+    pub async fn send_vectored(
+        &self,
+        packet_type: PacketType,
+        payload: &[Bytes],
+    ) -> Result<u64, TransportError> {
+        if self.options.compression {
+            return self.send(packet_type, payload.concat().into()).await;
+        }
+
+        let transport = self.transport.lock().await;
+        let header = self.packet(packet_type, Bytes::new()).header;
+        let parts = Packet::parts_with_payload(header, payload);
+        let len = parts.iter().map(Bytes::len).sum::<usize>() as u64;
+        transport.send_vectored(&parts).await?;
+        metrics::packet_sent(packet_type);
+        Ok(len)
+    }
+
+    /// Number and build a packet. Called with the transport held, so
+    /// numbers go out in order.
+    fn packet(&self, packet_type: PacketType, payload: Bytes) -> Packet {
+        let sequence = self.sequence.next();
+        let mut packet = if self.options.compression {
+            Packet::new_compressed(packet_type, 0, sequence, payload)
+        } else {
+            Packet::new(packet_type, 0, sequence, payload)
+        };
+        if !self.options.crc {
+            packet = packet.without_crc();
+        }
+        if self.options.stream_id != 0 {
+            packet = packet.with_stream_id(self.options.stream_id);
+        }
+        packet
+    }
+
+    /// Whether packets are compressed, which needs each payload in one piece
+    pub fn compresses(&self) -> bool {
+        self.options.compression
+    }
+
+    /// Next outgoing sequence number
+    pub fn sequence(&self) -> u32 {
+        self.sequence.peek()
+    }
+
+    /// The sequence numbers are taken from, to share with another sender on
+    /// the same transport
+    pub fn shared_sequence(&self) -> Arc<SequenceGenerator> {
+        Arc::clone(&self.sequence)
+    }
+
+    /// Number packets from `sequence` on
+    pub fn reset_sequence(&self, sequence: u32) {
+        self.sequence.reset(sequence);
+    }
+
+    /// Build packets with `options` from now on, e.g. after a new handshake
+    pub fn set_options(&mut self, options: PacketOptions) {
+        self.options = options;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use serialwarp_transport::{MockTransport, Transport};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_sends_numbered_in_order() {
+        const TASKS: u32 = 4;
+        const PACKETS: u32 = 100;
+
+        let (local, peer) = MockTransport::pair();
+        let options = PacketOptions {
+            compression: false,
+            crc: true,
+            stream_id: 0,
+        };
+        let sender = Arc::new(PacketSender::new(local.split().0, 10, options));
+        let reader = tokio::spawn(async move {
+            let mut sequences = Vec::new();
+            while sequences.len() < (TASKS * PACKETS) as usize {
+                let data = tokio::time::timeout(Duration::from_secs(5), peer.recv())
+                    .await
+                    .expect("packets missing")
+                    .unwrap();
+                sequences.push(Packet::parse(&data).unwrap().0.sequence());
+            }
+            sequences
+        });
+
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let sender = Arc::clone(&sender);
+                tokio::spawn(async move {
+                    for _ in 0..PACKETS {
+                        let payload = Bytes::from(vec![task as u8; 8]);
+                        sender.send(PacketType::Ping, payload).await.unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(sender.sequence(), 10 + TASKS * PACKETS);
+
+        let sequences = reader.await.unwrap();
+        assert_eq!(sequences, (10..10 + TASKS * PACKETS).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_vectored_send_matches_joined() {
+        let payload: Bytes = (0..5000).map(|i| (i % 13) as u8).collect::<Vec<_>>().into();
+        let parts = [payload.slice(..32), payload.slice(32..)];
+        for (compression, crc, stream_id) in [
+            (false, true, 0),
+            (false, false, 2),
+            (true, true, 0),
+            (true, false, 1),
+        ] {
+            let options = PacketOptions {
+                compression,
+                crc,
+                stream_id,
+            };
+            let (joined_local, joined_peer) = MockTransport::pair();
+            let (vectored_local, vectored_peer) = MockTransport::pair();
+            let joined = PacketSender::new(joined_local.split().0, 5, options);
+            let vectored = PacketSender::new(vectored_local.split().0, 5, options);
+
+            let joined_len = joined
+                .send(PacketType::Frame, payload.clone())
+                .await
+                .unwrap();
+            let vectored_len = vectored
+                .send_vectored(PacketType::Frame, &parts)
+                .await
+                .unwrap();
+            assert_eq!(vectored_len, joined_len);
+            assert_eq!(
+                vectored_peer.recv().await.unwrap(),
+                joined_peer.recv().await.unwrap(),
+                "{:?}",
+                options
+            );
+        }
+    }
+}
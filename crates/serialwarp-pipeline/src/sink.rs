@@ -0,0 +1,1091 @@
+//! Sink side: reassemble → decode → queue, returning credits as frames complete
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use serialwarp_core::{
+    contains_idr, error_codes, is_traced, metrics, unix_time_us, BandwidthProbePayload, ClockSync,
+    CreditUpdatePayload, DecodeEvent, DecodedFrame, EncodedFrame, ErrorPayload,
+    FrameAckBatchPayload, FrameAckEntry, FrameHeader, FrameReassembler, FrameTrace, Packet,
+    PacketType, PausePayload, PingPayload, PipelineError, ReassemblerConfig, ResilientDecoder,
+    Resolution, SequenceGenerator, SkipMode, StartAckPayload, TransportError, VideoDecoder,
+};
+use serialwarp_transport::{TransportHalves, TransportReceiver};
+use tracing::{debug, field, info, warn};
+
+use crate::credit::CreditGrants;
+use crate::handshake::NegotiatedStream;
+use crate::latency::{LatencyReport, LatencyStages, LatencyWindow, DEFAULT_LATENCY_WINDOW};
+use crate::probe::ProbeResponder;
+use crate::sender::{PacketOptions, PacketSender};
+use crate::trace::{frame_span, FRAME_TRACE_TARGET};
+use crate::watchdog::{StallEvent, StallWatchdog, DEFAULT_STALL_TIMEOUT};
+
+/// Decoded pictures followed until they're presented, more than any consumer
+/// holds; see `SinkPipeline::frame_presented`
+const PRESENTING_BACKLOG: usize = 64;
+
+/// Frames remembered after decoding until a picture with their pts comes
+/// out, more than any decoder holds back
+const DECODER_INPUT_BACKLOG: usize = 16;
+
+/// Frames found unrecoverable remembered until they're evicted, so a
+/// keyframe is asked for once per frame; more than the reassembler holds
+const LOST_FRAME_BACKLOG: usize = 16;
+
+/// Longer than any real capture to presentation time. A capture time further
+/// back is from a source that doesn't send wall-clock capture times.
+const MAX_LATENCY: Duration = Duration::from_secs(10);
+
+/// Sink pipeline configuration
+#[derive(Debug, Clone)]
+pub struct SinkPipelineConfig {
+    /// Nominal stream frame rate, used for the reassembly timeout
+    pub fps: u32,
+    /// Decoded frames held for the consumer; the oldest is dropped beyond this
+    pub queue_depth: usize,
+    /// Credits returned for each acknowledged frame
+    pub credits_per_frame: u16,
+    /// Credits the source was granted in START_ACK, from which the sink
+    /// counts the credits the source holds; see `SinkStats::source_credits`
+    pub initial_credits: u16,
+    /// Most credits withheld from acks while the decoded queue is full,
+    /// granted back with a CREDIT_UPDATE once it has drained to half, so a
+    /// consumer falling behind slows the source rather than having its
+    /// frames dropped late. The source is always left a credit; 0 returns
+    /// every frame's credits as it's acked, as does a source without
+    /// `credit_updates`.
+    pub max_withheld_credits: u16,
+    /// Frames acknowledged together in one FRAME_ACK; 1 acks every frame
+    /// as it completes
+    pub ack_batch: usize,
+    /// Longest a frame's ack waits for the rest of its batch
+    pub ack_batch_delay: Duration,
+    /// Whether sent packets carry a CRC; only turn this off when the
+    /// handshake found both ends support `capabilities::NO_CRC`
+    pub crc: bool,
+    /// Whether sent payloads are LZ4 compressed where that helps; only
+    /// turn this on when the handshake found both ends support
+    /// `capabilities::LZ4`
+    pub compression: bool,
+    /// Whether the source may be asked for a keyframe, e.g. after a decode
+    /// error; only turn this on when the handshake found both ends support
+    /// `capabilities::KEYFRAME_REQUEST`
+    pub keyframe_requests: bool,
+    /// Whether the source's credit window may be resized with
+    /// CREDIT_UPDATE; only turn this on when the handshake found both ends
+    /// support `capabilities::CREDIT_UPDATE`
+    pub credit_updates: bool,
+    /// Visible size from START; decoded frames are cropped to it, dropping
+    /// the padding an odd-sized source is coded with. Cleared if the stream
+    /// changes resolution, since it described the old one.
+    pub display_size: Option<Resolution>,
+    /// Stream whose FRAME packets this pipeline reassembles, and that its
+    /// acks and credit updates are tagged with; only nonzero when the
+    /// handshake found both ends support `capabilities::MULTI_STREAM`
+    pub stream_id: u8,
+    /// Backlog, in frames, past which the decoder skips `catch_up_mode`
+    /// frames until it has caught up; 0 never skips. See
+    /// `SinkPipeline::backlog`.
+    pub catch_up_backlog: usize,
+    /// Frames skipped while catching up
+    pub catch_up_mode: SkipMode,
+    /// Trace every Nth frame in full: its `sink_frame` span is raised from
+    /// TRACE to DEBUG, and a `FrameTrace` of its reassembly, decode and
+    /// present times is logged at DEBUG under `FRAME_TRACE_TARGET`; 0
+    /// traces none
+    pub trace_frames: u32,
+    /// Span of the rolling statistics from `SinkPipeline::latency_report`
+    pub latency_window: Duration,
+    /// How long the stream may go without a frame before
+    /// `SinkPipeline::check_stall` reports it stalled; `None` doesn't watch
+    /// for stalls
+    pub stall_timeout: Option<Duration>,
+}
+
+impl Default for SinkPipelineConfig {
+    fn default() -> Self {
+        Self {
+            fps: 60,
+            queue_depth: 3,
+            credits_per_frame: 1,
+            initial_credits: StartAckPayload::DEFAULT_CREDITS,
+            max_withheld_credits: 0,
+            ack_batch: 1,
+            ack_batch_delay: Duration::from_millis(4),
+            crc: true,
+            compression: false,
+            keyframe_requests: false,
+            credit_updates: false,
+            display_size: None,
+            stream_id: 0,
+            catch_up_backlog: 0,
+            catch_up_mode: SkipMode::NonKey,
+            trace_frames: 0,
+            latency_window: DEFAULT_LATENCY_WINDOW,
+            stall_timeout: Some(DEFAULT_STALL_TIMEOUT),
+        }
+    }
+}
+
+impl SinkPipelineConfig {
+    /// Credits that may be withheld, none unless they can be granted back
+    fn max_withheld(&self) -> u32 {
+        if self.credit_updates {
+            self.max_withheld_credits as u32
+        } else {
+            0
+        }
+    }
+}
+
+/// What a received packet turned into
+#[derive(Debug)]
+pub enum SinkOutput {
+    /// A frame completed and went through the decoder; any pictures it
+    /// produced are available from `next_decoded_frame`
+    Frame {
+        frame: EncodedFrame,
+        pictures: usize,
+        decode_time: Duration,
+        /// What the decoder noticed in this frame, e.g. a resolution change
+        /// that the pictures already have
+        events: Vec<DecodeEvent>,
+    },
+    /// A segment of a frame that's still incomplete (or was rejected)
+    Segment,
+    /// A non-FRAME packet for the caller to handle
+    Control(Packet),
+}
+
+/// Sink pipeline counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    pub segments_received: u64,
+    pub bytes_received: u64,
+    /// Frames fully reassembled
+    pub frames_received: u64,
+    /// Pictures produced by the decoder
+    pub frames_decoded: u64,
+    /// Decoded frames the consumer reported presenting
+    pub frames_presented: u64,
+    pub decode_errors: u64,
+    /// Frames discarded by the decoder while waiting for a keyframe
+    pub frames_awaiting_keyframe: u64,
+    /// Frames the decoder skipped to catch up with a backlog
+    pub frames_skipped: u64,
+    /// Decoded frames discarded because the consumer fell behind
+    pub frames_dropped_late: u64,
+    /// Incomplete frames evicted by the reassembler
+    pub frames_evicted: u64,
+    /// Frames acknowledged
+    pub acks_sent: u64,
+    /// FRAME_ACK packets sent, fewer than `acks_sent` when batching
+    pub ack_packets_sent: u64,
+    pub credit_updates_sent: u64,
+    /// Credits the source holds, as far as the sink can tell: the window,
+    /// less the frames that arrived or were given up on, plus the credits
+    /// returned since
+    pub source_credits: i64,
+    /// Credits withheld while the decoded queue is full, not yet granted
+    /// back
+    pub credits_withheld: u32,
+    /// Times `source_credits` ran out
+    pub credit_starvations: u64,
+    pub keyframe_requests: u64,
+    /// Times frames stopped arriving for `stall_timeout`
+    pub stalls: u64,
+    pub decode_time: Duration,
+    /// Decoded pictures whose capture time the source sent, in a
+    /// `LatencySei` or the FRAME header
+    pub latency_samples: u64,
+    /// Capture to decode time summed over `latency_samples` pictures
+    pub total_latency: Duration,
+}
+
+impl SinkStats {
+    /// Average capture to decode time, if the source sends capture times
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.latency_samples > 0).then(|| {
+            let average_us = self.total_latency.as_micros() / self.latency_samples as u128;
+            Duration::from_micros(average_us as u64)
+        })
+    }
+}
+
+/// Runs the sink side of a stream.
+///
+/// The caller receives packets (typically with a timeout, so it can keep a
+/// render loop going) and hands them to `handle_packet`. FRAME packets are
+/// reassembled, decoded, queued, and acknowledged with credits; everything
+/// else comes back as `SinkOutput::Control`. Decoded frames are taken with
+/// `next_decoded_frame`.
+///
+/// While the decoded queue is full, up to `max_withheld_credits` of the
+/// acked frames' credits are held back, and granted back with a
+/// CREDIT_UPDATE once the queue has drained to half. That's checked as
+/// packets are handled and by `recv` and `flush_due_acks`, so a caller
+/// draining the queue should keep calling one of them.
+///
+/// With `ack_batch` above 1, acks are held until the batch fills or its
+/// deadline passes. `recv` sends them on time by itself; a caller feeding
+/// `handle_packet` should call `flush_due_acks` from its idle loop.
+///
+/// If the frames waiting build up past `catch_up_backlog`, the decoder
+/// skips frames until the backlog is down to half that. Skipping non-key
+/// frames asks the source for a keyframe and instead carries on until one
+/// arrives with the backlog back under `catch_up_backlog`, since the frames
+/// in between would reference ones never decoded.
+///
+/// Pictures are timed from capture to decode against the source's clock,
+/// as estimated from its PINGs, which still come back as
+/// `SinkOutput::Control` for the caller to answer. The capture time comes
+/// from a `LatencySei` if the stream has one, else the FRAME header. A
+/// caller that reports each picture it presents to `frame_presented` gets
+/// glass-to-glass figures from `latency_report` too.
+///
+/// A stream whose frames stop while the transport stays up, e.g. with the
+/// source's encoder wedged, is found by `check_stall`, which the caller
+/// should call from its idle loop.
+///
+/// The decoder runs on the caller's thread, so the pipeline is not `Send`
+/// unless the decoder is.
+pub struct SinkPipeline {
+    config: SinkPipelineConfig,
+    sender: PacketSender,
+    receiver: Box<dyn TransportReceiver>,
+    reassembler: FrameReassembler,
+    decoder: ResilientDecoder<Box<dyn VideoDecoder>>,
+    skip_mode: SkipMode,
+    queue: VecDeque<DecodedFrame>,
+    /// Frames the consumer holds past `queue`, from `set_consumer_backlog`
+    consumer_backlog: usize,
+    pending_acks: Vec<FrameAckEntry>,
+    credits: CreditGrants,
+    /// When the oldest pending ack must go out
+    ack_deadline: Option<Instant>,
+    /// Frames given to the decoder whose pictures may still come out,
+    /// oldest first
+    decoder_inputs: VecDeque<DecoderInput>,
+    /// Frames found unrecoverable and not yet evicted, oldest first
+    lost_frames: VecDeque<u64>,
+    clock: ClockSync,
+    /// Decoded pictures not yet presented, oldest first
+    presenting: VecDeque<Presenting>,
+    latency: LatencyWindow,
+    /// Bandwidth probe burst being timed
+    probes: ProbeResponder,
+    /// Watches for frames to stop, if `stall_timeout` is set
+    watchdog: Option<StallWatchdog>,
+    stats: SinkStats,
+}
+
+/// A frame given to the decoder, so its pictures can be matched to it by pts
+#[derive(Debug, Clone, Copy)]
+struct DecoderInput {
+    pts_us: u64,
+    /// Frame number from the FRAME header
+    frame_number: u64,
+    /// Capture time from the FRAME header, on the source's clock
+    capture_ts_us: u64,
+}
+
+/// A decoded picture on its way to being presented
+#[derive(Debug)]
+struct Presenting {
+    /// Frame number of the picture, as the source numbered it
+    picture: u64,
+    /// Capture time on the source's clock
+    capture_ts_us: Option<u64>,
+    /// Reassembly and decode times, the rest being filled in once presented
+    stages: LatencyStages,
+    decoded_at: Instant,
+    /// When the consumer took it from the queue
+    taken_at: Option<Instant>,
+    /// Set for frames traced in full
+    trace: Option<FrameTrace>,
+}
+
+impl SinkPipeline {
+    /// Create a pipeline whose outgoing packets continue from `sequence`
+    /// (the next sequence number after the handshake)
+    pub fn new(
+        (sender, receiver): TransportHalves,
+        decoder: Box<dyn VideoDecoder>,
+        config: SinkPipelineConfig,
+        sequence: u32,
+    ) -> Self {
+        Self::sharing_sequence(
+            (sender, receiver),
+            decoder,
+            config,
+            Arc::new(SequenceGenerator::new(sequence)),
+        )
+    }
+
+    /// Create a pipeline for another stream on the same transport as
+    /// `sequence`'s, numbering its packets from the same generator so no
+    /// number goes out twice. A restart of any of them resets it for all.
+    pub fn sharing_sequence(
+        (sender, receiver): TransportHalves,
+        decoder: Box<dyn VideoDecoder>,
+        config: SinkPipelineConfig,
+        sequence: Arc<SequenceGenerator>,
+    ) -> Self {
+        let options = PacketOptions {
+            compression: config.compression,
+            crc: config.crc,
+            stream_id: config.stream_id,
+        };
+        Self {
+            sender: PacketSender::sharing(sender, sequence, options),
+            reassembler: FrameReassembler::with_config(ReassemblerConfig::for_fps(config.fps)),
+            decoder: ResilientDecoder::new(decoder),
+            skip_mode: SkipMode::None,
+            queue: VecDeque::with_capacity(config.queue_depth.max(1)),
+            consumer_backlog: 0,
+            pending_acks: Vec::new(),
+            credits: CreditGrants::new(config.initial_credits, config.max_withheld()),
+            ack_deadline: None,
+            latency: LatencyWindow::new(config.latency_window),
+            watchdog: config
+                .stall_timeout
+                .map(|timeout| StallWatchdog::new(timeout, Instant::now())),
+            config,
+            receiver,
+            decoder_inputs: VecDeque::new(),
+            lost_frames: VecDeque::new(),
+            clock: ClockSync::new(),
+            presenting: VecDeque::new(),
+            probes: ProbeResponder::default(),
+            stats: SinkStats::default(),
+        }
+    }
+
+    /// Start over after the source reconnects: new decoder, frame rate,
+    /// display size, packet options and source capabilities from its
+    /// handshake, empty
+    /// reassembler and queue. Stats keep accumulating.
+    /// Acks and credits still held for the old stream are dropped, since the
+    /// new one starts with a fresh window of `initial_credits`, and the
+    /// source's clock is estimated anew. The stall watchdog starts over,
+    /// ending any stall and resuming if it was suspended.
+    pub fn restart(
+        &mut self,
+        decoder: Box<dyn VideoDecoder>,
+        negotiated: &NegotiatedStream,
+        sequence: u32,
+    ) {
+        let fps = negotiated.start.fps();
+        self.config.fps = fps;
+        self.config.display_size = Some(negotiated.start.display_size());
+        self.config.keyframe_requests = negotiated.keyframe_requests;
+        self.config.credit_updates = negotiated.credit_updates;
+        self.config.crc = negotiated.crc;
+        self.config.compression = negotiated.compression;
+        self.sender.set_options(PacketOptions {
+            compression: negotiated.compression,
+            crc: negotiated.crc,
+            stream_id: self.config.stream_id,
+        });
+        self.reassembler = FrameReassembler::with_config(ReassemblerConfig::for_fps(fps));
+        self.decoder = ResilientDecoder::new(decoder);
+        self.skip_mode = SkipMode::None;
+        self.queue.clear();
+        self.consumer_backlog = 0;
+        self.pending_acks.clear();
+        self.credits
+            .reset(self.config.initial_credits, self.config.max_withheld());
+        self.ack_deadline = None;
+        self.sender.reset_sequence(sequence);
+        self.decoder_inputs.clear();
+        self.lost_frames.clear();
+        self.clock.reset();
+        self.presenting.clear();
+        self.latency.clear();
+        if let Some(watchdog) = &mut self.watchdog {
+            let now = Instant::now();
+            watchdog.resume(now);
+            watchdog.progress(now);
+        }
+    }
+
+    /// Receive one packet from the transport and handle it, sending batched
+    /// acks whose deadline passes while waiting
+    pub async fn recv(&mut self) -> Result<SinkOutput, PipelineError> {
+        self.grant_withheld().await;
+        let data = loop {
+            let Some(deadline) = self.ack_deadline else {
+                break self.receiver.recv().await?;
+            };
+            tokio::select! {
+                data = self.receiver.recv() => break data?,
+                _ = tokio::time::sleep_until(deadline.into()) => self.flush_acks().await,
+            }
+        };
+        let (packet, _) = Packet::parse(&data)?;
+        self.handle_packet(packet).await
+    }
+
+    /// Handle a received packet
+    pub async fn handle_packet(&mut self, packet: Packet) -> Result<SinkOutput, PipelineError> {
+        self.reap(Instant::now()).await;
+        self.grant_withheld().await;
+        metrics::packet_received(packet.packet_type());
+        if packet.packet_type() == PacketType::Ping {
+            if let Ok(ping) = PingPayload::parse(&packet.payload) {
+                self.clock.observe(ping.timestamp_us, unix_time_us());
+            }
+        }
+        if packet.packet_type() == PacketType::BandwidthProbe {
+            self.answer_probe(&packet).await;
+        }
+        if packet.packet_type() == PacketType::Pause {
+            match PausePayload::parse(&packet.payload) {
+                Ok(pause) => {
+                    // No frames is what the source said to expect, not a stall
+                    debug!(
+                        "Source {} frames",
+                        if pause.paused { "paused" } else { "resumed" }
+                    );
+                    self.set_watchdog_suspended(pause.paused);
+                }
+                Err(e) => warn!("Dropping malformed PAUSE: {}", e),
+            }
+        }
+        if packet.packet_type() == PacketType::Pong && self.is_stalled() {
+            info!("Source answered while stalled, so the link is up");
+        }
+        if packet.packet_type() != PacketType::Frame {
+            return Ok(SinkOutput::Control(packet));
+        }
+
+        // Each stream needs its own reassembler and decoder, i.e. pipeline
+        if packet.stream_id() != self.config.stream_id {
+            warn!("Rejected segment for stream {}", packet.stream_id());
+            return Ok(SinkOutput::Segment);
+        }
+
+        self.stats.segments_received += 1;
+        self.stats.bytes_received += packet.payload.len() as u64;
+
+        let header = match FrameHeader::parse(&packet.payload) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Rejected frame segment: {}", e);
+                return Ok(SinkOutput::Segment);
+            }
+        };
+        let data = packet.payload.slice(FrameHeader::SIZE..);
+        let frame = match self.reassembler.add_segment(&header, data) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                self.report_unrecoverable().await;
+                return Ok(SinkOutput::Segment);
+            }
+            Err(e) => {
+                warn!("Rejected frame segment: {}", e);
+                self.ack_rejected().await;
+                return Ok(SinkOutput::Segment);
+            }
+        };
+        self.stats.frames_received += 1;
+        self.credits.frame_used();
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.progress(Instant::now());
+        }
+
+        let traced = is_traced(header.frame_number, self.config.trace_frames);
+        let mut trace = FrameTrace::new(header.frame_number);
+        trace.stage("reassemble", self.reassembler.last_assembly_time());
+        let span = frame_span!(
+            traced,
+            "sink_frame",
+            frame = header.frame_number,
+            segments = header.segment_count,
+            reassemble_us = self.reassembler.last_assembly_time().as_micros() as u64,
+            decode_us = field::Empty,
+            pictures = field::Empty,
+        );
+        self.catch_up(contains_idr(&frame.data)).await;
+
+        if self.decoder_inputs.len() == DECODER_INPUT_BACKLOG {
+            self.decoder_inputs.pop_front();
+        }
+        let input = DecoderInput {
+            pts_us: frame.metadata.pts_us,
+            frame_number: header.frame_number,
+            capture_ts_us: frame.metadata.capture_ts_us,
+        };
+        self.decoder_inputs.push_back(input);
+
+        let start = Instant::now();
+        let was_waiting = self.decoder.needs_keyframe();
+        let dropped_before = self.decoder.frames_dropped();
+        let result = span.in_scope(|| {
+            self.decoder
+                .decode(&frame.data, frame.metadata.pts_us as i64)
+        });
+        let decode_time = start.elapsed();
+        span.record("decode_us", decode_time.as_micros() as u64);
+        trace.stage("decode", decode_time);
+        self.stats.decode_time += decode_time;
+        metrics::decode_time(decode_time);
+        let awaiting_keyframe = self.decoder.frames_dropped() - dropped_before;
+        self.stats.frames_awaiting_keyframe += awaiting_keyframe;
+        if awaiting_keyframe > 0 {
+            metrics::frames_dropped(metrics::DropReason::AwaitingKeyframe, awaiting_keyframe);
+        }
+
+        let (pictures, events) = match result {
+            Ok(output) => {
+                for event in &output.events {
+                    let DecodeEvent::ResolutionChanged { old, new } = event;
+                    info!("Stream resolution changed from {} to {}", old, new);
+                    self.config.display_size = None;
+                }
+                let count = output.frames.len();
+                self.stats.frames_decoded += count as u64;
+                metrics::frames_decoded(count as u64);
+                if count == 0 && self.skip_mode != SkipMode::None {
+                    self.stats.frames_skipped += 1;
+                    metrics::frames_dropped(metrics::DropReason::CatchUp, 1);
+                }
+                let stages = LatencyStages {
+                    reassembly: self.reassembler.last_assembly_time(),
+                    decode: decode_time,
+                    ..Default::default()
+                };
+                for (index, mut picture) in output.frames.into_iter().enumerate() {
+                    // A decoder with latency outputs pictures for earlier
+                    // frames, so number each after the frame it came from
+                    let input = self.take_decoder_input(picture.pts_us).unwrap_or(input);
+                    picture.set_frame_number(input.frame_number);
+                    if picture.source_timestamp_us.is_none() {
+                        picture.source_timestamp_us = Some(input.capture_ts_us);
+                    }
+                    if let Some(latency) = self.latency(&picture) {
+                        self.stats.latency_samples += 1;
+                        self.stats.total_latency += latency;
+                        metrics::latency(latency);
+                    }
+                    if let Some(size) = self.config.display_size {
+                        picture.crop(size.width, size.height);
+                    }
+                    // The frame's trace goes with its first picture
+                    let trace = (traced && index == 0).then(|| trace.clone());
+                    self.follow(&picture, stages, trace);
+                    self.enqueue(picture);
+                }
+                (count, output.events)
+            }
+            Err(e) => {
+                warn!("Decode error: {}", e);
+                self.stats.decode_errors += 1;
+                if !was_waiting && self.decoder.needs_keyframe() {
+                    self.request_keyframe(&e.to_string()).await;
+                }
+                (0, Vec::new())
+            }
+        };
+        span.record("pictures", pictures);
+        if traced && pictures == 0 {
+            // Nothing to present, so the trace ends here
+            span.in_scope(
+                || debug!(target: FRAME_TRACE_TARGET, frame = trace.frame_number, "{}", trace),
+            );
+        }
+
+        // The credit comes back whether or not the frame decoded, or the
+        // source would eventually stall
+        self.queue_ack(FrameAckEntry {
+            frame_number: header.frame_number,
+            decode_time_us: decode_time.as_micros() as u32,
+        })
+        .await;
+
+        Ok(SinkOutput::Frame {
+            frame,
+            pictures,
+            decode_time,
+            events,
+        })
+    }
+
+    /// Take the oldest decoded frame, numbered as the source numbered the
+    /// frame it was decoded from
+    pub fn next_decoded_frame(&mut self) -> Option<DecodedFrame> {
+        let frame = self.queue.pop_front()?;
+        if let Some(presenting) = self
+            .presenting
+            .iter_mut()
+            .find(|presenting| presenting.picture == frame.frame_number)
+        {
+            presenting.taken_at = Some(Instant::now());
+        }
+        Some(frame)
+    }
+
+    /// Tell the pipeline decoded frame `frame_number` was just presented,
+    /// returning its glass-to-glass latency if the source sent its capture
+    /// time. That latency, split into stages, goes into `latency_report`.
+    /// For a frame traced in full, the time since it was decoded is added to
+    /// its `FrameTrace` as the `present` stage, and the trace is logged.
+    /// Frames never presented are forgotten.
+    pub fn frame_presented(&mut self, frame_number: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let mut presented = None;
+        while self
+            .presenting
+            .front()
+            .is_some_and(|presenting| presenting.picture <= frame_number)
+        {
+            let presenting = self.presenting.pop_front().unwrap();
+            if presenting.picture == frame_number {
+                self.stats.frames_presented += 1;
+                presented = Some(presenting);
+            }
+        }
+        let Presenting {
+            capture_ts_us,
+            mut stages,
+            decoded_at,
+            taken_at,
+            trace,
+            ..
+        } = presented?;
+
+        if let Some(mut trace) = trace {
+            trace.stage("present", now.saturating_duration_since(decoded_at));
+            debug!(target: FRAME_TRACE_TARGET, frame = trace.frame_number, "{}", trace);
+        }
+
+        let total = self.clock_latency(capture_ts_us?)?;
+        let taken_at = taken_at.unwrap_or(now);
+        stages.queue = taken_at.saturating_duration_since(decoded_at);
+        stages.present = now.saturating_duration_since(taken_at);
+        stages.transport = total.saturating_sub(stages.total());
+        self.latency.record(now, stages);
+        Some(total)
+    }
+
+    /// Glass-to-glass latency over the last `latency_window`, of the frames
+    /// reported to `frame_presented`
+    pub fn latency_report(&mut self) -> Option<LatencyReport> {
+        self.latency.report(Instant::now())
+    }
+
+    /// Tell the pipeline how many frames the consumer is holding after
+    /// taking them, e.g. in a `FramePacer`, so they count towards the
+    /// backlog
+    pub fn set_consumer_backlog(&mut self, frames: usize) {
+        self.consumer_backlog = frames;
+    }
+
+    /// Frames waiting: decoded and not yet shown, plus partly reassembled
+    pub fn backlog(&self) -> usize {
+        self.queue.len() + self.consumer_backlog + self.reassembler.pending_frames()
+    }
+
+    /// Frames the decoder is currently skipping
+    pub fn skip_mode(&self) -> SkipMode {
+        self.skip_mode
+    }
+
+    /// Time from a frame's capture until now, if the source sent its
+    /// capture time
+    pub fn latency(&self, frame: &DecodedFrame) -> Option<Duration> {
+        self.clock_latency(frame.source_timestamp_us?)
+    }
+
+    /// Drop incomplete frames whose remaining segments never arrived. Each
+    /// is acked all the same, so its credit comes back, and the source is
+    /// asked for a keyframe, since the frames after may reference them,
+    /// unless it was asked when the frame was found unrecoverable. Called
+    /// on every packet; call it from idle loops too.
+    pub async fn reap(&mut self, now: Instant) {
+        self.report_unrecoverable().await;
+        let evicted = self.reassembler.reap(now);
+        if evicted.is_empty() {
+            return;
+        }
+        warn!("Dropped incomplete frames: {:?}", evicted);
+        self.stats.frames_evicted += evicted.len() as u64;
+        let mut asked = true;
+        for frame_number in evicted {
+            match self
+                .lost_frames
+                .iter()
+                .position(|&lost| lost == frame_number)
+            {
+                Some(index) => {
+                    self.lost_frames.remove(index);
+                }
+                None => asked = false,
+            }
+            self.credits.frame_used();
+            self.queue_ack(FrameAckEntry {
+                frame_number,
+                decode_time_us: 0,
+            })
+            .await;
+        }
+        if !asked {
+            self.request_keyframe("frames lost").await;
+        }
+    }
+
+    /// Acknowledge frames the reassembler rejected on completion, so the
+    /// source gets their credits back, and ask for a keyframe to replace
+    /// them
+    async fn ack_rejected(&mut self) {
+        let rejected = self.reassembler.take_rejected();
+        if rejected.is_empty() {
+            return;
+        }
+        for frame_number in rejected {
+            self.credits.frame_used();
+            self.queue_ack(FrameAckEntry {
+                frame_number,
+                decode_time_us: 0,
+            })
+            .await;
+        }
+        self.request_keyframe("frames rejected").await;
+    }
+
+    /// Ask for a keyframe as soon as a frame is found missing more than
+    /// parity can rebuild, rather than once it times out
+    async fn report_unrecoverable(&mut self) {
+        let lost = self.reassembler.take_unrecoverable();
+        if lost.is_empty() {
+            return;
+        }
+        warn!("Frames lost beyond what parity rebuilds: {:?}", lost);
+        for frame_number in lost {
+            if self.lost_frames.len() == LOST_FRAME_BACKLOG {
+                self.lost_frames.pop_front();
+            }
+            self.lost_frames.push_back(frame_number);
+        }
+        self.request_keyframe("frames unrecoverable").await;
+    }
+
+    /// Report whether the stream has stalled, with frames stopped for
+    /// `stall_timeout` though the transport is up, or has recovered from a
+    /// stall. Each time the stall is found, once per timeout while it lasts,
+    /// the source is asked for a keyframe, in case it's waiting to send one
+    /// or the frames are arriving undecodable, and then PINGed, which a
+    /// source still there answers. Call it from idle loops.
+    pub async fn check_stall(&mut self, now: Instant) -> Option<StallEvent> {
+        let event = self.watchdog.as_mut()?.check(now)?;
+        match event {
+            StallEvent::Stalled { waited, probes } => {
+                if probes == 1 {
+                    warn!("Stream stalled: no frame in {:?}", waited);
+                    self.stats.stalls += 1;
+                }
+                self.request_keyframe("stream stalled").await;
+                let ping = PingPayload::new(unix_time_us());
+                if let Err(e) = self.send_packet(PacketType::Ping, ping.to_bytes()).await {
+                    warn!("Failed to send PING: {}", e);
+                }
+            }
+            StallEvent::Recovered { stalled_for } => {
+                info!("Stream recovered after stalling for {:?}", stalled_for);
+            }
+        }
+        Some(event)
+    }
+
+    /// Stop or start watching for a stall, e.g. while the source is paused
+    /// or waiting to reconnect with a new configuration; the wait for a
+    /// frame starts over on resuming. A stall already found is reported
+    /// recovered by the next `check_stall`.
+    pub fn set_watchdog_suspended(&mut self, suspended: bool) {
+        let Some(watchdog) = &mut self.watchdog else {
+            return;
+        };
+        if suspended {
+            watchdog.suspend();
+        } else {
+            watchdog.resume(Instant::now());
+        }
+    }
+
+    /// Whether the stream is stalled, as last found by `check_stall`
+    pub fn is_stalled(&self) -> bool {
+        self.watchdog
+            .as_ref()
+            .is_some_and(StallWatchdog::is_stalled)
+    }
+
+    /// Send the held acks if their deadline has passed, and grant back
+    /// withheld credits if the decoded queue has drained
+    pub async fn flush_due_acks(&mut self, now: Instant) {
+        if self.ack_deadline.is_some_and(|deadline| now >= deadline) {
+            self.flush_acks().await;
+        }
+        self.grant_withheld().await;
+    }
+
+    /// Send the held acks now, as one FRAME_ACK
+    pub async fn flush_acks(&mut self) {
+        self.ack_deadline = None;
+        if self.pending_acks.is_empty() {
+            return;
+        }
+
+        let entries = std::mem::take(&mut self.pending_acks);
+        let count = entries.len();
+        let credits = count as u16 * self.config.credits_per_frame;
+        let credits = self.credits.ack(credits, self.queue_headroom());
+        let ack = FrameAckBatchPayload::new(entries, credits);
+        match self.send_packet(PacketType::FrameAck, ack.to_bytes()).await {
+            Ok(()) => {
+                self.stats.acks_sent += count as u64;
+                self.stats.ack_packets_sent += 1;
+            }
+            Err(e) => warn!("Failed to send FRAME_ACK: {}", e),
+        }
+    }
+
+    /// Grow or shrink the source's credit window by `delta`, e.g. shrink it
+    /// while the decoded queue stays full and grow it back once the consumer
+    /// catches up. Does nothing unless `credit_updates` is on, since a
+    /// source without it can't parse a CREDIT_UPDATE.
+    pub async fn update_credits(&mut self, delta: i32) -> Result<(), TransportError> {
+        if !self.config.credit_updates {
+            debug!("Source takes no CREDIT_UPDATE, keeping its window");
+            return Ok(());
+        }
+        let update = CreditUpdatePayload::new(delta);
+        self.send_packet(PacketType::CreditUpdate, update.to_bytes())
+            .await?;
+        self.credits.updated(delta);
+        self.stats.credit_updates_sent += 1;
+        Ok(())
+    }
+
+    /// Time a BANDWIDTH_PROBE, acking its burst once the last is in, or send
+    /// a burst back if it asks for one
+    async fn answer_probe(&mut self, packet: &Packet) {
+        let probe = match BandwidthProbePayload::parse(&packet.payload) {
+            Ok(probe) => probe,
+            Err(e) => {
+                warn!("Rejected BANDWIDTH_PROBE: {}", e);
+                return;
+            }
+        };
+        if probe.is_reverse() {
+            for reply in ProbeResponder::reverse_burst(&probe) {
+                let result = self
+                    .send_packet(PacketType::BandwidthProbe, reply.to_bytes())
+                    .await;
+                if let Err(e) = result {
+                    warn!("Failed to send BANDWIDTH_PROBE: {}", e);
+                    return;
+                }
+            }
+            return;
+        }
+
+        let now = tokio::time::Instant::now();
+        let size = packet.header.packet_size();
+        if let Some(ack) = self.probes.received(&probe, size, now) {
+            debug!(
+                "Bandwidth probe {}: {} packets, {} bytes in {}us",
+                ack.probe_id, ack.packets_received, ack.bytes_received, ack.elapsed_us
+            );
+            let result = self
+                .send_packet(PacketType::BandwidthProbeAck, ack.to_bytes())
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to send BANDWIDTH_PROBE_ACK: {}", e);
+            }
+        }
+    }
+
+    /// Send a packet with the next sequence number
+    pub async fn send_packet(
+        &mut self,
+        packet_type: PacketType,
+        payload: Bytes,
+    ) -> Result<(), TransportError> {
+        self.sender.send(packet_type, payload).await?;
+        Ok(())
+    }
+
+    /// Next outgoing sequence number
+    pub fn sequence(&self) -> u32 {
+        self.sender.sequence()
+    }
+
+    /// The generator this pipeline numbers its packets from, for pipelines
+    /// of other streams on the same transport to share
+    pub fn shared_sequence(&self) -> Arc<SequenceGenerator> {
+        self.sender.shared_sequence()
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        SinkStats {
+            source_credits: self.credits.source_credits(),
+            credits_withheld: self.credits.withheld(),
+            credit_starvations: self.credits.starvations(),
+            ..self.stats
+        }
+    }
+
+    /// Reassembler counters
+    pub fn reassembler(&self) -> &FrameReassembler {
+        &self.reassembler
+    }
+
+    /// Raise or drop the decoder's skip mode for the backlog, before
+    /// decoding the next frame
+    async fn catch_up(&mut self, is_keyframe: bool) {
+        let threshold = self.config.catch_up_backlog;
+        if threshold == 0 {
+            return;
+        }
+
+        let backlog = self.backlog();
+        let mode = if backlog > threshold {
+            self.config.catch_up_mode.max(self.skip_mode)
+        } else if self.skip_mode == SkipMode::NonKey {
+            // Frames after a skipped one may reference it, so only a
+            // keyframe ends the skipping
+            if !is_keyframe {
+                return;
+            }
+            SkipMode::None
+        } else if backlog <= threshold / 2 {
+            SkipMode::None
+        } else {
+            return;
+        };
+        if mode == self.skip_mode {
+            // Still behind at a keyframe; ask for another rather than wait
+            // out a whole keyframe interval
+            if mode == SkipMode::NonKey && is_keyframe {
+                self.request_keyframe("catching up").await;
+            }
+            return;
+        }
+
+        if let Err(e) = self.decoder.set_skip_mode(mode) {
+            warn!("Failed to change skip mode: {}", e);
+            return;
+        }
+        debug!("Backlog of {} frames, skipping {:?}", backlog, mode);
+        self.skip_mode = mode;
+        if mode == SkipMode::NonKey {
+            self.request_keyframe("catching up").await;
+        }
+    }
+
+    /// Time from `capture_ts_us` on the source's clock until now
+    fn clock_latency(&self, capture_ts_us: u64) -> Option<Duration> {
+        self.clock
+            .latency(capture_ts_us, unix_time_us())
+            .filter(|latency| *latency <= MAX_LATENCY)
+    }
+
+    /// Forget the frame given to the decoder with `pts_us`, returning it
+    fn take_decoder_input(&mut self, pts_us: u64) -> Option<DecoderInput> {
+        let index = self
+            .decoder_inputs
+            .iter()
+            .position(|input| input.pts_us == pts_us)?;
+        self.decoder_inputs.remove(index)
+    }
+
+    /// Follow a decoded picture until it's presented
+    fn follow(&mut self, picture: &DecodedFrame, stages: LatencyStages, trace: Option<FrameTrace>) {
+        if self.presenting.len() >= PRESENTING_BACKLOG {
+            self.presenting.pop_front();
+        }
+        self.presenting.push_back(Presenting {
+            picture: picture.frame_number,
+            capture_ts_us: picture.source_timestamp_us,
+            stages,
+            decoded_at: Instant::now(),
+            taken_at: None,
+            trace,
+        });
+    }
+
+    /// Decoded frames that fit in the queue before it's full, counting
+    /// those the consumer holds
+    fn queue_headroom(&self) -> usize {
+        self.config
+            .queue_depth
+            .max(1)
+            .saturating_sub(self.queue.len() + self.consumer_backlog)
+    }
+
+    /// Grant back withheld credits once the decoded queue has drained
+    async fn grant_withheld(&mut self) {
+        let depth = self.config.queue_depth.max(1);
+        let Some(credits) = self.credits.drained(self.queue_headroom(), depth) else {
+            return;
+        };
+        debug!("Decoded queue drained, granting {} credits", credits);
+        if let Err(e) = self.update_credits(credits as i32).await {
+            warn!("Failed to send CREDIT_UPDATE: {}", e);
+        }
+    }
+
+    fn enqueue(&mut self, frame: DecodedFrame) {
+        if self.queue.len() >= self.config.queue_depth.max(1) {
+            self.queue.pop_front();
+            self.stats.frames_dropped_late += 1;
+            metrics::frames_dropped(metrics::DropReason::Late, 1);
+        }
+        self.queue.push_back(frame);
+    }
+
+    /// Hold a frame's ack for the current batch, sending the batch once it
+    /// is full or overdue
+    async fn queue_ack(&mut self, entry: FrameAckEntry) {
+        let now = Instant::now();
+        self.pending_acks.push(entry);
+        let deadline = *self
+            .ack_deadline
+            .get_or_insert(now + self.config.ack_batch_delay);
+
+        // Keep the batch's total credits within the FRAME_ACK's u16
+        let max_credits = u16::MAX / self.config.credits_per_frame.max(1);
+        let limit = self
+            .config
+            .ack_batch
+            .clamp(1, FrameAckBatchPayload::MAX_ENTRIES)
+            .min(max_credits as usize);
+        if self.pending_acks.len() >= limit || now >= deadline {
+            self.flush_acks().await;
+        }
+    }
+
+    /// Ask the source for a keyframe, e.g. after a decode error. The
+    /// protocol has no dedicated request, so this is a non-fatal
+    /// DECODER_FAILED error, which a source should answer with a keyframe.
+    /// Only sent with `keyframe_requests` on, since a source without it
+    /// may not know the ERROR packet at all.
+    async fn request_keyframe(&mut self, reason: &str) {
+        if !self.config.keyframe_requests {
+            debug!("Source takes no keyframe requests ({})", reason);
+            return;
+        }
+        debug!("Requesting keyframe");
+        self.stats.keyframe_requests += 1;
+        let payload = ErrorPayload::new(error_codes::DECODER_FAILED, false, reason);
+        if let Err(e) = self
+            .send_packet(PacketType::Error, payload.to_bytes())
+            .await
+        {
+            warn!("Failed to send keyframe request: {}", e);
+        }
+    }
+}
@@ -0,0 +1,1333 @@
+//! Source side: capture → encode → segment → send, gated by sink credits
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle as ThreadHandle;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use serialwarp_core::{
+    error_codes, is_traced, metrics, unix_time_us, BufferPool, CaptureError, CreditUpdatePayload,
+    EncodedFrame, EncoderConfig, ErrorPayload, FrameAckBatchPayload, FrameSegment, FrameSource,
+    FrameTrace, Packet, PacketType, PausePayload, PingPayload, PipelineError, PongPayload,
+    StopPayload, StopReason, TimingPercentiles, TimingWindow, TransportError, VideoEncoder,
+};
+use serialwarp_transport::{TransportHalves, TransportReceiver};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, field, info, warn, Instrument};
+
+use crate::credit::CreditGate;
+use crate::pacing::{Pacer, PacingConfig};
+use crate::sender::{PacketOptions, PacketSender};
+use crate::trace::{frame_span, FRAME_TRACE_TARGET};
+use crate::watchdog::{SourceWatchdog, StallCause, StallEvent, DEFAULT_STALL_TIMEOUT};
+
+/// How often the encoder thread checks for a resume while paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Default for `SourcePipelineConfig::starvation_timeout`
+pub const DEFAULT_STARVATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default for `SourcePipelineConfig::timing_window`
+pub const DEFAULT_TIMING_WINDOW: Duration = Duration::from_secs(10);
+
+/// Inputs whose capture time is remembered until the encoder outputs them;
+/// more than any encoder holds back
+const MAX_PENDING_CAPTURES: usize = 64;
+
+/// Source pipeline configuration
+#[derive(Debug, Clone)]
+pub struct SourcePipelineConfig {
+    /// Credits granted by the sink's START_ACK
+    pub initial_credits: u16,
+    /// Sequence number of the first FRAME packet, i.e. the next one after
+    /// the handshake
+    pub first_sequence: u32,
+    /// Encoded frames queued between the encoder thread and the sender
+    pub send_queue_depth: usize,
+    /// Events buffered for the consumer; newer events are dropped when full
+    pub event_queue_depth: usize,
+    /// Whether FRAME packets carry a CRC; only turn this off when the
+    /// handshake found both ends support `capabilities::NO_CRC`
+    pub crc: bool,
+    /// Whether FRAME payloads are LZ4 compressed where that helps; only
+    /// turn this on when the handshake found both ends support
+    /// `capabilities::LZ4`
+    pub compression: bool,
+    /// Data segments covered by each XOR parity segment, see
+    /// `EncodedFrame::into_segments_with_parity`; only set this when the
+    /// handshake found both ends support `capabilities::FEC`
+    pub fec_group_size: Option<u16>,
+    /// Stream FRAME packets are tagged with, and whose acks and credits
+    /// apply here; only nonzero when the handshake found both ends support
+    /// `capabilities::MULTI_STREAM`
+    pub stream_id: u8,
+    /// Send PAUSE on `SourcePipeline::pause` and `resume`, so the sink
+    /// doesn't take the gap in frames for a stall; only turn this on when
+    /// the handshake found both ends support `capabilities::PAUSE`
+    pub pause_packets: bool,
+    /// Trace every Nth frame in full: its `source_frame` span is raised
+    /// from TRACE to DEBUG, and a `FrameTrace` of its capture, encode and
+    /// send times is logged at DEBUG under `FRAME_TRACE_TARGET`; 0 traces
+    /// none
+    pub trace_frames: u32,
+    /// Spread each frame's FRAME packets over part of the frame interval
+    /// rather than sending them back to back, so a keyframe doesn't hold up
+    /// acks on links that can't read and write at once. A frame still going
+    /// out when the next is ready is sent unpaced. `None` sends every frame
+    /// in one burst.
+    pub pacing: Option<PacingConfig>,
+    /// How long the credits may stay run out before the source warns and
+    /// PINGs the sink, in case its acks are being lost; `None` waits for
+    /// them indefinitely
+    pub starvation_timeout: Option<Duration>,
+    /// PINGs sent while starved before giving up: the pipeline then fails
+    /// with a timeout, for the frontend to reconnect and start over with a
+    /// fresh credit window
+    pub starvation_probes: u32,
+    /// How long capture, the encoder or the sink's acks may go without
+    /// progress before the stream is reported stalled, see
+    /// `SourceWatchdog`; an encoder stalled while capture goes on is
+    /// restarted after each timeout. `None` doesn't watch for stalls.
+    pub stall_timeout: Option<Duration>,
+    /// How often the capture, encode and send timing percentiles are logged
+    /// and started over; `SourceStats` covers the last window or two
+    pub timing_window: Duration,
+}
+
+impl Default for SourcePipelineConfig {
+    fn default() -> Self {
+        Self {
+            initial_credits: 8,
+            first_sequence: 0,
+            send_queue_depth: 4,
+            event_queue_depth: 64,
+            crc: true,
+            compression: false,
+            fec_group_size: None,
+            stream_id: 0,
+            pause_packets: false,
+            trace_frames: 0,
+            pacing: None,
+            starvation_timeout: Some(DEFAULT_STARVATION_TIMEOUT),
+            starvation_probes: 3,
+            stall_timeout: Some(DEFAULT_STALL_TIMEOUT),
+            timing_window: DEFAULT_TIMING_WINDOW,
+        }
+    }
+}
+
+/// Why the source forced a keyframe rather than waiting for the encoder's
+/// keyframe interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyframeReason {
+    /// The first frame of the stream; the decoder can't start without one
+    StreamStart,
+    /// The encoder was switched to a new configuration
+    Reconfigure,
+    /// `SourcePipeline::request_keyframe`, or the sink lost its reference
+    /// frames
+    Request,
+    /// The encoder dropped a frame later ones may have referenced
+    Drop,
+    /// The black frame sent on pausing, see `SourcePipeline::pause`
+    Pause,
+    /// Streaming resumed after a pause
+    Resume,
+    /// The encoder was restarted after it stopped putting out frames
+    EncoderRestart,
+}
+
+/// Notable things that happened while streaming
+#[derive(Debug)]
+pub enum SourceEvent {
+    /// The next frame is being encoded as a keyframe
+    KeyframeForced { reason: KeyframeReason },
+    /// A keyframe was fully sent
+    KeyframeSent { frame_number: u64 },
+    /// Credits ran out; captured frames are skipped until the sink acks
+    CreditStarvation { frames_in_flight: u64 },
+    /// `cause` has made no progress for `waited`, though the transport is
+    /// still up
+    StreamStalled { cause: StallCause, waited: Duration },
+    /// The stall reported for `cause` is over, after `stalled_for`
+    StreamRecovered {
+        cause: StallCause,
+        stalled_for: Duration,
+    },
+    /// The sink asked to stop the stream
+    StopRequested { reason: StopReason },
+    /// The pipeline hit an error and stopped
+    Error(PipelineError),
+}
+
+/// Source pipeline counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceStats {
+    pub frames_captured: u64,
+    /// Captured frames not encoded because no credits were available
+    pub frames_skipped: u64,
+    /// Frames output by the encoder
+    pub frames_encoded: u64,
+    /// Time spent encoding, summed over every input
+    pub encode_time: Duration,
+    pub frames_sent: u64,
+    pub keyframes_sent: u64,
+    /// Keyframes the source asked the encoder for, see `KeyframeReason`
+    pub keyframes_forced: u64,
+    /// Frames the encoder dropped, found by gaps in its frame numbers
+    pub frames_dropped: u64,
+    /// FRAME packets sent, parity included
+    pub segments_sent: u64,
+    /// FRAME packet bytes sent, parity included
+    pub bytes_sent: u64,
+    pub parity_segments_sent: u64,
+    pub parity_bytes_sent: u64,
+    /// Frames whose packets were paced, see `SourcePipelineConfig::pacing`
+    pub frames_paced: u64,
+    /// Paced frames sent out unpaced once the next frame was ready
+    pub pacing_bypasses: u64,
+    pub frames_acked: u64,
+    /// CREDIT_UPDATE packets applied
+    pub credit_updates: u64,
+    /// Credits currently available
+    pub credits: u32,
+    /// Times the credits ran out
+    pub starvations: u64,
+    /// PINGs sent after going `starvation_timeout` without credits
+    pub starvation_probes: u64,
+    /// Times the stream stalled, see `SourcePipelineConfig::stall_timeout`
+    pub stalls: u64,
+    /// Times the encoder was restarted after stalling
+    pub encoder_restarts: u64,
+    /// Whether capture is paused
+    pub paused: bool,
+    /// Time from one captured frame to the next, skipped ones included;
+    /// this and the other timings cover the last `timing_window` or two,
+    /// and are `None` until something is timed
+    pub capture_interval: Option<TimingPercentiles>,
+    /// Time the encoder took per input
+    pub encode_duration: Option<TimingPercentiles>,
+    /// Time from a frame's first FRAME packet going out to its last,
+    /// pacing included
+    pub send_duration: Option<TimingPercentiles>,
+}
+
+impl SourceStats {
+    /// Parity bytes sent per byte of frame data, e.g. 0.125 with one
+    /// parity segment for every eight full data segments
+    pub fn fec_overhead(&self) -> f64 {
+        let data_bytes = self.bytes_sent - self.parity_bytes_sent;
+        if data_bytes == 0 {
+            0.0
+        } else {
+            self.parity_bytes_sent as f64 / data_bytes as f64
+        }
+    }
+}
+
+/// Distributions of the source's frame timings, see `SourceStats`
+#[derive(Debug)]
+struct SourceTimings {
+    window: Duration,
+    started: Instant,
+    capture_interval: TimingWindow,
+    encode: TimingWindow,
+    send: TimingWindow,
+}
+
+impl SourceTimings {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started: Instant::now(),
+            capture_interval: TimingWindow::new(),
+            encode: TimingWindow::new(),
+            send: TimingWindow::new(),
+        }
+    }
+
+    /// Start a new window once the current one has run its length, logging
+    /// what it timed
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed < self.window {
+            return;
+        }
+        let latest = |timing: &TimingWindow| match timing.current().percentiles() {
+            Some(percentiles) => percentiles.to_string(),
+            None => "-".to_string(),
+        };
+        if !self.capture_interval.current().is_empty() {
+            info!(
+                "Source timings: capture interval {}, encode {}, send {}",
+                latest(&self.capture_interval),
+                latest(&self.encode),
+                latest(&self.send)
+            );
+        }
+        for timing in [&mut self.capture_interval, &mut self.encode, &mut self.send] {
+            // After a gap, e.g. a pause, the last window is too old to keep
+            if elapsed >= self.window * 2 {
+                timing.clear();
+            } else {
+                timing.rotate();
+            }
+        }
+        self.started = now;
+    }
+}
+
+impl Default for SourceTimings {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMING_WINDOW)
+    }
+}
+
+/// State shared between the pipeline handle and its tasks
+#[derive(Debug, Default)]
+struct Shared {
+    credits: CreditGate,
+    /// Reason the next encoded frame must be a keyframe; the first reason
+    /// given is kept until the keyframe is forced
+    keyframe_pending: Mutex<Option<KeyframeReason>>,
+    /// Configuration to switch the encoder to before the next frame
+    reconfigure: Mutex<Option<EncoderConfig>>,
+    /// Whether the encoder thread is switching to a new configuration
+    reconfiguring: AtomicBool,
+    /// Whether to restart the encoder before the next frame
+    restart_encoder: AtomicBool,
+    /// Set while watching for stalls
+    watchdog: Mutex<Option<SourceWatchdog>>,
+    timings: Mutex<SourceTimings>,
+    /// Whether to stop capturing and encoding until resumed
+    paused: AtomicBool,
+    /// Whether to send a black keyframe while paused
+    black_pending: AtomicBool,
+    frames_captured: AtomicU64,
+    frames_skipped: AtomicU64,
+    frames_encoded: AtomicU64,
+    encode_time_us: AtomicU64,
+    frames_sent: AtomicU64,
+    keyframes_sent: AtomicU64,
+    keyframes_forced: AtomicU64,
+    frames_dropped: AtomicU64,
+    segments_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    parity_segments_sent: AtomicU64,
+    parity_bytes_sent: AtomicU64,
+    frames_paced: AtomicU64,
+    pacing_bypasses: AtomicU64,
+    frames_acked: AtomicU64,
+    credit_updates: AtomicU64,
+    starvations: AtomicU64,
+    starvation_probes: AtomicU64,
+    stalls: AtomicU64,
+    encoder_restarts: AtomicU64,
+    /// Encoded frames waiting for the send task, counted from before they're
+    /// queued, so pacing knows when a frame has a successor ready
+    frames_queued: AtomicU64,
+    /// The encoder's frame interval and bitrate, 0 if not known, for pacing
+    frame_interval_us: AtomicU64,
+    bitrate_bps: AtomicU64,
+}
+
+impl Shared {
+    /// Have the next encoded frame be a keyframe
+    fn force_keyframe(&self, reason: KeyframeReason) {
+        self.keyframe_pending.lock().unwrap().get_or_insert(reason);
+    }
+
+    /// Tell the stall watchdog, if there is one, about progress
+    fn watch<T>(&self, update: impl FnOnce(&mut SourceWatchdog, Instant) -> T) -> Option<T> {
+        let mut watchdog = self.watchdog.lock().unwrap();
+        watchdog
+            .as_mut()
+            .map(|watchdog| update(watchdog, Instant::now()))
+    }
+
+    /// Add a duration to one of the frame timings
+    fn time(&self, timing: fn(&mut SourceTimings) -> &mut TimingWindow, duration: Duration) {
+        let mut timings = self.timings.lock().unwrap();
+        timings.roll(Instant::now());
+        timing(&mut timings).record(duration);
+    }
+
+    /// Note the frame interval and bitrate the encoder is working to
+    fn set_encoder_config(&self, config: &EncoderConfig) {
+        let interval_us = 1_000_000 / config.fps.max(1) as u64;
+        self.frame_interval_us.store(interval_us, Ordering::Relaxed);
+        let bitrate = config.rate_control.bitrate().unwrap_or(0);
+        self.bitrate_bps.store(bitrate as u64, Ordering::Relaxed);
+    }
+}
+
+/// Frame source, encoder, and transport receiver, held until `start` moves
+/// them to the encoder thread and the ack task
+struct Stages {
+    source: Box<dyn FrameSource>,
+    encoder: Box<dyn VideoEncoder>,
+    receiver: Box<dyn TransportReceiver>,
+}
+
+/// Runs the source side of a stream: frames are captured and encoded on a
+/// dedicated thread, then segmented and sent on a tokio task, while another
+/// task collects FRAME_ACKs and returns their credits.
+///
+/// A captured frame is only encoded when a credit is available; otherwise it
+/// is skipped, so a slow sink lowers the frame rate instead of building a
+/// backlog. Call `start` from within a tokio runtime, after the HELLO/START
+/// handshake.
+pub struct SourcePipeline {
+    config: SourcePipelineConfig,
+    stages: Option<Stages>,
+    /// Shared by the send task and `send_packet`
+    sender: Arc<PacketSender>,
+    shared: Arc<Shared>,
+    shutdown: CancellationToken,
+    events_tx: mpsc::Sender<SourceEvent>,
+    events_rx: Option<mpsc::Receiver<SourceEvent>>,
+    encoder_thread: Option<ThreadHandle<()>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SourcePipeline {
+    pub fn new(
+        source: Box<dyn FrameSource>,
+        encoder: Box<dyn VideoEncoder>,
+        (sender, receiver): TransportHalves,
+        config: SourcePipelineConfig,
+    ) -> Self {
+        let (events_tx, events_rx) = mpsc::channel(config.event_queue_depth.max(1));
+        let shared = Shared {
+            credits: CreditGate::new(config.initial_credits as i64),
+            keyframe_pending: Mutex::new(Some(KeyframeReason::StreamStart)),
+            timings: Mutex::new(SourceTimings::new(config.timing_window)),
+            ..Default::default()
+        };
+
+        let options = PacketOptions {
+            compression: config.compression,
+            crc: config.crc,
+            stream_id: config.stream_id,
+        };
+        Self {
+            sender: Arc::new(PacketSender::new(sender, config.first_sequence, options)),
+            config,
+            stages: Some(Stages {
+                source,
+                encoder,
+                receiver,
+            }),
+            shared: Arc::new(shared),
+            shutdown: CancellationToken::new(),
+            events_tx,
+            events_rx: Some(events_rx),
+            encoder_thread: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Take the event receiver; returns `None` after the first call
+    pub fn events(&mut self) -> Option<mpsc::Receiver<SourceEvent>> {
+        self.events_rx.take()
+    }
+
+    /// Send a packet on the stream, e.g. a STOP, numbered in order with the
+    /// FRAME packets the send task is sending
+    pub async fn send_packet(
+        &self,
+        packet_type: PacketType,
+        payload: Bytes,
+    ) -> Result<(), TransportError> {
+        self.sender.send(packet_type, payload).await?;
+        Ok(())
+    }
+
+    /// Next outgoing sequence number
+    pub fn sequence(&self) -> u32 {
+        self.sender.sequence()
+    }
+
+    /// Token cancelled when the pipeline stops, for whatever reason
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Encode the next frame as a keyframe
+    pub fn request_keyframe(&self) {
+        self.shared.force_keyframe(KeyframeReason::Request);
+    }
+
+    /// Switch the encoder to `config` before it encodes the next frame,
+    /// which is then a keyframe. The source is switched to the same size
+    /// and frame rate without restarting it. A resolution change also needs
+    /// a new START, so the sink knows what to expect.
+    pub fn reconfigure(&self, config: EncoderConfig) {
+        *self.shared.reconfigure.lock().unwrap() = Some(config);
+    }
+
+    /// Stop capturing and encoding, keeping the transport, handshake state
+    /// and encoder session for `resume`. With `black`, a single black
+    /// keyframe is sent so the sink doesn't sit on the last frame.
+    ///
+    /// Frames already in flight are still acked, so no credits are lost and
+    /// no starvation is reported for the gap. With `pause_packets` on, the
+    /// sink is told with a PAUSE not to report one either.
+    pub async fn pause(&self, black: bool) {
+        self.shared.black_pending.store(black, Ordering::Release);
+        if !self.shared.paused.swap(true, Ordering::AcqRel) {
+            self.send_pause(true).await;
+        }
+    }
+
+    /// Capture again after `pause`, starting with a keyframe so the sink's
+    /// decoder picks up at once
+    pub async fn resume(&self) {
+        self.shared.black_pending.store(false, Ordering::Release);
+        if self.shared.paused.swap(false, Ordering::AcqRel) {
+            self.shared.force_keyframe(KeyframeReason::Resume);
+            self.send_pause(false).await;
+        }
+    }
+
+    /// Tell the sink frames stop or start again, if it takes PAUSE
+    async fn send_pause(&self, paused: bool) {
+        if !self.config.pause_packets {
+            return;
+        }
+        let pause = PausePayload::new(paused);
+        if let Err(e) = self.send_packet(PacketType::Pause, pause.to_bytes()).await {
+            warn!("Failed to send PAUSE: {}", e);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Acquire)
+    }
+
+    pub fn stats(&self) -> SourceStats {
+        let shared = &self.shared;
+        let timings = shared.timings.lock().unwrap();
+        SourceStats {
+            frames_captured: shared.frames_captured.load(Ordering::Relaxed),
+            frames_skipped: shared.frames_skipped.load(Ordering::Relaxed),
+            frames_encoded: shared.frames_encoded.load(Ordering::Relaxed),
+            encode_time: Duration::from_micros(shared.encode_time_us.load(Ordering::Relaxed)),
+            frames_sent: shared.frames_sent.load(Ordering::Relaxed),
+            keyframes_sent: shared.keyframes_sent.load(Ordering::Relaxed),
+            keyframes_forced: shared.keyframes_forced.load(Ordering::Relaxed),
+            frames_dropped: shared.frames_dropped.load(Ordering::Relaxed),
+            segments_sent: shared.segments_sent.load(Ordering::Relaxed),
+            bytes_sent: shared.bytes_sent.load(Ordering::Relaxed),
+            parity_segments_sent: shared.parity_segments_sent.load(Ordering::Relaxed),
+            parity_bytes_sent: shared.parity_bytes_sent.load(Ordering::Relaxed),
+            frames_paced: shared.frames_paced.load(Ordering::Relaxed),
+            pacing_bypasses: shared.pacing_bypasses.load(Ordering::Relaxed),
+            frames_acked: shared.frames_acked.load(Ordering::Relaxed),
+            credit_updates: shared.credit_updates.load(Ordering::Relaxed),
+            credits: shared.credits.available().clamp(0, u32::MAX as i64) as u32,
+            starvations: shared.starvations.load(Ordering::Relaxed),
+            starvation_probes: shared.starvation_probes.load(Ordering::Relaxed),
+            stalls: shared.stalls.load(Ordering::Relaxed),
+            encoder_restarts: shared.encoder_restarts.load(Ordering::Relaxed),
+            paused: shared.paused.load(Ordering::Relaxed),
+            capture_interval: timings.capture_interval.percentiles(),
+            encode_duration: timings.encode.percentiles(),
+            send_duration: timings.send.percentiles(),
+        }
+    }
+
+    /// Start streaming
+    pub fn start(&mut self) -> Result<(), PipelineError> {
+        let Stages {
+            source,
+            encoder,
+            receiver,
+        } = self.stages.take().ok_or(PipelineError::AlreadyStarted)?;
+        let (frames_tx, frames_rx) = mpsc::channel(self.config.send_queue_depth.max(1));
+
+        let context = TaskContext {
+            shared: Arc::clone(&self.shared),
+            shutdown: self.shutdown.clone(),
+            events: self.events_tx.clone(),
+        };
+        self.encoder_thread = Some(std::thread::spawn({
+            let context = context.clone();
+            move || encode_loop(source, encoder, frames_tx, context)
+        }));
+        self.tasks.push(tokio::spawn(send_loop(
+            Arc::clone(&self.sender),
+            frames_rx,
+            self.config.clone(),
+            context.clone(),
+        )));
+        if let Some(timeout) = self.config.starvation_timeout {
+            self.tasks.push(tokio::spawn(starvation_watchdog(
+                Arc::clone(&self.sender),
+                timeout,
+                self.config.starvation_probes,
+                context.clone(),
+            )));
+        }
+        if let Some(timeout) = self.config.stall_timeout {
+            *self.shared.watchdog.lock().unwrap() =
+                Some(SourceWatchdog::new(timeout, Instant::now()));
+            self.tasks
+                .push(tokio::spawn(stall_watchdog(timeout, context.clone())));
+        }
+        self.tasks.push(tokio::spawn(ack_loop(
+            receiver,
+            Arc::clone(&self.sender),
+            self.config.stream_id,
+            context,
+        )));
+
+        info!(
+            "Source pipeline started with {} credits",
+            self.config.initial_credits
+        );
+        Ok(())
+    }
+
+    /// Stop streaming and wait for the pipeline's tasks to finish. The
+    /// encoder thread finishes the frame it's capturing first.
+    pub async fn stop(&mut self) {
+        self.shutdown.cancel();
+
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+        if let Some(thread) = self.encoder_thread.take() {
+            let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+        }
+    }
+}
+
+impl Drop for SourcePipeline {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+#[derive(Clone)]
+struct TaskContext {
+    shared: Arc<Shared>,
+    shutdown: CancellationToken,
+    events: mpsc::Sender<SourceEvent>,
+}
+
+impl TaskContext {
+    fn emit(&self, event: SourceEvent) {
+        if self.events.try_send(event).is_err() {
+            debug!("Source event dropped: queue full");
+        }
+    }
+
+    /// Report a fatal error and stop the pipeline
+    fn fail(&self, error: PipelineError) {
+        warn!("Source pipeline error: {}", error);
+        self.emit(SourceEvent::Error(error));
+        self.shutdown.cancel();
+    }
+}
+
+/// Capture and encode frames while credits allow, on a blocking thread
+fn encode_loop(
+    mut source: Box<dyn FrameSource>,
+    mut encoder: Box<dyn VideoEncoder>,
+    frames: mpsc::Sender<(EncodedFrame, FrameTrace)>,
+    context: TaskContext,
+) {
+    let shared = &context.shared;
+    let mut starved = false;
+    // Frame number the encoder's next output should have
+    let mut next_frame_number = None;
+    let mut last_pts_us = 0;
+    // Presentation time and wall-clock capture time of inputs not yet
+    // output, for the FRAME header's capture time
+    let mut captures = VecDeque::new();
+    // When the last frame was captured, for the interval to the next; none
+    // after a pause or a change of frame rate
+    let mut last_capture = None;
+    shared.set_encoder_config(encoder.config());
+
+    while !context.shutdown.is_cancelled() {
+        if shared.paused.load(Ordering::Acquire) {
+            // Nothing is captured, so there's nothing to be starved of
+            starved = false;
+            last_capture = None;
+            if shared.black_pending.load(Ordering::Acquire) && shared.credits.try_take() {
+                shared.black_pending.store(false, Ordering::Release);
+                let (black, stride) = encoder.config().black_frame();
+                last_pts_us += 1_000_000 / encoder.config().fps.max(1) as u64;
+
+                shared.keyframes_forced.fetch_add(1, Ordering::Relaxed);
+                context.emit(SourceEvent::KeyframeForced {
+                    reason: KeyframeReason::Pause,
+                });
+                let mut trace = FrameTrace::default();
+                remember_capture(&mut captures, last_pts_us, unix_time_us());
+                shared.watch(SourceWatchdog::encoding);
+                let encode_start = Instant::now();
+                if let Err(e) = encoder.encode_raw(&black, stride, last_pts_us, true) {
+                    return context.fail(e.into());
+                }
+                let encode_time = encode_start.elapsed();
+                trace.stage("encode", encode_time);
+                metrics::encode_time(encode_time);
+                shared.time(|timings| &mut timings.encode, encode_time);
+                shared
+                    .encode_time_us
+                    .fetch_add(encode_time.as_micros() as u64, Ordering::Relaxed);
+                if !send_encoded(
+                    &mut *encoder,
+                    &frames,
+                    &mut next_frame_number,
+                    &mut captures,
+                    shared,
+                    &trace,
+                ) {
+                    return;
+                }
+            }
+            std::thread::sleep(PAUSED_POLL_INTERVAL);
+            continue;
+        }
+
+        // Before capturing, so the next frame already has the new size
+        let restart = shared.restart_encoder.swap(false, Ordering::AcqRel);
+        let reconfigure = shared.reconfigure.lock().unwrap().take();
+        if let Some(config) = reconfigure {
+            shared.reconfiguring.store(true, Ordering::Release);
+            if let Err(e) = reconfigure_source(&mut *source, &config) {
+                return context.fail(e.into());
+            }
+            if let Err(e) = encoder.reconfigure(config) {
+                return context.fail(e.into());
+            }
+            shared.reconfiguring.store(false, Ordering::Release);
+            shared.set_encoder_config(encoder.config());
+            last_capture = None;
+            // Whatever the encoder does on its own, the sink gets a keyframe
+            shared.force_keyframe(KeyframeReason::Reconfigure);
+        } else if restart {
+            // Reconfiguring to the same settings opens a new encoder session
+            info!("Restarting the encoder");
+            if let Err(e) = encoder.reconfigure(encoder.config().clone()) {
+                return context.fail(e.into());
+            }
+            if !send_encoded(
+                &mut *encoder,
+                &frames,
+                &mut next_frame_number,
+                &mut captures,
+                shared,
+                &FrameTrace::default(),
+            ) {
+                return;
+            }
+            // Whatever the old session didn't put out is lost, so its
+            // credits are given back here rather than found as a gap in
+            // the new session's frame numbers
+            let lost = shared.watch(SourceWatchdog::encoder_restarted).unwrap_or(0);
+            shared.credits.add(lost as i64);
+            next_frame_number = None;
+            shared.encoder_restarts.fetch_add(1, Ordering::Relaxed);
+            shared.force_keyframe(KeyframeReason::EncoderRestart);
+        }
+
+        let mut trace = FrameTrace::default();
+        let capture_start = Instant::now();
+        let frame = match source.next_frame() {
+            Ok(frame) => frame,
+            Err(e) => return context.fail(e.into()),
+        };
+        let captured_us = unix_time_us();
+        let captured = Instant::now();
+        trace.stage("capture", captured - capture_start);
+        if let Some(last) = last_capture.replace(captured) {
+            shared.time(|timings| &mut timings.capture_interval, captured - last);
+        }
+        shared.frames_captured.fetch_add(1, Ordering::Relaxed);
+        shared.watch(SourceWatchdog::captured);
+        metrics::frame_captured();
+        last_pts_us = frame.pts_us;
+
+        if !shared.credits.try_take() {
+            shared.frames_skipped.fetch_add(1, Ordering::Relaxed);
+            metrics::frames_dropped(metrics::DropReason::NoCredit, 1);
+            if !starved {
+                starved = true;
+                shared.starvations.fetch_add(1, Ordering::Relaxed);
+                let in_flight = shared
+                    .frames_sent
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(shared.frames_acked.load(Ordering::Relaxed));
+                context.emit(SourceEvent::CreditStarvation {
+                    frames_in_flight: in_flight,
+                });
+            }
+            continue;
+        }
+        starved = false;
+
+        let forced = shared.keyframe_pending.lock().unwrap().take();
+        if let Some(reason) = forced {
+            debug!("Forcing a keyframe: {:?}", reason);
+            shared.keyframes_forced.fetch_add(1, Ordering::Relaxed);
+            context.emit(SourceEvent::KeyframeForced { reason });
+        }
+        remember_capture(&mut captures, frame.pts_us, captured_us);
+        shared.watch(SourceWatchdog::encoding);
+        let encode_start = Instant::now();
+        if let Err(e) =
+            encoder.encode_raw(&frame.data, frame.stride, frame.pts_us, forced.is_some())
+        {
+            return context.fail(e.into());
+        }
+        let encode_time = encode_start.elapsed();
+        trace.stage("encode", encode_time);
+        metrics::encode_time(encode_time);
+        shared.time(|timings| &mut timings.encode, encode_time);
+        shared
+            .encode_time_us
+            .fetch_add(encode_time.as_micros() as u64, Ordering::Relaxed);
+
+        if !send_encoded(
+            &mut *encoder,
+            &frames,
+            &mut next_frame_number,
+            &mut captures,
+            shared,
+            &trace,
+        ) {
+            return;
+        }
+    }
+}
+
+/// Bring the source's size and frame rate in line with `config`. A source
+/// that can't change its frame rate carries on at its own, which the encoder
+/// copes with; one that can't change size can't go on.
+fn reconfigure_source(
+    source: &mut dyn FrameSource,
+    config: &EncoderConfig,
+) -> Result<(), CaptureError> {
+    if source.resolution() != (config.width, config.height) {
+        source.set_dimensions(config.width, config.height)?;
+    }
+    if source.fps() != config.fps {
+        if let Err(e) = source.set_fps(config.fps) {
+            warn!("Capture stays at {} fps: {}", source.fps(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Remember when the input with `pts_us` was captured
+fn remember_capture(captures: &mut VecDeque<(u64, u64)>, pts_us: u64, captured_us: u64) {
+    if captures.len() == MAX_PENDING_CAPTURES {
+        captures.pop_front();
+    }
+    captures.push_back((pts_us, captured_us));
+}
+
+/// Queue whatever the encoder has output for sending, checking its frame
+/// numbers for drops and stamping them with their input's capture time from
+/// `captures`. Each frame goes with `trace`, the timings of the input that
+/// produced it. `false` once the send task has gone.
+fn send_encoded(
+    encoder: &mut dyn VideoEncoder,
+    frames: &mpsc::Sender<(EncodedFrame, FrameTrace)>,
+    next_frame_number: &mut Option<u64>,
+    captures: &mut VecDeque<(u64, u64)>,
+    shared: &Shared,
+    trace: &FrameTrace,
+) -> bool {
+    // The credit covers whatever this input produces; an encoder with
+    // latency may emit nothing now and several frames later
+    while let Some(mut encoded) = encoder.next_frame() {
+        metrics::frame_encoded();
+        shared.frames_encoded.fetch_add(1, Ordering::Relaxed);
+        shared.watch(SourceWatchdog::encoded);
+        // Output may be reordered, so match the input by pts
+        if let Some(index) = captures
+            .iter()
+            .position(|&(pts_us, _)| pts_us == encoded.metadata.pts_us)
+        {
+            encoded.metadata.capture_ts_us = captures.remove(index).unwrap().1;
+        }
+        // An encoder numbering its input, like VideoToolbox, leaves a
+        // gap where it dropped a frame under load. That input's credit
+        // will never be acked, and the sink may be left without a
+        // reference, so return the credit and resync with a keyframe.
+        // B-frames reorder the output, so gaps only mean drops without.
+        let frame_number = encoded.metadata.frame_number;
+        if let Some(expected) = *next_frame_number {
+            if frame_number > expected && !encoder.config().bframes_enabled() {
+                let dropped = frame_number - expected;
+                warn!(
+                    "Encoder dropped {} frame(s) before {}",
+                    dropped, frame_number
+                );
+                shared.frames_dropped.fetch_add(dropped, Ordering::Relaxed);
+                metrics::frames_dropped(metrics::DropReason::Encoder, dropped);
+                shared.credits.add(dropped as i64);
+                shared.watch(|watchdog, _| watchdog.encoder_dropped(dropped));
+                if !encoded.metadata.is_keyframe {
+                    shared.force_keyframe(KeyframeReason::Drop);
+                }
+            }
+        }
+        *next_frame_number = Some(frame_number + 1);
+
+        let mut trace = trace.clone();
+        trace.frame_number = frame_number;
+        shared.frames_queued.fetch_add(1, Ordering::AcqRel);
+        if frames.blocking_send((encoded, trace)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Segment encoded frames and send them as FRAME packets
+async fn send_loop(
+    sender: Arc<PacketSender>,
+    mut frames: mpsc::Receiver<(EncodedFrame, FrameTrace)>,
+    config: SourcePipelineConfig,
+    context: TaskContext,
+) {
+    let shared = &context.shared;
+    let pool = BufferPool::default();
+    let mut pacer = config.pacing.map(Pacer::new);
+
+    loop {
+        let (frame, mut trace) = tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            frame = frames.recv() => match frame {
+                Some(frame) => frame,
+                None => return,
+            },
+        };
+        shared.frames_queued.fetch_sub(1, Ordering::AcqRel);
+
+        let frame_number = frame.metadata.frame_number;
+        let is_keyframe = frame.metadata.is_keyframe;
+        let traced = is_traced(frame_number, config.trace_frames);
+        let span = frame_span!(
+            traced,
+            "source_frame",
+            frame = frame_number,
+            keyframe = is_keyframe,
+            capture_us = trace.get("capture").map(|time| time.as_micros() as u64),
+            encode_us = trace.get("encode").map(|time| time.as_micros() as u64),
+            segments = field::Empty,
+            send_us = field::Empty,
+        );
+
+        let send_start = Instant::now();
+        let segments = match config.fec_group_size {
+            Some(group_size) => frame.into_segments_with_parity(group_size),
+            None => frame.into_segments(),
+        };
+        let segment_count = segments.len();
+        if let Some(pacer) = pacer.as_mut() {
+            let bytes = segments.iter().map(FrameSegment::payload_len).sum();
+            let interval = Duration::from_micros(shared.frame_interval_us.load(Ordering::Relaxed));
+            let bitrate = match shared.bitrate_bps.load(Ordering::Relaxed) {
+                0 => None,
+                bps => Some(bps as u32),
+            };
+            if pacer.start_frame(bytes, interval, bitrate, tokio::time::Instant::now()) {
+                shared.frames_paced.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let sent = send_segments(&sender, segments, &pool, shared, pacer.as_mut())
+            .instrument(span.clone())
+            .await;
+        if let Err(e) = sent {
+            return context.fail(e.into());
+        }
+        let send_time = send_start.elapsed();
+        span.record("segments", segment_count);
+        span.record("send_us", send_time.as_micros() as u64);
+        trace.stage("send", send_time);
+        shared.time(|timings| &mut timings.send, send_time);
+        if traced {
+            span.in_scope(
+                || debug!(target: FRAME_TRACE_TARGET, frame = trace.frame_number, "{}", trace),
+            );
+        }
+
+        shared.frames_sent.fetch_add(1, Ordering::Relaxed);
+        shared.watch(SourceWatchdog::sent);
+        metrics::frame_sent();
+        if is_keyframe {
+            shared.keyframes_sent.fetch_add(1, Ordering::Relaxed);
+            context.emit(SourceEvent::KeyframeSent { frame_number });
+        }
+    }
+}
+
+/// Send one frame's segments as FRAME packets, each after whatever wait
+/// `pacer` gives it. Pacing is dropped for the rest of the frame once
+/// another is queued behind it.
+async fn send_segments(
+    sender: &PacketSender,
+    segments: Vec<FrameSegment>,
+    pool: &BufferPool,
+    shared: &Shared,
+    mut pacer: Option<&mut Pacer>,
+) -> Result<(), TransportError> {
+    for segment in segments {
+        if let Some(pacer) = pacer.as_deref_mut() {
+            if pacer.is_pacing() && shared.frames_queued.load(Ordering::Acquire) > 0 {
+                pacer.bypass();
+                shared.pacing_bypasses.fetch_add(1, Ordering::Relaxed);
+            }
+            let delay = pacer.delay(segment.payload_len(), tokio::time::Instant::now());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        let is_parity = segment.is_parity();
+        let send_start = tokio::time::Instant::now();
+        let len = if sender.compresses() {
+            sender
+                .send(PacketType::Frame, segment.to_payload_in(pool))
+                .await?
+        } else {
+            // The segment's data goes to the transport as it is, without
+            // being copied after its headers
+            sender
+                .send_vectored(PacketType::Frame, &segment.payload_parts())
+                .await?
+        };
+        if let Some(pacer) = pacer.as_deref_mut() {
+            pacer.record_send(len as usize, send_start.elapsed());
+        }
+        shared.segments_sent.fetch_add(1, Ordering::Relaxed);
+        shared.bytes_sent.fetch_add(len, Ordering::Relaxed);
+        if is_parity {
+            shared.parity_segments_sent.fetch_add(1, Ordering::Relaxed);
+            shared.parity_bytes_sent.fetch_add(len, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Watch for the credits staying run out. After each `timeout` without any
+/// coming back the sink is PINGed, which also gets a sink that only grants
+/// credits as it handles packets to look at its queue again; after `probes`
+/// of those the pipeline fails with a timeout. Time spent paused doesn't
+/// count, since nothing is asking for credits then.
+async fn starvation_watchdog(
+    sender: Arc<PacketSender>,
+    timeout: Duration,
+    probes: u32,
+    context: TaskContext,
+) {
+    let shared = &context.shared;
+
+    loop {
+        tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            _ = shared.credits.exhausted() => {}
+        }
+
+        let mut sent = 0;
+        loop {
+            let returned = tokio::select! {
+                _ = context.shutdown.cancelled() => return,
+                returned = shared.credits.wait(timeout) => returned,
+            };
+            if returned {
+                break;
+            }
+            if shared.paused.load(Ordering::Acquire) {
+                sent = 0;
+                continue;
+            }
+            if sent == probes {
+                let waited = timeout * (probes + 1);
+                warn!("No credits returned in {:?}, giving up", waited);
+                return context.fail(PipelineError::Transport(TransportError::Timeout {
+                    duration_ms: waited.as_millis() as u64,
+                }));
+            }
+
+            sent += 1;
+            warn!(
+                "No credits returned in {:?}, probing the sink ({}/{})",
+                timeout * sent,
+                sent,
+                probes
+            );
+            shared.starvation_probes.fetch_add(1, Ordering::Relaxed);
+            let ping = PingPayload::new(unix_time_us());
+            if let Err(e) = sender.send(PacketType::Ping, ping.to_bytes()).await {
+                warn!("Failed to send PING: {}", e);
+            }
+        }
+    }
+}
+
+/// Check every quarter of `timeout` for a stalled stage, see
+/// `SourceWatchdog`, reporting each stall and its end. An encoder stalled
+/// while capture goes on is restarted each time it's found stalled. Nothing
+/// is watched while paused or reconfiguring.
+async fn stall_watchdog(timeout: Duration, context: TaskContext) {
+    let shared = &context.shared;
+    let mut checks = tokio::time::interval(timeout / 4);
+    checks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            _ = checks.tick() => {}
+        }
+
+        let suspended = shared.paused.load(Ordering::Acquire)
+            || shared.reconfiguring.load(Ordering::Acquire)
+            || shared.reconfigure.lock().unwrap().is_some();
+        let mut found = Vec::new();
+        shared.watch(|watchdog, now| {
+            watchdog.set_suspended(suspended, now);
+            found = watchdog.check(now);
+        });
+
+        for stall in found {
+            let cause = stall.cause;
+            match stall.event {
+                StallEvent::Stalled { waited, probes } => {
+                    if probes == 1 {
+                        warn!("Stream stalled: no {:?} progress in {:?}", cause, waited);
+                        shared.stalls.fetch_add(1, Ordering::Relaxed);
+                        context.emit(SourceEvent::StreamStalled { cause, waited });
+                    }
+                    if cause == StallCause::Encoder {
+                        warn!("Encoder has put out nothing in {:?}, restarting it", waited);
+                        shared.restart_encoder.store(true, Ordering::Release);
+                    }
+                }
+                StallEvent::Recovered { stalled_for } => {
+                    info!(
+                        "Stream recovered from a {:?} stall of {:?}",
+                        cause, stalled_for
+                    );
+                    context.emit(SourceEvent::StreamRecovered { cause, stalled_for });
+                }
+            }
+        }
+    }
+}
+
+/// Collect FRAME_ACKs, single or batched, and CREDIT_UPDATEs and apply
+/// their credits, and answer the sink's keyframe requests and PINGs.
+/// Packets for other streams are ignored, so one stream's acks never feed
+/// another's credits.
+async fn ack_loop(
+    mut transport: Box<dyn TransportReceiver>,
+    sender: Arc<PacketSender>,
+    stream_id: u8,
+    context: TaskContext,
+) {
+    let shared = &context.shared;
+
+    loop {
+        let data = tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            data = transport.recv() => match data {
+                Ok(data) => data,
+                Err(e) => return context.fail(e.into()),
+            },
+        };
+
+        let packet = match Packet::parse(&data) {
+            Ok((packet, _)) => packet,
+            Err(e) => {
+                warn!("Dropping malformed packet: {}", e);
+                continue;
+            }
+        };
+        metrics::packet_received(packet.packet_type());
+        if packet.stream_id() != stream_id {
+            debug!("Ignoring packet for stream {}", packet.stream_id());
+            continue;
+        }
+
+        match packet.packet_type() {
+            PacketType::FrameAck => match FrameAckBatchPayload::parse(&packet.payload) {
+                Ok(ack) => {
+                    let frames = ack.entries.len() as u64;
+                    shared.frames_acked.fetch_add(frames, Ordering::Relaxed);
+                    shared.watch(|watchdog, now| watchdog.acked(frames, now));
+                    shared.credits.add(ack.credits_returned as i64);
+                }
+                Err(e) => warn!("Dropping malformed FRAME_ACK: {}", e),
+            },
+            PacketType::CreditUpdate => match CreditUpdatePayload::parse(&packet.payload) {
+                Ok(update) => {
+                    debug!("Sink resized the credit window by {}", update.delta);
+                    shared.credit_updates.fetch_add(1, Ordering::Relaxed);
+                    shared.credits.add(update.delta as i64);
+                }
+                Err(e) => warn!("Dropping malformed CREDIT_UPDATE: {}", e),
+            },
+            PacketType::Stop => {
+                let reason = match StopPayload::parse(&packet.payload) {
+                    Ok(stop) => stop.reason,
+                    Err(e) => {
+                        warn!("Malformed STOP payload: {}", e);
+                        StopReason::UserRequested
+                    }
+                };
+                info!("Sink requested stop: {:?}", reason);
+                context.emit(SourceEvent::StopRequested { reason });
+                context.shutdown.cancel();
+                return;
+            }
+            PacketType::Error => match ErrorPayload::parse(&packet.payload) {
+                // The sink lost its reference frames and needs a keyframe
+                Ok(error) if error.code == error_codes::DECODER_FAILED && !error.fatal => {
+                    debug!("Sink requested a keyframe: {}", error.message);
+                    shared.force_keyframe(KeyframeReason::Request);
+                }
+                Ok(error) => warn!(
+                    "Sink reported error {}: {}",
+                    error.code_name(),
+                    error.message
+                ),
+                Err(e) => warn!("Dropping malformed ERROR: {}", e),
+            },
+            // The sink checking the source is still there, e.g. when its
+            // frames have stopped
+            PacketType::Ping => match PingPayload::parse(&packet.payload) {
+                Ok(ping) => {
+                    let pong = PongPayload::new(ping.timestamp_us, unix_time_us());
+                    if let Err(e) = sender.send(PacketType::Pong, pong.to_bytes()).await {
+                        warn!("Failed to send PONG: {}", e);
+                    }
+                }
+                Err(e) => warn!("Dropping malformed PING: {}", e),
+            },
+            other => debug!("Ignoring {:?} packet while streaming", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pacing::DEFAULT_PACING_UTILIZATION;
+    use serialwarp_core::{FrameMetadata, MAX_SEGMENT_SIZE};
+    use serialwarp_transport::{MockTransport, Transport};
+
+    const INTERVAL: Duration = Duration::from_micros(16_667);
+    const SEGMENTS: usize = 8;
+
+    /// Send one paced frame of `SEGMENTS` full segments, returning when each
+    /// packet reached the peer, counted from the start
+    async fn send_paced_frame(shared: Arc<Shared>) -> Vec<Duration> {
+        let (local, peer) = MockTransport::pair();
+        let options = PacketOptions {
+            compression: false,
+            crc: true,
+            stream_id: 0,
+        };
+        let sender = PacketSender::new(local.split().0, 0, options);
+        let start = tokio::time::Instant::now();
+        let reader = tokio::spawn(async move {
+            let mut arrivals = Vec::new();
+            while arrivals.len() < SEGMENTS {
+                peer.recv().await.unwrap();
+                arrivals.push(start.elapsed());
+            }
+            arrivals
+        });
+
+        let frame = EncodedFrame::new(
+            FrameMetadata::new(0, 0, 0, true),
+            vec![0u8; SEGMENTS * MAX_SEGMENT_SIZE],
+        );
+        let segments = frame.into_segments();
+        assert_eq!(segments.len(), SEGMENTS);
+        let bytes = segments.iter().map(FrameSegment::payload_len).sum();
+        let mut pacer = Pacer::new(PacingConfig::default());
+        assert!(pacer.start_frame(bytes, INTERVAL, None, start));
+        send_segments(
+            &sender,
+            segments,
+            &BufferPool::default(),
+            &shared,
+            Some(&mut pacer),
+        )
+        .await
+        .unwrap();
+        reader.await.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paced_segments_spread_over_interval() {
+        let shared = Arc::new(Shared::default());
+        let arrivals = send_paced_frame(Arc::clone(&shared)).await;
+
+        // One packet at once, the rest at the paced rate. Timers fire on
+        // whole milliseconds, so each gap is off by up to one, but the wait
+        // after a late one is shorter and the frame ends on time.
+        let spacing = INTERVAL.mul_f64(DEFAULT_PACING_UTILIZATION) / SEGMENTS as u32;
+        assert_eq!(arrivals[0], Duration::ZERO);
+        for gap in arrivals.windows(2).map(|pair| pair[1] - pair[0]) {
+            assert!(
+                gap + Duration::from_millis(1) >= spacing
+                    && gap <= spacing + Duration::from_millis(1),
+                "{:?}",
+                arrivals
+            );
+        }
+        let last = arrivals[SEGMENTS - 1];
+        let expected = spacing * (SEGMENTS - 1) as u32;
+        assert!(
+            last + Duration::from_millis(1) >= expected
+                && last <= expected + Duration::from_millis(1),
+            "{:?}",
+            arrivals
+        );
+        assert_eq!(shared.pacing_bypasses.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing_dropped_once_next_frame_queued() {
+        let shared = Arc::new(Shared::default());
+        tokio::spawn({
+            let shared = Arc::clone(&shared);
+            async move {
+                tokio::time::sleep(Duration::from_millis(4)).await;
+                shared.frames_queued.fetch_add(1, Ordering::AcqRel);
+            }
+        });
+        let arrivals = send_paced_frame(Arc::clone(&shared)).await;
+
+        // Paced until the next frame is queued, then the rest in one burst
+        let queued = arrivals
+            .iter()
+            .position(|&arrival| arrival >= Duration::from_millis(4))
+            .unwrap();
+        assert!(queued > 1, "{:?}", arrivals);
+        assert!(
+            arrivals[queued..]
+                .iter()
+                .all(|&arrival| arrival == arrivals[queued]),
+            "{:?}",
+            arrivals
+        );
+        assert!(arrivals[queued] < Duration::from_millis(6));
+        assert_eq!(shared.pacing_bypasses.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,593 @@
+//! Per-second session statistics for offline analysis
+//!
+//! A `StatsExporter` samples a pipeline's stats and writes a row every
+//! interval to a CSV file or a newline-delimited JSON file, one object per
+//! line, starting a new file once one grows past a size cap.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serialwarp_core::{rotated_path, unix_time_us};
+
+use crate::latency::LatencyReport;
+use crate::sink::SinkStats;
+use crate::source::SourceStats;
+
+/// Default time covered by each row
+pub const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Columns of every row, in order; also the keys of each JSON object
+pub const STATS_COLUMNS: [&str; 18] = [
+    "timestamp_ms",
+    "elapsed_s",
+    "state",
+    "fps",
+    "bitrate_bps",
+    "frames_captured",
+    "frames_sent",
+    "frames_acked",
+    "frames_received",
+    "frames_decoded",
+    "frames_presented",
+    "frames_dropped",
+    "credits",
+    "latency_avg_ms",
+    "latency_p95_ms",
+    "latency_max_ms",
+    "encode_ms",
+    "decode_ms",
+];
+
+/// Stats file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// Comma-separated values under a header row
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl StatsFormat {
+    /// JSON for a `.json` or `.jsonl` file, CSV otherwise
+    pub fn for_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json" | "jsonl") => StatsFormat::Json,
+            _ => StatsFormat::Csv,
+        }
+    }
+}
+
+/// Stats export configuration
+#[derive(Debug, Clone)]
+pub struct StatsExportConfig {
+    /// Path of the first file; rotated files get a `-N` suffix
+    pub path: PathBuf,
+    pub format: StatsFormat,
+    /// Time covered by each row
+    pub interval: Duration,
+    /// Start a new file before a row would take one past this many bytes
+    pub max_file_bytes: Option<u64>,
+}
+
+impl StatsExportConfig {
+    /// Export to `path`, in the format its extension names
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            format: StatsFormat::for_path(&path),
+            path,
+            interval: DEFAULT_STATS_INTERVAL,
+            max_file_bytes: None,
+        }
+    }
+}
+
+/// One row of session statistics. Counters are totals for the session;
+/// rates and timings cover the row's interval. Columns that don't apply to
+/// the end of the stream that wrote the row are empty, or `null` in JSON.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsRow {
+    /// Wall-clock time at the end of the interval, in milliseconds since
+    /// the Unix epoch
+    pub timestamp_ms: u64,
+    /// Seconds since the session started
+    pub elapsed_s: f64,
+    /// What the stream was doing, e.g. `streaming` or `paused`
+    pub state: String,
+    /// Frames sent by the source, or presented by the sink, per second
+    pub fps: f64,
+    /// Bits per second of FRAME packets sent or received
+    pub bitrate_bps: f64,
+    pub frames_captured: Option<u64>,
+    pub frames_sent: Option<u64>,
+    pub frames_acked: Option<u64>,
+    pub frames_received: Option<u64>,
+    pub frames_decoded: Option<u64>,
+    pub frames_presented: Option<u64>,
+    /// Frames lost at this end, for any reason
+    pub frames_dropped: u64,
+    /// Credits the source has available
+    pub credits: Option<u64>,
+    pub latency_avg_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    pub latency_max_ms: Option<f64>,
+    /// Average time the encoder took per frame
+    pub encode_ms: Option<f64>,
+    /// Average time the decoder took per frame
+    pub decode_ms: Option<f64>,
+}
+
+/// A value in one column of a `StatsRow`
+enum Value<'a> {
+    Int(u64),
+    Float(f64),
+    Text(&'a str),
+    Missing,
+}
+
+impl From<Option<u64>> for Value<'_> {
+    fn from(value: Option<u64>) -> Self {
+        value.map_or(Value::Missing, Value::Int)
+    }
+}
+
+impl From<Option<f64>> for Value<'_> {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(Value::Missing, Value::Float)
+    }
+}
+
+impl StatsRow {
+    /// Values in `STATS_COLUMNS` order
+    fn values(&self) -> [Value<'_>; 18] {
+        [
+            Value::Int(self.timestamp_ms),
+            Value::Float(self.elapsed_s),
+            Value::Text(&self.state),
+            Value::Float(self.fps),
+            Value::Float(self.bitrate_bps),
+            self.frames_captured.into(),
+            self.frames_sent.into(),
+            self.frames_acked.into(),
+            self.frames_received.into(),
+            self.frames_decoded.into(),
+            self.frames_presented.into(),
+            Value::Int(self.frames_dropped),
+            self.credits.into(),
+            self.latency_avg_ms.into(),
+            self.latency_p95_ms.into(),
+            self.latency_max_ms.into(),
+            self.encode_ms.into(),
+            self.decode_ms.into(),
+        ]
+    }
+
+    /// Serialize as a CSV record (no trailing newline)
+    pub fn to_csv(&self) -> String {
+        let fields: Vec<String> = self
+            .values()
+            .iter()
+            .map(|value| match value {
+                Value::Int(value) => value.to_string(),
+                Value::Float(value) => format_float(*value),
+                Value::Text(text) => csv_escape(text),
+                Value::Missing => String::new(),
+            })
+            .collect();
+        fields.join(",")
+    }
+
+    /// Serialize as a single JSON object (no trailing newline)
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = STATS_COLUMNS
+            .iter()
+            .zip(self.values())
+            .map(|(column, value)| {
+                let value = match value {
+                    Value::Int(value) => value.to_string(),
+                    Value::Float(value) if value.is_finite() => format_float(value),
+                    Value::Text(text) => json_string(text),
+                    Value::Float(_) | Value::Missing => "null".to_string(),
+                };
+                format!("\"{}\":{}", column, value)
+            })
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Whichever end's stats a `StatsExporter` samples
+#[derive(Debug, Clone, Copy)]
+pub enum SessionStats<'a> {
+    Source(&'a SourceStats),
+    Sink {
+        stats: &'a SinkStats,
+        latency: Option<LatencyReport>,
+        /// e.g. `streaming`, or `reconnecting` while the source is away
+        state: &'a str,
+    },
+}
+
+impl SessionStats<'_> {
+    fn totals(&self) -> Totals {
+        match *self {
+            SessionStats::Source(stats) => Totals {
+                frames: stats.frames_sent,
+                bytes: stats.bytes_sent,
+                coded_frames: stats.frames_encoded,
+                coding_time: stats.encode_time,
+            },
+            SessionStats::Sink { stats, .. } => Totals {
+                frames: stats.frames_presented,
+                bytes: stats.bytes_received,
+                coded_frames: stats.frames_received,
+                coding_time: stats.decode_time,
+            },
+        }
+    }
+}
+
+/// Counters rates are worked out from, as of the last row
+#[derive(Debug, Clone, Copy, Default)]
+struct Totals {
+    /// Frames sent or presented
+    frames: u64,
+    bytes: u64,
+    /// Frames through the encoder or decoder
+    coded_frames: u64,
+    coding_time: Duration,
+}
+
+/// Writes a `StatsRow` every interval from the stats it's given.
+///
+/// Rows go through a buffer; `finish` writes the last, partial interval and
+/// flushes it.
+pub struct StatsExporter {
+    config: StatsExportConfig,
+    file: BufWriter<File>,
+    /// Bytes in the current file
+    written: u64,
+    files_written: usize,
+    started: Instant,
+    last_row: Instant,
+    last_totals: Totals,
+}
+
+impl StatsExporter {
+    /// Create the first file; the session is taken to start now
+    pub fn create(config: StatsExportConfig) -> io::Result<Self> {
+        let started = Instant::now();
+        let mut exporter = Self {
+            file: BufWriter::new(File::create(&config.path)?),
+            config,
+            written: 0,
+            files_written: 1,
+            started,
+            last_row: started,
+            last_totals: Totals::default(),
+        };
+        exporter.write_header()?;
+        Ok(exporter)
+    }
+
+    /// Write a row for the interval ending at `now` if it's complete.
+    /// Returns whether a row was written.
+    pub fn sample(&mut self, now: Instant, stats: SessionStats<'_>) -> io::Result<bool> {
+        if now.saturating_duration_since(self.last_row) < self.config.interval {
+            return Ok(false);
+        }
+        self.write_sample(now, stats)?;
+        Ok(true)
+    }
+
+    /// Write a row for whatever time has passed since the last one, and
+    /// flush the file
+    pub fn finish(mut self, now: Instant, stats: SessionStats<'_>) -> io::Result<()> {
+        if now > self.last_row {
+            self.write_sample(now, stats)?;
+        }
+        self.file.flush()
+    }
+
+    /// Write a row as it is, e.g. one built from stats kept elsewhere
+    pub fn write_row(&mut self, row: &StatsRow) -> io::Result<()> {
+        let line = match self.config.format {
+            StatsFormat::Csv => row.to_csv(),
+            StatsFormat::Json => row.to_json(),
+        };
+        let len = line.len() as u64 + 1;
+        let over_cap = self
+            .config
+            .max_file_bytes
+            .is_some_and(|cap| self.written + len > cap);
+        if over_cap && self.written > self.header_len() {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// Files created so far
+    pub fn files_written(&self) -> usize {
+        self.files_written
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn write_sample(&mut self, now: Instant, stats: SessionStats<'_>) -> io::Result<()> {
+        let interval = now.saturating_duration_since(self.last_row);
+        let totals = stats.totals();
+        let row = self.row(now, interval, totals, stats);
+        self.last_row = now;
+        self.last_totals = totals;
+        self.write_row(&row)
+    }
+
+    fn row(
+        &self,
+        now: Instant,
+        interval: Duration,
+        totals: Totals,
+        stats: SessionStats<'_>,
+    ) -> StatsRow {
+        let last = self.last_totals;
+        let seconds = interval.as_secs_f64().max(f64::EPSILON);
+        let coded_frames = totals.coded_frames.saturating_sub(last.coded_frames);
+        let coding_ms = (coded_frames > 0).then(|| {
+            let time = totals.coding_time.saturating_sub(last.coding_time);
+            time.as_secs_f64() * 1000.0 / coded_frames as f64
+        });
+        let mut row = StatsRow {
+            timestamp_ms: unix_time_us() / 1000,
+            elapsed_s: now.saturating_duration_since(self.started).as_secs_f64(),
+            fps: totals.frames.saturating_sub(last.frames) as f64 / seconds,
+            bitrate_bps: totals.bytes.saturating_sub(last.bytes) as f64 * 8.0 / seconds,
+            ..Default::default()
+        };
+
+        match stats {
+            SessionStats::Source(stats) => {
+                row.state = if stats.paused { "paused" } else { "streaming" }.to_string();
+                row.frames_captured = Some(stats.frames_captured);
+                row.frames_sent = Some(stats.frames_sent);
+                row.frames_acked = Some(stats.frames_acked);
+                row.frames_dropped = stats.frames_skipped + stats.frames_dropped;
+                row.credits = Some(stats.credits as u64);
+                row.encode_ms = coding_ms;
+            }
+            SessionStats::Sink {
+                stats,
+                latency,
+                state,
+            } => {
+                let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+                row.state = state.to_string();
+                row.frames_received = Some(stats.frames_received);
+                row.frames_decoded = Some(stats.frames_decoded);
+                row.frames_presented = Some(stats.frames_presented);
+                row.frames_dropped = stats.frames_awaiting_keyframe
+                    + stats.frames_skipped
+                    + stats.frames_dropped_late
+                    + stats.frames_evicted;
+                row.latency_avg_ms = latency.map(|report| ms(report.average));
+                row.latency_p95_ms = latency.map(|report| ms(report.p95));
+                row.latency_max_ms = latency.map(|report| ms(report.max));
+                row.decode_ms = coding_ms;
+            }
+        }
+        row
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.config.format == StatsFormat::Csv {
+            writeln!(self.file, "{}", STATS_COLUMNS.join(","))?;
+        }
+        self.written = self.header_len();
+        Ok(())
+    }
+
+    fn header_len(&self) -> u64 {
+        match self.config.format {
+            StatsFormat::Csv => STATS_COLUMNS.join(",").len() as u64 + 1,
+            StatsFormat::Json => 0,
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let path = rotated_path(&self.config.path, self.files_written);
+        self.file = BufWriter::new(File::create(path)?);
+        self.files_written += 1;
+        self.write_header()
+    }
+}
+
+/// Three decimal places at most, without trailing zeros
+fn format_float(value: f64) -> String {
+    let rounded = (value * 1000.0).round() / 1000.0;
+    format!("{}", rounded)
+}
+
+/// Quote a CSV field if it holds a delimiter, quote, or line break
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("serialwarp-stats-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn row(state: &str) -> StatsRow {
+        StatsRow {
+            timestamp_ms: 1_700_000_000_123,
+            elapsed_s: 2.0,
+            state: state.to_string(),
+            fps: 59.94,
+            bitrate_bps: 12_000_000.0,
+            frames_received: Some(120),
+            frames_decoded: Some(119),
+            frames_presented: Some(118),
+            frames_dropped: 1,
+            latency_avg_ms: Some(12.3456),
+            decode_ms: Some(1.5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        assert_eq!(csv_escape("streaming"), "streaming");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+
+        let line = row("waiting, \"again\"").to_csv();
+        assert_eq!(
+            line,
+            "1700000000123,2,\"waiting, \"\"again\"\"\",59.94,12000000,,,,120,119,118,1,,\
+             12.346,,,,1.5"
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let json = row("quote \" and \\ and \n").to_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let object = value.as_object().unwrap();
+
+        let mut keys: Vec<&str> = object.keys().map(String::as_str).collect();
+        let mut columns = STATS_COLUMNS.to_vec();
+        keys.sort_unstable();
+        columns.sort_unstable();
+        assert_eq!(keys, columns);
+
+        assert_eq!(object["timestamp_ms"], 1_700_000_000_123u64);
+        assert_eq!(object["state"], "quote \" and \\ and \n");
+        assert_eq!(object["fps"], 59.94);
+        assert_eq!(object["frames_presented"], 118);
+        assert!(object["frames_sent"].is_null());
+        assert_eq!(object["latency_avg_ms"], 12.346);
+        assert!(object["encode_ms"].is_null());
+    }
+
+    #[test]
+    fn test_rotation_at_size_cap() {
+        let dir = test_dir("rotation");
+        let path = dir.join("session.csv");
+        let header_len = STATS_COLUMNS.join(",").len() as u64 + 1;
+        let row_len = row("streaming").to_csv().len() as u64 + 1;
+        let mut config = StatsExportConfig::new(&path);
+        // Room for two rows a file
+        config.max_file_bytes = Some(header_len + 2 * row_len + 1);
+
+        let mut exporter = StatsExporter::create(config).unwrap();
+        for _ in 0..5 {
+            exporter.write_row(&row("streaming")).unwrap();
+        }
+        assert_eq!(exporter.files_written(), 3);
+        exporter.flush().unwrap();
+
+        let rows: Vec<usize> = (0..3)
+            .map(|index| {
+                let text = std::fs::read_to_string(rotated_path(&path, index)).unwrap();
+                let mut lines = text.lines();
+                assert_eq!(lines.next(), Some(STATS_COLUMNS.join(",").as_str()));
+                lines.count()
+            })
+            .collect();
+        assert_eq!(rows, vec![2, 2, 1]);
+        assert!(dir.join("session-2.csv").exists());
+    }
+
+    #[test]
+    fn test_rows_every_interval() {
+        let dir = test_dir("interval");
+        let path = dir.join("source.jsonl");
+        let mut exporter = StatsExporter::create(StatsExportConfig::new(&path)).unwrap();
+        let start = exporter.started;
+
+        let mut stats = SourceStats {
+            frames_captured: 60,
+            frames_sent: 60,
+            frames_encoded: 60,
+            encode_time: Duration::from_millis(120),
+            bytes_sent: 1_000_000,
+            credits: 4,
+            ..Default::default()
+        };
+        let sample = |exporter: &mut StatsExporter, at: Duration, stats: &SourceStats| {
+            exporter
+                .sample(start + at, SessionStats::Source(stats))
+                .unwrap()
+        };
+        assert!(!sample(&mut exporter, Duration::from_millis(500), &stats));
+        assert!(sample(&mut exporter, Duration::from_secs(1), &stats));
+        stats.frames_sent += 30;
+        stats.frames_encoded += 30;
+        stats.encode_time += Duration::from_millis(90);
+        stats.paused = true;
+        assert!(!sample(&mut exporter, Duration::from_millis(1900), &stats));
+        exporter
+            .finish(
+                start + Duration::from_millis(1500),
+                SessionStats::Source(&stats),
+            )
+            .unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["fps"], 60.0);
+        assert_eq!(rows[0]["bitrate_bps"], 8_000_000.0);
+        assert_eq!(rows[0]["encode_ms"], 2.0);
+        assert_eq!(rows[0]["state"], "streaming");
+        assert_eq!(rows[0]["credits"], 4);
+        assert!(rows[0]["frames_presented"].is_null());
+        assert_eq!(rows[1]["elapsed_s"], 1.5);
+        assert_eq!(rows[1]["fps"], 60.0);
+        assert_eq!(rows[1]["encode_ms"], 3.0);
+        assert_eq!(rows[1]["state"], "paused");
+    }
+}
@@ -0,0 +1,20 @@
+//! Per-frame spans, and the `FrameTrace` lines logged for frames traced in
+//! full
+
+/// Target of every per-frame span and `FrameTrace` line, so they can be
+/// enabled on their own, e.g. with `RUST_LOG=serialwarp::frames=debug`
+pub const FRAME_TRACE_TARGET: &str = "serialwarp::frames";
+
+/// Span for one frame's trip through a pipeline: at DEBUG for frames traced
+/// in full, TRACE otherwise
+macro_rules! frame_span {
+    ($traced:expr, $name:literal, $($fields:tt)*) => {
+        if $traced {
+            tracing::debug_span!(target: $crate::trace::FRAME_TRACE_TARGET, $name, $($fields)*)
+        } else {
+            tracing::trace_span!(target: $crate::trace::FRAME_TRACE_TARGET, $name, $($fields)*)
+        }
+    };
+}
+
+pub(crate) use frame_span;
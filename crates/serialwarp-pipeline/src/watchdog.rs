@@ -0,0 +1,585 @@
+//! Stall detection: a stream whose transport is still up but whose frames
+//! have stopped, told apart from one that has disconnected
+
+use std::time::{Duration, Instant};
+
+/// Default for `SinkPipelineConfig::stall_timeout` and
+/// `SourcePipelineConfig::stall_timeout`
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a watchdog found when checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallEvent {
+    /// Nothing has happened for `waited`. Reported again after each further
+    /// timeout while the stall lasts, `probes` counting the reports from 1.
+    Stalled { waited: Duration, probes: u32 },
+    /// Things are moving again, or stopped being watched, after being
+    /// stalled for `stalled_for`
+    Recovered { stalled_for: Duration },
+}
+
+/// Watches for something that should keep happening, e.g. frames arriving,
+/// to stop.
+///
+/// The caller reports `progress` as it's made and `check`s from time to
+/// time, driving it with the times of both. While suspended, e.g. while the
+/// stream is paused or being reconfigured, no stall is found, and the wait
+/// starts over on `resume`.
+#[derive(Debug, Clone)]
+pub struct StallWatchdog {
+    timeout: Duration,
+    /// Latest progress, resume or reset, which the wait is counted from
+    since: Instant,
+    /// When the last progress before the current stall was made
+    stalled_since: Option<Instant>,
+    /// When progress was first made again during the current stall
+    recovered_at: Option<Instant>,
+    /// Stalls reported since `since`
+    probes: u32,
+    suspended: bool,
+}
+
+impl StallWatchdog {
+    /// A watchdog finding a stall once `timeout` passes without progress,
+    /// counting from `now`
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            since: now,
+            stalled_since: None,
+            recovered_at: None,
+            probes: 0,
+            suspended: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Progress was made at `now`
+    pub fn progress(&mut self, now: Instant) {
+        self.since = now;
+        self.probes = 0;
+        if self.stalled_since.is_some() {
+            self.recovered_at.get_or_insert(now);
+        }
+    }
+
+    /// Count the wait from `now` again, as after retrying whatever stalled,
+    /// without ending a stall
+    pub fn reset(&mut self, now: Instant) {
+        self.since = now;
+        self.probes = 0;
+    }
+
+    /// Stop looking for a stall; one already found is reported recovered by
+    /// the next `check`
+    pub fn suspend(&mut self) {
+        self.suspended = true;
+    }
+
+    /// Look for a stall again, waiting from `now`
+    pub fn resume(&mut self, now: Instant) {
+        if self.suspended {
+            self.suspended = false;
+            self.reset(now);
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Whether a stall has been reported and not yet recovered from
+    pub fn is_stalled(&self) -> bool {
+        self.stalled_since.is_some()
+    }
+
+    /// Report a stall found or recovered from by `now`
+    pub fn check(&mut self, now: Instant) -> Option<StallEvent> {
+        if let Some(stalled_since) = self.stalled_since {
+            let recovered_at = match self.recovered_at {
+                Some(at) => Some(at),
+                None => self.suspended.then_some(now),
+            };
+            if let Some(at) = recovered_at {
+                self.stalled_since = None;
+                self.recovered_at = None;
+                let stalled_for = at.saturating_duration_since(stalled_since);
+                return Some(StallEvent::Recovered { stalled_for });
+            }
+        }
+        if self.suspended {
+            return None;
+        }
+
+        let waited = now.saturating_duration_since(self.since);
+        if waited < self.timeout * (self.probes + 1) {
+            return None;
+        }
+        self.probes += 1;
+        self.stalled_since.get_or_insert(self.since);
+        Some(StallEvent::Stalled {
+            waited,
+            probes: self.probes,
+        })
+    }
+}
+
+/// What a source's stream is stuck on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallCause {
+    /// The frame source has stopped delivering frames
+    Capture,
+    /// Frames went into the encoder and haven't come out, though capture
+    /// goes on
+    Encoder,
+    /// Frames were sent and the sink hasn't acked them
+    Acks,
+}
+
+/// A stall found by `SourceWatchdog::check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceStall {
+    pub cause: StallCause,
+    pub event: StallEvent,
+}
+
+/// Watches each stage of the source for a stall: capture, the sink's acks,
+/// and the encoder.
+///
+/// The encoder is only watched while frames given to it haven't come out,
+/// and the acks while frames are in flight, so going without input or
+/// credits isn't mistaken for a stall. Only one cause is reported at a
+/// time, the first in that order: with capture stalled the encoder has
+/// nothing to work on, and an encoder that holds frames back can't put
+/// them out while the credits are tied up in frames the sink hasn't acked.
+#[derive(Debug, Clone)]
+pub struct SourceWatchdog {
+    capture: StallWatchdog,
+    acks: StallWatchdog,
+    encoder: StallWatchdog,
+    /// Frames given to the encoder that it hasn't put out
+    pending: u64,
+    /// Frames sent and not yet acked
+    in_flight: u64,
+    suspended: bool,
+}
+
+impl SourceWatchdog {
+    /// Watch for a stage going `timeout` without progress, counting from
+    /// `now`
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        let mut idle = StallWatchdog::new(timeout, now);
+        idle.suspend();
+        Self {
+            capture: StallWatchdog::new(timeout, now),
+            acks: idle.clone(),
+            encoder: idle,
+            pending: 0,
+            in_flight: 0,
+            suspended: false,
+        }
+    }
+
+    /// A frame was captured
+    pub fn captured(&mut self, now: Instant) {
+        self.capture.progress(now);
+    }
+
+    /// A frame was given to the encoder
+    pub fn encoding(&mut self, now: Instant) {
+        self.pending += 1;
+        if !self.suspended {
+            self.encoder.resume(now);
+        }
+    }
+
+    /// The encoder put out a frame
+    pub fn encoded(&mut self, now: Instant) {
+        self.pending = self.pending.saturating_sub(1);
+        self.encoder.progress(now);
+        if self.pending == 0 {
+            self.encoder.suspend();
+        }
+    }
+
+    /// The encoder dropped `frames` frames, which will never come out
+    pub fn encoder_dropped(&mut self, frames: u64) {
+        self.pending = self.pending.saturating_sub(frames);
+        if self.pending == 0 {
+            self.encoder.suspend();
+        }
+    }
+
+    /// The encoder was restarted after stalling, and has put out whatever
+    /// the old one still could. It gets another timeout to put out frames
+    /// again. Returns how many frames were lost in the old one, whose
+    /// credits won't come back with an ack.
+    pub fn encoder_restarted(&mut self, now: Instant) -> u64 {
+        self.encoder.reset(now);
+        std::mem::take(&mut self.pending)
+    }
+
+    /// A frame was sent
+    pub fn sent(&mut self, now: Instant) {
+        self.in_flight += 1;
+        if !self.suspended {
+            self.acks.resume(now);
+        }
+    }
+
+    /// The sink acked `frames` frames
+    pub fn acked(&mut self, frames: u64, now: Instant) {
+        self.in_flight = self.in_flight.saturating_sub(frames);
+        self.acks.progress(now);
+        if self.in_flight == 0 {
+            self.acks.suspend();
+        }
+    }
+
+    /// Stop or start watching, e.g. while paused or reconfiguring; the
+    /// waits start over from `now` when watching starts again
+    pub fn set_suspended(&mut self, suspended: bool, now: Instant) {
+        if suspended == self.suspended {
+            return;
+        }
+        self.suspended = suspended;
+        if suspended {
+            self.capture.suspend();
+            self.acks.suspend();
+            self.encoder.suspend();
+            return;
+        }
+        self.capture.resume(now);
+        if self.in_flight > 0 {
+            self.acks.resume(now);
+        }
+        if self.pending > 0 {
+            self.encoder.resume(now);
+        }
+    }
+
+    /// Report stalls found or recovered from by `now`
+    pub fn check(&mut self, now: Instant) -> Vec<SourceStall> {
+        let mut found = Vec::new();
+        let mut report = |cause, watchdog: &mut StallWatchdog| {
+            if let Some(event) = watchdog.check(now) {
+                found.push(SourceStall { cause, event });
+            }
+        };
+
+        report(StallCause::Capture, &mut self.capture);
+        if self.capture.is_stalled() {
+            self.acks.suspend();
+            self.encoder.suspend();
+        }
+        if self.encoder.is_stalled() {
+            self.acks.suspend();
+        }
+        report(StallCause::Acks, &mut self.acks);
+        if self.acks.is_stalled() {
+            self.encoder.suspend();
+        }
+        report(StallCause::Encoder, &mut self.encoder);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn stalled(waited: u64, probes: u32) -> Option<StallEvent> {
+        Some(StallEvent::Stalled {
+            waited: ms(waited),
+            probes,
+        })
+    }
+
+    fn recovered(stalled_for: u64) -> Option<StallEvent> {
+        Some(StallEvent::Recovered {
+            stalled_for: ms(stalled_for),
+        })
+    }
+
+    #[test]
+    fn test_stall_and_recovery() {
+        let start = Instant::now();
+        let mut watchdog = StallWatchdog::new(TIMEOUT, start);
+        watchdog.progress(start + ms(500));
+        assert_eq!(watchdog.check(start + ms(2000)), None);
+
+        // Reported once per timeout while it lasts
+        assert_eq!(watchdog.check(start + ms(2500)), stalled(2000, 1));
+        assert!(watchdog.is_stalled());
+        assert_eq!(watchdog.check(start + ms(3000)), None);
+        assert_eq!(watchdog.check(start + ms(4600)), stalled(4100, 2));
+        assert_eq!(watchdog.check(start + ms(5000)), None);
+
+        // Progress ends the stall, timed from the last progress before it
+        watchdog.progress(start + ms(5000));
+        watchdog.progress(start + ms(5100));
+        assert_eq!(watchdog.check(start + ms(5200)), recovered(4500));
+        assert!(!watchdog.is_stalled());
+        assert_eq!(watchdog.check(start + ms(7000)), None);
+        assert_eq!(watchdog.check(start + ms(7100)), stalled(2000, 1));
+    }
+
+    #[test]
+    fn test_suspended_never_stalls() {
+        let start = Instant::now();
+        let mut watchdog = StallWatchdog::new(TIMEOUT, start);
+        watchdog.suspend();
+        assert_eq!(watchdog.check(start + ms(10_000)), None);
+
+        // The wait starts over on resuming
+        watchdog.resume(start + ms(10_000));
+        assert_eq!(watchdog.check(start + ms(11_000)), None);
+        assert_eq!(watchdog.check(start + ms(12_000)), stalled(2000, 1));
+
+        // Suspending ends a stall, e.g. pausing a frozen stream
+        watchdog.suspend();
+        assert_eq!(watchdog.check(start + ms(12_500)), recovered(2500));
+        assert_eq!(watchdog.check(start + ms(20_000)), None);
+    }
+
+    #[test]
+    fn test_reset_keeps_stall() {
+        let start = Instant::now();
+        let mut watchdog = StallWatchdog::new(TIMEOUT, start);
+        assert_eq!(watchdog.check(start + TIMEOUT), stalled(2000, 1));
+
+        // Retrying gives it another timeout, still stalled meanwhile
+        watchdog.reset(start + ms(2000));
+        assert!(watchdog.is_stalled());
+        assert_eq!(watchdog.check(start + ms(3000)), None);
+        assert_eq!(watchdog.check(start + ms(4000)), stalled(2000, 1));
+        watchdog.progress(start + ms(4500));
+        assert_eq!(watchdog.check(start + ms(4500)), recovered(4500));
+    }
+
+    /// Capture, encode, send and ack a frame every 100ms from `from` until
+    /// `until`, with no stall found
+    fn stream(watchdog: &mut SourceWatchdog, from: Instant, until: Instant) {
+        let mut now = from;
+        while now < until {
+            watchdog.captured(now);
+            watchdog.encoding(now);
+            watchdog.encoded(now);
+            watchdog.sent(now);
+            watchdog.acked(1, now);
+            assert_eq!(watchdog.check(now), Vec::new());
+            now += ms(100);
+        }
+    }
+
+    fn found(cause: StallCause, event: Option<StallEvent>) -> Vec<SourceStall> {
+        vec![SourceStall {
+            cause,
+            event: event.unwrap(),
+        }]
+    }
+
+    #[test]
+    fn test_source_capture_stall() {
+        let start = Instant::now();
+        let mut watchdog = SourceWatchdog::new(TIMEOUT, start);
+        stream(&mut watchdog, start, start + ms(1000));
+
+        // Capture stops with a frame still in flight and one in the
+        // encoder; only capture is reported
+        let last = start + ms(1000);
+        watchdog.captured(last);
+        watchdog.encoding(last);
+        watchdog.encoded(last);
+        watchdog.sent(last);
+        watchdog.encoding(last);
+        assert_eq!(watchdog.check(last + ms(1900)), Vec::new());
+        assert_eq!(
+            watchdog.check(last + ms(2000)),
+            found(StallCause::Capture, stalled(2000, 1))
+        );
+        assert_eq!(
+            watchdog.check(last + ms(4000)),
+            found(StallCause::Capture, stalled(4000, 2))
+        );
+
+        let resumed = last + ms(4500);
+        watchdog.captured(resumed);
+        assert_eq!(
+            watchdog.check(resumed),
+            found(StallCause::Capture, recovered(4500))
+        );
+        watchdog.encoded(resumed);
+        watchdog.acked(1, resumed);
+        stream(&mut watchdog, resumed, resumed + ms(3000));
+    }
+
+    #[test]
+    fn test_source_encoder_stall_and_restart() {
+        let start = Instant::now();
+        let mut watchdog = SourceWatchdog::new(TIMEOUT, start);
+        stream(&mut watchdog, start, start + ms(1000));
+
+        // The encoder stops putting frames out. Capture goes on, though
+        // the frames it swallowed took every credit, so after a few
+        // nothing more goes in.
+        let wedged = start + ms(1000);
+        let mut now = wedged;
+        while now < wedged + ms(2000) {
+            watchdog.captured(now);
+            if now < wedged + ms(800) {
+                watchdog.encoding(now);
+            }
+            assert_eq!(watchdog.check(now), Vec::new());
+            now += ms(100);
+        }
+        watchdog.captured(now);
+        assert_eq!(
+            watchdog.check(now),
+            found(StallCause::Encoder, stalled(2000, 1))
+        );
+
+        // The frames lost in it are handed back, and the new one gets
+        // another timeout, still stalled meanwhile
+        assert_eq!(watchdog.encoder_restarted(now), 8);
+        let restarted = now;
+        while now < restarted + ms(2000) {
+            now += ms(100);
+            watchdog.captured(now);
+            watchdog.encoding(now);
+            let expected = if now == restarted + ms(2000) {
+                found(StallCause::Encoder, stalled(2000, 1))
+            } else {
+                Vec::new()
+            };
+            assert_eq!(watchdog.check(now), expected);
+        }
+
+        // The second restart took
+        assert_eq!(watchdog.encoder_restarted(now), 20);
+        now += ms(100);
+        watchdog.captured(now);
+        watchdog.encoding(now);
+        watchdog.encoded(now);
+        assert_eq!(
+            watchdog.check(now),
+            found(StallCause::Encoder, recovered(4100))
+        );
+        stream(&mut watchdog, now, now + ms(3000));
+    }
+
+    #[test]
+    fn test_source_encoder_idle_is_no_stall() {
+        let start = Instant::now();
+        let mut watchdog = SourceWatchdog::new(TIMEOUT, start);
+
+        // Left without credits, or with its output dropped rather than
+        // held back, the encoder has nothing to put out
+        watchdog.captured(start);
+        watchdog.encoding(start);
+        watchdog.encoding(start);
+        watchdog.encoded(start);
+        watchdog.encoder_dropped(1);
+        let mut now = start;
+        while now < start + ms(10_000) {
+            now += ms(100);
+            watchdog.captured(now);
+            assert_eq!(watchdog.check(now), Vec::new());
+        }
+    }
+
+    #[test]
+    fn test_source_ack_stall() {
+        let start = Instant::now();
+        let mut watchdog = SourceWatchdog::new(TIMEOUT, start);
+        stream(&mut watchdog, start, start + ms(1000));
+
+        // The acks stop coming, and with the credits tied up in frames in
+        // flight, the encoder is left holding one back: that's the acks
+        let mut now = start + ms(1000);
+        let first_unacked = now;
+        while now < first_unacked + ms(500) {
+            watchdog.captured(now);
+            watchdog.encoding(now);
+            watchdog.encoded(now);
+            watchdog.sent(now);
+            now += ms(100);
+        }
+        watchdog.encoding(now);
+        let stall = loop {
+            watchdog.captured(now);
+            let found = watchdog.check(now);
+            if !found.is_empty() {
+                break found;
+            }
+            now += ms(100);
+        };
+        assert_eq!(now - first_unacked, TIMEOUT);
+        assert_eq!(stall, found(StallCause::Acks, stalled(2000, 1)));
+        now += TIMEOUT;
+        watchdog.captured(now);
+        assert_eq!(
+            watchdog.check(now),
+            found(StallCause::Acks, stalled(4000, 2))
+        );
+
+        // Acked at last, the held frame comes out
+        now += ms(50);
+        watchdog.acked(5, now);
+        watchdog.encoded(now);
+        watchdog.sent(now);
+        assert_eq!(
+            watchdog.check(now),
+            found(StallCause::Acks, recovered(4050))
+        );
+        watchdog.acked(1, now);
+
+        // With everything acked, going without frames isn't an ack stall
+        watchdog.captured(now + ms(1000));
+        assert_eq!(watchdog.check(now + ms(2500)), Vec::new());
+    }
+
+    #[test]
+    fn test_source_suspended_while_paused() {
+        let start = Instant::now();
+        let mut watchdog = SourceWatchdog::new(TIMEOUT, start);
+        stream(&mut watchdog, start, start + ms(1000));
+
+        // Paused with a frame in flight and one in the encoder: nothing is
+        // captured, acked or put out
+        let paused = start + ms(1000);
+        watchdog.sent(paused);
+        watchdog.encoding(paused);
+        watchdog.set_suspended(true, paused);
+        // The pause's black frame goes through the encoder too
+        watchdog.encoding(paused);
+        assert_eq!(watchdog.check(start + ms(60_000)), Vec::new());
+
+        // Resuming gives every stage a full timeout again
+        let resumed = start + ms(60_000);
+        watchdog.set_suspended(false, resumed);
+        watchdog.captured(resumed + ms(100));
+        assert_eq!(watchdog.check(resumed + ms(1900)), Vec::new());
+        assert_eq!(
+            watchdog.check(resumed + ms(2000)),
+            found(StallCause::Acks, stalled(2000, 1))
+        );
+
+        // Reconfiguring a stalled stream ends the stall
+        watchdog.set_suspended(true, resumed + ms(2100));
+        assert_eq!(
+            watchdog.check(resumed + ms(2200)),
+            found(StallCause::Acks, recovered(2200))
+        );
+        assert_eq!(watchdog.check(resumed + ms(60_000)), Vec::new());
+    }
+}
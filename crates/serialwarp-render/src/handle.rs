@@ -0,0 +1,720 @@
+//! A renderer on its own thread, driven from async code over channels
+//!
+//! SDL's canvas and event pump can't leave the thread that made them, nor
+//! can winit's event loop, so `Renderer::spawn` makes them on a dedicated
+//! thread and hands back a `RendererHandle` that can be used from anywhere.
+//! Where windows must be made on the main thread, as on macOS, or where
+//! there are several at once, a `RenderHost` running on one thread makes
+//! them instead.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serialwarp_core::{DecodedFrame, RenderError};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+
+#[cfg(feature = "wgpu-backend")]
+use crate::WgpuRenderer;
+use crate::{
+    DisplayInfo, KeyAction, Keycode, PlaceholderKind, RenderBackend, RenderOverlayStats, Renderer,
+    RendererConfig, ScalingMode, WindowGeometry,
+};
+
+/// Frames waiting for the render thread before `present` waits
+const FRAME_QUEUE_DEPTH: usize = 2;
+
+/// Settings changes waiting for the render thread
+const COMMAND_QUEUE_DEPTH: usize = 16;
+
+/// Events waiting to be read; key presses past this are dropped
+const EVENT_QUEUE_DEPTH: usize = 64;
+
+/// How long the render thread sleeps when there's nothing to present, so
+/// window events are still handled promptly
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Input and failures from the render thread
+#[derive(Debug)]
+pub enum RenderEvent {
+    /// The window was closed or the quit shortcut pressed. The thread stops
+    /// after this, so the event receiver closes too.
+    Quit,
+    /// A key that isn't bound to a shortcut
+    Key(Keycode),
+    /// A shortcut the renderer leaves to its caller, such as taking a
+    /// screenshot
+    Shortcut(KeyAction),
+    /// A frame or placeholder couldn't be shown; the thread carries on
+    /// with the next one
+    PresentFailed(RenderError),
+    /// The window was moved or resized, or entered or left fullscreen, by
+    /// the user. Sent for every step of a drag, so wait for them to stop
+    /// before acting on one.
+    WindowChanged(WindowGeometry),
+}
+
+/// What the render thread drives; every renderer has these as inherent
+/// methods
+trait Backend {
+    fn process_events(&mut self) -> bool;
+    fn key_presses(&self) -> &[Keycode];
+    fn shortcuts(&self) -> &[KeyAction];
+    fn geometry_changed(&self) -> bool;
+    fn geometry(&self) -> WindowGeometry;
+    fn set_geometry(&mut self, geometry: &WindowGeometry) -> Result<(), RenderError>;
+    fn present(&mut self, frame: &DecodedFrame) -> Result<(), RenderError>;
+    fn present_placeholder(&mut self, kind: PlaceholderKind) -> Result<(), RenderError>;
+    fn set_fullscreen(&mut self, fullscreen: bool);
+    fn set_window_size(&mut self, width: u32, height: u32) -> Result<(), RenderError>;
+    fn set_scaling_mode(&mut self, mode: ScalingMode);
+    fn set_overlay_stats(&mut self, stats: &RenderOverlayStats);
+    fn set_overlay_visible(&mut self, visible: bool);
+    fn move_to_display(&mut self, index: usize) -> Result<(), RenderError>;
+    fn list_displays(&self) -> Result<Vec<DisplayInfo>, RenderError>;
+}
+
+macro_rules! impl_backend {
+    ($renderer:ty) => {
+        impl Backend for $renderer {
+            fn process_events(&mut self) -> bool {
+                <$renderer>::process_events(self)
+            }
+            fn key_presses(&self) -> &[Keycode] {
+                <$renderer>::key_presses(self)
+            }
+            fn shortcuts(&self) -> &[KeyAction] {
+                <$renderer>::shortcuts(self)
+            }
+            fn geometry_changed(&self) -> bool {
+                <$renderer>::geometry_changed(self)
+            }
+            fn geometry(&self) -> WindowGeometry {
+                <$renderer>::geometry(self)
+            }
+            fn set_geometry(&mut self, geometry: &WindowGeometry) -> Result<(), RenderError> {
+                <$renderer>::set_geometry(self, geometry)
+            }
+            fn present(&mut self, frame: &DecodedFrame) -> Result<(), RenderError> {
+                <$renderer>::present(self, frame)
+            }
+            fn present_placeholder(&mut self, kind: PlaceholderKind) -> Result<(), RenderError> {
+                <$renderer>::present_placeholder(self, kind)
+            }
+            fn set_fullscreen(&mut self, fullscreen: bool) {
+                <$renderer>::set_fullscreen(self, fullscreen)
+            }
+            fn set_window_size(&mut self, width: u32, height: u32) -> Result<(), RenderError> {
+                <$renderer>::set_window_size(self, width, height)
+            }
+            fn set_scaling_mode(&mut self, mode: ScalingMode) {
+                <$renderer>::set_scaling_mode(self, mode)
+            }
+            fn set_overlay_stats(&mut self, stats: &RenderOverlayStats) {
+                <$renderer>::set_overlay_stats(self, stats)
+            }
+            fn set_overlay_visible(&mut self, visible: bool) {
+                <$renderer>::set_overlay_visible(self, visible)
+            }
+            fn move_to_display(&mut self, index: usize) -> Result<(), RenderError> {
+                <$renderer>::move_to_display(self, index)
+            }
+            fn list_displays(&self) -> Result<Vec<DisplayInfo>, RenderError> {
+                <$renderer>::list_displays(self)
+            }
+        }
+    };
+}
+
+impl_backend!(Renderer);
+#[cfg(feature = "wgpu-backend")]
+impl_backend!(WgpuRenderer);
+
+/// Open the renderer `config.backend` names
+fn open(config: RendererConfig) -> Result<Box<dyn Backend>, RenderError> {
+    match config.backend {
+        RenderBackend::Sdl => Ok(Box::new(Renderer::new(config)?)),
+        #[cfg(feature = "wgpu-backend")]
+        RenderBackend::Wgpu => Ok(Box::new(WgpuRenderer::new(config)?)),
+        #[cfg(not(feature = "wgpu-backend"))]
+        RenderBackend::Wgpu => Err(RenderError::RendererCreationFailed(
+            "built without the wgpu-backend feature".to_string(),
+        )),
+    }
+}
+
+/// Requests for the render thread other than frames
+enum Command {
+    SetFullscreen(bool),
+    SetWindowSize(u32, u32, oneshot::Sender<Result<(), RenderError>>),
+    SetScalingMode(ScalingMode),
+    SetOverlayStats(RenderOverlayStats),
+    SetOverlayVisible(bool),
+    MoveToDisplay(usize, oneshot::Sender<Result<(), RenderError>>),
+    ListDisplays(oneshot::Sender<Result<Vec<DisplayInfo>, RenderError>>),
+    Geometry(oneshot::Sender<WindowGeometry>),
+    SetGeometry(WindowGeometry, oneshot::Sender<Result<(), RenderError>>),
+    ShowPlaceholder(PlaceholderKind),
+}
+
+/// Handle to a renderer running on its own thread, from `Renderer::spawn`.
+///
+/// Frames are shown as soon as the thread gets to them. If it falls behind,
+/// frames queued behind a newer one are late and dropped, see
+/// `frames_dropped`; `present` only waits once even the newest frames queue
+/// up. Dropping the handle stops the thread and closes the window.
+pub struct RendererHandle {
+    frames: mpsc::Sender<DecodedFrame>,
+    commands: mpsc::Sender<Command>,
+    events: mpsc::Receiver<RenderEvent>,
+    frames_dropped: Arc<AtomicU64>,
+}
+
+impl RendererHandle {
+    /// Queue a frame to be shown
+    pub async fn present(&self, frame: DecodedFrame) -> Result<(), RenderError> {
+        self.frames
+            .send(frame)
+            .await
+            .map_err(|_| RenderError::Stopped)
+    }
+
+    /// Show a placeholder saying why there's no video, see
+    /// `Renderer::present_placeholder`. It stays up, redrawn as the window
+    /// changes, until the next frame is presented.
+    pub async fn present_placeholder(&self, kind: PlaceholderKind) -> Result<(), RenderError> {
+        self.send(Command::ShowPlaceholder(kind)).await
+    }
+
+    /// Enter or leave fullscreen
+    pub async fn set_fullscreen(&self, fullscreen: bool) -> Result<(), RenderError> {
+        self.send(Command::SetFullscreen(fullscreen)).await
+    }
+
+    /// Resize the window, in points, see `Renderer::set_window_size`
+    pub async fn set_window_size(&self, width: u32, height: u32) -> Result<(), RenderError> {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::SetWindowSize(width, height, reply))
+            .await?;
+        result.await.map_err(|_| RenderError::Stopped)?
+    }
+
+    /// Change how frames are scaled into the window
+    pub async fn set_scaling_mode(&self, mode: ScalingMode) -> Result<(), RenderError> {
+        self.send(Command::SetScalingMode(mode)).await
+    }
+
+    /// Update the statistics shown by the overlay
+    pub async fn set_overlay_stats(&self, stats: RenderOverlayStats) -> Result<(), RenderError> {
+        self.send(Command::SetOverlayStats(stats)).await
+    }
+
+    /// Show or hide the statistics overlay
+    pub async fn set_overlay_visible(&self, visible: bool) -> Result<(), RenderError> {
+        self.send(Command::SetOverlayVisible(visible)).await
+    }
+
+    /// Move the window onto the given display, see `Renderer::move_to_display`
+    pub async fn move_to_display(&self, index: usize) -> Result<(), RenderError> {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::MoveToDisplay(index, reply)).await?;
+        result.await.map_err(|_| RenderError::Stopped)?
+    }
+
+    /// Enumerate the displays attached to this machine
+    pub async fn list_displays(&self) -> Result<Vec<DisplayInfo>, RenderError> {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::ListDisplays(reply)).await?;
+        result.await.map_err(|_| RenderError::Stopped)?
+    }
+
+    /// Where the window is now, see `Renderer::geometry`
+    pub async fn geometry(&self) -> Result<WindowGeometry, RenderError> {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::Geometry(reply)).await?;
+        result.await.map_err(|_| RenderError::Stopped)
+    }
+
+    /// Put the window back where it was, see `Renderer::set_geometry`
+    pub async fn set_geometry(&self, geometry: WindowGeometry) -> Result<(), RenderError> {
+        let (reply, result) = oneshot::channel();
+        self.send(Command::SetGeometry(geometry, reply)).await?;
+        result.await.map_err(|_| RenderError::Stopped)?
+    }
+
+    /// Input and failures from the window. Closes once the thread stops,
+    /// which callers can treat like `RenderEvent::Quit`.
+    pub fn events(&mut self) -> &mut mpsc::Receiver<RenderEvent> {
+        &mut self.events
+    }
+
+    /// Frames dropped for being late so far
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    async fn send(&self, command: Command) -> Result<(), RenderError> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| RenderError::Stopped)
+    }
+}
+
+impl Renderer {
+    /// Open a renderer on a thread of its own and return a handle to it,
+    /// with the backend `config.backend` names.
+    ///
+    /// Blocks until the window is open, returning the error if it couldn't
+    /// be. The thread runs until the window is closed or the handle is
+    /// dropped; join it to be sure the window is gone.
+    ///
+    /// macOS only lets the main thread open windows, so there this fails
+    /// with `RenderError::MainThreadOnly`; use a `RenderHost` instead. So
+    /// does SDL with windows on several threads at once: open more than
+    /// one through a host.
+    pub fn spawn(config: RendererConfig) -> Result<(RendererHandle, JoinHandle<()>), RenderError> {
+        if cfg!(target_os = "macos") {
+            return Err(RenderError::MainThreadOnly);
+        }
+
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("serialwarp-render".to_string())
+            .spawn(move || match Renderer::open_local(config) {
+                Ok((handle, render_loop)) => {
+                    let _ = ready_tx.send(Ok(handle));
+                    render_loop.run();
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            })
+            .map_err(|e| RenderError::SdlInitFailed(e.to_string()))?;
+
+        // Only fails if the thread panicked before the window was open
+        let handle = ready_rx.recv().map_err(|_| RenderError::Stopped)??;
+        Ok((handle, thread))
+    }
+
+    /// Open a renderer on the current thread and return a handle to it,
+    /// with the loop that draws it. The loop has to run on this thread,
+    /// while the handle is used from another.
+    pub fn open_local(config: RendererConfig) -> Result<(RendererHandle, RenderLoop), RenderError> {
+        let (frame_tx, frame_rx) = mpsc::channel(FRAME_QUEUE_DEPTH);
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
+        let (event_tx, event_rx) = mpsc::channel(EVENT_QUEUE_DEPTH);
+        let frames_dropped = Arc::new(AtomicU64::new(0));
+
+        let render_loop = RenderLoop {
+            renderer: open(config)?,
+            placeholder: None,
+            frames: frame_rx,
+            commands: command_rx,
+            events: event_tx,
+            frames_dropped: Arc::clone(&frames_dropped),
+        };
+        let handle = RendererHandle {
+            frames: frame_tx,
+            commands: command_tx,
+            events: event_rx,
+            frames_dropped,
+        };
+        Ok((handle, render_loop))
+    }
+}
+
+/// A request to a `RenderHost`
+struct OpenRequest {
+    config: RendererConfig,
+    reply: std::sync::mpsc::Sender<Result<RendererHandle, RenderError>>,
+    /// Dropped once the window is gone
+    closed: std::sync::mpsc::Sender<()>,
+}
+
+/// Opens and draws renderers on the thread that calls `run`, for code on
+/// other threads. On macOS, where only the main thread may open windows,
+/// this is how async code gets a renderer, and anywhere it's how to have
+/// several windows open at once.
+pub struct RenderHost {
+    requests: std::sync::mpsc::Receiver<OpenRequest>,
+}
+
+impl RenderHost {
+    /// A host and the opener that asks it for renderers
+    pub fn new() -> (Self, RenderOpener) {
+        let (requests_tx, requests) = std::sync::mpsc::channel();
+        (
+            Self { requests },
+            RenderOpener {
+                requests: requests_tx,
+            },
+        )
+    }
+
+    /// Open each renderer asked for and draw them all, each until its
+    /// window closes. Returns once every `RenderOpener` is dropped and
+    /// every window is closed.
+    pub fn run(self) {
+        // Each with the sender its `RenderClosed` waits on, dropped after it
+        let mut open: Vec<(RenderLoop, std::sync::mpsc::Sender<()>)> = Vec::new();
+        let mut requests_open = true;
+        loop {
+            // Only wait for a request while there's nothing to draw
+            let request = if open.is_empty() {
+                match self.requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return,
+                }
+            } else if requests_open {
+                match self.requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(std::sync::mpsc::TryRecvError::Empty) => None,
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        requests_open = false;
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if let Some(OpenRequest {
+                config,
+                reply,
+                closed,
+            }) = request
+            {
+                match Renderer::open_local(config) {
+                    Ok((handle, render_loop)) => {
+                        let _ = reply.send(Ok(handle));
+                        open.push((render_loop, closed));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+                continue;
+            }
+
+            let mut busy = false;
+            open.retain_mut(|(render_loop, _)| match render_loop.step() {
+                Some(presented) => {
+                    busy |= presented;
+                    true
+                }
+                None => false,
+            });
+            if !busy {
+                thread::sleep(IDLE_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Asks a `RenderHost` for renderers; clones ask the same host
+#[derive(Clone)]
+pub struct RenderOpener {
+    requests: std::sync::mpsc::Sender<OpenRequest>,
+}
+
+impl RenderOpener {
+    /// Open a renderer on the host's thread and return a handle to it, like
+    /// `Renderer::spawn`. Blocks until the window is open, returning the
+    /// error if it couldn't be.
+    pub fn open(
+        &self,
+        config: RendererConfig,
+    ) -> Result<(RendererHandle, RenderClosed), RenderError> {
+        let (reply, result) = std::sync::mpsc::channel();
+        let (closed_tx, closed) = std::sync::mpsc::channel();
+        let request = OpenRequest {
+            config,
+            reply,
+            closed: closed_tx,
+        };
+        self.requests
+            .send(request)
+            .map_err(|_| RenderError::Stopped)?;
+        let handle = result.recv().map_err(|_| RenderError::Stopped)??;
+        Ok((handle, RenderClosed(closed)))
+    }
+}
+
+/// Waits for the window of a renderer from `RenderOpener::open` to close
+pub struct RenderClosed(std::sync::mpsc::Receiver<()>);
+
+impl RenderClosed {
+    /// Block until the window is gone; drop the renderer's handle first
+    pub fn wait(self) {
+        let _ = self.0.recv();
+    }
+}
+
+/// The renderer's side of the channels, from `Renderer::open_local`; runs
+/// on the thread that opened it
+pub struct RenderLoop {
+    renderer: Box<dyn Backend>,
+    /// Placeholder showing until the next frame
+    placeholder: Option<PlaceholderKind>,
+    frames: mpsc::Receiver<DecodedFrame>,
+    commands: mpsc::Receiver<Command>,
+    events: mpsc::Sender<RenderEvent>,
+    frames_dropped: Arc<AtomicU64>,
+}
+
+impl RenderLoop {
+    /// Draw frames and handle the window until it's closed or the handle
+    /// is dropped
+    pub fn run(mut self) {
+        while let Some(presented) = self.step() {
+            if !presented {
+                thread::sleep(IDLE_POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Handle the window's events and the handle's commands, and show the
+    /// newest frame, once. None when the window is closed or the handle
+    /// dropped, otherwise whether there was a frame.
+    fn step(&mut self) -> Option<bool> {
+        if !self.renderer.process_events() {
+            let _ = self.events.try_send(RenderEvent::Quit);
+            return None;
+        }
+        for &key in self.renderer.key_presses() {
+            let _ = self.events.try_send(RenderEvent::Key(key));
+        }
+        for &action in self.renderer.shortcuts() {
+            let _ = self.events.try_send(RenderEvent::Shortcut(action));
+        }
+        if self.renderer.geometry_changed() {
+            let geometry = self.renderer.geometry();
+            let _ = self.events.try_send(RenderEvent::WindowChanged(geometry));
+            // Laid out anew for the window's new size
+            if let Some(kind) = self.placeholder {
+                self.show_placeholder(kind);
+            }
+        }
+
+        loop {
+            match self.commands.try_recv() {
+                Ok(command) => self.apply(command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+
+        match newest_frame(&mut self.frames, &self.frames_dropped) {
+            Ok(Some(frame)) => {
+                self.placeholder = None;
+                if let Err(e) = self.renderer.present(&frame) {
+                    let _ = self.events.try_send(RenderEvent::PresentFailed(e));
+                }
+                Some(true)
+            }
+            Ok(None) => Some(false),
+            Err(HandleDropped) => None,
+        }
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::SetFullscreen(fullscreen) => self.renderer.set_fullscreen(fullscreen),
+            Command::SetWindowSize(width, height, reply) => {
+                let _ = reply.send(self.renderer.set_window_size(width, height));
+            }
+            Command::SetScalingMode(mode) => self.renderer.set_scaling_mode(mode),
+            Command::SetOverlayStats(stats) => self.renderer.set_overlay_stats(&stats),
+            Command::SetOverlayVisible(visible) => self.renderer.set_overlay_visible(visible),
+            Command::MoveToDisplay(index, reply) => {
+                let _ = reply.send(self.renderer.move_to_display(index));
+            }
+            Command::ListDisplays(reply) => {
+                let _ = reply.send(self.renderer.list_displays());
+            }
+            Command::Geometry(reply) => {
+                let _ = reply.send(self.renderer.geometry());
+            }
+            Command::SetGeometry(geometry, reply) => {
+                let _ = reply.send(self.renderer.set_geometry(&geometry));
+            }
+            Command::ShowPlaceholder(kind) => self.show_placeholder(kind),
+        }
+    }
+
+    fn show_placeholder(&mut self, kind: PlaceholderKind) {
+        self.placeholder = Some(kind);
+        if let Err(e) = self.renderer.present_placeholder(kind) {
+            let _ = self.events.try_send(RenderEvent::PresentFailed(e));
+        }
+    }
+}
+
+/// The handle is gone and every frame it sent has been taken
+#[derive(Debug, PartialEq, Eq)]
+struct HandleDropped;
+
+/// Take every queued frame and keep the newest; the ones queued before it
+/// are already late and counted in `dropped`. `Ok(None)` when nothing is
+/// queued.
+fn newest_frame(
+    frames: &mut mpsc::Receiver<DecodedFrame>,
+    dropped: &AtomicU64,
+) -> Result<Option<DecodedFrame>, HandleDropped> {
+    let mut newest = None;
+    loop {
+        match frames.try_recv() {
+            Ok(frame) => {
+                if newest.replace(frame).is_some() {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(TryRecvError::Empty) => return Ok(newest),
+            Err(TryRecvError::Disconnected) if newest.is_none() => return Err(HandleDropped),
+            Err(TryRecvError::Disconnected) => return Ok(newest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_number: u64) -> DecodedFrame {
+        DecodedFrame::new(frame_number, 0, 64, 32, vec![128; 64 * 32 * 3 / 2])
+    }
+
+    #[test]
+    fn test_newest_frame_drops_late() {
+        let (tx, mut rx) = mpsc::channel(FRAME_QUEUE_DEPTH);
+        let dropped = AtomicU64::new(0);
+        assert!(newest_frame(&mut rx, &dropped).unwrap().is_none());
+
+        tx.try_send(frame(1)).unwrap();
+        tx.try_send(frame(2)).unwrap();
+        let newest = newest_frame(&mut rx, &dropped).unwrap().unwrap();
+        assert_eq!(newest.frame_number, 2);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+
+        // Frames sent before the handle went away are still shown
+        tx.try_send(frame(3)).unwrap();
+        drop(tx);
+        let newest = newest_frame(&mut rx, &dropped).unwrap().unwrap();
+        assert_eq!(newest.frame_number, 3);
+        assert_eq!(newest_frame(&mut rx, &dropped).unwrap_err(), HandleDropped);
+    }
+
+    /// Open a window with SDL's dummy video driver, which needs no display.
+    /// None if SDL can't even do that here.
+    fn spawn_headless() -> Option<(RendererHandle, JoinHandle<()>)> {
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let config = RendererConfig {
+            width: 64,
+            height: 32,
+            vsync: false,
+            ..Default::default()
+        };
+        match Renderer::spawn(config) {
+            Ok(spawned) => Some(spawned),
+            Err(e) => {
+                eprintln!("Skipping, no headless SDL renderer: {}", e);
+                None
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_drives_renderer() {
+        let Some((mut handle, thread)) = spawn_headless() else {
+            return;
+        };
+
+        for frame_number in 0..10 {
+            handle.present(frame(frame_number)).await.unwrap();
+        }
+        handle
+            .present_placeholder(PlaceholderKind::Reconnecting)
+            .await
+            .unwrap();
+        handle.set_scaling_mode(ScalingMode::Integer).await.unwrap();
+        handle.set_overlay_visible(true).await.unwrap();
+        handle
+            .set_overlay_stats(RenderOverlayStats::default())
+            .await
+            .unwrap();
+        handle.set_fullscreen(true).await.unwrap();
+        handle.set_fullscreen(false).await.unwrap();
+        handle.set_window_size(128, 64).await.unwrap();
+        assert!(!handle.list_displays().await.unwrap().is_empty());
+        assert!(matches!(
+            handle.move_to_display(99).await,
+            Err(RenderError::InvalidDisplay { index: 99, .. })
+        ));
+        let mut geometry = handle.geometry().await.unwrap();
+        geometry.width = 96;
+        handle.set_geometry(geometry).await.unwrap();
+        assert_eq!(handle.geometry().await.unwrap().width, 96);
+        assert!(handle.frames_dropped() < 10);
+        assert!(handle.events().try_recv().is_err());
+
+        // Dropping the handle stops the thread cleanly
+        drop(handle);
+        thread.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_close_with_thread() {
+        let Some((handle, thread)) = spawn_headless() else {
+            return;
+        };
+
+        // Keep reading events after the rest of the handle is gone
+        let RendererHandle {
+            frames,
+            commands,
+            mut events,
+            ..
+        } = handle;
+        drop((frames, commands));
+        thread.join().unwrap();
+        assert!(events.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_host_draws_on_its_thread() {
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let (host, opener) = RenderHost::new();
+        let caller = thread::spawn(move || {
+            let config = RendererConfig {
+                width: 64,
+                height: 32,
+                vsync: false,
+                ..Default::default()
+            };
+            let (first, first_closed) = match opener.open(config.clone()) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    eprintln!("Skipping, no headless SDL renderer: {}", e);
+                    return;
+                }
+            };
+            // A second window is drawn alongside the first
+            let (second, second_closed) = opener.open(config).unwrap();
+            first.frames.blocking_send(frame(1)).unwrap();
+            second.frames.blocking_send(frame(1)).unwrap();
+
+            // Each closes on its own
+            drop(first);
+            first_closed.wait();
+            second.frames.blocking_send(frame(2)).unwrap();
+            drop(second);
+            second_closed.wait();
+        });
+
+        // Returns once the caller is done with its opener
+        host.run();
+        caller.join().unwrap();
+    }
+}
@@ -0,0 +1,497 @@
+//! Keyboard shortcuts, and the syntax they're written in, e.g. "ctrl+alt+f"
+//!
+//! Every default needs Ctrl+Alt, so plain keys such as Escape or F reach
+//! whatever's running on the source rather than closing or resizing the
+//! window under it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use sdl2::keyboard::{Keycode, Mod};
+use serialwarp_core::RenderError;
+
+/// Something a shortcut does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyAction {
+    /// Close the window
+    Quit,
+    /// Enter or leave fullscreen
+    ToggleFullscreen,
+    /// Show or hide the statistics overlay
+    ToggleOverlay,
+    /// Save the frame on screen. Left to the caller, as `RenderEvent::Shortcut`.
+    Screenshot,
+    /// Move on to the next `ScalingMode`
+    CycleScaling,
+    /// Start or stop sending input to the source. Left to the caller, as
+    /// `RenderEvent::Shortcut`.
+    ToggleInputCapture,
+}
+
+impl KeyAction {
+    /// Every action, in the order they're listed in
+    pub const ALL: [KeyAction; 6] = [
+        KeyAction::Quit,
+        KeyAction::ToggleFullscreen,
+        KeyAction::ToggleOverlay,
+        KeyAction::Screenshot,
+        KeyAction::CycleScaling,
+        KeyAction::ToggleInputCapture,
+    ];
+
+    /// Name used for the action in bindings, e.g. "fullscreen"
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyAction::Quit => "quit",
+            KeyAction::ToggleFullscreen => "fullscreen",
+            KeyAction::ToggleOverlay => "overlay",
+            KeyAction::Screenshot => "screenshot",
+            KeyAction::CycleScaling => "scaling",
+            KeyAction::ToggleInputCapture => "input-capture",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KeyAction {
+    type Err = RenderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase().replace('_', "-");
+        KeyAction::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = KeyAction::ALL.iter().map(|action| action.name()).collect();
+                RenderError::InvalidKeyBinding(format!(
+                    "unknown action {:?}, expected one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// Modifier keys held with a key; left and right count the same
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// The Windows, Command or Super key
+    pub gui: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        ctrl: false,
+        alt: false,
+        shift: false,
+        gui: false,
+    };
+
+    /// The modifiers in an SDL key event, ignoring the lock keys
+    pub fn from_sdl(keymod: Mod) -> Self {
+        Self {
+            ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+            alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+            shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+            gui: keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD),
+        }
+    }
+}
+
+/// Names for keys that aren't a single letter, digit, or F1-F12
+const NAMED_KEYS: &[(&str, Keycode)] = &[
+    ("escape", Keycode::Escape),
+    ("esc", Keycode::Escape),
+    ("enter", Keycode::Return),
+    ("return", Keycode::Return),
+    ("space", Keycode::Space),
+    ("tab", Keycode::Tab),
+    ("backspace", Keycode::Backspace),
+    ("delete", Keycode::Delete),
+    ("insert", Keycode::Insert),
+    ("home", Keycode::Home),
+    ("end", Keycode::End),
+    ("pageup", Keycode::PageUp),
+    ("pagedown", Keycode::PageDown),
+    ("up", Keycode::Up),
+    ("down", Keycode::Down),
+    ("left", Keycode::Left),
+    ("right", Keycode::Right),
+    ("minus", Keycode::Minus),
+    ("equals", Keycode::Equals),
+    ("comma", Keycode::Comma),
+    ("period", Keycode::Period),
+    ("slash", Keycode::Slash),
+    ("printscreen", Keycode::PrintScreen),
+];
+
+const FUNCTION_KEYS: [Keycode; 12] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+    Keycode::F10,
+    Keycode::F11,
+    Keycode::F12,
+];
+
+fn parse_key(name: &str) -> Option<Keycode> {
+    if let Some(&(_, key)) = NAMED_KEYS.iter().find(|(known, _)| *known == name) {
+        return Some(key);
+    }
+    if let Some(number) = name.strip_prefix('f') {
+        if let Ok(number @ 1..=12) = number.parse::<usize>() {
+            return Some(FUNCTION_KEYS[number - 1]);
+        }
+    }
+    // SDL's keycodes for letters and digits are their lowercase ASCII
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_lowercase() || c.is_ascii_digit() => {
+            Keycode::from_i32(c as i32)
+        }
+        _ => None,
+    }
+}
+
+fn key_name(key: Keycode) -> String {
+    if let Some(&(name, _)) = NAMED_KEYS.iter().find(|(_, known)| *known == key) {
+        return name.to_string();
+    }
+    if let Some(index) = FUNCTION_KEYS.iter().position(|&known| known == key) {
+        return format!("f{}", index + 1);
+    }
+    match char::from_u32(key as i32 as u32) {
+        Some(c) if c.is_ascii_lowercase() || c.is_ascii_digit() => c.to_string(),
+        _ => key.name().to_ascii_lowercase(),
+    }
+}
+
+/// A key and the modifiers held with it, written like "ctrl+alt+f"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    pub key: Keycode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyCombo {
+    pub fn new(key: Keycode, modifiers: Modifiers) -> Self {
+        Self { key, modifiers }
+    }
+
+    /// `key` with Ctrl+Alt, as all the default bindings are
+    fn ctrl_alt(key: Keycode) -> Self {
+        Self::new(
+            key,
+            Modifiers {
+                ctrl: true,
+                alt: true,
+                ..Modifiers::NONE
+            },
+        )
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = [
+            (self.modifiers.ctrl, "ctrl"),
+            (self.modifiers.alt, "alt"),
+            (self.modifiers.shift, "shift"),
+            (self.modifiers.gui, "gui"),
+        ];
+        for (held, name) in modifiers {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        f.write_str(&key_name(self.key))
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = RenderError;
+
+    /// Parse modifiers and a key joined by `+`, in any case, e.g.
+    /// "Ctrl+Alt+F" or "shift+f11"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| RenderError::InvalidKeyBinding(format!("{:?}: {}", s, reason));
+        let lower = s.to_ascii_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        if key.is_empty() {
+            return Err(invalid("no key"));
+        }
+
+        let mut modifiers = Modifiers::NONE;
+        for part in parts {
+            let held = match part {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "alt" | "option" => &mut modifiers.alt,
+                "shift" => &mut modifiers.shift,
+                "gui" | "super" | "cmd" | "win" | "meta" => &mut modifiers.gui,
+                "" => return Err(invalid("empty modifier")),
+                other => return Err(invalid(&format!("unknown modifier {:?}", other))),
+            };
+            if *held {
+                return Err(invalid(&format!("{} given twice", part)));
+            }
+            *held = true;
+        }
+
+        let key = parse_key(key).ok_or_else(|| invalid(&format!("unknown key {:?}", key)))?;
+        Ok(Self { key, modifiers })
+    }
+}
+
+/// Which shortcut, if any, each `KeyAction` has. Keys that aren't bound go
+/// to the caller as `RenderEvent::Key`; bound ones never do, so they're never
+/// passed on to the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: [Option<KeyCombo>; KeyAction::ALL.len()],
+}
+
+impl Default for KeyBindings {
+    /// Ctrl+Alt with Q, F, O, P, S and C, in the order of `KeyAction::ALL`
+    fn default() -> Self {
+        let mut bindings = Self::none();
+        bindings.set(KeyAction::Quit, Some(KeyCombo::ctrl_alt(Keycode::Q)));
+        bindings.set(
+            KeyAction::ToggleFullscreen,
+            Some(KeyCombo::ctrl_alt(Keycode::F)),
+        );
+        bindings.set(
+            KeyAction::ToggleOverlay,
+            Some(KeyCombo::ctrl_alt(Keycode::O)),
+        );
+        bindings.set(KeyAction::Screenshot, Some(KeyCombo::ctrl_alt(Keycode::P)));
+        bindings.set(
+            KeyAction::CycleScaling,
+            Some(KeyCombo::ctrl_alt(Keycode::S)),
+        );
+        bindings.set(
+            KeyAction::ToggleInputCapture,
+            Some(KeyCombo::ctrl_alt(Keycode::C)),
+        );
+        bindings
+    }
+}
+
+impl KeyBindings {
+    /// No shortcuts at all; every key goes to the caller
+    pub fn none() -> Self {
+        Self {
+            bindings: [None; KeyAction::ALL.len()],
+        }
+    }
+
+    /// The shortcut for `action`, if it has one
+    pub fn get(&self, action: KeyAction) -> Option<KeyCombo> {
+        self.bindings[action.index()]
+    }
+
+    /// Bind `action` to `combo`, or unbind it with None
+    pub fn set(&mut self, action: KeyAction, combo: Option<KeyCombo>) {
+        self.bindings[action.index()] = combo;
+    }
+
+    /// Apply an `action=keys` assignment, e.g. "fullscreen=ctrl+alt+f", as
+    /// given on the command line. "none" as the keys unbinds the action.
+    pub fn assign(&mut self, assignment: &str) -> Result<(), RenderError> {
+        let (action, keys) = assignment.split_once('=').ok_or_else(|| {
+            RenderError::InvalidKeyBinding(format!("{:?}: expected ACTION=KEYS", assignment))
+        })?;
+        self.bind(action.parse()?, keys)
+    }
+
+    /// Bind `action` to the shortcut `keys` is written as, or unbind it if
+    /// `keys` is "none"
+    pub fn bind(&mut self, action: KeyAction, keys: &str) -> Result<(), RenderError> {
+        let combo = if keys.trim().eq_ignore_ascii_case("none") {
+            None
+        } else {
+            Some(keys.parse()?)
+        };
+        self.set(action, combo);
+        Ok(())
+    }
+
+    /// The action bound to `key` pressed with exactly `modifiers`
+    pub fn action(&self, key: Keycode, modifiers: Modifiers) -> Option<KeyAction> {
+        let pressed = KeyCombo::new(key, modifiers);
+        KeyAction::ALL
+            .into_iter()
+            .find(|&action| self.get(action) == Some(pressed))
+    }
+
+    /// Fail if two actions share a shortcut, as only the first would ever
+    /// happen
+    pub fn check_conflicts(&self) -> Result<(), RenderError> {
+        for (i, &first) in KeyAction::ALL.iter().enumerate() {
+            let Some(combo) = self.get(first) else {
+                continue;
+            };
+            if let Some(&second) = KeyAction::ALL[i + 1..]
+                .iter()
+                .find(|&&action| self.get(action) == Some(combo))
+            {
+                return Err(RenderError::InvalidKeyBinding(format!(
+                    "{} is bound to both {} and {}",
+                    combo, first, second
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl_alt() -> Modifiers {
+        Modifiers {
+            ctrl: true,
+            alt: true,
+            ..Modifiers::NONE
+        }
+    }
+
+    #[test]
+    fn test_parse_combo() {
+        let combo: KeyCombo = "ctrl+alt+f".parse().unwrap();
+        assert_eq!(combo, KeyCombo::new(Keycode::F, ctrl_alt()));
+        // Case and spacing don't matter, nor does the modifiers' order
+        assert_eq!("Alt + Ctrl + F".parse::<KeyCombo>().unwrap(), combo);
+
+        let combo: KeyCombo = "shift+F11".parse().unwrap();
+        assert_eq!(combo.key, Keycode::F11);
+        assert!(combo.modifiers.shift && !combo.modifiers.ctrl);
+        assert_eq!("esc".parse::<KeyCombo>().unwrap().key, Keycode::Escape);
+        assert_eq!("cmd+1".parse::<KeyCombo>().unwrap().key, Keycode::Num1);
+        assert_eq!(
+            "cmd+1".parse::<KeyCombo>().unwrap().modifiers,
+            Modifiers {
+                gui: true,
+                ..Modifiers::NONE
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_combo_invalid() {
+        for text in [
+            "",
+            "ctrl+",
+            "ctrl++f",
+            "hyper+f",
+            "ctrl+ctrl+f",
+            "ctrl+alt+ff",
+            "f13",
+            "ctrl+alt",
+        ] {
+            assert!(
+                matches!(
+                    text.parse::<KeyCombo>(),
+                    Err(RenderError::InvalidKeyBinding(_))
+                ),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_combo_round_trip() {
+        for text in [
+            "ctrl+alt+q",
+            "shift+gui+f12",
+            "escape",
+            "ctrl+pagedown",
+            "alt+7",
+        ] {
+            let combo: KeyCombo = text.parse().unwrap();
+            assert_eq!(combo.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_defaults_need_modifiers() {
+        let bindings = KeyBindings::default();
+        assert!(bindings.check_conflicts().is_ok());
+        for action in KeyAction::ALL {
+            let combo = bindings.get(action).unwrap();
+            assert_eq!(combo.modifiers, ctrl_alt(), "{}", action);
+        }
+
+        // Plain keys are left for the source
+        assert_eq!(bindings.action(Keycode::Escape, Modifiers::NONE), None);
+        assert_eq!(bindings.action(Keycode::F, Modifiers::NONE), None);
+        assert_eq!(
+            bindings.action(Keycode::F, ctrl_alt()),
+            Some(KeyAction::ToggleFullscreen)
+        );
+        // Modifiers must match exactly
+        let ctrl_alt_shift = Modifiers {
+            shift: true,
+            ..ctrl_alt()
+        };
+        assert_eq!(bindings.action(Keycode::F, ctrl_alt_shift), None);
+    }
+
+    #[test]
+    fn test_assign() {
+        let mut bindings = KeyBindings::default();
+        bindings.assign("quit=ctrl+shift+escape").unwrap();
+        bindings.assign("input_capture = none").unwrap();
+        assert_eq!(
+            bindings.get(KeyAction::Quit).unwrap().to_string(),
+            "ctrl+shift+escape"
+        );
+        assert_eq!(bindings.get(KeyAction::ToggleInputCapture), None);
+        assert_eq!(
+            bindings.action(Keycode::C, ctrl_alt()),
+            None,
+            "an unbound action's old keys go to the caller"
+        );
+
+        for assignment in ["quit", "jump=ctrl+j", "quit=ctrl+"] {
+            assert!(bindings.assign(assignment).is_err(), "{:?}", assignment);
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut bindings = KeyBindings::default();
+        bindings.assign("screenshot=ctrl+alt+f").unwrap();
+        let message = bindings.check_conflicts().unwrap_err().to_string();
+        assert!(
+            message.contains("ctrl+alt+f is bound to both fullscreen and screenshot"),
+            "{}",
+            message
+        );
+
+        // Unbinding one of them resolves it
+        bindings.assign("fullscreen=none").unwrap();
+        assert!(bindings.check_conflicts().is_ok());
+        assert!(KeyBindings::none().check_conflicts().is_ok());
+    }
+}
@@ -1,31 +1,105 @@
 //! serialwarp-render - SDL2-based video renderer
 //!
 //! This crate provides video rendering functionality for the sink application.
+//! With the `wgpu-backend` feature it also has `WgpuRenderer`, which draws
+//! through wgpu instead of SDL's renderer.
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use sdl2::event::{Event, WindowEvent};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::sys::SDL_YUV_CONVERSION_MODE;
+use sdl2::video::{FullscreenType, Window, WindowPos};
 use sdl2::EventPump;
 use sdl2::Sdl;
 
-use serialwarp_core::{DecodedFrame, RenderError};
+use serialwarp_core::{ColorMatrix, DecodedFrame, RenderError};
+
+mod handle;
+mod keys;
+mod overlay;
+mod placeholder;
+#[cfg(feature = "wgpu-backend")]
+mod wgpu_backend;
+
+pub use handle::{RenderClosed, RenderEvent, RenderHost, RenderLoop, RenderOpener, RendererHandle};
+pub use keys::{KeyAction, KeyBindings, KeyCombo, Modifiers};
+pub use overlay::RenderOverlayStats;
+pub use placeholder::PlaceholderKind;
+#[cfg(feature = "wgpu-backend")]
+pub use wgpu_backend::WgpuRenderer;
+
+use overlay::Overlay;
+
+pub use sdl2::keyboard::Keycode;
+
+/// How frames are scaled into the window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScalingMode {
+    /// Fit the whole frame in the window, letterboxing as needed
+    #[default]
+    Fit,
+    /// Fill the window, cropping the frame edges as needed
+    Fill,
+    /// Stretch the frame to the window, ignoring aspect ratio
+    Stretch,
+    /// Largest whole-number multiple of the frame size that fits, centered
+    Integer,
+}
+
+impl ScalingMode {
+    /// The mode after this one, for cycling with a keyboard shortcut
+    pub fn next(self) -> Self {
+        match self {
+            ScalingMode::Fit => ScalingMode::Fill,
+            ScalingMode::Fill => ScalingMode::Stretch,
+            ScalingMode::Stretch => ScalingMode::Integer,
+            ScalingMode::Integer => ScalingMode::Fit,
+        }
+    }
+}
+
+/// Graphics stack a spawned renderer draws with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// SDL2's renderer, see `Renderer`
+    #[default]
+    Sdl,
+    /// wgpu, see `WgpuRenderer`; only with the `wgpu-backend` feature
+    Wgpu,
+}
 
 /// Renderer configuration
 #[derive(Debug, Clone)]
 pub struct RendererConfig {
     /// Window title
     pub title: String,
-    /// Initial window width
+    /// Initial window width, in points. On a HiDPI screen the window has
+    /// more pixels than this, and frames are drawn at full pixel size.
     pub width: u32,
-    /// Initial window height
+    /// Initial window height, in points
     pub height: u32,
     /// Start in fullscreen mode
     pub fullscreen: bool,
     /// Enable VSync
     pub vsync: bool,
+    /// Display to open the window on (None uses the window manager's choice)
+    pub display_index: Option<usize>,
+    /// How frames are scaled into the window
+    pub scaling_mode: ScalingMode,
+    /// Backend `Renderer::spawn` opens; `Renderer::new` is always SDL
+    pub backend: RenderBackend,
+    /// Where the window was last time, see `Renderer::set_geometry`. Replaces
+    /// the size, display, and fullscreen settings above.
+    pub geometry: Option<WindowGeometry>,
+    /// Keyboard shortcuts the window handles
+    pub key_bindings: KeyBindings,
+    /// Keep the last frame, dimmed, behind a placeholder rather than
+    /// clearing the window for it, see `Renderer::present_placeholder`
+    pub keep_last_frame: bool,
 }
 
 impl Default for RendererConfig {
@@ -36,24 +110,167 @@ impl Default for RendererConfig {
             height: 1080,
             fullscreen: false,
             vsync: true,
+            display_index: None,
+            scaling_mode: ScalingMode::Fit,
+            backend: RenderBackend::Sdl,
+            geometry: None,
+            key_bindings: KeyBindings::default(),
+            keep_last_frame: false,
         }
     }
 }
 
+/// A monitor reported by SDL's video subsystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// Index to pass to `RendererConfig::display_index` or `Renderer::move_to_display`
+    pub index: usize,
+    /// Display name as reported by the OS
+    pub name: String,
+    /// Left edge of the display in desktop coordinates
+    pub x: i32,
+    /// Top edge of the display in desktop coordinates
+    pub y: i32,
+    /// Display width in pixels
+    pub width: u32,
+    /// Display height in pixels
+    pub height: u32,
+    /// Refresh rate in Hz (0 if unknown)
+    pub refresh_rate: i32,
+}
+
+/// Least of a window, in each direction, that must be on a display for
+/// `Renderer::set_geometry` to put it back where it was
+const MIN_VISIBLE_SIZE: u32 = 64;
+
+/// Where a window is on the desktop, so it can be put back there next time.
+/// Coordinates are the same desktop coordinates as `DisplayInfo`'s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowGeometry {
+    /// Left edge of the window when not fullscreen
+    pub x: i32,
+    /// Top edge of the window when not fullscreen
+    pub y: i32,
+    /// Window width when not fullscreen
+    pub width: u32,
+    /// Window height when not fullscreen
+    pub height: u32,
+    /// Display the window is on, if known
+    pub display_index: Option<usize>,
+    /// Whether the window is fullscreen
+    pub fullscreen: bool,
+}
+
+impl WindowGeometry {
+    /// Whether enough of the window is on one of `displays` to be seen and
+    /// dragged back
+    pub fn is_visible_on(&self, displays: &[DisplayInfo]) -> bool {
+        let min_width = self.width.min(MIN_VISIBLE_SIZE) as i64;
+        let min_height = self.height.min(MIN_VISIBLE_SIZE) as i64;
+        displays.iter().any(|display| {
+            overlap(self.x, self.width, display.x, display.width) >= min_width
+                && overlap(self.y, self.height, display.y, display.height) >= min_height
+        })
+    }
+}
+
+/// Length shared by two spans of a line
+fn overlap(a: i32, a_len: u32, b: i32, b_len: u32) -> i64 {
+    let start = (a as i64).max(b as i64);
+    let end = (a as i64 + a_len as i64).min(b as i64 + b_len as i64);
+    (end - start).max(0)
+}
+
+/// Events from SDL's event pump, of which there's only one, shared by the
+/// renderers on a thread; each takes those for its own window
+struct EventQueue {
+    pump: EventPump,
+    /// Polled, for windows whose renderers haven't taken them yet
+    pending: Vec<Event>,
+    /// SDL asked to quit, which is for every window
+    quit: bool,
+}
+
+thread_local! {
+    static EVENT_QUEUE: RefCell<Weak<RefCell<EventQueue>>> = RefCell::new(Weak::new());
+}
+
+impl EventQueue {
+    /// The thread's queue, opening the event pump for its first renderer
+    fn shared(sdl_context: &Sdl) -> Result<Rc<RefCell<Self>>, RenderError> {
+        EVENT_QUEUE.with(|current| {
+            if let Some(queue) = current.borrow().upgrade() {
+                return Ok(queue);
+            }
+            let pump = sdl_context
+                .event_pump()
+                .map_err(|e| RenderError::SdlInitFailed(e.to_string()))?;
+            let queue = Rc::new(RefCell::new(Self {
+                pump,
+                pending: Vec::new(),
+                quit: false,
+            }));
+            *current.borrow_mut() = Rc::downgrade(&queue);
+            Ok(queue)
+        })
+    }
+
+    /// Poll SDL, then take the events for `window_id` along with those for
+    /// no window in particular
+    fn take(&mut self, window_id: u32) -> Vec<Event> {
+        self.pending.extend(self.pump.poll_iter());
+        let (ours, others) = self
+            .pending
+            .drain(..)
+            .partition(|event| event.get_window_id().map_or(true, |id| id == window_id));
+        self.pending = others;
+        ours
+    }
+}
+
 /// SDL2-based video renderer
+///
+/// Several can be open on one thread, each with a window of its own, but
+/// SDL can't be used from more than one thread at a time.
 pub struct Renderer {
     #[allow(dead_code)]
     sdl_context: Sdl,
     canvas: Canvas<Window>,
-    event_pump: EventPump,
+    events: Rc<RefCell<EventQueue>>,
     current_width: u32,
     current_height: u32,
     is_fullscreen: bool,
+    display_index: Option<usize>,
+    scaling_mode: ScalingMode,
+    overlay: Overlay,
+    overlay_visible: bool,
+    key_bindings: KeyBindings,
+    key_presses: Vec<Keycode>,
+    shortcuts: Vec<KeyAction>,
+    /// Position and size the last time the window wasn't fullscreen
+    windowed: Rect,
+    geometry_changed: bool,
+    keep_last_frame: bool,
+    /// The frame last presented, kept to dim behind placeholders
+    last_frame: Option<DecodedFrame>,
 }
 
 impl Renderer {
     /// Create a new renderer with the given configuration
     pub fn new(config: RendererConfig) -> Result<Self, RenderError> {
+        // A saved geometry is applied once the window is open
+        let geometry = config.geometry;
+        let config = match geometry {
+            Some(geometry) => RendererConfig {
+                width: geometry.width,
+                height: geometry.height,
+                fullscreen: false,
+                display_index: None,
+                ..config
+            },
+            None => config,
+        };
+
         let sdl_context =
             sdl2::init().map_err(|e| RenderError::SdlInitFailed(e.to_string()))?;
 
@@ -62,9 +279,12 @@ impl Renderer {
             .map_err(|e| RenderError::SdlInitFailed(e.to_string()))?;
 
         let mut window_builder = video_subsystem.window(&config.title, config.width, config.height);
-        window_builder.position_centered().resizable();
+        // Without allow_highdpi a HiDPI screen scales up a point-sized
+        // drawable, blurring frames sent at full pixel size
+        window_builder.position_centered().resizable().allow_highdpi();
 
-        if config.fullscreen {
+        // With a target display, fullscreen is applied after the window is moved there
+        if config.fullscreen && config.display_index.is_none() {
             window_builder.fullscreen_desktop();
         }
 
@@ -77,27 +297,236 @@ impl Renderer {
             canvas_builder = canvas_builder.present_vsync();
         }
 
+        // Without asking for acceleration SDL still prefers it, but falls
+        // back to software where there's none, e.g. the dummy video driver
         let canvas = canvas_builder
-            .accelerated()
             .build()
             .map_err(|e| RenderError::RendererCreationFailed(e.to_string()))?;
 
-        let event_pump = sdl_context
-            .event_pump()
-            .map_err(|e| RenderError::SdlInitFailed(e.to_string()))?;
+        let events = EventQueue::shared(&sdl_context)?;
 
-        Ok(Self {
+        let (x, y) = canvas.window().position();
+        let (width, height) = canvas.window().size();
+        let mut renderer = Self {
             sdl_context,
             canvas,
-            event_pump,
+            events,
             current_width: 0,
             current_height: 0,
             is_fullscreen: config.fullscreen,
-        })
+            display_index: None,
+            scaling_mode: config.scaling_mode,
+            overlay: Overlay::default(),
+            overlay_visible: false,
+            key_bindings: config.key_bindings,
+            key_presses: Vec::new(),
+            shortcuts: Vec::new(),
+            windowed: Rect::new(x, y, width, height),
+            geometry_changed: false,
+            keep_last_frame: config.keep_last_frame,
+            last_frame: None,
+        };
+
+        if let Some(index) = config.display_index {
+            renderer.move_to_display(index)?;
+        }
+        if let Some(geometry) = &geometry {
+            renderer.set_geometry(geometry)?;
+        }
+
+        Ok(renderer)
+    }
+
+    /// Enumerate the displays attached to this machine
+    pub fn list_displays(&self) -> Result<Vec<DisplayInfo>, RenderError> {
+        let video = self.canvas.window().subsystem();
+        let count = video
+            .num_video_displays()
+            .map_err(RenderError::DisplayQueryFailed)?;
+
+        (0..count)
+            .map(|index| {
+                let name = video
+                    .display_name(index)
+                    .map_err(RenderError::DisplayQueryFailed)?;
+                let bounds = video
+                    .display_bounds(index)
+                    .map_err(RenderError::DisplayQueryFailed)?;
+                let refresh_rate = video
+                    .current_display_mode(index)
+                    .map(|mode| mode.refresh_rate)
+                    .unwrap_or(0);
+
+                Ok(DisplayInfo {
+                    index: index as usize,
+                    name,
+                    x: bounds.x(),
+                    y: bounds.y(),
+                    width: bounds.width(),
+                    height: bounds.height(),
+                    refresh_rate,
+                })
+            })
+            .collect()
+    }
+
+    /// Move the window onto the given display.
+    ///
+    /// The display is remembered, so later fullscreen toggles stay on it.
+    pub fn move_to_display(&mut self, index: usize) -> Result<(), RenderError> {
+        let displays = self.list_displays()?;
+        let display = displays
+            .get(index)
+            .ok_or(RenderError::InvalidDisplay {
+                index,
+                available: displays.len(),
+            })?
+            .clone();
+
+        self.display_index = Some(index);
+
+        // Leave fullscreen so the window can be repositioned, then re-enter it
+        // on the new display
+        let window = self.canvas.window_mut();
+        if self.is_fullscreen {
+            window
+                .set_fullscreen(FullscreenType::Off)
+                .map_err(RenderError::RenderFailed)?;
+        }
+        Self::place_on_display(window, &display);
+        if self.is_fullscreen {
+            window
+                .set_fullscreen(FullscreenType::Desktop)
+                .map_err(RenderError::RenderFailed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enter or leave fullscreen, on the display from `move_to_display` if
+    /// one was chosen
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        if fullscreen != self.is_fullscreen {
+            self.toggle_fullscreen();
+        }
+    }
+
+    /// Resize the window, in points, e.g. when the stream changes
+    /// resolution. Takes effect once the window leaves fullscreen.
+    pub fn set_window_size(&mut self, width: u32, height: u32) -> Result<(), RenderError> {
+        self.canvas
+            .window_mut()
+            .set_size(width, height)
+            .map_err(|e| RenderError::RenderFailed(e.to_string()))
+    }
+
+    /// Whether the window is fullscreen
+    pub fn is_fullscreen(&self) -> bool {
+        self.is_fullscreen
+    }
+
+    /// Change how frames are scaled into the window
+    pub fn set_scaling_mode(&mut self, mode: ScalingMode) {
+        self.scaling_mode = mode;
+    }
+
+    /// Current scaling mode
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    /// Display chosen with `move_to_display`, if any
+    pub fn display_index(&self) -> Option<usize> {
+        self.display_index
+    }
+
+    /// Where the window is now, to restore with `set_geometry` next time
+    pub fn geometry(&self) -> WindowGeometry {
+        let display_index = self.canvas.window().display_index().ok();
+        WindowGeometry {
+            x: self.windowed.x(),
+            y: self.windowed.y(),
+            width: self.windowed.width(),
+            height: self.windowed.height(),
+            display_index: display_index.and_then(|index| usize::try_from(index).ok()),
+            fullscreen: self.is_fullscreen,
+        }
+    }
+
+    /// Put the window back where `geometry` says it was. If that's no longer
+    /// on any display, e.g. the monitor was unplugged, the window is
+    /// centered on its display or the first one instead.
+    pub fn set_geometry(&mut self, geometry: &WindowGeometry) -> Result<(), RenderError> {
+        let displays = self.list_displays()?;
+        let position = Self::restored_position(geometry, &displays);
+
+        let window = self.canvas.window_mut();
+        if self.is_fullscreen {
+            window
+                .set_fullscreen(FullscreenType::Off)
+                .map_err(RenderError::RenderFailed)?;
+        }
+        window
+            .set_size(geometry.width, geometry.height)
+            .map_err(|e| RenderError::RenderFailed(e.to_string()))?;
+        if let Some((x, y)) = position {
+            window.set_position(WindowPos::Positioned(x), WindowPos::Positioned(y));
+        }
+        if geometry.fullscreen {
+            window
+                .set_fullscreen(FullscreenType::Desktop)
+                .map_err(RenderError::RenderFailed)?;
+        }
+        self.is_fullscreen = geometry.fullscreen;
+
+        let (x, y) = position.unwrap_or((self.windowed.x(), self.windowed.y()));
+        self.windowed = Rect::new(x, y, geometry.width, geometry.height);
+        Ok(())
+    }
+
+    /// Whether the window moved, resized, or entered or left fullscreen
+    /// during the last `process_events` call
+    pub fn geometry_changed(&self) -> bool {
+        self.geometry_changed
     }
 
     /// Present a decoded frame to the screen
     pub fn present(&mut self, frame: &DecodedFrame) -> Result<(), RenderError> {
+        self.canvas.clear();
+        self.draw_frame(frame)?;
+        self.draw_overlay()?;
+        self.canvas.present();
+        if self.keep_last_frame {
+            self.last_frame = Some(frame.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Show a placeholder saying why there's no video, until the next frame
+    /// is presented. With `RendererConfig::keep_last_frame` it's drawn over
+    /// the last frame, dimmed, which stays kept for the next placeholder;
+    /// otherwise over a plain background.
+    pub fn present_placeholder(&mut self, kind: PlaceholderKind) -> Result<(), RenderError> {
+        self.canvas.clear();
+        let dim = match self.last_frame.take() {
+            Some(frame) => {
+                let drawn = self.draw_frame(&frame);
+                self.last_frame = Some(frame);
+                drawn?;
+                true
+            }
+            None => false,
+        };
+        placeholder::draw(&mut self.canvas, kind, dim).map_err(RenderError::RenderFailed)?;
+        self.draw_overlay()?;
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    /// Upload `frame` and draw it into the canvas for the scaling mode
+    fn draw_frame(&mut self, frame: &DecodedFrame) -> Result<(), RenderError> {
         let texture_creator = self.canvas.texture_creator();
 
         // Recreate texture if dimensions changed
@@ -109,72 +538,269 @@ impl Renderer {
             self.current_height = frame.height;
         }
 
+        // SDL reads the conversion mode when the texture is created
+        Self::set_yuv_conversion(frame.color_space.matrix);
+
         // Create texture for this frame
         let mut texture = texture_creator
             .create_texture_streaming(PixelFormatEnum::IYUV, frame.width, frame.height)
             .map_err(|e| RenderError::TextureCreationFailed(e.to_string()))?;
 
-        // Update texture with YUV data
-        texture
-            .update_yuv(
-                None,
-                frame.y_plane(),
-                frame.y_stride(),
-                frame.u_plane(),
-                frame.uv_stride(),
-                frame.v_plane(),
-                frame.uv_stride(),
+        // Update texture with YUV data. sdl2's update_yuv wants every plane
+        // to be exactly pitch * rows bytes, which a padded or cropped frame
+        // isn't; DecodedFrame has already checked its planes cover every
+        // visible row, with chroma rounded up for odd sizes as SDL expects.
+        let status = unsafe {
+            sdl2::sys::SDL_UpdateYUVTexture(
+                texture.raw(),
+                std::ptr::null(),
+                frame.y_plane().as_ptr(),
+                frame.y_stride() as i32,
+                frame.u_plane().as_ptr(),
+                frame.u_stride() as i32,
+                frame.v_plane().as_ptr(),
+                frame.v_stride() as i32,
             )
-            .map_err(|e| RenderError::TextureUpdateFailed(e.to_string()))?;
+        };
+        if status != 0 {
+            return Err(RenderError::TextureUpdateFailed(sdl2::get_error()));
+        }
 
-        // Calculate destination rect to maintain aspect ratio
+        // Calculate source and destination rects for the scaling mode
         let (win_width, win_height) = self
             .canvas
             .output_size()
             .map_err(|e| RenderError::RenderFailed(e.to_string()))?;
 
-        let dst_rect = Self::calculate_dest_rect(frame.width, frame.height, win_width, win_height);
+        let (src_rect, dst_rect) = Self::calculate_rects(
+            self.scaling_mode,
+            frame.width,
+            frame.height,
+            win_width,
+            win_height,
+        );
 
-        self.canvas.clear();
         self.canvas
-            .copy(&texture, None, Some(dst_rect))
-            .map_err(|e| RenderError::RenderFailed(e.to_string()))?;
-        self.canvas.present();
+            .copy(&texture, src_rect, Some(dst_rect))
+            .map_err(|e| RenderError::RenderFailed(e.to_string()))
+    }
 
+    fn draw_overlay(&mut self) -> Result<(), RenderError> {
+        if self.overlay_visible {
+            self.overlay
+                .draw(&mut self.canvas)
+                .map_err(RenderError::RenderFailed)?;
+        }
         Ok(())
     }
 
-    /// Process SDL events. Returns false if quit was requested.
+    /// SDL's IYUV conversion for a frame's matrix. SDL only has a full range
+    /// mode for BT.601, so full range BT.709 uses it too: slightly off hues
+    /// beat the washed-out look of the wrong range.
+    fn yuv_conversion_mode(matrix: ColorMatrix) -> SDL_YUV_CONVERSION_MODE {
+        match matrix {
+            ColorMatrix::Bt601Limited => SDL_YUV_CONVERSION_MODE::SDL_YUV_CONVERSION_BT601,
+            ColorMatrix::Bt709Limited => SDL_YUV_CONVERSION_MODE::SDL_YUV_CONVERSION_BT709,
+            ColorMatrix::Bt601Full | ColorMatrix::Bt709Full => {
+                SDL_YUV_CONVERSION_MODE::SDL_YUV_CONVERSION_JPEG
+            }
+        }
+    }
+
+    fn set_yuv_conversion(matrix: ColorMatrix) {
+        unsafe { sdl2::sys::SDL_SetYUVConversionMode(Self::yuv_conversion_mode(matrix)) }
+    }
+
+    /// Process SDL events for this window. Returns false if quit was
+    /// requested or the window closed.
     pub fn process_events(&mut self) -> bool {
         // Collect events first to avoid borrow issues
-        let events: Vec<_> = self.event_pump.poll_iter().collect();
+        let events = self.events.borrow_mut().take(self.canvas.window().id());
+        self.key_presses.clear();
+        self.shortcuts.clear();
+        self.geometry_changed = false;
 
         for event in events {
             match event {
-                Event::Quit { .. } => return false,
+                Event::Quit { .. } => self.events.borrow_mut().quit = true,
+                // With other windows open there's no quit after this
+                Event::Window {
+                    win_event: WindowEvent::Close,
+                    ..
+                } => return false,
                 Event::KeyDown {
-                    keycode: Some(key), ..
-                } => match key {
-                    Keycode::Escape => return false,
-                    Keycode::F | Keycode::F11 => {
+                    keycode: Some(key),
+                    keymod,
+                    ..
+                } => match self.key_bindings.action(key, Modifiers::from_sdl(keymod)) {
+                    Some(KeyAction::Quit) => return false,
+                    Some(KeyAction::ToggleFullscreen) => {
                         self.toggle_fullscreen();
                     }
-                    _ => {}
+                    Some(KeyAction::ToggleOverlay) => {
+                        self.overlay_visible = !self.overlay_visible;
+                    }
+                    Some(KeyAction::CycleScaling) => {
+                        self.scaling_mode = self.scaling_mode.next();
+                    }
+                    Some(action) => self.shortcuts.push(action),
+                    None => self.key_presses.push(key),
                 },
+                Event::Window {
+                    win_event: WindowEvent::Moved(..) | WindowEvent::SizeChanged(..),
+                    ..
+                } => self.window_changed(),
                 _ => {}
             }
         }
-        true
+        !self.events.borrow().quit
+    }
+
+    fn window_changed(&mut self) {
+        // While fullscreen the window covers its display; keep the size and
+        // position to go back to
+        if !self.is_fullscreen {
+            let window = self.canvas.window();
+            let (x, y) = window.position();
+            let (width, height) = window.size();
+            self.windowed = Rect::new(x, y, width, height);
+        }
+        self.geometry_changed = true;
+    }
+
+    /// Keys from the last `process_events` call that aren't bound to a
+    /// shortcut, and so can be passed on to the source
+    pub fn key_presses(&self) -> &[Keycode] {
+        &self.key_presses
+    }
+
+    /// Shortcuts from the last `process_events` call that the renderer
+    /// leaves to its caller, see `KeyAction`
+    pub fn shortcuts(&self) -> &[KeyAction] {
+        &self.shortcuts
+    }
+
+    /// Update the statistics shown by the overlay
+    pub fn set_overlay_stats(&mut self, stats: &RenderOverlayStats) {
+        self.overlay.update(stats);
+    }
+
+    /// Show or hide the statistics overlay
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.overlay_visible = visible;
+    }
+
+    /// Whether the statistics overlay is shown
+    pub fn overlay_visible(&self) -> bool {
+        self.overlay_visible
     }
 
     fn toggle_fullscreen(&mut self) {
+        // Keep the window on the chosen display; the window manager may otherwise
+        // restore it elsewhere when leaving fullscreen
+        let display = self
+            .display_index
+            .and_then(|index| self.list_displays().ok()?.into_iter().nth(index));
+
         let window = self.canvas.window_mut();
         if self.is_fullscreen {
-            let _ = window.set_fullscreen(sdl2::video::FullscreenType::Off);
+            let _ = window.set_fullscreen(FullscreenType::Off);
+            if let Some(display) = &display {
+                Self::place_on_display(window, display);
+            }
         } else {
-            let _ = window.set_fullscreen(sdl2::video::FullscreenType::Desktop);
+            if let Some(display) = &display {
+                Self::place_on_display(window, display);
+            }
+            let _ = window.set_fullscreen(FullscreenType::Desktop);
         }
         self.is_fullscreen = !self.is_fullscreen;
+        self.geometry_changed = true;
+    }
+
+    fn place_on_display(window: &mut Window, display: &DisplayInfo) {
+        let (width, height) = window.size();
+        let (x, y) = Self::center_in_display(display, width, height);
+        window.set_position(WindowPos::Positioned(x), WindowPos::Positioned(y));
+    }
+
+    /// Top-left position that centers a window on a display, clamped so the
+    /// window's origin stays inside the display
+    fn center_in_display(display: &DisplayInfo, width: u32, height: u32) -> (i32, i32) {
+        let x = display.x + (display.width.saturating_sub(width) / 2) as i32;
+        let y = display.y + (display.height.saturating_sub(height) / 2) as i32;
+        (x, y)
+    }
+
+    /// Top-left position to restore a window at: where it was if it would
+    /// still be visible, otherwise centered on its display or the first one.
+    /// None if there are no displays to go by.
+    fn restored_position(
+        geometry: &WindowGeometry,
+        displays: &[DisplayInfo],
+    ) -> Option<(i32, i32)> {
+        if geometry.is_visible_on(displays) {
+            return Some((geometry.x, geometry.y));
+        }
+        let display = geometry
+            .display_index
+            .and_then(|index| displays.get(index))
+            .or_else(|| displays.first())?;
+        Some(Self::center_in_display(
+            display,
+            geometry.width,
+            geometry.height,
+        ))
+    }
+
+    /// Source crop (None for the whole frame) and destination rect for a mode
+    fn calculate_rects(
+        mode: ScalingMode,
+        src_width: u32,
+        src_height: u32,
+        dst_width: u32,
+        dst_height: u32,
+    ) -> (Option<Rect>, Rect) {
+        match mode {
+            ScalingMode::Fit => (
+                None,
+                Self::calculate_dest_rect(src_width, src_height, dst_width, dst_height),
+            ),
+            ScalingMode::Stretch => (None, Rect::new(0, 0, dst_width, dst_height)),
+            ScalingMode::Fill => {
+                // Scale so the frame covers the window, then crop the overflow
+                let scale = f64::max(
+                    dst_width as f64 / src_width as f64,
+                    dst_height as f64 / src_height as f64,
+                );
+                let crop_width = ((dst_width as f64 / scale) as u32).clamp(1, src_width);
+                let crop_height = ((dst_height as f64 / scale) as u32).clamp(1, src_height);
+                let x = ((src_width - crop_width) / 2) as i32;
+                let y = ((src_height - crop_height) / 2) as i32;
+
+                (
+                    Some(Rect::new(x, y, crop_width, crop_height)),
+                    Rect::new(0, 0, dst_width, dst_height),
+                )
+            }
+            ScalingMode::Integer => {
+                let multiple = (dst_width / src_width).min(dst_height / src_height);
+                if multiple == 0 {
+                    // Window is smaller than the frame: downscale like Fit
+                    return (
+                        None,
+                        Self::calculate_dest_rect(src_width, src_height, dst_width, dst_height),
+                    );
+                }
+
+                let render_width = src_width * multiple;
+                let render_height = src_height * multiple;
+                let x = ((dst_width - render_width) / 2) as i32;
+                let y = ((dst_height - render_height) / 2) as i32;
+
+                (None, Rect::new(x, y, render_width, render_height))
+            }
+        }
     }
 
     fn calculate_dest_rect(
@@ -205,6 +831,18 @@ impl Renderer {
     }
 }
 
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // Nothing else will take this window's events
+        let window_id = self.canvas.window().id();
+        if let Ok(mut events) = self.events.try_borrow_mut() {
+            events
+                .pending
+                .retain(|event| event.get_window_id() != Some(window_id));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,6 +854,115 @@ mod tests {
         assert_eq!(config.height, 1080);
         assert!(!config.fullscreen);
         assert!(config.vsync);
+        assert_eq!(config.display_index, None);
+        assert_eq!(config.backend, RenderBackend::Sdl);
+        assert_eq!(config.geometry, None);
+    }
+
+    fn display(x: i32, y: i32, width: u32, height: u32) -> DisplayInfo {
+        DisplayInfo {
+            index: 1,
+            name: "test".to_string(),
+            x,
+            y,
+            width,
+            height,
+            refresh_rate: 60,
+        }
+    }
+
+    #[test]
+    fn test_center_in_display() {
+        // Secondary monitor to the right of a 1920-wide primary
+        let secondary = display(1920, 0, 2560, 1440);
+        assert_eq!(Renderer::center_in_display(&secondary, 1280, 720), (2560, 360));
+
+        // Monitor above and left of the primary has negative coordinates
+        let offset = display(-1920, -1080, 1920, 1080);
+        assert_eq!(Renderer::center_in_display(&offset, 1920, 1080), (-1920, -1080));
+    }
+
+    #[test]
+    fn test_center_in_display_window_larger() {
+        let small = display(1920, 0, 1280, 720);
+        assert_eq!(Renderer::center_in_display(&small, 1920, 1080), (1920, 0));
+    }
+
+    fn geometry(x: i32, y: i32, display_index: Option<usize>) -> WindowGeometry {
+        WindowGeometry {
+            x,
+            y,
+            width: 1280,
+            height: 720,
+            display_index,
+            fullscreen: false,
+        }
+    }
+
+    #[test]
+    fn test_restored_position_on_screen() {
+        let displays = [display(0, 0, 1920, 1080), display(1920, 0, 2560, 1440)];
+
+        // Straddling the two displays
+        let saved = geometry(1500, 100, Some(0));
+        assert!(saved.is_visible_on(&displays));
+        assert_eq!(
+            Renderer::restored_position(&saved, &displays),
+            Some((1500, 100))
+        );
+
+        // Mostly off the bottom, but the title bar is still showing
+        let saved = geometry(100, 1000, Some(0));
+        assert_eq!(
+            Renderer::restored_position(&saved, &displays),
+            Some((100, 1000))
+        );
+    }
+
+    #[test]
+    fn test_restored_position_off_screen() {
+        let displays = [display(0, 0, 1920, 1080)];
+
+        // Saved on a monitor that's since been unplugged: center on the
+        // first display instead
+        let saved = geometry(2200, 200, Some(1));
+        assert!(!saved.is_visible_on(&displays));
+        assert_eq!(
+            Renderer::restored_position(&saved, &displays),
+            Some((320, 180))
+        );
+
+        // Only a sliver on screen counts as off it
+        let saved = geometry(1900, 200, Some(0));
+        assert!(!saved.is_visible_on(&displays));
+        assert_eq!(
+            Renderer::restored_position(&saved, &displays),
+            Some((320, 180))
+        );
+
+        assert_eq!(Renderer::restored_position(&saved, &[]), None);
+    }
+
+    #[test]
+    fn test_restored_position_own_display() {
+        // Its own display is preferred when still attached
+        let displays = [display(0, 0, 1920, 1080), display(-2560, 0, 2560, 1440)];
+        let saved = geometry(-5000, 200, Some(1));
+        assert_eq!(
+            Renderer::restored_position(&saved, &displays),
+            Some((-1920, 360))
+        );
+    }
+
+    #[test]
+    fn test_small_window_visible() {
+        // A window smaller than the minimum only has to be entirely on screen
+        let saved = WindowGeometry {
+            width: 32,
+            height: 32,
+            ..geometry(1888, 1048, None)
+        };
+        assert!(saved.is_visible_on(&[display(0, 0, 1920, 1080)]));
     }
 
     #[test]
@@ -247,4 +994,130 @@ mod tests {
         assert_eq!(rect.x(), 0);
         assert_eq!(rect.y(), 0);
     }
+
+    #[test]
+    fn test_calculate_rects_fit() {
+        let (src, dst) = Renderer::calculate_rects(ScalingMode::Fit, 1920, 1080, 800, 600);
+        assert_eq!(src, None);
+        assert_eq!(dst, Renderer::calculate_dest_rect(1920, 1080, 800, 600));
+    }
+
+    #[test]
+    fn test_calculate_rects_stretch() {
+        let (src, dst) = Renderer::calculate_rects(ScalingMode::Stretch, 1920, 1080, 800, 600);
+        assert_eq!(src, None);
+        assert_eq!(dst, Rect::new(0, 0, 800, 600));
+    }
+
+    #[test]
+    fn test_calculate_rects_fill() {
+        // 16:9 source in 4:3 window: crop the sides
+        let (src, dst) = Renderer::calculate_rects(ScalingMode::Fill, 1920, 1080, 800, 600);
+        assert_eq!(dst, Rect::new(0, 0, 800, 600));
+        assert_eq!(src, Some(Rect::new(240, 0, 1440, 1080)));
+
+        // 4:3 source in 16:9 window: crop top and bottom
+        let (src, _) = Renderer::calculate_rects(ScalingMode::Fill, 800, 600, 1920, 1080);
+        assert_eq!(src, Some(Rect::new(0, 75, 800, 450)));
+    }
+
+    #[test]
+    fn test_calculate_rects_integer() {
+        // 2x fits in 2560x1440 but 3x doesn't
+        let (src, dst) = Renderer::calculate_rects(ScalingMode::Integer, 1024, 576, 2560, 1440);
+        assert_eq!(src, None);
+        assert_eq!(dst, Rect::new(256, 144, 2048, 1152));
+
+        // Exact fit
+        let (_, dst) = Renderer::calculate_rects(ScalingMode::Integer, 1920, 1080, 1920, 1080);
+        assert_eq!(dst, Rect::new(0, 0, 1920, 1080));
+    }
+
+    #[test]
+    fn test_calculate_rects_integer_window_too_small() {
+        // Window smaller than one multiple falls back to downscaling
+        let (src, dst) = Renderer::calculate_rects(ScalingMode::Integer, 1920, 1080, 800, 600);
+        assert_eq!(src, None);
+        assert_eq!(dst, Renderer::calculate_dest_rect(1920, 1080, 800, 600));
+    }
+
+    #[test]
+    fn test_yuv_conversion_mode() {
+        use SDL_YUV_CONVERSION_MODE::*;
+        for (matrix, mode) in [
+            (ColorMatrix::Bt601Limited, SDL_YUV_CONVERSION_BT601),
+            (ColorMatrix::Bt709Limited, SDL_YUV_CONVERSION_BT709),
+            (ColorMatrix::Bt601Full, SDL_YUV_CONVERSION_JPEG),
+            (ColorMatrix::Bt709Full, SDL_YUV_CONVERSION_JPEG),
+        ] {
+            assert_eq!(Renderer::yuv_conversion_mode(matrix), mode);
+        }
+    }
+
+    fn frame(frame_number: u64) -> DecodedFrame {
+        DecodedFrame::new(frame_number, 0, 64, 32, vec![128; 64 * 32 * 3 / 2])
+    }
+
+    #[test]
+    fn test_placeholder_keeps_last_frame() {
+        // SDL's dummy video driver needs no display
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let config = RendererConfig {
+            width: 64,
+            height: 32,
+            vsync: false,
+            keep_last_frame: true,
+            ..Default::default()
+        };
+        let mut renderer = match Renderer::new(config) {
+            Ok(renderer) => renderer,
+            Err(e) => {
+                eprintln!("Skipping, no headless SDL renderer: {}", e);
+                return;
+            }
+        };
+
+        // Before the first frame there's nothing to dim
+        renderer
+            .present_placeholder(PlaceholderKind::WaitingForConnection)
+            .unwrap();
+        assert!(renderer.last_frame.is_none());
+
+        let shown = frame(7);
+        renderer.present(&shown).unwrap();
+        for kind in [PlaceholderKind::Reconnecting, PlaceholderKind::Paused] {
+            renderer.present_placeholder(kind).unwrap();
+        }
+        let kept = renderer.last_frame.as_ref().unwrap();
+        assert_eq!(kept.frame_number, 7);
+        assert_eq!(kept.y_plane(), shown.y_plane());
+
+        // Video picks up again after a placeholder
+        renderer.present(&frame(8)).unwrap();
+        assert_eq!(renderer.last_frame.as_ref().unwrap().frame_number, 8);
+
+        // Without keep_last_frame, no frame is held for placeholders
+        renderer.keep_last_frame = false;
+        renderer.last_frame = None;
+        renderer.present(&frame(9)).unwrap();
+        renderer
+            .present_placeholder(PlaceholderKind::Paused)
+            .unwrap();
+        assert!(renderer.last_frame.is_none());
+    }
+
+    #[test]
+    fn test_scaling_mode_cycle() {
+        let mut mode = ScalingMode::default();
+        assert_eq!(mode, ScalingMode::Fit);
+        for expected in [
+            ScalingMode::Fill,
+            ScalingMode::Stretch,
+            ScalingMode::Integer,
+            ScalingMode::Fit,
+        ] {
+            mode = mode.next();
+            assert_eq!(mode, expected);
+        }
+    }
 }
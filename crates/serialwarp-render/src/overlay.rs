@@ -0,0 +1,253 @@
+//! On-screen statistics overlay drawn with an embedded 5x7 bitmap font
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+/// Glyph width in font pixels
+pub const GLYPH_WIDTH: i32 = 5;
+
+/// Glyph height in font pixels
+pub const GLYPH_HEIGHT: i32 = 7;
+
+/// Horizontal and vertical spacing between glyphs in font pixels
+const GLYPH_SPACING: i32 = 1;
+
+/// Screen pixels per font pixel
+const SCALE: i32 = 2;
+
+/// Padding around the text block in screen pixels
+const PADDING: i32 = 8;
+
+/// Statistics shown in the overlay, updated by the caller each frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderOverlayStats {
+    /// Presented frames per second
+    pub fps: f64,
+    /// Average decode time in milliseconds
+    pub decode_time_ms: f64,
+    /// End-to-end latency in milliseconds, if known
+    pub latency_ms: Option<f64>,
+    /// Received bitrate in bits per second
+    pub bitrate_bps: f64,
+    /// Frames dropped anywhere on the sink
+    pub frames_dropped: u64,
+    /// Decoded frames waiting to be presented
+    pub frames_queued: usize,
+}
+
+impl RenderOverlayStats {
+    /// Overlay text, one entry per line
+    pub fn lines(&self) -> Vec<String> {
+        let latency = match self.latency_ms {
+            Some(ms) => format!("{:.1} MS", ms),
+            None => "-".to_string(),
+        };
+
+        vec![
+            format!("FPS     {:.1}", self.fps),
+            format!("DECODE  {:.1} MS", self.decode_time_ms),
+            format!("LATENCY {}", latency),
+            format!("BITRATE {:.1} MBPS", self.bitrate_bps / 1_000_000.0),
+            format!("DROPPED {}", self.frames_dropped),
+            format!("QUEUED  {}", self.frames_queued),
+        ]
+    }
+}
+
+/// Rasterized overlay text, rebuilt only when the text changes
+#[derive(Debug, Default)]
+pub(crate) struct Overlay {
+    lines: Vec<String>,
+    rects: Vec<Rect>,
+    width: u32,
+    height: u32,
+}
+
+impl Overlay {
+    /// Update the overlay text from new stats
+    pub fn update(&mut self, stats: &RenderOverlayStats) {
+        let lines = stats.lines();
+        if lines == self.lines {
+            return;
+        }
+
+        let pixels = rasterize(&lines);
+        self.rects = pixels
+            .into_iter()
+            .map(|(x, y)| {
+                Rect::new(
+                    PADDING + x * SCALE,
+                    PADDING + y * SCALE,
+                    SCALE as u32,
+                    SCALE as u32,
+                )
+            })
+            .collect();
+
+        let (width, height) = text_size(&lines);
+        self.width = (width * SCALE + PADDING * 2) as u32;
+        self.height = (height * SCALE + PADDING * 2) as u32;
+        self.lines = lines;
+    }
+
+    /// Draw the overlay in the top-left corner of the canvas
+    pub fn draw(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+
+        let previous_blend = canvas.blend_mode();
+        let previous_color = canvas.draw_color();
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(Rect::new(0, 0, self.width, self.height))?;
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
+        canvas.fill_rects(&self.rects)?;
+
+        canvas.set_blend_mode(previous_blend);
+        canvas.set_draw_color(previous_color);
+        Ok(())
+    }
+}
+
+/// Size of a block of text in font pixels
+pub(crate) fn text_size(lines: &[String]) -> (i32, i32) {
+    let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+    let rows = lines.len() as i32;
+    let width = (columns * (GLYPH_WIDTH + GLYPH_SPACING) - GLYPH_SPACING).max(0);
+    let height = (rows * (GLYPH_HEIGHT + GLYPH_SPACING) - GLYPH_SPACING).max(0);
+    (width, height)
+}
+
+/// Rasterize lines of text into lit font-pixel coordinates
+pub(crate) fn rasterize(lines: &[String]) -> Vec<(i32, i32)> {
+    let mut pixels = Vec::new();
+
+    for (row, line) in lines.iter().enumerate() {
+        let origin_y = row as i32 * (GLYPH_HEIGHT + GLYPH_SPACING);
+        for (column, c) in line.chars().enumerate() {
+            let origin_x = column as i32 * (GLYPH_WIDTH + GLYPH_SPACING);
+            for (y, bits) in glyph(c).iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> x) != 0 {
+                        pixels.push((origin_x + x, origin_y + y as i32));
+                    }
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+/// 5x7 glyph rows, most significant of the low five bits is the leftmost column
+pub(crate) fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        ' ' => [0x00; 7],
+        // Unknown characters render as '?'
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rasterize_single_glyph() {
+        let pixels = rasterize(&["1".to_string()]);
+        // '1' has 10 lit pixels: 1 + 2 + 1 + 1 + 1 + 1 + 3
+        assert_eq!(pixels.len(), 10);
+        assert!(pixels.contains(&(2, 0)));
+        assert!(pixels.contains(&(1, 1)));
+        assert!(pixels
+            .iter()
+            .all(|&(x, y)| x < GLYPH_WIDTH && y < GLYPH_HEIGHT));
+    }
+
+    #[test]
+    fn test_rasterize_layout() {
+        let pixels = rasterize(&["- -".to_string(), "-".to_string()]);
+        // Each '-' is a full-width bar on row 3 of its cell
+        assert_eq!(pixels.len(), 15);
+        assert!(pixels.contains(&(12, 3)));
+        assert!(pixels.contains(&(0, 3 + GLYPH_HEIGHT + 1)));
+    }
+
+    #[test]
+    fn test_space_is_blank_and_case_insensitive() {
+        assert!(rasterize(&["   ".to_string()]).is_empty());
+        assert_eq!(glyph('a'), glyph('A'));
+    }
+
+    #[test]
+    fn test_text_size() {
+        let lines = vec!["AB".to_string(), "ABCD".to_string()];
+        assert_eq!(text_size(&lines), (23, 15));
+        assert_eq!(text_size(&[]), (0, 0));
+    }
+
+    #[test]
+    fn test_overlay_stats_lines() {
+        let stats = RenderOverlayStats {
+            fps: 59.94,
+            decode_time_ms: 2.25,
+            latency_ms: None,
+            bitrate_bps: 12_500_000.0,
+            frames_dropped: 3,
+            frames_queued: 2,
+        };
+        let lines = stats.lines();
+        assert_eq!(lines[0], "FPS     59.9");
+        assert_eq!(lines[2], "LATENCY -");
+        assert_eq!(lines[3], "BITRATE 12.5 MBPS");
+        assert_eq!(lines[4], "DROPPED 3");
+        assert_eq!(lines[5], "QUEUED  2");
+    }
+}
@@ -0,0 +1,187 @@
+//! What the window shows in place of video: a message on a plain
+//! background, drawn with the overlay's bitmap font
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::overlay::{rasterize, text_size};
+
+/// Background behind the message when there's no frame under it
+pub(crate) const BACKGROUND: (u8, u8, u8) = (16, 16, 16);
+
+/// Opacity of the black laid over a kept frame to dim it
+const DIM_ALPHA: u8 = 192;
+
+/// Largest number of screen pixels per font pixel
+const MAX_SCALE: i32 = 4;
+
+/// Space kept clear around the message, in screen pixels
+const MARGIN: i32 = 16;
+
+/// Why there's no video to show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    /// No stream has started yet
+    WaitingForConnection,
+    /// The source has gone and is expected back
+    Reconnecting,
+    /// Frames have stopped with the source still connected
+    Paused,
+}
+
+impl PlaceholderKind {
+    /// The text shown
+    pub fn message(self) -> &'static str {
+        match self {
+            PlaceholderKind::WaitingForConnection => "WAITING FOR CONNECTION",
+            PlaceholderKind::Reconnecting => "RECONNECTING",
+            PlaceholderKind::Paused => "PAUSED",
+        }
+    }
+
+    /// Color of the square before the text, telling the states apart at a
+    /// glance
+    pub fn indicator_color(self) -> (u8, u8, u8) {
+        match self {
+            PlaceholderKind::WaitingForConnection => (255, 176, 0),
+            PlaceholderKind::Reconnecting => (64, 160, 255),
+            PlaceholderKind::Paused => (160, 160, 160),
+        }
+    }
+}
+
+/// Where the indicator and the message's pixels go, centered in a
+/// `width` x `height` window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layout {
+    pub indicator: Rect,
+    pub text: Vec<Rect>,
+}
+
+impl Layout {
+    pub fn new(kind: PlaceholderKind, width: u32, height: u32) -> Self {
+        let lines = [kind.message().to_string()];
+        let (text_width, text_height) = text_size(&lines);
+        // The indicator is a square as tall as the text, with as much space
+        // again between it and the text
+        let gap = text_height;
+        let font_width = text_height + gap + text_width;
+
+        // Shrink the font to fit narrow windows, but no smaller than a pixel
+        let room = width as i32 - MARGIN * 2;
+        let scale = (room / font_width).clamp(1, MAX_SCALE);
+
+        let x = (width as i32 - font_width * scale) / 2;
+        let y = (height as i32 - text_height * scale) / 2;
+        let side = (text_height * scale) as u32;
+        let text_x = x + (text_height + gap) * scale;
+        let text = rasterize(&lines)
+            .into_iter()
+            .map(|(px, py)| {
+                Rect::new(
+                    text_x + px * scale,
+                    y + py * scale,
+                    scale as u32,
+                    scale as u32,
+                )
+            })
+            .collect();
+
+        Self {
+            indicator: Rect::new(x, y, side, side),
+            text,
+        }
+    }
+}
+
+/// Draw `kind`'s placeholder over the canvas. With `dim` whatever's already
+/// drawn, such as the last frame, is darkened and kept; otherwise the
+/// canvas is filled with the background first.
+pub(crate) fn draw(
+    canvas: &mut Canvas<Window>,
+    kind: PlaceholderKind,
+    dim: bool,
+) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let layout = Layout::new(kind, width, height);
+
+    let previous_blend = canvas.blend_mode();
+    let previous_color = canvas.draw_color();
+
+    if dim {
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, DIM_ALPHA));
+        canvas.fill_rect(None)?;
+    } else {
+        let (r, g, b) = BACKGROUND;
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(None)?;
+    }
+    let (r, g, b) = kind.indicator_color();
+    canvas.set_draw_color(Color::RGB(r, g, b));
+    canvas.fill_rect(layout.indicator)?;
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.fill_rects(&layout.text)?;
+
+    canvas.set_blend_mode(previous_blend);
+    canvas.set_draw_color(previous_color);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+    const KINDS: [PlaceholderKind; 3] = [
+        PlaceholderKind::WaitingForConnection,
+        PlaceholderKind::Reconnecting,
+        PlaceholderKind::Paused,
+    ];
+
+    #[test]
+    fn test_kinds_distinct() {
+        for (i, a) in KINDS.iter().enumerate() {
+            for b in &KINDS[i + 1..] {
+                assert_ne!(a.message(), b.message());
+                assert_ne!(a.indicator_color(), b.indicator_color());
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_centered() {
+        let layout = Layout::new(PlaceholderKind::Paused, 1920, 1080);
+        // "PAUSED" at the largest scale: the indicator is the text's height
+        assert_eq!(layout.indicator.height(), (GLYPH_HEIGHT * MAX_SCALE) as u32);
+        assert_eq!(layout.indicator.width(), layout.indicator.height());
+
+        let right = layout.text.iter().map(|r| r.right()).max().unwrap();
+        let left = layout.indicator.left();
+        assert_eq!(left, 1920 - right);
+        let top = layout.indicator.top();
+        assert_eq!(top, 1080 - layout.indicator.bottom());
+        assert!(layout
+            .text
+            .iter()
+            .all(|r| r.left() > layout.indicator.right()));
+    }
+
+    #[test]
+    fn test_layout_shrinks_to_fit() {
+        let message = PlaceholderKind::WaitingForConnection.message();
+        let font_width = message.len() as i32 * (GLYPH_WIDTH + 1) - 1 + GLYPH_HEIGHT * 2;
+
+        // Two font pixels to a screen pixel is as large as fits
+        let width = (font_width * 2 + MARGIN * 2) as u32;
+        let layout = Layout::new(PlaceholderKind::WaitingForConnection, width, 200);
+        assert_eq!(layout.text[0].width(), 2);
+        assert!(layout.indicator.left() >= MARGIN);
+
+        // A window too small for any of it still gets single pixels
+        let layout = Layout::new(PlaceholderKind::WaitingForConnection, 32, 16);
+        assert_eq!(layout.text[0].width(), 1);
+    }
+}
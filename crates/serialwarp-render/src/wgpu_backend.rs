@@ -0,0 +1,899 @@
+//! wgpu-based video renderer
+//!
+//! Frames are uploaded as one texture per plane and converted to RGB in a
+//! fragment shader, so the conversion matches the stream's matrix and range
+//! exactly rather than whatever the platform's SDL backend supports.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use sdl2::rect::Rect;
+use serialwarp_core::{ColorMatrix, DecodedFrame, RenderError};
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::{EventLoop, EventLoopBuilder};
+use winit::keyboard::{Key, NamedKey};
+use winit::monitor::MonitorHandle;
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Fullscreen, Window, WindowBuilder};
+
+use crate::placeholder::BACKGROUND;
+use crate::{
+    DisplayInfo, KeyAction, KeyBindings, Keycode, Modifiers, PlaceholderKind, RenderOverlayStats,
+    Renderer, RendererConfig, ScalingMode, WindowGeometry,
+};
+
+/// Size of the shader's `Params`: three vec4s
+const PARAMS_SIZE: usize = 48;
+
+/// Chroma samples are centered on 128
+const CHROMA_OFFSET: f32 = 128.0 / 255.0;
+
+/// A frame's planes as textures, remade when the frame size changes
+struct Planes {
+    width: u32,
+    height: u32,
+    y: wgpu::Texture,
+    u: wgpu::Texture,
+    v: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+/// wgpu-based video renderer, in a winit window.
+///
+/// Has the same surface and keyboard shortcuts as `Renderer`, but doesn't
+/// draw the statistics overlay or placeholder messages yet. winit allows one event loop per
+/// process, so only one can be created; on macOS it must be created on the
+/// main thread.
+pub struct WgpuRenderer {
+    // Dropped in order: the surface before its window, the window before
+    // its event loop
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    planes: Option<Planes>,
+    window: Arc<Window>,
+    event_loop: EventLoop<()>,
+    is_fullscreen: bool,
+    display_index: Option<usize>,
+    scaling_mode: ScalingMode,
+    overlay_visible: bool,
+    key_bindings: KeyBindings,
+    /// Modifiers held as of the last event
+    modifiers: Modifiers,
+    key_presses: Vec<Keycode>,
+    shortcuts: Vec<KeyAction>,
+    /// Position and size the last time the window wasn't fullscreen
+    windowed: (PhysicalPosition<i32>, PhysicalSize<u32>),
+    geometry_changed: bool,
+}
+
+impl WgpuRenderer {
+    /// Create a new renderer with the given configuration
+    pub fn new(config: RendererConfig) -> Result<Self, RenderError> {
+        let event_loop = Self::event_loop()?;
+        let window = WindowBuilder::new()
+            .with_title(&config.title)
+            .with_inner_size(LogicalSize::new(config.width, config.height))
+            .build(&event_loop)
+            .map_err(|e| RenderError::WindowCreationFailed(e.to_string()))?;
+        let window = Arc::new(window);
+
+        let instance = wgpu::Instance::default();
+        let surface = instance
+            .create_surface(Arc::clone(&window))
+            .map_err(|e| RenderError::RendererCreationFailed(e.to_string()))?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: Some(&surface),
+            ..Default::default()
+        }))
+        .ok_or_else(|| RenderError::RendererCreationFailed("no usable GPU".to_string()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("serialwarp"),
+                required_features: wgpu::Features::empty(),
+                required_limits:
+                    wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .map_err(|e| RenderError::RendererCreationFailed(e.to_string()))?;
+
+        let size = window.inner_size();
+        let position = window.outer_position().unwrap_or_default();
+        let mut surface_config = surface
+            .get_default_config(&adapter, size.width.max(1), size.height.max(1))
+            .ok_or_else(|| {
+                RenderError::RendererCreationFailed("window surface unsupported".to_string())
+            })?;
+        // The shader's output is already gamma encoded, so an sRGB surface
+        // would encode it twice and wash it out
+        if let Some(&format) = surface
+            .get_capabilities(&adapter)
+            .formats
+            .iter()
+            .find(|format| !format.is_srgb())
+        {
+            surface_config.format = format;
+        }
+        surface_config.present_mode = if config.vsync {
+            wgpu::PresentMode::AutoVsync
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        };
+        surface.configure(&device, &surface_config);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("serialwarp planes"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                Self::plane_layout_entry(1),
+                Self::plane_layout_entry(2),
+                Self::plane_layout_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("serialwarp"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("yuv.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("serialwarp yuv"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("serialwarp planes"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("serialwarp params"),
+            size: PARAMS_SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut renderer = Self {
+            surface,
+            surface_config,
+            device,
+            queue,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            params,
+            planes: None,
+            window,
+            event_loop,
+            is_fullscreen: false,
+            display_index: None,
+            scaling_mode: config.scaling_mode,
+            overlay_visible: false,
+            key_bindings: config.key_bindings.clone(),
+            modifiers: Modifiers::NONE,
+            key_presses: Vec::new(),
+            shortcuts: Vec::new(),
+            windowed: (position, size),
+            geometry_changed: false,
+        };
+
+        if let Some(geometry) = &config.geometry {
+            renderer.set_geometry(geometry)?;
+            return Ok(renderer);
+        }
+        // As with SDL, fullscreen goes on the target display once the
+        // window has moved there
+        if let Some(index) = config.display_index {
+            renderer.move_to_display(index)?;
+        }
+        if config.fullscreen {
+            renderer.set_fullscreen(true);
+        }
+
+        Ok(renderer)
+    }
+
+    /// An event loop that may run off the main thread, as it does behind a
+    /// `RendererHandle`. macOS doesn't allow that at all.
+    fn event_loop() -> Result<EventLoop<()>, RenderError> {
+        let mut builder = EventLoopBuilder::new();
+        // The X11 and Wayland backends share this setting
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        winit::platform::x11::EventLoopBuilderExtX11::with_any_thread(&mut builder, true);
+        #[cfg(target_os = "windows")]
+        winit::platform::windows::EventLoopBuilderExtWindows::with_any_thread(&mut builder, true);
+        builder
+            .build()
+            .map_err(|e| RenderError::WindowCreationFailed(e.to_string()))
+    }
+
+    fn plane_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    /// Enumerate the displays attached to this machine
+    pub fn list_displays(&self) -> Result<Vec<DisplayInfo>, RenderError> {
+        Ok(self
+            .window
+            .available_monitors()
+            .enumerate()
+            .map(|(index, monitor)| display_info(index, &monitor))
+            .collect())
+    }
+
+    /// Move the window onto the given display.
+    ///
+    /// The display is remembered, so later fullscreen toggles stay on it.
+    pub fn move_to_display(&mut self, index: usize) -> Result<(), RenderError> {
+        let monitors: Vec<_> = self.window.available_monitors().collect();
+        let monitor = monitors.get(index).ok_or(RenderError::InvalidDisplay {
+            index,
+            available: monitors.len(),
+        })?;
+
+        self.display_index = Some(index);
+        if self.is_fullscreen {
+            self.window
+                .set_fullscreen(Some(Fullscreen::Borderless(Some(monitor.clone()))));
+        } else {
+            self.place_on_display(&display_info(index, monitor));
+        }
+
+        Ok(())
+    }
+
+    /// Enter or leave fullscreen, on the display from `move_to_display` if
+    /// one was chosen
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let monitor = self
+            .display_index
+            .and_then(|index| self.window.available_monitors().nth(index));
+
+        if fullscreen {
+            self.window
+                .set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        } else {
+            self.window.set_fullscreen(None);
+            // Keep the window on the chosen display; the window manager may
+            // otherwise restore it elsewhere
+            if let (Some(index), Some(monitor)) = (self.display_index, monitor) {
+                self.place_on_display(&display_info(index, &monitor));
+            }
+        }
+        self.is_fullscreen = fullscreen;
+    }
+
+    /// Resize the window, in points, e.g. when the stream changes
+    /// resolution. Takes effect once the window leaves fullscreen.
+    pub fn set_window_size(&mut self, width: u32, height: u32) -> Result<(), RenderError> {
+        // The new size, if applied at once, comes back as a Resized event
+        let _ = self
+            .window
+            .request_inner_size(LogicalSize::new(width, height));
+        Ok(())
+    }
+
+    /// Whether the window is fullscreen
+    pub fn is_fullscreen(&self) -> bool {
+        self.is_fullscreen
+    }
+
+    /// Change how frames are scaled into the window
+    pub fn set_scaling_mode(&mut self, mode: ScalingMode) {
+        self.scaling_mode = mode;
+    }
+
+    /// Current scaling mode
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    /// Display chosen with `move_to_display`, if any
+    pub fn display_index(&self) -> Option<usize> {
+        self.display_index
+    }
+
+    /// Where the window is now, to restore with `set_geometry` next time.
+    /// Unlike `Renderer`'s, the size is in pixels.
+    pub fn geometry(&self) -> WindowGeometry {
+        let (position, size) = self.windowed;
+        let current = self.window.current_monitor();
+        WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            display_index: self
+                .window
+                .available_monitors()
+                .position(|monitor| Some(monitor) == current),
+            fullscreen: self.is_fullscreen,
+        }
+    }
+
+    /// Put the window back where `geometry` says it was, or centered on its
+    /// display or the first one if that's no longer on any display
+    pub fn set_geometry(&mut self, geometry: &WindowGeometry) -> Result<(), RenderError> {
+        let displays = self.list_displays()?;
+        let position = Renderer::restored_position(geometry, &displays);
+
+        if self.is_fullscreen {
+            self.window.set_fullscreen(None);
+        }
+        let size = PhysicalSize::new(geometry.width, geometry.height);
+        let _ = self.window.request_inner_size(size);
+        if let Some((x, y)) = position {
+            self.window.set_outer_position(PhysicalPosition::new(x, y));
+        }
+        if geometry.fullscreen {
+            self.window
+                .set_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
+        self.is_fullscreen = geometry.fullscreen;
+
+        let position = position.map_or(self.windowed.0, |(x, y)| PhysicalPosition::new(x, y));
+        self.windowed = (position, size);
+        Ok(())
+    }
+
+    /// Whether the window moved, resized, or entered or left fullscreen
+    /// during the last `process_events` call
+    pub fn geometry_changed(&self) -> bool {
+        self.geometry_changed
+    }
+
+    /// Present a decoded frame to the screen
+    pub fn present(&mut self, frame: &DecodedFrame) -> Result<(), RenderError> {
+        if frame.width == 0 || frame.height == 0 {
+            return Err(RenderError::TextureCreationFailed(format!(
+                "empty {}x{} frame",
+                frame.width, frame.height
+            )));
+        }
+
+        let planes = match self.planes.take() {
+            Some(planes) if planes.width == frame.width && planes.height == frame.height => planes,
+            _ => self.create_planes(frame.width, frame.height),
+        };
+        let planes = self.planes.insert(planes);
+        // DecodedFrame has already checked its planes cover every visible row
+        for (texture, data, stride) in [
+            (&planes.y, frame.y_plane(), frame.y_stride()),
+            (&planes.u, frame.u_plane(), frame.u_stride()),
+            (&planes.v, frame.v_plane(), frame.v_stride()),
+        ] {
+            self.queue.write_texture(
+                texture.as_image_copy(),
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(stride as u32),
+                    rows_per_image: None,
+                },
+                texture.size(),
+            );
+        }
+
+        let (src_rect, dst_rect) = Renderer::calculate_rects(
+            self.scaling_mode,
+            frame.width,
+            frame.height,
+            self.surface_config.width,
+            self.surface_config.height,
+        );
+        self.queue.write_buffer(
+            &self.params,
+            0,
+            &shader_params(
+                frame.color_space.matrix,
+                crop(src_rect, frame.width, frame.height),
+            ),
+        );
+
+        // Skip this frame if the window changed under the surface; the next
+        // shows on a fresh one
+        let output = current_texture(&self.surface, &self.device, &self.surface_config)?;
+        let Some(output) = output else {
+            return Ok(());
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("serialwarp frame"),
+            });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("serialwarp frame"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            // A window squashed to nothing only needs clearing
+            if dst_rect.width() > 0 && dst_rect.height() > 0 {
+                pass.set_viewport(
+                    dst_rect.x() as f32,
+                    dst_rect.y() as f32,
+                    dst_rect.width() as f32,
+                    dst_rect.height() as f32,
+                    0.0,
+                    1.0,
+                );
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &planes.bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        self.queue.submit([encoder.finish()]);
+        output.present();
+
+        Ok(())
+    }
+
+    /// Show a plain background in place of video, until the next frame is
+    /// presented. Unlike `Renderer::present_placeholder` the message isn't
+    /// drawn yet, nor is the last frame kept; the frame's textures are left
+    /// as they are.
+    pub fn present_placeholder(&mut self, _kind: PlaceholderKind) -> Result<(), RenderError> {
+        let output = current_texture(&self.surface, &self.device, &self.surface_config)?;
+        let Some(output) = output else {
+            return Ok(());
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let (r, g, b) = BACKGROUND;
+        let background = wgpu::Color {
+            r: r as f64 / 255.0,
+            g: g as f64 / 255.0,
+            b: b as f64 / 255.0,
+            a: 1.0,
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("serialwarp placeholder"),
+            });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("serialwarp placeholder"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(background),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        self.queue.submit([encoder.finish()]);
+        output.present();
+
+        Ok(())
+    }
+
+    fn create_planes(&self, width: u32, height: u32) -> Planes {
+        let plane = |label, width, height| {
+            self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+        // Chroma is rounded up for odd sizes
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+        let y = plane("y plane", width, height);
+        let u = plane("u plane", chroma_width, chroma_height);
+        let v = plane("v plane", chroma_width, chroma_height);
+
+        let views = [&y, &u, &v].map(|texture| texture.create_view(&Default::default()));
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("serialwarp planes"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&views[2]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        Planes {
+            width,
+            height,
+            y,
+            u,
+            v,
+            bind_group,
+        }
+    }
+
+    /// Process window events. Returns false if quit was requested.
+    pub fn process_events(&mut self) -> bool {
+        self.key_presses.clear();
+        self.shortcuts.clear();
+        self.geometry_changed = false;
+
+        let mut quit = false;
+        let mut modifiers = self.modifiers;
+        let mut moved = false;
+        let mut resized = None;
+        let mut keys = Vec::new();
+        let status = self
+            .event_loop
+            .pump_events(Some(Duration::ZERO), |event, _| {
+                let Event::WindowEvent { event, .. } = event else {
+                    return;
+                };
+                match event {
+                    WindowEvent::CloseRequested => quit = true,
+                    WindowEvent::Resized(size) => resized = Some(size),
+                    WindowEvent::Moved(_) => moved = true,
+                    WindowEvent::ModifiersChanged(changed) => {
+                        let state = changed.state();
+                        modifiers = Modifiers {
+                            ctrl: state.control_key(),
+                            alt: state.alt_key(),
+                            shift: state.shift_key(),
+                            gui: state.super_key(),
+                        };
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key,
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => keys.extend(keycode(&logical_key).map(|key| (key, modifiers))),
+                    _ => {}
+                }
+            });
+        self.modifiers = modifiers;
+        if quit || matches!(status, PumpStatus::Exit(_)) {
+            return false;
+        }
+        if let Some(size) = resized {
+            self.resize(size);
+        }
+        if moved || resized.is_some() {
+            self.window_changed();
+        }
+
+        for (key, modifiers) in keys {
+            match self.key_bindings.action(key, modifiers) {
+                Some(KeyAction::Quit) => return false,
+                Some(KeyAction::ToggleFullscreen) => {
+                    self.set_fullscreen(!self.is_fullscreen);
+                    self.geometry_changed = true;
+                }
+                Some(KeyAction::ToggleOverlay) => self.overlay_visible = !self.overlay_visible,
+                Some(KeyAction::CycleScaling) => self.scaling_mode = self.scaling_mode.next(),
+                Some(action) => self.shortcuts.push(action),
+                None => self.key_presses.push(key),
+            }
+        }
+        true
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        // Minimized windows report a zero size, which a surface can't have
+        if size.width > 0 && size.height > 0 {
+            self.surface_config.width = size.width;
+            self.surface_config.height = size.height;
+            self.surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    fn window_changed(&mut self) {
+        // While fullscreen the window covers its display; keep the size and
+        // position to go back to
+        if !self.is_fullscreen {
+            let position = self.window.outer_position().unwrap_or(self.windowed.0);
+            self.windowed = (position, self.window.inner_size());
+        }
+        self.geometry_changed = true;
+    }
+
+    /// Keys from the last `process_events` call that aren't bound to a
+    /// shortcut, and so can be passed on to the source
+    pub fn key_presses(&self) -> &[Keycode] {
+        &self.key_presses
+    }
+
+    /// Shortcuts from the last `process_events` call that the renderer
+    /// leaves to its caller, see `KeyAction`
+    pub fn shortcuts(&self) -> &[KeyAction] {
+        &self.shortcuts
+    }
+
+    /// Update the statistics shown by the overlay. The overlay isn't drawn
+    /// by this renderer yet, so this does nothing.
+    pub fn set_overlay_stats(&mut self, _stats: &RenderOverlayStats) {}
+
+    /// Show or hide the statistics overlay, once this renderer draws one
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.overlay_visible = visible;
+    }
+
+    /// Whether the statistics overlay is shown
+    pub fn overlay_visible(&self) -> bool {
+        self.overlay_visible
+    }
+
+    fn place_on_display(&self, display: &DisplayInfo) {
+        let size = self.window.outer_size();
+        let (x, y) = Renderer::center_in_display(display, size.width, size.height);
+        self.window.set_outer_position(PhysicalPosition::new(x, y));
+    }
+}
+
+fn display_info(index: usize, monitor: &MonitorHandle) -> DisplayInfo {
+    let position = monitor.position();
+    let size = monitor.size();
+    DisplayInfo {
+        index,
+        name: monitor.name().unwrap_or_default(),
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        refresh_rate: monitor
+            .refresh_rate_millihertz()
+            .map_or(0, |millihertz| (millihertz / 1000) as i32),
+    }
+}
+
+/// The SDL keycode for a winit key, so both renderers report keys alike
+fn keycode(key: &Key) -> Option<Keycode> {
+    match key {
+        Key::Named(NamedKey::Escape) => Some(Keycode::Escape),
+        Key::Named(NamedKey::Enter) => Some(Keycode::Return),
+        Key::Named(NamedKey::Space) => Some(Keycode::Space),
+        Key::Named(NamedKey::Tab) => Some(Keycode::Tab),
+        Key::Named(NamedKey::Backspace) => Some(Keycode::Backspace),
+        Key::Named(NamedKey::Delete) => Some(Keycode::Delete),
+        Key::Named(NamedKey::Insert) => Some(Keycode::Insert),
+        Key::Named(NamedKey::Home) => Some(Keycode::Home),
+        Key::Named(NamedKey::End) => Some(Keycode::End),
+        Key::Named(NamedKey::PageUp) => Some(Keycode::PageUp),
+        Key::Named(NamedKey::PageDown) => Some(Keycode::PageDown),
+        Key::Named(NamedKey::ArrowUp) => Some(Keycode::Up),
+        Key::Named(NamedKey::ArrowDown) => Some(Keycode::Down),
+        Key::Named(NamedKey::ArrowLeft) => Some(Keycode::Left),
+        Key::Named(NamedKey::ArrowRight) => Some(Keycode::Right),
+        Key::Named(NamedKey::PrintScreen) => Some(Keycode::PrintScreen),
+        Key::Named(NamedKey::F1) => Some(Keycode::F1),
+        Key::Named(NamedKey::F2) => Some(Keycode::F2),
+        Key::Named(NamedKey::F3) => Some(Keycode::F3),
+        Key::Named(NamedKey::F4) => Some(Keycode::F4),
+        Key::Named(NamedKey::F5) => Some(Keycode::F5),
+        Key::Named(NamedKey::F6) => Some(Keycode::F6),
+        Key::Named(NamedKey::F7) => Some(Keycode::F7),
+        Key::Named(NamedKey::F8) => Some(Keycode::F8),
+        Key::Named(NamedKey::F9) => Some(Keycode::F9),
+        Key::Named(NamedKey::F10) => Some(Keycode::F10),
+        Key::Named(NamedKey::F11) => Some(Keycode::F11),
+        Key::Named(NamedKey::F12) => Some(Keycode::F12),
+        // SDL's keycodes for printable keys are their unshifted ASCII
+        Key::Character(text) => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii() => Keycode::from_i32(c.to_ascii_lowercase() as i32),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `surface`'s next texture to draw into. None if the window changed under
+/// it, in which case it's set up afresh for the next draw.
+fn current_texture(
+    surface: &wgpu::Surface,
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> Result<Option<wgpu::SurfaceTexture>, RenderError> {
+    match surface.get_current_texture() {
+        Ok(output) => Ok(Some(output)),
+        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+            surface.configure(device, config);
+            Ok(None)
+        }
+        Err(e) => Err(RenderError::RenderFailed(e.to_string())),
+    }
+}
+
+/// The part of the frame shown, as fractions of its size
+fn crop(src_rect: Option<Rect>, width: u32, height: u32) -> [f32; 4] {
+    match src_rect {
+        None => [0.0, 0.0, 1.0, 1.0],
+        Some(rect) => [
+            rect.x() as f32 / width as f32,
+            rect.y() as f32 / height as f32,
+            rect.width() as f32 / width as f32,
+            rect.height() as f32 / height as f32,
+        ],
+    }
+}
+
+/// The shader's `Params` for a frame, as the uniform buffer holds them
+fn shader_params(matrix: ColorMatrix, crop: [f32; 4]) -> [u8; PARAMS_SIZE] {
+    let c = matrix.shader_coefficients();
+    let [x, y, width, height] = crop;
+    let values = [
+        c.y_offset,
+        c.y,
+        c.r_v,
+        c.g_u,
+        c.g_v,
+        c.b_u,
+        CHROMA_OFFSET,
+        0.0,
+        x,
+        y,
+        width,
+        height,
+    ];
+
+    let mut bytes = [0; PARAMS_SIZE];
+    for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_shader_params_layout() {
+        let params = shader_params(ColorMatrix::Bt709Limited, [0.25, 0.0, 0.5, 1.0]);
+        let c = ColorMatrix::Bt709Limited.shader_coefficients();
+        assert_eq!(
+            floats(&params),
+            [
+                c.y_offset,
+                c.y,
+                c.r_v,
+                c.g_u,
+                c.g_v,
+                c.b_u,
+                CHROMA_OFFSET,
+                0.0,
+                0.25,
+                0.0,
+                0.5,
+                1.0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crop() {
+        assert_eq!(crop(None, 1920, 1080), [0.0, 0.0, 1.0, 1.0]);
+
+        // Fill's crop of a 16:9 frame in a 4:3 window keeps the middle 3/4
+        let (src, _) = Renderer::calculate_rects(ScalingMode::Fill, 1920, 1080, 800, 600);
+        assert_eq!(crop(src, 1920, 1080), [0.125, 0.0, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn test_keycode() {
+        assert_eq!(
+            keycode(&Key::Named(NamedKey::Escape)),
+            Some(Keycode::Escape)
+        );
+        assert_eq!(keycode(&Key::Named(NamedKey::F11)), Some(Keycode::F11));
+        assert_eq!(keycode(&Key::Character("s".into())), Some(Keycode::S));
+        assert_eq!(keycode(&Key::Character("F".into())), Some(Keycode::F));
+        assert_eq!(keycode(&Key::Character("1".into())), Some(Keycode::Num1));
+        assert_eq!(keycode(&Key::Character("é".into())), None);
+        assert_eq!(keycode(&Key::Named(NamedKey::CapsLock)), None);
+    }
+}
@@ -0,0 +1,48 @@
+// YUV420P planes to RGB, drawn as one triangle covering the viewport
+
+struct Params {
+    // y_offset, y, r_v, g_u
+    coefficients0: vec4<f32>,
+    // g_v, b_u, chroma offset, unused
+    coefficients1: vec4<f32>,
+    // Part of the frame shown: x, y, width, height, all 0..1
+    crop: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var y_plane: texture_2d<f32>;
+@group(0) @binding(2) var u_plane: texture_2d<f32>;
+@group(0) @binding(3) var v_plane: texture_2d<f32>;
+@group(0) @binding(4) var plane_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0, 0), (2, 0), (0, 2): twice the viewport, so it's all covered
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    // Texture rows run top to bottom, clip space bottom to top
+    let uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    out.uv = params.crop.xy + uv * params.crop.zw;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let c0 = params.coefficients0;
+    let c1 = params.coefficients1;
+    let luma = c0.y * (textureSample(y_plane, plane_sampler, in.uv).r - c0.x);
+    let u = textureSample(u_plane, plane_sampler, in.uv).r - c1.z;
+    let v = textureSample(v_plane, plane_sampler, in.uv).r - c1.z;
+    let rgb = vec3<f32>(
+        luma + c0.z * v,
+        luma - c0.w * u - c1.x * v,
+        luma + c1.y * u,
+    );
+    return vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
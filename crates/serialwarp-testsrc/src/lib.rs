@@ -0,0 +1,403 @@
+//! serialwarp-testsrc - Synthetic frame source for development without macOS
+//!
+//! This crate provides a test-pattern generator behind the same frame source
+//! interface as screen capture, so the source pipeline can run on any OS, and
+//! an async `Stream` adapter for any frame source.
+
+use std::time::{Duration, Instant};
+
+use serialwarp_core::{CaptureError, FramePool, Resolution, MAX_DIMENSION};
+use tokio_util::sync::CancellationToken;
+
+mod stream;
+
+pub use serialwarp_core::{CapturedFrame, FrameSource};
+pub use stream::{FrameStream, DEFAULT_STREAM_DEPTH};
+
+/// Number of bits in the frame counter strip
+pub const COUNTER_BITS: u32 = 32;
+
+/// Side length in pixels of each counter bit block
+pub const COUNTER_BLOCK: u32 = 16;
+
+/// Bytes per BGRA pixel
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Horizontal distance the color bars move per frame
+const BAR_SPEED: u32 = 4;
+
+/// Classic 75% color bars, in BGRA order
+const BARS: [[u8; 4]; 8] = [
+    [191, 191, 191, 255], // white
+    [0, 191, 191, 255],   // yellow
+    [191, 191, 0, 255],   // cyan
+    [0, 191, 0, 255],     // green
+    [191, 0, 191, 255],   // magenta
+    [0, 0, 191, 255],     // red
+    [191, 0, 0, 255],     // blue
+    [0, 0, 0, 255],       // black
+];
+
+/// Test pattern configuration
+#[derive(Debug, Clone)]
+pub struct TestPatternConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// Wait for each frame's presentation time in `next_frame`, like a real
+    /// capture stream; disable to generate frames as fast as possible
+    pub paced: bool,
+}
+
+impl Default for TestPatternConfig {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            paced: true,
+        }
+    }
+}
+
+/// Generates moving color bars with the frame counter burned into the top-left
+/// corner as a strip of black/white bit blocks (most significant bit first).
+pub struct TestPatternSource {
+    config: TestPatternConfig,
+    frame_number: u64,
+    /// When frame `base_frame` is due; later frames follow at the frame rate
+    started: Option<Instant>,
+    /// First frame at the current frame rate, and its pts
+    base_frame: u64,
+    base_pts_us: u64,
+    /// Frame buffers, reused once the consumer drops them
+    pool: FramePool,
+}
+
+impl TestPatternSource {
+    pub fn new(config: TestPatternConfig) -> Result<Self, CaptureError> {
+        validate(&config)?;
+
+        Ok(Self {
+            config,
+            frame_number: 0,
+            started: None,
+            base_frame: 0,
+            base_pts_us: 0,
+            pool: FramePool::default(),
+        })
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_micros(1_000_000 / self.config.fps as u64)
+    }
+
+    /// When the next frame is due, once the first has been taken
+    fn next_due(&self) -> Option<Instant> {
+        let frames = (self.frame_number - self.base_frame) as u32;
+        self.started
+            .map(|started| started + self.frame_interval() * frames)
+    }
+
+    /// Presentation time of frame `frame_number`, counting from the last
+    /// frame rate change
+    fn pts_us(&self, frame_number: u64) -> u64 {
+        let frames = frame_number.saturating_sub(self.base_frame);
+        self.base_pts_us + frames * self.frame_interval().as_micros() as u64
+    }
+
+    /// Render frame `frame_number` of the pattern
+    pub fn render(&self, frame_number: u64) -> CapturedFrame {
+        let width = self.config.width;
+        let height = self.config.height;
+        let stride = width as usize * BYTES_PER_PIXEL;
+        let mut data = self.pool.get(stride, height);
+
+        // Color bars scrolling left
+        let bar_width = (width / BARS.len() as u32).max(1);
+        let offset = (frame_number * BAR_SPEED as u64 % width as u64) as u32;
+        for x in 0..width {
+            let bar = ((x + offset) % width / bar_width) as usize % BARS.len();
+            let color = BARS[bar];
+            for y in 0..height {
+                let i = y as usize * stride + x as usize * BYTES_PER_PIXEL;
+                data[i..i + BYTES_PER_PIXEL].copy_from_slice(&color);
+            }
+        }
+
+        // Frame counter (low 32 bits)
+        let counter = frame_number as u32;
+        for bit in 0..COUNTER_BITS {
+            let set = counter & (1 << (COUNTER_BITS - 1 - bit)) != 0;
+            let value = if set { 255 } else { 0 };
+            for y in 0..COUNTER_BLOCK {
+                for x in bit * COUNTER_BLOCK..(bit + 1) * COUNTER_BLOCK {
+                    let i = y as usize * stride + x as usize * BYTES_PER_PIXEL;
+                    data[i..i + BYTES_PER_PIXEL].copy_from_slice(&[value, value, value, 255]);
+                }
+            }
+        }
+
+        CapturedFrame {
+            frame_number,
+            pts_us: self.pts_us(frame_number),
+            width,
+            height,
+            stride,
+            data,
+        }
+    }
+
+    /// Generate frames on a background thread as a `Stream`, stopping when
+    /// `shutdown` is cancelled
+    pub fn into_stream(self, shutdown: CancellationToken) -> FrameStream {
+        FrameStream::spawn(self, DEFAULT_STREAM_DEPTH, shutdown)
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn next_frame(&mut self) -> Result<CapturedFrame, CaptureError> {
+        let now = Instant::now();
+        self.started.get_or_insert(now);
+
+        if self.config.paced {
+            let due = self.next_due().unwrap_or(now);
+            if due > now {
+                std::thread::sleep(due - now);
+            }
+        }
+
+        let frame = self.render(self.frame_number);
+        self.frame_number += 1;
+        Ok(frame)
+    }
+
+    fn resolution(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    fn fps(&self) -> u32 {
+        self.config.fps
+    }
+
+    fn set_fps(&mut self, fps: u32) -> Result<(), CaptureError> {
+        let config = TestPatternConfig {
+            fps,
+            ..self.config.clone()
+        };
+        validate(&config)?;
+
+        // The next frame is due and timed as it would have been at the old
+        // rate; the ones after it follow at the new rate
+        let next = self.frame_number;
+        self.started = self.next_due();
+        self.base_pts_us = self.pts_us(next);
+        self.base_frame = next;
+        self.config = config;
+        Ok(())
+    }
+
+    fn set_dimensions(&mut self, width: u32, height: u32) -> Result<(), CaptureError> {
+        let config = TestPatternConfig {
+            width,
+            height,
+            ..self.config.clone()
+        };
+        validate(&config)?;
+        self.config = config;
+        Ok(())
+    }
+}
+
+fn validate(config: &TestPatternConfig) -> Result<(), CaptureError> {
+    if config.fps == 0 {
+        return Err(CaptureError::InvalidConfiguration(
+            "fps must be non-zero".to_string(),
+        ));
+    }
+    // Odd sizes are fine; the encoder pads them
+    if !Resolution::new(config.width, config.height).is_valid() {
+        return Err(CaptureError::InvalidConfiguration(format!(
+            "resolution {}x{} is outside 1x1 to {}x{}",
+            config.width, config.height, MAX_DIMENSION, MAX_DIMENSION
+        )));
+    }
+    if config.width < COUNTER_BITS * COUNTER_BLOCK || config.height < COUNTER_BLOCK {
+        return Err(CaptureError::InvalidConfiguration(format!(
+            "resolution {}x{} is too small for the frame counter (min {}x{})",
+            config.width,
+            config.height,
+            COUNTER_BITS * COUNTER_BLOCK,
+            COUNTER_BLOCK
+        )));
+    }
+    Ok(())
+}
+
+/// Read the frame counter back out of a test pattern frame.
+///
+/// Samples the center of each bit block, so it tolerates lossy compression.
+/// `luma` returns the brightness of the pixel at (x, y).
+pub fn read_counter(luma: impl Fn(u32, u32) -> u8) -> u32 {
+    (0..COUNTER_BITS).fold(0, |counter, bit| {
+        let x = bit * COUNTER_BLOCK + COUNTER_BLOCK / 2;
+        let y = COUNTER_BLOCK / 2;
+        (counter << 1) | u32::from(luma(x, y) > 128)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpaced(width: u32, height: u32) -> TestPatternSource {
+        TestPatternSource::new(TestPatternConfig {
+            width,
+            height,
+            fps: 60,
+            paced: false,
+        })
+        .unwrap()
+    }
+
+    fn counter_of(frame: &CapturedFrame) -> u32 {
+        read_counter(|x, y| frame.pixel(x, y)[1])
+    }
+
+    #[test]
+    fn test_frame_dimensions() {
+        let mut source = unpaced(640, 360);
+        let frame = source.next_frame().unwrap();
+
+        assert_eq!((frame.width, frame.height), (640, 360));
+        assert_eq!(frame.stride, 640 * 4);
+        assert_eq!(frame.data.len(), 640 * 4 * 360);
+        assert_eq!(source.resolution(), (640, 360));
+        assert_eq!(source.fps(), 60);
+    }
+
+    #[test]
+    fn test_counter_increments() {
+        let mut source = unpaced(640, 360);
+        for expected in 0..5 {
+            let frame = source.next_frame().unwrap();
+            assert_eq!(frame.frame_number, expected);
+            assert_eq!(counter_of(&frame), expected as u32);
+        }
+    }
+
+    #[test]
+    fn test_counter_large_values() {
+        let source = unpaced(640, 360);
+        for value in [0x8000_0001u64, 0xDEAD_BEEF, u32::MAX as u64] {
+            assert_eq!(counter_of(&source.render(value)), value as u32);
+        }
+    }
+
+    #[test]
+    fn test_pts_follows_frame_rate() {
+        let source = unpaced(640, 360);
+        assert_eq!(source.render(0).pts_us, 0);
+        assert_eq!(source.render(60).pts_us, 60 * 16_666);
+    }
+
+    #[test]
+    fn test_bars_move() {
+        let source = unpaced(640, 360);
+        let first = source.render(0);
+        let later = source.render(10);
+
+        // Below the counter strip the bars have shifted left by 40 pixels
+        let y = 100;
+        assert_eq!(first.pixel(0, y), BARS[0]);
+        assert_eq!(later.pixel(0, y), first.pixel(10 * BAR_SPEED, y));
+        assert_ne!(first.pixel(79, y), first.pixel(80, y));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = |width, height, fps| TestPatternConfig {
+            width,
+            height,
+            fps,
+            paced: false,
+        };
+        assert!(TestPatternSource::new(config(640, 360, 0)).is_err());
+        assert!(TestPatternSource::new(config(0, 360, 60)).is_err());
+        assert!(TestPatternSource::new(config(MAX_DIMENSION + 2, 360, 60)).is_err());
+        assert!(TestPatternSource::new(config(128, 128, 60)).is_err());
+    }
+
+    #[test]
+    fn test_set_fps_keeps_pts_increasing() {
+        let mut source = unpaced(640, 360);
+        let pts: Vec<u64> = (0..3)
+            .map(|_| source.next_frame().unwrap().pts_us)
+            .collect();
+        assert_eq!(pts, [0, 16_666, 33_332]);
+
+        source.set_fps(30).unwrap();
+        assert_eq!(source.fps(), 30);
+        let pts: Vec<u64> = (0..3)
+            .map(|_| source.next_frame().unwrap().pts_us)
+            .collect();
+        assert_eq!(pts, [49_998, 83_331, 116_664]);
+
+        // Faster again: still never going back
+        source.set_fps(120).unwrap();
+        let frame = source.next_frame().unwrap();
+        assert_eq!(frame.frame_number, 6);
+        assert_eq!(frame.pts_us, 149_997);
+        assert_eq!(source.next_frame().unwrap().pts_us, 149_997 + 8_333);
+
+        assert!(source.set_fps(0).is_err());
+        assert_eq!(source.fps(), 120);
+    }
+
+    #[test]
+    fn test_set_fps_paces_at_new_rate() {
+        // Unpaced frames are still timed; only the sleeping is skipped
+        let mut source = unpaced(512, 32);
+        source.set_fps(100).unwrap();
+        assert_eq!(source.next_due(), None);
+        source.next_frame().unwrap();
+        let started = source.next_due().unwrap() - Duration::from_millis(10);
+        source.next_frame().unwrap();
+        assert_eq!(source.next_due(), Some(started + Duration::from_millis(20)));
+
+        source.set_fps(25).unwrap();
+        // Due 10ms after the last at the old rate, then 40ms apart
+        let dues: Vec<Duration> = (0..3)
+            .map(|_| {
+                let due = source.next_due().unwrap() - started;
+                source.next_frame().unwrap();
+                due
+            })
+            .collect();
+        assert_eq!(dues, [20, 60, 100].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn test_set_dimensions() {
+        let mut source = unpaced(640, 360);
+        source.next_frame().unwrap();
+
+        source.set_dimensions(1280, 720).unwrap();
+        let frame = source.next_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (1280, 720));
+        assert_eq!(frame.frame_number, 1);
+        assert_eq!(counter_of(&frame), 1);
+
+        assert!(source.set_dimensions(128, 128).is_err());
+        assert_eq!(source.resolution(), (1280, 720));
+    }
+
+    #[test]
+    fn test_odd_dimensions() {
+        let mut source = unpaced(1367, 769);
+        let frame = source.next_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (1367, 769));
+        assert_eq!(frame.data.len(), 1367 * 4 * 769);
+        assert_eq!(counter_of(&frame), 0);
+    }
+}
@@ -0,0 +1,297 @@
+//! Async frame stream over a blocking `FrameSource`
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::JoinHandle;
+
+use futures_core::Stream;
+use serialwarp_core::CaptureError;
+use tokio::sync::mpsc;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+use crate::{CapturedFrame, FrameSource};
+
+/// Default number of captured frames buffered ahead of the consumer
+pub const DEFAULT_STREAM_DEPTH: usize = 2;
+
+/// Captured frames as a `Stream`.
+///
+/// The source runs on its own thread and hands frames over a bounded channel,
+/// so a slow consumer backpressures capture instead of queueing frames. The
+/// stream ends when the source fails, `stop()` is called, or the shutdown
+/// token is cancelled; a consumer waiting on the stream wakes up with `None`
+/// as soon as either of the last two happens. A failed source yields its
+/// error once before the stream ends, so a capture that stops on its own
+/// isn't mistaken for one that has nothing new to show.
+pub struct FrameStream {
+    receiver: mpsc::Receiver<CapturedFrame>,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+    shutdown: CancellationToken,
+    error: Arc<Mutex<Option<CaptureError>>>,
+    thread: Option<JoinHandle<()>>,
+    finished: bool,
+}
+
+impl FrameStream {
+    /// Start pulling frames from `source` on a capture thread, stopping when
+    /// `shutdown` is cancelled
+    pub fn spawn<S>(mut source: S, depth: usize, shutdown: CancellationToken) -> Self
+    where
+        S: FrameSource + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(depth.max(1));
+        let error = Arc::new(Mutex::new(None));
+
+        let thread_error = Arc::clone(&error);
+        let thread_shutdown = shutdown.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_shutdown.is_cancelled() {
+                match source.next_frame() {
+                    Ok(frame) => {
+                        // Fails once the stream is stopped or dropped
+                        if sender.blocking_send(frame).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        *thread_error.lock().unwrap() = Some(e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            receiver,
+            cancelled: Box::pin(shutdown.clone().cancelled_owned()),
+            shutdown,
+            error,
+            thread: Some(thread),
+            finished: false,
+        }
+    }
+
+    /// Wait for the next frame; `Ok(None)` once the stream has ended, after
+    /// the error that ended it if the source failed
+    pub async fn next_frame(&mut self) -> Result<Option<CapturedFrame>, CaptureError> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+            .await
+            .transpose()
+    }
+
+    /// End the stream: frames still buffered are discarded, later polls return
+    /// `None`, and the capture thread exits after its current frame
+    pub fn stop(&mut self) {
+        self.shutdown.cancel();
+        self.finish();
+    }
+
+    /// Token that stops this stream when cancelled
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        self.receiver.close();
+        // Unblock a sender waiting for space so the thread sees the close
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = Result<CapturedFrame, CaptureError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<CapturedFrame, CaptureError>>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        if self.cancelled.as_mut().poll(cx).is_ready() {
+            self.finish();
+            return Poll::Ready(None);
+        }
+
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(frame)) => Poll::Ready(Some(Ok(frame))),
+            Poll::Ready(None) => {
+                // The capture thread stores its error before dropping the
+                // sender, so it's there by the time the channel closes
+                self.finished = true;
+                let error = self.error.lock().unwrap().take();
+                Poll::Ready(error.map(Err))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.stop();
+        // The source may be blocked inside next_frame (e.g. pacing), so don't
+        // wait for the thread; it exits on its own after that frame
+        self.thread.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc as std_mpsc;
+    use std::time::Duration;
+
+    use crate::{TestPatternConfig, TestPatternSource};
+
+    /// Source that yields one frame per message and blocks in between, like
+    /// a capture handler waiting for the next screen update
+    struct MockSource {
+        frames: std_mpsc::Receiver<Result<u64, CaptureError>>,
+    }
+
+    impl FrameSource for MockSource {
+        fn next_frame(&mut self) -> Result<CapturedFrame, CaptureError> {
+            let frame_number = self
+                .frames
+                .recv()
+                .map_err(|_| CaptureError::CaptureFailed("handler gone".to_string()))??;
+            Ok(CapturedFrame {
+                frame_number,
+                pts_us: 0,
+                width: 2,
+                height: 2,
+                stride: 8,
+                data: vec![0; 16].into(),
+            })
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (2, 2)
+        }
+
+        fn fps(&self) -> u32 {
+            60
+        }
+    }
+
+    fn mock_stream() -> (FrameStream, std_mpsc::Sender<Result<u64, CaptureError>>) {
+        let (sender, frames) = std_mpsc::channel();
+        let stream = FrameStream::spawn(MockSource { frames }, 2, CancellationToken::new());
+        (stream, sender)
+    }
+
+    #[tokio::test]
+    async fn test_frames_in_order() {
+        let (mut stream, handler) = mock_stream();
+        for n in 0..5 {
+            handler.send(Ok(n)).unwrap();
+        }
+
+        for n in 0..5 {
+            assert_eq!(stream.next_frame().await.unwrap().unwrap().frame_number, n);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_unblocks_waiting_consumer() {
+        let (mut stream, _handler) = mock_stream();
+        let token = stream.shutdown_token();
+
+        let consumer = tokio::spawn(async move { stream.next_frame().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!consumer.is_finished());
+
+        token.cancel();
+        let frame = tokio::time::timeout(Duration::from_millis(100), consumer)
+            .await
+            .expect("consumer still blocked after cancel")
+            .unwrap();
+        assert!(frame.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stop_discards_buffered_frames() {
+        let (mut stream, handler) = mock_stream();
+        handler.send(Ok(0)).unwrap();
+        handler.send(Ok(1)).unwrap();
+        assert_eq!(stream.next_frame().await.unwrap().unwrap().frame_number, 0);
+
+        stream.stop();
+        assert!(stream.next_frame().await.unwrap().is_none());
+        assert!(stream.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_source_error_ends_stream() {
+        let (mut stream, handler) = mock_stream();
+        handler.send(Ok(0)).unwrap();
+        handler
+            .send(Err(CaptureError::CaptureFailed("lost display".to_string())))
+            .unwrap();
+
+        assert!(stream.next_frame().await.unwrap().is_some());
+        assert!(matches!(
+            stream.next_frame().await,
+            Err(CaptureError::CaptureFailed(_))
+        ));
+        // Reported once, then the stream has ended
+        assert!(stream.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stopped_source_reports_why() {
+        // Like ScreenCaptureKit stopping the stream when the display goes
+        // away: no more frames, and the consumer must not just wait forever
+        let (mut stream, handler) = mock_stream();
+        handler
+            .send(Err(CaptureError::Stopped(
+                "display disconnected".to_string(),
+            )))
+            .unwrap();
+
+        let result = tokio::time::timeout(Duration::from_millis(100), stream.next_frame())
+            .await
+            .expect("consumer still waiting after the source stopped");
+        assert!(matches!(result, Err(CaptureError::Stopped(_))));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_hides_source_error() {
+        // Errors after a deliberate stop are noise, not failures
+        let (mut stream, handler) = mock_stream();
+        stream.stop();
+        handler
+            .send(Err(CaptureError::Stopped("stream stopped".to_string())))
+            .unwrap();
+        assert!(stream.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_paced_test_pattern_stops_promptly() {
+        // 1 fps, so the capture thread spends nearly all its time sleeping
+        let source = TestPatternSource::new(TestPatternConfig {
+            width: 640,
+            height: 360,
+            fps: 1,
+            paced: true,
+        })
+        .unwrap();
+        let token = CancellationToken::new();
+        let mut stream = source.into_stream(token.clone());
+
+        assert_eq!(stream.next_frame().await.unwrap().unwrap().frame_number, 0);
+        let consumer = tokio::spawn(async move { stream.next_frame().await });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        token.cancel();
+        let frame = tokio::time::timeout(Duration::from_millis(100), consumer)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(frame.unwrap().is_none());
+    }
+}
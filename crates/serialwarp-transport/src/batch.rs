@@ -0,0 +1,462 @@
+//! Coalescing small packets into fewer, larger transfers
+
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use serialwarp_core::{PacketDecoder, TransportError};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::{Transport, TransportStats};
+
+/// Default longest a packet waits for others to share its transfer
+pub const DEFAULT_BATCH_DELAY: Duration = Duration::from_micros(500);
+
+/// Default size at which a batch is sent without waiting
+pub const DEFAULT_BATCH_BYTES: usize = 16 * 1024;
+
+/// When `BatchingTransport` sends a batch
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// Longest the first packet in a batch waits before it is sent
+    pub max_delay: Duration,
+    /// A batch is sent as soon as it reaches this size, and packets at
+    /// least this large are sent on their own
+    pub max_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_delay: DEFAULT_BATCH_DELAY,
+            max_bytes: DEFAULT_BATCH_BYTES,
+        }
+    }
+}
+
+/// Wraps a transport so small packets sent close together go out as one
+/// transfer, instead of paying for a bulk transaction each.
+///
+/// `send` returns once a packet is in the batch; the batch is written when
+/// it fills, when `max_delay` has passed since its first packet, or on
+/// `flush`. An error writing a batch at its deadline is returned by the
+/// next `send` or `flush`. Latency-sensitive packets can be followed by a
+/// `flush`, which `PrioritizedTransport` does for every control packet.
+///
+/// Received transfers are split back into single packets with a
+/// `PacketDecoder`, so both ends of a link can batch. Whatever is still
+/// batched when the transport is dropped is lost; `close` flushes first.
+///
+/// Must be created within a tokio runtime.
+pub struct BatchingTransport<T: Transport> {
+    shared: Arc<Shared<T>>,
+    decoder: Mutex<PacketDecoder>,
+    flusher: JoinHandle<()>,
+}
+
+struct Shared<T> {
+    inner: T,
+    config: BatchConfig,
+    batch: StdMutex<Batch>,
+    /// Held while a batch is taken and written, so batches go out in order
+    write: Mutex<()>,
+    /// Wakes the flusher when a batch is started
+    started: Notify,
+}
+
+#[derive(Default)]
+struct Batch {
+    buffer: BytesMut,
+    /// When the current batch must be sent; `None` while it is empty
+    deadline: Option<Instant>,
+    /// Error from writing a batch at its deadline, for the caller to see
+    error: Option<TransportError>,
+}
+
+impl<T: Transport + 'static> BatchingTransport<T> {
+    pub fn new(inner: T, config: BatchConfig) -> Self {
+        let shared = Arc::new(Shared {
+            inner,
+            config,
+            batch: StdMutex::new(Batch::default()),
+            write: Mutex::new(()),
+            started: Notify::new(),
+        });
+        let flusher = tokio::spawn(flush_loop(Arc::clone(&shared)));
+
+        Self {
+            shared,
+            decoder: Mutex::new(PacketDecoder::new()),
+            flusher,
+        }
+    }
+}
+
+impl<T: Transport> BatchingTransport<T> {
+    /// The wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.shared.inner
+    }
+
+    /// Bytes waiting in the current batch
+    pub fn pending(&self) -> usize {
+        self.shared.batch.lock().unwrap().buffer.len()
+    }
+}
+
+impl<T: Transport> Drop for BatchingTransport<T> {
+    fn drop(&mut self) {
+        self.flusher.abort();
+    }
+}
+
+impl<T: Transport> Shared<T> {
+    fn take_error(&self) -> Result<(), TransportError> {
+        match self.batch.lock().unwrap().error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the current batch, then `large` as a transfer of its own
+    async fn write_out(&self, large: Option<&[Bytes]>) -> Result<(), TransportError> {
+        let _write = self.write.lock().await;
+        let pending = {
+            let mut batch = self.batch.lock().unwrap();
+            batch.deadline = None;
+            batch.buffer.split().freeze()
+        };
+        if !pending.is_empty() {
+            self.inner.send(pending).await?;
+        }
+        if let Some(parts) = large {
+            self.inner.send_vectored(parts).await?;
+        }
+        Ok(())
+    }
+
+    /// Write the current batch if its deadline has passed, which it may
+    /// not have if it was flushed and another started meanwhile
+    async fn write_out_due(&self) {
+        let _write = self.write.lock().await;
+        let pending = {
+            let mut batch = self.batch.lock().unwrap();
+            if !batch
+                .deadline
+                .is_some_and(|deadline| deadline <= Instant::now())
+            {
+                return;
+            }
+            batch.deadline = None;
+            batch.buffer.split().freeze()
+        };
+        if let Err(e) = self.inner.send(pending).await {
+            self.batch.lock().unwrap().error = Some(e);
+        }
+    }
+}
+
+/// Write each batch once its deadline passes
+async fn flush_loop<T: Transport>(shared: Arc<Shared<T>>) {
+    loop {
+        let deadline = shared.batch.lock().unwrap().deadline;
+        match deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => shared.write_out_due().await,
+                    _ = shared.started.notified() => {}
+                }
+            }
+            None => shared.started.notified().await,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + 'static> Transport for BatchingTransport<T> {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        self.send_vectored(std::slice::from_ref(&data)).await
+    }
+
+    /// Parts are copied straight into the batch; a packet large enough to
+    /// go on its own is handed to the wrapped transport still in parts
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        let shared = &self.shared;
+        if !shared.inner.is_connected() {
+            return Err(TransportError::Disconnected);
+        }
+        shared.take_error()?;
+
+        let len: usize = parts.iter().map(Bytes::len).sum();
+        let max_bytes = shared.config.max_bytes;
+        if len >= max_bytes {
+            return shared.write_out(Some(parts)).await;
+        }
+        if self.pending() + len > max_bytes {
+            shared.write_out(None).await?;
+        }
+
+        let full = {
+            let mut batch = shared.batch.lock().unwrap();
+            for part in parts {
+                batch.buffer.extend_from_slice(part);
+            }
+            if batch.deadline.is_none() {
+                batch.deadline = Some(Instant::now() + shared.config.max_delay);
+                shared.started.notify_one();
+            }
+            batch.buffer.len() >= max_bytes
+        };
+        if full {
+            shared.write_out(None).await?;
+        }
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        let mut decoder = self.decoder.lock().await;
+        loop {
+            if let Some(packet) = next_packet(&mut decoder)? {
+                return Ok(packet);
+            }
+            let data = self.shared.inner.recv().await?;
+            decoder.push(&data);
+        }
+    }
+
+    async fn try_recv(&self) -> Result<Option<Bytes>, TransportError> {
+        let mut decoder = self.decoder.lock().await;
+        loop {
+            if let Some(packet) = next_packet(&mut decoder)? {
+                return Ok(Some(packet));
+            }
+            match self.shared.inner.try_recv().await? {
+                Some(data) => decoder.push(&data),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    async fn flush(&self) -> Result<(), TransportError> {
+        self.shared.take_error()?;
+        self.shared.write_out(None).await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.shared.inner.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.shared.inner.stats()
+    }
+
+    async fn close(&self) {
+        if self.shared.inner.is_connected() {
+            let _ = self.flush().await;
+        }
+        self.shared.inner.close().await;
+    }
+}
+
+fn next_packet(decoder: &mut PacketDecoder) -> Result<Option<Bytes>, TransportError> {
+    decoder
+        .next_packet_bytes()
+        .map_err(|e| TransportError::IoError(format!("invalid packet stream: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockTransport, PrioritizedTransport};
+    use serialwarp_core::{FrameAckPayload, Packet, PacketType, PingPayload};
+
+    const LONG_DELAY: Duration = Duration::from_secs(60);
+
+    fn ack(frame_number: u64) -> Bytes {
+        let payload = FrameAckPayload::new(frame_number, 0, 1);
+        Packet::new(
+            PacketType::FrameAck,
+            0,
+            frame_number as u32,
+            payload.to_bytes(),
+        )
+        .to_bytes()
+    }
+
+    fn ping() -> Bytes {
+        Packet::new(PacketType::Ping, 0, 0, PingPayload::new(1).to_bytes()).to_bytes()
+    }
+
+    fn decode_all(data: &[u8]) -> Vec<Packet> {
+        let mut decoder = PacketDecoder::new();
+        decoder.push(data);
+        let mut packets = Vec::new();
+        while let Some(packet) = decoder.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        assert_eq!(decoder.buffered(), 0);
+        packets
+    }
+
+    fn batching(
+        inner: MockTransport,
+        max_delay: Duration,
+        max_bytes: usize,
+    ) -> BatchingTransport<MockTransport> {
+        BatchingTransport::new(
+            inner,
+            BatchConfig {
+                max_delay,
+                max_bytes,
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_sends_one_transfer() {
+        let (inner, peer) = MockTransport::pair();
+        let transport = batching(inner, LONG_DELAY, DEFAULT_BATCH_BYTES);
+
+        for frame_number in 0..3 {
+            transport.send(ack(frame_number)).await.unwrap();
+        }
+        assert!(peer.try_recv().await.unwrap().is_none());
+        assert_eq!(transport.pending(), 3 * ack(0).len());
+
+        transport.flush().await.unwrap();
+        let transfer = peer.try_recv().await.unwrap().unwrap();
+        assert!(peer.try_recv().await.unwrap().is_none());
+
+        let packets = decode_all(&transfer);
+        let numbers: Vec<u32> = packets.iter().map(|p| p.sequence()).collect();
+        assert_eq!(numbers, vec![0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_vectored_sends_match_joined() {
+        let max_bytes = 4 * ack(0).len();
+        let (joined_inner, joined_peer) = MockTransport::pair();
+        let (vectored_inner, vectored_peer) = MockTransport::pair();
+        let joined = batching(joined_inner, LONG_DELAY, max_bytes);
+        let vectored = batching(vectored_inner, LONG_DELAY, max_bytes);
+
+        // Small packets batched, and one large enough to go on its own
+        let large = Packet::new(PacketType::Frame, 0, 9, vec![5; max_bytes].into());
+        let packets = [ack(0), ack(1), large.to_bytes(), ack(2)];
+        for packet in &packets {
+            joined.send(packet.clone()).await.unwrap();
+            let parts = [packet.slice(..3), packet.slice(3..20), packet.slice(20..)];
+            vectored.send_vectored(&parts).await.unwrap();
+        }
+        joined.flush().await.unwrap();
+        vectored.flush().await.unwrap();
+
+        let mut transfers = 0;
+        while let Some(transfer) = joined_peer.try_recv().await.unwrap() {
+            assert_eq!(vectored_peer.try_recv().await.unwrap(), Some(transfer));
+            transfers += 1;
+        }
+        assert_eq!(transfers, 3);
+        assert!(vectored_peer.try_recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_default_vectored_send_joins_parts() {
+        let (transport, peer) = MockTransport::pair();
+        let packet = ack(4);
+        let parts = [packet.slice(..10), packet.slice(10..10), packet.slice(10..)];
+        transport.send_vectored(&parts).await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), packet);
+        assert_eq!(transport.stats().bytes_sent, packet.len() as u64);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_sent_at_deadline() {
+        let delay = Duration::from_millis(2);
+        let (inner, peer) = MockTransport::pair();
+        let transport = batching(inner, delay, DEFAULT_BATCH_BYTES);
+
+        transport.send(ack(0)).await.unwrap();
+        tokio::time::sleep(delay / 2).await;
+        transport.send(ack(1)).await.unwrap();
+        assert!(peer.try_recv().await.unwrap().is_none());
+
+        // Measured from the first packet, not the latest
+        tokio::time::sleep(delay / 2).await;
+        tokio::task::yield_now().await;
+        let transfer = peer.try_recv().await.unwrap().unwrap();
+        assert_eq!(decode_all(&transfer).len(), 2);
+        assert_eq!(transport.pending(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_boundaries() {
+        let ack_len = ack(0).len();
+        let (inner, peer) = MockTransport::pair();
+        let transport = batching(inner, LONG_DELAY, 3 * ack_len);
+
+        // A full batch goes out at once
+        for frame_number in 0..4 {
+            transport.send(ack(frame_number)).await.unwrap();
+        }
+        let transfer = peer.try_recv().await.unwrap().unwrap();
+        assert_eq!(transfer.len(), 3 * ack_len);
+        assert_eq!(transport.pending(), ack_len);
+
+        // A packet that would overflow the batch starts the next one
+        let big_ping = Packet::new(PacketType::Ping, 0, 9, vec![0; 2 * ack_len].into());
+        transport.send(big_ping.to_bytes()).await.unwrap();
+        let transfer = peer.try_recv().await.unwrap().unwrap();
+        assert_eq!(transfer, ack(3));
+        assert_eq!(transport.pending(), big_ping.to_bytes().len());
+
+        // Large packets are sent on their own, after what was batched
+        let segment = Packet::new(PacketType::Frame, 0, 10, vec![0; 3 * ack_len].into());
+        transport.send(segment.to_bytes()).await.unwrap();
+        let first = peer.try_recv().await.unwrap().unwrap();
+        assert_eq!(decode_all(&first)[0].sequence(), 9);
+        let second = peer.try_recv().await.unwrap().unwrap();
+        assert_eq!(second, segment.to_bytes());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recv_splits_batches() {
+        let (a, b) = MockTransport::pair();
+        let sender = batching(a, LONG_DELAY, DEFAULT_BATCH_BYTES);
+        let receiver = batching(b, LONG_DELAY, DEFAULT_BATCH_BYTES);
+
+        for frame_number in 0..3 {
+            sender.send(ack(frame_number)).await.unwrap();
+        }
+        sender.flush().await.unwrap();
+
+        for frame_number in 0..3 {
+            assert_eq!(receiver.recv().await.unwrap(), ack(frame_number));
+        }
+        assert!(receiver.try_recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_control_packets_bypass_batching() {
+        let (inner, peer) = MockTransport::pair();
+        let transport = PrioritizedTransport::new(batching(inner, LONG_DELAY, DEFAULT_BATCH_BYTES));
+
+        transport.send(ping()).await.unwrap();
+        assert_eq!(peer.try_recv().await.unwrap(), Some(ping()));
+        assert_eq!(transport.inner().pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_close_flushes_batch() {
+        let (inner, _peer) = MockTransport::pair();
+        let transport = batching(inner, LONG_DELAY, DEFAULT_BATCH_BYTES);
+
+        transport.send(ack(0)).await.unwrap();
+        assert_eq!(transport.stats().bytes_sent, 0);
+        transport.close().await;
+        assert_eq!(transport.stats().bytes_sent, ack(0).len() as u64);
+    }
+}
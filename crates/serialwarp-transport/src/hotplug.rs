@@ -0,0 +1,78 @@
+//! Notifications of supported USB devices being plugged in and unplugged
+
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::pin::Pin;
+
+use futures_core::Stream;
+use nusb::hotplug::{HotplugEvent, HotplugWatch};
+use serialwarp_core::{DeviceRegistry, TransportError, UsbDeviceId};
+
+/// A supported device appearing or going away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Attached(UsbDeviceId),
+    Detached(UsbDeviceId),
+}
+
+/// Watches for the devices in a `DeviceRegistry` being attached and
+/// detached. Devices already attached when the watch starts are counted
+/// but not reported.
+pub struct DeviceWatch {
+    watch: HotplugWatch,
+    registry: DeviceRegistry,
+    /// Supported devices currently attached. Detach events only carry the
+    /// OS device ID, so this maps it back.
+    attached: HashMap<nusb::DeviceId, UsbDeviceId>,
+}
+
+impl DeviceWatch {
+    /// Start watching for the devices in `registry`
+    pub fn new(registry: DeviceRegistry) -> Result<Self, TransportError> {
+        // Watch first so nothing attached in between is missed
+        let watch = nusb::watch_devices().map_err(|e| TransportError::UsbError(e.to_string()))?;
+        let attached = nusb::list_devices()
+            .map_err(|e| TransportError::UsbError(e.to_string()))?
+            .filter_map(|info| {
+                let supported = registry.find(info.vendor_id(), info.product_id())?;
+                Some((info.id(), *supported))
+            })
+            .collect();
+
+        Ok(Self {
+            watch,
+            registry,
+            attached,
+        })
+    }
+
+    /// Whether any supported device is attached
+    pub fn is_attached(&self) -> bool {
+        !self.attached.is_empty()
+    }
+
+    /// Wait for the next supported device to be attached or detached.
+    /// `None` if the OS stops delivering events.
+    pub async fn next_event(&mut self) -> Option<DeviceEvent> {
+        loop {
+            let event = poll_fn(|cx| Pin::new(&mut self.watch).poll_next(cx)).await?;
+            match event {
+                HotplugEvent::Connected(info) => {
+                    let Some(&supported) = self.registry.find(info.vendor_id(), info.product_id())
+                    else {
+                        continue;
+                    };
+                    tracing::info!("{} attached", supported.name);
+                    self.attached.insert(info.id(), supported);
+                    return Some(DeviceEvent::Attached(supported));
+                }
+                HotplugEvent::Disconnected(id) => {
+                    if let Some(supported) = self.attached.remove(&id) {
+                        tracing::info!("{} detached", supported.name);
+                        return Some(DeviceEvent::Detached(supported));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -3,15 +3,41 @@
 //! This crate provides transport abstractions for sending and receiving
 //! data between source and sink applications.
 
+mod batch;
+mod hotplug;
 mod mock;
+mod priority;
+#[cfg(feature = "serial")]
+mod serial;
+mod split;
+mod stats;
+mod streams;
+mod tcp;
 mod usb;
 
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
 use bytes::Bytes;
 use serialwarp_core::TransportError;
+use tokio_util::sync::CancellationToken;
 
-pub use mock::MockTransport;
-pub use usb::UsbTransport;
+pub use batch::{BatchConfig, BatchingTransport, DEFAULT_BATCH_BYTES, DEFAULT_BATCH_DELAY};
+pub use hotplug::{DeviceEvent, DeviceWatch};
+pub use mock::{MockReceiver, MockSender, MockTransport};
+pub use priority::PrioritizedTransport;
+#[cfg(feature = "serial")]
+pub use serial::{serial_link_bitrate_bps, SerialTransport, DEFAULT_BAUD_RATE};
+pub use split::{split_shared, TransportHalves, TransportReceiver, TransportSender};
+pub use stats::{TransportCounters, TransportStats};
+pub use streams::split_streams;
+pub use tcp::TcpTransport;
+pub use usb::{
+    StaleDataFlush, UsbTransport, DEFAULT_FLUSH_IDLE_TIMEOUT, DEFAULT_FLUSH_MAX_BYTES,
+    DEFAULT_FLUSH_MAX_TIME, DEFAULT_RECV_TIMEOUT,
+};
 
 /// Transport trait for sending and receiving data
 #[async_trait]
@@ -19,12 +45,89 @@ pub trait Transport: Send + Sync {
     /// Send data to the remote endpoint
     async fn send(&self, data: Bytes) -> Result<(), TransportError>;
 
+    /// Send `parts` joined, as one `send` of them would, e.g. a packet
+    /// header, its payload and its CRC
+    ///
+    /// The default joins them into one buffer and sends that. Transports
+    /// that copy data on its way out anyway should copy the parts straight
+    /// from where they are instead.
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        self.send(join_parts(parts)).await
+    }
+
     /// Receive data from the remote endpoint
     async fn recv(&self) -> Result<Bytes, TransportError>;
 
+    /// Receive data, failing with `TransportError::Timeout` if none arrives
+    /// within `timeout`
+    ///
+    /// The default implementation wraps `recv` in a timeout, so it only loses
+    /// nothing if `recv` is cancel safe.
+    async fn recv_timeout(&self, timeout: Duration) -> Result<Bytes, TransportError> {
+        match tokio::time::timeout(timeout, self.recv()).await {
+            Ok(result) => result,
+            Err(_) => Err(TransportError::Timeout {
+                duration_ms: timeout.as_millis() as u64,
+            }),
+        }
+    }
+
+    /// Receive data that has already arrived, returning `Ok(None)` rather
+    /// than waiting when nothing is pending
+    async fn try_recv(&self) -> Result<Option<Bytes>, TransportError> {
+        match self.recv_timeout(Duration::ZERO).await {
+            Ok(data) => Ok(Some(data)),
+            Err(TransportError::Timeout { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write out anything the transport is holding back, e.g. a partly
+    /// filled batch. Transports that send immediately need do nothing.
+    async fn flush(&self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
     /// Check if the transport is still connected
     fn is_connected(&self) -> bool;
 
+    /// Bytes moved and errors seen so far
+    fn stats(&self) -> TransportStats;
+
     /// Close the transport
     async fn close(&self);
+
+    /// Split into send and receive halves that can be owned by different
+    /// tasks. Closing either half closes the whole connection.
+    ///
+    /// The default shares the transport between the halves through an
+    /// `Arc`, which is enough when sending and receiving don't contend.
+    fn split(self) -> TransportHalves
+    where
+        Self: Sized + 'static,
+    {
+        split_shared(Arc::new(self))
+    }
+}
+
+/// `parts` joined into one buffer, copying only if there's more than one
+pub(crate) fn join_parts(parts: &[Bytes]) -> Bytes {
+    match parts {
+        [] => Bytes::new(),
+        [part] => part.clone(),
+        parts => parts.concat().into(),
+    }
+}
+
+/// Run `operation` unless `closed` is cancelled first, in which case the
+/// operation is dropped, aborting it, and `Disconnected` is returned
+pub(crate) async fn until_closed<F: Future>(
+    closed: &CancellationToken,
+    operation: F,
+) -> Result<F::Output, TransportError> {
+    tokio::select! {
+        biased;
+        _ = closed.cancelled() => Err(TransportError::Disconnected),
+        output = operation => Ok(output),
+    }
 }
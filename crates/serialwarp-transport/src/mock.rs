@@ -1,20 +1,101 @@
 //! Mock transport for testing
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use serialwarp_core::TransportError;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
-use crate::Transport;
+use crate::{
+    until_closed, Transport, TransportCounters, TransportHalves, TransportReceiver,
+    TransportSender, TransportStats,
+};
 
 /// A mock transport for testing that connects two endpoints via channels
 pub struct MockTransport {
-    sender: mpsc::Sender<Bytes>,
-    receiver: tokio::sync::Mutex<mpsc::Receiver<Bytes>>,
+    sender: MockSender,
+    receiver: tokio::sync::Mutex<MockReceiver>,
+}
+
+/// State shared by both halves of one end, and the connection state by
+/// both ends
+#[derive(Clone)]
+struct Link {
     connected: Arc<AtomicBool>,
+    /// Cancelled on close to wake pending operations
+    closed: CancellationToken,
+    counters: Arc<TransportCounters>,
+}
+
+impl Link {
+    fn check_connected(&self) -> Result<(), TransportError> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(TransportError::Disconnected)
+        }
+    }
+
+    fn close(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.closed.cancel();
+    }
+}
+
+/// Resolution of tokio's timers
+const TIMER_TICK: Duration = Duration::from_millis(1);
+
+/// A link of limited speed in one direction: each send takes as long as
+/// its bytes would on the wire, after the ones before it
+struct Bandwidth {
+    bytes_per_second: u64,
+    /// When the sends so far are through
+    free_at: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            free_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Queue `len` bytes, returning when they're through
+    fn reserve(&self, len: usize) -> Instant {
+        let duration = Duration::from_secs_f64(len as f64 / self.bytes_per_second as f64);
+        let now = Instant::now();
+        let mut free_at = self.free_at.lock().unwrap();
+        // Timers fire on millisecond ticks, so the last send may have woken
+        // up to a tick after it was through; the link carries on from when
+        // it was, unless it has since gone idle
+        let start = if now > *free_at + TIMER_TICK {
+            now
+        } else {
+            *free_at
+        };
+        *free_at = start + duration;
+        *free_at
+    }
+}
+
+/// Sending half of a split `MockTransport`
+pub struct MockSender {
+    sender: mpsc::Sender<Bytes>,
+    link: Link,
+    /// `None` delivers sends at once
+    bandwidth: Option<Bandwidth>,
+}
+
+/// Receiving half of a split `MockTransport`
+pub struct MockReceiver {
+    receiver: mpsc::Receiver<Bytes>,
+    link: Link,
 }
 
 impl MockTransport {
@@ -22,60 +103,186 @@ impl MockTransport {
     ///
     /// Data sent on one transport will be received by the other.
     pub fn pair() -> (Self, Self) {
+        Self::pair_with(None)
+    }
+
+    /// Create a connected pair whose sends each way are delivered no faster
+    /// than `bytes_per_second`, as over a real cable
+    pub fn pair_with_bandwidth(bytes_per_second: u64) -> (Self, Self) {
+        Self::pair_with(Some(bytes_per_second))
+    }
+
+    fn pair_with(bytes_per_second: Option<u64>) -> (Self, Self) {
         let (tx1, rx1) = mpsc::channel(64);
         let (tx2, rx2) = mpsc::channel(64);
         let connected = Arc::new(AtomicBool::new(true));
+        let closed = CancellationToken::new();
 
-        let transport1 = MockTransport {
-            sender: tx1,
-            receiver: tokio::sync::Mutex::new(rx2),
-            connected: Arc::clone(&connected),
-        };
+        let bandwidth = || bytes_per_second.map(Bandwidth::new);
+        let transport1 = MockTransport::new(
+            tx1,
+            rx2,
+            Arc::clone(&connected),
+            closed.clone(),
+            bandwidth(),
+        );
+        let transport2 = MockTransport::new(tx2, rx1, connected, closed, bandwidth());
 
-        let transport2 = MockTransport {
-            sender: tx2,
-            receiver: tokio::sync::Mutex::new(rx1),
+        (transport1, transport2)
+    }
+
+    fn new(
+        sender: mpsc::Sender<Bytes>,
+        receiver: mpsc::Receiver<Bytes>,
+        connected: Arc<AtomicBool>,
+        closed: CancellationToken,
+        bandwidth: Option<Bandwidth>,
+    ) -> Self {
+        let link = Link {
             connected,
+            closed,
+            counters: Arc::new(TransportCounters::new()),
         };
+        Self {
+            sender: MockSender {
+                sender,
+                link: link.clone(),
+                bandwidth,
+            },
+            receiver: tokio::sync::Mutex::new(MockReceiver { receiver, link }),
+        }
+    }
+}
 
-        (transport1, transport2)
+impl MockReceiver {
+    /// Count a receive; `None` means the peer's channel is gone
+    fn received(&self, data: Option<Bytes>) -> Result<Bytes, TransportError> {
+        match data {
+            Some(data) => {
+                self.link.counters.record_recv(data.len());
+                Ok(data)
+            }
+            None => {
+                self.link.counters.record_recv_error();
+                Err(TransportError::ChannelClosed)
+            }
+        }
     }
 }
 
 #[async_trait]
-impl Transport for MockTransport {
+impl TransportSender for MockSender {
     async fn send(&self, data: Bytes) -> Result<(), TransportError> {
-        if !self.connected.load(Ordering::SeqCst) {
-            return Err(TransportError::Disconnected);
+        self.link.check_connected()?;
+
+        let len = data.len();
+        if let Some(bandwidth) = &self.bandwidth {
+            let through = bandwidth.reserve(len);
+            until_closed(&self.link.closed, tokio::time::sleep_until(through)).await?;
+        }
+        match until_closed(&self.link.closed, self.sender.send(data)).await? {
+            Ok(()) => {
+                self.link.counters.record_send(len);
+                Ok(())
+            }
+            Err(_) => {
+                self.link.counters.record_send_error();
+                Err(TransportError::ChannelClosed)
+            }
         }
+    }
 
-        self.sender
-            .send(data)
-            .await
-            .map_err(|_| TransportError::ChannelClosed)
+    fn is_connected(&self) -> bool {
+        self.link.connected.load(Ordering::SeqCst)
     }
 
-    async fn recv(&self) -> Result<Bytes, TransportError> {
-        if !self.connected.load(Ordering::SeqCst) {
-            return Err(TransportError::Disconnected);
+    fn stats(&self) -> TransportStats {
+        self.link.counters.snapshot()
+    }
+
+    async fn close(&self) {
+        self.link.close();
+    }
+}
+
+#[async_trait]
+impl TransportReceiver for MockReceiver {
+    async fn recv(&mut self) -> Result<Bytes, TransportError> {
+        self.link.check_connected()?;
+
+        let received = until_closed(&self.link.closed, self.receiver.recv()).await?;
+        self.received(received)
+    }
+
+    async fn try_recv(&mut self) -> Result<Option<Bytes>, TransportError> {
+        self.link.check_connected()?;
+
+        match self.receiver.try_recv() {
+            Ok(data) => self.received(Some(data)).map(Some),
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => self.received(None).map(Some),
         }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.link.connected.load(Ordering::SeqCst)
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.link.counters.snapshot()
+    }
+
+    async fn close(&self) {
+        self.link.close();
+    }
+}
 
-        let mut receiver = self.receiver.lock().await;
-        receiver.recv().await.ok_or(TransportError::ChannelClosed)
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        self.sender.send(data).await
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.sender.link.check_connected()?;
+
+        // Wait for the lock under the close signal too, in case another
+        // receive holds it
+        let mut receiver = until_closed(&self.sender.link.closed, self.receiver.lock()).await?;
+        receiver.recv().await
+    }
+
+    async fn try_recv(&self) -> Result<Option<Bytes>, TransportError> {
+        self.sender.link.check_connected()?;
+
+        // Another receive is already waiting on the channel
+        let Ok(mut receiver) = self.receiver.try_lock() else {
+            return Ok(None);
+        };
+        receiver.try_recv().await
     }
 
     fn is_connected(&self) -> bool {
-        self.connected.load(Ordering::SeqCst)
+        self.sender.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.sender.stats()
     }
 
     async fn close(&self) {
-        self.connected.store(false, Ordering::SeqCst);
+        self.sender.link.close();
+    }
+
+    fn split(self) -> TransportHalves {
+        (Box::new(self.sender), Box::new(self.receiver.into_inner()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::{Duration, Instant};
 
     #[tokio::test]
     async fn test_pair_communication() {
@@ -126,6 +333,226 @@ mod tests {
         assert!(matches!(result, Err(TransportError::Disconnected)));
     }
 
+    #[tokio::test]
+    async fn test_close_wakes_pending_recv() {
+        let (transport1, transport2) = MockTransport::pair();
+        let transport1 = Arc::new(transport1);
+
+        let receiver = Arc::clone(&transport1);
+        let pending = tokio::spawn(async move { receiver.recv().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!pending.is_finished());
+
+        transport2.close().await;
+
+        let result = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .expect("recv still pending after close")
+            .unwrap();
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+        assert!(matches!(
+            transport1.recv().await,
+            Err(TransportError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recv_timeout() {
+        let (transport1, transport2) = MockTransport::pair();
+        let timeout = Duration::from_millis(20);
+
+        let start = Instant::now();
+        let result = transport2.recv_timeout(timeout).await;
+        assert!(matches!(
+            result,
+            Err(TransportError::Timeout { duration_ms: 20 })
+        ));
+        assert!(start.elapsed() >= timeout);
+
+        transport1.send(Bytes::from_static(b"ready")).await.unwrap();
+        let start = Instant::now();
+        let received = transport2.recv_timeout(Duration::from_secs(5)).await;
+        assert_eq!(received.unwrap(), Bytes::from_static(b"ready"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_try_recv() {
+        let (transport1, transport2) = MockTransport::pair();
+
+        assert_eq!(transport2.try_recv().await.unwrap(), None);
+
+        transport1.send(Bytes::from_static(b"one")).await.unwrap();
+        transport1.send(Bytes::from_static(b"two")).await.unwrap();
+        assert_eq!(
+            transport2.try_recv().await.unwrap(),
+            Some(Bytes::from_static(b"one"))
+        );
+        assert_eq!(
+            transport2.try_recv().await.unwrap(),
+            Some(Bytes::from_static(b"two"))
+        );
+        assert_eq!(transport2.try_recv().await.unwrap(), None);
+
+        transport1.close().await;
+        assert!(matches!(
+            transport2.try_recv().await,
+            Err(TransportError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let (transport1, transport2) = MockTransport::pair();
+        assert_eq!(transport1.stats(), TransportStats::default());
+
+        transport1
+            .send(Bytes::from_static(b"twelve bytes"))
+            .await
+            .unwrap();
+        transport1.send(Bytes::from_static(b"four")).await.unwrap();
+        transport2.recv().await.unwrap();
+        transport2.try_recv().await.unwrap();
+        transport2.send(Bytes::from_static(b"ack")).await.unwrap();
+        transport1.recv().await.unwrap();
+
+        let stats1 = transport1.stats();
+        assert_eq!(stats1.bytes_sent, 16);
+        assert_eq!(stats1.bytes_received, 3);
+        let stats2 = transport2.stats();
+        assert_eq!(stats2.bytes_sent, 3);
+        assert_eq!(stats2.bytes_received, 16);
+        assert!(stats1.last_activity.is_some());
+        assert!(stats2.last_activity.is_some());
+
+        // Timeouts are not errors; a vanished peer is
+        transport1
+            .recv_timeout(Duration::from_millis(1))
+            .await
+            .unwrap_err();
+        assert_eq!(transport1.stats().recv_errors, 0);
+        drop(transport2);
+        transport1.recv().await.unwrap_err();
+        transport1
+            .send(Bytes::from_static(b"lost"))
+            .await
+            .unwrap_err();
+        let stats1 = transport1.stats();
+        assert_eq!((stats1.send_errors, stats1.recv_errors), (1, 1));
+        assert_eq!(stats1.bytes_sent, 16);
+    }
+
+    #[tokio::test]
+    async fn test_split_full_duplex() {
+        const COUNT: usize = 500;
+        let (transport1, transport2) = MockTransport::pair();
+        let (sender1, mut receiver1) = transport1.split();
+        let (sender2, mut receiver2) = transport2.split();
+
+        // A receive waiting with nothing to read doesn't hold up sending
+        // from the same end
+        let pending = tokio::spawn(async move {
+            let first = receiver1.recv().await.unwrap();
+            (first, receiver1)
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        sender1
+            .send(Bytes::from_static(b"while waiting"))
+            .await
+            .unwrap();
+        assert_eq!(
+            receiver2.recv().await.unwrap(),
+            Bytes::from_static(b"while waiting")
+        );
+        sender2.send(Bytes::from_static(b"wake")).await.unwrap();
+        let (first, mut receiver1) = pending.await.unwrap();
+        assert_eq!(first, Bytes::from_static(b"wake"));
+
+        // Both directions stream at once, each half in its own task
+        let send1 = tokio::spawn(async move {
+            for i in 0..COUNT {
+                sender1.send(Bytes::from(format!("1:{}", i))).await.unwrap();
+            }
+        });
+        let send2 = tokio::spawn(async move {
+            for i in 0..COUNT {
+                sender2.send(Bytes::from(format!("2:{}", i))).await.unwrap();
+            }
+        });
+        let recv1 = tokio::spawn(async move {
+            for i in 0..COUNT {
+                let data = receiver1.recv().await.unwrap();
+                assert_eq!(data, Bytes::from(format!("2:{}", i)));
+            }
+        });
+        let recv2 = tokio::spawn(async move {
+            for i in 0..COUNT {
+                let data = receiver2.recv().await.unwrap();
+                assert_eq!(data, Bytes::from(format!("1:{}", i)));
+            }
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            for task in [send1, send2, recv1, recv2] {
+                task.await.unwrap();
+            }
+        })
+        .await
+        .expect("full-duplex traffic stalled");
+    }
+
+    #[tokio::test]
+    async fn test_closing_half_closes_connection() {
+        let (transport1, transport2) = MockTransport::pair();
+        let (sender1, mut receiver1) = transport1.split();
+
+        let pending = tokio::spawn(async move { receiver1.recv().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        sender1.close().await;
+
+        let result = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .expect("recv still pending after close")
+            .unwrap();
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+        assert!(!sender1.is_connected());
+        assert!(!transport2.is_connected());
+
+        let (transport1, _transport2) = MockTransport::pair();
+        let (sender1, receiver1) = transport1.split();
+        receiver1.close().await;
+        assert!(matches!(
+            sender1.send(Bytes::from_static(b"late")).await,
+            Err(TransportError::Disconnected)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bandwidth_limits_delivery() {
+        // 1 MB/s each way: 10 KB sends take 10ms apiece, back to back
+        let (transport1, transport2) = MockTransport::pair_with_bandwidth(1_000_000);
+        let start = tokio::time::Instant::now();
+        let sending = tokio::spawn(async move {
+            for _ in 0..5 {
+                transport1.send(Bytes::from(vec![0; 10_000])).await.unwrap();
+            }
+            transport1
+        });
+        for i in 1..=5 {
+            transport2.recv().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_millis(10 * i));
+        }
+        let transport1 = sending.await.unwrap();
+
+        // The other direction has a link of its own, and an idle link
+        // doesn't bank time for later
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let start = tokio::time::Instant::now();
+        transport2.send(Bytes::from(vec![0; 5_000])).await.unwrap();
+        transport1.recv().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(5));
+    }
+
     #[tokio::test]
     async fn test_multiple_messages() {
         let (transport1, transport2) = MockTransport::pair();
@@ -0,0 +1,248 @@
+//! Control packets ahead of frame data on a shared link
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serialwarp_core::{PacketHeader, TransportError};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::{Transport, TransportStats};
+
+/// A packet waiting for the writer task, and where to report how its send
+/// went
+struct Queued {
+    data: Bytes,
+    done: oneshot::Sender<Result<(), TransportError>>,
+}
+
+/// Wraps a transport so control packets (see `PacketType::is_control`) are
+/// sent before any queued FRAME segments.
+///
+/// Sends go through two queues drained by one writer task, which always
+/// takes a waiting control packet first and has at most one bulk packet in
+/// flight, so a PING or STOP waits for one segment at most. `send` still
+/// resolves once the packet has been written, with the inner transport's
+/// result. Data that doesn't parse as a packet header is treated as bulk.
+/// Each control packet is followed by a `flush` of the inner transport, so
+/// a `BatchingTransport` underneath sends it without waiting out its
+/// deadline.
+///
+/// Must be created within a tokio runtime.
+pub struct PrioritizedTransport<T: Transport> {
+    inner: Arc<T>,
+    control: mpsc::UnboundedSender<Queued>,
+    bulk: mpsc::UnboundedSender<Queued>,
+    writer: JoinHandle<()>,
+}
+
+impl<T: Transport + 'static> PrioritizedTransport<T> {
+    pub fn new(inner: T) -> Self {
+        let inner = Arc::new(inner);
+        let (control, control_rx) = mpsc::unbounded_channel();
+        let (bulk, bulk_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_loop(Arc::clone(&inner), control_rx, bulk_rx));
+
+        Self {
+            inner,
+            control,
+            bulk,
+            writer,
+        }
+    }
+}
+
+impl<T: Transport> PrioritizedTransport<T> {
+    /// The wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Transport> Drop for PrioritizedTransport<T> {
+    fn drop(&mut self) {
+        self.writer.abort();
+    }
+}
+
+/// Send queued packets, control first, one at a time
+async fn write_loop<T: Transport + ?Sized>(
+    inner: Arc<T>,
+    mut control: mpsc::UnboundedReceiver<Queued>,
+    mut bulk: mpsc::UnboundedReceiver<Queued>,
+) {
+    loop {
+        let queued = tokio::select! {
+            biased;
+            Some(queued) = control.recv() => queued,
+            Some(queued) = bulk.recv() => queued,
+            else => return,
+        };
+        let control = is_control(&queued.data);
+        let mut result = inner.send(queued.data).await;
+        if control && result.is_ok() {
+            // Don't let a batching transport underneath hold it back
+            result = inner.flush().await;
+        }
+        // The sender may have given up waiting
+        let _ = queued.done.send(result);
+    }
+}
+
+fn is_control(data: &[u8]) -> bool {
+    PacketHeader::parse(data).is_ok_and(|header| header.packet_type.is_control())
+}
+
+#[async_trait]
+impl<T: Transport + 'static> Transport for PrioritizedTransport<T> {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        if !self.inner.is_connected() {
+            return Err(TransportError::Disconnected);
+        }
+
+        let queue = if is_control(&data) {
+            &self.control
+        } else {
+            &self.bulk
+        };
+        let (done, result) = oneshot::channel();
+        queue
+            .send(Queued { data, done })
+            .map_err(|_| TransportError::Disconnected)?;
+        result.await.unwrap_or(Err(TransportError::Disconnected))
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.inner.recv().await
+    }
+
+    async fn flush(&self) -> Result<(), TransportError> {
+        self.inner.flush().await
+    }
+
+    async fn recv_timeout(&self, timeout: Duration) -> Result<Bytes, TransportError> {
+        self.inner.recv_timeout(timeout).await
+    }
+
+    async fn try_recv(&self) -> Result<Option<Bytes>, TransportError> {
+        self.inner.try_recv().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.inner.stats()
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockTransport;
+    use serialwarp_core::{FrameHeader, Packet, PacketType, PingPayload};
+    use tokio::sync::Semaphore;
+
+    /// Holds each send until the test lets it through
+    struct GatedTransport {
+        inner: MockTransport,
+        gate: Arc<Semaphore>,
+    }
+
+    #[async_trait]
+    impl Transport for GatedTransport {
+        async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+            self.gate.acquire().await.unwrap().forget();
+            self.inner.send(data).await
+        }
+
+        async fn recv(&self) -> Result<Bytes, TransportError> {
+            self.inner.recv().await
+        }
+
+        fn is_connected(&self) -> bool {
+            self.inner.is_connected()
+        }
+
+        fn stats(&self) -> TransportStats {
+            self.inner.stats()
+        }
+
+        async fn close(&self) {
+            self.inner.close().await
+        }
+    }
+
+    fn segment(frame_number: u64) -> Bytes {
+        let header = FrameHeader::new(frame_number, 0, 0, 1024, 0, 1);
+        let mut payload = header.to_bytes().to_vec();
+        payload.resize(FrameHeader::SIZE + 1024, 0);
+        Packet::new(PacketType::Frame, 0, 0, payload.into()).to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_ping_overtakes_frame_backlog() {
+        const SEGMENTS: u64 = 20;
+        let (inner, peer) = MockTransport::pair();
+        let gate = Arc::new(Semaphore::new(0));
+        let transport = Arc::new(PrioritizedTransport::new(GatedTransport {
+            inner,
+            gate: Arc::clone(&gate),
+        }));
+
+        let mut sends = Vec::new();
+        for frame_number in 0..SEGMENTS {
+            let transport = Arc::clone(&transport);
+            sends.push(tokio::spawn(async move {
+                transport.send(segment(frame_number)).await
+            }));
+        }
+        // The writer is stuck on the first segment, with the rest queued
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let ping = Packet::new(PacketType::Ping, 0, 0, PingPayload::new(42).to_bytes());
+        let ping_transport = Arc::clone(&transport);
+        sends.push(tokio::spawn(async move {
+            ping_transport.send(ping.to_bytes()).await
+        }));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        gate.add_permits(SEGMENTS as usize + 1);
+        for send in sends {
+            send.await.unwrap().unwrap();
+        }
+
+        let mut types = Vec::new();
+        for _ in 0..=SEGMENTS {
+            let (packet, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+            types.push(packet.packet_type());
+        }
+        // Only the segment already in flight goes before the PING
+        assert_eq!(types[0], PacketType::Frame);
+        assert_eq!(types[1], PacketType::Ping);
+        assert!(types[2..].iter().all(|&t| t == PacketType::Frame));
+    }
+
+    #[tokio::test]
+    async fn test_send_reports_inner_result() {
+        let (inner, peer) = MockTransport::pair();
+        let transport = PrioritizedTransport::new(inner);
+
+        transport.send(segment(0)).await.unwrap();
+        assert!(peer.recv().await.is_ok());
+        assert_eq!(transport.stats().bytes_sent, segment(0).len() as u64);
+
+        transport.close().await;
+        assert!(matches!(
+            transport.send(segment(1)).await,
+            Err(TransportError::Disconnected)
+        ));
+    }
+}
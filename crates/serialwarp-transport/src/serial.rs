@@ -0,0 +1,546 @@
+//! Serial port transport for UART and CDC-ACM links
+//!
+//! Packets are COBS encoded and each is followed by a zero byte, so the
+//! receiver can find packet boundaries in the byte stream and resync after
+//! line noise by skipping to the next zero.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use serialwarp_core::{TransportError, CRC_SIZE, HEADER_SIZE, MAX_SEGMENT_SIZE};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{until_closed, Transport, TransportCounters, TransportStats};
+
+/// Baud rate used unless another is given
+pub const DEFAULT_BAUD_RATE: u32 = 3_000_000;
+
+/// Times a port that failed with EIO is reopened before giving up
+const REOPEN_ATTEMPTS: u32 = 5;
+
+/// Wait before each reopen, giving the adapter time to re-enumerate
+const REOPEN_DELAY: Duration = Duration::from_millis(200);
+
+/// Bytes read from the port at a time
+const READ_SIZE: usize = 4096;
+
+/// Largest encoded packet accepted; longer runs without a delimiter are
+/// treated as noise and dropped
+const MAX_ENCODED_SIZE: usize = 2 * (HEADER_SIZE + MAX_SEGMENT_SIZE + CRC_SIZE);
+
+/// `EIO`, which a USB-serial adapter returns once it is unplugged or reset
+const EIO: i32 = 5;
+
+/// Payload bits per second a serial link carries at `baud_rate`, with one
+/// start and one stop bit per byte (8N1).
+///
+/// A source streaming over serial must keep its bitrate well under this,
+/// which at the usual 3 Mbaud or less means a small, low frame rate stream.
+pub fn serial_link_bitrate_bps(baud_rate: u32) -> u32 {
+    baud_rate / 10 * 8
+}
+
+/// Transport over a serial port, such as a USB-serial adapter driving a
+/// small status display.
+///
+/// A port opened by path is reopened if it fails with EIO, as happens when
+/// the adapter is reset or replugged; the operation that saw the error is
+/// retried once, or fails with `Disconnected` if the port doesn't come back.
+pub struct SerialTransport {
+    /// Path to reopen the port at; `None` for ports not opened by path
+    path: Option<String>,
+    baud_rate: u32,
+    reader: Mutex<Reader>,
+    writer: Mutex<WriteHalf<SerialStream>>,
+    /// Bumped each time the port is reopened
+    generation: AtomicU64,
+    /// Held while reopening, so only one task does it
+    reopen: Mutex<()>,
+    connected: AtomicBool,
+    /// Cancelled on close to wake pending operations
+    closed: CancellationToken,
+    counters: TransportCounters,
+}
+
+struct Reader {
+    port: ReadHalf<SerialStream>,
+    /// Bytes read past the last delimiter
+    buffer: BytesMut,
+}
+
+/// How an operation on the port went when it didn't succeed
+enum PortError {
+    /// The port failed in a way reopening may fix
+    Lost,
+    Failed(TransportError),
+}
+
+impl SerialTransport {
+    /// Open the serial port at `path`, e.g. `/dev/ttyUSB0` or `COM3`
+    pub fn open(path: &str, baud_rate: u32) -> Result<Self, TransportError> {
+        let stream = open_port(path, baud_rate)?;
+        tracing::info!("Opened serial port {} at {} baud", path, baud_rate);
+
+        let mut transport = Self::from_stream(stream);
+        transport.path = Some(path.to_string());
+        transport.baud_rate = baud_rate;
+        Ok(transport)
+    }
+
+    /// Use an already open port. It is not reopened after EIO, as there is
+    /// no path to reopen it at.
+    pub fn from_stream(stream: SerialStream) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            path: None,
+            baud_rate: DEFAULT_BAUD_RATE,
+            reader: Mutex::new(Reader {
+                port: reader,
+                buffer: BytesMut::with_capacity(READ_SIZE),
+            }),
+            writer: Mutex::new(writer),
+            generation: AtomicU64::new(0),
+            reopen: Mutex::new(()),
+            connected: AtomicBool::new(true),
+            closed: CancellationToken::new(),
+            counters: TransportCounters::new(),
+        }
+    }
+
+    /// Baud rate the port was opened at
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    fn check_connected(&self) -> Result<(), TransportError> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(TransportError::Disconnected)
+        }
+    }
+
+    fn disconnect(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.closed.cancel();
+    }
+
+    async fn write_frame(&self, frame: &[u8]) -> Result<(), PortError> {
+        let mut writer = self.writer.lock().await;
+        let result = until_closed(&self.closed, async {
+            writer.write_all(frame).await?;
+            writer.flush().await
+        })
+        .await
+        .map_err(PortError::Failed)?;
+        result.map_err(port_error)
+    }
+
+    /// Read until a whole packet has arrived
+    async fn read_packet(&self) -> Result<Bytes, PortError> {
+        let mut reader = self.reader.lock().await;
+        loop {
+            if let Some(packet) = self.take_packet(&mut reader.buffer) {
+                return Ok(packet);
+            }
+
+            let Reader { port, buffer } = &mut *reader;
+            buffer.reserve(READ_SIZE);
+            match until_closed(&self.closed, port.read_buf(buffer)).await {
+                Ok(Ok(0)) => return Err(PortError::Lost),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(port_error(e)),
+                Err(e) => return Err(PortError::Failed(e)),
+            }
+        }
+    }
+
+    /// Take the first complete packet from `buffer`, skipping any that
+    /// don't decode
+    fn take_packet(&self, buffer: &mut BytesMut) -> Option<Bytes> {
+        while let Some(end) = buffer.iter().position(|&byte| byte == 0) {
+            let frame = buffer.split_to(end + 1);
+            if end == 0 {
+                continue;
+            }
+            match cobs_decode(&frame[..end]) {
+                Some(packet) => {
+                    self.counters.record_recv(packet.len());
+                    return Some(packet.into());
+                }
+                None => {
+                    tracing::warn!("Dropping undecodable serial frame ({} bytes)", end);
+                    self.counters.record_recv_error();
+                }
+            }
+        }
+
+        if buffer.len() > MAX_ENCODED_SIZE {
+            tracing::warn!("Dropping {} bytes without a frame delimiter", buffer.len());
+            self.counters.record_recv_error();
+            buffer.clear();
+        }
+        None
+    }
+
+    /// Reopen the port after it was lost at `generation`, unless another
+    /// task already has
+    async fn reopen(&self, generation: u64) -> Result<(), TransportError> {
+        let _reopen = self.reopen.lock().await;
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return Ok(());
+        }
+        let Some(path) = &self.path else {
+            self.disconnect();
+            return Err(TransportError::Disconnected);
+        };
+
+        for attempt in 1..=REOPEN_ATTEMPTS {
+            tokio::time::sleep(REOPEN_DELAY).await;
+            self.check_connected()?;
+
+            match open_port(path, self.baud_rate) {
+                Ok(stream) => {
+                    let (reader, writer) = tokio::io::split(stream);
+                    let mut current_writer = self.writer.lock().await;
+                    let mut current_reader = self.reader.lock().await;
+                    *current_writer = writer;
+                    current_reader.port = reader;
+                    current_reader.buffer.clear();
+                    self.generation.fetch_add(1, Ordering::SeqCst);
+                    tracing::info!("Reopened serial port {}", path);
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Reopening serial port {} failed (attempt {}/{}): {}",
+                        path,
+                        attempt,
+                        REOPEN_ATTEMPTS,
+                        e
+                    );
+                }
+            }
+        }
+
+        self.disconnect();
+        Err(TransportError::Disconnected)
+    }
+}
+
+#[async_trait]
+impl Transport for SerialTransport {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        self.send_vectored(std::slice::from_ref(&data)).await
+    }
+
+    /// The parts are COBS encoded one after another into the frame, so
+    /// they're only copied the once encoding takes anyway
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        self.check_connected()?;
+
+        let len: usize = parts.iter().map(Bytes::len).sum();
+        let mut frame = BytesMut::with_capacity(len + len / 254 + 2);
+        cobs_encode(parts.iter().map(|part| &part[..]), &mut frame);
+
+        for retried in [false, true] {
+            let generation = self.generation.load(Ordering::SeqCst);
+            match self.write_frame(&frame).await {
+                Ok(()) => {
+                    self.counters.record_send(len);
+                    return Ok(());
+                }
+                Err(PortError::Lost) if !retried => self.reopen(generation).await?,
+                Err(PortError::Lost) => break,
+                Err(PortError::Failed(e)) => {
+                    if !matches!(e, TransportError::Disconnected) {
+                        self.counters.record_send_error();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        self.counters.record_send_error();
+        self.disconnect();
+        Err(TransportError::Disconnected)
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        loop {
+            self.check_connected()?;
+
+            let generation = self.generation.load(Ordering::SeqCst);
+            match self.read_packet().await {
+                Ok(packet) => return Ok(packet),
+                Err(PortError::Lost) => {
+                    self.counters.record_recv_error();
+                    self.reopen(generation).await?;
+                }
+                Err(PortError::Failed(e)) => {
+                    if !matches!(e, TransportError::Disconnected) {
+                        self.counters.record_recv_error();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    async fn close(&self) {
+        self.disconnect();
+    }
+}
+
+fn open_port(path: &str, baud_rate: u32) -> Result<SerialStream, TransportError> {
+    tokio_serial::new(path, baud_rate)
+        .open_native_async()
+        .map_err(|e| match e.kind {
+            tokio_serial::ErrorKind::NoDevice => TransportError::DeviceNotFound,
+            _ => TransportError::IoError(e.to_string()),
+        })
+}
+
+fn port_error(e: io::Error) -> PortError {
+    if e.raw_os_error() == Some(EIO) {
+        PortError::Lost
+    } else {
+        PortError::Failed(TransportError::IoError(e.to_string()))
+    }
+}
+
+/// COBS encode `data`, the slices taken as one, into `out`, followed by the
+/// zero delimiter
+fn cobs_encode<'a>(data: impl IntoIterator<Item = &'a [u8]>, out: &mut BytesMut) {
+    let mut code_index = out.len();
+    out.put_u8(0);
+    let mut code = 1u8;
+
+    for &byte in data.into_iter().flatten() {
+        if byte != 0 {
+            out.put_u8(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_index] = code;
+            code_index = out.len();
+            out.put_u8(0);
+            code = 1;
+        }
+    }
+
+    out[code_index] = code;
+    out.put_u8(0);
+}
+
+/// Decode one COBS frame, without its delimiter
+fn cobs_decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(frame.len());
+    let mut i = 0;
+
+    while i < frame.len() {
+        let code = frame[i] as usize;
+        if code == 0 || i + code > frame.len() {
+            return None;
+        }
+        out.extend_from_slice(&frame[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < frame.len() {
+            out.push(0);
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::{Packet, PacketType, PingPayload};
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        cobs_encode([data], &mut out);
+        out.to_vec()
+    }
+
+    #[test]
+    fn test_cobs_known_vectors() {
+        assert_eq!(encode(&[]), vec![0x01, 0x00]);
+        assert_eq!(encode(&[0x00]), vec![0x01, 0x01, 0x00]);
+        assert_eq!(
+            encode(&[0x11, 0x22, 0x00, 0x33]),
+            vec![0x03, 0x11, 0x22, 0x02, 0x33, 0x00]
+        );
+        assert_eq!(
+            encode(&[0x11, 0x00, 0x00, 0x00]),
+            vec![0x02, 0x11, 0x01, 0x01, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_cobs_roundtrip() {
+        let long_run: Vec<u8> = (0..1000).map(|i| (i % 255 + 1) as u8).collect();
+        let mixed: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        for data in [vec![], vec![0; 10], long_run, mixed] {
+            let encoded = encode(&data);
+            let (&delimiter, body) = encoded.split_last().unwrap();
+            assert_eq!(delimiter, 0);
+            assert!(!body.contains(&0));
+            assert_eq!(cobs_decode(body).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_cobs_parts_encode_as_joined() {
+        // Runs of non-zero bytes longer than a COBS block, split across parts
+        let data: Vec<u8> = (0..1000).map(|i| (i % 300 % 256) as u8).collect();
+        for split in [0, 1, 253, 254, 255, 600, 1000] {
+            let (first, rest) = data.split_at(split);
+            let mut out = BytesMut::new();
+            cobs_encode(
+                [first, &rest[..rest.len() / 2], &rest[rest.len() / 2..]],
+                &mut out,
+            );
+            assert_eq!(out, encode(&data), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_cobs_rejects_truncated_frame() {
+        assert!(cobs_decode(&[0x05, 0x11, 0x22]).is_none());
+        assert!(cobs_decode(&[0x02, 0x11, 0x00]).is_none());
+    }
+
+    #[test]
+    fn test_link_bitrate() {
+        assert_eq!(serial_link_bitrate_bps(3_000_000), 2_400_000);
+        assert_eq!(serial_link_bitrate_bps(115_200), 92_160);
+    }
+
+    #[cfg(unix)]
+    fn pty_pair() -> (SerialTransport, SerialTransport) {
+        let (a, b) = SerialStream::pair().unwrap();
+        (
+            SerialTransport::from_stream(a),
+            SerialTransport::from_stream(b),
+        )
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pty_loopback() {
+        let (a, b) = pty_pair();
+
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(0).to_bytes());
+        let segment = Packet::new(PacketType::Frame, 0, 2, vec![0; 20_000].into());
+        let packets = [ping.to_bytes(), segment.to_bytes(), ping.to_bytes()];
+
+        // The segment is larger than the pty buffers, so send while receiving
+        let to_send = packets.clone();
+        let sender = tokio::spawn(async move {
+            for data in to_send {
+                a.send(data).await.unwrap();
+            }
+            a
+        });
+        for expected in &packets {
+            let data = b.recv_timeout(Duration::from_secs(5)).await.unwrap();
+            assert_eq!(&data, expected);
+        }
+        sender.await.unwrap();
+
+        let stats = b.stats();
+        assert_eq!(
+            stats.bytes_received,
+            (2 * ping.to_bytes().len() + segment.to_bytes().len()) as u64
+        );
+        assert_eq!(stats.recv_errors, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pty_full_duplex() {
+        let (a, b) = pty_pair();
+        let b = std::sync::Arc::new(b);
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(0).to_bytes());
+
+        // A pending receive doesn't hold up a send on the same transport
+        let receiver = std::sync::Arc::clone(&b);
+        let pending = tokio::spawn(async move { receiver.recv().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        b.send(ping.to_bytes()).await.unwrap();
+
+        let echoed = a.recv_timeout(Duration::from_secs(5)).await.unwrap();
+        a.send(echoed).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), pending)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.unwrap(), ping.to_bytes());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resyncs_after_noise() {
+        let (a, b) = pty_pair();
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(0).to_bytes());
+
+        // Garbage that isn't valid COBS, then a good packet
+        {
+            let mut writer = a.writer.lock().await;
+            writer.write_all(&[0x09, 0x01, 0x00]).await.unwrap();
+        }
+        a.send(ping.to_bytes()).await.unwrap();
+
+        let data = b.recv_timeout(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(data, ping.to_bytes());
+        assert_eq!(b.stats().recv_errors, 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_close_wakes_pending_recv() {
+        let (a, _b) = pty_pair();
+        let a = std::sync::Arc::new(a);
+
+        let receiver = std::sync::Arc::clone(&a);
+        let pending = tokio::spawn(async move { receiver.recv().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        a.close().await;
+
+        let result = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+        assert!(!a.is_connected());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_lost_port_without_path_disconnects() {
+        let (a, b) = pty_pair();
+        drop(a);
+
+        // Reading a pty whose other end is gone fails with EIO, and a port
+        // that wasn't opened by path can't be reopened
+        let result = b.recv_timeout(Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+        assert!(!b.is_connected());
+    }
+}
@@ -0,0 +1,144 @@
+//! Independently owned send and receive halves of a transport
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serialwarp_core::TransportError;
+
+use crate::{join_parts, Transport, TransportStats};
+
+/// Sending and receiving halves of a split transport
+pub type TransportHalves = (Box<dyn TransportSender>, Box<dyn TransportReceiver>);
+
+/// Sending half of a split transport
+#[async_trait]
+pub trait TransportSender: Send + Sync {
+    /// Send data to the remote endpoint
+    async fn send(&self, data: Bytes) -> Result<(), TransportError>;
+
+    /// Send `parts` joined, see `Transport::send_vectored`
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        self.send(join_parts(parts)).await
+    }
+
+    /// Write out anything held back, see `Transport::flush`
+    async fn flush(&self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    /// Check if the connection is still up
+    fn is_connected(&self) -> bool;
+
+    /// Counters for the whole connection
+    fn stats(&self) -> TransportStats;
+
+    /// Close the whole connection, including the receiving half
+    async fn close(&self);
+}
+
+/// Receiving half of a split transport
+#[async_trait]
+pub trait TransportReceiver: Send {
+    /// Receive data from the remote endpoint
+    async fn recv(&mut self) -> Result<Bytes, TransportError>;
+
+    /// Receive data, failing with `TransportError::Timeout` if none arrives
+    /// within `timeout`
+    async fn recv_timeout(&mut self, timeout: Duration) -> Result<Bytes, TransportError> {
+        match tokio::time::timeout(timeout, self.recv()).await {
+            Ok(result) => result,
+            Err(_) => Err(TransportError::Timeout {
+                duration_ms: timeout.as_millis() as u64,
+            }),
+        }
+    }
+
+    /// Receive data that has already arrived, returning `Ok(None)` rather
+    /// than waiting when nothing is pending
+    async fn try_recv(&mut self) -> Result<Option<Bytes>, TransportError> {
+        match self.recv_timeout(Duration::ZERO).await {
+            Ok(data) => Ok(Some(data)),
+            Err(TransportError::Timeout { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check if the connection is still up
+    fn is_connected(&self) -> bool;
+
+    /// Counters for the whole connection
+    fn stats(&self) -> TransportStats;
+
+    /// Close the whole connection, including the sending half
+    async fn close(&self);
+}
+
+/// Split a shared transport into halves that both refer to it. This suits
+/// transports whose two directions don't contend, and callers that still
+/// need the whole transport, e.g. to run the handshake again after a
+/// reconnect.
+pub fn split_shared<T: Transport + ?Sized + 'static>(transport: Arc<T>) -> TransportHalves {
+    (
+        Box::new(SharedHalf(Arc::clone(&transport))),
+        Box::new(SharedHalf(transport)),
+    )
+}
+
+/// One half of a transport shared through an `Arc`
+struct SharedHalf<T: ?Sized>(Arc<T>);
+
+#[async_trait]
+impl<T: Transport + ?Sized> TransportSender for SharedHalf<T> {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        self.0.send(data).await
+    }
+
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        self.0.send_vectored(parts).await
+    }
+
+    async fn flush(&self) -> Result<(), TransportError> {
+        self.0.flush().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.0.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.0.stats()
+    }
+
+    async fn close(&self) {
+        self.0.close().await
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> TransportReceiver for SharedHalf<T> {
+    async fn recv(&mut self) -> Result<Bytes, TransportError> {
+        self.0.recv().await
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Result<Bytes, TransportError> {
+        self.0.recv_timeout(timeout).await
+    }
+
+    async fn try_recv(&mut self) -> Result<Option<Bytes>, TransportError> {
+        self.0.try_recv().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.0.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.0.stats()
+    }
+
+    async fn close(&self) {
+        self.0.close().await
+    }
+}
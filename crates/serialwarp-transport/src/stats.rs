@@ -0,0 +1,86 @@
+//! Transport byte and error counters
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Snapshot of a transport's counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Sends that failed on the link; sends after close are not counted
+    pub send_errors: u64,
+    /// Receives that failed on the link; timeouts are not counted
+    pub recv_errors: u64,
+    /// When data last went either way
+    pub last_activity: Option<Instant>,
+}
+
+/// Counters a transport updates as it sends and receives
+#[derive(Debug)]
+pub struct TransportCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    send_errors: AtomicU64,
+    recv_errors: AtomicU64,
+    created: Instant,
+    /// Microseconds after `created` plus one; zero means no activity yet
+    last_activity_us: AtomicU64,
+}
+
+impl TransportCounters {
+    pub fn new() -> Self {
+        Self {
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+            recv_errors: AtomicU64::new(0),
+            created: Instant::now(),
+            last_activity_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_send(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn record_recv(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn record_send_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_recv_error(&self) {
+        self.recv_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TransportStats {
+        let last_activity_us = self.last_activity_us.load(Ordering::Relaxed);
+        TransportStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            recv_errors: self.recv_errors.load(Ordering::Relaxed),
+            last_activity: last_activity_us
+                .checked_sub(1)
+                .map(|us| self.created + Duration::from_micros(us)),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.created.elapsed().as_micros() as u64;
+        self.last_activity_us
+            .fetch_max(elapsed + 1, Ordering::Relaxed);
+    }
+}
+
+impl Default for TransportCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
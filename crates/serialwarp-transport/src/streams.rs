@@ -0,0 +1,284 @@
+//! Several streams over one link, told apart by the packet header's stream id
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serialwarp_core::{PacketHeader, TransportError, MAX_STREAMS};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::{TransportHalves, TransportReceiver, TransportSender, TransportStats};
+
+/// The link's sender, taken in turns by every stream
+struct SharedSender {
+    inner: Box<dyn TransportSender>,
+    /// Held for each send; tokio's mutex is fair, so streams waiting to
+    /// send go in the order they started waiting
+    turn: Mutex<()>,
+}
+
+/// Sending half of one stream from `split_streams`
+struct StreamSender(Arc<SharedSender>);
+
+/// Receiving half of one stream from `split_streams`
+struct StreamReceiver {
+    packets: mpsc::UnboundedReceiver<Bytes>,
+    sender: Arc<SharedSender>,
+    _router: Arc<Router>,
+}
+
+/// The task routing received packets; stops once every stream's receiver
+/// has dropped
+struct Router(JoinHandle<()>);
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Split one connection into transport halves for each of `stream_ids`, so
+/// each stream can run its own pipeline over the same link.
+///
+/// Received packets go to the stream their header's stream id names, see
+/// `Packet::with_stream_id`; packets for other streams, or that don't parse
+/// as a packet, are dropped. Nothing is sent on a stream's behalf, so its
+/// packets must already carry its id. Sends from every stream go through
+/// the one sender in turn, so streams sending flat out share the link's
+/// bandwidth a packet at a time. Streams aren't limited in how far they can
+/// fall behind in receiving; the credits each grants its peer bound that.
+///
+/// When the link fails every stream sees it disconnect. Closing any stream
+/// closes the whole link.
+///
+/// Must be called within a tokio runtime.
+pub fn split_streams(
+    (sender, receiver): TransportHalves,
+    stream_ids: &[u8],
+) -> Vec<TransportHalves> {
+    let sender = Arc::new(SharedSender {
+        inner: sender,
+        turn: Mutex::new(()),
+    });
+
+    let mut routes: Vec<Option<mpsc::UnboundedSender<Bytes>>> = vec![None; MAX_STREAMS];
+    let mut receivers = Vec::with_capacity(stream_ids.len());
+    for &stream_id in stream_ids {
+        assert!(
+            (stream_id as usize) < MAX_STREAMS,
+            "stream id {} out of range",
+            stream_id
+        );
+        assert!(
+            routes[stream_id as usize].is_none(),
+            "stream id {} given twice",
+            stream_id
+        );
+        let (tx, rx) = mpsc::unbounded_channel();
+        routes[stream_id as usize] = Some(tx);
+        receivers.push(rx);
+    }
+
+    let router = Arc::new(Router(tokio::spawn(route(receiver, routes))));
+    receivers
+        .into_iter()
+        .map(|packets| {
+            let halves: TransportHalves = (
+                Box::new(StreamSender(Arc::clone(&sender))),
+                Box::new(StreamReceiver {
+                    packets,
+                    sender: Arc::clone(&sender),
+                    _router: Arc::clone(&router),
+                }),
+            );
+            halves
+        })
+        .collect()
+}
+
+/// Hand each received packet to its stream until the link fails or no
+/// stream is listening
+async fn route(
+    mut receiver: Box<dyn TransportReceiver>,
+    mut routes: Vec<Option<mpsc::UnboundedSender<Bytes>>>,
+) {
+    loop {
+        let data = match receiver.recv().await {
+            Ok(data) => data,
+            // An idle link, not a failed one
+            Err(TransportError::Timeout { .. }) => continue,
+            Err(e) => {
+                warn!("Stream link failed: {}", e);
+                return;
+            }
+        };
+
+        let stream_id = match PacketHeader::parse(&data) {
+            Ok(header) => header.stream_id(),
+            Err(e) => {
+                warn!("Dropping malformed packet: {}", e);
+                continue;
+            }
+        };
+        let Some(route) = &routes[stream_id as usize] else {
+            debug!("Dropping packet for unknown stream {}", stream_id);
+            continue;
+        };
+        if route.send(data).is_err() {
+            routes[stream_id as usize] = None;
+            if routes.iter().all(Option::is_none) {
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransportSender for StreamSender {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        let _turn = self.0.turn.lock().await;
+        self.0.inner.send(data).await
+    }
+
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        let _turn = self.0.turn.lock().await;
+        self.0.inner.send_vectored(parts).await
+    }
+
+    async fn flush(&self) -> Result<(), TransportError> {
+        let _turn = self.0.turn.lock().await;
+        self.0.inner.flush().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.0.inner.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.0.inner.stats()
+    }
+
+    async fn close(&self) {
+        self.0.inner.close().await
+    }
+}
+
+#[async_trait]
+impl TransportReceiver for StreamReceiver {
+    async fn recv(&mut self) -> Result<Bytes, TransportError> {
+        self.packets
+            .recv()
+            .await
+            .ok_or(TransportError::Disconnected)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.sender.inner.is_connected()
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.sender.inner.stats()
+    }
+
+    async fn close(&self) {
+        self.sender.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use serialwarp_core::{Packet, PacketType};
+
+    use crate::{MockTransport, Transport};
+
+    fn packet(stream_id: u8, sequence: u32) -> Bytes {
+        Packet::new(PacketType::FrameAck, 0, sequence, Bytes::new())
+            .with_stream_id(stream_id)
+            .to_bytes()
+    }
+
+    fn parse(data: &Bytes) -> (u8, u32) {
+        let (packet, _) = Packet::parse(data).unwrap();
+        (packet.stream_id(), packet.sequence())
+    }
+
+    #[tokio::test]
+    async fn test_routes_by_stream_id() {
+        let (local, remote) = MockTransport::pair();
+        let mut streams = split_streams(local.split(), &[0, 3]);
+        let (_, mut three) = streams.pop().unwrap();
+        let (_, mut zero) = streams.pop().unwrap();
+
+        for (stream_id, sequence) in [(3, 0), (0, 1), (7, 2), (3, 3), (0, 4)] {
+            remote.send(packet(stream_id, sequence)).await.unwrap();
+        }
+        remote
+            .send(Bytes::from_static(b"not a packet"))
+            .await
+            .unwrap();
+
+        assert_eq!(parse(&zero.recv().await.unwrap()), (0, 1));
+        assert_eq!(parse(&zero.recv().await.unwrap()), (0, 4));
+        assert_eq!(parse(&three.recv().await.unwrap()), (3, 0));
+        assert_eq!(parse(&three.recv().await.unwrap()), (3, 3));
+        assert!(zero.try_recv().await.unwrap().is_none());
+        assert!(three.try_recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_senders_share_the_link() {
+        let (local, remote) = MockTransport::pair();
+        let streams = split_streams(local.split(), &[1, 2]);
+
+        let senders: Vec<_> = streams
+            .into_iter()
+            .enumerate()
+            .map(|(i, (sender, receiver))| {
+                tokio::spawn(async move {
+                    let _receiver = receiver;
+                    for sequence in 0..50 {
+                        sender.send(packet(i as u8 + 1, sequence)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.push(parse(&remote.recv().await.unwrap()));
+        }
+        for sender in senders {
+            sender.await.unwrap();
+        }
+
+        // Each stream's packets arrive in order
+        for stream_id in [1, 2] {
+            let sequences: Vec<u32> = received
+                .iter()
+                .filter(|(id, _)| *id == stream_id)
+                .map(|&(_, sequence)| sequence)
+                .collect();
+            assert_eq!(sequences, (0..50).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_failure_reaches_every_stream() {
+        let (local, remote) = MockTransport::pair();
+        let streams = split_streams(local.split(), &[0, 1]);
+
+        remote.close().await;
+        for (sender, mut receiver) in streams {
+            let result = tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+                .await
+                .expect("stream still waiting after the link closed");
+            assert!(result.is_err());
+            assert!(!sender.is_connected());
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! TCP transport, for a source on the network or trying things out without
+//! a device
+//!
+//! Each send goes out as its length, a little-endian u32, followed by the
+//! data, so a `recv` returns exactly what one `send` sent.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serialwarp_core::TransportError;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{until_closed, Transport, TransportCounters, TransportStats};
+
+/// Bytes of the length before each frame
+const LENGTH_SIZE: usize = 4;
+
+/// Bytes read from the socket at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// Longest frame accepted; a longer length means the peer isn't speaking
+/// this framing
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Transport over a TCP connection
+pub struct TcpTransport {
+    peer: SocketAddr,
+    reader: Mutex<Reader>,
+    writer: Mutex<OwnedWriteHalf>,
+    connected: AtomicBool,
+    /// Cancelled on close to wake pending operations
+    closed: CancellationToken,
+    counters: TransportCounters,
+}
+
+struct Reader {
+    socket: OwnedReadHalf,
+    /// Bytes read past the last whole frame
+    buffer: BytesMut,
+}
+
+impl TcpTransport {
+    /// Connect to a peer listening at `addr`
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, TransportError> {
+        let stream = TcpStream::connect(addr).await.map_err(io_error)?;
+        let transport = Self::from_stream(stream)?;
+        tracing::info!("Connected to {} over TCP", transport.peer);
+        Ok(transport)
+    }
+
+    /// Listen at `addr` until one peer connects
+    pub async fn listen(addr: impl ToSocketAddrs) -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(addr).await.map_err(io_error)?;
+        if let Ok(local) = listener.local_addr() {
+            tracing::info!("Listening for a TCP connection on {}", local);
+        }
+        Self::accept(&listener).await
+    }
+
+    /// Wait for the next peer to connect to `listener`
+    pub async fn accept(listener: &TcpListener) -> Result<Self, TransportError> {
+        let (stream, _) = listener.accept().await.map_err(io_error)?;
+        let transport = Self::from_stream(stream)?;
+        tracing::info!("Accepted a TCP connection from {}", transport.peer);
+        Ok(transport)
+    }
+
+    /// Use an already connected stream
+    pub fn from_stream(stream: TcpStream) -> Result<Self, TransportError> {
+        // Packets are sent whole, so there's nothing to gain from holding
+        // small ones back
+        stream.set_nodelay(true).map_err(io_error)?;
+        let peer = stream.peer_addr().map_err(io_error)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            peer,
+            reader: Mutex::new(Reader {
+                socket: reader,
+                buffer: BytesMut::with_capacity(READ_SIZE),
+            }),
+            writer: Mutex::new(writer),
+            connected: AtomicBool::new(true),
+            closed: CancellationToken::new(),
+            counters: TransportCounters::new(),
+        })
+    }
+
+    /// Address of the other end
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn check_connected(&self) -> Result<(), TransportError> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(TransportError::Disconnected)
+        }
+    }
+
+    fn disconnect(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.closed.cancel();
+    }
+
+    /// Take the first whole frame from `buffer`
+    fn take_frame(&self, buffer: &mut BytesMut) -> Result<Option<Bytes>, TransportError> {
+        if buffer.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(buffer[..LENGTH_SIZE].try_into().unwrap()) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(TransportError::IoError(format!(
+                "frame of {} bytes from {} is over the {} byte limit",
+                len, self.peer, MAX_FRAME_SIZE
+            )));
+        }
+        if buffer.len() < LENGTH_SIZE + len {
+            buffer.reserve(LENGTH_SIZE + len - buffer.len());
+            return Ok(None);
+        }
+
+        buffer.advance(LENGTH_SIZE);
+        let frame = buffer.split_to(len).freeze();
+        self.counters.record_recv(frame.len());
+        Ok(Some(frame))
+    }
+
+    /// The connection can't be used after an error; close it, counting the
+    /// error unless it was the close itself
+    fn fail(&self, error: TransportError, record: fn(&TransportCounters)) -> TransportError {
+        if !matches!(error, TransportError::Disconnected) {
+            record(&self.counters);
+        }
+        self.disconnect();
+        error
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn send(&self, data: Bytes) -> Result<(), TransportError> {
+        self.send_vectored(std::slice::from_ref(&data)).await
+    }
+
+    /// The length and parts are copied into one buffer and written at once
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        self.check_connected()?;
+
+        let len: usize = parts.iter().map(Bytes::len).sum();
+        let mut frame = BytesMut::with_capacity(LENGTH_SIZE + len);
+        frame.put_u32_le(len as u32);
+        for part in parts {
+            frame.extend_from_slice(part);
+        }
+
+        let mut writer = self.writer.lock().await;
+        match until_closed(&self.closed, writer.write_all(&frame)).await {
+            Ok(Ok(())) => {
+                self.counters.record_send(len);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(self.fail(io_error(e), TransportCounters::record_send_error)),
+            Err(e) => Err(self.fail(e, TransportCounters::record_send_error)),
+        }
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.check_connected()?;
+
+        let mut reader = self.reader.lock().await;
+        loop {
+            match self.take_frame(&mut reader.buffer) {
+                Ok(Some(frame)) => return Ok(frame),
+                Ok(None) => {}
+                Err(e) => return Err(self.fail(e, TransportCounters::record_recv_error)),
+            }
+
+            let Reader { socket, buffer } = &mut *reader;
+            buffer.reserve(READ_SIZE);
+            match until_closed(&self.closed, socket.read_buf(buffer)).await {
+                // The peer closed the connection
+                Ok(Ok(0)) => {
+                    self.disconnect();
+                    return Err(TransportError::Disconnected);
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    return Err(self.fail(io_error(e), TransportCounters::record_recv_error))
+                }
+                Err(e) => return Err(self.fail(e, TransportCounters::record_recv_error)),
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    async fn close(&self) {
+        self.disconnect();
+        let mut writer = self.writer.lock().await;
+        let _ = writer.shutdown().await;
+    }
+}
+
+fn io_error(e: io::Error) -> TransportError {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => TransportError::ConnectionRefused,
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => TransportError::Disconnected,
+        _ => TransportError::IoError(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialwarp_core::{Packet, PacketType, PingPayload};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn pair() -> (TcpTransport, TcpTransport) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (accepted, connected) =
+            tokio::join!(TcpTransport::accept(&listener), TcpTransport::connect(addr));
+        (accepted.unwrap(), connected.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let (a, b) = pair().await;
+        assert_eq!(b.peer_addr(), a.writer.lock().await.local_addr().unwrap());
+
+        let ping = Packet::new(PacketType::Ping, 0, 1, PingPayload::new(0).to_bytes());
+        let segment = Packet::new(PacketType::Frame, 0, 2, vec![0; 200_000].into());
+        let packets = [ping.to_bytes(), segment.to_bytes(), Bytes::new()];
+
+        let to_send = packets.clone();
+        let sender = tokio::spawn(async move {
+            for data in to_send {
+                a.send(data).await.unwrap();
+            }
+            a
+        });
+        for expected in &packets {
+            let data = b.recv_timeout(Duration::from_secs(5)).await.unwrap();
+            assert_eq!(&data, expected);
+        }
+        let a = sender.await.unwrap();
+
+        // Parts arrive joined
+        let data = segment.to_bytes();
+        let (first, rest) = data.split_at(10);
+        a.send_vectored(&[Bytes::copy_from_slice(first), Bytes::copy_from_slice(rest)])
+            .await
+            .unwrap();
+        assert_eq!(b.recv().await.unwrap(), segment.to_bytes());
+
+        let stats = b.stats();
+        assert_eq!(
+            stats.bytes_received,
+            (ping.to_bytes().len() + 2 * segment.to_bytes().len()) as u64
+        );
+        assert_eq!(stats.recv_errors, 0);
+    }
+
+    #[tokio::test]
+    async fn test_recv_timeout_loses_nothing() {
+        let (a, b) = pair().await;
+        let data = Bytes::from(vec![7; 100_000]);
+
+        // Time out part way through a frame, then read the rest of it
+        let mut writer = a.writer.lock().await;
+        writer
+            .write_all(&(data.len() as u32).to_le_bytes())
+            .await
+            .unwrap();
+        writer.write_all(&data[..50_000]).await.unwrap();
+        assert!(matches!(
+            b.recv_timeout(Duration::from_millis(50)).await,
+            Err(TransportError::Timeout { .. })
+        ));
+        writer.write_all(&data[50_000..]).await.unwrap();
+        drop(writer);
+        assert_eq!(b.recv_timeout(Duration::from_secs(5)).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_peer_close_disconnects() {
+        let (a, b) = pair().await;
+        a.close().await;
+        assert!(!a.is_connected());
+        assert!(matches!(
+            a.send(Bytes::new()).await,
+            Err(TransportError::Disconnected)
+        ));
+
+        let result = b.recv_timeout(Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+        assert!(!b.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_close_wakes_pending_recv() {
+        let (a, _b) = pair().await;
+        let a = Arc::new(a);
+
+        let receiver = Arc::clone(&a);
+        let pending = tokio::spawn(async move { receiver.recv().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        a.close().await;
+
+        let result = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_frame() {
+        let (a, b) = pair().await;
+        let mut writer = a.writer.lock().await;
+        writer.write_all(&u32::MAX.to_le_bytes()).await.unwrap();
+        drop(writer);
+
+        let result = b.recv_timeout(Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(TransportError::IoError(_))));
+        assert!(!b.is_connected());
+        assert_eq!(b.stats().recv_errors, 1);
+    }
+}
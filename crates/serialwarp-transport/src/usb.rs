@@ -1,17 +1,18 @@
 //! USB transport implementation using nusb
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use nusb::transfer::RequestBuffer;
+use nusb::transfer::{Completion, Queue, RequestBuffer};
 use nusb::Device;
-use serialwarp_core::{TransportError, SUPPORTED_USB_DEVICES};
+use serialwarp_core::{DeviceRegistry, TransportError, UsbDeviceId, EXTRA_DEVICES_ENV};
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 
-use crate::Transport;
+use crate::{until_closed, Transport, TransportCounters, TransportStats};
 
 /// USB OUT endpoint address
 const ENDPOINT_OUT: u8 = 0x01;
@@ -22,36 +23,233 @@ const ENDPOINT_IN: u8 = 0x81;
 /// Transfer buffer size (64KB)
 const TRANSFER_SIZE: usize = 65536;
 
-/// USB timeout in milliseconds
-const TIMEOUT_MS: u64 = 5000;
+/// Bulk IN transfers kept queued
+const IN_TRANSFERS: usize = 2;
+
+/// Timeout for `recv` unless another is given when opening
+pub const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default for `StaleDataFlush::idle_timeout`
+pub const DEFAULT_FLUSH_IDLE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Default for `StaleDataFlush::max_bytes`: a few keyframes' worth
+pub const DEFAULT_FLUSH_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Default for `StaleDataFlush::max_time`
+pub const DEFAULT_FLUSH_MAX_TIME: Duration = Duration::from_millis(500);
+
+/// EBUSY on Linux and macOS
+const EBUSY: i32 = 16;
+
+/// ERROR_BUSY on Windows
+const ERROR_BUSY: i32 = 170;
+
+/// Operating system families whose USB stacks fail in different ways
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Platform {
+    Linux,
+    Windows,
+    Other,
+}
+
+impl Platform {
+    fn current() -> Self {
+        if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else if cfg!(windows) {
+            Platform::Windows
+        } else {
+            Platform::Other
+        }
+    }
+}
+
+/// Turn an error opening or claiming `device` into one that says what went
+/// wrong: a missing udev rule on Linux, a missing WinUSB driver on Windows,
+/// or another program holding the device
+fn classify_open_error(
+    error: std::io::Error,
+    device: &UsbDeviceId,
+    platform: Platform,
+) -> TransportError {
+    use std::io::ErrorKind;
+
+    let busy = match platform {
+        Platform::Windows => ERROR_BUSY,
+        Platform::Linux | Platform::Other => EBUSY,
+    };
+    if error.raw_os_error() == Some(busy) {
+        return TransportError::DeviceBusy;
+    }
+
+    match (error.kind(), platform) {
+        // WinUSB handles are exclusive, so access is denied while another
+        // program has the device open
+        (ErrorKind::PermissionDenied, Platform::Windows) => TransportError::DeviceBusy,
+        (ErrorKind::PermissionDenied, Platform::Linux) => TransportError::PermissionDenied {
+            suggested_udev_rule: Some(device.udev_rule()),
+        },
+        (ErrorKind::PermissionDenied, Platform::Other) => TransportError::PermissionDenied {
+            suggested_udev_rule: None,
+        },
+        // nusb can only open devices bound to WinUSB
+        (ErrorKind::Unsupported | ErrorKind::NotFound, Platform::Windows) => {
+            TransportError::DriverMissing {
+                device: format!(
+                    "{} ({:04X}:{:04X})",
+                    device.name, device.vendor_id, device.product_id
+                ),
+            }
+        }
+        _ => TransportError::UsbError(error.to_string()),
+    }
+}
+
+/// How `UsbTransport` gets rid of data a previous session left in the
+/// cable's FIFOs when it opens. A session that ended uncleanly can leave
+/// frame segments queued on the IN endpoint, which the next session would
+/// otherwise read as its handshake.
+#[derive(Debug, Clone, Copy)]
+pub struct StaleDataFlush {
+    /// Whether to flush on opening at all
+    pub enabled: bool,
+    /// A read that returns nothing for this long means the IN endpoint is
+    /// empty
+    pub idle_timeout: Duration,
+    /// Most data discarded before giving up, for a source that is still
+    /// streaming into the cable
+    pub max_bytes: usize,
+    /// Longest the flush takes
+    pub max_time: Duration,
+    /// Zero-length writes sent after draining, for bridge chips that need
+    /// them to resynchronize; 0 sends none
+    pub resync_writes: usize,
+}
+
+impl Default for StaleDataFlush {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: DEFAULT_FLUSH_IDLE_TIMEOUT,
+            max_bytes: DEFAULT_FLUSH_MAX_BYTES,
+            max_time: DEFAULT_FLUSH_MAX_TIME,
+            resync_writes: 0,
+        }
+    }
+}
+
+/// Something stale data can be read from, one transfer at a time
+#[async_trait]
+trait StaleRead {
+    /// Length of the next transfer, or `None` if none arrives within
+    /// `timeout`
+    async fn read_stale(&mut self, timeout: Duration) -> Result<Option<usize>, TransportError>;
+}
+
+#[async_trait]
+impl StaleRead for Option<Queue<RequestBuffer>> {
+    async fn read_stale(&mut self, timeout: Duration) -> Result<Option<usize>, TransportError> {
+        let Ok(completion) =
+            tokio::time::timeout(timeout, UsbTransport::next_in_transfer(self)).await
+        else {
+            return Ok(None);
+        };
+        let completion = completion?;
+        completion
+            .status
+            .map(|()| Some(completion.data.len()))
+            .map_err(|e| TransportError::UsbError(e.to_string()))
+    }
+}
+
+/// Read and discard from `source` until a read comes back empty after
+/// `idle_timeout`, or the byte or time budget runs out. Returns the bytes
+/// discarded and whether the source was drained.
+async fn drain_stale(
+    source: &mut (dyn StaleRead + Send),
+    config: &StaleDataFlush,
+) -> Result<(usize, bool), TransportError> {
+    let deadline = tokio::time::Instant::now() + config.max_time;
+    let mut discarded = 0;
+    while discarded < config.max_bytes {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        match source
+            .read_stale(config.idle_timeout.min(remaining))
+            .await?
+        {
+            Some(len) => discarded += len,
+            None if remaining >= config.idle_timeout => return Ok((discarded, true)),
+            // Cut short by the deadline, so not known to be empty
+            None => break,
+        }
+    }
+    Ok((discarded, false))
+}
 
 /// USB transport for link cable communication
 pub struct UsbTransport {
-    interface: Arc<nusb::Interface>,
+    /// Claimed interface, taken on close so the claim is released
+    interface: StdMutex<Option<nusb::Interface>>,
+    /// Bulk IN transfers. They stay queued when a receive times out or is
+    /// dropped, so no data is lost between receives.
+    in_queue: Mutex<Option<Queue<RequestBuffer>>>,
     connected: Arc<AtomicBool>,
-    #[allow(dead_code)]
-    recv_buffer: Mutex<Vec<u8>>,
+    /// Cancelled on close to abort in-flight transfers
+    closed: CancellationToken,
+    /// Timeout for `recv`
+    default_timeout: Duration,
+    counters: TransportCounters,
 }
 
 impl UsbTransport {
-    /// Open a USB transport, auto-detecting the first supported link cable
+    /// Open a USB transport, auto-detecting the first supported link cable,
+    /// including any listed in `SERIALWARP_EXTRA_DEVICES`
     pub async fn open() -> Result<Self, TransportError> {
-        let device = Self::find_device()?;
-        Self::from_device(device).await
+        Self::open_with_recv_timeout(DEFAULT_RECV_TIMEOUT).await
+    }
+
+    /// Open a USB transport whose `recv` gives up after `recv_timeout`
+    pub async fn open_with_recv_timeout(recv_timeout: Duration) -> Result<Self, TransportError> {
+        let registry = DeviceRegistry::from_env()
+            .map_err(|e| TransportError::UsbError(format!("{}: {}", EXTRA_DEVICES_ENV, e)))?;
+        Self::open_from(&registry, recv_timeout).await
+    }
+
+    /// Open the first connected device in `registry`
+    pub async fn open_from(
+        registry: &DeviceRegistry,
+        recv_timeout: Duration,
+    ) -> Result<Self, TransportError> {
+        Self::open_with_flush(registry, recv_timeout, StaleDataFlush::default()).await
+    }
+
+    /// Open the first connected device in `registry`, flushing stale data
+    /// as `flush` says
+    pub async fn open_with_flush(
+        registry: &DeviceRegistry,
+        recv_timeout: Duration,
+        flush: StaleDataFlush,
+    ) -> Result<Self, TransportError> {
+        let (device, id) = Self::find_device(registry)?;
+        let transport = Self::from_device(device, &id, recv_timeout).await?;
+        if flush.enabled {
+            transport.flush_stale(&flush).await?;
+        }
+        Ok(transport)
     }
 
-    /// Find the first supported USB device
-    fn find_device() -> Result<Device, TransportError> {
+    /// Find and open the first registered USB device
+    fn find_device(registry: &DeviceRegistry) -> Result<(Device, UsbDeviceId), TransportError> {
         for device_info in
             nusb::list_devices().map_err(|e| TransportError::UsbError(e.to_string()))?
         {
             let vid = device_info.vendor_id();
             let pid = device_info.product_id();
 
-            if let Some(supported) = SUPPORTED_USB_DEVICES
-                .iter()
-                .find(|d| d.vendor_id == vid && d.product_id == pid)
-            {
+            if let Some(supported) = registry.find(vid, pid) {
                 tracing::info!(
                     "Found {} (VID: 0x{:04X}, PID: 0x{:04X})",
                     supported.name,
@@ -60,7 +258,8 @@ impl UsbTransport {
                 );
                 return device_info
                     .open()
-                    .map_err(|e| TransportError::UsbError(e.to_string()));
+                    .map(|device| (device, *supported))
+                    .map_err(|e| classify_open_error(e, supported, Platform::current()));
             }
         }
 
@@ -68,35 +267,158 @@ impl UsbTransport {
     }
 
     /// Create transport from an opened USB device
-    async fn from_device(device: Device) -> Result<Self, TransportError> {
+    async fn from_device(
+        device: Device,
+        id: &UsbDeviceId,
+        recv_timeout: Duration,
+    ) -> Result<Self, TransportError> {
         // Find the right interface with bulk endpoints
         // Link cables typically use interface 0
         let interface_num = 0;
 
         let interface = device
             .claim_interface(interface_num)
-            .map_err(|e| TransportError::UsbError(e.to_string()))?;
+            .map_err(|e| classify_open_error(e, id, Platform::current()))?;
+
+        let in_queue = interface.bulk_in_queue(ENDPOINT_IN);
 
         Ok(Self {
-            interface: Arc::new(interface),
+            interface: StdMutex::new(Some(interface)),
+            in_queue: Mutex::new(Some(in_queue)),
             connected: Arc::new(AtomicBool::new(true)),
-            recv_buffer: Mutex::new(Vec::with_capacity(TRANSFER_SIZE)),
+            closed: CancellationToken::new(),
+            default_timeout: recv_timeout,
+            counters: TransportCounters::new(),
         })
     }
+
+    /// Clear any halt on the bulk endpoints, then discard whatever is
+    /// waiting on the IN endpoint, returning how many bytes that was. Done
+    /// on opening unless disabled; call it again before a new session on
+    /// a transport that stays open.
+    pub async fn flush_stale(&self, config: &StaleDataFlush) -> Result<usize, TransportError> {
+        let interface = self.interface()?;
+        for endpoint in [ENDPOINT_IN, ENDPOINT_OUT] {
+            if let Err(e) = interface.clear_halt(endpoint) {
+                tracing::debug!("Failed to clear halt on endpoint 0x{:02X}: {}", endpoint, e);
+            }
+        }
+
+        let mut queue = self.in_queue.lock().await;
+        let (discarded, drained) =
+            until_closed(&self.closed, drain_stale(&mut *queue, config)).await??;
+        drop(queue);
+        if !drained {
+            tracing::warn!(
+                "Discarded {} bytes of stale data and more keeps coming",
+                discarded
+            );
+        } else if discarded > 0 {
+            tracing::info!("Discarded {} bytes of stale data", discarded);
+        }
+
+        for _ in 0..config.resync_writes {
+            let completion =
+                until_closed(&self.closed, interface.bulk_out(ENDPOINT_OUT, Vec::new())).await?;
+            if let Err(e) = completion.status {
+                return Err(TransportError::UsbError(e.to_string()));
+            }
+        }
+        Ok(discarded)
+    }
+
+    /// The claimed interface, or `Disconnected` once closed
+    fn interface(&self) -> Result<nusb::Interface, TransportError> {
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(TransportError::Disconnected);
+        }
+        self.interface
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or(TransportError::Disconnected)
+    }
+
+    /// Top up the IN queue and wait for its next transfer
+    async fn next_in_transfer(
+        queue: &mut Option<Queue<RequestBuffer>>,
+    ) -> Result<Completion<Vec<u8>>, TransportError> {
+        let queue = queue.as_mut().ok_or(TransportError::Disconnected)?;
+        while queue.pending() < IN_TRANSFERS {
+            queue.submit(RequestBuffer::new(TRANSFER_SIZE));
+        }
+        Ok(queue.next_complete().await)
+    }
+
+    /// Data from a completed IN transfer
+    fn received(&self, completion: Completion<Vec<u8>>) -> Result<Bytes, TransportError> {
+        match completion.status {
+            Ok(()) => {
+                self.counters.record_recv(completion.data.len());
+                Ok(Bytes::from(completion.data))
+            }
+            Err(e) => {
+                self.counters.record_recv_error();
+                self.connected.store(false, Ordering::SeqCst);
+                Err(TransportError::UsbError(e.to_string()))
+            }
+        }
+    }
+
+    /// Cancel and drop the IN queue
+    fn release_queue(queue: &mut Option<Queue<RequestBuffer>>) {
+        if let Some(mut queue) = queue.take() {
+            queue.cancel_all();
+        }
+    }
+
+    /// Abort in-flight transfers and release the interface. The claim is
+    /// released as soon as the aborted transfers and the IN queue have
+    /// dropped their handles, so the device can be reopened straight away.
+    fn shutdown(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.closed.cancel();
+        // Dropped here rather than when the transport itself is dropped
+        self.interface
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        self.shutdown();
+        Self::release_queue(self.in_queue.get_mut());
+    }
 }
 
 #[async_trait]
 impl Transport for UsbTransport {
     async fn send(&self, data: Bytes) -> Result<(), TransportError> {
-        if !self.connected.load(Ordering::SeqCst) {
-            return Err(TransportError::Disconnected);
-        }
+        self.send_vectored(std::slice::from_ref(&data)).await
+    }
 
-        let completion = self.interface.bulk_out(ENDPOINT_OUT, data.to_vec()).await;
+    /// A bulk transfer needs one owned buffer, so the parts are copied into
+    /// it, the same one copy a single buffer gets
+    async fn send_vectored(&self, parts: &[Bytes]) -> Result<(), TransportError> {
+        let interface = self.interface()?;
+
+        let len = parts.iter().map(Bytes::len).sum();
+        let mut buffer = Vec::with_capacity(len);
+        for part in parts {
+            buffer.extend_from_slice(part);
+        }
+        let completion =
+            until_closed(&self.closed, interface.bulk_out(ENDPOINT_OUT, buffer)).await?;
 
         match completion.status {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                self.counters.record_send(len);
+                Ok(())
+            }
             Err(e) => {
+                self.counters.record_send_error();
                 self.connected.store(false, Ordering::SeqCst);
                 Err(TransportError::UsbError(e.to_string()))
             }
@@ -104,29 +426,39 @@ impl Transport for UsbTransport {
     }
 
     async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.recv_timeout(self.default_timeout).await
+    }
+
+    async fn recv_timeout(&self, timeout: Duration) -> Result<Bytes, TransportError> {
         if !self.connected.load(Ordering::SeqCst) {
             return Err(TransportError::Disconnected);
         }
 
-        let request = RequestBuffer::new(TRANSFER_SIZE);
+        let receive = async {
+            let mut queue = self.in_queue.lock().await;
+            Self::next_in_transfer(&mut queue).await
+        };
+        let completion = until_closed(&self.closed, tokio::time::timeout(timeout, receive))
+            .await?
+            .map_err(|_| TransportError::Timeout {
+                duration_ms: timeout.as_millis() as u64,
+            })??;
 
-        let result = tokio::time::timeout(
-            Duration::from_millis(TIMEOUT_MS),
-            self.interface.bulk_in(ENDPOINT_IN, request),
-        )
-        .await;
+        self.received(completion)
+    }
 
-        match result {
-            Ok(completion) => match completion.status {
-                Ok(_) => Ok(Bytes::copy_from_slice(&completion.data)),
-                Err(e) => {
-                    self.connected.store(false, Ordering::SeqCst);
-                    Err(TransportError::UsbError(e.to_string()))
-                }
-            },
-            Err(_) => Err(TransportError::Timeout {
-                duration_ms: TIMEOUT_MS,
-            }),
+    async fn try_recv(&self) -> Result<Option<Bytes>, TransportError> {
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(TransportError::Disconnected);
+        }
+
+        // Another receive is already waiting on the queue
+        let Ok(mut queue) = self.in_queue.try_lock() else {
+            return Ok(None);
+        };
+        match tokio::time::timeout(Duration::ZERO, Self::next_in_transfer(&mut queue)).await {
+            Ok(completion) => self.received(completion?).map(Some),
+            Err(_) => Ok(None),
         }
     }
 
@@ -134,20 +466,203 @@ impl Transport for UsbTransport {
         self.connected.load(Ordering::SeqCst)
     }
 
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
     async fn close(&self) {
-        self.connected.store(false, Ordering::SeqCst);
+        self.shutdown();
+        Self::release_queue(&mut *self.in_queue.lock().await);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::VecDeque;
 
     #[test]
     fn test_supported_devices() {
-        assert_eq!(SUPPORTED_USB_DEVICES.len(), 3);
-        assert!(SUPPORTED_USB_DEVICES
-            .iter()
-            .any(|d| d.vendor_id == 0x067B && d.product_id == 0x27A1));
+        let registry = DeviceRegistry::new();
+        assert_eq!(registry.devices().len(), 3);
+        assert!(registry.is_supported(0x067B, 0x27A1));
+    }
+
+    fn cable() -> UsbDeviceId {
+        *DeviceRegistry::new().find(0x067B, 0x27A1).unwrap()
+    }
+
+    #[test]
+    fn test_permission_denied_on_linux_suggests_udev_rule() {
+        let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let error = classify_open_error(error, &cable(), Platform::Linux);
+        let TransportError::PermissionDenied {
+            suggested_udev_rule: Some(rule),
+        } = &error
+        else {
+            panic!("expected PermissionDenied, got {:?}", error);
+        };
+        assert!(rule.contains(r#"ATTR{idVendor}=="067b", ATTR{idProduct}=="27a1""#));
+        assert!(error.hint().unwrap().contains(rule.as_str()));
+
+        let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            classify_open_error(error, &cable(), Platform::Other),
+            TransportError::PermissionDenied {
+                suggested_udev_rule: None
+            }
+        ));
+    }
+
+    #[test]
+    fn test_windows_driver_missing() {
+        for kind in [
+            std::io::ErrorKind::Unsupported,
+            std::io::ErrorKind::NotFound,
+        ] {
+            let error = classify_open_error(kind.into(), &cable(), Platform::Windows);
+            let TransportError::DriverMissing { device } = &error else {
+                panic!("expected DriverMissing, got {:?}", error);
+            };
+            assert_eq!(device, "Prolific PL27A1 (067B:27A1)");
+            assert!(error.hint().unwrap().contains("Zadig"));
+        }
+
+        // The same error elsewhere is just reported
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            classify_open_error(error, &cable(), Platform::Linux),
+            TransportError::UsbError(_)
+        ));
+    }
+
+    #[test]
+    fn test_device_busy() {
+        for (code, platform) in [(EBUSY, Platform::Linux), (ERROR_BUSY, Platform::Windows)] {
+            let error = std::io::Error::from_raw_os_error(code);
+            assert!(matches!(
+                classify_open_error(error, &cable(), platform),
+                TransportError::DeviceBusy
+            ));
+        }
+
+        let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            classify_open_error(error, &cable(), Platform::Windows),
+            TransportError::DeviceBusy
+        ));
+    }
+
+    #[test]
+    fn test_other_errors_keep_message() {
+        let error = std::io::Error::new(std::io::ErrorKind::Other, "pipe error");
+        match classify_open_error(error, &cable(), Platform::Linux) {
+            TransportError::UsbError(message) => assert_eq!(message, "pipe error"),
+            other => panic!("expected UsbError, got {:?}", other),
+        }
+    }
+
+    /// An IN endpoint holding `queued` transfers of stale data, then
+    /// optionally a `trickle` of one transfer every so often, as from a
+    /// source still streaming
+    #[derive(Default)]
+    struct MockInterface {
+        queued: VecDeque<usize>,
+        trickle: Option<(usize, Duration)>,
+        reads: usize,
+    }
+
+    #[async_trait]
+    impl StaleRead for MockInterface {
+        async fn read_stale(&mut self, timeout: Duration) -> Result<Option<usize>, TransportError> {
+            self.reads += 1;
+            if let Some(len) = self.queued.pop_front() {
+                return Ok(Some(len));
+            }
+            match self.trickle {
+                Some((len, every)) if every <= timeout => {
+                    tokio::time::sleep(every).await;
+                    Ok(Some(len))
+                }
+                _ => {
+                    tokio::time::sleep(timeout).await;
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_drains_queued_garbage() {
+        let mut interface = MockInterface {
+            queued: [TRANSFER_SIZE, TRANSFER_SIZE, 1000].into(),
+            ..Default::default()
+        };
+        let config = StaleDataFlush::default();
+        let start = tokio::time::Instant::now();
+        let flushed = drain_stale(&mut interface, &config).await.unwrap();
+        assert_eq!(flushed, (2 * TRANSFER_SIZE + 1000, true));
+        assert_eq!(interface.reads, 4);
+        // Only the last, empty read waits
+        assert_eq!(start.elapsed(), config.idle_timeout);
+
+        let mut empty = MockInterface::default();
+        assert_eq!(drain_stale(&mut empty, &config).await.unwrap(), (0, true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_stops_at_byte_budget() {
+        let mut interface = MockInterface {
+            queued: vec![TRANSFER_SIZE; 100].into(),
+            ..Default::default()
+        };
+        let config = StaleDataFlush {
+            max_bytes: 16 * TRANSFER_SIZE,
+            ..Default::default()
+        };
+        let flushed = drain_stale(&mut interface, &config).await.unwrap();
+        assert_eq!(flushed, (16 * TRANSFER_SIZE, false));
+        assert_eq!(interface.reads, 16);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_stops_at_time_budget() {
+        let mut interface = MockInterface {
+            trickle: Some((512, Duration::from_millis(10))),
+            ..Default::default()
+        };
+        let config = StaleDataFlush {
+            max_time: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let start = tokio::time::Instant::now();
+        let flushed = drain_stale(&mut interface, &config).await.unwrap();
+        assert_eq!(flushed, (10 * 512, false));
+        assert_eq!(start.elapsed(), config.max_time);
+    }
+
+    #[tokio::test]
+    async fn test_close_aborts_pending_transfer() {
+        let closed = CancellationToken::new();
+        let transfer = until_closed(&closed, std::future::pending::<()>());
+
+        let closer = closed.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            closer.cancel();
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(1), transfer)
+            .await
+            .expect("pending transfer was not aborted");
+        assert!(matches!(result, Err(TransportError::Disconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_closed_before_transfer() {
+        let closed = CancellationToken::new();
+        closed.cancel();
+        let result = until_closed(&closed, async { 42 }).await;
+        assert!(matches!(result, Err(TransportError::Disconnected)));
     }
 }
@@ -0,0 +1,652 @@
+//! Handshake, SourcePipeline, and SinkPipeline together over MockTransport
+
+use std::time::Duration;
+
+use serialwarp_core::{
+    capabilities, error_codes, EncoderConfig, ErrorPayload, FrameAckPayload, FrameHeader,
+    NullEncoder, Packet, PacketType, PassthroughDecoder, PeerIdentity, Resolution,
+};
+use serialwarp_pipeline::{
+    local_identity, SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, SourceHandshake,
+    SourcePipeline, SourcePipelineConfig, SourceStats,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{MockTransport, Transport};
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 32;
+const FRAME_COUNT: u64 = 20;
+const CREDITS: u16 = 3;
+
+#[tokio::test]
+async fn test_handshake_then_stream() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+
+    let source_handshake = SourceHandshake {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake {
+        initial_credits: CREDITS,
+        ..Default::default()
+    };
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert_eq!(started.initial_credits, CREDITS);
+    assert_eq!(started.sequence, 2);
+    assert_eq!(negotiated.sequence, 2);
+    assert_eq!(
+        (negotiated.start.width, negotiated.start.height),
+        (WIDTH, HEIGHT)
+    );
+
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            fps: negotiated.start.fps(),
+            queue_depth: FRAME_COUNT as usize,
+            ..Default::default()
+        },
+        negotiated.sequence,
+    );
+
+    let mut frames = Vec::new();
+    let mut requested_keyframe = false;
+    while (frames.len() as u64) < FRAME_COUNT {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        let SinkOutput::Frame { frame, .. } = output else {
+            continue;
+        };
+        frames.push(frame.metadata.frame_number);
+
+        // Halfway through, ask for a keyframe the way the sink does after a
+        // decode error
+        if frames.len() as u64 == FRAME_COUNT / 2 && !requested_keyframe {
+            let request = ErrorPayload::new(error_codes::DECODER_FAILED, false, "test");
+            sink.send_packet(PacketType::Error, request.to_bytes())
+                .await
+                .unwrap();
+            requested_keyframe = true;
+        }
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while source.stats().frames_acked < FRAME_COUNT {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("acks not processed");
+    source.stop().await;
+
+    // Frame numbers skip captured frames that had no credit, but never go
+    // backwards
+    assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+    let mut decoded = 0;
+    while sink.next_decoded_frame().is_some() {
+        decoded += 1;
+    }
+    assert_eq!(decoded, FRAME_COUNT);
+
+    let sink_stats = sink.stats();
+    assert_eq!(sink_stats.frames_decoded, FRAME_COUNT);
+    assert_eq!(sink_stats.acks_sent, FRAME_COUNT);
+    assert_eq!(sink_stats.decode_errors, 0);
+
+    let source_stats = source.stats();
+    assert!(source_stats.frames_sent >= FRAME_COUNT);
+    assert!(source_stats.frames_acked >= FRAME_COUNT);
+    // The stream's first keyframe plus the requested one
+    assert_eq!(source_stats.keyframes_sent, 2);
+    // Every stage was timed
+    let capture_interval = source_stats.capture_interval.unwrap();
+    assert!(capture_interval.p50 <= capture_interval.p95);
+    assert!(capture_interval.p95 <= capture_interval.p99);
+    assert!(source_stats.encode_duration.is_some());
+    assert!(source_stats.send_duration.is_some());
+}
+
+#[tokio::test]
+async fn test_options_used_only_when_both_agree() {
+    for (source_caps, sink_caps, crc, compression) in [
+        (capabilities::NO_CRC, capabilities::NO_CRC, false, false),
+        (capabilities::NO_CRC, 0, true, false),
+        (0, capabilities::NO_CRC, true, false),
+        (
+            capabilities::LZ4,
+            capabilities::LZ4 | capabilities::NO_CRC,
+            true,
+            true,
+        ),
+        (capabilities::LZ4, capabilities::HIDPI, true, false),
+    ] {
+        let (source_transport, sink_transport) = MockTransport::pair();
+        let source_handshake = SourceHandshake {
+            capabilities: source_caps,
+            ..Default::default()
+        };
+        let sink_handshake = SinkHandshake {
+            capabilities: sink_caps,
+            ..Default::default()
+        };
+        let (started, negotiated) = tokio::join!(
+            source_handshake.connect(&source_transport, 0),
+            sink_handshake.accept(&sink_transport, 0),
+        );
+        let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+        assert_eq!((started.crc, started.compression), (crc, compression));
+        assert_eq!((negotiated.crc, negotiated.compression), (crc, compression));
+    }
+}
+
+#[tokio::test]
+async fn test_fec_negotiated_with_default_sink() {
+    for (source_caps, fec) in [(capabilities::FEC, true), (0, false)] {
+        let (source_transport, sink_transport) = MockTransport::pair();
+        let source_handshake = SourceHandshake {
+            capabilities: source_caps,
+            ..Default::default()
+        };
+        let sink_handshake = SinkHandshake::default();
+        let (started, negotiated) = tokio::join!(
+            source_handshake.connect(&source_transport, 0),
+            sink_handshake.accept(&sink_transport, 0),
+        );
+        assert_eq!(started.unwrap().fec, fec);
+        assert_eq!(negotiated.unwrap().fec, fec);
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_exchanges_identity() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_identity = PeerIdentity::new("studio.local", "macos-aarch64", "2.1.0");
+    let source_handshake = SourceHandshake {
+        identity: source_identity.clone(),
+        ..Default::default()
+    };
+    // Left as the default, the sink sends this machine's
+    let sink_handshake = SinkHandshake::default();
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    assert_eq!(negotiated.unwrap().hello.identity, source_identity);
+    let sink_identity = started.unwrap().hello_ack.identity;
+    assert_eq!(sink_identity, local_identity());
+    assert_eq!(sink_identity.app_version, env!("CARGO_PKG_VERSION"));
+    assert!(sink_identity.platform.starts_with(std::env::consts::OS));
+}
+
+#[tokio::test]
+async fn test_hidpi_handshake_then_stream() {
+    // A 2x display: 512x32 points, captured and streamed as 1024x64 pixels
+    let points = Resolution::new(WIDTH, HEIGHT);
+    let pixels = points.to_pixels(2);
+
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_handshake = SourceHandshake {
+        width: pixels.width,
+        height: pixels.height,
+        scale: 2,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake::default();
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert_eq!(started.hello_ack.max_scale(), 2);
+    assert_eq!(negotiated.hello.max_scale(), 2);
+    assert_eq!(started.scale, 2);
+    assert_eq!(negotiated.scale, 2);
+    assert_eq!(negotiated.start.coded_size(), pixels);
+    assert_eq!(negotiated.start.display_size(), pixels);
+    assert_eq!(negotiated.point_size(), points);
+
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: pixels.width,
+        height: pixels.height,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: pixels.width,
+        height: pixels.height,
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            fps: negotiated.start.fps(),
+            display_size: Some(negotiated.start.display_size()),
+            ..Default::default()
+        },
+        negotiated.sequence,
+    );
+
+    // Frames arrive at full pixel size, not the window's point size
+    for _ in 0..3 {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if let SinkOutput::Frame { .. } = output {
+            while let Some(frame) = sink.next_decoded_frame() {
+                assert_eq!(Resolution::new(frame.width, frame.height), pixels);
+            }
+        }
+    }
+    source.stop().await;
+}
+
+#[tokio::test]
+async fn test_scale_limited_by_sink() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_handshake = SourceHandshake {
+        width: 2048,
+        height: 1536,
+        scale: 2,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake {
+        max_scale: 1,
+        ..Default::default()
+    };
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+
+    // The pixels are unchanged, shown one to a point
+    assert_eq!(started.scale, 1);
+    assert_eq!(negotiated.scale, 1);
+    assert_eq!(negotiated.point_size(), Resolution::new(2048, 1536));
+}
+
+/// Stream `FRAME_COUNT` frames with the given settings and check they all
+/// arrive and are acknowledged
+async fn stream_frames(
+    source_config: SourcePipelineConfig,
+    sink_config: SinkPipelineConfig,
+) -> SourceStats {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: CREDITS,
+            ..source_config
+        },
+    );
+    source.start().unwrap();
+
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: FRAME_COUNT as usize,
+            ..sink_config
+        },
+        0,
+    );
+
+    let mut frames = 0;
+    while frames < FRAME_COUNT {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if matches!(output, SinkOutput::Frame { .. }) {
+            frames += 1;
+        }
+    }
+    sink.flush_acks().await;
+    source.stop().await;
+
+    let sink_stats = sink.stats();
+    assert_eq!(sink_stats.frames_decoded, FRAME_COUNT);
+    assert_eq!(sink_stats.acks_sent, FRAME_COUNT);
+    assert_eq!(sink_stats.decode_errors, 0);
+    source.stats()
+}
+
+#[tokio::test]
+async fn test_stream_without_crc() {
+    stream_frames(
+        SourcePipelineConfig {
+            crc: false,
+            ..Default::default()
+        },
+        SinkPipelineConfig {
+            crc: false,
+            ..Default::default()
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_no_crc_negotiated_then_sent() {
+    // As between the sink's --no-crc and a source offering it too
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_handshake = SourceHandshake {
+        capabilities: SourceHandshake::default().capabilities | capabilities::NO_CRC,
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake {
+        capabilities: SinkHandshake::default().capabilities | capabilities::NO_CRC,
+        ..Default::default()
+    };
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert!(!started.crc && !negotiated.crc);
+
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            crc: started.crc,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    // FRAME packets leave the CRC out from the first
+    let data = tokio::time::timeout(Duration::from_secs(5), sink_transport.recv())
+        .await
+        .expect("no frame sent")
+        .unwrap();
+    let (packet, _) = Packet::parse(&data).unwrap();
+    assert_eq!(packet.packet_type(), PacketType::Frame);
+    assert!(!packet.header.has_crc());
+    tokio::time::timeout(Duration::from_secs(1), source.stop())
+        .await
+        .expect("pipeline didn't stop");
+}
+
+#[tokio::test]
+async fn test_stream_compressed() {
+    stream_frames(
+        SourcePipelineConfig {
+            compression: true,
+            ..Default::default()
+        },
+        SinkPipelineConfig {
+            compression: true,
+            ..Default::default()
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_stream_with_parity() {
+    let stats = stream_frames(
+        SourcePipelineConfig {
+            fec_group_size: Some(2),
+            ..Default::default()
+        },
+        SinkPipelineConfig::default(),
+    )
+    .await;
+
+    // Every frame here fits one segment, so each gets its own parity, just
+    // longer for the lengths and checksums
+    assert!(stats.parity_segments_sent >= stats.frames_sent);
+    assert!(stats.fec_overhead() > 1.0);
+}
+
+#[tokio::test]
+async fn test_stream_batched_acks() {
+    // Batches larger than the credit window only go out on their deadline
+    for ack_batch in [2, CREDITS as usize + 1] {
+        stream_frames(
+            SourcePipelineConfig::default(),
+            SinkPipelineConfig {
+                ack_batch,
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn test_odd_size_cropped_by_sink() {
+    let (width, height) = (WIDTH + 1, HEIGHT + 1);
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_handshake = SourceHandshake {
+        width,
+        height,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake::default();
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert_eq!(
+        negotiated.start.coded_size(),
+        Resolution::new(width + 1, height + 1)
+    );
+    assert_eq!(
+        negotiated.start.display_size(),
+        Resolution::new(width, height)
+    );
+
+    let source = TestPatternSource::new(TestPatternConfig {
+        width,
+        height,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width,
+        height,
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            display_size: Some(negotiated.start.display_size()),
+            ..Default::default()
+        },
+        negotiated.sequence,
+    );
+    loop {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if matches!(output, SinkOutput::Frame { .. }) {
+            break;
+        }
+    }
+    source.stop().await;
+
+    let frame = sink.next_decoded_frame().unwrap();
+    assert_eq!((frame.width, frame.height), (width, height));
+}
+
+#[tokio::test]
+async fn test_sink_keeps_source_frame_numbers_across_a_drop() {
+    const FRAMES: u64 = 6;
+    const LOST: u64 = 2;
+
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: FRAMES as u16,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    // The sink's acks go to `acks` rather than back to the source, which
+    // has credits enough for every frame
+    let (ack_transport, acks) = MockTransport::pair();
+    let mut sink = SinkPipeline::new(
+        ack_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: FRAMES as usize,
+            ..Default::default()
+        },
+        0,
+    );
+
+    let mut sent = Vec::new();
+    while sent.len() < FRAMES as usize {
+        let data = tokio::time::timeout(Duration::from_secs(5), sink_transport.recv())
+            .await
+            .expect("no FRAME")
+            .unwrap();
+        let packet = Packet::parse(&data).unwrap().0;
+        let header = FrameHeader::parse(&packet.payload).unwrap();
+        if header.segment_index == 0 {
+            sent.push(header.frame_number);
+        }
+        if header.frame_number != LOST {
+            sink.handle_packet(packet).await.unwrap();
+        }
+    }
+    sink.flush_acks().await;
+    source.stop().await;
+
+    let expected: Vec<u64> = sent.into_iter().filter(|&n| n != LOST).collect();
+    assert_eq!(expected, vec![0, 1, 3, 4, 5]);
+
+    let mut acked = Vec::new();
+    while let Ok(Ok(data)) = tokio::time::timeout(Duration::from_millis(10), acks.recv()).await {
+        let packet = Packet::parse(&data).unwrap().0;
+        assert_eq!(packet.packet_type(), PacketType::FrameAck);
+        acked.push(
+            FrameAckPayload::parse(&packet.payload)
+                .unwrap()
+                .frame_number,
+        );
+    }
+    assert_eq!(acked, expected);
+
+    let mut decoded = Vec::new();
+    while let Some(frame) = sink.next_decoded_frame() {
+        decoded.push(frame.frame_number);
+        sink.frame_presented(frame.frame_number);
+    }
+    assert_eq!(decoded, expected);
+    assert_eq!(sink.stats().frames_presented, expected.len() as u64);
+}
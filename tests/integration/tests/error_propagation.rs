@@ -0,0 +1,46 @@
+//! ERROR packets raised by the sink reach the source over MockTransport
+
+use serialwarp_core::{
+    error_codes, EncodedFrame, ErrorPayload, FrameHeader, FrameMetadata, Packet, PacketType,
+};
+use serialwarp_transport::{MockTransport, Transport};
+
+/// Sink stand-in whose decoder fails on the first frame
+async fn failing_sink(transport: MockTransport) {
+    let data = transport.recv().await.unwrap();
+    let (packet, _) = Packet::parse(&data).unwrap();
+    assert_eq!(packet.packet_type(), PacketType::Frame);
+    FrameHeader::parse(&packet.payload).unwrap();
+
+    // Simulated decoder failure
+    let payload = ErrorPayload::new(
+        error_codes::DECODER_FAILED,
+        true,
+        "simulated decode failure",
+    );
+    let error = Packet::new(PacketType::Error, 0, 0, payload.to_bytes());
+    transport.send(error.to_bytes()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_decoder_failure_reaches_source() {
+    let (source, sink) = MockTransport::pair();
+    let sink_task = tokio::spawn(failing_sink(sink));
+
+    let frame = EncodedFrame::new(FrameMetadata::new(0, 0, 0, true), vec![0u8; 1024]);
+    for segment in frame.into_segments() {
+        let packet = Packet::new(PacketType::Frame, 0, 0, segment.to_payload());
+        source.send(packet.to_bytes()).await.unwrap();
+    }
+
+    let data = source.recv().await.unwrap();
+    let (packet, _) = Packet::parse(&data).unwrap();
+    assert_eq!(packet.packet_type(), PacketType::Error);
+
+    let error = ErrorPayload::parse(&packet.payload).unwrap();
+    assert_eq!(error.code, error_codes::DECODER_FAILED);
+    assert!(error.fatal);
+    assert_eq!(error.message, "simulated decode failure");
+
+    sink_task.await.unwrap();
+}
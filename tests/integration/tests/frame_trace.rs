@@ -0,0 +1,314 @@
+//! Per-frame spans and `FrameTrace` lines from both pipelines, captured with
+//! a subscriber that records every span's fields
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serialwarp_core::{
+    EncodedFrame, EncoderConfig, NullEncoder, Packet, PacketType, PassthroughDecoder, VideoEncoder,
+    MAX_SEGMENT_SIZE,
+};
+use serialwarp_pipeline::{
+    SinkPipeline, SinkPipelineConfig, SourcePipeline, SourcePipelineConfig, FRAME_TRACE_TARGET,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{MockTransport, Transport};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 32;
+
+/// A span or event seen under `FRAME_TRACE_TARGET`
+#[derive(Debug, Clone)]
+struct Captured {
+    name: String,
+    level: Level,
+    fields: HashMap<String, String>,
+}
+
+impl Captured {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    fn frame(&self) -> u64 {
+        self.field("frame").unwrap().parse().unwrap()
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// Records frame spans, with the fields they had when they closed, and
+/// frame trace events
+#[derive(Clone, Default)]
+struct CaptureLayer {
+    open: Arc<Mutex<HashMap<Id, Captured>>>,
+    closed: Arc<Mutex<Vec<Captured>>>,
+    events: Arc<Mutex<Vec<Captured>>>,
+}
+
+impl CaptureLayer {
+    fn spans(&self, name: &str) -> Vec<Captured> {
+        let closed = self.closed.lock().unwrap();
+        closed
+            .iter()
+            .filter(|span| span.name == name)
+            .cloned()
+            .collect()
+    }
+
+    fn events(&self) -> Vec<Captured> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CaptureLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if metadata.target() != FRAME_TRACE_TARGET {
+            return;
+        }
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        let span = Captured {
+            name: metadata.name().to_string(),
+            level: *metadata.level(),
+            fields,
+        };
+        self.open.lock().unwrap().insert(id.clone(), span);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(span) = self.open.lock().unwrap().get_mut(id) {
+            values.record(&mut FieldVisitor(&mut span.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if metadata.target() != FRAME_TRACE_TARGET {
+            return;
+        }
+        let mut fields = HashMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        self.events.lock().unwrap().push(Captured {
+            name: metadata.name().to_string(),
+            level: *metadata.level(),
+            fields,
+        });
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(span) = self.open.lock().unwrap().remove(&id) {
+            self.closed.lock().unwrap().push(span);
+        }
+    }
+}
+
+/// Run `test` on a single-threaded runtime with `layer` capturing, so spans
+/// from spawned tasks are captured too
+fn capture<F: std::future::Future>(layer: &CaptureLayer, test: F) -> F::Output {
+    let subscriber = tracing_subscriber::registry().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test)
+    })
+}
+
+/// Encoded frames, padded past one segment so every frame is reassembled
+/// from two
+fn encoded_frames(count: u64) -> Vec<EncodedFrame> {
+    let mut encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    });
+    let bgra = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+
+    (0..count)
+        .map(|pts| {
+            encoder
+                .encode_raw(&bgra, WIDTH as usize * 4, pts * 1000, false)
+                .unwrap();
+            let mut frame = encoder.next_frame().unwrap();
+            let mut data = frame.data.to_vec();
+            data.resize(MAX_SEGMENT_SIZE + 16, 0);
+            frame.data = data.into();
+            frame
+        })
+        .collect()
+}
+
+#[test]
+fn test_sink_frame_spans() {
+    let layer = CaptureLayer::default();
+    capture(&layer, async {
+        let (_source, sink) = MockTransport::pair();
+        let mut pipeline = SinkPipeline::new(
+            sink.split(),
+            Box::new(PassthroughDecoder::new()),
+            SinkPipelineConfig {
+                trace_frames: 2,
+                ..Default::default()
+            },
+            0,
+        );
+
+        for frame in encoded_frames(4) {
+            for segment in frame.into_segments() {
+                let packet = Packet::new(PacketType::Frame, 0, 0, segment.to_payload());
+                pipeline.handle_packet(packet).await.unwrap();
+            }
+            let decoded = pipeline.next_decoded_frame().unwrap();
+            pipeline.frame_presented(decoded.frame_number);
+        }
+    });
+
+    let spans = layer.spans("sink_frame");
+    assert_eq!(
+        spans.iter().map(Captured::frame).collect::<Vec<_>>(),
+        vec![0, 1, 2, 3]
+    );
+    for span in &spans {
+        let expected = if span.frame() % 2 == 0 {
+            Level::DEBUG
+        } else {
+            Level::TRACE
+        };
+        assert_eq!(span.level, expected, "frame {}", span.frame());
+        assert_eq!(span.field("segments"), Some("2"));
+        assert_eq!(span.field("pictures"), Some("1"));
+        for field in ["reassemble_us", "decode_us"] {
+            let value = span.field(field).unwrap_or_else(|| panic!("no {}", field));
+            value.parse::<u64>().unwrap();
+        }
+    }
+
+    // One line per frame traced in full, once it's presented
+    let events = layer.events();
+    assert_eq!(
+        events.iter().map(Captured::frame).collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    for event in &events {
+        assert_eq!(event.level, Level::DEBUG);
+        let line = event.field("message").unwrap();
+        assert!(
+            line.starts_with(&format!("frame={} reassemble=", event.frame())),
+            "{}",
+            line
+        );
+        for stage in [" decode=", " present=", " total="] {
+            assert!(line.contains(stage), "{} in {}", stage, line);
+        }
+        assert!(!line.contains('\n'));
+    }
+}
+
+#[test]
+fn test_source_frame_spans() {
+    const FRAMES: u64 = 8;
+
+    let layer = CaptureLayer::default();
+    capture(&layer, async {
+        let (source_transport, sink_transport) = MockTransport::pair();
+        let source = TestPatternSource::new(TestPatternConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 60,
+            paced: false,
+        })
+        .unwrap();
+        let encoder = NullEncoder::new(EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            ..Default::default()
+        });
+        let mut source = SourcePipeline::new(
+            Box::new(source),
+            Box::new(encoder),
+            source_transport.split(),
+            SourcePipelineConfig {
+                initial_credits: FRAMES as u16,
+                trace_frames: 4,
+                ..Default::default()
+            },
+        );
+        source.start().unwrap();
+
+        // No acks, so it stops once the credits run out
+        for _ in 0..FRAMES {
+            let data = tokio::time::timeout(Duration::from_secs(5), sink_transport.recv())
+                .await
+                .expect("no FRAME")
+                .unwrap();
+            assert!(matches!(
+                Packet::parse(&data).unwrap().0.packet_type(),
+                PacketType::Frame
+            ));
+        }
+        while source.stats().frames_sent < FRAMES {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        source.stop().await;
+    });
+
+    let spans = layer.spans("source_frame");
+    assert_eq!(
+        spans.iter().map(Captured::frame).collect::<Vec<_>>(),
+        (0..FRAMES).collect::<Vec<_>>()
+    );
+    for span in &spans {
+        let expected = if span.frame() % 4 == 0 {
+            Level::DEBUG
+        } else {
+            Level::TRACE
+        };
+        assert_eq!(span.level, expected, "frame {}", span.frame());
+        assert_eq!(span.field("segments"), Some("1"));
+        for field in ["capture_us", "encode_us", "send_us"] {
+            let value = span.field(field).unwrap_or_else(|| panic!("no {}", field));
+            value.parse::<u64>().unwrap();
+        }
+    }
+    assert_eq!(spans[0].field("keyframe"), Some("true"));
+
+    let events = layer.events();
+    assert_eq!(
+        events.iter().map(Captured::frame).collect::<Vec<_>>(),
+        vec![0, 4]
+    );
+    for event in &events {
+        let line = event.field("message").unwrap();
+        assert!(
+            line.starts_with(&format!("frame={} capture=", event.frame())),
+            "{}",
+            line
+        );
+        for stage in [" encode=", " send=", " total="] {
+            assert!(line.contains(stage), "{} in {}", stage, line);
+        }
+    }
+}
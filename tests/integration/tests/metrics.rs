@@ -0,0 +1,219 @@
+//! Metrics recorded through the `metrics` facade during a session over
+//! MockTransport
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use metrics_util::MetricKind;
+use serialwarp_core::metrics::{self as names, DropReason};
+use serialwarp_core::{
+    EncodedFrame, EncoderConfig, FrameMetadata, NullEncoder, Packet, PacketType,
+    PassthroughDecoder, PingPayload, MAX_SEGMENT_SIZE,
+};
+use serialwarp_pipeline::{
+    SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, SourceHandshake, SourcePipeline,
+    SourcePipelineConfig,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{split_shared, MockTransport, Transport};
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 32;
+const FRAME_COUNT: u64 = 10;
+
+/// A metric's kind, name, and labels, with its value
+type Metric = (MetricKind, String, Vec<(String, String)>, DebugValue);
+
+/// Every metric recorded so far
+struct Recorded(Vec<Metric>);
+
+impl Recorded {
+    fn take(snapshotter: &Snapshotter) -> Self {
+        let metrics = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let (kind, key) = key.into_parts();
+                let labels = key
+                    .labels()
+                    .map(|label| (label.key().to_string(), label.value().to_string()))
+                    .collect();
+                (kind, key.name().to_string(), labels, value)
+            })
+            .collect();
+        Self(metrics)
+    }
+
+    fn find(&self, kind: MetricKind, name: &str, labels: &[(&str, &str)]) -> Option<&DebugValue> {
+        self.0
+            .iter()
+            .find(|(k, n, l, _)| {
+                *k == kind
+                    && n == name
+                    && l.len() == labels.len()
+                    && labels
+                        .iter()
+                        .all(|(key, value)| l.contains(&(key.to_string(), value.to_string())))
+            })
+            .map(|(_, _, _, value)| value)
+    }
+
+    /// A counter's value, or 0 if it was never registered
+    fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        match self.find(MetricKind::Counter, name, labels) {
+            Some(DebugValue::Counter(value)) => *value,
+            _ => 0,
+        }
+    }
+
+    /// Samples a histogram holds
+    fn histogram(&self, name: &str) -> usize {
+        match self.find(MetricKind::Histogram, name, &[]) {
+            Some(DebugValue::Histogram(samples)) => samples.len(),
+            _ => 0,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_session_records_metrics() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    // Handshake
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source_handshake = SourceHandshake {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake::default();
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+
+    // Stream
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    // Kept to send the sink bad packets once the pipeline is done
+    let source_transport = Arc::new(source_transport);
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        split_shared(Arc::clone(&source_transport) as Arc<dyn Transport>),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            fps: negotiated.start.fps(),
+            queue_depth: FRAME_COUNT as usize,
+            ..Default::default()
+        },
+        negotiated.sequence,
+    );
+
+    let mut frames = 0;
+    while frames < FRAME_COUNT {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if matches!(output, SinkOutput::Frame { .. }) {
+            frames += 1;
+        }
+    }
+    source.stop().await;
+    let stats = source.stats();
+    // Segments sent before the source stopped
+    while let Ok(output) = tokio::time::timeout(Duration::from_millis(50), sink.recv()).await {
+        output.unwrap();
+    }
+
+    // A packet damaged on the link
+    let ping = Packet::new(PacketType::Ping, 0, 0, PingPayload::new(0).to_bytes());
+    let mut damaged = ping.to_bytes().to_vec();
+    *damaged.last_mut().unwrap() ^= 0xFF;
+    source_transport.send(damaged.into()).await.unwrap();
+    assert!(sink.recv().await.is_err());
+
+    // The first half of a frame whose second half never comes
+    let frame = EncodedFrame::new(
+        FrameMetadata::new(1_000_000, 0, 0, false),
+        vec![0; 2 * MAX_SEGMENT_SIZE],
+    );
+    let segment = &frame.into_segments()[0];
+    let packet = Packet::new(PacketType::Frame, 0, 0, segment.to_payload());
+    source_transport.send(packet.to_bytes()).await.unwrap();
+    assert!(matches!(sink.recv().await.unwrap(), SinkOutput::Segment));
+    sink.reap(Instant::now() + Duration::from_secs(1)).await;
+
+    let recorded = Recorded::take(&snapshotter);
+    let sent = |packet_type: PacketType| {
+        recorded.counter(names::PACKETS_SENT, &[("type", packet_type.name())])
+    };
+    let received = |packet_type: PacketType| {
+        recorded.counter(names::PACKETS_RECEIVED, &[("type", packet_type.name())])
+    };
+    for packet_type in [
+        PacketType::Hello,
+        PacketType::HelloAck,
+        PacketType::Start,
+        PacketType::StartAck,
+    ] {
+        assert_eq!(sent(packet_type), 1, "{:?} sent", packet_type);
+        assert_eq!(received(packet_type), 1, "{:?} received", packet_type);
+    }
+    // Packets sent by hand above bypass the pipeline, so only its FRAMEs
+    // count as sent
+    assert_eq!(sent(PacketType::Frame), stats.segments_sent);
+    assert_eq!(received(PacketType::Frame), sink.stats().segments_received);
+    assert!(sent(PacketType::FrameAck) > 0);
+    assert!(received(PacketType::FrameAck) > 0);
+
+    assert_eq!(
+        recorded.counter(names::FRAMES_CAPTURED, &[]),
+        stats.frames_captured
+    );
+    assert_eq!(
+        recorded.counter(
+            names::FRAMES_DROPPED,
+            &[("reason", DropReason::NoCredit.as_str())]
+        ),
+        stats.frames_skipped
+    );
+    assert!(recorded.counter(names::FRAMES_ENCODED, &[]) >= stats.frames_sent);
+    assert_eq!(recorded.counter(names::FRAMES_SENT, &[]), stats.frames_sent);
+    assert_eq!(
+        recorded.counter(names::FRAMES_DECODED, &[]),
+        sink.stats().frames_decoded
+    );
+    assert!(sink.stats().frames_decoded >= FRAME_COUNT);
+    assert_eq!(recorded.counter(names::CRC_FAILURES, &[]), 1);
+    assert_eq!(recorded.counter(names::REASSEMBLY_EVICTIONS, &[]), 1);
+
+    assert!(recorded.histogram(names::ENCODE_TIME) as u64 >= stats.frames_sent);
+    assert!(recorded.histogram(names::DECODE_TIME) as u64 >= FRAME_COUNT);
+}
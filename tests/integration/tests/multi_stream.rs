@@ -0,0 +1,272 @@
+//! Two streams sharing one MockTransport, each with its own pipelines
+
+use std::time::Duration;
+
+use serialwarp_core::{
+    capabilities, EncoderConfig, NullEncoder, Packet, PacketType, PassthroughDecoder,
+};
+use serialwarp_pipeline::{
+    SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, SourceHandshake, SourcePipeline,
+    SourcePipelineConfig, StreamRequest,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{split_streams, MockTransport, Transport, TransportHalves};
+
+const FRAME_COUNT: usize = 20;
+const CREDITS: u16 = 2;
+
+/// Stream ids and frame sizes; the sizes tell the streams apart once decoded
+const STREAMS: [(u8, u32, u32); 2] = [(0, 512, 32), (5, 1024, 64)];
+
+fn source(stream_id: u8, width: u32, height: u32, halves: TransportHalves) -> SourcePipeline {
+    let source = TestPatternSource::new(TestPatternConfig {
+        width,
+        height,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width,
+        height,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    });
+    SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        halves,
+        SourcePipelineConfig {
+            initial_credits: CREDITS,
+            stream_id,
+            ..Default::default()
+        },
+    )
+}
+
+/// Receive `FRAME_COUNT` frames, returning the decoded sizes
+async fn receive(mut sink: SinkPipeline) -> (SinkPipeline, Vec<(u32, u32)>) {
+    let mut sizes = Vec::new();
+    while sizes.len() < FRAME_COUNT {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if let SinkOutput::Frame { .. } = output {
+            while let Some(frame) = sink.next_decoded_frame() {
+                sizes.push((frame.width, frame.height));
+            }
+        }
+    }
+    (sink, sizes)
+}
+
+#[tokio::test]
+async fn test_two_streams_demultiplexed() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let ids: Vec<u8> = STREAMS.iter().map(|&(id, _, _)| id).collect();
+    let source_halves = split_streams(source_transport.split(), &ids);
+    let sink_halves = split_streams(sink_transport.split(), &ids);
+
+    let mut sources: Vec<_> = STREAMS
+        .iter()
+        .zip(source_halves)
+        .map(|(&(id, width, height), halves)| source(id, width, height, halves))
+        .collect();
+    for source in &mut sources {
+        source.start().unwrap();
+    }
+
+    let mut sinks: Vec<_> = STREAMS
+        .iter()
+        .zip(sink_halves)
+        .map(|(&(stream_id, _, _), halves)| {
+            SinkPipeline::new(
+                halves,
+                Box::new(PassthroughDecoder::new()),
+                SinkPipelineConfig {
+                    queue_depth: FRAME_COUNT,
+                    stream_id,
+                    ..Default::default()
+                },
+                0,
+            )
+        })
+        .collect();
+    let second = sinks.pop().unwrap();
+    let first = sinks.pop().unwrap();
+    let ((first, first_sizes), (second, second_sizes)) =
+        tokio::join!(receive(first), receive(second));
+
+    // Every frame reached the sink for its own stream
+    assert!(first_sizes.iter().all(|&size| size == (512, 32)));
+    assert!(second_sizes.iter().all(|&size| size == (1024, 64)));
+
+    // Each stream's credits came back to it alone
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while sources
+            .iter()
+            .any(|source| source.stats().frames_acked < FRAME_COUNT as u64)
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("acks not processed");
+    for source in &mut sources {
+        source.stop().await;
+    }
+    for (source, sink) in sources.iter().zip([&first, &second]) {
+        let stats = source.stats();
+        assert!(stats.frames_acked <= stats.frames_sent);
+        assert!(stats.frames_sent - stats.frames_acked <= CREDITS as u64);
+        assert_eq!(sink.stats().decode_errors, 0);
+    }
+}
+
+fn request(width: u32, height: u32) -> StreamRequest {
+    StreamRequest {
+        width,
+        height,
+        fps: 60,
+        bitrate_bps: 8_000_000,
+        scale: 1,
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_starts_negotiated_streams() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    // Three streams asked for, two shown
+    let source_handshake = SourceHandshake {
+        width: 512,
+        height: 32,
+        fps: 60,
+        extra_streams: vec![request(1024, 64), request(640, 48)],
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake {
+        max_streams: 2,
+        ..Default::default()
+    };
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert_eq!(started.extra_streams.len(), 1);
+    assert_eq!(negotiated.extra_streams.len(), 1);
+    let (sent, received) = (&started.extra_streams[0], &negotiated.extra_streams[0]);
+    assert_eq!((sent.stream_id, received.stream_id), (1, 1));
+    assert_eq!(sent.start, received.start);
+    assert_eq!(received.start.display_size().width, 1024);
+    assert_eq!(sent.initial_credits, received.initial_credits);
+    // Several streams aren't resumed
+    assert_eq!(started.resume_token, None);
+
+    // Then each stream runs on its own id
+    let ids = [0, received.stream_id];
+    let source_halves = split_streams(source_transport.split(), &ids);
+    let sink_halves = split_streams(sink_transport.split(), &ids);
+    let mut sources: Vec<_> = [&started.start, &sent.start]
+        .into_iter()
+        .zip(ids)
+        .zip(source_halves)
+        .map(|((start, id), halves)| source(id, start.width, start.height, halves))
+        .collect();
+    for source in &mut sources {
+        source.start().unwrap();
+    }
+    let mut sinks = ids.into_iter().zip(sink_halves).map(|(stream_id, halves)| {
+        SinkPipeline::new(
+            halves,
+            Box::new(PassthroughDecoder::new()),
+            SinkPipelineConfig {
+                queue_depth: FRAME_COUNT,
+                stream_id,
+                ..Default::default()
+            },
+            negotiated.sequence,
+        )
+    });
+    let (first, second) = (sinks.next().unwrap(), sinks.next().unwrap());
+    let ((_, first_sizes), (_, second_sizes)) = tokio::join!(receive(first), receive(second));
+    assert!(first_sizes.iter().all(|&size| size == (512, 32)));
+    assert!(second_sizes.iter().all(|&size| size == (1024, 64)));
+    for source in &mut sources {
+        source.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn test_one_stream_without_capability() {
+    let source_handshake = SourceHandshake {
+        extra_streams: vec![request(1024, 64)],
+        ..Default::default()
+    };
+    for sink_handshake in [
+        // Predates multiple streams
+        SinkHandshake {
+            capabilities: capabilities::FEC,
+            max_streams: 4,
+            ..Default::default()
+        },
+        // Shows only one
+        SinkHandshake::default(),
+    ] {
+        let (source_transport, sink_transport) = MockTransport::pair();
+        let (started, negotiated) = tokio::join!(
+            source_handshake.connect(&source_transport, 0),
+            sink_handshake.accept(&sink_transport, 0),
+        );
+        assert!(started.unwrap().extra_streams.is_empty());
+        assert!(negotiated.unwrap().extra_streams.is_empty());
+        // No START left over for a stream that wasn't taken
+        let leftover = tokio::time::timeout(Duration::from_millis(20), sink_transport.recv()).await;
+        assert!(leftover.is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_sink_streams_share_sequence_numbers() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let ids: Vec<u8> = STREAMS.iter().map(|&(id, _, _)| id).collect();
+    let mut sink_halves = split_streams(sink_transport.split(), &ids).into_iter();
+
+    let config = |stream_id| SinkPipelineConfig {
+        stream_id,
+        ..Default::default()
+    };
+    let mut first = SinkPipeline::new(
+        sink_halves.next().unwrap(),
+        Box::new(PassthroughDecoder::new()),
+        config(STREAMS[0].0),
+        7,
+    );
+    let mut second = SinkPipeline::sharing_sequence(
+        sink_halves.next().unwrap(),
+        Box::new(PassthroughDecoder::new()),
+        config(STREAMS[1].0),
+        first.shared_sequence(),
+    );
+    for _ in 0..3 {
+        first
+            .send_packet(PacketType::Ping, Default::default())
+            .await
+            .unwrap();
+        second
+            .send_packet(PacketType::Ping, Default::default())
+            .await
+            .unwrap();
+    }
+
+    // Numbered in the order sent, whichever stream sent them
+    let mut sequences = Vec::new();
+    for _ in 0..6 {
+        let (packet, _) = Packet::parse(&source_transport.recv().await.unwrap()).unwrap();
+        sequences.push(packet.header.sequence);
+    }
+    assert_eq!(sequences, (7..13).collect::<Vec<_>>());
+    assert_eq!(first.sequence(), 13);
+    assert_eq!(second.sequence(), 13);
+}
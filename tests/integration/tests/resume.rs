@@ -0,0 +1,160 @@
+//! Resuming a session after a reconnect, without a new START
+
+use std::time::Duration;
+
+use serialwarp_core::{capabilities, ResumeToken};
+use serialwarp_pipeline::{
+    NegotiatedStream, SinkHandshake, SourceHandshake, StartedStream, DEFAULT_RESUME_EXPIRY,
+};
+use serialwarp_transport::{MockTransport, Transport};
+
+/// Bitrate the source had adapted down to before it left
+const LAST_BITRATE: u32 = 12_000_000;
+
+fn source_handshake() -> SourceHandshake {
+    SourceHandshake {
+        capabilities: capabilities::RESUME,
+        width: 1280,
+        height: 720,
+        fps: 30,
+        bitrate_bps: 20_000_000,
+        ..Default::default()
+    }
+}
+
+/// Run a handshake over a new link, as after a reconnect, and check
+/// nothing more was sent to the sink
+async fn connect(
+    source: &SourceHandshake,
+    sink: &SinkHandshake,
+) -> (StartedStream, NegotiatedStream) {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let (started, negotiated) = tokio::join!(
+        source.connect(&source_transport, 0),
+        sink.accept(&sink_transport, 0),
+    );
+    let leftover = tokio::time::timeout(Duration::from_millis(20), sink_transport.recv()).await;
+    assert!(leftover.is_err(), "sink left a packet unread");
+    (started.unwrap(), negotiated.unwrap())
+}
+
+#[tokio::test]
+async fn test_resume_skips_start() {
+    let sink = SinkHandshake::default();
+    assert_eq!(sink.resume_expiry, Some(DEFAULT_RESUME_EXPIRY));
+    let (started, negotiated) = connect(&source_handshake(), &sink).await;
+    assert!(!started.resumed && !negotiated.resumed);
+    let token = started.resume_token.unwrap();
+    assert_eq!(sink.sessions.token(), Some(token));
+
+    // The source leaves with a STOP hinting it will be back
+    sink.suspend();
+    let source = SourceHandshake {
+        resume: started.resume_session(LAST_BITRATE),
+        ..source_handshake()
+    };
+    let (resumed, negotiated) = connect(&source, &sink).await;
+    assert!(resumed.resumed);
+    assert!(negotiated.resumed);
+    assert_eq!(resumed.bitrate_bps, LAST_BITRATE);
+    assert_eq!(resumed.start, started.start);
+    assert_eq!(resumed.initial_credits, started.initial_credits);
+    assert_eq!(negotiated.start, started.start);
+    assert_eq!((negotiated.crc, negotiated.fec), (resumed.crc, resumed.fec));
+    // Only HELLO and HELLO_ACK were exchanged
+    assert_eq!((resumed.sequence, negotiated.sequence), (1, 1));
+
+    // The same token resumes it again
+    assert_eq!(resumed.resume_token, Some(token));
+    sink.suspend();
+    let (again, _) = connect(&source, &sink).await;
+    assert!(again.resumed);
+}
+
+#[tokio::test]
+async fn test_expired_token_falls_back() {
+    let sink = SinkHandshake {
+        resume_expiry: Some(Duration::from_millis(30)),
+        ..Default::default()
+    };
+    let (started, _) = connect(&source_handshake(), &sink).await;
+    sink.suspend();
+    tokio::time::sleep(Duration::from_millis(60)).await;
+
+    let source = SourceHandshake {
+        resume: started.resume_session(LAST_BITRATE),
+        ..source_handshake()
+    };
+    let (restarted, negotiated) = connect(&source, &sink).await;
+    assert!(!restarted.resumed && !negotiated.resumed);
+    // START's bitrate, and a new session to resume next time
+    assert_eq!(restarted.bitrate_bps, 20_000_000);
+    assert_eq!(restarted.sequence, 2);
+    let token = restarted.resume_token.unwrap();
+    assert_ne!(Some(token), started.resume_token);
+    assert_eq!(sink.sessions.token(), Some(token));
+}
+
+#[tokio::test]
+async fn test_unknown_token_falls_back() {
+    let sink = SinkHandshake::default();
+    let (started, _) = connect(&source_handshake(), &sink).await;
+    sink.suspend();
+
+    let mut resume = started.resume_session(LAST_BITRATE).unwrap();
+    resume.token = ResumeToken([0; 16]);
+    let source = SourceHandshake {
+        resume: Some(resume),
+        ..source_handshake()
+    };
+    let (restarted, negotiated) = connect(&source, &sink).await;
+    assert!(!restarted.resumed && !negotiated.resumed);
+    assert_eq!(negotiated.start.bitrate_bps, 20_000_000);
+
+    // The sink's own session is replaced by the new one
+    assert_eq!(sink.sessions.token(), restarted.resume_token);
+    assert_ne!(restarted.resume_token, started.resume_token);
+}
+
+#[tokio::test]
+async fn test_resume_only_when_both_agree() {
+    // A source that changed the stream starts a new session
+    let sink = SinkHandshake::default();
+    let (started, _) = connect(&source_handshake(), &sink).await;
+    sink.suspend();
+    let source = SourceHandshake {
+        width: 1920,
+        height: 1080,
+        resume: started.resume_session(LAST_BITRATE),
+        ..source_handshake()
+    };
+    let (restarted, negotiated) = connect(&source, &sink).await;
+    assert!(!restarted.resumed && !negotiated.resumed);
+    assert_eq!(negotiated.start.display_size().width, 1920);
+
+    // Neither a sink without the capability nor one with resuming turned
+    // off gives out tokens
+    for sink in [
+        SinkHandshake {
+            capabilities: capabilities::FEC,
+            ..Default::default()
+        },
+        SinkHandshake {
+            resume_expiry: None,
+            ..Default::default()
+        },
+    ] {
+        let (started, _) = connect(&source_handshake(), &sink).await;
+        assert_eq!(started.resume_token, None);
+        assert!(started.resume_session(LAST_BITRATE).is_none());
+        assert_eq!(sink.sessions.token(), None);
+    }
+
+    // Nor does any sink to a source without it
+    let source = SourceHandshake {
+        capabilities: 0,
+        ..source_handshake()
+    };
+    let (started, _) = connect(&source, &SinkHandshake::default()).await;
+    assert_eq!(started.resume_token, None);
+}
@@ -0,0 +1,131 @@
+//! Heap allocations made segmenting, sending and reassembling a keyframe,
+//! counted by a global allocator so copies of frame data can't creep back in
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bytes::{Bytes, BytesMut};
+use serialwarp_core::{EncodedFrame, FrameHeader, FrameMetadata, FrameReassembler};
+
+/// Counts allocations made by the current thread, so tests running
+/// alongside don't add to them
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn record(size: usize) {
+    // Thread locals may already be gone while a thread exits
+    let _ = ALLOCATIONS.try_with(|count| {
+        let (allocations, bytes) = count.get();
+        count.set((allocations + 1, bytes + size));
+    });
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations and bytes allocated by the current thread running `f`
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let after = ALLOCATIONS.with(Cell::get);
+    (result, after.0 - before.0, after.1 - before.1)
+}
+
+const KEYFRAME_SIZE: usize = 500 * 1024;
+
+fn keyframe() -> EncodedFrame {
+    let data: Vec<u8> = (0..KEYFRAME_SIZE)
+        .map(|i| (i * 13 + i / 509) as u8)
+        .collect();
+    EncodedFrame::new(FrameMetadata::new(1, 1000, 1000, true), data)
+}
+
+#[test]
+fn test_segmenting_does_not_copy_frame_data() {
+    let frame = keyframe();
+    let (segments, count, _) = allocations(|| frame.into_segments());
+    assert_eq!(segments.len(), 8);
+    // The list of segments, and the shared handle to the frame's data the
+    // first slice of it creates
+    assert_eq!(count, 2);
+
+    let mut buf = BytesMut::with_capacity(KEYFRAME_SIZE + segments.len() * FrameHeader::SIZE);
+    let (_, count, _) = allocations(|| {
+        for segment in &segments {
+            segment.to_payload_into(&mut buf);
+        }
+    });
+    assert_eq!(count, 0);
+
+    let (parts, _, bytes) = allocations(|| {
+        segments
+            .iter()
+            .map(|segment| segment.payload_parts())
+            .collect::<Vec<_>>()
+    });
+    // Headers and the list of parts, but none of the data
+    assert!(bytes < 4096, "{} bytes allocated", bytes);
+    let joined: Vec<u8> = parts
+        .iter()
+        .flatten()
+        .flat_map(|part| part.to_vec())
+        .collect();
+    assert_eq!(joined, buf);
+}
+
+#[test]
+fn test_reassembly_copies_frame_data_once() {
+    // What the sink hands the reassembler: each FRAME payload split into
+    // its header and data
+    let segments: Vec<(FrameHeader, Bytes)> = keyframe()
+        .into_segments()
+        .iter()
+        .map(|segment| {
+            let payload = segment.to_payload();
+            let header = FrameHeader::parse(&payload).unwrap();
+            (header, payload.slice(FrameHeader::SIZE..))
+        })
+        .collect();
+
+    let mut reassembler = FrameReassembler::new();
+    let (frame, count, bytes) = allocations(|| {
+        let mut complete = None;
+        for (header, data) in segments {
+            complete = reassembler.add_segment(&header, data).unwrap();
+        }
+        complete.expect("frame not reassembled")
+    });
+    assert_eq!(frame.data, keyframe().data);
+    // The frame's buffer, plus bookkeeping for the frame in flight
+    assert!(count <= 4, "{} allocations", count);
+    assert!(
+        bytes < KEYFRAME_SIZE + 4096,
+        "{} bytes allocated for a {} byte frame",
+        bytes,
+        KEYFRAME_SIZE
+    );
+}
@@ -0,0 +1,366 @@
+//! A whole session, handshake to STOP/STOP_ACK, with every packet checked as
+//! it crosses the link
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serialwarp_core::{
+    CreditUpdatePayload, DecodeError, DecodeOutput, DecodedFrame, EncodeError, EncodedFrame,
+    EncoderConfig, FrameAckBatchPayload, FrameHeader, NullEncoder, Packet, PacketType,
+    PassthroughDecoder, StopPayload, StopReason, VideoDecoder, VideoEncoder, MAX_SEGMENT_SIZE,
+};
+use serialwarp_pipeline::{
+    SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, SourceHandshake, SourcePipeline,
+    SourcePipelineConfig,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{split_shared, MockTransport, Transport};
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 32;
+const FPS: u32 = 60;
+const FRAME_COUNT: u64 = 12;
+const CREDITS: u16 = 2;
+
+/// Which way a packet crossed the link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    ToSink,
+    ToSource,
+}
+
+/// Every packet the relay forwarded, in the order it forwarded them
+type WireLog = Arc<Mutex<Vec<(Direction, Packet)>>>;
+
+/// NullEncoder output followed by a canned payload, different for every
+/// frame and up to a few segments long, so segmentation and reassembly are
+/// exercised
+struct FixtureEncoder {
+    inner: NullEncoder,
+    sent: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+/// Bytes appended to frame `frame_number`
+fn fixture_payload(frame_number: u64) -> Vec<u8> {
+    let len = (frame_number as usize * 23_456) % (3 * MAX_SEGMENT_SIZE);
+    (0..len)
+        .map(|i| (i as u64).wrapping_mul(31).wrapping_add(frame_number) as u8)
+        .collect()
+}
+
+impl VideoEncoder for FixtureEncoder {
+    fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        self.inner.encode_raw(data, stride, pts_us, force_keyframe)
+    }
+
+    fn next_frame(&mut self) -> Option<EncodedFrame> {
+        let mut frame = self.inner.next_frame()?;
+        let mut data = frame.data.to_vec();
+        data.extend(fixture_payload(frame.metadata.frame_number));
+        self.sent.lock().unwrap().push(data.clone());
+        frame.data = data.into();
+        Some(frame)
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        self.inner.flush()
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+        self.inner.set_bitrate(bitrate)
+    }
+
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+        self.inner.reconfigure(config)
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        self.inner.config()
+    }
+}
+
+/// PassthroughDecoder that keeps a copy of every frame it's given
+struct RecordingDecoder {
+    inner: PassthroughDecoder,
+    received: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl VideoDecoder for RecordingDecoder {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        self.received.lock().unwrap().push(data.to_vec());
+        self.inner.decode(data, pts_us)
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        self.inner.flush()
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        self.inner.reconfigure()
+    }
+}
+
+/// Forward packets between the two ends, logging each, until either side
+/// closes
+async fn relay(source_side: MockTransport, sink_side: MockTransport, log: WireLog) {
+    loop {
+        let (direction, data) = tokio::select! {
+            data = source_side.recv() => (Direction::ToSink, data),
+            data = sink_side.recv() => (Direction::ToSource, data),
+        };
+        let Ok(data) = data else {
+            return;
+        };
+        let (packet, _) = Packet::parse(&data).unwrap();
+        log.lock().unwrap().push((direction, packet));
+
+        let to = match direction {
+            Direction::ToSink => &sink_side,
+            Direction::ToSource => &source_side,
+        };
+        if to.send(data).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Check that each direction numbers its packets 0, 1, 2, ... and that,
+/// replaying the log from the source's side, it never sent a frame it had no
+/// credit for
+fn check_wire(log: &[(Direction, Packet)]) {
+    for direction in [Direction::ToSink, Direction::ToSource] {
+        let sequences: Vec<u32> = log
+            .iter()
+            .filter(|(d, _)| *d == direction)
+            .map(|(_, packet)| packet.sequence())
+            .collect();
+        assert_eq!(
+            sequences,
+            (0..sequences.len() as u32).collect::<Vec<_>>(),
+            "{:?} sequence numbers",
+            direction
+        );
+    }
+
+    let mut credits = CREDITS as i64;
+    for (_, packet) in log {
+        match packet.packet_type() {
+            PacketType::Frame => {
+                let header = FrameHeader::parse(&packet.payload).unwrap();
+                if header.segment_index == 0 {
+                    credits -= 1;
+                    assert!(
+                        credits >= 0,
+                        "frame {} sent without credit",
+                        header.frame_number
+                    );
+                }
+            }
+            PacketType::FrameAck => {
+                credits += FrameAckBatchPayload::parse(&packet.payload)
+                    .unwrap()
+                    .credits_returned as i64;
+            }
+            PacketType::CreditUpdate => {
+                credits += CreditUpdatePayload::parse(&packet.payload).unwrap().delta as i64;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_session_over_mock_transport() {
+    let (source_transport, source_relay) = MockTransport::pair();
+    let (sink_relay, sink_transport) = MockTransport::pair();
+    let log = WireLog::default();
+    let relay = tokio::spawn(relay(source_relay, sink_relay, Arc::clone(&log)));
+
+    // Handshake
+    let source_handshake = SourceHandshake {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: FPS,
+        bitrate_bps: 4_000_000,
+        ..Default::default()
+    };
+    let sink_handshake = SinkHandshake {
+        initial_credits: CREDITS,
+        ..Default::default()
+    };
+    let (started, negotiated) = tokio::join!(
+        source_handshake.connect(&source_transport, 0),
+        sink_handshake.accept(&sink_transport, 0),
+    );
+    let (started, negotiated) = (started.unwrap(), negotiated.unwrap());
+    assert_eq!(started.initial_credits, CREDITS);
+    assert_eq!(
+        (negotiated.start.width, negotiated.start.height),
+        (WIDTH, HEIGHT)
+    );
+    assert_eq!(negotiated.start.fps(), FPS);
+    assert_eq!(negotiated.start.bitrate_bps, 4_000_000);
+    assert_eq!(negotiated.scale, 1);
+    assert!(started.crc && negotiated.crc);
+    assert!(started.pause);
+
+    // Stream
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let encoder = FixtureEncoder {
+        inner: NullEncoder::new(EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            keyframe_interval: Duration::from_secs(60),
+            ..Default::default()
+        }),
+        sent: Arc::clone(&sent),
+    };
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: FPS,
+        paced: false,
+    })
+    .unwrap();
+    // Kept to send STOP once the pipeline is done with the link
+    let source_transport = Arc::new(source_transport);
+    let mut source = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        split_shared(Arc::clone(&source_transport) as Arc<dyn Transport>),
+        SourcePipelineConfig {
+            initial_credits: started.initial_credits,
+            first_sequence: started.sequence,
+            pause_packets: started.pause,
+            ..Default::default()
+        },
+    );
+    source.start().unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let decoder = RecordingDecoder {
+        inner: PassthroughDecoder::new(),
+        received: Arc::clone(&received),
+    };
+    let mut sink = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(decoder),
+        SinkPipelineConfig {
+            fps: negotiated.start.fps(),
+            queue_depth: FRAME_COUNT as usize,
+            ..Default::default()
+        },
+        negotiated.sequence,
+    );
+
+    let mut frames = 0;
+    while frames < FRAME_COUNT {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("stream stalled")
+            .unwrap();
+        if matches!(output, SinkOutput::Frame { .. }) {
+            frames += 1;
+        }
+    }
+    sink.flush_acks().await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while source.stats().frames_acked < FRAME_COUNT {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("acks not processed");
+    source.stop().await;
+    let stats = source.stats();
+
+    // Stop: the source asks, the sink answers
+    let stop = StopPayload::new(StopReason::UserRequested, false);
+    let stop_sequence = started.sequence + stats.segments_sent as u32;
+    let stop = Packet::new(PacketType::Stop, 0, stop_sequence, stop.to_bytes());
+    source_transport.send(stop.to_bytes()).await.unwrap();
+    loop {
+        let output = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .expect("STOP not delivered")
+            .unwrap();
+        if let SinkOutput::Control(packet) = output {
+            assert_eq!(packet.packet_type(), PacketType::Stop);
+            sink.send_packet(PacketType::StopAck, Default::default())
+                .await
+                .unwrap();
+            break;
+        }
+    }
+    let stop_ack = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let data = source_transport.recv().await.unwrap();
+            let (packet, _) = Packet::parse(&data).unwrap();
+            if packet.packet_type() == PacketType::StopAck {
+                return packet;
+            }
+        }
+    })
+    .await
+    .expect("no STOP_ACK");
+    assert!(stop_ack.payload.is_empty());
+
+    source_transport.close().await;
+    relay.await.unwrap();
+
+    // Every frame the sink decoded is the one the source encoded, byte for
+    // byte, and in order
+    let sent = sent.lock().unwrap();
+    let received = received.lock().unwrap();
+    assert!(received.len() as u64 >= FRAME_COUNT);
+    assert!(sent.len() >= received.len());
+    for (i, data) in received.iter().enumerate() {
+        assert!(sent.contains(data), "frame {} corrupted", i);
+    }
+    let order: Vec<usize> = received
+        .iter()
+        .map(|data| sent.iter().position(|s| s == data).unwrap())
+        .collect();
+    assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(received
+        .iter()
+        .any(|data| data.len() > 2 * MAX_SEGMENT_SIZE));
+
+    let log = log.lock().unwrap();
+    check_wire(&log);
+    let frames_on_wire = log
+        .iter()
+        .filter(|(_, packet)| packet.packet_type() == PacketType::Frame)
+        .filter(|(_, packet)| FrameHeader::parse(&packet.payload).unwrap().segment_index == 0)
+        .count() as u64;
+    assert_eq!(frames_on_wire, stats.frames_sent);
+    let packet_types: Vec<(Direction, PacketType)> = log
+        .iter()
+        .map(|(direction, packet)| (*direction, packet.packet_type()))
+        .collect();
+    assert_eq!(
+        packet_types[..4],
+        [
+            (Direction::ToSink, PacketType::Hello),
+            (Direction::ToSource, PacketType::HelloAck),
+            (Direction::ToSink, PacketType::Start),
+            (Direction::ToSource, PacketType::StartAck),
+        ]
+    );
+    // Acks for frames that arrived before STOP may still follow it
+    let last = |direction| {
+        packet_types
+            .iter()
+            .rev()
+            .find(|(d, _)| *d == direction)
+            .map(|(_, packet_type)| *packet_type)
+    };
+    assert_eq!(last(Direction::ToSink), Some(PacketType::Stop));
+    assert_eq!(last(Direction::ToSource), Some(PacketType::StopAck));
+}
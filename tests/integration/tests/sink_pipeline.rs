@@ -0,0 +1,1030 @@
+//! SinkPipeline over MockTransport with NullEncoder output and PassthroughDecoder
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use serialwarp_core::{
+    error_codes, unix_time_us, CreditUpdatePayload, DecodeError, DecodeEvent, DecodeOutput,
+    DecodedFrame, EncodedFrame, EncoderConfig, ErrorPayload, FrameAckBatchPayload, FrameAckPayload,
+    HelloPayload, LatencySei, NullEncoder, Packet, PacketType, PassthroughDecoder, PausePayload,
+    PingPayload, PipelineError, ProtocolError, Resolution, SkipMode, StartAckPayload, StartPayload,
+    StopPayload, StopReason, VideoDecoder, VideoEncoder, MAX_SEGMENT_SIZE,
+};
+use serialwarp_pipeline::{
+    SinkHandshake, SinkOutput, SinkPipeline, SinkPipelineConfig, StallEvent, DEFAULT_STALL_TIMEOUT,
+};
+use serialwarp_transport::{MockTransport, Transport};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 32;
+
+/// Encoded frames, padded past one segment so every frame is reassembled
+/// from two
+fn recorded_frames(count: u64) -> Vec<EncodedFrame> {
+    let mut encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    });
+    let bgra = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+
+    let mut frames = Vec::new();
+    for pts in 0..count {
+        encoder
+            .encode_raw(&bgra, WIDTH as usize * 4, pts * 1000, false)
+            .unwrap();
+        let mut frame = encoder.next_frame().unwrap();
+        let mut data = frame.data.to_vec();
+        data.resize(MAX_SEGMENT_SIZE + 16, 0);
+        frame.data = data.into();
+        frames.push(frame);
+    }
+    frames
+}
+
+fn frame_packets(frame: EncodedFrame) -> Vec<Packet> {
+    frame
+        .into_segments()
+        .iter()
+        .map(|segment| Packet::new(PacketType::Frame, 0, 0, segment.to_payload()))
+        .collect()
+}
+
+/// A pipeline as negotiated with a `SourcePipeline`, which takes keyframe
+/// requests and CREDIT_UPDATE
+fn pipeline(transport: MockTransport, queue_depth: usize) -> SinkPipeline {
+    SinkPipeline::new(
+        transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth,
+            keyframe_requests: true,
+            credit_updates: true,
+            ..Default::default()
+        },
+        5,
+    )
+}
+
+/// Packets the pipeline sent, by type
+async fn sent_packets(peer: &MockTransport) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    while let Ok(Ok(data)) = tokio::time::timeout(Duration::from_millis(10), peer.recv()).await {
+        packets.push(Packet::parse(&data).unwrap().0);
+    }
+    packets
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_decodes_and_acks() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    for frame in recorded_frames(4) {
+        let mut packets = frame_packets(frame);
+        assert_eq!(packets.len(), 2);
+        let last = packets.pop().unwrap();
+        for packet in packets {
+            assert!(matches!(
+                pipeline.handle_packet(packet).await.unwrap(),
+                SinkOutput::Segment
+            ));
+        }
+        match pipeline.handle_packet(last).await.unwrap() {
+            SinkOutput::Frame {
+                frame, pictures, ..
+            } => {
+                assert_eq!(frame.data.len(), MAX_SEGMENT_SIZE + 16);
+                assert_eq!(pictures, 1);
+            }
+            other => panic!("expected a frame, got {:?}", other),
+        }
+    }
+
+    let mut frame_numbers = Vec::new();
+    while let Some(frame) = pipeline.next_decoded_frame() {
+        assert_eq!((frame.width, frame.height), (WIDTH, HEIGHT));
+        frame_numbers.push(frame.frame_number);
+    }
+    assert_eq!(frame_numbers, vec![0, 1, 2, 3]);
+
+    let acks = sent_packets(&peer).await;
+    assert_eq!(acks.len(), 4);
+    for (n, packet) in acks.iter().enumerate() {
+        assert_eq!(packet.packet_type(), PacketType::FrameAck);
+        assert_eq!(packet.sequence(), 5 + n as u32);
+        let ack = FrameAckPayload::parse(&packet.payload).unwrap();
+        assert_eq!(ack.frame_number, n as u64);
+        assert_eq!(ack.credits_returned, 1);
+    }
+    assert_eq!(pipeline.sequence(), 9);
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.segments_received, 8);
+    assert_eq!(stats.frames_received, 4);
+    assert_eq!(stats.frames_decoded, 4);
+    assert_eq!(stats.acks_sent, 4);
+    assert_eq!(stats.decode_errors, 0);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_acks_undecodable_frames() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    let mut frames = recorded_frames(4);
+    // Odd dimensions, which PassthroughDecoder rejects
+    let mut data = frames[1].data.to_vec();
+    data[0] = 3;
+    frames[1].data = data.into();
+    for frame in frames {
+        for packet in frame_packets(frame) {
+            pipeline.handle_packet(packet).await.unwrap();
+        }
+    }
+
+    // NullEncoder output never looks like an IDR, so everything after the
+    // error is skipped, but every frame still returns its credit
+    let stats = pipeline.stats();
+    assert_eq!(stats.frames_received, 4);
+    assert_eq!(stats.frames_decoded, 1);
+    assert_eq!(stats.decode_errors, 1);
+    assert_eq!(stats.frames_awaiting_keyframe, 2);
+    assert_eq!(stats.keyframe_requests, 1);
+    assert_eq!(stats.acks_sent, 4);
+
+    let packets = sent_packets(&peer).await;
+    let types: Vec<_> = packets.iter().map(Packet::packet_type).collect();
+    assert_eq!(
+        types,
+        vec![
+            PacketType::FrameAck,
+            PacketType::Error,
+            PacketType::FrameAck,
+            PacketType::FrameAck,
+            PacketType::FrameAck,
+        ]
+    );
+    let request = ErrorPayload::parse(&packets[1].payload).unwrap();
+    assert_eq!(request.code, error_codes::DECODER_FAILED);
+    assert!(!request.fatal);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_acks_evicted_frames() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    // Only the first half of frame 1 arrives
+    let mut frames = recorded_frames(2).into_iter();
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    let lost = frame_packets(frames.next().unwrap()).remove(0);
+    pipeline.handle_packet(lost).await.unwrap();
+    sent_packets(&peer).await;
+
+    pipeline.reap(Instant::now() + Duration::from_secs(1)).await;
+    let sent = sent_packets(&peer).await;
+    let types: Vec<_> = sent.iter().map(Packet::packet_type).collect();
+    assert_eq!(types, [PacketType::FrameAck, PacketType::Error]);
+
+    // Its credit comes back, and a keyframe is asked for in place of the
+    // frames that may reference it
+    let ack = FrameAckBatchPayload::parse(&sent[0].payload).unwrap();
+    assert_eq!(ack.entries.len(), 1);
+    assert_eq!(ack.entries[0].frame_number, 1);
+    assert_eq!(ack.credits_returned, 1);
+    let request = ErrorPayload::parse(&sent[1].payload).unwrap();
+    assert_eq!(request.code, error_codes::DECODER_FAILED);
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.frames_evicted, 1);
+    assert_eq!(stats.keyframe_requests, 1);
+    assert_eq!(stats.acks_sent, 2);
+    assert_eq!(stats.source_credits, 8);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_acks_rejected_frames() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    // Every segment of frame 1 arrives, but they declare a byte more than
+    // they carry
+    let mut frames = recorded_frames(2).into_iter();
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    sent_packets(&peer).await;
+    for mut segment in frames.next().unwrap().into_segments() {
+        segment.frame_size += 1;
+        let packet = Packet::new(PacketType::Frame, 0, 0, segment.to_payload());
+        assert!(matches!(
+            pipeline.handle_packet(packet).await.unwrap(),
+            SinkOutput::Segment
+        ));
+    }
+
+    // Its credit comes back at once, and a keyframe replaces it
+    let sent = sent_packets(&peer).await;
+    let types: Vec<_> = sent.iter().map(Packet::packet_type).collect();
+    assert_eq!(types, [PacketType::FrameAck, PacketType::Error]);
+    let ack = FrameAckBatchPayload::parse(&sent[0].payload).unwrap();
+    assert_eq!(ack.entries.len(), 1);
+    assert_eq!(ack.entries[0].frame_number, 1);
+    assert_eq!(ack.credits_returned, 1);
+    assert_eq!(pipeline.reassembler().stats().frames_rejected, 1);
+
+    // Nothing is left to time out and be acked again
+    pipeline.reap(Instant::now() + Duration::from_secs(1)).await;
+    assert!(sent_packets(&peer).await.is_empty());
+    let stats = pipeline.stats();
+    assert_eq!(stats.keyframe_requests, 1);
+    assert_eq!(stats.acks_sent, 2);
+    assert_eq!(stats.source_credits, 8);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_skips_truncated_segment() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    let truncated = Packet::new(PacketType::Frame, 0, 0, vec![0u8; 5].into());
+    assert!(matches!(
+        pipeline.handle_packet(truncated).await.unwrap(),
+        SinkOutput::Segment
+    ));
+
+    // Frames after it are taken as usual
+    receive(&mut pipeline, recorded_frames(1).remove(0)).await;
+    assert_eq!(pipeline.stats().frames_decoded, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_queue_drops_oldest() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 2);
+
+    for frame in recorded_frames(5) {
+        for packet in frame_packets(frame) {
+            pipeline.handle_packet(packet).await.unwrap();
+        }
+    }
+
+    assert_eq!(pipeline.next_decoded_frame().unwrap().frame_number, 3);
+    assert_eq!(pipeline.next_decoded_frame().unwrap().frame_number, 4);
+    assert!(pipeline.next_decoded_frame().is_none());
+    assert_eq!(pipeline.stats().frames_dropped_late, 3);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_rebuilds_lost_segment_from_parity() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    for frame in recorded_frames(3) {
+        let mut segments = frame.into_segments_with_parity(2);
+        // Both data segments and the parity covering them
+        assert_eq!(segments.len(), 3);
+        segments.remove(0);
+
+        let mut frames = 0;
+        for segment in segments {
+            let packet = Packet::new(PacketType::Frame, 0, 0, segment.to_payload());
+            if let SinkOutput::Frame { frame, .. } = pipeline.handle_packet(packet).await.unwrap() {
+                assert_eq!(frame.data.len(), MAX_SEGMENT_SIZE + 16);
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 1);
+    }
+
+    assert_eq!(pipeline.stats().frames_decoded, 3);
+    assert_eq!(pipeline.reassembler().stats().segments_recovered, 3);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_asks_for_keyframe_when_parity_cant_rebuild() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    // Both data segments of the frame's one group are lost
+    let frame = recorded_frames(1).remove(0);
+    let mut segments = frame.into_segments_with_parity(2);
+    segments.drain(..2);
+    let parity = Packet::new(PacketType::Frame, 0, 0, segments[0].to_payload());
+    assert!(matches!(
+        pipeline.handle_packet(parity).await.unwrap(),
+        SinkOutput::Segment
+    ));
+
+    // The source is asked for a keyframe as soon as the parity is in
+    let types: Vec<_> = sent_packets(&peer)
+        .await
+        .iter()
+        .map(Packet::packet_type)
+        .collect();
+    assert_eq!(types, [PacketType::Error]);
+    assert_eq!(pipeline.stats().keyframe_requests, 1);
+    assert_eq!(pipeline.reassembler().stats().frames_unrecoverable, 1);
+
+    // Once it times out it's acked, without asking again
+    pipeline.reap(Instant::now() + Duration::from_secs(1)).await;
+    let types: Vec<_> = sent_packets(&peer)
+        .await
+        .iter()
+        .map(Packet::packet_type)
+        .collect();
+    assert_eq!(types, [PacketType::FrameAck]);
+    let stats = pipeline.stats();
+    assert_eq!(stats.frames_evicted, 1);
+    assert_eq!(stats.keyframe_requests, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_batches_acks() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: 8,
+            credits_per_frame: 2,
+            ack_batch: 3,
+            ack_batch_delay: Duration::from_secs(60),
+            ..Default::default()
+        },
+        0,
+    );
+
+    for frame in recorded_frames(7) {
+        for packet in frame_packets(frame) {
+            pipeline.handle_packet(packet).await.unwrap();
+        }
+    }
+    // Two full batches went out; the last frame waits for more
+    assert_eq!(pipeline.stats().ack_packets_sent, 2);
+    pipeline.flush_acks().await;
+
+    let batches: Vec<_> = sent_packets(&peer)
+        .await
+        .iter()
+        .map(|packet| {
+            assert_eq!(packet.packet_type(), PacketType::FrameAck);
+            FrameAckBatchPayload::parse(&packet.payload).unwrap()
+        })
+        .collect();
+    let sizes: Vec<_> = batches.iter().map(|batch| batch.entries.len()).collect();
+    assert_eq!(sizes, vec![3, 3, 1]);
+
+    // The same frames and credits as one ack per frame
+    let frame_numbers: Vec<_> = batches
+        .iter()
+        .flat_map(|batch| batch.entries.iter().map(|entry| entry.frame_number))
+        .collect();
+    assert_eq!(frame_numbers, (0..7).collect::<Vec<_>>());
+    let credits: u32 = batches
+        .iter()
+        .map(|batch| batch.credits_returned as u32)
+        .sum();
+    assert_eq!(credits, 14);
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.acks_sent, 7);
+    assert_eq!(stats.ack_packets_sent, 3);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_sends_overdue_acks_while_waiting() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            ack_batch: 8,
+            ack_batch_delay: Duration::from_millis(5),
+            ..Default::default()
+        },
+        0,
+    );
+
+    for packet in recorded_frames(1).into_iter().flat_map(frame_packets) {
+        peer.send(packet.to_bytes()).await.unwrap();
+    }
+    let mut frames = 0;
+    while frames == 0 {
+        if let SinkOutput::Frame { .. } = pipeline.recv().await.unwrap() {
+            frames += 1;
+        }
+    }
+    assert_eq!(pipeline.stats().ack_packets_sent, 0);
+
+    // Nothing else arrives, but the ack goes out once its deadline passes
+    let waited = tokio::time::timeout(Duration::from_millis(100), pipeline.recv()).await;
+    assert!(waited.is_err());
+    let (packet, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+    let ack = FrameAckBatchPayload::parse(&packet.payload).unwrap();
+    assert_eq!(ack.entries.len(), 1);
+    assert_eq!(ack.credits_returned, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_credit_update() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 2);
+
+    pipeline.update_credits(-3).await.unwrap();
+    let (packet, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+    assert_eq!(packet.packet_type(), PacketType::CreditUpdate);
+    assert_eq!(packet.sequence(), 5);
+    assert_eq!(
+        CreditUpdatePayload::parse(&packet.payload).unwrap().delta,
+        -3
+    );
+    assert_eq!(pipeline.stats().credit_updates_sent, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_sends_only_what_source_takes() {
+    // A source that advertised neither keyframe requests nor CREDIT_UPDATE
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: 1,
+            max_withheld_credits: 3,
+            ..Default::default()
+        },
+        5,
+    );
+
+    // The second can't be decoded, which would ask for a keyframe
+    let mut frames = recorded_frames(4);
+    let mut data = frames[1].data.to_vec();
+    data[0] = 3;
+    frames[1].data = data.into();
+    for frame in frames {
+        receive(&mut pipeline, frame).await;
+    }
+    pipeline.update_credits(-3).await.unwrap();
+    let start = Instant::now();
+    pipeline.check_stall(start).await;
+    pipeline.check_stall(start + DEFAULT_STALL_TIMEOUT).await;
+
+    // Its credits all come back with the acks, though the queue is full
+    let types: Vec<_> = sent_packets(&peer)
+        .await
+        .iter()
+        .map(Packet::packet_type)
+        .collect();
+    assert_eq!(
+        types,
+        [[PacketType::FrameAck; 4].as_slice(), &[PacketType::Ping]].concat()
+    );
+    let stats = pipeline.stats();
+    assert_eq!(stats.decode_errors, 1);
+    assert_eq!(stats.keyframe_requests, 0);
+    assert_eq!(stats.credit_updates_sent, 0);
+    assert_eq!(stats.credits_withheld, 0);
+    assert_eq!(stats.stalls, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_passes_control_packets_through() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 2);
+
+    let stop = StopPayload::new(StopReason::UserRequested, true);
+    let stop = Packet::new(PacketType::Stop, 0, 0, stop.to_bytes());
+    peer.send(stop.to_bytes()).await.unwrap();
+
+    match pipeline.recv().await.unwrap() {
+        SinkOutput::Control(packet) => {
+            assert_eq!(packet.packet_type(), PacketType::Stop);
+            assert!(StopPayload::parse(&packet.payload).unwrap().reconnect_hint);
+        }
+        other => panic!("expected a control packet, got {:?}", other),
+    }
+    assert_eq!(pipeline.stats().segments_received, 0);
+}
+
+/// `PassthroughDecoder` that records skip mode changes and, since it
+/// can't tell keyframes apart, skips every frame while skipping at all
+struct SkippingDecoder {
+    inner: PassthroughDecoder,
+    modes: Rc<RefCell<Vec<SkipMode>>>,
+}
+
+impl VideoDecoder for SkippingDecoder {
+    fn decode(&mut self, data: &[u8], pts_us: i64) -> Result<DecodeOutput, DecodeError> {
+        match self.modes.borrow().last() {
+            Some(SkipMode::NonRef | SkipMode::NonKey) => Ok(DecodeOutput::default()),
+            _ => self.inner.decode(data, pts_us),
+        }
+    }
+
+    fn flush(&mut self) -> Result<Vec<DecodedFrame>, DecodeError> {
+        self.inner.flush()
+    }
+
+    fn reconfigure(&mut self) -> Result<(), DecodeError> {
+        self.inner.reconfigure()
+    }
+
+    fn set_skip_mode(&mut self, mode: SkipMode) -> Result<(), DecodeError> {
+        self.modes.borrow_mut().push(mode);
+        Ok(())
+    }
+}
+
+fn catch_up_pipeline(
+    transport: MockTransport,
+    catch_up_mode: SkipMode,
+) -> (SinkPipeline, Rc<RefCell<Vec<SkipMode>>>) {
+    let modes = Rc::new(RefCell::new(Vec::new()));
+    let decoder = SkippingDecoder {
+        inner: PassthroughDecoder::new(),
+        modes: Rc::clone(&modes),
+    };
+    let config = SinkPipelineConfig {
+        queue_depth: 8,
+        catch_up_backlog: 4,
+        catch_up_mode,
+        keyframe_requests: true,
+        ..Default::default()
+    };
+    let pipeline = SinkPipeline::new(transport.split(), Box::new(decoder), config, 5);
+    (pipeline, modes)
+}
+
+async fn receive(pipeline: &mut SinkPipeline, frame: EncodedFrame) {
+    for packet in frame_packets(frame) {
+        pipeline.handle_packet(packet).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_withholds_credits_while_queue_full() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: 2,
+            initial_credits: 8,
+            max_withheld_credits: 3,
+            credit_updates: true,
+            ..Default::default()
+        },
+        5,
+    );
+
+    // Nothing taken from the queue, so it's full from the second frame on,
+    // and credits are withheld until three are
+    for frame in recorded_frames(5) {
+        receive(&mut pipeline, frame).await;
+    }
+    let credits: Vec<u16> = sent_packets(&peer)
+        .await
+        .iter()
+        .map(|packet| {
+            assert_eq!(packet.packet_type(), PacketType::FrameAck);
+            FrameAckBatchPayload::parse(&packet.payload)
+                .unwrap()
+                .credits_returned
+        })
+        .collect();
+    assert_eq!(credits, vec![1, 0, 0, 0, 1]);
+    let stats = pipeline.stats();
+    assert_eq!(stats.credits_withheld, 3);
+    assert_eq!(stats.source_credits, 8 - 5 + 2);
+
+    // Taking one frame drains it to half, which is enough to grant them back
+    pipeline.next_decoded_frame().unwrap();
+    pipeline.flush_due_acks(std::time::Instant::now()).await;
+    assert_eq!(pipeline.stats().credits_withheld, 0);
+    let (packet, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+    assert_eq!(packet.packet_type(), PacketType::CreditUpdate);
+    assert_eq!(
+        CreditUpdatePayload::parse(&packet.payload).unwrap().delta,
+        3
+    );
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.source_credits, 8);
+    assert_eq!(stats.credit_updates_sent, 1);
+    assert_eq!(stats.credit_starvations, 0);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_skips_to_catch_up() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let (mut pipeline, modes) = catch_up_pipeline(sink_transport, SkipMode::NonKey);
+
+    let mut frames = recorded_frames(8).into_iter();
+
+    // Five frames queue up undisturbed; the sixth finds too many waiting
+    for frame in frames.by_ref().take(6) {
+        receive(&mut pipeline, frame).await;
+    }
+    assert_eq!(*modes.borrow(), [SkipMode::NonKey]);
+    assert_eq!(pipeline.skip_mode(), SkipMode::NonKey);
+    let stats = pipeline.stats();
+    assert_eq!((stats.frames_decoded, stats.frames_skipped), (5, 1));
+    assert_eq!(stats.keyframe_requests, 1);
+
+    // Caught up, but frames only decode again from a keyframe
+    while pipeline.next_decoded_frame().is_some() {}
+    assert_eq!(pipeline.backlog(), 0);
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    assert_eq!(pipeline.stats().frames_skipped, 2);
+
+    let mut keyframe = frames.next().unwrap();
+    // An IDR slice after NullEncoder's header, which the decoder ignores
+    keyframe.data = [&keyframe.data[..], &[0, 0, 0, 1, 0x65, 0x88]]
+        .concat()
+        .into();
+    receive(&mut pipeline, keyframe).await;
+    assert_eq!(*modes.borrow(), [SkipMode::NonKey, SkipMode::None]);
+    let stats = pipeline.stats();
+    assert_eq!((stats.frames_decoded, stats.frames_skipped), (6, 2));
+    assert_eq!(pipeline.next_decoded_frame().unwrap().pts_us, 7000);
+
+    let requests = sent_packets(&peer)
+        .await
+        .into_iter()
+        .filter(|packet| packet.packet_type() == PacketType::Error)
+        .count();
+    assert_eq!(requests, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_consumer_backlog() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let (mut pipeline, modes) = catch_up_pipeline(sink_transport, SkipMode::NonRef);
+
+    // Frames the consumer holds count, and skipping stops once the backlog
+    // halves without waiting for a keyframe
+    let mut held = [5, 3, 2].into_iter();
+    for frame in recorded_frames(3) {
+        pipeline.set_consumer_backlog(held.next().unwrap());
+        receive(&mut pipeline, frame).await;
+        while pipeline.next_decoded_frame().is_some() {}
+    }
+
+    assert_eq!(*modes.borrow(), [SkipMode::NonRef, SkipMode::None]);
+    let stats = pipeline.stats();
+    assert_eq!((stats.frames_decoded, stats.frames_skipped), (1, 2));
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_latency_from_sei() {
+    // The source's clock runs 5s ahead of ours
+    const SOURCE_AHEAD_US: u64 = 5_000_000_000;
+
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    let ping = PingPayload::new(unix_time_us() + SOURCE_AHEAD_US);
+    let ping = Packet::new(PacketType::Ping, 0, 0, ping.to_bytes());
+    assert!(matches!(
+        pipeline.handle_packet(ping).await.unwrap(),
+        SinkOutput::Control(packet) if packet.packet_type() == PacketType::Ping
+    ));
+
+    // One frame captured 20ms ago by the source's clock, one without a time
+    let mut frames = recorded_frames(2);
+    let captured = unix_time_us() + SOURCE_AHEAD_US - 20_000;
+    frames[0].data = LatencySei::new(captured, 0)
+        .insert_into(&frames[0].data)
+        .into();
+    for frame in frames {
+        for packet in frame_packets(frame) {
+            pipeline.handle_packet(packet).await.unwrap();
+        }
+    }
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.frames_decoded, 2);
+    assert_eq!(stats.latency_samples, 1);
+    let latency = stats.average_latency().unwrap();
+    assert!(
+        latency >= Duration::from_millis(20) && latency < Duration::from_secs(1),
+        "{:?}",
+        latency
+    );
+
+    let timed = pipeline.next_decoded_frame().unwrap();
+    assert_eq!(timed.source_timestamp_us, Some(captured));
+    assert!(pipeline.latency(&timed).unwrap() >= latency);
+    let untimed = pipeline.next_decoded_frame().unwrap();
+    assert_eq!(pipeline.latency(&untimed), None);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_latency_report_from_header() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+    assert_eq!(pipeline.latency_report(), None);
+
+    // Capture times in the FRAME header, 20ms ago, and one that's a pts
+    // rather than a wall-clock time
+    let mut frames = recorded_frames(3);
+    frames[0].metadata.capture_ts_us = unix_time_us() - 20_000;
+    frames[1].metadata.capture_ts_us = unix_time_us() - 20_000;
+    for frame in frames {
+        for packet in frame_packets(frame) {
+            pipeline.handle_packet(packet).await.unwrap();
+        }
+    }
+    assert_eq!(pipeline.stats().latency_samples, 2);
+
+    let mut presented = Vec::new();
+    while let Some(frame) = pipeline.next_decoded_frame() {
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        presented.push(pipeline.frame_presented(frame.frame_number));
+    }
+    assert!(presented[0].unwrap() >= Duration::from_millis(22));
+    assert!(presented[1].is_some());
+    assert_eq!(presented[2], None);
+    // Only once
+    assert_eq!(pipeline.frame_presented(0), None);
+
+    let report = pipeline.latency_report().unwrap();
+    assert_eq!(report.samples, 2);
+    assert!(report.average >= Duration::from_millis(22));
+    assert!(report.average < Duration::from_secs(1), "{}", report);
+    assert!(report.p95 >= report.average && report.max == report.p95);
+    assert!(report.stages.total() <= report.average);
+    assert!(report.stages.present >= Duration::from_millis(2));
+    assert!(report.stages.transport >= Duration::from_millis(20));
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_reports_resolution_change() {
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            queue_depth: 8,
+            display_size: Some(Resolution::new(60, 30)),
+            ..Default::default()
+        },
+        5,
+    );
+
+    let mut encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut events = Vec::new();
+    for pts in 0..4 {
+        if pts == 2 {
+            let config = EncoderConfig {
+                width: WIDTH * 2,
+                height: HEIGHT * 2,
+                ..encoder.config().clone()
+            };
+            encoder.reconfigure(config).unwrap();
+        }
+        let width = encoder.config().width;
+        let bgra = vec![0u8; (width * encoder.config().height * 4) as usize];
+        encoder
+            .encode_raw(&bgra, width as usize * 4, pts * 1000, false)
+            .unwrap();
+        let frame = encoder.next_frame().unwrap();
+        for packet in frame_packets(frame) {
+            if let SinkOutput::Frame { events: new, .. } =
+                pipeline.handle_packet(packet).await.unwrap()
+            {
+                events.push(new);
+            }
+        }
+    }
+
+    let changed = DecodeEvent::ResolutionChanged {
+        old: Resolution::new(WIDTH, HEIGHT),
+        new: Resolution::new(WIDTH * 2, HEIGHT * 2),
+    };
+    assert_eq!(events, [vec![], vec![], vec![changed], vec![]]);
+
+    // START's visible size was for the old resolution, so it's dropped
+    let mut sizes = Vec::new();
+    while let Some(frame) = pipeline.next_decoded_frame() {
+        sizes.push((frame.width, frame.height));
+    }
+    assert_eq!(sizes, [(60, 30), (60, 30), (128, 64), (128, 64)]);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_reports_stall() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+    let mut frames = recorded_frames(2).into_iter();
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    sent_packets(&peer).await;
+
+    // Frames stop though the transport is up
+    let start = Instant::now();
+    assert_eq!(pipeline.check_stall(start).await, None);
+    match pipeline.check_stall(start + DEFAULT_STALL_TIMEOUT).await {
+        Some(StallEvent::Stalled { waited, probes: 1 }) => {
+            assert!(waited >= DEFAULT_STALL_TIMEOUT)
+        }
+        other => panic!("expected a stall, got {:?}", other),
+    }
+    assert!(pipeline.is_stalled());
+
+    // The source is asked for a keyframe, then PINGed
+    let sent = sent_packets(&peer).await;
+    let types: Vec<_> = sent.iter().map(Packet::packet_type).collect();
+    assert_eq!(types, [PacketType::Error, PacketType::Ping]);
+    let request = ErrorPayload::parse(&sent[0].payload).unwrap();
+    assert_eq!(request.code, error_codes::DECODER_FAILED);
+    assert!(!request.fatal);
+
+    // And again after each further timeout
+    assert_eq!(
+        pipeline.check_stall(start + DEFAULT_STALL_TIMEOUT).await,
+        None
+    );
+    assert!(matches!(
+        pipeline
+            .check_stall(start + DEFAULT_STALL_TIMEOUT * 2)
+            .await,
+        Some(StallEvent::Stalled { probes: 2, .. })
+    ));
+    assert_eq!(sent_packets(&peer).await.len(), 2);
+
+    // A frame ends the stall
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    assert!(matches!(
+        pipeline.check_stall(Instant::now()).await,
+        Some(StallEvent::Recovered { .. })
+    ));
+    assert!(!pipeline.is_stalled());
+
+    let stats = pipeline.stats();
+    assert_eq!(stats.stalls, 1);
+    assert_eq!(stats.keyframe_requests, 2);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_no_stall_while_source_paused() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+    let mut frames = recorded_frames(2).into_iter();
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    sent_packets(&peer).await;
+
+    // The source says frames stop, e.g. paused or nothing on screen changing
+    let pause = Packet::new(PacketType::Pause, 0, 0, PausePayload::new(true).to_bytes());
+    assert!(matches!(
+        pipeline.handle_packet(pause).await.unwrap(),
+        SinkOutput::Control(_)
+    ));
+    let start = Instant::now();
+    assert_eq!(
+        pipeline
+            .check_stall(start + DEFAULT_STALL_TIMEOUT * 10)
+            .await,
+        None
+    );
+    assert!(sent_packets(&peer).await.is_empty());
+
+    // Once they start again, a gap is a stall as before
+    let resume = Packet::new(PacketType::Pause, 0, 1, PausePayload::new(false).to_bytes());
+    pipeline.handle_packet(resume).await.unwrap();
+    let resumed = Instant::now();
+    assert_eq!(
+        pipeline
+            .check_stall(resumed + DEFAULT_STALL_TIMEOUT / 2)
+            .await,
+        None
+    );
+    assert!(matches!(
+        pipeline.check_stall(resumed + DEFAULT_STALL_TIMEOUT).await,
+        Some(StallEvent::Stalled { probes: 1, .. })
+    ));
+    receive(&mut pipeline, frames.next().unwrap()).await;
+    assert_eq!(pipeline.stats().stalls, 1);
+}
+
+#[tokio::test]
+async fn test_sink_pipeline_no_stall_while_suspended() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let mut pipeline = pipeline(sink_transport, 8);
+
+    // E.g. waiting for the source to reconnect with a new configuration
+    pipeline.set_watchdog_suspended(true);
+    let start = Instant::now();
+    assert_eq!(
+        pipeline
+            .check_stall(start + DEFAULT_STALL_TIMEOUT * 10)
+            .await,
+        None
+    );
+    assert!(sent_packets(&peer).await.is_empty());
+
+    // The wait starts over on resuming
+    pipeline.set_watchdog_suspended(false);
+    let resumed = Instant::now();
+    assert_eq!(
+        pipeline
+            .check_stall(resumed + DEFAULT_STALL_TIMEOUT / 2)
+            .await,
+        None
+    );
+    assert!(matches!(
+        pipeline.check_stall(resumed + DEFAULT_STALL_TIMEOUT).await,
+        Some(StallEvent::Stalled { probes: 1, .. })
+    ));
+
+    // Suspending a stalled stream ends the stall
+    pipeline.set_watchdog_suspended(true);
+    assert!(matches!(
+        pipeline.check_stall(resumed + DEFAULT_STALL_TIMEOUT).await,
+        Some(StallEvent::Recovered { .. })
+    ));
+    assert_eq!(pipeline.stats().stalls, 1);
+
+    // With the watchdog off, nothing is watched at all
+    let (sink_transport, _peer) = MockTransport::pair();
+    let mut pipeline = SinkPipeline::new(
+        sink_transport.split(),
+        Box::new(PassthroughDecoder::new()),
+        SinkPipelineConfig {
+            stall_timeout: None,
+            ..Default::default()
+        },
+        5,
+    );
+    assert_eq!(
+        pipeline
+            .check_stall(start + DEFAULT_STALL_TIMEOUT * 10)
+            .await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_sink_handshake() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let handshake = SinkHandshake {
+        max_width: 1920,
+        max_height: 1080,
+        initial_credits: 3,
+        ..Default::default()
+    };
+    let sink = tokio::spawn(async move { handshake.accept(&sink_transport, 7).await });
+
+    let hello = HelloPayload::new(1, 3840, 2160, 60, 0);
+    let hello = Packet::new(PacketType::Hello, 0, 0, hello.to_bytes());
+    peer.send(hello.to_bytes()).await.unwrap();
+    let (ack, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+    assert_eq!(ack.packet_type(), PacketType::HelloAck);
+    assert_eq!(ack.sequence(), 7);
+    let ack = HelloPayload::parse(&ack.payload).unwrap();
+    assert_eq!((ack.max_width, ack.max_height), (1920, 1080));
+
+    let start = StartPayload::new(WIDTH, HEIGHT, 30, 1_000_000);
+    let start = Packet::new(PacketType::Start, 0, 1, start.to_bytes());
+    peer.send(start.to_bytes()).await.unwrap();
+    let (ack, _) = Packet::parse(&peer.recv().await.unwrap()).unwrap();
+    assert_eq!(ack.packet_type(), PacketType::StartAck);
+    let ack = StartAckPayload::parse(&ack.payload).unwrap();
+    assert!(ack.is_ok());
+    assert_eq!(ack.initial_credits, 3);
+
+    let negotiated = sink.await.unwrap().unwrap();
+    assert_eq!(negotiated.hello.max_width, 3840);
+    assert_eq!(
+        (negotiated.start.width, negotiated.start.height),
+        (WIDTH, HEIGHT)
+    );
+    assert_eq!(negotiated.start.fps(), 30);
+    assert_eq!(negotiated.sequence, 9);
+}
+
+#[tokio::test]
+async fn test_sink_handshake_rejects_unexpected_packet() {
+    let (sink_transport, peer) = MockTransport::pair();
+    let sink =
+        tokio::spawn(async move { SinkHandshake::default().accept(&sink_transport, 0).await });
+
+    let start = StartPayload::new(WIDTH, HEIGHT, 30, 1_000_000);
+    let start = Packet::new(PacketType::Start, 0, 0, start.to_bytes());
+    peer.send(start.to_bytes()).await.unwrap();
+
+    assert!(matches!(
+        sink.await.unwrap(),
+        Err(PipelineError::Protocol(
+            ProtocolError::UnexpectedPacketType {
+                expected: "HELLO",
+                ..
+            }
+        ))
+    ));
+}
@@ -0,0 +1,1042 @@
+//! SourcePipeline over MockTransport with the test pattern and NullEncoder
+
+use std::time::Duration;
+
+use serialwarp_core::{
+    unix_time_us, CaptureError, CapturedFrame, CreditUpdatePayload, EncodeError, EncodedFrame,
+    EncoderConfig, FrameAckBatchPayload, FrameAckEntry, FrameHeader, FrameReassembler, FrameSource,
+    NullEncoder, Packet, PacketType, PausePayload, PingPayload, PipelineError, StopPayload,
+    StopReason, TransportError, VideoEncoder,
+};
+use serialwarp_pipeline::{
+    KeyframeReason, SourceEvent, SourcePipeline, SourcePipelineConfig, StallCause,
+};
+use serialwarp_testsrc::{TestPatternConfig, TestPatternSource};
+use serialwarp_transport::{MockTransport, Transport};
+use tokio::sync::mpsc;
+
+/// Smallest frame that fits the test pattern counter, so capture outpaces the sink
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 32;
+const FRAME_COUNT: u64 = 30;
+const INITIAL_CREDITS: u16 = 2;
+
+/// Slow sink: acknowledge complete frames with one credit each, taking a
+/// millisecond per frame, in FRAME_ACKs of `ack_batch` frames. After the
+/// frame at each index in `credit_updates`, the window is resized by the
+/// paired delta. Hands the transport back so the link stays up.
+async fn acking_sink(
+    transport: MockTransport,
+    ack_batch: usize,
+    credit_updates: &[(usize, i32)],
+) -> (Vec<u64>, MockTransport) {
+    let mut reassembler = FrameReassembler::new();
+    let mut received = Vec::new();
+    let mut pending = Vec::new();
+    let mut sequence = 0;
+
+    while (received.len() as u64) < FRAME_COUNT {
+        let data = transport.recv().await.unwrap();
+        let (packet, _) = Packet::parse(&data).unwrap();
+        assert_eq!(packet.packet_type(), PacketType::Frame);
+
+        let header = FrameHeader::parse(&packet.payload).unwrap();
+        let segment = packet.payload.slice(FrameHeader::SIZE..);
+        let Some(frame) = reassembler.add_segment(&header, segment).unwrap() else {
+            continue;
+        };
+        received.push(frame.metadata.frame_number);
+        // Stamped with the wall-clock time it was captured
+        let since_capture = unix_time_us() - frame.metadata.capture_ts_us;
+        assert!(since_capture < 1_000_000, "{}us", since_capture);
+
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        pending.push(FrameAckEntry {
+            frame_number: frame.metadata.frame_number,
+            decode_time_us: 1000,
+        });
+        if pending.len() >= ack_batch || received.len() as u64 == FRAME_COUNT {
+            let credits = pending.len() as u16;
+            let ack = FrameAckBatchPayload::new(std::mem::take(&mut pending), credits);
+            let ack = Packet::new(PacketType::FrameAck, 0, sequence, ack.to_bytes());
+            sequence += 1;
+            transport.send(ack.to_bytes()).await.unwrap();
+        }
+
+        for &(_, delta) in credit_updates
+            .iter()
+            .filter(|(index, _)| *index == received.len() - 1)
+        {
+            let update = CreditUpdatePayload::new(delta);
+            let update = Packet::new(PacketType::CreditUpdate, 0, sequence, update.to_bytes());
+            sequence += 1;
+            transport.send(update.to_bytes()).await.unwrap();
+        }
+    }
+
+    (received, transport)
+}
+
+/// Stream `FRAME_COUNT` frames to `acking_sink` and check that every credit
+/// the sink granted, through acks or window updates, is accounted for
+async fn check_delivery_and_credits(ack_batch: usize, credit_updates: &[(usize, i32)]) {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(
+        EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            ..Default::default()
+        }
+        .with_keyframe_interval_frames(1000),
+    );
+
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: INITIAL_CREDITS,
+            ..Default::default()
+        },
+    );
+    let mut events = pipeline.events().unwrap();
+    let updates = credit_updates.to_vec();
+    let sink = tokio::spawn(async move { acking_sink(sink_transport, ack_batch, &updates).await });
+    pipeline.start().unwrap();
+    assert!(pipeline.start().is_err());
+
+    let (received, _sink_transport) = sink.await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while pipeline.stats().frames_acked < FRAME_COUNT
+            || pipeline.stats().credit_updates < credit_updates.len() as u64
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("acks not processed");
+    pipeline.stop().await;
+
+    // Frames arrive complete and in order
+    assert_eq!(received, (0..FRAME_COUNT).collect::<Vec<_>>());
+
+    // Every encoded frame took a credit and every ack returned one
+    let stats = pipeline.stats();
+    let encoded = stats.frames_captured - stats.frames_skipped;
+    let window =
+        INITIAL_CREDITS as i64 + credit_updates.iter().map(|(_, d)| *d as i64).sum::<i64>();
+    let max_window = INITIAL_CREDITS as i64
+        + credit_updates
+            .iter()
+            .map(|(_, d)| (*d).max(0) as i64)
+            .sum::<i64>();
+    assert_eq!(stats.frames_acked, FRAME_COUNT);
+    assert!(stats.frames_sent <= encoded);
+    assert!((stats.frames_sent - stats.frames_acked) as i64 <= max_window);
+    assert_eq!(
+        stats.credits as i64,
+        window + stats.frames_acked as i64 - encoded as i64
+    );
+    assert_eq!(stats.keyframes_sent, 1);
+    assert_eq!(stats.keyframes_forced, 1);
+    assert!(stats.frames_skipped > 0);
+
+    let mut keyframe_sent = false;
+    let mut starved = false;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            SourceEvent::KeyframeForced { reason } => {
+                assert_eq!(reason, KeyframeReason::StreamStart);
+            }
+            SourceEvent::KeyframeSent { frame_number } => {
+                assert_eq!(frame_number, 0);
+                keyframe_sent = true;
+            }
+            SourceEvent::CreditStarvation { frames_in_flight } => {
+                assert!(frames_in_flight as i64 <= max_window);
+                starved = true;
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+    assert!(keyframe_sent);
+    assert!(starved);
+}
+
+#[tokio::test]
+async fn test_source_pipeline_delivery_and_credits() {
+    check_delivery_and_credits(1, &[]).await;
+}
+
+#[tokio::test]
+async fn test_source_pipeline_batched_acks() {
+    // Same accounting as acking every frame, with half the FRAME_ACKs
+    check_delivery_and_credits(INITIAL_CREDITS as usize, &[]).await;
+}
+
+#[tokio::test]
+async fn test_source_pipeline_credit_updates() {
+    // Grow the window early on, then give some of it back
+    check_delivery_and_credits(2, &[(0, 3), (FRAME_COUNT as usize / 2, -2)]).await;
+}
+
+#[tokio::test]
+async fn test_source_pipeline_stops_on_sink_stop() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig::default(),
+    );
+    let mut events = pipeline.events().unwrap();
+    pipeline.start().unwrap();
+
+    let stop = StopPayload::new(StopReason::UserRequested, false);
+    let stop = Packet::new(PacketType::Stop, 0, 0, stop.to_bytes());
+    sink_transport.send(stop.to_bytes()).await.unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        pipeline.shutdown_token().cancelled(),
+    )
+    .await
+    .expect("pipeline didn't stop");
+    pipeline.stop().await;
+
+    let mut stop_requested = false;
+    while let Ok(event) = events.try_recv() {
+        stop_requested |= matches!(
+            event,
+            SourceEvent::StopRequested {
+                reason: StopReason::UserRequested
+            }
+        );
+    }
+    assert!(stop_requested);
+}
+
+/// Encoder that loses the output of one input, as VideoToolbox does when it
+/// drops a frame under load
+struct DroppingEncoder {
+    inner: NullEncoder,
+    drop_at: u64,
+}
+
+impl VideoEncoder for DroppingEncoder {
+    fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        self.inner.encode_raw(data, stride, pts_us, force_keyframe)
+    }
+
+    fn next_frame(&mut self) -> Option<EncodedFrame> {
+        let frame = self.inner.next_frame()?;
+        if frame.metadata.frame_number == self.drop_at {
+            return self.inner.next_frame();
+        }
+        Some(frame)
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        self.inner.flush()
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+        self.inner.set_bitrate(bitrate)
+    }
+
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+        self.inner.reconfigure(config)
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        self.inner.config()
+    }
+}
+
+/// Ack every complete frame with one credit until the link goes down,
+/// returning the frame number and pts of each frame received
+async fn timing_sink(transport: MockTransport) -> Vec<(u64, u64)> {
+    let mut reassembler = FrameReassembler::new();
+    let mut received = Vec::new();
+    let mut sequence = 0;
+
+    while let Ok(data) = transport.recv().await {
+        let (packet, _) = Packet::parse(&data).unwrap();
+        let header = FrameHeader::parse(&packet.payload).unwrap();
+        let segment = packet.payload.slice(FrameHeader::SIZE..);
+        let Some(frame) = reassembler.add_segment(&header, segment).unwrap() else {
+            continue;
+        };
+        received.push((frame.metadata.frame_number, frame.metadata.pts_us));
+
+        let entry = FrameAckEntry {
+            frame_number: frame.metadata.frame_number,
+            decode_time_us: 1000,
+        };
+        let ack = FrameAckBatchPayload::new(vec![entry], 1);
+        let ack = Packet::new(PacketType::FrameAck, 0, sequence, ack.to_bytes());
+        sequence += 1;
+        if transport.send(ack.to_bytes()).await.is_err() {
+            break;
+        }
+    }
+
+    received
+}
+
+/// `timing_sink`, returning just the frame numbers
+async fn numbering_sink(transport: MockTransport) -> Vec<u64> {
+    timing_sink(transport)
+        .await
+        .into_iter()
+        .map(|(frame_number, _)| frame_number)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_source_pipeline_forces_keyframes() {
+    const DROP_AT: u64 = 20;
+
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let config = EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let encoder = DroppingEncoder {
+        inner: NullEncoder::new(config.clone()),
+        drop_at: DROP_AT,
+    };
+
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig::default(),
+    );
+    let mut events = pipeline.events().unwrap();
+    let sink = tokio::spawn(numbering_sink(sink_transport));
+    pipeline.start().unwrap();
+
+    // Wait for each forced keyframe before triggering the next, so none
+    // are merged into one
+    let mut keyframes = Vec::new();
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::StreamStart
+    );
+    pipeline.request_keyframe();
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Request
+    );
+    pipeline.reconfigure(EncoderConfig {
+        keyframe_interval: Duration::from_secs(30),
+        ..config
+    });
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Reconfigure
+    );
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Drop
+    );
+
+    // Let the keyframe after the drop reach the sink
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while pipeline.stats().keyframes_sent < 4 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("keyframes not sent");
+    pipeline.stop().await;
+    let stats = pipeline.stats();
+    while let Ok(event) = events.try_recv() {
+        if let SourceEvent::KeyframeSent { frame_number } = event {
+            keyframes.push(frame_number);
+        }
+    }
+    drop(pipeline);
+    let received = sink.await.unwrap();
+
+    assert_eq!(stats.keyframes_forced, 4);
+    assert_eq!(stats.keyframes_sent, 4);
+    assert_eq!(stats.frames_dropped, 1);
+    assert_eq!(keyframes.len(), 4);
+    assert_eq!(keyframes[0], 0);
+
+    // The drop is noticed on the frame after the gap and the next one is
+    // the forced keyframe
+    assert!(!received.contains(&DROP_AT));
+    assert!(received.contains(&(DROP_AT + 1)));
+    assert_eq!(keyframes[3], DROP_AT + 2);
+}
+
+/// Wait for the next `KeyframeForced`, collecting the keyframes sent
+/// meanwhile
+async fn next_forced(
+    events: &mut mpsc::Receiver<SourceEvent>,
+    keyframes: &mut Vec<u64>,
+) -> KeyframeReason {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match events.recv().await.unwrap() {
+                SourceEvent::KeyframeForced { reason } => return reason,
+                SourceEvent::KeyframeSent { frame_number } => keyframes.push(frame_number),
+                SourceEvent::Error(e) => panic!("pipeline failed: {}", e),
+                _ => {}
+            }
+        }
+    })
+    .await
+    .expect("no keyframe forced")
+}
+
+/// Paced source and NullEncoder pipeline streaming to `numbering_sink`
+fn paced_pipeline() -> (SourcePipeline, tokio::task::JoinHandle<Vec<u64>>) {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let pipeline = paced_pipeline_on(source_transport, SourcePipelineConfig::default());
+    (pipeline, tokio::spawn(numbering_sink(sink_transport)))
+}
+
+/// Paced source and NullEncoder pipeline streaming on `transport`
+fn paced_pipeline_on(transport: MockTransport, config: SourcePipelineConfig) -> SourcePipeline {
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    });
+
+    SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        transport.split(),
+        config,
+    )
+}
+
+async fn wait_for_keyframes_sent(pipeline: &SourcePipeline, count: u64) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while pipeline.stats().keyframes_sent < count {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("keyframes not sent");
+}
+
+#[tokio::test]
+async fn test_source_pipeline_pause_resume_stop() {
+    let (mut pipeline, sink) = paced_pipeline();
+    let mut events = pipeline.events().unwrap();
+    pipeline.start().unwrap();
+
+    let mut keyframes = Vec::new();
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::StreamStart
+    );
+
+    // Pausing sends one black keyframe, then nothing is captured
+    pipeline.pause(true).await;
+    assert!(pipeline.is_paused());
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Pause
+    );
+    wait_for_keyframes_sent(&pipeline, 2).await;
+    let paused = pipeline.stats();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let still_paused = pipeline.stats();
+    assert!(still_paused.paused);
+    assert_eq!(still_paused.frames_captured, paused.frames_captured);
+    assert_eq!(still_paused.keyframes_sent, 2);
+    // Everything in flight was acked meanwhile
+    assert_eq!(still_paused.frames_acked, still_paused.frames_sent);
+
+    // Resuming starts with a keyframe
+    pipeline.resume().await;
+    assert!(!pipeline.is_paused());
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Resume
+    );
+    wait_for_keyframes_sent(&pipeline, 3).await;
+
+    tokio::time::timeout(Duration::from_secs(1), pipeline.stop())
+        .await
+        .expect("pipeline didn't stop");
+    let stats = pipeline.stats();
+    assert!(stats.frames_captured > paused.frames_captured);
+    assert_eq!(stats.keyframes_forced, 3);
+    while let Ok(event) = events.try_recv() {
+        assert!(
+            !matches!(event, SourceEvent::CreditStarvation { .. }),
+            "starved while paused"
+        );
+    }
+    drop(pipeline);
+    sink.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_source_pipeline_tells_sink_it_paused() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let config = SourcePipelineConfig {
+        pause_packets: true,
+        ..Default::default()
+    };
+    let mut pipeline = paced_pipeline_on(source_transport, config);
+    pipeline.start().unwrap();
+
+    // One PAUSE per change, however often it's asked for
+    pipeline.pause(true).await;
+    pipeline.pause(false).await;
+    pipeline.resume().await;
+    pipeline.resume().await;
+
+    let mut pauses = Vec::new();
+    while pauses.len() < 2 {
+        let data = tokio::time::timeout(Duration::from_secs(1), sink_transport.recv())
+            .await
+            .expect("no PAUSE sent")
+            .unwrap();
+        let (packet, _) = Packet::parse(&data).unwrap();
+        if packet.packet_type() == PacketType::Pause {
+            pauses.push(PausePayload::parse(&packet.payload).unwrap().paused);
+        }
+    }
+    assert_eq!(pauses, [true, false]);
+
+    tokio::time::timeout(Duration::from_secs(1), pipeline.stop())
+        .await
+        .expect("pipeline didn't stop");
+    while let Ok(Some(data)) = sink_transport.try_recv().await {
+        let (packet, _) = Packet::parse(&data).unwrap();
+        assert_ne!(packet.packet_type(), PacketType::Pause);
+    }
+}
+
+#[tokio::test]
+async fn test_source_pipeline_stop_while_paused() {
+    let (mut pipeline, sink) = paced_pipeline();
+    let mut events = pipeline.events().unwrap();
+    pipeline.start().unwrap();
+
+    let mut keyframes = Vec::new();
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::StreamStart
+    );
+
+    // Resuming without a pause does nothing
+    pipeline.resume().await;
+    // Without a black frame, pausing just stops capture
+    pipeline.pause(false).await;
+    pipeline.pause(false).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let paused = pipeline.stats();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pipeline.stats().frames_captured, paused.frames_captured);
+
+    tokio::time::timeout(Duration::from_secs(1), pipeline.stop())
+        .await
+        .expect("pipeline didn't stop while paused");
+    assert_eq!(pipeline.stats().keyframes_forced, 1);
+    while let Ok(event) = events.try_recv() {
+        assert!(
+            !matches!(event, SourceEvent::KeyframeForced { .. }),
+            "unexpected {:?}",
+            event
+        );
+    }
+    drop(pipeline);
+    sink.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_source_pipeline_changes_capture_fps() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let config = EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        keyframe_interval: Duration::from_secs(60),
+        ..Default::default()
+    };
+
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(NullEncoder::new(config.clone())),
+        source_transport.split(),
+        SourcePipelineConfig::default(),
+    );
+    let mut events = pipeline.events().unwrap();
+    let sink = tokio::spawn(timing_sink(sink_transport));
+    pipeline.start().unwrap();
+
+    let mut keyframes = Vec::new();
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::StreamStart
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    pipeline.reconfigure(EncoderConfig { fps: 30, ..config });
+    assert_eq!(
+        next_forced(&mut events, &mut keyframes).await,
+        KeyframeReason::Reconfigure
+    );
+    wait_for_keyframes_sent(&pipeline, 2).await;
+    let sent_at_switch = pipeline.stats().frames_sent;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while pipeline.stats().frames_sent < sent_at_switch + 5 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("frames not sent after the switch");
+    pipeline.stop().await;
+    while let Ok(event) = events.try_recv() {
+        if let SourceEvent::KeyframeSent { frame_number } = event {
+            keyframes.push(frame_number);
+        }
+    }
+    drop(pipeline);
+    let received = sink.await.unwrap();
+
+    // The first frame at the new rate is the reconfigure keyframe
+    assert_eq!(keyframes.len(), 2);
+    let switch = keyframes[1];
+    let index = received
+        .iter()
+        .position(|&(frame_number, _)| frame_number == switch)
+        .expect("reconfigure keyframe not received");
+    assert!(index >= 2 && received.len() > index + 2);
+
+    let intervals: Vec<u64> = received
+        .windows(2)
+        .map(|pair| {
+            assert!(pair[1].1 > pair[0].1, "pts went backwards: {:?}", pair);
+            pair[1].1 - pair[0].1
+        })
+        .collect();
+    // 60 fps up to the switch, then 30. Frames skipped for want of credit
+    // would leave a whole number of intervals.
+    let before = &intervals[..index];
+    let after = &intervals[index..];
+    assert!(before.iter().all(|&interval| interval % 16_666 == 0));
+    assert_eq!(before.iter().min(), Some(&16_666));
+    assert!(after.iter().all(|&interval| interval % 33_333 == 0));
+    assert_eq!(after.iter().min(), Some(&33_333));
+}
+
+/// Source that stops after a few frames, as ScreenCaptureKit does when the
+/// display goes away
+struct StoppingSource {
+    inner: TestPatternSource,
+    stop_after: u64,
+}
+
+impl FrameSource for StoppingSource {
+    fn next_frame(&mut self) -> Result<CapturedFrame, CaptureError> {
+        let frame = self.inner.next_frame()?;
+        if frame.frame_number >= self.stop_after {
+            return Err(CaptureError::Stopped("display disconnected".to_string()));
+        }
+        Ok(frame)
+    }
+
+    fn resolution(&self) -> (u32, u32) {
+        self.inner.resolution()
+    }
+
+    fn fps(&self) -> u32 {
+        self.inner.fps()
+    }
+}
+
+#[tokio::test]
+async fn test_source_pipeline_reports_stopped_capture() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = StoppingSource {
+        inner: TestPatternSource::new(TestPatternConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            fps: 60,
+            paced: true,
+        })
+        .unwrap(),
+        stop_after: 5,
+    };
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig::default(),
+    );
+    let mut events = pipeline.events().unwrap();
+    let sink = tokio::spawn(numbering_sink(sink_transport));
+    pipeline.start().unwrap();
+
+    // Fails rather than waiting forever for the next frame
+    let error = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let SourceEvent::Error(e) = events.recv().await.unwrap() {
+                return e;
+            }
+        }
+    })
+    .await
+    .expect("stopped capture not reported");
+    assert!(
+        matches!(error, PipelineError::Capture(CaptureError::Stopped(_))),
+        "unexpected error: {}",
+        error
+    );
+
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        pipeline.shutdown_token().cancelled(),
+    )
+    .await
+    .expect("pipeline didn't stop");
+    pipeline.stop().await;
+    drop(pipeline);
+    let received = sink.await.unwrap();
+    assert!(received.len() <= 5);
+}
+
+/// Encoder that stops putting frames out from `wedge_at` until it's
+/// restarted, as a hardware encoder can after the GPU resets
+struct WedgingEncoder {
+    inner: NullEncoder,
+    wedge_at: u64,
+    restarted: bool,
+}
+
+impl VideoEncoder for WedgingEncoder {
+    fn encode_raw(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        pts_us: u64,
+        force_keyframe: bool,
+    ) -> Result<(), EncodeError> {
+        self.inner.encode_raw(data, stride, pts_us, force_keyframe)
+    }
+
+    fn next_frame(&mut self) -> Option<EncodedFrame> {
+        let frame = self.inner.next_frame()?;
+        if !self.restarted && frame.metadata.frame_number >= self.wedge_at {
+            return None;
+        }
+        Some(frame)
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        self.inner.flush()
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), EncodeError> {
+        self.inner.set_bitrate(bitrate)
+    }
+
+    fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncodeError> {
+        self.restarted = true;
+        self.inner.reconfigure(config)
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        self.inner.config()
+    }
+}
+
+#[tokio::test]
+async fn test_source_pipeline_restarts_stalled_encoder() {
+    const WEDGE_AT: u64 = 10;
+    const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let encoder = WedgingEncoder {
+        inner: NullEncoder::new(EncoderConfig {
+            width: WIDTH,
+            height: HEIGHT,
+            ..Default::default()
+        }),
+        wedge_at: WEDGE_AT,
+        restarted: false,
+    };
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            stall_timeout: Some(STALL_TIMEOUT),
+            ..Default::default()
+        },
+    );
+    let mut events = pipeline.events().unwrap();
+    let sink = tokio::spawn(numbering_sink(sink_transport));
+    pipeline.start().unwrap();
+
+    // Capture goes on while the encoder swallows every frame, taking all
+    // the credits, so it's the encoder that's found stalled and restarted
+    let mut seen = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match events.recv().await.unwrap() {
+                SourceEvent::StreamStalled { cause, waited } => {
+                    assert_eq!(cause, StallCause::Encoder);
+                    assert!(waited >= STALL_TIMEOUT);
+                    seen.push("stalled");
+                }
+                SourceEvent::KeyframeForced {
+                    reason: KeyframeReason::EncoderRestart,
+                } => seen.push("restarted"),
+                SourceEvent::StreamRecovered { cause, .. } => {
+                    assert_eq!(cause, StallCause::Encoder);
+                    seen.push("recovered");
+                    return;
+                }
+                SourceEvent::Error(e) => panic!("pipeline failed: {}", e),
+                _ => {}
+            }
+        }
+    })
+    .await
+    .expect("stalled encoder not restarted");
+    assert_eq!(seen, ["stalled", "restarted", "recovered"]);
+
+    // The lost frames' credits came back, so streaming carries on
+    let sent = pipeline.stats().frames_sent;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while pipeline.stats().frames_sent < sent + 20 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("streaming didn't carry on after the restart");
+    pipeline.stop().await;
+    let stats = pipeline.stats();
+    assert_eq!(stats.stalls, 1);
+    assert_eq!(stats.encoder_restarts, 1);
+    assert_eq!(stats.frames_dropped, 0);
+
+    drop(pipeline);
+    let received = sink.await.unwrap();
+    assert!(received.iter().any(|&frame_number| frame_number > WEDGE_AT));
+}
+
+#[tokio::test]
+async fn test_source_pipeline_probes_then_fails_when_starved() {
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: true,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: INITIAL_CREDITS,
+            starvation_timeout: Some(Duration::from_millis(50)),
+            starvation_probes: 2,
+            ..Default::default()
+        },
+    );
+    let mut events = pipeline.events().unwrap();
+    // A sink whose acks never arrive
+    let sink = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Ok(Ok(data)) =
+            tokio::time::timeout(Duration::from_millis(500), sink_transport.recv()).await
+        {
+            received.push(Packet::parse(&data).unwrap().0.packet_type());
+        }
+        received
+    });
+    pipeline.start().unwrap();
+
+    let error = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let SourceEvent::Error(e) = events.recv().await.unwrap() {
+                return e;
+            }
+        }
+    })
+    .await
+    .expect("starvation not given up on");
+    assert!(
+        matches!(
+            error,
+            PipelineError::Transport(TransportError::Timeout { duration_ms: 150 })
+        ),
+        "unexpected error: {}",
+        error
+    );
+    pipeline.stop().await;
+    let stats = pipeline.stats();
+    assert_eq!(stats.frames_sent, INITIAL_CREDITS as u64);
+    assert_eq!(stats.credits, 0);
+    assert_eq!(stats.starvations, 1);
+    assert_eq!(stats.starvation_probes, 2);
+
+    // The frames the credits allowed, then a PING per timeout
+    drop(pipeline);
+    let received = sink.await.unwrap();
+    let pings = received
+        .iter()
+        .skip_while(|packet_type| **packet_type == PacketType::Frame)
+        .collect::<Vec<_>>();
+    assert_eq!(pings, vec![&PacketType::Ping; 2]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_source_pipeline_sequences_across_tasks() {
+    const FRAMES: u16 = 10;
+    const TASKS: u32 = 3;
+    const PINGS: u32 = 20;
+
+    let (source_transport, sink_transport) = MockTransport::pair();
+    let source = TestPatternSource::new(TestPatternConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 60,
+        paced: false,
+    })
+    .unwrap();
+    let encoder = NullEncoder::new(EncoderConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        ..Default::default()
+    });
+    let mut pipeline = SourcePipeline::new(
+        Box::new(source),
+        Box::new(encoder),
+        source_transport.split(),
+        SourcePipelineConfig {
+            initial_credits: FRAMES,
+            first_sequence: 3,
+            ..Default::default()
+        },
+    );
+    pipeline.start().unwrap();
+
+    let expected = FRAMES as usize + (TASKS * PINGS) as usize;
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while received.len() < expected {
+            let data = tokio::time::timeout(Duration::from_secs(5), sink_transport.recv())
+                .await
+                .expect("packets missing")
+                .unwrap();
+            let (packet, _) = Packet::parse(&data).unwrap();
+            received.push((packet.packet_type(), packet.sequence()));
+        }
+        received
+    });
+
+    // Pings go out while the send task sends FRAMEs on another thread;
+    // with no acks it stops once the credits run out
+    let ping = |task: u32| {
+        let pipeline = &pipeline;
+        async move {
+            for n in 0..PINGS {
+                let ping = PingPayload::new((task * PINGS + n) as u64);
+                pipeline
+                    .send_packet(PacketType::Ping, ping.to_bytes())
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+        }
+    };
+    tokio::join!(ping(0), ping(1), ping(2));
+
+    let received = reader.await.unwrap();
+    let frames = received
+        .iter()
+        .filter(|(packet_type, _)| *packet_type == PacketType::Frame)
+        .count();
+    assert_eq!(frames, FRAMES as usize);
+    let sequences: Vec<u32> = received.iter().map(|&(_, sequence)| sequence).collect();
+    let last = 3 + expected as u32;
+    assert_eq!(sequences, (3..last).collect::<Vec<_>>());
+    assert_eq!(pipeline.sequence(), last);
+
+    pipeline.stop().await;
+}